@@ -33,6 +33,29 @@ pub fn digest_128(data: &[u8]) -> Result<Vec<u8>, Blake2bError> {
     digest(data, 16)
 }
 
+/// Generate root of the merkle tree of the list (e.g. operations hash of the block header),
+/// list is padded by its last element to the power of two and empty list is hashed as empty data
+/// (see `Make_merkle_tree` in Tezos `blake2B.ml`)
+pub fn merkle_tree(list: &[Vec<u8>]) -> Result<Vec<u8>, Blake2bError> {
+    let last = match list.last() {
+        Some(last) => digest_256(last)?,
+        None => return digest_256(&[]),
+    };
+    let mut nodes = list
+        .iter()
+        .map(|element| digest_256(element))
+        .collect::<Result<Vec<_>, _>>()?;
+    nodes.resize(list.len().next_power_of_two(), last);
+
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| digest_256(&[pair[0].as_slice(), pair[1].as_slice()].concat()))
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(nodes.remove(0))
+}
+
 /// Arbitrary Blake2b digest generation from generic data.
 // Should be noted, that base Blake2b supports arbitrary digest length from 16 to 64 bytes
 fn digest(data: &[u8], out_len: usize) -> Result<Vec<u8>, Blake2bError> {
@@ -49,6 +72,31 @@ fn digest(data: &[u8], out_len: usize) -> Result<Vec<u8>, Blake2bError> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn merkle_tree_root() {
+        let empty = digest_256(&[]).unwrap();
+        assert_eq!(empty, merkle_tree(&[]).unwrap());
+        assert_eq!(
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+            hex::encode(&empty)
+        );
+
+        let leaf = |element: &[u8]| digest_256(element).unwrap();
+        let node = |left: &[u8], right: &[u8]| digest_256(&[left, right].concat()).unwrap();
+        let (a, b, c) = (b"a".to_vec(), b"b".to_vec(), b"c".to_vec());
+
+        assert_eq!(leaf(&a), merkle_tree(std::slice::from_ref(&a)).unwrap());
+        assert_eq!(
+            node(&leaf(&a), &leaf(&b)),
+            merkle_tree(&[a.clone(), b.clone()]).unwrap()
+        );
+        // padded by the last element
+        assert_eq!(
+            node(&node(&leaf(&a), &leaf(&b)), &node(&leaf(&c), &leaf(&c))),
+            merkle_tree(&[a, b, c]).unwrap()
+        );
+    }
+
     #[test]
     fn blake2b_256() {
         let hash = digest_256(b"hello world").unwrap();
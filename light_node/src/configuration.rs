@@ -19,23 +19,30 @@ use slog::{Drain, Duplicate, Logger, Never, SendSyncRefUnwindSafeDrain};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
+use crypto::hash::{BlockHash, CryptoboxPublicKeyHash};
 use logging::detailed_json;
 use logging::file::FileAppenderBuilder;
+use networking::p2p::peer::PeerPolicy;
+use networking::p2p::throttle::BandwidthLimits;
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
 use storage::context::actions::action_file_storage::ActionFileStorage;
 use storage::context::actions::context_action_storage::ContextActionStorage;
 use storage::context::actions::ContextActionStoreBackend;
+use storage::context::gc::ContextGc;
+use storage::context::kv_store::compressed::ContextCompression;
 use storage::context::kv_store::SupportedContextKeyValueStore;
 use storage::context::ActionRecorder;
 use storage::initializer::{
     ContextActionsRocksDbTableInitializer, ContextKvStoreConfiguration,
     ContextRocksDbTableInitializer, DbsRocksDbTableInitializer, RocksDbConfig,
 };
-use storage::PersistentStorage;
+use storage::persistent::{CommitLogCompression, CommitLogConfiguration};
+use storage::{HistoryMode, PersistentStorage};
 use tezos_api::environment;
 use tezos_api::environment::{TezosEnvironment, ZcashParams};
 use tezos_api::ffi::PatchContext;
+use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_wrapper::TezosApiConnectionPoolConfiguration;
 
 macro_rules! create_terminal_logger {
@@ -155,6 +162,19 @@ pub struct Storage {
     pub context_action_recorders: Vec<ContextActionStoreBackend>,
     pub compute_context_action_tree_hashes: bool,
     pub patch_context: Option<PatchContext>,
+    pub history_mode: HistoryMode,
+    /// Garbage collector of context store, used just for non-archive history modes
+    pub context_gc: ContextGc,
+    /// Compression of context store entries (None if entries are not compressed)
+    pub context_compression: Option<ContextCompression>,
+    /// Block storage commit log (block headers, block json data)
+    pub commit_log: CommitLogConfiguration,
+    pub archive: Option<ArchiveCommand>,
+    pub checkpoint: Option<Checkpoint>,
+    /// Run pending database migrations and stop node
+    pub migrate_db: bool,
+    /// Index applied operations by operation hash and by touched accounts
+    pub operations_index: bool,
 
     // merkle cfg
     pub context_kv_store: ContextKvStoreConfiguration,
@@ -163,13 +183,16 @@ pub struct Storage {
 
     // TODO: TE-447 - remove one_context when integration done
     pub one_context: bool,
+
+    /// If set, node just serves RPCs from storage owned by another node process
+    pub replica: Option<StorageReplica>,
 }
 
 impl Storage {
     const STORAGES_COUNT: usize = 3;
     const MINIMAL_THREAD_COUNT: usize = 1;
 
-    const DB_STORAGE_VERSION: i64 = 19;
+    const DB_STORAGE_VERSION: i64 = 20;
     const DB_CONTEXT_STORAGE_VERSION: i64 = 17;
     const DB_CONTEXT_ACTIONS_STORAGE_VERSION: i64 = 17;
 
@@ -179,6 +202,65 @@ impl Storage {
 
     const DEFAULT_CONTEXT_KV_STORE_BACKEND: &'static str = storage::context::kv_store::ROCKSDB;
     const DEFAULT_CONTEXT_ACTIONS_RECORDER: &'static str = storage::context::actions::ROCKSDB;
+    const DEFAULT_HISTORY_MODE: &'static str = "archive";
+}
+
+/// Block archive export is one-shot action, after archive import node continues and applies imported blocks
+#[derive(Debug, Clone)]
+pub enum ArchiveCommand {
+    Export {
+        path: PathBuf,
+        /// Lowest exported level
+        from_level: Level,
+        /// If not set, current head is exported
+        block_hash: Option<BlockHash>,
+    },
+    Import {
+        path: PathBuf,
+    },
+}
+
+/// Read-only storage replica - node opens storage of another (primary) node process running on the same data directory,
+/// it does not connect to p2p network nor apply blocks, it just serves RPCs and periodically catches up with the primary
+#[derive(Debug, Clone)]
+pub struct StorageReplica {
+    /// Directory for own state of the replica (e.g. RocksDB secondary instances), every replica needs its own
+    pub secondary_path: PathBuf,
+    /// How often storage catches up with the primary node
+    pub sync_interval: Duration,
+}
+
+impl StorageReplica {
+    const DEFAULT_SYNC_INTERVAL_MS: u64 = 1000;
+}
+
+/// Checkpoint configured by user, which every accepted branch has to pass through
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub block_hash: BlockHash,
+    /// If not set, level is resolved from stored block header
+    pub level: Option<i32>,
+}
+
+impl FromStr for Checkpoint {
+    type Err = String;
+
+    /// Expected format is `<block_hash>[,<level>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ',');
+        let block_hash = BlockHash::from_base58_check(parts.next().unwrap_or_default().trim())
+            .map_err(|e| format!("Invalid checkpoint block hash, reason: {}", e))?;
+        let level = match parts.next() {
+            Some(level) => Some(
+                level
+                    .trim()
+                    .parse::<i32>()
+                    .map_err(|e| format!("Invalid checkpoint level, reason: {}", e))?,
+            ),
+            None => None,
+        };
+        Ok(Checkpoint { block_hash, level })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -432,6 +514,87 @@ pub fn tezos_app() -> App<'static, 'static> {
                     Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,IP3:PORT3", v))
                 }
             }))
+        .arg(Arg::with_name("trusted-peers")
+            .long("trusted-peers")
+            .takes_value(true)
+            .value_name("IP:PORT")
+            .help("Trusted peers are never blacklisted, not counted to the peer-thresh-high and always reconnected. Peers are delimited by a comma. Format: IP1:PORT1,IP2:PORT2,IP3:PORT3")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(|ip_port| ip_port.parse::<SocketAddr>())
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,IP3:PORT3", v))
+                }
+            }))
+        .arg(Arg::with_name("trusted-peer-ids")
+            .long("trusted-peer-ids")
+            .takes_value(true)
+            .value_name("PEER_ID")
+            .help("Trusted peer ids are never blacklisted, not counted to the peer-thresh-high and always reconnected (to their last known address). Peer ids are delimited by a comma. Format: PEER_ID1,PEER_ID2")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(CryptoboxPublicKeyHash::from_base58_check)
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: PEER_ID1,PEER_ID2", v))
+                }
+            }))
+        .arg(Arg::with_name("private-peer-whitelist")
+            .long("private-peer-whitelist")
+            .takes_value(true)
+            .value_name("PEER_ID")
+            .help("Peer ids accepted in private mode (trusted peers are accepted always). Peer ids are delimited by a comma. Format: PEER_ID1,PEER_ID2")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(CryptoboxPublicKeyHash::from_base58_check)
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: PEER_ID1,PEER_ID2", v))
+                }
+            }))
+        .arg(Arg::with_name("peer-policy")
+            .long("peer-policy")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(false)
+            .value_name("PEER_ID:RULES")
+            .help("Policy applied to the connections with the peer, can be used multiple times. Rules are delimited by a comma. Supported rules: disable-mempool-relay, max-msg-rate=<messages per second>. Format: PEER_ID:disable-mempool-relay,max-msg-rate=100")
+            .validator(|v| parse_peer_policy(&v).map(|_| ())))
+        .arg(Arg::with_name("p2p-max-upload-kbps")
+            .long("p2p-max-upload-kbps")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Maximal upload bandwidth of all p2p connections in kilobits per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-download-kbps")
+            .long("p2p-max-download-kbps")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Maximal download bandwidth of all p2p connections in kilobits per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-peer-upload-kbps")
+            .long("p2p-max-peer-upload-kbps")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Maximal upload bandwidth of a single p2p connection in kilobits per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("p2p-max-peer-download-kbps")
+            .long("p2p-max-peer-download-kbps")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Maximal download bandwidth of a single p2p connection in kilobits per second. Default: unlimited")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
         .arg(Arg::with_name("peer-thresh-low")
             .long("peer-thresh-low")
             .takes_value(true)
@@ -572,7 +735,98 @@ pub fn tezos_app() -> App<'static, 'static> {
             .takes_value(true)
             .value_name("STRING")
             .possible_values(&SupportedContextKeyValueStore::possible_values())
-            .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree'"))
+            .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree', 'pack'"))
+        .arg(Arg::with_name("history-mode")
+            .long("history-mode")
+            .takes_value(true)
+            .value_name("STRING")
+            .help("Choose how much of the chain history is kept in storage - supported modes: 'archive', 'full', 'full:<cycles>', 'rolling', 'rolling:<cycles>'")
+            .validator(|v| v.parse::<HistoryMode>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("context-gc")
+            .long("context-gc")
+            .takes_value(true)
+            .value_name("STRING")
+            .possible_values(&ContextGc::possible_values())
+            .help("Choose garbage collector of context storage for non-archive history modes - supported: 'concurrent' (default, runs in background thread), 'mark-sweep', 'disabled'"))
+        .arg(Arg::with_name("context-compression")
+            .long("context-compression")
+            .help("Compress context store entries above threshold size with zstd (dictionary is trained on the first stored entries), already stored entries are kept"))
+        .arg(Arg::with_name("context-compression-threshold")
+            .long("context-compression-threshold")
+            .takes_value(true)
+            .value_name("NUM")
+            .requires("context-compression")
+            .help("Context store entries smaller than threshold (in bytes) are stored uncompressed (default: 256)")
+            .validator(|v| v.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("commit-log-compression")
+            .long("commit-log-compression")
+            .takes_value(true)
+            .value_name("STRING")
+            .possible_values(&CommitLogCompression::possible_values())
+            .help("Compression of block storage commit log segments - supported: 'zstd' (default), 'none'. Applied to newly created segments, already stored segments are kept"))
+        .arg(Arg::with_name("commit-log-segment-size-mb")
+            .long("commit-log-segment-size-mb")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Size of block storage commit log segment in MB, when it is reached, new segment is started (default: 256, max: 1024)")
+            .validator(|v| match v.parse::<u64>() {
+                Ok(size) if size > 0 && size <= 1024 => Ok(()),
+                _ => Err("Value must be a number between 1 and 1024".to_string()),
+            }))
+        .arg(Arg::with_name("archive-export")
+            .long("archive-export")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with("archive-import")
+            .help("Export block headers, operations and metadata of the current head branch (or branch of 'archive-export-block') to the block archive file and stop node"))
+        .arg(Arg::with_name("archive-export-block")
+            .long("archive-export-block")
+            .takes_value(true)
+            .value_name("BLOCK_HASH")
+            .requires("archive-export")
+            .help("Last block exported to the block archive (default is current head)")
+            .validator(|v| BlockHash::from_base58_check(&v).map(|_| ()).map_err(|e| e.to_string())))
+        .arg(Arg::with_name("archive-export-from-level")
+            .long("archive-export-from-level")
+            .takes_value(true)
+            .value_name("LEVEL")
+            .requires("archive-export")
+            .help("Lowest level exported to the block archive (default: 1)")
+            .validator(|v| match v.parse::<Level>() {
+                Ok(level) if level > 0 => Ok(()),
+                _ => Err("Value must be a positive level".to_string()),
+            }))
+        .arg(Arg::with_name("archive-import")
+            .long("archive-import")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Import blocks from the block archive file, which continues local chain, and apply them after node starts")
+            .validator(|v| if Path::new(&v).exists() { Ok(()) } else { Err(format!("Block archive file not found at '{}'", v)) }))
+        .arg(Arg::with_name("checkpoint")
+            .long("checkpoint")
+            .takes_value(true)
+            .value_name("BLOCK_HASH[,LEVEL]")
+            .help("Block, which every accepted branch has to pass through, level is required only if block is not stored yet")
+            .validator(|v| v.parse::<Checkpoint>().map(|_| ())))
+        .arg(Arg::with_name("storage-replica")
+            .long("storage-replica")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with_all(&["migrate-db", "archive-export", "archive-import", "checkpoint"])
+            .help("Run as read-only RPC replica of another node process, which owns the storage in the same data directory ('tezos-data-dir', 'bootstrap-db-path' and 'context-kv-store' have to match the primary node). PATH is directory for own state of the replica (must not be shared with other replicas). Replica does not connect to p2p network, supported context stores are 'rocksdb' and 'pack'"))
+        .arg(Arg::with_name("storage-replica-sync-interval-ms")
+            .long("storage-replica-sync-interval-ms")
+            .takes_value(true)
+            .value_name("NUM")
+            .requires("storage-replica")
+            .help("How often storage replica catches up with the primary node in milliseconds (default: 1000)")
+            .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+        .arg(Arg::with_name("migrate-db")
+            .long("migrate-db")
+            .help("Run pending database migrations and stop node (pending migrations are also applied on every node startup)"))
+        .arg(Arg::with_name("operations-index")
+            .long("operations-index")
+            .help("Index applied operations by operation hash and by touched accounts (implicit or originated), enables RPCs '/dev/chains/main/operations/:operation_hash' and '/dev/chains/main/accounts/:account_address/operations'"))
         .arg(Arg::with_name("compute-context-action-tree-hashes")
             .long("compute-context-action-tree-hashes")
             .takes_value(true)
@@ -698,6 +952,34 @@ pub fn get_final_path(tezos_data_dir: &Path, path: PathBuf) -> PathBuf {
     final_path
 }
 
+/// Parses peer policy in format `PEER_ID:RULES`
+fn parse_peer_policy(value: &str) -> Result<(CryptoboxPublicKeyHash, PeerPolicy), String> {
+    let mut peer_id_rules = value.splitn(2, ':');
+    match (peer_id_rules.next(), peer_id_rules.next()) {
+        (Some(peer_id), Some(rules)) => {
+            let peer_id = CryptoboxPublicKeyHash::from_base58_check(peer_id)
+                .map_err(|e| format!("Value '{}' has invalid peer id, reason: {}", value, e))?;
+            let policy = rules
+                .parse::<PeerPolicy>()
+                .map_err(|e| format!("Value '{}' is not valid, reason: {}", value, e))?;
+            Ok((peer_id, policy))
+        }
+        _ => Err(format!(
+            "Value '{}' is not valid. Expected format is: PEER_ID:RULES",
+            value
+        )),
+    }
+}
+
+/// Parses optional bandwidth limit in kilobits per second
+fn parse_kbps(args: &clap::ArgMatches, arg_name: &str) -> Option<u64> {
+    args.value_of(arg_name).map(|value| {
+        value
+            .parse::<u64>()
+            .expect("Provided value cannot be converted to number")
+    })
+}
+
 // Parses config file and returns vector of OsString representing all argument strings from file
 // All lines that are empty or begin with "#" or "//" are ignored
 pub fn parse_config(config_path: PathBuf) -> Vec<OsString> {
@@ -848,6 +1130,55 @@ impl Environment {
                             .collect()
                     })
                     .unwrap_or_default(),
+                trusted_peers: args
+                    .value_of("trusted-peers")
+                    .map(|peers_str| {
+                        peers_str
+                            .split(',')
+                            .map(|ip_port| ip_port.parse().expect("Was expecting IP:PORT"))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                trusted_peer_ids: args
+                    .value_of("trusted-peer-ids")
+                    .map(|peer_ids_str| {
+                        peer_ids_str
+                            .split(',')
+                            .map(|peer_id| {
+                                CryptoboxPublicKeyHash::from_base58_check(peer_id)
+                                    .expect("Was expecting PEER_ID")
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                private_peer_whitelist: args
+                    .value_of("private-peer-whitelist")
+                    .map(|peer_ids_str| {
+                        peer_ids_str
+                            .split(',')
+                            .map(|peer_id| {
+                                CryptoboxPublicKeyHash::from_base58_check(peer_id)
+                                    .expect("Was expecting PEER_ID")
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                peer_policies: args
+                    .values_of("peer-policy")
+                    .map(|values| {
+                        values
+                            .map(|value| {
+                                parse_peer_policy(value).expect("Was expecting PEER_ID:RULES")
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                bandwidth_limits: BandwidthLimits {
+                    max_upload_kbps: parse_kbps(&args, "p2p-max-upload-kbps"),
+                    max_download_kbps: parse_kbps(&args, "p2p-max-download-kbps"),
+                    max_peer_upload_kbps: parse_kbps(&args, "p2p-max-peer-upload-kbps"),
+                    max_peer_download_kbps: parse_kbps(&args, "p2p-max-peer-download-kbps"),
+                },
                 peer_threshold: PeerConnectionThreshold::try_new(
                     args.value_of("peer-thresh-low")
                         .unwrap_or("")
@@ -1025,6 +1356,11 @@ impl Environment {
                         SupportedContextKeyValueStore::BTreeMap => {
                             ContextKvStoreConfiguration::BTreeMap
                         }
+                        SupportedContextKeyValueStore::PackFile { .. } => {
+                            ContextKvStoreConfiguration::PackFile {
+                                path: db_path.join("context_pack"),
+                            }
+                        }
                     })
                     .unwrap_or_else(|e| {
                         panic!(
@@ -1034,6 +1370,105 @@ impl Environment {
                         )
                     });
 
+                let history_mode = args
+                    .value_of("history-mode")
+                    .unwrap_or(Storage::DEFAULT_HISTORY_MODE)
+                    .parse::<HistoryMode>()
+                    .unwrap_or_else(|e| panic!("Invalid history mode, reason: {}", e));
+
+                let context_gc = args
+                    .value_of("context-gc")
+                    .map(|v| {
+                        v.parse::<ContextGc>()
+                            .unwrap_or_else(|e| panic!("Invalid context gc, reason: {}", e))
+                    })
+                    .unwrap_or_default();
+
+                let context_compression = if args.is_present("context-compression") {
+                    Some(ContextCompression {
+                        threshold: args
+                            .value_of("context-compression-threshold")
+                            .map(|v| {
+                                v.parse::<usize>()
+                                    .expect("Provided value cannot be converted to number")
+                            })
+                            .unwrap_or(ContextCompression::DEFAULT_THRESHOLD),
+                    })
+                } else {
+                    None
+                };
+
+                let commit_log = CommitLogConfiguration {
+                    segment_size: args
+                        .value_of("commit-log-segment-size-mb")
+                        .map(|v| {
+                            v.parse::<u64>()
+                                .expect("Provided value cannot be converted to number")
+                                * 1024
+                                * 1024
+                        })
+                        .unwrap_or(CommitLogConfiguration::DEFAULT_SEGMENT_SIZE),
+                    compression: args
+                        .value_of("commit-log-compression")
+                        .map(|v| {
+                            v.parse::<CommitLogCompression>().unwrap_or_else(|e| {
+                                panic!("Invalid commit log compression, reason: {}", e)
+                            })
+                        })
+                        .unwrap_or(CommitLogCompression::Zstd),
+                };
+
+                let archive = if let Some(path) = args.value_of("archive-export") {
+                    Some(ArchiveCommand::Export {
+                        path: get_final_path(
+                            &data_dir,
+                            path.parse::<PathBuf>()
+                                .expect("Provided value cannot be converted to path"),
+                        ),
+                        from_level: args
+                            .value_of("archive-export-from-level")
+                            .map(|v| {
+                                v.parse::<Level>()
+                                    .expect("Provided value cannot be converted to level")
+                            })
+                            .unwrap_or(1),
+                        block_hash: args.value_of("archive-export-block").map(|block_hash| {
+                            BlockHash::from_base58_check(block_hash).unwrap_or_else(|e| {
+                                panic!("Invalid archive block hash, reason: {}", e)
+                            })
+                        }),
+                    })
+                } else {
+                    args.value_of("archive-import")
+                        .map(|path| ArchiveCommand::Import {
+                            path: path
+                                .parse::<PathBuf>()
+                                .expect("Provided value cannot be converted to path"),
+                        })
+                };
+
+                let checkpoint = args.value_of("checkpoint").map(|checkpoint| {
+                    checkpoint
+                        .parse::<Checkpoint>()
+                        .unwrap_or_else(|e| panic!("{}", e))
+                });
+
+                let replica = args.value_of("storage-replica").map(|path| StorageReplica {
+                    secondary_path: get_final_path(
+                        &data_dir,
+                        path.parse::<PathBuf>()
+                            .expect("Provided value cannot be converted to path"),
+                    ),
+                    sync_interval: Duration::from_millis(
+                        args.value_of("storage-replica-sync-interval-ms")
+                            .map(|v| {
+                                v.parse::<u64>()
+                                    .expect("Provided value cannot be converted to number")
+                            })
+                            .unwrap_or(StorageReplica::DEFAULT_SYNC_INTERVAL_MS),
+                    ),
+                });
+
                 let compute_context_action_tree_hashes = args
                     .value_of("compute-context-action-tree-hashes")
                     .unwrap_or("false")
@@ -1048,6 +1483,14 @@ impl Environment {
                     context_action_recorders,
                     context_kv_store,
                     merkle_context_actions_store,
+                    history_mode,
+                    context_gc,
+                    context_compression,
+                    commit_log,
+                    archive,
+                    checkpoint,
+                    migrate_db: args.is_present("migrate-db"),
+                    operations_index: args.is_present("operations-index"),
                     patch_context: {
                         match args.value_of("sandbox-patch-context-json-file") {
                             Some(path) => {
@@ -1093,6 +1536,7 @@ impl Environment {
                     // TODO: TE-447 - we will support just one context
                     // one_context: args.is_present("one-context"),
                     one_context: true,
+                    replica,
                 }
             },
             identity: crate::configuration::Identity {
@@ -1229,8 +1673,10 @@ impl Environment {
                         }
                     }
                     storage::context::actions::ContextActionStoreBackend::FileStorage { path } => {
-                        Ok(Some(Box::new(ActionFileStorage::new(path.to_path_buf()))
-                            as Box<dyn ActionRecorder + Send>))
+                        Ok(Some(
+                            Box::new(ActionFileStorage::new(path.to_path_buf(), storage))
+                                as Box<dyn ActionRecorder + Send>,
+                        ))
                     }
                     storage::context::actions::ContextActionStoreBackend::NoneBackend => Ok(None),
                 })
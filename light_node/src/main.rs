@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: MIT
 // #![forbid(unsafe_code)]
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -9,12 +11,13 @@ use std::time::Duration;
 use riker::actors::*;
 use slog::{debug, error, info, warn, Logger};
 
+use crypto::hash::BlockHash;
 use monitoring::{Monitor, WebsocketHandler};
 use networking::p2p::network_channel::NetworkChannel;
 use networking::ShellCompatibilityVersion;
 use rpc::rpc_actor::RpcServer;
 use shell::chain_current_head_manager::ChainCurrentHeadManager;
-use shell::chain_feeder::ChainFeeder;
+use shell::chain_feeder::{ChainFeeder, ScheduleApplyBlock};
 use shell::chain_manager::ChainManager;
 use shell::context_listener::ContextListener;
 use shell::mempool::{init_mempool_state_storage, MempoolPrevalidatorFactory};
@@ -22,13 +25,21 @@ use shell::peer_manager::PeerManager;
 use shell::shell_channel::{ShellChannel, ShellChannelTopic, ShuttingDown};
 use shell::state::head_state::init_current_head_state;
 use shell::state::synchronization_state::init_synchronization_bootstrap_state_storage;
+use shell::state::ApplyBlockBatch;
+use storage::archive::{export_archive, import_archive};
+use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context::TezedgeContext;
 use storage::initializer::{
-    initialize_merkle, initialize_rocksdb, GlobalRocksDbCacheHolder, MainChain, RocksDbCache,
+    check_history_mode_compatibility, initialize_merkle, initialize_rocksdb,
+    GlobalRocksDbCacheHolder, MainChain, RocksDbCache,
 };
+use storage::migration::{migrate_rocksdb, MigrationRegistry};
 use storage::persistent::sequence::Sequences;
 use storage::persistent::{open_cl, CommitLogSchema};
-use storage::{resolve_storage_init_chain_data, BlockStorage, PersistentStorage, StorageInitInfo};
+use storage::{
+    ensure_consistent_current_head, resolve_storage_init_chain_data, store_configured_checkpoint,
+    BlockStorage, ChainMetaStorage, PersistentStorage, StorageInitInfo,
+};
 use tezos_api::environment;
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::TezosRuntimeConfiguration;
@@ -38,10 +49,11 @@ use tezos_wrapper::ProtocolEndpointConfiguration;
 use tezos_wrapper::TezosApiConnectionPoolError;
 use tezos_wrapper::{TezosApiConnectionPool, TezosApiConnectionPoolConfiguration};
 
-use crate::configuration::Environment;
+use crate::configuration::{ArchiveCommand, Environment};
 
 mod configuration;
 mod identity;
+mod replica;
 mod system;
 
 extern crate jemallocator;
@@ -158,6 +170,9 @@ fn create_tezos_writeable_api_pool(
     )
 }
 
+/// Count of imported archive blocks scheduled to the chain feeder at once
+const ARCHIVE_APPLY_BATCH_SIZE: usize = 100;
+
 fn block_on_actors(
     env: crate::configuration::Environment,
     tezos_env: &TezosEnvironmentConfiguration,
@@ -165,6 +180,7 @@ fn block_on_actors(
     identity: Arc<Identity>,
     persistent_storage: PersistentStorage,
     tezedge_context: TezedgeContext,
+    archive_blocks_to_apply: Vec<BlockHash>,
     log: Logger,
 ) {
     // if feeding is started, than run chain manager
@@ -304,6 +320,23 @@ fn block_on_actors(
         log.clone(),
     )
     .expect("Failed to create chain feeder");
+
+    // blocks imported from archive are applied in batches one after another
+    let archive_chain_id = Arc::new(init_storage_data.chain_id.clone());
+    for batch in archive_blocks_to_apply.chunks(ARCHIVE_APPLY_BATCH_SIZE) {
+        let mut batch = batch.iter().cloned().map(Arc::new);
+        if let Some(starting_block) = batch.next() {
+            block_applier.tell(
+                ScheduleApplyBlock::new(
+                    archive_chain_id.clone(),
+                    ApplyBlockBatch::batch(starting_block, batch.collect()),
+                    None,
+                ),
+                None,
+            );
+        }
+    }
+
     let _ = ChainManager::actor(
         &actor_system,
         block_applier,
@@ -363,6 +396,7 @@ fn block_on_actors(
         &actor_system,
         network_channel,
         shell_channel.clone(),
+        persistent_storage.clone(),
         tokio_runtime.handle().clone(),
         identity,
         shell_compatibility_version,
@@ -500,16 +534,73 @@ fn main() {
         tezos_env.version.clone(),
     );
 
+    // storage is owned by another node process, just serve RPCs
+    if let Some(replica) = env.storage.replica.clone() {
+        let persistent_storage = replica::open_replica_storage(&env, &replica, &main_chain, &log);
+        let tezedge_context = TezedgeContext::new(
+            Some(BlockStorage::new(&persistent_storage)),
+            persistent_storage.merkle(),
+        );
+        match resolve_storage_init_chain_data(
+            tezos_env,
+            &env.storage.db_path,
+            &env.storage.tezos_data_dir,
+            &env.storage.patch_context,
+            &env.storage.history_mode,
+            env.storage.one_context,
+            &log,
+        )
+        .map(|init_data| init_data.with_operations_index(env.storage.operations_index))
+        {
+            Ok(init_data) => {
+                info!(log, "Databases opened as storage replica"; "secondary_path" => format!("{:?}", replica.secondary_path));
+                replica::block_on_replica(
+                    env,
+                    replica,
+                    tezos_env,
+                    init_data,
+                    persistent_storage,
+                    tezedge_context,
+                    log,
+                );
+            }
+            Err(e) => panic!("Failed to resolve init storage chain data, reason: {}", e),
+        }
+        return;
+    }
+
+    // apply pending migrations, before db version is checked
+    match migrate_rocksdb(
+        &env.storage.db,
+        &env.storage.db_path,
+        &MigrationRegistry::db_migrations(),
+        &log,
+    ) {
+        Ok(outcome) => {
+            info!(log, "Database migration check finished"; "result" => outcome.to_string());
+            if env.storage.migrate_db {
+                return;
+            }
+        }
+        Err(e) => panic!("Failed to migrate RocksDB database (db), reason: {}", e),
+    }
+
     // initialize dbs
     let kv_cache = RocksDbCache::new_lru_cache(env.storage.db.cache_size)
         .expect("Failed to initialize RocksDB cache (db)");
     let kv = initialize_rocksdb(&log, &kv_cache, &env.storage.db, &main_chain)
         .expect("Failed to create/initialize RocksDB database (db)");
     caches.push(kv_cache);
+    check_history_mode_compatibility(kv.clone(), &env.storage.history_mode, &log)
+        .expect("Failed to verify history mode of RocksDB database (db)");
 
     let commit_logs = Arc::new(
-        open_cl(&env.storage.db_path, vec![BlockStorage::descriptor()])
-            .expect("Failed to open plain block_header storage"),
+        open_cl(
+            &env.storage.db_path,
+            vec![BlockStorage::descriptor()],
+            &env.storage.commit_log,
+        )
+        .expect("Failed to open plain block_header storage"),
     );
     let sequences = Arc::new(Sequences::new(kv.clone(), 1000));
 
@@ -517,6 +608,9 @@ fn main() {
     let merkle = Arc::new(Mutex::new(
         initialize_merkle(
             &env.storage.context_kv_store,
+            &env.storage.history_mode,
+            env.storage.context_gc,
+            env.storage.context_compression,
             &main_chain,
             &log,
             &mut caches,
@@ -562,11 +656,42 @@ fn main() {
             &env.storage.db_path,
             &env.storage.tezos_data_dir,
             &env.storage.patch_context,
+            &env.storage.history_mode,
             env.storage.one_context,
             &log,
-        ) {
+        )
+        .map(|init_data| init_data.with_operations_index(env.storage.operations_index))
+        {
             Ok(init_data) => {
                 info!(log, "Databases loaded successfully");
+                // node could be stopped in the middle of block application
+                ensure_consistent_current_head(&persistent_storage, &init_data.chain_id, &log)
+                    .expect("Failed to find consistent current head");
+                if let Some(checkpoint) = env.storage.checkpoint.as_ref() {
+                    store_configured_checkpoint(
+                        &BlockStorage::new(&persistent_storage),
+                        &ChainMetaStorage::new(&persistent_storage),
+                        &init_data.chain_id,
+                        &checkpoint.block_hash,
+                        checkpoint.level,
+                        &log,
+                    )
+                    .expect("Failed to configure checkpoint");
+                }
+                let archive_blocks_to_apply = match env.storage.archive.as_ref() {
+                    Some(archive_command) => {
+                        match process_archive_command(
+                            archive_command,
+                            &persistent_storage,
+                            &init_data,
+                            &log,
+                        ) {
+                            Some(blocks_to_apply) => blocks_to_apply,
+                            None => return,
+                        }
+                    }
+                    None => vec![],
+                };
                 block_on_actors(
                     env,
                     tezos_env,
@@ -574,6 +699,7 @@ fn main() {
                     Arc::new(tezos_identity),
                     persistent_storage,
                     tezedge_context,
+                    archive_blocks_to_apply,
                     log,
                 )
             }
@@ -581,3 +707,66 @@ fn main() {
         }
     }
 }
+
+/// Export of the block archive is one-shot action, returns None if node should not continue.
+///
+/// After import node continues with imported blocks, which need to be applied.
+fn process_archive_command(
+    archive_command: &ArchiveCommand,
+    persistent_storage: &PersistentStorage,
+    init_data: &StorageInitInfo,
+    log: &Logger,
+) -> Option<Vec<BlockHash>> {
+    match archive_command {
+        ArchiveCommand::Export {
+            path,
+            from_level,
+            block_hash,
+        } => {
+            let block_hash = match block_hash {
+                Some(block_hash) => block_hash.clone(),
+                None => ChainMetaStorage::new(persistent_storage)
+                    .get_current_head(&init_data.chain_id)
+                    .expect("Failed to read current head")
+                    .expect("Storage does not contain any current head to export")
+                    .block_hash()
+                    .clone(),
+            };
+            let file = File::create(path).expect("Failed to create block archive file");
+            match export_archive(
+                persistent_storage,
+                &init_data.chain_id,
+                &block_hash,
+                *from_level,
+                BufWriter::new(file),
+                log,
+            ) {
+                Ok(_) => {
+                    info!(log, "Block archive exported successfully"; "path" => format!("{:?}", path))
+                }
+                Err(e) => {
+                    error!(log, "Failed to export block archive"; "path" => format!("{:?}", path), "reason" => e)
+                }
+            }
+            None
+        }
+        ArchiveCommand::Import { path } => {
+            let file = File::open(path).expect("Failed to open block archive file");
+            match import_archive(
+                persistent_storage,
+                &init_data.chain_id,
+                BufReader::new(file),
+                log,
+            ) {
+                Ok(imported) => {
+                    info!(log, "Block archive imported successfully"; "path" => format!("{:?}", path), "blocks_to_apply" => imported.blocks_to_apply().len());
+                    Some(imported.into_blocks_to_apply())
+                }
+                Err(e) => {
+                    error!(log, "Failed to import block archive"; "path" => format!("{:?}", path), "reason" => e);
+                    None
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Read-only storage replica - node process, which just serves RPCs from storage owned by another (primary) node process.
+//!
+//! Replica opens RocksDB databases as secondary instances and commit logs and context store read-only,
+//! then it periodically catches up with the primary and propagates new current head to the RPC server.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use riker::actors::*;
+use slog::{debug, info, warn, Logger};
+
+use crypto::hash::{BlockHash, ChainId};
+use networking::ShellCompatibilityVersion;
+use rpc::rpc_actor::RpcServer;
+use shell::mempool::init_mempool_state_storage;
+use shell::shell_channel::{ShellChannel, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::TezedgeContext;
+use storage::initializer::{
+    initialize_merge_operators_secondary, initialize_merkle_replica, initialize_rocksdb_secondary,
+    MainChain,
+};
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{open_cl_read_only, CommitLogSchema};
+use storage::{
+    BlockStorage, BlockStorageReader, ChainMetaStorage, PersistentStorage, StorageError,
+    StorageInitInfo,
+};
+use tezos_api::environment::TezosEnvironmentConfiguration;
+
+use crate::configuration::{Environment, StorageReplica};
+
+/// Opens storage of the primary node process for reading, nothing is written to it
+pub fn open_replica_storage(
+    env: &Environment,
+    replica: &StorageReplica,
+    main_chain: &MainChain,
+    log: &Logger,
+) -> PersistentStorage {
+    let kv = initialize_rocksdb_secondary(
+        log,
+        &env.storage.db,
+        &replica.secondary_path.join("db"),
+        main_chain,
+    )
+    .expect("Failed to open RocksDB database (db) as secondary instance - is the primary node initialized?");
+    let merge_secondary = initialize_merge_operators_secondary(
+        &env.storage.db,
+        &replica.secondary_path.join("db_meta"),
+    )
+    .expect("Failed to open RocksDB database (db) metadata as secondary instance");
+
+    let commit_logs = Arc::new(
+        open_cl_read_only(
+            &env.storage.db_path,
+            vec![BlockStorage::descriptor()],
+            &env.storage.commit_log,
+        )
+        .expect("Failed to open plain block_header storage for reading"),
+    );
+    let sequences = Arc::new(Sequences::new(kv.clone(), 1000));
+
+    let merkle = Arc::new(Mutex::new(
+        initialize_merkle_replica(
+            &env.storage.context_kv_store,
+            &replica.secondary_path.join("context"),
+            main_chain,
+            log,
+        )
+        .expect("Failed to open merkle storage as replica"),
+    ));
+
+    let merkle_context_actions_store =
+        env.storage
+            .merkle_context_actions_store
+            .as_ref()
+            .map(|merkle_context_actions_store| {
+                initialize_rocksdb_secondary(
+                    log,
+                    merkle_context_actions_store,
+                    &replica.secondary_path.join("context_actions"),
+                    main_chain,
+                )
+                .expect(
+                    "Failed to open RocksDB database (db_context_actions) as secondary instance",
+                )
+            });
+
+    PersistentStorage::new_replica(
+        kv,
+        merge_secondary,
+        commit_logs,
+        sequences,
+        merkle,
+        merkle_context_actions_store,
+    )
+}
+
+/// Runs just RPC server and storage synchronization until ctrl-c
+pub fn block_on_replica(
+    env: Environment,
+    replica: StorageReplica,
+    tezos_env: &TezosEnvironmentConfiguration,
+    init_storage_data: StorageInitInfo,
+    persistent_storage: PersistentStorage,
+    tezedge_context: TezedgeContext,
+    log: Logger,
+) {
+    let shell_compatibility_version = ShellCompatibilityVersion::new(
+        tezos_env.version.clone(),
+        shell::SUPPORTED_DISTRIBUTED_DB_VERSION.to_vec(),
+        shell::SUPPORTED_P2P_VERSION.to_vec(),
+    );
+
+    info!(log, "Initializing protocol runners... (4/5)");
+    let tezos_readonly_api_pool = Arc::new(
+        crate::create_tezos_readonly_api_pool(
+            "tezos_readonly_api_pool",
+            env.ffi.tezos_readonly_api_pool.clone(),
+            &env,
+            tezos_env.clone(),
+            log.clone(),
+        )
+        .expect("Failed to initialize read-only API pool"),
+    );
+    let tezos_readonly_prevalidation_api_pool = Arc::new(
+        crate::create_tezos_readonly_api_pool(
+            "tezos_readonly_prevalidation_api",
+            env.ffi.tezos_readonly_prevalidation_api_pool.clone(),
+            &env,
+            tezos_env.clone(),
+            log.clone(),
+        )
+        .expect("Failed to initialize read-only prevalidation API pool"),
+    );
+    let tezos_without_context_api_pool = Arc::new(
+        crate::create_tezos_without_context_api_pool(
+            "tezos_without_context_api_pool",
+            env.ffi.tezos_without_context_api_pool.clone(),
+            &env,
+            tezos_env.clone(),
+            log.clone(),
+        )
+        .expect("Failed to initialize API pool without context"),
+    );
+    info!(log, "Protocol runners initialized");
+
+    info!(log, "Initializing RPC server... (5/5)");
+    let tokio_runtime = crate::create_tokio_runtime(&env).expect("Failed to create tokio runtime");
+    let actor_system = SystemBuilder::new()
+        .name("light-node-replica")
+        .log(log.clone())
+        .create()
+        .expect("Failed to create actor system");
+    let shell_channel = ShellChannel::actor(&actor_system).expect("Failed to create shell channel");
+
+    let _ = RpcServer::actor(
+        &actor_system,
+        shell_channel.clone(),
+        ([0, 0, 0, 0], env.rpc.listener_port).into(),
+        tokio_runtime.handle(),
+        &persistent_storage,
+        init_mempool_state_storage(),
+        &tezedge_context,
+        tezos_readonly_api_pool.clone(),
+        tezos_readonly_prevalidation_api_pool.clone(),
+        tezos_without_context_api_pool.clone(),
+        tezos_env.clone(),
+        Arc::new(shell_compatibility_version.to_network_version()),
+        &init_storage_data,
+    )
+    .expect("Failed to create RPC server");
+
+    let running = Arc::new(AtomicBool::new(true));
+    let sync_thread = spawn_sync_thread(
+        persistent_storage.clone(),
+        init_storage_data.chain_id.clone(),
+        shell_channel,
+        replica.sync_interval,
+        running.clone(),
+        log.clone(),
+    )
+    .expect("Failed to start storage replica sync thread");
+    info!(log, "Storage replica initialized"; "sync_interval_ms" => replica.sync_interval.as_millis() as u64);
+
+    tokio_runtime.block_on(async move {
+        use tokio::signal;
+        use tokio::time::timeout;
+
+        signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c event");
+        info!(log, "Ctrl-c or SIGINT received!");
+
+        info!(log, "Stopping storage replica sync (1/4)");
+        running.store(false, Ordering::Release);
+        if sync_thread.join().is_err() {
+            warn!(log, "Storage replica sync thread panicked");
+        }
+
+        info!(log, "Shutting down actors (2/4)");
+        match timeout(Duration::from_secs(10), actor_system.shutdown()).await {
+            Ok(_) => info!(log, "Shutdown actors complete"),
+            Err(_) => info!(log, "Shutdown actors did not finish to timeout (10s)"),
+        };
+
+        info!(log, "Shutting down protocol runner pools (3/4)");
+        drop(tezos_readonly_api_pool);
+        drop(tezos_readonly_prevalidation_api_pool);
+        drop(tezos_without_context_api_pool);
+        drop(persistent_storage);
+
+        info!(log, "Shutdown complete (4/4)");
+    });
+}
+
+/// Periodically catches up storage with the primary node and notifies RPC server about new current head
+fn spawn_sync_thread(
+    persistent_storage: PersistentStorage,
+    chain_id: ChainId,
+    shell_channel: ShellChannelRef,
+    sync_interval: Duration,
+    running: Arc<AtomicBool>,
+    log: Logger,
+) -> std::io::Result<JoinHandle<()>> {
+    std::thread::Builder::new()
+        .name("storage-replica-sync".to_string())
+        .spawn(move || {
+            let chain_meta_storage = ChainMetaStorage::new(&persistent_storage);
+            let block_storage = BlockStorage::new(&persistent_storage);
+            let mut current_head: Option<BlockHash> = None;
+
+            while running.load(Ordering::Acquire) {
+                std::thread::sleep(sync_interval);
+
+                if let Err(e) = persistent_storage.sync_with_primary() {
+                    warn!(log, "Failed to sync storage replica with primary node"; "reason" => e);
+                    continue;
+                }
+
+                let head = match chain_meta_storage.get_current_head(&chain_id) {
+                    Ok(Some(head)) => head,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!(log, "Failed to read current head of primary node"; "reason" => e);
+                        continue;
+                    }
+                };
+                if current_head.as_ref() == Some(head.block_hash()) {
+                    continue;
+                }
+
+                let block = match block_storage
+                    .get(head.block_hash())
+                    .and_then(|block| block.ok_or(StorageError::MissingKey))
+                {
+                    Ok(block) => Arc::new(block),
+                    Err(e) => {
+                        warn!(log, "Failed to read current head block of primary node"; "reason" => e);
+                        continue;
+                    }
+                };
+                debug!(log, "Storage replica caught up with new current head"; "block_hash" => head.block_hash().to_base58_check(), "level" => head.level());
+                current_head = Some(head.block_hash().clone());
+                shell_channel.tell(
+                    Publish {
+                        msg: ShellChannelMsg::NewCurrentHead(head, block),
+                        topic: ShellChannelTopic::ShellNewCurrentHead.into(),
+                    },
+                    None,
+                );
+            }
+        })
+}
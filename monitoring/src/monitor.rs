@@ -262,7 +262,11 @@ impl Receive<NetworkChannelMsg> for Monitor {
                 let key = peer_id.peer_ref.uri();
                 let previous = self.peer_monitors.insert(
                     key.clone(),
-                    PeerMonitor::new(peer_id.peer_address, peer_id.peer_id_marker.clone()),
+                    PeerMonitor::new(
+                        peer_id.peer_address,
+                        peer_id.peer_id_marker.clone(),
+                        peer_id.connection_state.clone(),
+                    ),
                 );
                 if let Some(previous) = previous {
                     warn!(ctx.system.log(), "Duplicate monitor found for peer"; "key" => key.to_string(), "peer_address" => previous.peer_address());
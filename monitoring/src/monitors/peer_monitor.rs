@@ -1,7 +1,9 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{net::SocketAddr, time::Instant};
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+use networking::p2p::peer::PeerConnectionState;
 
 use crate::websocket::handler_messages::PeerMetrics;
 
@@ -14,10 +16,19 @@ pub(crate) struct PeerMonitor {
     current_transferred: usize,
     last_update: Instant,
     first_update: Instant,
+
+    /// Raw (encrypted) byte counters of the connection, used to show the throttled bandwidth
+    connection_state: Arc<PeerConnectionState>,
+    last_bytes_sent: u64,
+    last_bytes_received: u64,
 }
 
 impl PeerMonitor {
-    pub fn new(peer_addr: SocketAddr, public_key: String) -> Self {
+    pub fn new(
+        peer_addr: SocketAddr,
+        public_key: String,
+        connection_state: Arc<PeerConnectionState>,
+    ) -> Self {
         let now = Instant::now();
         Self {
             peer_address: peer_addr,
@@ -26,6 +37,9 @@ impl PeerMonitor {
             current_transferred: 0,
             last_update: now,
             first_update: now,
+            last_bytes_sent: connection_state.bytes_sent(),
+            last_bytes_received: connection_state.bytes_received(),
+            connection_state,
         }
     }
 
@@ -37,22 +51,41 @@ impl PeerMonitor {
         self.current_transferred as f32 / self.last_update.elapsed().as_secs_f32()
     }
 
+    /// Returns current (upload, download) speed in bytes per second
+    fn current_bandwidth(&self) -> (f32, f32) {
+        let elapsed = self.last_update.elapsed().as_secs_f32();
+        let sent = self
+            .connection_state
+            .bytes_sent()
+            .saturating_sub(self.last_bytes_sent);
+        let received = self
+            .connection_state
+            .bytes_received()
+            .saturating_sub(self.last_bytes_received);
+        (sent as f32 / elapsed, received as f32 / elapsed)
+    }
+
     pub fn incoming_bytes(&mut self, incoming: usize) {
         self.total_transferred += incoming;
         self.current_transferred += incoming
     }
 
     pub fn snapshot(&mut self) -> PeerMetrics {
+        let (current_upload_speed, current_download_speed) = self.current_bandwidth();
         let ret = PeerMetrics::new(
             self.public_key.clone(),
             self.peer_address(),
             self.total_transferred,
             self.avg_speed(),
             self.current_speed(),
+            current_upload_speed,
+            current_download_speed,
         );
 
         self.current_transferred = 0;
         self.last_update = Instant::now();
+        self.last_bytes_sent = self.connection_state.bytes_sent();
+        self.last_bytes_received = self.connection_state.bytes_received();
         ret
     }
 
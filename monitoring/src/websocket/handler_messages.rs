@@ -86,6 +86,10 @@ pub struct PeerMetrics {
     transferred_bytes: usize,
     average_transfer_speed: f32,
     current_transfer_speed: f32,
+    /// Bytes per second sent to the peer since the last snapshot
+    current_upload_speed: f32,
+    /// Bytes per second received from the peer since the last snapshot
+    current_download_speed: f32,
 }
 
 impl PeerMetrics {
@@ -95,6 +99,8 @@ impl PeerMetrics {
         transferred_bytes: usize,
         average_transfer_speed: f32,
         current_transfer_speed: f32,
+        current_upload_speed: f32,
+        current_download_speed: f32,
     ) -> Self {
         Self {
             public_key,
@@ -102,6 +108,8 @@ impl PeerMetrics {
             transferred_bytes,
             average_transfer_speed,
             current_transfer_speed,
+            current_upload_speed,
+            current_download_speed,
         }
     }
 }
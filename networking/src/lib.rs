@@ -12,7 +12,7 @@ use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::ack::NackMotive;
 use tezos_messages::p2p::encoding::prelude::NetworkVersion;
 
-use crate::p2p::peer::PeerRef;
+use crate::p2p::peer::{PeerConnectionState, PeerRef};
 
 pub mod p2p;
 
@@ -26,6 +26,8 @@ pub struct PeerId {
     pub peer_id_marker: String,
     /// Peer address
     pub peer_address: SocketAddr,
+    /// Live state of the connection (counters, policy)
+    pub connection_state: Arc<PeerConnectionState>,
 }
 
 impl PeerId {
@@ -34,12 +36,14 @@ impl PeerId {
         peer_public_key_hash: CryptoboxPublicKeyHash,
         peer_id_marker: String,
         peer_address: SocketAddr,
+        connection_state: Arc<PeerConnectionState>,
     ) -> Self {
         Self {
             peer_ref,
             peer_public_key_hash,
             peer_id_marker,
             peer_address,
+            connection_state,
         }
     }
 }
@@ -6,3 +6,4 @@
 pub mod network_channel;
 pub mod peer;
 pub mod stream;
+pub mod throttle;
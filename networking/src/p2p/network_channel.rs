@@ -11,6 +11,7 @@ use riker::actors::*;
 use tezos_messages::p2p::encoding::advertise::AdvertiseMessage;
 use tezos_messages::p2p::encoding::metadata::MetadataMessage;
 use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+use tezos_messages::p2p::encoding::swap::SwapMessage;
 
 use crate::PeerId;
 
@@ -45,6 +46,10 @@ pub enum NetworkChannelMsg {
     BlacklistPeer(Arc<PeerId>, String),
     ProcessAdvertisedPeers(Arc<PeerId>, AdvertiseMessage),
     SendBootstrapPeers(Arc<PeerId>),
+    /// Peer proposes to replace one of our connections by connection to the point from the message
+    ProcessSwapRequest(Arc<PeerId>, SwapMessage),
+    /// Peer accepted our swap request and proposes the point from the message
+    ProcessSwapAck(Arc<PeerId>, SwapMessage),
     ProcessFailedBootstrapAddress(PeerBootstrapFailed),
 }
 
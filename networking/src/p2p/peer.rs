@@ -1,11 +1,13 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryFrom;
 use std::fmt;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use failure::{Error, Fail};
 use futures::lock::Mutex;
@@ -23,22 +25,27 @@ use crypto::{
 };
 use crypto::{
     crypto_box::PublicKeyError,
-    hash::{CryptoboxPublicKeyHash, Hash},
+    hash::{BlockHash, CryptoboxPublicKeyHash, Hash},
 };
 use crypto::{
     nonce::{self, Nonce, NoncePair},
     proof_of_work::check_proof_of_work,
 };
 use tezos_encoding::{binary_reader::BinaryReaderError, binary_writer::BinaryWriterError};
-use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryRead, BinaryWrite};
+use tezos_messages::p2p::binary_message::{
+    BinaryChunk, BinaryChunkError, BinaryRead, BinaryWrite, MessageHash,
+};
 use tezos_messages::p2p::encoding::ack::{NackInfo, NackMotive};
+use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::p2p::network_channel::NetworkChannelMsg;
 use crate::{LocalPeerInfo, PeerId};
 
 use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerMessageReceived};
+use super::stream::TransferredBytes;
 use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStream, StreamError};
+use super::throttle::{BandwidthLimiter, MessagePriority, Throttle};
 
 const IO_TIMEOUT: Duration = Duration::from_secs(6);
 /// There is a 90-second timeout for ping peers with GetCurrentHead
@@ -146,6 +153,8 @@ pub struct Bootstrap {
     incoming: bool,
     disable_mempool: bool,
     private_node: bool,
+    /// Creates bandwidth throttles for the connection
+    bandwidth_limiter: Arc<BandwidthLimiter>,
 }
 
 impl Bootstrap {
@@ -154,6 +163,7 @@ impl Bootstrap {
         address: SocketAddr,
         disable_mempool: bool,
         private_node: bool,
+        bandwidth_limiter: Arc<BandwidthLimiter>,
     ) -> Self {
         Bootstrap {
             stream,
@@ -161,6 +171,7 @@ impl Bootstrap {
             incoming: true,
             disable_mempool,
             private_node,
+            bandwidth_limiter,
         }
     }
 
@@ -169,6 +180,7 @@ impl Bootstrap {
         address: SocketAddr,
         disable_mempool: bool,
         private_node: bool,
+        bandwidth_limiter: Arc<BandwidthLimiter>,
     ) -> Self {
         Bootstrap {
             stream: Arc::new(Mutex::new(Some(stream))),
@@ -176,6 +188,7 @@ impl Bootstrap {
             incoming: false,
             disable_mempool,
             private_node,
+            bandwidth_limiter,
         }
     }
 }
@@ -193,6 +206,212 @@ impl SendMessage {
     }
 }
 
+#[derive(Debug, Fail)]
+#[fail(display = "Invalid peer policy - {}", _0)]
+pub struct InvalidPeerPolicyError(String);
+
+/// Policy applied to the connection with the concrete peer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeerPolicy {
+    /// Do not send our mempool to the peer
+    pub disable_mempool_relay: bool,
+    /// Max count of messages accepted from the peer per second, messages above the limit are dropped
+    pub max_messages_per_sec: Option<u32>,
+}
+
+impl FromStr for PeerPolicy {
+    type Err = InvalidPeerPolicyError;
+
+    /// Parses comma separated list of rules, e.g. `disable-mempool-relay,max-msg-rate=100`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = PeerPolicy::default();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let mut key_value = rule.splitn(2, '=');
+            match (key_value.next(), key_value.next()) {
+                (Some("disable-mempool-relay"), None) => policy.disable_mempool_relay = true,
+                (Some("max-msg-rate"), Some(rate)) => {
+                    policy.max_messages_per_sec = Some(rate.trim().parse().map_err(|_| {
+                        InvalidPeerPolicyError(format!("invalid max-msg-rate: {}", rate))
+                    })?)
+                }
+                _ => {
+                    return Err(InvalidPeerPolicyError(format!(
+                        "unsupported rule: {}",
+                        rule
+                    )))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for PeerPolicy {
+    /// Formats comma separated list of rules, which can be parsed back
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules = vec![];
+        if self.disable_mempool_relay {
+            rules.push("disable-mempool-relay".to_string());
+        }
+        if let Some(max_messages_per_sec) = self.max_messages_per_sec {
+            rules.push(format!("max-msg-rate={}", max_messages_per_sec));
+        }
+        write!(f, "{}", rules.join(","))
+    }
+}
+
+/// Counts messages in one second windows, used to enforce [`PeerPolicy::max_messages_per_sec`]
+struct MessageRateLimiter {
+    window_start: Instant,
+    count: u32,
+}
+
+impl MessageRateLimiter {
+    fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Returns false, if message exceeds the limit and should be dropped
+    fn allow(&mut self, max_messages_per_sec: Option<u32>) -> bool {
+        let max_messages_per_sec = match max_messages_per_sec {
+            Some(max_messages_per_sec) => max_messages_per_sec,
+            None => return true,
+        };
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        self.count <= max_messages_per_sec
+    }
+}
+
+/// Current flows are computed from bytes transferred at least during this interval
+const FLOW_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bytes transferred at the time of the last sample with flows computed from the previous sample
+#[derive(Debug)]
+struct FlowSample {
+    at: Instant,
+    bytes_received: u64,
+    bytes_sent: u64,
+    inflow: u64,
+    outflow: u64,
+}
+
+/// Live state of the connection to the remote peer, which is shared with the peer manager
+#[derive(Debug)]
+pub struct PeerConnectionState {
+    /// When the connection was bootstrapped
+    connected_since: SystemTime,
+    bytes_received: TransferredBytes,
+    bytes_sent: TransferredBytes,
+    /// Hash and level of the last current head sent by the peer
+    current_head: RwLock<Option<(BlockHash, Level)>>,
+    /// Policy can be changed by the peer manager during the connection lifetime
+    policy: RwLock<PeerPolicy>,
+    /// Limits upload bandwidth (download is limited by the reader)
+    upload_throttle: Throttle,
+    flow_sample: std::sync::Mutex<FlowSample>,
+}
+
+impl PeerConnectionState {
+    pub fn new(bytes_received: TransferredBytes, bytes_sent: TransferredBytes) -> Self {
+        let flow_sample = FlowSample {
+            at: Instant::now(),
+            bytes_received: bytes_received.load(Ordering::Relaxed),
+            bytes_sent: bytes_sent.load(Ordering::Relaxed),
+            inflow: 0,
+            outflow: 0,
+        };
+        Self {
+            connected_since: SystemTime::now(),
+            bytes_received,
+            bytes_sent,
+            current_head: RwLock::new(None),
+            policy: RwLock::new(PeerPolicy::default()),
+            upload_throttle: Throttle::default(),
+            flow_sample: std::sync::Mutex::new(flow_sample),
+        }
+    }
+
+    pub fn with_upload_throttle(mut self, upload_throttle: Throttle) -> Self {
+        self.upload_throttle = upload_throttle;
+        self
+    }
+
+    pub fn policy(&self) -> PeerPolicy {
+        self.policy.read().map(|policy| *policy).unwrap_or_default()
+    }
+
+    pub fn set_policy(&self, new_policy: PeerPolicy) {
+        if let Ok(mut policy) = self.policy.write() {
+            *policy = new_policy;
+        }
+    }
+
+    pub fn connected_since(&self) -> SystemTime {
+        self.connected_since
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns current `(inflow, outflow)` in bytes per second.
+    ///
+    /// Flows are computed from the bytes transferred since the previous sample, new sample is taken, if the previous one
+    /// is older than [FLOW_SAMPLE_INTERVAL]. Octez updates flows every second in the background,
+    /// here they are updated on demand, so the first call after a long time returns average of that time.
+    pub fn current_flows(&self) -> (u64, u64) {
+        let mut sample = match self.flow_sample.lock() {
+            Ok(sample) => sample,
+            Err(_) => return (0, 0),
+        };
+        let elapsed = sample.at.elapsed();
+        if elapsed >= FLOW_SAMPLE_INTERVAL {
+            let (bytes_received, bytes_sent) = (self.bytes_received(), self.bytes_sent());
+            let secs = elapsed.as_secs_f64();
+            sample.inflow =
+                (bytes_received.saturating_sub(sample.bytes_received) as f64 / secs) as u64;
+            sample.outflow = (bytes_sent.saturating_sub(sample.bytes_sent) as f64 / secs) as u64;
+            sample.at = Instant::now();
+            sample.bytes_received = bytes_received;
+            sample.bytes_sent = bytes_sent;
+        }
+        (sample.inflow, sample.outflow)
+    }
+
+    pub fn current_head(&self) -> Option<(BlockHash, Level)> {
+        self.current_head
+            .read()
+            .ok()
+            .and_then(|current_head| current_head.clone())
+    }
+
+    fn update_current_head(&self, current_head: &CurrentHeadMessage) {
+        let header = current_head.current_block_header();
+        let block_hash = match header
+            .message_hash()
+            .ok()
+            .and_then(|hash| BlockHash::try_from(hash).ok())
+        {
+            Some(block_hash) => block_hash,
+            None => return,
+        };
+        if let Ok(mut current_head) = self.current_head.write() {
+            *current_head = Some((block_hash, header.level()));
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Network {
     /// Message receiver boolean indicating whether
@@ -204,6 +423,8 @@ struct Network {
     rx: Arc<Mutex<Option<EncryptedMessageReader>>>,
     /// Socket address of the peer
     socket_address: SocketAddr,
+    /// Live state of the connection
+    connection_state: Arc<PeerConnectionState>,
 }
 
 pub type PeerRef = ActorRef<PeerMsg>;
@@ -251,6 +472,7 @@ impl ActorFactoryArgs<(NetworkChannelRef, Handle, BootstrapOutput)> for Peer {
                 tx: info.1,
                 rx: info.0,
                 socket_address: info.6,
+                connection_state: info.7,
             },
             tokio_executor,
             peer_public_key_hash: info.2,
@@ -280,7 +502,7 @@ impl Actor for Peer {
 
         self.tokio_executor.spawn(async move {
             // prepare PeerId
-            let peer_id = Arc::new(PeerId::new(myself.clone(), peer_public_key_hash, peer_id_marker, net.socket_address));
+            let peer_id = Arc::new(PeerId::new(myself.clone(), peer_public_key_hash, peer_id_marker, net.socket_address, net.connection_state.clone()));
             let log = {
                 let myself_name = myself.name().to_string();
                 let myself_uri = myself.uri().to_string();
@@ -318,13 +540,32 @@ impl Receive<SendMessage> for Peer {
         let system = ctx.system.clone();
         let myself = ctx.myself();
         let tx = self.net.tx.clone();
+        let connection_state = self.net.connection_state.clone();
         let peer_id_marker = self.peer_id_marker.clone();
 
         self.tokio_executor.spawn(async move {
+            let message_bytes = match msg.message.as_bytes() {
+                Ok(message_bytes) => message_bytes,
+                Err(e) => {
+                    warn!(system.log(), "Failed to serialize message"; "reason" => format!("{}", e), "msg" => format!("{:?}", msg.message.as_ref()),
+                                        "peer_id" => peer_id_marker, "peer" => myself.name(), "peer_uri" => myself.uri().to_string());
+                    return;
+                }
+            };
+
+            // wait for the bandwidth before locking the writer, so high priority messages can overtake
+            connection_state
+                .upload_throttle
+                .consume(
+                    message_bytes.len(),
+                    MessagePriority::of(msg.message.message()),
+                )
+                .await;
+
             let mut tx_lock = tx.lock().await;
             if let Some(tx) = tx_lock.as_mut() {
                 let write_result =
-                    timeout(IO_TIMEOUT, tx.write_message(msg.message.as_ref())).await;
+                    timeout(IO_TIMEOUT, tx.write_message_bytes(&message_bytes)).await;
                 // release mutex as soon as possible
                 drop(tx_lock);
 
@@ -357,6 +598,7 @@ pub struct BootstrapOutput(
     pub MetadataMessage,
     pub NetworkVersion,
     pub SocketAddr,
+    pub Arc<PeerConnectionState>,
 );
 
 impl fmt::Debug for BootstrapOutput {
@@ -369,6 +611,7 @@ impl fmt::Debug for BootstrapOutput {
             peer_metadata,
             peer_compatible_network_version,
             peer_address,
+            _,
         ) = self;
         let peer_public_key_hash: &Hash = peer_public_key_hash.as_ref();
         f.debug_tuple("BootstrapOutput")
@@ -476,6 +719,8 @@ pub async fn bootstrap(
     // from now on all messages will be encrypted
     let mut msg_rx =
         EncryptedMessageReader::new(msg_rx, precomputed_key.clone(), nonce_remote, log.clone());
+    let (upload_throttle, download_throttle) = msg.bandwidth_limiter.connection_throttles();
+    msg_rx.set_throttle(download_throttle);
     let mut msg_tx = EncryptedMessageWriter::new(msg_tx, precomputed_key, nonce_local, log.clone());
 
     // send metadata
@@ -533,6 +778,10 @@ pub async fn bootstrap(
     match ack_received {
         AckMessage::Ack => {
             debug!(log, "Received ACK");
+            let connection_state = Arc::new(
+                PeerConnectionState::new(msg_rx.bytes_received(), msg_tx.bytes_sent())
+                    .with_upload_throttle(upload_throttle),
+            );
             Ok(BootstrapOutput(
                 Arc::new(Mutex::new(Some(msg_rx))),
                 Arc::new(Mutex::new(Some(msg_tx))),
@@ -541,6 +790,7 @@ pub async fn bootstrap(
                 metadata_received,
                 compatible_network_version,
                 msg.address,
+                connection_state,
             ))
         }
         AckMessage::NackV0 => {
@@ -579,10 +829,18 @@ async fn begin_process_incoming(
     let mut rx = rx
         .take()
         .expect("Someone took ownership of the encrypted reader before the Peer");
+    let mut rate_limiter = MessageRateLimiter::new();
     while net.rx_run.load(Ordering::Acquire) {
         match timeout(READ_TIMEOUT_LONG, rx.read_message::<PeerMessageResponse>()).await {
             Ok(res) => match res {
                 Ok(msg) => {
+                    if !rate_limiter.allow(net.connection_state.policy().max_messages_per_sec) {
+                        trace!(log, "Message rate limit exceeded - dropping message");
+                        continue;
+                    }
+                    if let PeerMessage::CurrentHead(current_head) = msg.message() {
+                        net.connection_state.update_current_head(current_head);
+                    }
                     let should_broadcast_message = net.rx_run.load(Ordering::Acquire);
                     if should_broadcast_message {
                         trace!(log, "Message parsed successfully"; "msg" => format!("{:?}", &msg));
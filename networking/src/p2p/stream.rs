@@ -7,6 +7,8 @@
 
 use std::convert::TryInto;
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use bytes::Buf;
 use failure::_core::time::Duration;
@@ -25,10 +27,15 @@ use tezos_messages::p2p::binary_message::{
     BinaryChunk, BinaryChunkError, BinaryMessage, SizeFromChunk, CONTENT_LENGTH_FIELD_BYTES,
 };
 
+use super::throttle::{Prioritized, Throttle};
+
 /// Max allowed content length in bytes when taking into account extra data added by encryption
 pub const CONTENT_LENGTH_MAX: usize =
     tezos_messages::p2p::binary_message::CONTENT_LENGTH_MAX - crypto::crypto_box::BOX_ZERO_BYTES;
 
+/// Count of bytes transferred by the encrypted stream (encrypted chunks including their length bytes)
+pub type TransferredBytes = Arc<AtomicU64>;
+
 /// This is common error that might happen when communicating with peer over the network.
 #[derive(Debug, Fail)]
 pub enum StreamError {
@@ -219,6 +226,8 @@ pub struct EncryptedMessageWriterBase<W> {
     tx: MessageWriterBase<W>,
     /// To encrypt data
     crypto: Crypto,
+    /// Count of sent bytes
+    bytes_sent: TransferredBytes,
     /// Logger
     log: Logger,
 }
@@ -236,16 +245,26 @@ impl<W: AsyncWrite + Unpin> EncryptedMessageWriterBase<W> {
                 precomputed_key,
                 nonce: nonce_local,
             },
+            bytes_sent: Arc::new(AtomicU64::new(0)),
             log,
         }
     }
 
+    /// Returns shared counter of sent bytes
+    pub fn bytes_sent(&self) -> TransferredBytes {
+        self.bytes_sent.clone()
+    }
+
     pub async fn write_message<'a>(
         &'a mut self,
         message: &'a impl BinaryMessage,
     ) -> Result<(), StreamError> {
-        let message_bytes = message.as_bytes()?;
-        trace!(self.log, "Writing message"; "message" => FnValue(|_| hex::encode(&message_bytes)));
+        self.write_message_bytes(&message.as_bytes()?).await
+    }
+
+    /// Writes already serialized message, e.g. when the message size is needed before write
+    pub async fn write_message_bytes(&mut self, message_bytes: &[u8]) -> Result<(), StreamError> {
+        trace!(self.log, "Writing message"; "message" => FnValue(|_| hex::encode(message_bytes)));
 
         for chunk_content_bytes in message_bytes.chunks(CONTENT_LENGTH_MAX) {
             let message_bytes_encrypted = match self.crypto.encrypt(&chunk_content_bytes) {
@@ -256,6 +275,8 @@ impl<W: AsyncWrite + Unpin> EncryptedMessageWriterBase<W> {
             // send
             let chunk = BinaryChunk::from_content(&message_bytes_encrypted)?;
             self.tx.write_message(&chunk).await?;
+            self.bytes_sent
+                .fetch_add(chunk.raw().len() as u64, Ordering::Relaxed);
         }
 
         Ok(())
@@ -271,6 +292,10 @@ pub struct EncryptedMessageReaderBase<A> {
     crypto: Crypto,
     /// Incoming message reader
     rx: MessageReaderBase<A>,
+    /// Count of received bytes
+    bytes_received: TransferredBytes,
+    /// Limits download bandwidth
+    throttle: Throttle,
     /// Logger
     log: Logger,
 }
@@ -289,21 +314,37 @@ impl<A: AsyncRead + Unpin + Send> EncryptedMessageReaderBase<A> {
                 precomputed_key,
                 nonce: nonce_remote,
             },
+            bytes_received: Arc::new(AtomicU64::new(0)),
+            throttle: Throttle::default(),
             log,
         }
     }
 
+    /// Sets download bandwidth throttle, received message with normal priority is returned after the bandwidth is available
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    /// Returns shared counter of received bytes
+    pub fn bytes_received(&self) -> TransferredBytes {
+        self.bytes_received.clone()
+    }
+
     /// Consume content of inner message reader into specific message
     pub async fn read_message<M>(&mut self) -> Result<M, StreamError>
     where
-        M: BinaryMessage + SizeFromChunk,
+        M: BinaryMessage + SizeFromChunk + Prioritized,
     {
         let mut input_size = 0;
         let mut input_data = vec![];
+        let mut input_raw_size = 0;
 
-        loop {
+        let message = loop {
             // read
             let message_encrypted = self.rx.read_message().await?;
+            self.bytes_received
+                .fetch_add(message_encrypted.raw().len() as u64, Ordering::Relaxed);
+            input_raw_size += message_encrypted.raw().len();
 
             // decrypt
             match self.crypto.decrypt(&message_encrypted.content()) {
@@ -317,16 +358,23 @@ impl<A: AsyncRead + Unpin + Send> EncryptedMessageReaderBase<A> {
 
                     if input_size <= input_data.len() {
                         match M::from_bytes(&input_data) {
-                            Ok(message) => break Ok(message),
-                            Err(e) => break Err(e.into()),
+                            Ok(message) => break message,
+                            Err(e) => return Err(e.into()),
                         }
                     }
                 }
                 Err(error) => {
-                    break Err(StreamError::FailedToDecryptMessage { error });
+                    return Err(StreamError::FailedToDecryptMessage { error });
                 }
             }
-        }
+        };
+
+        // priority is known only for the whole decoded message, so all its chunks are charged at once
+        self.throttle
+            .consume(input_raw_size, message.priority())
+            .await;
+
+        Ok(message)
     }
 }
 
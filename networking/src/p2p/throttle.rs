@@ -0,0 +1,287 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Token bucket bandwidth throttling of the p2p connections.
+//!
+//! Every connection has its own upload and download buckets (if per-peer limit is configured)
+//! and shares global buckets with all other connections (if global limit is configured).
+//! Transferred bytes are always charged to the buckets, so buckets can get into debt,
+//! but only messages with [`MessagePriority::Normal`] wait until the debt is paid.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tezos_messages::p2p::encoding::prelude::{
+    AckMessage, MetadataMessage, PeerMessage, PeerMessageResponse,
+};
+
+/// Bandwidth limits in kilobits per second (1 kbps = 125 bytes per second), `None` means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BandwidthLimits {
+    /// Upload limit shared by all connections
+    pub max_upload_kbps: Option<u64>,
+    /// Download limit shared by all connections
+    pub max_download_kbps: Option<u64>,
+    /// Upload limit of a single connection
+    pub max_peer_upload_kbps: Option<u64>,
+    /// Download limit of a single connection
+    pub max_peer_download_kbps: Option<u64>,
+}
+
+fn kbps_to_bytes_per_sec(kbps: u64) -> f64 {
+    kbps as f64 * 1000.0 / 8.0
+}
+
+/// Priority of the transferred message, high priority messages never wait for the bandwidth
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessagePriority {
+    /// Small messages important for the chain synchronization (heads, branches) and p2p maintenance
+    High,
+    /// Bulk messages (block headers, operations, protocols)
+    Normal,
+}
+
+impl MessagePriority {
+    pub fn of(message: &PeerMessage) -> Self {
+        match message {
+            PeerMessage::Disconnect
+            | PeerMessage::Advertise(_)
+            | PeerMessage::SwapRequest(_)
+            | PeerMessage::SwapAck(_)
+            | PeerMessage::Bootstrap
+            | PeerMessage::GetCurrentBranch(_)
+            | PeerMessage::CurrentBranch(_)
+            | PeerMessage::Deactivate(_)
+            | PeerMessage::GetCurrentHead(_)
+            | PeerMessage::CurrentHead(_) => MessagePriority::High,
+            _ => MessagePriority::Normal,
+        }
+    }
+}
+
+/// Received message, which can be classified for the download throttling
+pub trait Prioritized {
+    fn priority(&self) -> MessagePriority;
+}
+
+impl Prioritized for PeerMessageResponse {
+    fn priority(&self) -> MessagePriority {
+        MessagePriority::of(self.message())
+    }
+}
+
+/// Handshake messages are small and the connection cannot continue without them
+impl Prioritized for MetadataMessage {
+    fn priority(&self) -> MessagePriority {
+        MessagePriority::High
+    }
+}
+
+impl Prioritized for AckMessage {
+    fn priority(&self) -> MessagePriority {
+        MessagePriority::High
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucketState {
+    /// Available bytes, negative value means debt
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Bucket is refilled continuously with `rate` bytes per second up to the capacity of one second of transfer
+#[derive(Debug)]
+pub struct TokenBucket {
+    /// Bytes per second
+    rate: f64,
+    state: Mutex<TokenBucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            rate: bytes_per_sec,
+            state: Mutex::new(TokenBucketState {
+                tokens: bytes_per_sec,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Bucket limited by kilobits per second
+    pub fn with_kbps(kbps: u64) -> Self {
+        Self::new(kbps_to_bytes_per_sec(kbps))
+    }
+
+    /// Takes `bytes` from the bucket, returns how long to wait until the debt is paid
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        self.reserve_at(bytes, Instant::now())
+    }
+
+    fn reserve_at(&self, bytes: usize, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::from_secs(0);
+        }
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let elapsed = now
+            .checked_duration_since(state.last_refill)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        state.last_refill = cmp::max(state.last_refill, now);
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+}
+
+/// Throttles one direction of the connection by its own bucket and the global bucket
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    fn new(global: Option<Arc<TokenBucket>>, peer_kbps: Option<u64>) -> Self {
+        let mut buckets = Vec::with_capacity(2);
+        if let Some(peer_kbps) = peer_kbps {
+            buckets.push(Arc::new(TokenBucket::with_kbps(peer_kbps)));
+        }
+        if let Some(global) = global {
+            buckets.push(global);
+        }
+        Self { buckets }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Charges transferred bytes, waits for the bandwidth, if the message has normal priority
+    pub async fn consume(&self, bytes: usize, priority: MessagePriority) {
+        let delay = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.reserve(bytes))
+            .max()
+            .unwrap_or_else(|| Duration::from_secs(0));
+        if priority == MessagePriority::Normal && delay > Duration::from_secs(0) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Global buckets shared by all connections and configuration of per connection buckets
+#[derive(Debug, Default)]
+pub struct BandwidthLimiter {
+    limits: BandwidthLimits,
+    upload: Option<Arc<TokenBucket>>,
+    download: Option<Arc<TokenBucket>>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self {
+            limits,
+            upload: limits
+                .max_upload_kbps
+                .map(|kbps| Arc::new(TokenBucket::with_kbps(kbps))),
+            download: limits
+                .max_download_kbps
+                .map(|kbps| Arc::new(TokenBucket::with_kbps(kbps))),
+        }
+    }
+
+    pub fn limits(&self) -> &BandwidthLimits {
+        &self.limits
+    }
+
+    /// Creates upload and download throttle for a new connection
+    pub fn connection_throttles(&self) -> (Throttle, Throttle) {
+        (
+            Throttle::new(self.upload.clone(), self.limits.max_peer_upload_kbps),
+            Throttle::new(self.download.clone(), self.limits.max_peer_download_kbps),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tezos_messages::p2p::encoding::prelude::GetOperationsForBlocksMessage;
+
+    #[test]
+    fn test_token_bucket_debt() {
+        // 1000 bytes per second
+        let bucket = TokenBucket::new(1000.0);
+        let start = bucket.state.lock().unwrap().last_refill;
+
+        // full bucket, no wait
+        assert_eq!(bucket.reserve_at(1000, start), Duration::from_secs(0));
+        // debt of 500 bytes is paid in a half of second
+        assert_eq!(bucket.reserve_at(500, start), Duration::from_millis(500));
+        // after a second, 1000 bytes were refilled
+        assert_eq!(
+            bucket.reserve_at(500, start + Duration::from_secs(1)),
+            Duration::from_secs(0)
+        );
+        // capacity is limited to one second of transfer
+        assert_eq!(
+            bucket.reserve_at(2000, start + Duration::from_secs(10)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_bandwidth_limiter_throttles() {
+        let limiter = BandwidthLimiter::default();
+        let (upload, download) = limiter.connection_throttles();
+        assert!(upload.is_unlimited());
+        assert!(download.is_unlimited());
+
+        let limiter = BandwidthLimiter::new(BandwidthLimits {
+            max_upload_kbps: Some(800),
+            max_download_kbps: None,
+            max_peer_upload_kbps: Some(80),
+            max_peer_download_kbps: Some(80),
+        });
+        let (upload, download) = limiter.connection_throttles();
+        assert_eq!(upload.buckets.len(), 2);
+        assert_eq!(download.buckets.len(), 1);
+        // 80 kbps = 10 000 bytes per second
+        assert_eq!(upload.buckets[0].rate, 10_000.0);
+        assert_eq!(upload.buckets[1].rate, 100_000.0);
+
+        // global bucket is shared
+        let (other_upload, _) = limiter.connection_throttles();
+        assert!(Arc::ptr_eq(&upload.buckets[1], &other_upload.buckets[1]));
+        assert!(!Arc::ptr_eq(&upload.buckets[0], &other_upload.buckets[0]));
+    }
+
+    #[test]
+    fn test_message_priority() {
+        assert_eq!(
+            MessagePriority::of(&PeerMessage::Bootstrap),
+            MessagePriority::High
+        );
+        assert_eq!(
+            MessagePriority::of(&PeerMessage::Disconnect),
+            MessagePriority::High
+        );
+        assert_eq!(
+            MessagePriority::of(&PeerMessage::GetOperationsForBlocks(
+                GetOperationsForBlocksMessage::new(vec![])
+            )),
+            MessagePriority::Normal
+        );
+    }
+}
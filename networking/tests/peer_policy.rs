@@ -0,0 +1,58 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use networking::p2p::peer::{PeerConnectionState, PeerPolicy};
+
+#[test]
+fn test_parse_peer_policy() {
+    assert_eq!("".parse::<PeerPolicy>().unwrap(), PeerPolicy::default());
+    assert_eq!(
+        "disable-mempool-relay".parse::<PeerPolicy>().unwrap(),
+        PeerPolicy {
+            disable_mempool_relay: true,
+            max_messages_per_sec: None,
+        }
+    );
+    assert_eq!(
+        "max-msg-rate=100, disable-mempool-relay"
+            .parse::<PeerPolicy>()
+            .unwrap(),
+        PeerPolicy {
+            disable_mempool_relay: true,
+            max_messages_per_sec: Some(100),
+        }
+    );
+
+    assert!("max-msg-rate=".parse::<PeerPolicy>().is_err());
+    assert!("max-msg-rate=-1".parse::<PeerPolicy>().is_err());
+    assert!("disable-mempool-relay=true".parse::<PeerPolicy>().is_err());
+    assert!("unknown".parse::<PeerPolicy>().is_err());
+}
+
+#[test]
+fn test_format_peer_policy() {
+    assert_eq!(PeerPolicy::default().to_string(), "");
+
+    let policy = PeerPolicy {
+        disable_mempool_relay: true,
+        max_messages_per_sec: Some(100),
+    };
+    assert_eq!(policy.to_string(), "disable-mempool-relay,max-msg-rate=100");
+    assert_eq!(policy.to_string().parse::<PeerPolicy>().unwrap(), policy);
+}
+
+#[test]
+fn test_connection_state_policy() {
+    let state = PeerConnectionState::new(Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    assert_eq!(state.policy(), PeerPolicy::default());
+
+    let policy = PeerPolicy {
+        disable_mempool_relay: false,
+        max_messages_per_sec: Some(5),
+    };
+    state.set_policy(policy);
+    assert_eq!(state.policy(), policy);
+}
@@ -2,7 +2,10 @@
 // SPDX-License-Identifier: MIT
 
 use failure::Error;
+use std::time::{Duration, Instant};
+
 use networking::p2p::stream::{EncryptedMessageReaderBase, MessageReaderBase};
+use networking::p2p::throttle::{BandwidthLimiter, BandwidthLimits, Throttle};
 use tezos_messages::p2p::binary_message::BinaryWrite;
 use tezos_messages::p2p::encoding::limits::BLOCK_HEADER_MAX_SIZE;
 use tezos_messages::p2p::{
@@ -12,7 +15,7 @@ use tezos_messages::p2p::{
         swap::SwapMessage,
     },
 };
-use tokio_test::io::Builder;
+use tokio_test::io::{Builder, Mock};
 
 pub mod common;
 
@@ -161,3 +164,132 @@ async fn can_read_message_block_header_small_chunks() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn read_message_is_throttled() -> Result<(), Error> {
+    let crypto_mock = CryptoMock::new();
+
+    let crypto_remote = crypto_mock.remote;
+    let mut peer_mock = PeerMock::new(crypto_remote.precompute_key, crypto_remote.nonce_pair.local);
+
+    let messages = vec![
+        block_header_message_encoded(1024),
+        block_header_message_encoded(1024),
+    ];
+    for message in messages.iter() {
+        peer_mock.incoming_message(message.clone());
+    }
+
+    let reader = MessageReaderBase {
+        stream: peer_mock.get_mock(),
+    };
+    let crypto_local = crypto_mock.local;
+    let mut reader = EncryptedMessageReaderBase::new(
+        reader,
+        crypto_local.precompute_key,
+        crypto_local.nonce_pair.remote,
+        new_log(),
+    );
+    // 8 kbps = 1000 bytes per second
+    let (_, download) = BandwidthLimiter::new(BandwidthLimits {
+        max_peer_download_kbps: Some(8),
+        ..Default::default()
+    })
+    .connection_throttles();
+    reader.set_throttle(download);
+
+    // throttle sleeps with tokio timer
+    let runtime = tokio::runtime::Runtime::new()?;
+    let started = Instant::now();
+    runtime.block_on(async {
+        for message in messages {
+            let recv_message = reader.read_message::<PeerMessageResponse>().await?;
+            assert_eq!(message, recv_message.as_bytes()?);
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    // more than 2000 bytes were received, so the second message waits at least for a second
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[test]
+fn read_message_high_priority_is_not_throttled() -> Result<(), Error> {
+    // 8 kbps = 1000 bytes per second, throttle is shared like the global download bucket
+    let (_, download) = BandwidthLimiter::new(BandwidthLimits {
+        max_download_kbps: Some(8),
+        ..Default::default()
+    })
+    .connection_throttles();
+
+    let bulk_message = block_header_message_encoded(2048);
+    let high_priority_message = PeerMessageResponse::from(PeerMessage::Bootstrap).as_bytes()?;
+    let normal_priority_message = block_header_message_encoded(1);
+    let mut bulk_reader = throttled_reader(&bulk_message, download.clone());
+    let mut high_priority_reader = throttled_reader(&high_priority_message, download.clone());
+    let mut normal_priority_reader = throttled_reader(&normal_priority_message, download);
+
+    // throttle sleeps with tokio timer
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        // bulk traffic gets the bucket into the debt and waits for it to be paid
+        let bulk = async {
+            let started = Instant::now();
+            let recv_message = bulk_reader.read_message::<PeerMessageResponse>().await?;
+            assert_eq!(bulk_message, recv_message.as_bytes()?);
+            Ok::<Duration, Error>(started.elapsed())
+        };
+        let others = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            // high priority message is not delayed behind the bulk traffic
+            let started = Instant::now();
+            let recv_message = high_priority_reader
+                .read_message::<PeerMessageResponse>()
+                .await?;
+            assert_eq!(high_priority_message, recv_message.as_bytes()?);
+            let high_priority_elapsed = started.elapsed();
+
+            // normal priority message waits for the debt to be paid
+            let started = Instant::now();
+            let recv_message = normal_priority_reader
+                .read_message::<PeerMessageResponse>()
+                .await?;
+            assert_eq!(normal_priority_message, recv_message.as_bytes()?);
+            Ok::<(Duration, Duration), Error>((high_priority_elapsed, started.elapsed()))
+        };
+
+        let (bulk_elapsed, others_elapsed) = futures::join!(bulk, others);
+        let (high_priority_elapsed, normal_priority_elapsed) = others_elapsed?;
+        assert!(bulk_elapsed? >= Duration::from_secs(1));
+        assert!(high_priority_elapsed < Duration::from_millis(100));
+        assert!(normal_priority_elapsed >= Duration::from_millis(500));
+
+        Ok::<(), Error>(())
+    })?;
+
+    Ok(())
+}
+
+fn throttled_reader(message: &[u8], throttle: Throttle) -> EncryptedMessageReaderBase<Mock> {
+    let crypto_mock = CryptoMock::new();
+
+    let crypto_remote = crypto_mock.remote;
+    let mut peer_mock = PeerMock::new(crypto_remote.precompute_key, crypto_remote.nonce_pair.local);
+    peer_mock.incoming_message(message.to_vec());
+
+    let reader = MessageReaderBase {
+        stream: peer_mock.get_mock(),
+    };
+    let crypto_local = crypto_mock.local;
+    let mut reader = EncryptedMessageReaderBase::new(
+        reader,
+        crypto_local.precompute_key,
+        crypto_local.nonce_pair.remote,
+        new_log(),
+    );
+    reader.set_throttle(throttle);
+    reader
+}
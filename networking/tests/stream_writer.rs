@@ -1,11 +1,13 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::sync::atomic::Ordering;
+
 use common::block_header_message_encoded;
 use failure::Error;
 use networking::p2p::stream::{EncryptedMessageWriterBase, MessageWriterBase};
 use tezos_messages::p2p::{
-    binary_message::BinaryChunk,
+    binary_message::{BinaryChunk, CONTENT_LENGTH_FIELD_BYTES},
     encoding::{
         peer::{PeerMessage, PeerMessageResponse},
         swap::SwapMessage,
@@ -77,6 +79,12 @@ async fn can_write_message_swap() -> Result<(), Error> {
 
     writer.write_message(&message).await?;
 
+    // encrypted content with length bytes and authentication tag
+    assert_eq!(
+        (message.as_bytes()?.len() + CONTENT_LENGTH_FIELD_BYTES + 16) as u64,
+        writer.bytes_sent().load(Ordering::Relaxed)
+    );
+
     Ok(())
 }
 
@@ -14,6 +14,8 @@ use serde_json::Value;
 use crypto::hash::{BlockHash, ChainId, ContextHash, ProtocolHash};
 use shell::mempool::mempool_prevalidator::MempoolPrevalidator;
 use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::merkle::merkle_storage::SubtreeEntry;
+use storage::context::ContextKeysPage;
 use storage::{
     BlockAdditionalData, BlockHeaderWithHash, BlockJsonData, BlockMetaStorage,
     BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage,
@@ -22,6 +24,7 @@ use tezos_api::ffi::{RpcMethod, RpcRequest};
 use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
 use tezos_messages::ts_to_rfc3339;
+use tezos_messages::Head;
 
 use crate::encoding::base_types::UniString;
 use crate::server::{HasSingleValue, Query, RpcServiceEnvironment};
@@ -148,6 +151,31 @@ pub struct BlockHeaderShellInfo {
     pub context: String,
 }
 
+/// Object containing block hash and level, e.g. for checkpoint, savepoint or caboose
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockLevelInfo {
+    pub block_hash: String,
+    pub level: Level,
+}
+
+impl From<&Head> for BlockLevelInfo {
+    fn from(head: &Head) -> Self {
+        BlockLevelInfo {
+            block_hash: head.block_hash().to_base58_check(),
+            level: *head.level(),
+        }
+    }
+}
+
+/// Object containing checkpoint block with the save_point/caboose levels and history mode
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckpointInfo {
+    pub block: BlockHeaderShellInfo,
+    pub save_point: Level,
+    pub caboose: Level,
+    pub history_mode: String,
+}
+
 impl BlockHeaderShellInfo {
     pub fn new(block: &BlockHeaderWithHash) -> Self {
         BlockHeaderShellInfo {
@@ -268,6 +296,45 @@ where
     }
 }
 
+/// Page of the raw context keys, continued by passing `next_after` as `after` query parameter.
+#[derive(Debug, Serialize)]
+pub struct ContextRawBytesPage {
+    /// Keys (relative to `/data`) with hex encoded values, subtrees at the depth limit have no value
+    data: Vec<ContextRawBytesEntry>,
+    /// Last key of this page if more keys are available, otherwise `None`.
+    next_after: Option<String>,
+    /// Limit used in the request which produced this page.
+    limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextRawBytesEntry {
+    key: String,
+    value: Option<String>,
+}
+
+impl ContextRawBytesPage {
+    /// Creates page from keys under the `/data` context root
+    pub fn new(page: ContextKeysPage, limit: usize) -> Self {
+        let data_key_to_string = |key: &[String]| key[1..].join("/");
+        Self {
+            data: page
+                .entries
+                .into_iter()
+                .map(|(key, entry)| ContextRawBytesEntry {
+                    key: data_key_to_string(&key),
+                    value: match entry {
+                        SubtreeEntry::Blob(blob) => Some(hex::encode(blob)),
+                        SubtreeEntry::Tree => None,
+                    },
+                })
+                .collect(),
+            next_after: page.next_after.map(|key| data_key_to_string(&key)),
+            limit,
+        }
+    }
+}
+
 // TODO: refactor errors
 /// Struct is defining Error message response, there are different keys is these messages so only needed one are defined for each message
 #[derive(Serialize, Debug, Clone)]
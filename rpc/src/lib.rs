@@ -77,6 +77,30 @@ pub(crate) fn result_to_json_response<T: serde::Serialize>(
     }
 }
 
+/// Returns result as a plain text response with `content_type`.
+pub(crate) fn result_to_text_response(
+    res: Result<String, failure::Error>,
+    content_type: &'static str,
+    log: &Logger,
+) -> ServiceResult {
+    match res {
+        Ok(text) => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, content_type)
+            .header(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+            .header(hyper::header::ACCESS_CONTROL_ALLOW_HEADERS, "content-type")
+            .header(
+                hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
+                "GET, POST, OPTIONS, PUT",
+            )
+            .body(Body::from(text))?),
+        Err(err) => {
+            error!(log, "Failed to execute RPC function"; "reason" => format!("{:?}", &err));
+            error(err)
+        }
+    }
+}
+
 /// Returns optional result as a JSON response.
 pub(crate) fn result_option_to_json_response<T: serde::Serialize>(
     res: Result<Option<T>, failure::Error>,
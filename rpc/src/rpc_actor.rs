@@ -93,6 +93,7 @@ impl RpcServer {
                 init_storage_data.genesis_block_header_hash.clone(),
                 shared_state,
                 init_storage_data.one_context,
+                init_storage_data.operations_index,
                 &sys.log(),
             );
             let inner_log = sys.log();
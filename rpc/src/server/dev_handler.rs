@@ -8,7 +8,11 @@ use slog::warn;
 use crate::helpers::{parse_block_hash, parse_chain_id, MAIN_CHAIN_ID};
 use crate::server::{HasSingleValue, Params, Query, RpcServiceEnvironment};
 use crate::services::dev_services;
-use crate::{empty, make_json_response, required_param, result_to_json_response, ServiceResult};
+use crate::{
+    empty, make_json_response, make_json_stream_response, required_param,
+    result_option_to_json_response, result_to_json_response, result_to_text_response,
+    ServiceResult,
+};
 
 pub async fn dev_blocks(
     _: Request<Body>,
@@ -81,6 +85,34 @@ pub async fn dev_contract_actions(
     )
 }
 
+pub async fn dev_operation_location(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let operation_hash = required_param!(params, "operation_hash")?;
+    result_option_to_json_response(
+        dev_services::get_operation_location(operation_hash, &env),
+        env.log(),
+    )
+}
+
+pub async fn dev_account_operations(
+    _: Request<Body>,
+    params: Params,
+    query: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let account_address = required_param!(params, "account_address")?;
+    let cursor_id = query.get_u64("cursor_id");
+    let limit = query.get_usize("limit").unwrap_or(50);
+    result_to_json_response(
+        dev_services::get_account_operations(account_address, cursor_id, limit, &env),
+        env.log(),
+    )
+}
+
 pub async fn dev_action_cursor(
     _: Request<Body>,
     params: Params,
@@ -154,6 +186,33 @@ pub async fn dev_stats_storage(
     )
 }
 
+/// Storage metrics - reads, writes and latencies per column family and context store, sizes of column families
+pub async fn dev_stats_storage_metrics(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(
+        crate::services::stats_services::get_storage_metrics(env.persistent_storage()),
+        env.log(),
+    )
+}
+
+/// Storage metrics in Prometheus text exposition format
+pub async fn dev_stats_storage_prometheus(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_text_response(
+        crate::services::stats_services::get_storage_metrics_prometheus(env.persistent_storage()),
+        "text/plain; version=0.0.4",
+        env.log(),
+    )
+}
+
 pub async fn dev_stats_memory(
     _: Request<Body>,
     _: Params,
@@ -196,6 +255,31 @@ pub async fn context_stats(
     )
 }
 
+/// Streams changes of the context between two blocks, one JSON object per line
+pub async fn dev_context_diff(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(MAIN_CHAIN_ID, &env)?;
+    let from_block_hash =
+        parse_block_hash(&chain_id, required_param!(params, "from_block_id")?, &env)?;
+    let to_block_hash = parse_block_hash(&chain_id, required_param!(params, "to_block_id")?, &env)?;
+    let prefix = params.get_str("any");
+
+    match dev_services::get_context_diff(&from_block_hash, &to_block_hash, prefix, &env) {
+        Ok(changes) => make_json_stream_response(futures::stream::iter(changes.map(
+            |change| -> Result<String, failure::Error> {
+                let mut line = serde_json::to_string(&change?)?;
+                line.push('\n');
+                Ok(line)
+            },
+        ))),
+        Err(e) => result_to_json_response::<()>(Err(e), env.log()),
+    }
+}
+
 /// Get the version string
 pub async fn dev_version(
     _: Request<Body>,
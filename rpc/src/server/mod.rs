@@ -68,6 +68,8 @@ pub struct RpcServiceEnvironment {
 
     // TODO: TE-447 - remove one_context when integration done
     pub one_context: bool,
+    /// Operations index RPCs are enabled
+    pub operations_index: bool,
 }
 
 impl RpcServiceEnvironment {
@@ -87,6 +89,7 @@ impl RpcServiceEnvironment {
         main_chain_genesis_hash: BlockHash,
         state: RpcCollectedStateRef,
         one_context: bool,
+        operations_index: bool,
         log: &Logger,
     ) -> Self {
         Self {
@@ -106,6 +109,7 @@ impl RpcServiceEnvironment {
             tezos_readonly_prevalidation_api,
             tezos_without_context_api,
             one_context,
+            operations_index,
         }
     }
 }
@@ -118,11 +122,11 @@ pub type HResult = Result<Response<Body>, Box<dyn std::error::Error + Sync + Sen
 
 pub type Handler = Arc<
     dyn Fn(
-        Request<Body>,
-        Params,
-        Query,
-        RpcServiceEnvironment,
-    ) -> Box<dyn Future<Output = HResult> + Send>
+            Request<Body>,
+            Params,
+            Query,
+            RpcServiceEnvironment,
+        ) -> Box<dyn Future<Output = HResult> + Send>
         + Send
         + Sync,
 >;
@@ -146,7 +150,7 @@ pub fn spawn_server(
     bind_address: &SocketAddr,
     env: RpcServiceEnvironment,
 ) -> impl Future<Output = Result<(), hyper::Error>> {
-    let routes = Arc::new(router::create_routes(env.one_context));
+    let routes = Arc::new(router::create_routes(env.one_context, env.operations_index));
 
     hyper::Server::bind(bind_address)
         .serve(make_service_fn(move |_| {
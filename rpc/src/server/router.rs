@@ -23,7 +23,7 @@ macro_rules! hash_set {
     };
 }
 
-pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
+pub(crate) fn create_routes(one_context: bool, operations_index: bool) -> PathTree<MethodHandler> {
     let mut routes = PathTree::<MethodHandler>::new();
 
     // Shell rpc - implemented
@@ -67,6 +67,26 @@ pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
         "/chains/:chain_id/chain_id",
         shell_handler::get_chain_id,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/checkpoint",
+        shell_handler::get_checkpoint,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/levels/checkpoint",
+        shell_handler::get_levels_checkpoint,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/levels/savepoint",
+        shell_handler::get_levels_savepoint,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/chains/:chain_id/levels/caboose",
+        shell_handler::get_levels_caboose,
+    );
     routes.handle(
         hash_set![Method::GET],
         "/chains/:chain_id/blocks",
@@ -173,6 +193,16 @@ pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
             "/chains/:chain_id/blocks/:block_id/context/raw/bytes/*any",
             shell_handler::context_raw_bytes,
         );
+        routes.handle(
+            hash_set![Method::GET],
+            "/chains/:chain_id/blocks/:block_id/context/merkle_tree",
+            shell_handler::context_merkle_tree,
+        );
+        routes.handle(
+            hash_set![Method::GET],
+            "/chains/:chain_id/blocks/:block_id/context/merkle_tree/*any",
+            shell_handler::context_merkle_tree,
+        );
     }
     routes.handle(
         hash_set![Method::GET],
@@ -267,6 +297,30 @@ pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
         "/dev/chains/main/actions/contracts/:contract_address",
         dev_handler::dev_action_cursor,
     );
+    if operations_index {
+        routes.handle(
+            hash_set![Method::GET],
+            "/dev/chains/main/operations/:operation_hash",
+            dev_handler::dev_operation_location,
+        );
+        routes.handle(
+            hash_set![Method::GET],
+            "/dev/chains/main/accounts/:account_address/operations",
+            dev_handler::dev_account_operations,
+        );
+    }
+    if !one_context {
+        routes.handle(
+            hash_set![Method::GET],
+            "/dev/chains/main/context/diff/:from_block_id/:to_block_id",
+            dev_handler::dev_context_diff,
+        );
+        routes.handle(
+            hash_set![Method::GET],
+            "/dev/chains/main/context/diff/:from_block_id/:to_block_id/*any",
+            dev_handler::dev_context_diff,
+        );
+    }
     routes.handle(
         hash_set![Method::GET],
         "/dev/version",
@@ -287,7 +341,16 @@ pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
         "/stats/context",
         dev_handler::context_stats,
     );
-    //routes.handle(hash_set![Method::GET], "/stats/storage", dev_handler::dev_stats_storage);
+    routes.handle(
+        hash_set![Method::GET],
+        "/stats/storage",
+        dev_handler::dev_stats_storage_metrics,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/stats/storage/prometheus",
+        dev_handler::dev_stats_storage_prometheus,
+    );
 
     // DEPRECATED in ocaml but still used by python tests
     routes.handle(
@@ -295,6 +358,96 @@ pub(crate) fn create_routes(one_context: bool) -> PathTree<MethodHandler> {
         "/network/version",
         shell_handler::node_version,
     );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/connections",
+        shell_handler::network_connections,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/connections/:peer_id",
+        shell_handler::network_connection,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/stat",
+        shell_handler::network_stat,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers",
+        shell_handler::network_peers,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id",
+        shell_handler::network_peer,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points",
+        shell_handler::network_points,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point",
+        shell_handler::network_point,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/ban",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/ban",
+        shell_handler::network_point_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/unban",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/unban",
+        shell_handler::network_point_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/trust",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/trust",
+        shell_handler::network_point_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/untrust",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/points/:point/untrust",
+        shell_handler::network_point_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/whitelist",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET],
+        "/network/peers/:peer_id/unwhitelist",
+        shell_handler::network_peer_acl,
+    );
+    routes.handle(
+        hash_set![Method::GET, Method::PUT],
+        "/network/peers/:peer_id/policy",
+        shell_handler::network_peer_policy,
+    );
 
     routes
 }
@@ -10,6 +10,7 @@ use hyper::{Body, Method, Request};
 use serde::Serialize;
 
 use crypto::hash::ProtocolHash;
+use shell::peer_manager::{NetworkAcl, NetworkAclTarget};
 use tezos_api::ffi::ProtocolRpcError;
 use tezos_messages::ts_to_rfc3339;
 use tezos_wrapper::service::{ProtocolError, ProtocolServiceError};
@@ -18,7 +19,7 @@ use crate::helpers::{
     create_rpc_request, parse_async, parse_block_hash, parse_chain_id, MAIN_CHAIN_ID,
 };
 use crate::server::{HResult, HasSingleValue, Params, Query, RpcServiceEnvironment};
-use crate::services::{base_services, stream_services};
+use crate::services::{base_services, network_services, stream_services};
 use crate::{
     empty,
     encoding::{base_types::*, monitor::BootstrapInfo},
@@ -28,6 +29,10 @@ use crate::{
 };
 use storage::BlockHeaderWithHash;
 
+/// Default and max count of keys returned by one page of raw context bytes
+const CONTEXT_RAW_BYTES_DEFAULT_PAGE_LIMIT: usize = 100;
+const CONTEXT_RAW_BYTES_MAX_PAGE_LIMIT: usize = 10_000;
+
 #[derive(Serialize)]
 pub struct ErrorMessage {
     error_type: String,
@@ -258,12 +263,52 @@ pub async fn context_raw_bytes(
     let prefix = params.get_str("any");
     let depth = query.get_usize("depth");
 
+    // big subtrees can be paged by `limit` with `offset` or `after` key (returned as `next_after`),
+    // `offset` is limited by storage::context::MAX_CONTEXT_KEYS_PAGE_OFFSET, so `after` is preferred
+    let after = query.get_str("after");
+    let offset = query.get_usize("offset");
+    let limit = query.get_usize("limit");
+    if after.is_some() || offset.is_some() || limit.is_some() {
+        let limit = limit
+            .unwrap_or(CONTEXT_RAW_BYTES_DEFAULT_PAGE_LIMIT)
+            .max(1)
+            .min(CONTEXT_RAW_BYTES_MAX_PAGE_LIMIT);
+        return result_to_json_response(
+            base_services::get_context_raw_bytes_page(
+                &block_hash,
+                prefix,
+                depth,
+                after,
+                offset.unwrap_or(0),
+                limit,
+                &env,
+            ),
+            env.log(),
+        );
+    }
+
     result_to_json_response(
         base_services::get_context_raw_bytes(&block_hash, prefix, depth, &env),
         env.log(),
     )
 }
 
+pub async fn context_merkle_tree(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+    let block_hash = parse_block_hash(&chain_id, required_param!(params, "block_id")?, &env)?;
+    let path = params.get_str("any");
+
+    result_to_json_response(
+        base_services::get_context_merkle_proof(&block_hash, path, &env),
+        env.log(),
+    )
+}
+
 pub async fn mempool_pending_operations(
     _: Request<Body>,
     params: Params,
@@ -375,6 +420,62 @@ pub async fn get_chain_id(
     result_to_json_response(Ok(chain_id.to_base58_check()), env.log())
 }
 
+pub async fn get_checkpoint(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+
+    result_option_to_json_response(
+        base_services::get_checkpoint(&chain_id, env.persistent_storage()),
+        env.log(),
+    )
+}
+
+pub async fn get_levels_checkpoint(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+
+    result_option_to_json_response(
+        base_services::get_checkpoint_level(&chain_id, env.persistent_storage()),
+        env.log(),
+    )
+}
+
+pub async fn get_levels_savepoint(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+
+    result_option_to_json_response(
+        base_services::get_save_point_level(&chain_id, env.persistent_storage()),
+        env.log(),
+    )
+}
+
+pub async fn get_levels_caboose(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let chain_id = parse_chain_id(required_param!(params, "chain_id")?, &env)?;
+
+    result_option_to_json_response(
+        base_services::get_caboose_level(&chain_id, env.persistent_storage()),
+        env.log(),
+    )
+}
+
 pub async fn get_metadata_hash(
     _: Request<Body>,
     params: Params,
@@ -696,6 +797,143 @@ pub async fn config_user_activated_protocol_overrides(
     )
 }
 
+pub async fn network_connections(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_connections(&env).await, env.log())
+}
+
+pub async fn network_connection(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = network_services::parse_peer_id(required_param!(params, "peer_id")?)?;
+    result_option_to_json_response(
+        network_services::get_connection(&peer_id, &env).await,
+        env.log(),
+    )
+}
+
+pub async fn network_stat(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_stat(&env).await, env.log())
+}
+
+pub async fn network_peers(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_peers(&env).await, env.log())
+}
+
+pub async fn network_peer(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = network_services::parse_peer_id(required_param!(params, "peer_id")?)?;
+    result_option_to_json_response(network_services::get_peer(&peer_id, &env).await, env.log())
+}
+
+pub async fn network_points(
+    _: Request<Body>,
+    _: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    result_to_json_response(network_services::get_points(&env).await, env.log())
+}
+
+pub async fn network_point(
+    _: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = network_services::parse_point(required_param!(params, "point")?)?;
+    result_option_to_json_response(network_services::get_point(&point, &env).await, env.log())
+}
+
+/// Resolves access control change from the last segment of the path (`ban`, `unban`, `trust`, `untrust`)
+fn network_acl_from_path(req: &Request<Body>) -> Result<NetworkAcl, failure::Error> {
+    match req.uri().path().trim_end_matches('/').rsplit('/').next() {
+        Some("ban") => Ok(NetworkAcl::Ban),
+        Some("unban") => Ok(NetworkAcl::Unban),
+        Some("trust") => Ok(NetworkAcl::Trust),
+        Some("untrust") => Ok(NetworkAcl::Untrust),
+        Some("whitelist") => Ok(NetworkAcl::Whitelist),
+        Some("unwhitelist") => Ok(NetworkAcl::Unwhitelist),
+        _ => Err(format_err!(
+            "Unsupported access control: {}",
+            req.uri().path()
+        )),
+    }
+}
+
+pub async fn network_peer_acl(
+    req: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = network_services::parse_peer_id(required_param!(params, "peer_id")?)?;
+    let acl = network_acl_from_path(&req)?;
+    result_to_empty_json_response(
+        network_services::change_network_acl(NetworkAclTarget::Peer(peer_id), acl, &env).await,
+        env.log(),
+    )
+}
+
+/// GET returns policy of the peer, PUT changes it (default policy removes the custom one)
+pub async fn network_peer_policy(
+    req: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let peer_id = network_services::parse_peer_id(required_param!(params, "peer_id")?)?;
+    if req.method() == Method::PUT {
+        let policy_raw = hyper::body::aggregate(req).await?;
+        let policy: network_services::PeerPolicyJson =
+            serde_json::from_reader(&mut policy_raw.reader())?;
+        result_to_empty_json_response(
+            network_services::change_peer_policy(peer_id, policy, &env).await,
+            env.log(),
+        )
+    } else {
+        result_to_json_response(
+            network_services::get_peer_policy(&peer_id, &env).await,
+            env.log(),
+        )
+    }
+}
+
+pub async fn network_point_acl(
+    req: Request<Body>,
+    params: Params,
+    _: Query,
+    env: RpcServiceEnvironment,
+) -> ServiceResult {
+    let point = network_services::parse_point(required_param!(params, "point")?)?;
+    let acl = network_acl_from_path(&req)?;
+    result_to_empty_json_response(
+        network_services::change_network_acl(NetworkAclTarget::Point(point), acl, &env).await,
+        env.log(),
+    )
+}
+
 // TODO: TE-275 - implement correctly - at least for protocol rpcs. This is a 'fake it till you make it' handler
 /// Handler mockin the describe routes in ocaml to be compatible with tezoses python test framework
 pub async fn describe(
@@ -4,23 +4,28 @@
 use failure::bail;
 
 use crypto::hash::{BlockHash, ChainId, ContextHash};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::context::merkle::proof::MerkleProof;
 use storage::context::ContextApi;
 use storage::context::StringTreeEntry;
 use storage::{
     context_key, BlockJsonData, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader, OperationsStorage, OperationsStorageReader,
+    BlockStorageReader, ChainMetaStorage, HistoryMode, OperationsStorage, OperationsStorageReader,
+    SystemStorage,
 };
 use storage::{BlockAdditionalData, PersistentStorage};
 use tezos_messages::p2p::encoding::version::NetworkVersion;
 
 use crate::helpers::{
-    get_context_hash, BlockHeaderInfo, BlockHeaderShellInfo, BlockInfo, BlockMetadata,
-    BlockOperation, BlockOperations, BlockValidationPass, InnerBlockHeader, NodeVersion, Protocols,
+    get_context_hash, BlockHeaderInfo, BlockHeaderShellInfo, BlockInfo, BlockLevelInfo,
+    BlockMetadata, BlockOperation, BlockOperations, BlockValidationPass, CheckpointInfo,
+    ContextRawBytesPage, InnerBlockHeader, NodeVersion, Protocols,
 };
 use crate::server::RpcServiceEnvironment;
 use tezos_api::ffi::ApplyBlockRequest;
 use tezos_messages::p2p::encoding::prelude::OperationsForBlocksMessage;
 use tezos_messages::ts_to_rfc3339;
+use tezos_messages::Head;
 
 pub type BlockOperationsHashes = Vec<String>;
 
@@ -157,6 +162,67 @@ pub(crate) fn get_block_shell_header(
         .map(|header| BlockHeaderShellInfo::new(&header)))
 }
 
+/// Get checkpoint block header with save_point and caboose levels
+pub(crate) fn get_checkpoint(
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<CheckpointInfo>, failure::Error> {
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    let checkpoint = match chain_meta_storage.get_checkpoint(chain_id)? {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(None),
+    };
+    let block = match BlockStorage::new(persistent_storage).get(checkpoint.block_hash())? {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+
+    let level_of = |head: Option<Head>| head.map(|head| *head.level()).unwrap_or(0);
+    let history_mode = SystemStorage::new(persistent_storage.db())
+        .get_history_mode()?
+        .unwrap_or(HistoryMode::Archive);
+
+    Ok(Some(CheckpointInfo {
+        block: BlockHeaderShellInfo::new(&block),
+        save_point: level_of(chain_meta_storage.get_save_point(chain_id)?),
+        caboose: level_of(chain_meta_storage.get_caboose(chain_id)?),
+        history_mode: history_mode.to_string(),
+    }))
+}
+
+/// Get block hash and level of the checkpoint
+pub(crate) fn get_checkpoint_level(
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<BlockLevelInfo>, failure::Error> {
+    Ok(ChainMetaStorage::new(persistent_storage)
+        .get_checkpoint(chain_id)?
+        .as_ref()
+        .map(BlockLevelInfo::from))
+}
+
+/// Get block hash and level of the save_point
+pub(crate) fn get_save_point_level(
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<BlockLevelInfo>, failure::Error> {
+    Ok(ChainMetaStorage::new(persistent_storage)
+        .get_save_point(chain_id)?
+        .as_ref()
+        .map(BlockLevelInfo::from))
+}
+
+/// Get block hash and level of the caboose
+pub(crate) fn get_caboose_level(
+    chain_id: &ChainId,
+    persistent_storage: &PersistentStorage,
+) -> Result<Option<BlockLevelInfo>, failure::Error> {
+    Ok(ChainMetaStorage::new(persistent_storage)
+        .get_caboose(chain_id)?
+        .as_ref()
+        .map(BlockLevelInfo::from))
+}
+
 pub(crate) fn live_blocks(
     _: ChainId,
     block_hash: BlockHash,
@@ -206,6 +272,62 @@ pub(crate) fn get_context_raw_bytes(
         .get_context_tree_by_prefix(&ctx_hash, &key_prefix, depth)?)
 }
 
+/// Get page of the raw context keys under the prefix, keys are returned in lexicographic order.
+/// Prefix and `after` key are relative to "/data" (same as for raw/bytes).
+pub(crate) fn get_context_raw_bytes_page(
+    block_hash: &BlockHash,
+    prefix: Option<&str>,
+    depth: Option<usize>,
+    after: Option<&str>,
+    offset: usize,
+    limit: usize,
+    env: &RpcServiceEnvironment,
+) -> Result<ContextRawBytesPage, failure::Error> {
+    let data_key = |path: Option<&str>| {
+        let mut key = context_key!("data");
+        if let Some(path) = path {
+            key.extend(
+                path.split('/')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string()),
+            );
+        }
+        key
+    };
+    let key_prefix = data_key(prefix);
+    let after = after.map(|after| data_key(Some(after)));
+
+    let ctx_hash = get_context_hash(block_hash, env)?;
+    let page = env.tezedge_context().get_context_keys_page(
+        &ctx_hash,
+        &key_prefix,
+        depth,
+        after.as_ref(),
+        offset,
+        limit,
+    )?;
+    Ok(ContextRawBytesPage::new(page, limit))
+}
+
+pub(crate) fn get_context_merkle_proof(
+    block_hash: &BlockHash,
+    path: Option<&str>,
+    env: &RpcServiceEnvironment,
+) -> Result<MerkleProof, failure::Error> {
+    // we assume that root is at "/data" (same as for raw/bytes)
+    let mut key = context_key!("data");
+    if let Some(path) = path {
+        key.extend(
+            path.split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        );
+    };
+
+    let ctx_hash = get_context_hash(block_hash, env)?;
+    Ok(env.tezedge_context().get_merkle_proof(&ctx_hash, &key)?)
+}
+
 /// Extract the current_protocol and the next_protocol from the block metadata
 pub(crate) fn get_block_protocols(
     _: &ChainId,
@@ -1,25 +1,29 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryFrom;
+
 use failure::bail;
 use serde::Serialize;
 use slog::Logger;
 
-use crypto::hash::{BlockHash, ChainId};
+use crypto::hash::{BlockHash, ChainId, OperationHash};
 use shell::stats::memory::{Memory, MemoryData, MemoryStatsResult};
 use storage::context::actions::context_action_storage::{
     contract_id_to_contract_address_for_index, ContextActionBlockDetails, ContextActionFilters,
     ContextActionJson, ContextActionRecordValue, ContextActionStorageReader, ContextActionType,
 };
 use storage::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
-use storage::context::{ContextApi, TezedgeContext};
+use storage::context::{ContextApi, ContextKeyDiff, TezedgeContext};
+use storage::operations_index_storage::{AccountOperation, MainChainHead, OperationLocation};
 use storage::{
-    BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, PersistentStorage,
+    context_key, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader,
+    OperationsIndexStorage, PersistentStorage,
 };
 use tezos_context::channel::ContextAction;
 use tezos_messages::base::rpc_support::UniversalValue;
 
-use crate::helpers::{BlockMetadata, PagedResult};
+use crate::helpers::{get_context_hash, BlockMetadata, PagedResult};
 use crate::server::RpcServiceEnvironment;
 use crate::services::protocol::get_context_protocol_params;
 
@@ -125,6 +129,101 @@ pub(crate) fn get_contract_actions(
     Ok(PagedResult::new(context_records, next_id, limit))
 }
 
+/// Location of the indexed operation
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct OperationLocationJson {
+    block_hash: String,
+    level: i32,
+    validation_pass: u8,
+    operation_index: u16,
+}
+
+impl From<OperationLocation> for OperationLocationJson {
+    fn from(location: OperationLocation) -> Self {
+        Self {
+            block_hash: location.block_hash().to_base58_check(),
+            level: location.level(),
+            validation_pass: location.validation_pass(),
+            operation_index: location.operation_index(),
+        }
+    }
+}
+
+/// Operation touching the account
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct AccountOperationJson {
+    operation_hash: String,
+    block_hash: String,
+    level: i32,
+    validation_pass: u8,
+    operation_index: u16,
+}
+
+impl From<AccountOperation> for AccountOperationJson {
+    fn from(operation: AccountOperation) -> Self {
+        Self {
+            operation_hash: operation.operation_hash.to_base58_check(),
+            block_hash: operation.block_hash.to_base58_check(),
+            level: operation.level,
+            validation_pass: operation.validation_pass,
+            operation_index: operation.operation_index,
+        }
+    }
+}
+
+/// Operations index returns just operations of the main chain (ancestors of the current head)
+fn main_chain_head(env: &RpcServiceEnvironment) -> Result<MainChainHead, failure::Error> {
+    match env.state().read().unwrap().current_head() {
+        Some(current_head) => Ok(MainChainHead::new(
+            current_head.hash.clone(),
+            current_head.header.level(),
+        )),
+        None => bail!("Head not initialized"),
+    }
+}
+
+/// Find location of the applied operation (on the main chain) in operations index
+pub(crate) fn get_operation_location(
+    operation_hash: &str,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<OperationLocationJson>, failure::Error> {
+    let operation_hash = OperationHash::try_from(operation_hash)?;
+    Ok(OperationsIndexStorage::new(env.persistent_storage())
+        .find_operation(&operation_hash, &main_chain_head(env)?)?
+        .map(OperationLocationJson::from))
+}
+
+/// Get operations (on the main chain) touching the account (implicit or originated) in descending order.
+pub(crate) fn get_account_operations(
+    account_address: &str,
+    cursor_id: Option<u64>,
+    limit: usize,
+    env: &RpcServiceEnvironment,
+) -> Result<PagedResult<Vec<AccountOperationJson>>, failure::Error> {
+    let account_address = contract_id_to_contract_address_for_index(account_address)?;
+    let mut operations = OperationsIndexStorage::new(env.persistent_storage())
+        .get_account_operations(
+            &account_address,
+            cursor_id,
+            limit + 1,
+            &main_chain_head(env)?,
+        )?;
+    let next_id = if operations.len() > limit {
+        operations.last().map(|operation| operation.cursor_id)
+    } else {
+        None
+    };
+    operations.truncate(std::cmp::min(operations.len(), limit));
+    Ok(PagedResult::new(
+        operations
+            .into_iter()
+            .map(AccountOperationJson::from)
+            .collect(),
+        next_id,
+        limit,
+    ))
+}
+
 pub(crate) fn get_stats_memory() -> MemoryStatsResult<MemoryData> {
     let memory = Memory::new();
     memory.get_memory_stats()
@@ -141,6 +240,64 @@ pub(crate) fn get_context_stats(
     Ok(context.get_merkle_stats()?)
 }
 
+/// Change of the context key, key is relative to "/data" and values are hex encoded
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct ContextKeyDiffJson {
+    change: &'static str,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_value: Option<String>,
+}
+
+impl From<ContextKeyDiff> for ContextKeyDiffJson {
+    fn from(diff: ContextKeyDiff) -> Self {
+        let (change, key, old_value, new_value) = match diff {
+            ContextKeyDiff::Added { key, value } => ("added", key, None, Some(value)),
+            ContextKeyDiff::Removed { key, value } => ("removed", key, Some(value), None),
+            ContextKeyDiff::Modified {
+                key,
+                old_value,
+                new_value,
+            } => ("modified", key, Some(old_value), Some(new_value)),
+        };
+        Self {
+            change,
+            // skip "data"
+            key: key[1..].join("/"),
+            old_value: old_value.map(hex::encode),
+            new_value: new_value.map(hex::encode),
+        }
+    }
+}
+
+/// Get added, removed and modified context keys (under /data/prefix) between contexts of two blocks,
+/// changes are looked up lazily (context is not locked for the whole diff)
+pub(crate) fn get_context_diff(
+    from_block_hash: &BlockHash,
+    to_block_hash: &BlockHash,
+    prefix: Option<&str>,
+    env: &RpcServiceEnvironment,
+) -> Result<impl Iterator<Item = Result<ContextKeyDiffJson, failure::Error>>, failure::Error> {
+    let mut key_prefix = context_key!("data");
+    if let Some(prefix) = prefix {
+        key_prefix.extend(
+            prefix
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        );
+    };
+
+    let from = get_context_hash(from_block_hash, env)?;
+    let to = get_context_hash(to_block_hash, env)?;
+    Ok(env
+        .tezedge_context()
+        .diff(&from, &to, &key_prefix)?
+        .map(|change| Ok(ContextKeyDiffJson::from(change?))))
+}
+
 pub(crate) fn get_cycle_length_for_block(
     block_hash: &BlockHash,
     env: &RpcServiceEnvironment,
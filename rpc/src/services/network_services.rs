@@ -0,0 +1,557 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Octez compatible `/network` services, live state is queried from the peer manager, history of peers/points is read from the peer storage.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{bail, format_err};
+use serde::{Deserialize, Serialize};
+
+use crypto::hash::CryptoboxPublicKeyHash;
+use shell::peer_manager::{
+    find_peer_manager, ChangeNetworkAcl, ChangePeerPolicy, GetNetworkState, NetworkAcl,
+    NetworkAclTarget, NetworkState, PeerConnectionInfo, PeerManagerMsg, PeerPolicy,
+};
+use shell::utils::OneshotResultCallback;
+use storage::peer_storage::{ConnectionStats, KnownPeer, KnownPoint};
+use storage::PeerStorage;
+use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
+use tezos_messages::ts_to_rfc3339;
+
+use crate::server::RpcServiceEnvironment;
+
+const NETWORK_STATE_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PointId {
+    addr: String,
+    port: u16,
+}
+
+impl From<&SocketAddr> for PointId {
+    fn from(address: &SocketAddr) -> Self {
+        Self {
+            addr: address.ip().to_string(),
+            port: address.port(),
+        }
+    }
+}
+
+/// Bytes transferred by the connection(s), current flows (bytes per second) are computed from the bytes transferred
+/// since the previous query (see [shell::peer_manager::PeerConnectionInfo] state)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct NetworkStat {
+    total_sent: String,
+    total_recv: String,
+    current_inflow: u64,
+    current_outflow: u64,
+}
+
+impl NetworkStat {
+    fn of_connections<'a, I: IntoIterator<Item = &'a PeerConnectionInfo>>(connections: I) -> Self {
+        let (mut total_sent, mut total_recv, mut current_inflow, mut current_outflow) =
+            (0, 0, 0, 0);
+        for connection in connections {
+            let (inflow, outflow) = connection.state.current_flows();
+            total_sent += connection.state.bytes_sent();
+            total_recv += connection.state.bytes_received();
+            current_outflow += outflow;
+            current_inflow += inflow;
+        }
+        Self {
+            total_sent: total_sent.to_string(),
+            total_recv: total_recv.to_string(),
+            current_inflow,
+            current_outflow,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CurrentHead {
+    block_hash: String,
+    level: i32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnectionInfo {
+    incoming: bool,
+    peer_id: String,
+    id_point: PointId,
+    remote_socket_port: u16,
+    announced_version: NetworkVersion,
+    private: bool,
+    local_metadata: MetadataMessage,
+    remote_metadata: MetadataMessage,
+    connected_since: String,
+    stat: NetworkStat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_head: Option<CurrentHead>,
+}
+
+impl ConnectionInfo {
+    fn new(connection: &PeerConnectionInfo, local_metadata: &MetadataMessage) -> Self {
+        Self {
+            incoming: connection.incoming,
+            peer_id: connection.peer_id_marker.clone(),
+            id_point: PointId::from(&connection.peer_address),
+            remote_socket_port: connection.peer_address.port(),
+            announced_version: connection.network_version.clone(),
+            private: connection.peer_metadata.private_node(),
+            local_metadata: local_metadata.clone(),
+            remote_metadata: connection.peer_metadata.clone(),
+            connected_since: to_rfc3339(connection.state.connected_since()),
+            stat: NetworkStat::of_connections(vec![connection]),
+            current_head: connection
+                .state
+                .current_head()
+                .map(|(block_hash, level)| CurrentHead {
+                    block_hash: block_hash.to_base58_check(),
+                    level,
+                }),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerInfo {
+    score: f64,
+    trusted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conn_metadata: Option<MetadataMessage>,
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reachable_at: Option<PointId>,
+    stat: NetworkStat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_failed_connection: Option<(PointId, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_established_connection: Option<(PointId, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<(PointId, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    greylisted_until: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PointState {
+    event_kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_peer_id: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PointInfo {
+    score: f64,
+    trusted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    greylisted_until: Option<String>,
+    state: PointState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p2p_peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_failed_connection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_established_connection: Option<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<(String, String)>,
+}
+
+/// Policy of the connections with the peer (tezedge specific)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PeerPolicyJson {
+    #[serde(default)]
+    disable_mempool_relay: bool,
+    #[serde(default)]
+    max_messages_per_sec: Option<u32>,
+}
+
+impl From<PeerPolicyJson> for PeerPolicy {
+    fn from(policy: PeerPolicyJson) -> Self {
+        Self {
+            disable_mempool_relay: policy.disable_mempool_relay,
+            max_messages_per_sec: policy.max_messages_per_sec,
+        }
+    }
+}
+
+impl From<PeerPolicy> for PeerPolicyJson {
+    fn from(policy: PeerPolicy) -> Self {
+        Self {
+            disable_mempool_relay: policy.disable_mempool_relay,
+            max_messages_per_sec: policy.max_messages_per_sec,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PeerPolicyInfo {
+    trusted: bool,
+    /// Peer is accepted in private mode
+    whitelisted: bool,
+    #[serde(flatten)]
+    policy: PeerPolicyJson,
+}
+
+fn to_rfc3339(time: SystemTime) -> String {
+    ts_to_rfc3339(
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0),
+    )
+}
+
+fn unix_secs_to_rfc3339(secs: u64) -> String {
+    ts_to_rfc3339(secs as i64)
+}
+
+fn greylisted_until(stats: &ConnectionStats, now: SystemTime) -> Option<String> {
+    stats.greylisted_until_time(now).map(to_rfc3339)
+}
+
+/// Sends request to the peer manager and waits for the result
+async fn ask_peer_manager<T, F>(
+    env: &RpcServiceEnvironment,
+    msg_name: &str,
+    create_msg: F,
+) -> Result<T, failure::Error>
+where
+    T: Send + 'static,
+    F: FnOnce(OneshotResultCallback<T>) -> PeerManagerMsg,
+{
+    let peer_manager = match find_peer_manager(env.sys()) {
+        Some(peer_manager) => peer_manager,
+        None => bail!("Peer manager is not running"),
+    };
+
+    let (result_callback_sender, result_callback_receiver) = std::sync::mpsc::sync_channel(1);
+    if peer_manager
+        .try_tell(
+            create_msg(std::sync::Arc::new(result_callback_sender)),
+            None,
+        )
+        .is_err()
+    {
+        bail!("Peer manager does not support message `{}`!", msg_name);
+    }
+
+    // we spawn as blocking because we are under async/await
+    tokio::task::spawn_blocking(move || {
+        result_callback_receiver.recv_timeout(NETWORK_STATE_WAIT_TIMEOUT)
+    })
+    .await?
+    .map_err(|e| format_err!("Failed to receive result of `{}`, reason: {}", msg_name, e))
+}
+
+/// Queries live network state from the peer manager
+async fn get_network_state(env: &RpcServiceEnvironment) -> Result<NetworkState, failure::Error> {
+    ask_peer_manager(env, "GetNetworkState", |result_callback| {
+        PeerManagerMsg::GetNetworkState(GetNetworkState { result_callback })
+    })
+    .await
+}
+
+/// Changes access control of the peer/point in the peer manager
+pub(crate) async fn change_network_acl(
+    target: NetworkAclTarget,
+    acl: NetworkAcl,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    ask_peer_manager(env, "ChangeNetworkAcl", |result_callback| {
+        PeerManagerMsg::ChangeNetworkAcl(ChangeNetworkAcl {
+            target,
+            acl,
+            result_callback: Some(result_callback),
+        })
+    })
+    .await?
+    .map_err(|e| format_err!("Failed to change access control, reason: {}", e))
+}
+
+/// Changes policy of the connections with the peer, default policy removes the custom one
+pub(crate) async fn change_peer_policy(
+    peer_id: CryptoboxPublicKeyHash,
+    policy: PeerPolicyJson,
+    env: &RpcServiceEnvironment,
+) -> Result<(), failure::Error> {
+    ask_peer_manager(env, "ChangePeerPolicy", |result_callback| {
+        PeerManagerMsg::ChangePeerPolicy(ChangePeerPolicy {
+            peer_id,
+            policy: policy.into(),
+            result_callback: Some(result_callback),
+        })
+    })
+    .await?
+    .map_err(|e| format_err!("Failed to change peer policy, reason: {}", e))
+}
+
+pub(crate) async fn get_peer_policy(
+    peer_id: &CryptoboxPublicKeyHash,
+    env: &RpcServiceEnvironment,
+) -> Result<PeerPolicyInfo, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    Ok(PeerPolicyInfo {
+        trusted: network_state.trusted_peers.contains(peer_id),
+        whitelisted: network_state.private_peer_whitelist.contains(peer_id),
+        policy: network_state
+            .peer_policies
+            .get(peer_id)
+            .cloned()
+            .unwrap_or_default()
+            .into(),
+    })
+}
+
+pub(crate) fn parse_peer_id(peer_id: &str) -> Result<CryptoboxPublicKeyHash, failure::Error> {
+    CryptoboxPublicKeyHash::from_base58_check(peer_id)
+        .map_err(|e| format_err!("Invalid peer_id: {}, reason: {}", peer_id, e))
+}
+
+pub(crate) fn parse_point(point: &str) -> Result<SocketAddr, failure::Error> {
+    point
+        .parse()
+        .map_err(|e| format_err!("Invalid point: {}, reason: {}", point, e))
+}
+
+pub(crate) async fn get_connections(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<ConnectionInfo>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    Ok(network_state
+        .connections
+        .iter()
+        .map(|connection| ConnectionInfo::new(connection, &network_state.local_metadata))
+        .collect())
+}
+
+pub(crate) async fn get_connection(
+    peer_id: &CryptoboxPublicKeyHash,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<ConnectionInfo>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    Ok(network_state
+        .connections
+        .iter()
+        .find(|connection| &connection.peer_public_key_hash == peer_id)
+        .map(|connection| ConnectionInfo::new(connection, &network_state.local_metadata)))
+}
+
+pub(crate) async fn get_stat(env: &RpcServiceEnvironment) -> Result<NetworkStat, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    Ok(NetworkStat::of_connections(&network_state.connections))
+}
+
+fn peer_info(
+    peer_id: &CryptoboxPublicKeyHash,
+    known_peer: Option<&KnownPeer>,
+    network_state: &NetworkState,
+    now: SystemTime,
+) -> PeerInfo {
+    let connections = network_state
+        .connections
+        .iter()
+        .filter(|connection| &connection.peer_public_key_hash == peer_id)
+        .collect::<Vec<_>>();
+    let stats = known_peer.map(|known_peer| known_peer.stats());
+    let last_address = known_peer.and_then(|known_peer| known_peer.last_address().as_ref());
+    let with_last_address = |secs: Option<u64>| {
+        last_address
+            .zip(secs)
+            .map(|(address, secs)| (PointId::from(address), unix_secs_to_rfc3339(secs)))
+    };
+
+    PeerInfo {
+        score: stats.map_or(0, |stats| stats.score()) as f64,
+        trusted: network_state.trusted_peers.contains(peer_id),
+        conn_metadata: connections
+            .first()
+            .map(|connection| connection.peer_metadata.clone()),
+        state: if connections.is_empty() {
+            "disconnected"
+        } else {
+            "running"
+        },
+        reachable_at: connections
+            .first()
+            .filter(|connection| !connection.incoming)
+            .map(|connection| PointId::from(&connection.peer_address)),
+        stat: NetworkStat::of_connections(connections),
+        last_failed_connection: with_last_address(stats.and_then(|stats| stats.last_failed())),
+        last_established_connection: with_last_address(stats.and_then(|stats| stats.last_seen())),
+        last_seen: with_last_address(stats.and_then(|stats| stats.last_seen())),
+        greylisted_until: stats.and_then(|stats| greylisted_until(stats, now)),
+    }
+}
+
+pub(crate) async fn get_peers(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<(String, PeerInfo)>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    let now = SystemTime::now();
+
+    let mut known_peers = PeerStorage::new(env.persistent_storage())
+        .known_peers()?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    // connected and trusted peers are listed, even if they are not stored yet
+    let mut peer_ids = known_peers.keys().cloned().collect::<Vec<_>>();
+    for peer_id in network_state
+        .connections
+        .iter()
+        .map(|connection| &connection.peer_public_key_hash)
+        .chain(network_state.trusted_peers.iter())
+    {
+        if !known_peers.contains_key(peer_id) && !peer_ids.contains(peer_id) {
+            peer_ids.push(peer_id.clone());
+        }
+    }
+
+    Ok(peer_ids
+        .into_iter()
+        .map(|peer_id| {
+            let known_peer = known_peers.remove(&peer_id);
+            let info = peer_info(&peer_id, known_peer.as_ref(), &network_state, now);
+            (peer_id.to_base58_check(), info)
+        })
+        .collect())
+}
+
+pub(crate) async fn get_peer(
+    peer_id: &CryptoboxPublicKeyHash,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<PeerInfo>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    let known_peer = PeerStorage::new(env.persistent_storage()).get_peer(peer_id)?;
+    let is_connected = network_state
+        .connections
+        .iter()
+        .any(|connection| &connection.peer_public_key_hash == peer_id);
+
+    if known_peer.is_none() && !is_connected && !network_state.trusted_peers.contains(peer_id) {
+        return Ok(None);
+    }
+    Ok(Some(peer_info(
+        peer_id,
+        known_peer.as_ref(),
+        &network_state,
+        SystemTime::now(),
+    )))
+}
+
+fn point_info(
+    address: &SocketAddr,
+    known_point: Option<&KnownPoint>,
+    network_state: &NetworkState,
+    now: SystemTime,
+) -> PointInfo {
+    let connection = network_state
+        .connections
+        .iter()
+        .find(|connection| &connection.peer_address == address);
+    let stats = known_point.map(|known_point| known_point.stats());
+    let peer_id = connection
+        .map(|connection| connection.peer_id_marker.clone())
+        .or_else(|| {
+            known_point
+                .and_then(|known_point| known_point.peer_id().as_ref())
+                .map(|peer_id| peer_id.to_base58_check())
+        });
+    let with_peer_id = |secs: Option<u64>| {
+        peer_id
+            .clone()
+            .zip(secs)
+            .map(|(peer_id, secs)| (peer_id, unix_secs_to_rfc3339(secs)))
+    };
+
+    // points blacklisted just in memory (e.g. when storage failed) are reported too
+    let greylisted_until = stats
+        .and_then(|stats| greylisted_until(stats, now))
+        .or_else(|| {
+            network_state
+                .blacklisted_ips
+                .get(&address.ip())
+                .filter(|until| **until > now)
+                .map(|until| to_rfc3339(*until))
+        });
+
+    PointInfo {
+        score: stats.map_or(0, |stats| stats.score()) as f64,
+        trusted: network_state.trusted_points.contains(address),
+        greylisted_until,
+        state: match connection {
+            Some(connection) => PointState {
+                event_kind: "running",
+                p2p_peer_id: Some(connection.peer_id_marker.clone()),
+            },
+            None => PointState {
+                event_kind: "disconnected",
+                p2p_peer_id: None,
+            },
+        },
+        p2p_peer_id: peer_id.clone(),
+        last_failed_connection: stats
+            .and_then(|stats| stats.last_failed())
+            .map(unix_secs_to_rfc3339),
+        last_established_connection: with_peer_id(stats.and_then(|stats| stats.last_seen())),
+        last_seen: with_peer_id(stats.and_then(|stats| stats.last_seen())),
+    }
+}
+
+pub(crate) async fn get_points(
+    env: &RpcServiceEnvironment,
+) -> Result<Vec<(String, PointInfo)>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    let now = SystemTime::now();
+
+    let mut known_points = PeerStorage::new(env.persistent_storage())
+        .known_points()?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    // outgoing connections and trusted points are listed, even if they are not stored yet
+    let mut addresses = known_points.keys().cloned().collect::<Vec<_>>();
+    for address in network_state
+        .connections
+        .iter()
+        .filter(|connection| !connection.incoming)
+        .map(|connection| &connection.peer_address)
+        .chain(network_state.trusted_points.iter())
+    {
+        if !known_points.contains_key(address) && !addresses.contains(address) {
+            addresses.push(*address);
+        }
+    }
+
+    Ok(addresses
+        .into_iter()
+        .map(|address| {
+            let known_point = known_points.remove(&address);
+            let info = point_info(&address, known_point.as_ref(), &network_state, now);
+            (address.to_string(), info)
+        })
+        .collect())
+}
+
+pub(crate) async fn get_point(
+    address: &SocketAddr,
+    env: &RpcServiceEnvironment,
+) -> Result<Option<PointInfo>, failure::Error> {
+    let network_state = get_network_state(env).await?;
+    let known_point = PeerStorage::new(env.persistent_storage()).get_point(address)?;
+    let is_connected = network_state
+        .connections
+        .iter()
+        .any(|connection| &connection.peer_address == address);
+
+    if known_point.is_none() && !is_connected && !network_state.trusted_points.contains(address) {
+        return Ok(None);
+    }
+    Ok(Some(point_info(
+        address,
+        known_point.as_ref(),
+        &network_state,
+        SystemTime::now(),
+    )))
+}
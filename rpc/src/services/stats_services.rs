@@ -10,6 +10,9 @@ use rayon::iter::ParallelIterator;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::BlockHash;
+use storage::persistent::metrics::{
+    store_metrics_snapshot, to_prometheus_text, DatabaseStats, StoreMetricsSnapshot,
+};
 use storage::PersistentStorage;
 use storage::{BlockStorage, BlockStorageReader};
 use tezos_context::channel::ContextAction;
@@ -288,3 +291,27 @@ pub(crate) fn compute_storage_stats<'a>(
             .expect("Unable to access the stat contents of mutex!"),
     })
 }
+
+#[derive(Serialize)]
+pub struct StorageMetrics {
+    databases: Vec<DatabaseStats>,
+    stores: Vec<StoreMetricsSnapshot>,
+}
+
+pub(crate) fn get_storage_metrics(
+    persistent_storage: &PersistentStorage,
+) -> Result<StorageMetrics, failure::Error> {
+    Ok(StorageMetrics {
+        databases: persistent_storage.database_stats()?,
+        stores: store_metrics_snapshot(),
+    })
+}
+
+pub(crate) fn get_storage_metrics_prometheus(
+    persistent_storage: &PersistentStorage,
+) -> Result<String, failure::Error> {
+    Ok(to_prometheus_text(
+        &persistent_storage.database_stats()?,
+        &store_metrics_snapshot(),
+    ))
+}
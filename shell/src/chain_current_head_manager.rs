@@ -104,7 +104,7 @@ impl ChainCurrentHeadManager {
     /// - set bootstrapped flag
     /// - broadcast new current head/branch to peers (if bootstrapped)
     /// - start test chain (if needed) (TODO: TE-123 - not implemented yet)
-    /// - update checkpoint (TODO: TE-210 - not implemented yet)
+    /// - update checkpoint
     /// - reset mempool_prevalidator
     /// ...
     fn process_applied_block(
@@ -133,6 +133,21 @@ impl ChainCurrentHeadManager {
                 None,
             );
 
+            // move checkpoint according to the new head
+            match self.head_state.try_update_checkpoint(&new_head) {
+                Ok(Some(checkpoint)) => {
+                    debug!(ctx.system.log(), "New checkpoint";
+                                             "block_header_hash" => checkpoint.block_hash().to_base58_check(),
+                                             "level" => checkpoint.level());
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    warn!(ctx.system.log(), "Failed to update checkpoint";
+                                            "block_header_hash" => new_head.block_hash().to_base58_check(),
+                                            "reason" => e);
+                }
+            }
+
             let mut is_bootstrapped = self.current_bootstrap_state.read()?.is_bootstrapped();
 
             if !is_bootstrapped {
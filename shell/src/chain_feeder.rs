@@ -18,18 +18,21 @@ use slog::{debug, info, trace, warn, Logger};
 
 use crypto::hash::{BlockHash, ChainId, ContextHash};
 use storage::chain_meta_storage::ChainMetaStorageReader;
-use storage::context::{ContextApi, TezedgeContext};
+use storage::context::{ContextApi, ContextError, TezedgeContext};
+use storage::operations_index_storage::extract_indexed_operations;
 use storage::{
     block_meta_storage, BlockAdditionalData, BlockHeaderWithHash, BlockMetaStorageReader,
-    PersistentStorage,
+    HistoryMode, HistoryPruner, OperationsIndexStorage, PersistentStorage,
 };
 use storage::{
     initialize_storage_with_genesis_block, store_applied_block_result, store_commit_genesis_result,
-    BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, OperationsMetaStorage,
-    OperationsStorage, OperationsStorageReader, StorageError, StorageInitInfo,
+    BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, OperationsStorage,
+    OperationsStorageReader, StorageError, StorageInitInfo,
 };
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::ApplyBlockRequest;
+use tezos_messages::p2p::encoding::block_header::Level;
+use tezos_messages::Head;
 use tezos_wrapper::service::{
     handle_protocol_service_error, ProtocolController, ProtocolServiceError,
 };
@@ -39,11 +42,14 @@ use crate::chain_current_head_manager::{ChainCurrentHeadManagerRef, ProcessValid
 use crate::peer_branch_bootstrapper::{
     ApplyBlockBatchDone, ApplyBlockBatchFailed, PeerBranchBootstrapperRef,
 };
-use crate::shell_channel::{InjectBlockOneshotResultCallback, ShellChannelMsg, ShellChannelRef};
+use crate::shell_channel::{
+    InjectBlockOneshotResultCallback, ShellChannelMsg, ShellChannelRef, ShellChannelTopic,
+};
 use crate::state::{ApplyBlockBatch, StateError};
 use crate::stats::apply_block_stats::{ApplyBlockStats, BlockValidationTimer};
 use crate::subscription::subscribe_to_shell_shutdown;
 use crate::utils::dispatch_oneshot_result;
+use crate::validation::fitness_comparator::FitnessWrapper;
 use std::collections::VecDeque;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
@@ -185,6 +191,7 @@ impl ChainFeeder {
         let (block_applier_event_sender, block_applier_run, block_applier_thread) =
             BlockApplierThreadSpawner::new(
                 chain_current_head_manager,
+                shell_channel.clone(),
                 persistent_storage,
                 Arc::new(init_storage_data),
                 Arc::new(tezos_env),
@@ -480,6 +487,14 @@ pub enum FeedChainError {
     ProtocolServiceError { error: ProtocolServiceError },
     #[fail(display = "Block apply processing error, reason: {:?}", reason)]
     ProcessingError { reason: String },
+    #[fail(display = "Context error, reason: {:?}", error)]
+    ContextError { error: ContextError },
+}
+
+impl From<ContextError> for FeedChainError {
+    fn from(error: ContextError) -> Self {
+        FeedChainError::ContextError { error }
+    }
 }
 
 impl From<StorageError> for FeedChainError {
@@ -498,6 +513,8 @@ impl From<ProtocolServiceError> for FeedChainError {
 pub(crate) struct BlockApplierThreadSpawner {
     /// actor for managing current head
     chain_current_head_manager: ChainCurrentHeadManagerRef,
+    /// shell events (failed block application) are published here
+    shell_channel: ShellChannelRef,
     persistent_storage: PersistentStorage,
     init_storage_data: Arc<StorageInitInfo>,
     tezos_env: Arc<TezosEnvironmentConfiguration>,
@@ -508,6 +525,7 @@ pub(crate) struct BlockApplierThreadSpawner {
 impl BlockApplierThreadSpawner {
     pub(crate) fn new(
         chain_current_head_manager: ChainCurrentHeadManagerRef,
+        shell_channel: ShellChannelRef,
         persistent_storage: PersistentStorage,
         init_storage_data: Arc<StorageInitInfo>,
         tezos_env: Arc<TezosEnvironmentConfiguration>,
@@ -516,6 +534,7 @@ impl BlockApplierThreadSpawner {
     ) -> Self {
         Self {
             chain_current_head_manager,
+            shell_channel,
             persistent_storage,
             tezos_writeable_api,
             init_storage_data,
@@ -538,6 +557,7 @@ impl BlockApplierThreadSpawner {
 
         let block_applier_thread = {
             let chain_current_head_manager = self.chain_current_head_manager.clone();
+            let shell_channel = self.shell_channel.clone();
             let persistent_storage = self.persistent_storage.clone();
             let tezos_writeable_api = self.tezos_writeable_api.clone();
             let init_storage_data = self.init_storage_data.clone();
@@ -550,7 +570,27 @@ impl BlockApplierThreadSpawner {
                 let block_meta_storage = BlockMetaStorage::new(&persistent_storage);
                 let chain_meta_storage = ChainMetaStorage::new(&persistent_storage);
                 let operations_storage = OperationsStorage::new(&persistent_storage);
-                let operations_meta_storage = OperationsMetaStorage::new(&persistent_storage);
+                let operations_index = if init_storage_data.operations_index {
+                    Some(OperationsIndexStorage::new(&persistent_storage))
+                } else {
+                    None
+                };
+                let history_pruner = match init_storage_data.history_mode {
+                    HistoryMode::Archive => None,
+                    _ => {
+                        let history_pruner = match operations_index.as_ref() {
+                            Some(operations_index) => HistoryPruner::new(&persistent_storage)
+                                .with_operations_index(operations_index.clone()),
+                            None => HistoryPruner::new(&persistent_storage),
+                        };
+                        Some(HistoryPrunerThread::spawn(
+                            history_pruner,
+                            init_storage_data.chain_id.clone(),
+                            init_storage_data.history_mode.clone(),
+                            log.clone(),
+                        )?)
+                    }
+                };
                 let context: Box<dyn ContextApi> = Box::new(TezedgeContext::new(
                     Some(block_storage.clone()),
                     persistent_storage.merkle(),
@@ -566,11 +606,14 @@ impl BlockApplierThreadSpawner {
                             &init_storage_data,
                             &block_applier_run,
                             &chain_current_head_manager,
+                            &shell_channel,
                             &block_storage,
                             &block_meta_storage,
                             &chain_meta_storage,
                             &operations_storage,
-                            &operations_meta_storage,
+                            &persistent_storage,
+                            operations_index.as_ref(),
+                            history_pruner.as_ref(),
                             &context,
                             &protocol_controller.api,
                             &mut block_applier_event_receiver,
@@ -593,6 +636,11 @@ impl BlockApplierThreadSpawner {
                     }
                 }
 
+                // wait for the running pruning, so the storage is not closed under it
+                if let Some(history_pruner) = history_pruner {
+                    history_pruner.stop();
+                }
+
                 info!(log, "Chain feeder thread finished");
                 Ok(())
             })
@@ -610,11 +658,14 @@ fn feed_chain_to_protocol(
     init_storage_data: &StorageInitInfo,
     apply_block_run: &AtomicBool,
     chain_current_head_manager: &ChainCurrentHeadManagerRef,
+    shell_channel: &ShellChannelRef,
     block_storage: &BlockStorage,
     block_meta_storage: &BlockMetaStorage,
     chain_meta_storage: &ChainMetaStorage,
     operations_storage: &OperationsStorage,
-    operations_meta_storage: &OperationsMetaStorage,
+    persistent_storage: &PersistentStorage,
+    operations_index: Option<&OperationsIndexStorage>,
+    history_pruner: Option<&HistoryPrunerThread>,
     context: &Box<dyn ContextApi>,
     protocol_controller: &ProtocolController,
     block_applier_event_receiver: &mut QueueReceiver<Event>,
@@ -626,8 +677,7 @@ fn feed_chain_to_protocol(
         chain_current_head_manager,
         block_storage,
         block_meta_storage,
-        chain_meta_storage,
-        operations_meta_storage,
+        persistent_storage,
         context,
         &protocol_controller,
         &log,
@@ -644,6 +694,19 @@ fn feed_chain_to_protocol(
         return Err(FeedChainError::UnknownCurrentHeadError);
     };
 
+    // rebuild operations index for blocks, which failed to be indexed before
+    if let Some(operations_index) = operations_index {
+        reindex_unindexed_blocks(
+            &init_storage_data.chain_id,
+            block_storage,
+            block_meta_storage,
+            operations_storage,
+            operations_index,
+            protocol_controller,
+            log,
+        )?;
+    }
+
     // now we can start applying block
     while apply_block_run.load(Ordering::Acquire) {
         // let's handle event, if any
@@ -700,8 +763,7 @@ fn feed_chain_to_protocol(
                             apply_block_request_data,
                             validated_at_timer,
                             load_metadata_elapsed,
-                            block_storage,
-                            block_meta_storage,
+                            persistent_storage,
                             context,
                             protocol_controller,
                             init_storage_data.one_context,
@@ -718,6 +780,47 @@ fn feed_chain_to_protocol(
                                         if result_callback.is_some() {
                                             oneshot_result = Some(Ok(()));
                                         }
+
+                                        // index operations, failure here does not break block application
+                                        if let Some(operations_index) = operations_index {
+                                            if let Err(e) = index_block_operations(
+                                                &chain_id,
+                                                &validated_block.block,
+                                                &block_additional_data,
+                                                block_storage,
+                                                operations_storage,
+                                                operations_index,
+                                                protocol_controller,
+                                            ) {
+                                                warn!(log, "Failed to index operations of applied block, block will be indexed again on restart";
+                                                           "block" => validated_block.block.hash.to_base58_check(),
+                                                           "reason" => format!("{}", e));
+                                                if let Err(e) = operations_index
+                                                    .mark_unindexed(&validated_block.block.hash)
+                                                {
+                                                    warn!(log, "Failed to record block, which failed to be indexed";
+                                                               "block" => validated_block.block.hash.to_base58_check(),
+                                                               "reason" => format!("{}", e));
+                                                }
+                                            }
+                                        }
+
+                                        // handle history mode (gc/pruning), failure here does not break block application
+                                        if let Err(e) = handle_history_mode(
+                                            init_storage_data,
+                                            &validated_block.block,
+                                            &block_additional_data,
+                                            block_meta_storage,
+                                            history_pruner,
+                                            context,
+                                            log,
+                                        ) {
+                                            warn!(log, "Failed to handle history mode for applied block";
+                                                       "block" => validated_block.block.hash.to_base58_check(),
+                                                       "history_mode" => init_storage_data.history_mode.to_string(),
+                                                       "reason" => format!("{}", e));
+                                        }
+
                                         previous_block_data_cache = Some((
                                             validated_block.block.clone(),
                                             block_additional_data,
@@ -777,6 +880,17 @@ fn feed_chain_to_protocol(
                                             None,
                                         );
                                     }
+
+                                    // block could fail, because its protocol is not known yet
+                                    shell_channel.tell(
+                                        Publish {
+                                            msg: ShellChannelMsg::BlockApplicationFailed(
+                                                block_to_apply.clone(),
+                                            ),
+                                            topic: ShellChannelTopic::ShellEvents.into(),
+                                        },
+                                        None,
+                                    );
                                 }
 
                                 // we need to fire stats here (because we can throw error potentialy)
@@ -853,8 +967,7 @@ fn _apply_block(
     >,
     validated_at_timer: Instant,
     load_metadata_elapsed: Duration,
-    block_storage: &BlockStorage,
-    block_meta_storage: &BlockMetaStorage,
+    persistent_storage: &PersistentStorage,
     context: &Box<dyn ContextApi>,
     protocol_controller: &ProtocolController,
     one_context: bool,
@@ -904,15 +1017,35 @@ fn _apply_block(
                            "protocol_call_elapsed" => format!("{:?}", &protocol_call_elapsed));
     }
 
+    // block becomes current head, if it increases fitness of the stored one, so the head is stored atomically with the block data
+    // (chain current head manager then moves in-memory current head according to the actual mempool state)
+    let new_current_head =
+        match ChainMetaStorage::new(persistent_storage).get_current_head(&chain_id)? {
+            Some(current_head)
+                if FitnessWrapper::new(block.header.fitness())
+                    <= FitnessWrapper::new(current_head.fitness()) =>
+            {
+                None
+            }
+            _ => Some((
+                chain_id.as_ref(),
+                Head::new(
+                    block.hash.clone(),
+                    block.header.level(),
+                    block.header.fitness().clone(),
+                ),
+            )),
+        };
+
     // Lets mark header as applied and store result
     // store success result
     let store_result_timer = Instant::now();
     let block_additional_data = store_applied_block_result(
-        block_storage,
-        block_meta_storage,
+        persistent_storage,
         &block_hash,
         apply_block_result,
         &mut block_meta,
+        new_current_head,
     )?;
     let store_result_elapsed = store_result_timer.elapsed();
 
@@ -929,6 +1062,214 @@ fn _apply_block(
     )))
 }
 
+/// Decodes operations of the applied block with protocol and stores them to the operations index
+fn index_block_operations(
+    chain_id: &ChainId,
+    block: &BlockHeaderWithHash,
+    block_additional_data: &BlockAdditionalData,
+    block_storage: &BlockStorage,
+    operations_storage: &OperationsStorage,
+    operations_index: &OperationsIndexStorage,
+    protocol_controller: &ProtocolController,
+) -> Result<(), FeedChainError> {
+    let operations = operations_storage.get_operations(&block.hash)?;
+    let operations_proto_metadata_bytes = match block_storage.get_json_data(&block.hash)? {
+        Some(json_data) => json_data.operations_proto_metadata_bytes,
+        None => {
+            return Err(FeedChainError::StorageError {
+                error: StorageError::MissingKey,
+            });
+        }
+    };
+
+    let operations_json = protocol_controller.apply_block_operations_metadata(
+        chain_id.clone(),
+        ApplyBlockRequest::convert_operations(operations.clone()),
+        operations_proto_metadata_bytes,
+        block_additional_data.protocol_hash.clone(),
+        block_additional_data.next_protocol_hash.clone(),
+    )?;
+
+    operations_index.put_block_operations(
+        &block.hash,
+        block.header.level(),
+        extract_indexed_operations(&operations, &operations_json)?,
+    )?;
+    Ok(())
+}
+
+/// Indexes again operations of the blocks, which failed to be indexed (see [OperationsIndexStorage::mark_unindexed]),
+/// blocks, which were pruned meanwhile, are just forgotten
+fn reindex_unindexed_blocks(
+    chain_id: &ChainId,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    operations_storage: &OperationsStorage,
+    operations_index: &OperationsIndexStorage,
+    protocol_controller: &ProtocolController,
+    log: &Logger,
+) -> Result<(), FeedChainError> {
+    for block_hash in operations_index.unindexed_blocks()? {
+        let block = block_storage.get(&block_hash)?;
+        let block_additional_data = block_meta_storage.get_additional_data(&block_hash)?;
+        let (block, block_additional_data) = match (block, block_additional_data) {
+            (Some(block), Some(block_additional_data)) => (block, block_additional_data),
+            _ => {
+                operations_index.remove_unindexed_mark(&block_hash)?;
+                continue;
+            }
+        };
+
+        match index_block_operations(
+            chain_id,
+            &block,
+            &block_additional_data,
+            block_storage,
+            operations_storage,
+            operations_index,
+            protocol_controller,
+        ) {
+            Ok(()) => {
+                info!(log, "Operations of block were indexed again"; "block" => block_hash.to_base58_check());
+                operations_index.remove_unindexed_mark(&block_hash)?;
+            }
+            Err(e) => {
+                warn!(log, "Failed to index operations of block again";
+                           "block" => block_hash.to_base58_check(),
+                           "reason" => format!("{}", e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Notifies context garbage collector about applied block and schedules pruning of old history on the new cycle
+/// according to configured [HistoryMode].
+///
+/// New cycle is detected by change of last_allowed_fork_level, which is moved exactly by one cycle,
+/// so the difference is used as the cycle length.
+fn handle_history_mode(
+    init_storage_data: &StorageInitInfo,
+    block: &BlockHeaderWithHash,
+    block_additional_data: &BlockAdditionalData,
+    block_meta_storage: &BlockMetaStorage,
+    history_pruner: Option<&HistoryPrunerThread>,
+    context: &Box<dyn ContextApi>,
+    log: &Logger,
+) -> Result<(), FeedChainError> {
+    // pruner is not running for archive mode
+    let history_pruner = match history_pruner {
+        Some(history_pruner) => history_pruner,
+        None => return Ok(()),
+    };
+    let history_mode = &init_storage_data.history_mode;
+
+    // context is collected just when its commits are stored by this node
+    // (protocol runner with its own context does not feed our context)
+    let collect_context = context.get_last_commit_hash()?.is_some();
+    if collect_context {
+        context.block_applied()?;
+    }
+
+    let predecessor_last_allowed_fork_level = match block_meta_storage
+        .get_additional_data(block.header.predecessor())?
+    {
+        Some(predecessor_additional_data) => predecessor_additional_data.last_allowed_fork_level(),
+        None => return Ok(()),
+    };
+    let last_allowed_fork_level = block_additional_data.last_allowed_fork_level();
+    if last_allowed_fork_level <= predecessor_last_allowed_fork_level {
+        // still the same cycle
+        return Ok(());
+    }
+    let blocks_per_cycle = last_allowed_fork_level - predecessor_last_allowed_fork_level;
+
+    if collect_context {
+        context.cycle_started()?;
+    }
+
+    if let Some(prune_level) = history_mode.prune_level(
+        block.header.level(),
+        blocks_per_cycle,
+        last_allowed_fork_level,
+    ) {
+        debug!(log, "Scheduling pruning of blocks history";
+                    "block" => block.hash.to_base58_check(),
+                    "prune_level" => prune_level,
+                    "history_mode" => history_mode.to_string());
+        history_pruner.schedule(PruneRequest {
+            block_hash: block.hash.clone(),
+            block_level: block.header.level(),
+            prune_level,
+        });
+    }
+
+    Ok(())
+}
+
+/// Request for pruning of all blocks below `prune_level` on the branch of the `block_hash`
+struct PruneRequest {
+    block_hash: BlockHash,
+    block_level: Level,
+    prune_level: Level,
+}
+
+/// Pruning walks through all the blocks of the pruned cycles,
+/// so it runs in dedicated thread and does not block the block application.
+struct HistoryPrunerThread {
+    requests: QueueSender<PruneRequest>,
+    thread: JoinHandle<()>,
+}
+
+impl HistoryPrunerThread {
+    fn spawn(
+        history_pruner: HistoryPruner,
+        chain_id: ChainId,
+        history_mode: HistoryMode,
+        log: Logger,
+    ) -> Result<Self, Error> {
+        let (requests, requests_receiver) = channel::<PruneRequest>();
+        let thread = thread::Builder::new()
+            .name("history-pruner".to_string())
+            .spawn(move || {
+                while let Ok(mut request) = requests_receiver.recv() {
+                    // pruning continues from the last pruned level, so just the latest request is needed
+                    while let Ok(newer_request) = requests_receiver.try_recv() {
+                        request = newer_request;
+                    }
+                    if let Err(e) = history_pruner.prune(
+                        &chain_id,
+                        &history_mode,
+                        &request.block_hash,
+                        request.block_level,
+                        request.prune_level,
+                        &log,
+                    ) {
+                        warn!(log, "Failed to prune blocks history";
+                                   "block" => request.block_hash.to_base58_check(),
+                                   "prune_level" => request.prune_level,
+                                   "history_mode" => history_mode.to_string(),
+                                   "reason" => format!("{}", e));
+                    }
+                }
+                debug!(log, "History pruner thread finished");
+            })?;
+        Ok(Self { requests, thread })
+    }
+
+    fn schedule(&self, request: PruneRequest) {
+        // send fails just if the thread already finished, so there is nobody to prune anyway
+        let _ = self.requests.send(request);
+    }
+
+    /// Finishes already scheduled pruning and waits for the thread
+    fn stop(self) {
+        let HistoryPrunerThread { requests, thread } = self;
+        drop(requests);
+        let _ = thread.join();
+    }
+}
+
 /// Collects complete data for applying block, if not complete, return None
 fn prepare_apply_request(
     block_hash: &BlockHash,
@@ -1043,8 +1384,7 @@ pub(crate) fn initialize_protocol_context(
     chain_current_head_manager: &ChainCurrentHeadManagerRef,
     block_storage: &BlockStorage,
     block_meta_storage: &BlockMetaStorage,
-    chain_meta_storage: &ChainMetaStorage,
-    operations_meta_storage: &OperationsMetaStorage,
+    persistent_storage: &PersistentStorage,
     context: &Box<dyn ContextApi>,
     protocol_controller: &ProtocolController,
     log: &Logger,
@@ -1098,14 +1438,7 @@ pub(crate) fn initialize_protocol_context(
             let commit_data = protocol_controller.genesis_result_data(&genesis_context_hash)?;
 
             // this, marks genesis block as applied
-            let _ = store_commit_genesis_result(
-                block_storage,
-                block_meta_storage,
-                chain_meta_storage,
-                operations_meta_storage,
-                &init_storage_data,
-                commit_data,
-            )?;
+            store_commit_genesis_result(persistent_storage, init_storage_data, commit_data)?;
             let store_result_elapsed = store_result_timer.elapsed();
 
             let mut stats = ApplyBlockStats::default();
@@ -12,15 +12,17 @@
 //! -- ...
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use failure::{format_err, Error};
 use itertools::{Itertools, MinMaxResult};
+use rand::seq::IteratorRandom;
 use riker::actors::*;
 use slog::{debug, info, trace, warn, Logger};
 
-use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, OperationHash};
+use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, OperationHash, ProtocolHash};
 use crypto::seeded_step::Seed;
 use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic};
 use networking::PeerId;
@@ -28,13 +30,14 @@ use storage::mempool_storage::MempoolOperationType;
 use storage::PersistentStorage;
 use storage::{
     BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
-    BlockStorageReader, MempoolStorage, OperationsStorage, OperationsStorageReader, StorageError,
-    StorageInitInfo,
+    BlockStorageReader, MempoolStorage, OperationsStorage, OperationsStorageReader,
+    ProtocolStorage, StorageError, StorageInitInfo,
 };
 use tezos_identity::Identity;
 use tezos_messages::p2p::binary_message::MessageHash;
 use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::*;
+use tezos_messages::protocol::SupportedProtocol;
 use tezos_messages::Head;
 use tezos_wrapper::TezosApiConnectionPool;
 
@@ -77,6 +80,9 @@ const SILENT_PEER_TIMEOUT_SANDBOX: Duration = Duration::from_secs(31_536_000);
 /// How often to print stats in logs
 const LOG_INTERVAL: Duration = Duration::from_secs(60);
 
+/// After this time we ask another peer for the missing protocol
+const PROTOCOL_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Message commands [`ChainManager`] to disconnect stalled peers.
 #[derive(Clone, Debug)]
 pub struct DisconnectStalledPeers {
@@ -219,6 +225,10 @@ pub struct ChainManager {
     operations_storage: Box<dyn OperationsStorageReader>,
     /// Mempool operation storage
     mempool_storage: MempoolStorage,
+    /// Protocols fetched from peers
+    protocol_storage: ProtocolStorage,
+    /// Protocols needed by the applied blocks, which are not known yet, with the time of the last request
+    missing_protocols: HashMap<ProtocolHash, Option<Instant>>,
     /// Holds state of the blockchain
     chain_state: BlockchainState,
 
@@ -316,6 +326,8 @@ impl ChainManager {
             operations_storage,
             stats,
             mempool_storage,
+            protocol_storage,
+            missing_protocols,
             current_head,
             identity_peer_id,
             ..
@@ -428,6 +440,17 @@ impl ChainManager {
                                 let block_header_with_hash =
                                     BlockHeaderWithHash::new(message.block_header().clone())?;
 
+                                // check, if block passes through checkpoint
+                                if !chain_state.is_compatible_with_checkpoint(
+                                    &block_header_with_hash.hash,
+                                    &block_header_with_hash.header,
+                                )? {
+                                    warn!(log, "Ignoring received block header, which is not compatible with checkpoint";
+                                                "block_header_hash" => block_header_with_hash.hash.to_base58_check(),
+                                                "level" => block_header_with_hash.header.level());
+                                    return Ok(());
+                                }
+
                                 // check, if we requested data from this peer
                                 if let Some(requested_data) =
                                     chain_state.requester().block_header_received(
@@ -820,6 +843,78 @@ impl ChainManager {
                                     None,
                                 );
                             }
+                            PeerMessage::SwapRequest(msg) => {
+                                // re-send command to network layer
+                                network_channel.tell(
+                                    Publish {
+                                        msg: NetworkChannelMsg::ProcessSwapRequest(
+                                            peer.peer_id.clone(),
+                                            msg.clone(),
+                                        ),
+                                        topic: NetworkChannelTopic::NetworkCommands.into(),
+                                    },
+                                    None,
+                                );
+                            }
+                            PeerMessage::SwapAck(msg) => {
+                                // re-send command to network layer
+                                network_channel.tell(
+                                    Publish {
+                                        msg: NetworkChannelMsg::ProcessSwapAck(
+                                            peer.peer_id.clone(),
+                                            msg.clone(),
+                                        ),
+                                        topic: NetworkChannelTopic::NetworkCommands.into(),
+                                    },
+                                    None,
+                                );
+                            }
+                            PeerMessage::Deactivate(message) => {
+                                if chain_state.get_chain_id().as_ref() == message.deactivate() {
+                                    debug!(
+                                        log,
+                                        "Peer deactivated our chain - dropping its chain state"
+                                    );
+                                    peer.deactivate();
+                                    // tell bootstrapper to clean potential data
+                                    if let Some(peer_branch_bootstrapper) =
+                                        chain_state.peer_branch_bootstrapper()
+                                    {
+                                        peer_branch_bootstrapper.tell(
+                                            CleanPeerData(Arc::new(
+                                                peer.peer_id.peer_ref.uri().clone(),
+                                            )),
+                                            None,
+                                        );
+                                    }
+                                }
+                            }
+                            PeerMessage::GetProtocols(message) => {
+                                for protocol_hash in message.get_protocols() {
+                                    if let Some(protocol) = protocol_storage.get(protocol_hash)? {
+                                        tell_peer(ProtocolMessage::new(protocol).into(), peer);
+                                    }
+                                }
+                            }
+                            PeerMessage::Protocol(message) => {
+                                let protocol = message.protocol();
+                                let protocol_hash: ProtocolHash = protocol.message_typed_hash()?;
+
+                                // check, if we requested the protocol
+                                if missing_protocols.remove(&protocol_hash).is_some() {
+                                    protocol_storage.put(&protocol_hash, protocol)?;
+                                    info!(log, "Protocol received and stored";
+                                               "protocol_hash" => protocol_hash.to_base58_check(),
+                                               "expected_env_version" => protocol.expected_env_version(),
+                                               "components" => protocol.components().len());
+                                    // compilation of fetched protocols is not supported, protocol runner runs just the embedded ones,
+                                    // so the protocol is stored just to be served to other peers
+                                    warn!(log, "Protocol is not embedded in protocol runner and compilation of fetched protocols is not supported - blocks of this protocol cannot be applied";
+                                               "protocol_hash" => protocol_hash.to_base58_check());
+                                } else {
+                                    debug!(log, "Unexpected protocol received"; "protocol_hash" => protocol_hash.to_base58_check());
+                                }
+                            }
                             ignored_message => {
                                 trace!(log, "Ignored message"; "message" => format!("{:?}", ignored_message))
                             }
@@ -906,6 +1001,33 @@ impl ChainManager {
                     warn!(ctx.system.log(), "Failed to resolve is_bootstrapped for chain manager"; "msg" => format!("{:?}", msg), "reason" => format!("{:?}", e))
                 }
             }
+            ShellChannelMsg::BlockApplicationFailed(block_hash) => {
+                // block fails, if protocol runner does not know protocol of the block, so we try to fetch it
+                if let Some(block) = self.block_storage.get(&block_hash)? {
+                    if let Some(additional_data) = self
+                        .block_meta_storage
+                        .get_additional_data(block.header.predecessor())?
+                    {
+                        self.schedule_protocol_download(
+                            additional_data.next_protocol_hash,
+                            &ctx.system.log(),
+                        )?;
+                    }
+                }
+            }
+            ShellChannelMsg::NewCurrentHead(_, block) => {
+                // protocol of the next blocks can differ from the protocol of the current head
+                if let Some(additional_data) =
+                    self.block_meta_storage.get_additional_data(&block.hash)?
+                {
+                    if additional_data.next_protocol_hash != additional_data.protocol_hash {
+                        self.schedule_protocol_download(
+                            additional_data.next_protocol_hash,
+                            &ctx.system.log(),
+                        )?;
+                    }
+                }
+            }
             ShellChannelMsg::ShuttingDown(_) => {
                 self.shutting_down = true;
             }
@@ -915,6 +1037,52 @@ impl ChainManager {
         Ok(())
     }
 
+    /// Schedules download of the protocol, which is not embedded in the protocol runner and was not fetched yet.
+    ///
+    /// Fetched protocol is just stored and served to other peers, protocol runner does not support compilation of protocols.
+    fn schedule_protocol_download(
+        &mut self,
+        protocol_hash: ProtocolHash,
+        log: &Logger,
+    ) -> Result<(), Error> {
+        if SupportedProtocol::try_from(&protocol_hash).is_ok()
+            || self.missing_protocols.contains_key(&protocol_hash)
+            || self.protocol_storage.contains(&protocol_hash)?
+        {
+            return Ok(());
+        }
+
+        info!(log, "Next blocks need unknown protocol - fetching it from peers"; "protocol_hash" => protocol_hash.to_base58_check());
+        self.missing_protocols.insert(protocol_hash, None);
+        self.request_missing_protocols();
+        Ok(())
+    }
+
+    /// Asks random peer for every missing protocol, which was not requested yet or the request timed out
+    fn request_missing_protocols(&mut self) {
+        let ChainManager {
+            peers,
+            missing_protocols,
+            ..
+        } = self;
+
+        for (protocol_hash, requested_last) in missing_protocols.iter_mut() {
+            if requested_last
+                .filter(|requested_last| requested_last.elapsed() < PROTOCOL_REQUEST_TIMEOUT)
+                .is_some()
+            {
+                continue;
+            }
+            if let Some(peer) = peers.values().choose(&mut rand::thread_rng()) {
+                tell_peer(
+                    GetProtocolsMessage::new(vec![protocol_hash.clone()]).into(),
+                    peer,
+                );
+                *requested_last = Some(Instant::now());
+            }
+        }
+    }
+
     fn process_downloaded_header(
         received_block: BlockHeaderWithHash,
         stats: &mut Stats,
@@ -1344,7 +1512,7 @@ impl ChainManager {
 
         // send messsages
         self.peers.iter().for_each(|(_, peer)| {
-            let (msg, msg_is_mempool_empty) = if peer.mempool_enabled {
+            let (msg, msg_is_mempool_empty) = if peer.is_mempool_relay_enabled() {
                 (
                     msg_for_mempool_enabled.clone(),
                     msg_for_mempool_enabled_is_mempool_empty,
@@ -1372,7 +1540,7 @@ impl ChainManager {
         if p2p_disable_mempool {
             return Ok(Mempool::default());
         }
-        if !peer.mempool_enabled {
+        if !peer.is_mempool_relay_enabled() {
             return Ok(Mempool::default());
         }
 
@@ -1461,6 +1629,8 @@ impl
             block_meta_storage: Box::new(BlockMetaStorage::new(&persistent_storage)),
             operations_storage: Box::new(OperationsStorage::new(&persistent_storage)),
             mempool_storage: MempoolStorage::new(&persistent_storage),
+            protocol_storage: ProtocolStorage::new(&persistent_storage),
+            missing_protocols: HashMap::new(),
             chain_state: BlockchainState::new(
                 block_applier,
                 &persistent_storage,
@@ -1500,6 +1670,8 @@ impl Actor for ChainManager {
         subscribe_to_network_events(&self.network_channel, ctx.myself());
         subscribe_to_shell_shutdown(&self.shell_channel, ctx.myself());
         subscribe_to_shell_commands(&self.shell_channel, ctx.myself());
+        subscribe_to_shell_new_current_head(&self.shell_channel, ctx.myself());
+        subscribe_to_shell_events(&self.shell_channel, ctx.myself());
 
         ctx.schedule::<Self::Msg, _>(
             ASK_CURRENT_HEAD_INITIAL_DELAY,
@@ -1816,6 +1988,9 @@ impl Receive<AskPeersAboutCurrentHead> for ChainManager {
                     peer,
                 )
             }
-        })
+        });
+
+        // retry timed out protocol requests
+        self.request_missing_protocols();
     }
 }
@@ -9,7 +9,7 @@ use std::iter::FromIterator;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, PoisonError, RwLock};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use dns_lookup::LookupError;
 use failure::Fail;
@@ -22,26 +22,35 @@ use tokio::runtime::Handle;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::timeout;
 
-use networking::p2p::peer::{bootstrap, Bootstrap, BootstrapOutput, Peer, PeerRef, SendMessage};
+use crypto::hash::CryptoboxPublicKeyHash;
+use networking::p2p::peer::{
+    bootstrap, Bootstrap, BootstrapOutput, Peer, PeerConnectionState, PeerRef, SendMessage,
+};
 use networking::p2p::{
     network_channel::{
         NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapFailed,
     },
     peer::PeerError,
+    throttle::{BandwidthLimiter, BandwidthLimits},
 };
 use networking::{LocalPeerInfo, PeerId, ShellCompatibilityVersion};
+use storage::peer_storage::{PeerAccessRule, INITIAL_GREYLIST_DELAY};
+use storage::{PeerStorage, PersistentStorage, StorageError};
 use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::limits::ADVERTISE_ID_LIST_MAX_LENGTH_FOR_SEND;
 use tezos_messages::p2p::encoding::prelude::*;
 
 use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
 use crate::subscription::*;
+use crate::utils::{dispatch_oneshot_result, OneshotResultCallback};
 use crate::PeerConnectionThreshold;
 
+pub use networking::p2p::peer::PeerPolicy;
+
 /// Timeout for outgoing connections
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
-/// Whitelist all IP addresses after 30 minutes
-const WHITELIST_INTERVAL: Duration = Duration::from_secs(1_800);
+/// How often to remove expired blacklisted IP addresses and expired known points/peers
+const EXPIRE_BLACKLIST_INTERVAL: Duration = Duration::from_secs(300);
 /// How often to do DNS peer discovery
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 /// Limit how often we allow to trigger check of a peer count
@@ -53,6 +62,10 @@ static ACTOR_ID_GENERATOR: AtomicU64 = AtomicU64::new(0);
 const LOG_INTERVAL: Duration = Duration::from_secs(60);
 /// Limit how often we can ask peer for Bootstrap
 const BOOTSTRAP_MESSAGE_REQUEST_PER_PEER_LIMIT: Duration = Duration::from_secs(60 * 5);
+/// Minimal time between two accepted swaps, also how long we wait for the swap ack (see Octez `swap_linger`)
+const SWAP_LINGER: Duration = Duration::from_secs(30);
+/// How often we propose swap to a random peer, when peer count is between thresholds
+const SWAP_REQUEST_INTERVAL: Duration = Duration::from_secs(2 * 60);
 
 /// Message commands [`PeerManager`] to log its internal stats.
 #[derive(Clone, Debug)]
@@ -67,6 +80,99 @@ pub struct CheckPeerCount;
 #[derive(Clone, Debug)]
 pub struct WhitelistAllIpAddresses;
 
+/// Remove expired blacklisted IP addresses and known points/peers, which were not seen for a long time.
+#[derive(Clone, Debug)]
+pub struct ExpireBlacklist;
+
+/// Request for the snapshot of the live p2p network state (used by RPC).
+#[derive(Clone, Debug)]
+pub struct GetNetworkState {
+    pub result_callback: OneshotResultCallback<NetworkState>,
+}
+
+/// Peer or point, which access control is changed
+#[derive(Clone, Debug)]
+pub enum NetworkAclTarget {
+    Peer(CryptoboxPublicKeyHash),
+    Point(SocketAddr),
+}
+
+/// Access control change (as `ban`/`unban`/`trust`/`untrust` in Octez)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetworkAcl {
+    /// Ban (until unbanned) and disconnect
+    Ban,
+    /// End ban and greylisting
+    Unban,
+    /// Unban and never greylist
+    Trust,
+    /// Remove from trusted
+    Untrust,
+    /// Accept the peer in private mode
+    Whitelist,
+    /// Remove from the private mode whitelist
+    Unwhitelist,
+}
+
+/// Change access control of the peer or point (used by RPC).
+#[derive(Clone, Debug)]
+pub struct ChangeNetworkAcl {
+    pub target: NetworkAclTarget,
+    pub acl: NetworkAcl,
+    pub result_callback: Option<OneshotResultCallback<Result<(), PeerManagerError>>>,
+}
+
+/// Change policy applied to the connections with the peer (used by RPC), default policy removes the custom one.
+#[derive(Clone, Debug)]
+pub struct ChangePeerPolicy {
+    pub peer_id: CryptoboxPublicKeyHash,
+    pub policy: PeerPolicy,
+    pub result_callback: Option<OneshotResultCallback<Result<(), PeerManagerError>>>,
+}
+
+/// Connection to the peer with metadata negotiated during bootstrap
+#[derive(Clone, Debug)]
+pub struct PeerConnectionInfo {
+    pub peer_public_key_hash: CryptoboxPublicKeyHash,
+    pub peer_id_marker: String,
+    pub peer_address: SocketAddr,
+    pub incoming: bool,
+    pub peer_metadata: MetadataMessage,
+    pub network_version: NetworkVersion,
+    /// Byte counters and the current head of the peer
+    pub state: Arc<PeerConnectionState>,
+}
+
+impl PeerConnectionInfo {
+    fn new(bootstrap_output: &BootstrapOutput, incoming: bool) -> Self {
+        Self {
+            peer_public_key_hash: bootstrap_output.2.clone(),
+            peer_id_marker: bootstrap_output.3.clone(),
+            peer_address: bootstrap_output.6,
+            incoming,
+            peer_metadata: bootstrap_output.4.clone(),
+            network_version: bootstrap_output.5.clone(),
+            state: bootstrap_output.7.clone(),
+        }
+    }
+}
+
+/// Snapshot of the live p2p network state of the [`PeerManager`]
+#[derive(Clone, Debug)]
+pub struct NetworkState {
+    pub connections: Vec<PeerConnectionInfo>,
+    /// Metadata sent by this node to the peers
+    pub local_metadata: MetadataMessage,
+    pub trusted_peers: HashSet<CryptoboxPublicKeyHash>,
+    pub trusted_points: HashSet<SocketAddr>,
+    pub private_peer_whitelist: HashSet<CryptoboxPublicKeyHash>,
+    pub peer_policies: HashMap<CryptoboxPublicKeyHash, PeerPolicy>,
+    /// Blacklisted IP addresses with the time, when blacklisting expires
+    pub blacklisted_ips: HashMap<IpAddr, SystemTime>,
+    /// IP addresses banned until unbanned
+    pub banned_ips: HashSet<IpAddr>,
+}
+
 pub type IncomingConnectionPermit = Arc<OwnedSemaphorePermit>;
 
 /// Accept incoming peer connection.
@@ -102,6 +208,18 @@ pub struct P2p {
 
     /// Peers (IP:port) which we try to connect all the time
     pub bootstrap_peers: Vec<SocketAddr>,
+
+    /// Peers (IP:port) which are never blacklisted, not counted to the high threshold and always reconnected
+    pub trusted_peers: Vec<SocketAddr>,
+    /// Peers (identities) which are never blacklisted, not counted to the high threshold and always reconnected
+    pub trusted_peer_ids: HashSet<CryptoboxPublicKeyHash>,
+    /// Peers accepted in private mode (trusted peers are accepted always)
+    pub private_peer_whitelist: HashSet<CryptoboxPublicKeyHash>,
+    /// Policies applied to the connections with the concrete peers
+    pub peer_policies: HashMap<CryptoboxPublicKeyHash, PeerPolicy>,
+
+    /// Global and per connection upload/download limits
+    pub bandwidth_limits: BandwidthLimits,
 }
 
 impl P2p {
@@ -113,6 +231,18 @@ impl P2p {
 pub enum PeerManagerError {
     #[fail(display = "Mutex/lock error, reason: {:?}", reason)]
     LockError { reason: String },
+    #[fail(display = "Storage error, reason: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Trusted peer/point cannot be banned")]
+    TrustedCannotBeBanned,
+    #[fail(display = "Access control {:?} is not supported for points", acl)]
+    UnsupportedPointAcl { acl: NetworkAcl },
+}
+
+impl From<StorageError> for PeerManagerError {
+    fn from(error: StorageError) -> Self {
+        PeerManagerError::StorageError { error }
+    }
 }
 
 impl<T> From<PoisonError<T>> for PeerManagerError {
@@ -131,6 +261,10 @@ impl<T> From<PoisonError<T>> for PeerManagerError {
 #[actor(
     CheckPeerCount,
     WhitelistAllIpAddresses,
+    ExpireBlacklist,
+    GetNetworkState,
+    ChangeNetworkAcl,
+    ChangePeerPolicy,
     AcceptPeer,
     ConnectToPeer,
     LogPeerStats,
@@ -152,6 +286,8 @@ pub struct PeerManager {
 
     // PeerManager's state of peers (potential and connected)
     peers: Arc<P2pPeers>,
+    /// Persistent state of known points/peers (score, connection history, greylisting)
+    peer_storage: PeerStorage,
 
     /// Bootstrap peer, which we try to connect all the the, if no other peers presents
     bootstrap_addresses: HashSet<(String, u16)>,
@@ -160,6 +296,8 @@ pub struct PeerManager {
     disable_mempool: bool,
     /// Indicates that p2p is working in private mode
     private_node: bool,
+    /// Global bandwidth buckets shared by all connections
+    bandwidth_limiter: Arc<BandwidthLimiter>,
 
     /// Local node info covers:
     /// - listener_port - we will listen for incoming connection at this port
@@ -172,16 +310,29 @@ pub struct PeerManager {
     /// Message receiver boolean indicating whether
     /// more connections should be accepted from network
     rx_run: Arc<AtomicBool>,
-    /// set of blacklisted IP addresses
-    ip_blacklist: HashSet<IpAddr>,
+    /// blacklisted IP addresses with the time, when blacklisting expires
+    ip_blacklist: HashMap<IpAddr, SystemTime>,
+    /// IP addresses banned (by RPC) until unbanned, ban is persisted in the peer storage
+    banned_ips: HashSet<IpAddr>,
     /// Last time we did DNS peer discovery
     discovery_last: Option<Instant>,
     /// Last time we checked peer count
     check_peer_count_last: Option<Instant>,
+    /// Last time we accepted swap (connected to the proposed point)
+    latest_accepted_swap: Option<Instant>,
+    /// Last swap request sent by us
+    latest_swap_request: Option<SwapRequestSent>,
     /// Indicates that system is shutting down
     shutting_down: bool,
 }
 
+/// Swap request waiting for the ack
+struct SwapRequestSent {
+    /// Peer proposed in the swap request, which is disconnected, when we accept the swap ack
+    proposed: PeerRef,
+    sent_at: Instant,
+}
+
 /// Reference to [peer manager](PeerManager) actor.
 pub type PeerManagerRef = ActorRef<PeerManagerMsg>;
 
@@ -190,6 +341,7 @@ impl PeerManager {
         sys: &impl ActorRefFactory,
         network_channel: NetworkChannelRef,
         shell_channel: ShellChannelRef,
+        persistent_storage: PersistentStorage,
         tokio_executor: Handle,
         identity: Arc<Identity>,
         shell_compatibility_version: Arc<ShellCompatibilityVersion>,
@@ -201,6 +353,7 @@ impl PeerManager {
             Props::new_args((
                 network_channel,
                 shell_channel,
+                persistent_storage,
                 tokio_executor,
                 identity,
                 shell_compatibility_version,
@@ -265,9 +418,9 @@ impl PeerManager {
             return Ok(());
         }
 
-        // randomize potential peers as a security measurement
-        let mut addresses_to_connect = potential_peers.iter().cloned().collect::<Vec<SocketAddr>>();
-        addresses_to_connect.shuffle(&mut rand::thread_rng());
+        // randomize potential peers as a security measurement, but prefer the best scored ones
+        let mut addresses_to_connect =
+            self.order_by_score(potential_peers.iter().cloned().collect())?;
 
         // drain required count
         addresses_to_connect
@@ -305,17 +458,77 @@ impl PeerManager {
         )
     }
 
-    /// Check if given ip address is blacklisted to connect to
+    /// Shuffles addresses and (stable) sorts them by score of the known point, so the best scored are the first
+    fn order_by_score(
+        &self,
+        mut addresses: Vec<SocketAddr>,
+    ) -> Result<Vec<SocketAddr>, PeerManagerError> {
+        addresses.shuffle(&mut rand::thread_rng());
+        let mut scored_addresses = addresses
+            .into_iter()
+            .map(|address| Ok((self.peer_storage.point_score(&address)?, address)))
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        scored_addresses.sort_by_key(|(score, _)| cmp::Reverse(*score));
+        Ok(scored_addresses
+            .into_iter()
+            .map(|(_, address)| address)
+            .collect())
+    }
+
+    /// Check if given ip address is blacklisted (or banned) to connect to
     fn is_blacklisted(&self, ip_address: &IpAddr) -> bool {
-        self.ip_blacklist.contains(ip_address)
+        (self.banned_ips.contains(ip_address)
+            || matches!(self.ip_blacklist.get(ip_address), Some(until) if *until > SystemTime::now()))
+            && !self.is_trusted_ip(ip_address)
     }
 
-    fn blacklist_address(&mut self, address: SocketAddr, reason: String, log: &Logger) {
+    fn is_trusted_ip(&self, ip_address: &IpAddr) -> bool {
+        matches!(
+            self.peers.access_control.read(),
+            Ok(access_control) if access_control.is_trusted_ip(ip_address)
+        )
+    }
+
+    fn is_trusted_peer(&self, peer_id: &CryptoboxPublicKeyHash) -> bool {
+        matches!(
+            self.peers.access_control.read(),
+            Ok(access_control) if access_control.trusted_peers.contains(peer_id)
+        )
+    }
+
+    fn blacklist_address(
+        &mut self,
+        address: SocketAddr,
+        peer_id: Option<&CryptoboxPublicKeyHash>,
+        reason: String,
+        log: &Logger,
+    ) {
+        if self.is_trusted_ip(&address.ip())
+            || matches!(peer_id, Some(peer_id) if self.is_trusted_peer(peer_id))
+        {
+            info!(log, "Trusted IP/peer is not blacklisted"; "ip" => format!("{}", address.ip()), "reason" => reason);
+            return;
+        }
+
+        let now = SystemTime::now();
+        // greylisting is persisted with exponential backoff
+        let until = match self.peer_storage.greylist(&address, peer_id, now) {
+            Ok(until) => until,
+            Err(e) => {
+                warn!(log, "Failed to store blacklisted IP"; "ip" => format!("{}", address.ip()), "reason" => format!("{}", e));
+                now + INITIAL_GREYLIST_DELAY
+            }
+        };
         info!(log, "Blacklisting IP";
                    "ip" => format!("{}", address.ip()),
+                   "until" => format!("{:?}", until),
                    "reason" => reason,
         );
-        self.ip_blacklist.insert(address.ip());
+        let until = self
+            .ip_blacklist
+            .get(&address.ip())
+            .map_or(until, |previous_until| cmp::max(*previous_until, until));
+        self.ip_blacklist.insert(address.ip(), until);
 
         // TODO: call firewall
     }
@@ -329,7 +542,12 @@ impl PeerManager {
         );
 
         // blacklist
-        self.blacklist_address(peer_id.peer_address, reason, &log);
+        self.blacklist_address(
+            peer_id.peer_address,
+            Some(&peer_id.peer_public_key_hash),
+            reason,
+            &log,
+        );
 
         // stop actor
         actor_system.stop(peer_id.peer_ref.clone());
@@ -344,6 +562,236 @@ impl PeerManager {
         );
     }
 
+    /// Stops actors of all connected peers matching the filter
+    fn disconnect_peers<F: Fn(&PeerConnectionInfo) -> bool>(
+        &self,
+        filter: F,
+        actor_system: &ActorSystem,
+    ) -> Result<(), PeerManagerError> {
+        self.peers
+            .connected_peers
+            .read()?
+            .values()
+            .filter(|peer_state| filter(&peer_state.connection))
+            .for_each(|peer_state| actor_system.stop(peer_state.peer_ref.clone()));
+        Ok(())
+    }
+
+    fn change_network_acl(
+        &mut self,
+        target: NetworkAclTarget,
+        acl: NetworkAcl,
+        ctx: &Context<PeerManagerMsg>,
+    ) -> Result<(), PeerManagerError> {
+        let log = ctx.system.log();
+        info!(log, "Changing access control"; "target" => format!("{:?}", target), "acl" => format!("{:?}", acl));
+
+        match (target, acl) {
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Ban) => {
+                if self.is_trusted_peer(&peer_id) {
+                    return Err(PeerManagerError::TrustedCannotBeBanned);
+                }
+                self.peer_storage.ban_peer(&peer_id, SystemTime::now())?;
+                self.disconnect_peers(
+                    |connection| connection.peer_public_key_hash == peer_id,
+                    &ctx.system,
+                )?;
+            }
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Unban) => {
+                self.peer_storage.whitelist_peer(&peer_id)?;
+            }
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Trust) => {
+                self.peer_storage.whitelist_peer(&peer_id)?;
+                self.peer_storage
+                    .put_access_rule(&PeerAccessRule::TrustedPeer(peer_id.clone()), "")?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .trusted_peers
+                    .insert(peer_id);
+                self.trigger_check_peer_count(ctx);
+            }
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Untrust) => {
+                self.peer_storage
+                    .delete_access_rule(&PeerAccessRule::TrustedPeer(peer_id.clone()))?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .trusted_peers
+                    .remove(&peer_id);
+                self.disconnect_not_allowed_in_private_mode(&ctx.system)?;
+            }
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Whitelist) => {
+                self.peer_storage.put_access_rule(
+                    &PeerAccessRule::PrivateWhitelistedPeer(peer_id.clone()),
+                    "",
+                )?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .private_peer_whitelist
+                    .insert(peer_id);
+            }
+            (NetworkAclTarget::Peer(peer_id), NetworkAcl::Unwhitelist) => {
+                self.peer_storage
+                    .delete_access_rule(&PeerAccessRule::PrivateWhitelistedPeer(peer_id.clone()))?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .private_peer_whitelist
+                    .remove(&peer_id);
+                self.disconnect_not_allowed_in_private_mode(&ctx.system)?;
+            }
+            (NetworkAclTarget::Point(address), NetworkAcl::Ban) => {
+                if self.is_trusted_ip(&address.ip()) {
+                    return Err(PeerManagerError::TrustedCannotBeBanned);
+                }
+                self.peer_storage.ban_point(&address, SystemTime::now())?;
+                self.banned_ips.insert(address.ip());
+                self.peers.potential_peers.write()?.remove(&address);
+                self.disconnect_peers(
+                    |connection| connection.peer_address.ip() == address.ip(),
+                    &ctx.system,
+                )?;
+            }
+            (NetworkAclTarget::Point(address), NetworkAcl::Unban) => {
+                self.ip_blacklist.remove(&address.ip());
+                self.banned_ips.remove(&address.ip());
+                self.peer_storage.whitelist_point(&address)?;
+            }
+            (NetworkAclTarget::Point(address), NetworkAcl::Trust) => {
+                self.ip_blacklist.remove(&address.ip());
+                self.banned_ips.remove(&address.ip());
+                self.peer_storage.whitelist_point(&address)?;
+                self.peer_storage
+                    .put_access_rule(&PeerAccessRule::TrustedPoint(address), "")?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .trusted_points
+                    .insert(address);
+                self.trigger_check_peer_count(ctx);
+            }
+            (NetworkAclTarget::Point(address), NetworkAcl::Untrust) => {
+                self.peer_storage
+                    .delete_access_rule(&PeerAccessRule::TrustedPoint(address))?;
+                self.peers
+                    .access_control
+                    .write()?
+                    .trusted_points
+                    .remove(&address);
+                self.disconnect_not_allowed_in_private_mode(&ctx.system)?;
+            }
+            (NetworkAclTarget::Point(_), acl) => {
+                return Err(PeerManagerError::UnsupportedPointAcl { acl });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores access rules changed at runtime (by RPC) on top of the configured ones,
+    /// configured peer policies take precedence over the stored ones
+    fn restore_access_rules(&self, log: &Logger) -> Result<(), PeerManagerError> {
+        let access_rules = self.peer_storage.access_rules()?;
+        let mut access_control = self.peers.access_control.write()?;
+        for (rule, value) in access_rules {
+            match rule {
+                PeerAccessRule::TrustedPeer(peer_id) => {
+                    access_control.trusted_peers.insert(peer_id);
+                }
+                PeerAccessRule::TrustedPoint(address) => {
+                    access_control.trusted_points.insert(address);
+                }
+                PeerAccessRule::PrivateWhitelistedPeer(peer_id) => {
+                    access_control.private_peer_whitelist.insert(peer_id);
+                }
+                PeerAccessRule::PeerPolicy(peer_id) => match value.parse::<PeerPolicy>() {
+                    Ok(policy) => {
+                        access_control
+                            .peer_policies
+                            .entry(peer_id)
+                            .or_insert(policy);
+                    }
+                    Err(e) => {
+                        warn!(log, "Ignoring invalid stored peer policy"; "peer_id" => peer_id.to_base58_check(), "reason" => format!("{}", e));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// In private mode stops connections with peers, which are neither whitelisted nor trusted anymore
+    fn disconnect_not_allowed_in_private_mode(
+        &self,
+        actor_system: &ActorSystem,
+    ) -> Result<(), PeerManagerError> {
+        if !self.private_node {
+            return Ok(());
+        }
+        let access_control = self.peers.access_control.read()?;
+        self.disconnect_peers(
+            |connection| {
+                !access_control.is_allowed_in_private_mode(
+                    &connection.peer_public_key_hash,
+                    &connection.peer_address,
+                )
+            },
+            actor_system,
+        )
+    }
+
+    fn change_peer_policy(
+        &mut self,
+        peer_id: CryptoboxPublicKeyHash,
+        policy: PeerPolicy,
+        log: &Logger,
+    ) -> Result<(), PeerManagerError> {
+        info!(log, "Changing peer policy"; "peer_id" => peer_id.to_base58_check(), "policy" => format!("{:?}", policy));
+
+        let rule = PeerAccessRule::PeerPolicy(peer_id.clone());
+        let mut access_control = self.peers.access_control.write()?;
+        if policy == PeerPolicy::default() {
+            self.peer_storage.delete_access_rule(&rule)?;
+            access_control.peer_policies.remove(&peer_id);
+        } else {
+            self.peer_storage
+                .put_access_rule(&rule, &policy.to_string())?;
+            access_control.peer_policies.insert(peer_id.clone(), policy);
+        }
+
+        // apply to the live connections
+        self.peers
+            .connected_peers
+            .read()?
+            .values()
+            .filter(|peer_state| peer_state.connection.peer_public_key_hash == peer_id)
+            .for_each(|peer_state| peer_state.connection.state.set_policy(policy));
+
+        Ok(())
+    }
+
+    fn network_state(&self) -> Result<NetworkState, PeerManagerError> {
+        let access_control = self.peers.access_control.read()?;
+        Ok(NetworkState {
+            connections: self
+                .peers
+                .connected_peers
+                .read()?
+                .values()
+                .map(|peer_state| peer_state.connection.clone())
+                .collect(),
+            local_metadata: MetadataMessage::new(self.disable_mempool, self.private_node),
+            trusted_peers: access_control.trusted_peers.clone(),
+            trusted_points: access_control.trusted_points.clone(),
+            private_peer_whitelist: access_control.private_peer_whitelist.clone(),
+            peer_policies: access_control.peer_policies.clone(),
+            blacklisted_ips: self.ip_blacklist.clone(),
+            banned_ips: self.banned_ips.clone(),
+        })
+    }
+
     fn trigger_check_peer_count(&mut self, ctx: &Context<PeerManagerMsg>) {
         if self.shutting_down {
             return;
@@ -375,11 +823,15 @@ impl PeerManager {
         // write lock for potential peers
         let mut potential_peers = self.peers.potential_peers.write()?;
 
+        // remember newly discovered points
+        self.peer_storage
+            .add_points(sock_addresses.iter().cloned(), SystemTime::now())?;
+
         // collect all
-        let mut addresses_to_connect = potential_peers.iter().cloned().collect::<Vec<SocketAddr>>();
-        addresses_to_connect.extend(sock_addresses);
-        // randomize peers as a security measurement
-        addresses_to_connect.shuffle(&mut rand::thread_rng());
+        let mut addresses = potential_peers.iter().cloned().collect::<Vec<SocketAddr>>();
+        addresses.extend(sock_addresses);
+        // randomize peers as a security measurement, but keep the best scored ones
+        let mut addresses_to_connect = self.order_by_score(addresses)?;
 
         // try to limit
         if addresses_to_connect.len() > num_of_max_potential_peers {
@@ -395,8 +847,141 @@ impl PeerManager {
         Ok(())
     }
 
+    /// Trusted points and last known addresses of trusted peers, which are not connected, are dialed again
+    fn reconnect_trusted(&mut self, ctx: &Context<PeerManagerMsg>) -> Result<(), PeerManagerError> {
+        let addresses_to_connect = {
+            let access_control = self.peers.access_control.read()?;
+            let connected_peers = self.peers.connected_peers.read()?;
+            let is_peer_connected = |peer_id: &CryptoboxPublicKeyHash| {
+                connected_peers
+                    .values()
+                    .any(|peer_state| peer_state.connection.peer_public_key_hash == *peer_id)
+            };
+
+            let mut addresses_to_connect = Vec::new();
+            for point in &access_control.trusted_points {
+                if connected_peers
+                    .values()
+                    .any(|peer_state| peer_state.peer_address == *point)
+                {
+                    continue;
+                }
+                // connection could be incoming, so check also the last peer seen on the point
+                let point_peer_id = self
+                    .peer_storage
+                    .get_point(point)?
+                    .and_then(|known_point| known_point.peer_id().clone());
+                if matches!(point_peer_id, Some(peer_id) if is_peer_connected(&peer_id)) {
+                    continue;
+                }
+                addresses_to_connect.push(*point);
+            }
+            for peer_id in &access_control.trusted_peers {
+                if is_peer_connected(peer_id) {
+                    continue;
+                }
+                if let Some(address) = self
+                    .peer_storage
+                    .get_peer(peer_id)?
+                    .and_then(|known_peer| *known_peer.last_address())
+                {
+                    if !addresses_to_connect.contains(&address) {
+                        addresses_to_connect.push(address);
+                    }
+                }
+            }
+            addresses_to_connect
+        };
+
+        for address in addresses_to_connect {
+            debug!(ctx.system.log(), "Reconnecting trusted peer"; "ip" => address);
+            ctx.myself()
+                .tell(ConnectToPeer { address }, ctx.myself().into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks, if we can replace one of our connections by connection to the proposed point/peer
+    fn can_swap_to(
+        &self,
+        point: &SocketAddr,
+        peer_id: &CryptoboxPublicKeyHash,
+    ) -> Result<bool, PeerManagerError> {
+        if self.private_node || self.is_blacklisted(&point.ip()) {
+            return Ok(false);
+        }
+        Ok(!self.peers.is_connected_to(point, peer_id)?)
+    }
+
+    /// Returns random untrusted outgoing connection (its address is a listening point), which can be offered for swap
+    fn random_swap_candidate(
+        &self,
+        except: &PeerRef,
+    ) -> Result<Option<P2pPeerState>, PeerManagerError> {
+        let candidates = self.peers.swap_candidates(except)?;
+        Ok(candidates.choose(&mut rand::thread_rng()).cloned())
+    }
+
+    /// Connects to the new point and disconnects swapped peer, when the new connection is established (see Octez `P2p_pool.swap`)
+    fn swap(
+        &mut self,
+        ctx: &Context<PeerManagerMsg>,
+        new_point: SocketAddr,
+        swapped_peer: PeerRef,
+    ) -> Result<(), PeerManagerError> {
+        info!(ctx.system.log(), "Swapping peer connection"; "new_point" => new_point, "swapped_peer" => swapped_peer.name());
+        *self.peers.pending_swap.write()? = Some((new_point, swapped_peer));
+        self.latest_accepted_swap = Some(Instant::now());
+        ctx.myself()
+            .tell(ConnectToPeer { address: new_point }, ctx.myself().into());
+        Ok(())
+    }
+
+    /// Proposes one of our connections to a random peer in exchange for one of its connections
+    fn send_swap_request(&mut self, ctx: &Context<PeerManagerMsg>) -> Result<(), PeerManagerError> {
+        if self.private_node
+            || self
+                .latest_swap_request
+                .as_ref()
+                .filter(|request| request.sent_at.elapsed() <= SWAP_REQUEST_INTERVAL)
+                .is_some()
+        {
+            return Ok(());
+        }
+
+        let recipient = match self
+            .peers
+            .untrusted_connected_peers()?
+            .choose(&mut rand::thread_rng())
+        {
+            Some(recipient) => recipient.clone(),
+            None => return Ok(()),
+        };
+        if let Some(proposed) = self.random_swap_candidate(&recipient.peer_ref)? {
+            debug!(ctx.system.log(), "Sending swap request"; "recipient" => recipient.peer_ref.name(), "proposed_point" => proposed.peer_address);
+            let msg = PeerMessage::SwapRequest(SwapMessage::new(
+                proposed.peer_address.to_string(),
+                proposed.connection.peer_public_key_hash.clone(),
+            ));
+            recipient
+                .peer_ref
+                .tell(SendMessage::new(Arc::new(msg.into())), None);
+            self.latest_swap_request = Some(SwapRequestSent {
+                proposed: proposed.peer_ref,
+                sent_at: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
     fn check_peer_count(&mut self, ctx: &Context<PeerManagerMsg>) -> Result<(), PeerManagerError> {
+        if let Err(e) = self.reconnect_trusted(ctx) {
+            warn!(ctx.system.log(), "Failed to reconnect trusted peers"; "reason" => format!("{:?}", e));
+        }
+
         let connected_peers_count = self.peers.connected_peers.read()?.len();
+        let untrusted_peers_count = self.peers.untrusted_connected_peers()?.len();
 
         if connected_peers_count < self.threshold.low {
             let potential_peers_count = self.peers.potential_peers.read()?.len();
@@ -410,23 +995,20 @@ impl PeerManager {
                 }
             }
             self.try_to_connect_to_potential_peers(ctx)?;
-        } else if connected_peers_count > self.threshold.high {
-            // peer count is too high, disconnect some peers
-            warn!(ctx.system.log(), "Peer count is too high. Some peers will be stopped"; "actual" => connected_peers_count, "limit" => self.threshold.high);
+        } else if untrusted_peers_count > self.threshold.high {
+            // peer count is too high, disconnect some peers (trusted are not counted)
+            warn!(ctx.system.log(), "Peer count is too high. Some peers will be stopped"; "actual" => untrusted_peers_count, "limit" => self.threshold.high);
 
             // stop some (random) peers
-            let mut connected_peers = self
-                .peers
-                .connected_peers
-                .read()?
-                .values()
-                .cloned()
-                .collect::<Vec<_>>();
-            connected_peers.shuffle(&mut rand::thread_rng());
-            connected_peers
+            let mut untrusted_peers = self.peers.untrusted_connected_peers()?;
+            untrusted_peers.shuffle(&mut rand::thread_rng());
+            untrusted_peers
                 .iter()
-                .take(connected_peers_count - self.threshold.high)
+                .take(untrusted_peers_count - self.threshold.high)
                 .for_each(|peer_state| ctx.system.stop(peer_state.peer_ref.clone()))
+        } else {
+            // peer count is fine, rotate connections by swapping them with other peers
+            self.send_swap_request(ctx)?;
         }
 
         self.check_peer_count_last = Some(Instant::now());
@@ -441,6 +1023,10 @@ impl PeerManager {
     ) -> Result<(), PeerManagerError> {
         match msg {
             NetworkChannelMsg::ProcessAdvertisedPeers(peer, message) => {
+                if self.private_node {
+                    // in private mode we connect just to the configured peers
+                    return Ok(());
+                }
                 // extract potential peers from the advertise message
                 info!(ctx.system.log(), "Received advertise message"; "peer_id" => peer.peer_id_marker.clone(), "peers" => format!("{:?}", message.id().join(", ")));
                 self.process_new_potential_peers(
@@ -467,6 +1053,56 @@ impl PeerManager {
                 let msg = Arc::new(AdvertiseMessage::new(&addresses).into());
                 peer.peer_ref.tell(SendMessage::new(msg), None);
             }
+            NetworkChannelMsg::ProcessSwapRequest(peer, message) => {
+                // peer proposes to connect to its peer, we offer one of ours in exchange (see Octez `P2p_pool.swap_request`)
+                if self
+                    .latest_accepted_swap
+                    .filter(|accepted| accepted.elapsed() < SWAP_LINGER)
+                    .is_some()
+                {
+                    debug!(ctx.system.log(), "Ignoring swap request, last swap was accepted recently"; "peer_id" => peer.peer_id_marker.clone());
+                    return Ok(());
+                }
+                let new_point = match message.point().parse::<SocketAddr>() {
+                    Ok(new_point) => new_point,
+                    Err(_) => {
+                        debug!(ctx.system.log(), "Ignoring swap request with invalid point"; "peer_id" => peer.peer_id_marker.clone(), "point" => message.point());
+                        return Ok(());
+                    }
+                };
+                if !self.can_swap_to(&new_point, message.peer_id())? {
+                    return Ok(());
+                }
+                if let Some(proposed) = self.random_swap_candidate(&peer.peer_ref)? {
+                    let msg = PeerMessage::SwapAck(SwapMessage::new(
+                        proposed.peer_address.to_string(),
+                        proposed.connection.peer_public_key_hash.clone(),
+                    ));
+                    peer.peer_ref
+                        .tell(SendMessage::new(Arc::new(msg.into())), None);
+                    self.swap(ctx, new_point, proposed.peer_ref)?;
+                }
+            }
+            NetworkChannelMsg::ProcessSwapAck(peer, message) => {
+                // peer accepted our swap request, so we connect to its peer instead of the proposed one
+                match self.latest_swap_request.take() {
+                    Some(request) if request.sent_at.elapsed() < SWAP_LINGER => {
+                        let new_point = match message.point().parse::<SocketAddr>() {
+                            Ok(new_point) => new_point,
+                            Err(_) => {
+                                debug!(ctx.system.log(), "Ignoring swap ack with invalid point"; "peer_id" => peer.peer_id_marker.clone(), "point" => message.point());
+                                return Ok(());
+                            }
+                        };
+                        if self.can_swap_to(&new_point, message.peer_id())? {
+                            self.swap(ctx, new_point, request.proposed)?;
+                        }
+                    }
+                    _ => {
+                        debug!(ctx.system.log(), "Ignoring unexpected swap ack"; "peer_id" => peer.peer_id_marker.clone());
+                    }
+                }
+            }
             NetworkChannelMsg::ProcessFailedBootstrapAddress(PeerBootstrapFailed {
                 address,
                 potential_peers_to_connect,
@@ -485,6 +1121,7 @@ impl PeerManager {
                     None => {
                         self.blacklist_address(
                             address,
+                            None,
                             String::from("peer failed at bootstrap process"),
                             &ctx.system.log(),
                         );
@@ -505,6 +1142,7 @@ impl
     ActorFactoryArgs<(
         NetworkChannelRef,
         ShellChannelRef,
+        PersistentStorage,
         Handle,
         Arc<Identity>,
         Arc<ShellCompatibilityVersion>,
@@ -516,6 +1154,7 @@ impl
         (
             network_channel,
             shell_channel,
+            persistent_storage,
             tokio_executor,
             identity,
             shell_compatibility_version,
@@ -524,6 +1163,7 @@ impl
         ): (
             NetworkChannelRef,
             ShellChannelRef,
+            PersistentStorage,
             Handle,
             Arc<Identity>,
             Arc<ShellCompatibilityVersion>,
@@ -547,6 +1187,18 @@ impl
 
         let peers_threshold = Arc::new(p2p_config.peer_threshold);
 
+        let mut trusted_points = HashSet::from_iter(p2p_config.trusted_peers);
+        // in private mode, configured bootstrap peers are trusted
+        if p2p_config.private_node {
+            trusted_points.extend(p2p_config.bootstrap_peers);
+        }
+        let access_control = PeerAccessControl {
+            trusted_peers: p2p_config.trusted_peer_ids,
+            trusted_points,
+            private_peer_whitelist: p2p_config.private_peer_whitelist,
+            peer_policies: p2p_config.peer_policies,
+        };
+
         PeerManager {
             network_channel,
             shell_channel,
@@ -562,11 +1214,16 @@ impl
             listener_address: p2p_config.listener_address,
             disable_mempool: p2p_config.disable_mempool,
             private_node: p2p_config.private_node,
+            bandwidth_limiter: Arc::new(BandwidthLimiter::new(p2p_config.bandwidth_limits)),
             rx_run: Arc::new(AtomicBool::new(true)),
-            peers: Arc::new(P2pPeers::new(peers_threshold)),
-            ip_blacklist: HashSet::new(),
+            peers: Arc::new(P2pPeers::new(peers_threshold, access_control)),
+            peer_storage: PeerStorage::new(&persistent_storage),
+            ip_blacklist: HashMap::new(),
+            banned_ips: HashSet::new(),
             discovery_last: None,
             check_peer_count_last: None,
+            latest_accepted_swap: None,
+            latest_swap_request: None,
             shutting_down: false,
         }
     }
@@ -589,11 +1246,11 @@ impl Actor for PeerManager {
             CheckPeerCount.into(),
         );
         ctx.schedule::<Self::Msg, _>(
-            WHITELIST_INTERVAL,
-            WHITELIST_INTERVAL,
+            EXPIRE_BLACKLIST_INTERVAL,
+            EXPIRE_BLACKLIST_INTERVAL,
             ctx.myself(),
             None,
-            WhitelistAllIpAddresses.into(),
+            ExpireBlacklist.into(),
         );
         ctx.schedule::<Self::Msg, _>(
             LOG_INTERVAL / 2,
@@ -603,7 +1260,31 @@ impl Actor for PeerManager {
             LogPeerStats.into(),
         );
 
-        let listener_address = self.listener_address.clone();
+        // reload blacklisted IP addresses persisted before restart
+        match self.peer_storage.greylisted_points(SystemTime::now()) {
+            Ok(greylisted_points) => {
+                for (address, until) in greylisted_points {
+                    let until = self
+                        .ip_blacklist
+                        .get(&address.ip())
+                        .map_or(until, |previous_until| cmp::max(*previous_until, until));
+                    self.ip_blacklist.insert(address.ip(), until);
+                }
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to load blacklisted IP addresses"; "reason" => format!("{}", e))
+            }
+        }
+        match self.peer_storage.banned_points() {
+            Ok(banned_points) => self
+                .banned_ips
+                .extend(banned_points.iter().map(|address| address.ip())),
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to load banned IP addresses"; "reason" => format!("{}", e))
+            }
+        }
+
+        let listener_address = self.listener_address;
         let peers = self.peers.clone();
         let myself = ctx.myself();
         let rx_run = self.rx_run.clone();
@@ -616,12 +1297,35 @@ impl Actor for PeerManager {
     }
 
     fn post_start(&mut self, ctx: &Context<Self::Msg>) {
+        if let Err(e) = self.restore_access_rules(&ctx.system.log()) {
+            warn!(ctx.system.log(), "Failed to restore access rules on startup"; "reason" => format!("{:?}", e));
+        }
+
+        // try the best known points first, before asking for new ones
+        let dial_candidates = self.calculate_count_of_required_peers().and_then(|count| {
+            Ok(self
+                .peer_storage
+                .dial_candidates(SystemTime::now(), count * 10)?)
+        });
+        match dial_candidates {
+            Ok(dial_candidates) => {
+                if let Err(e) = self.process_new_potential_peers(dial_candidates) {
+                    warn!(ctx.system.log(), "Failed to process known peers on startup"; "reason" => format!("{:?}", e));
+                }
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to load known peers on startup"; "reason" => format!("{:?}", e))
+            }
+        }
         if let Err(e) = self.discover_peers(&ctx.system.log()) {
             warn!(ctx.system.log(), "Failed to discovery peers on startup"; "reason" => format!("{:?}", e));
         }
         if let Err(e) = self.try_to_connect_to_potential_peers(ctx) {
             warn!(ctx.system.log(), "Failed to connect to potential peers on startup"; "reason" => format!("{:?}", e));
         }
+        if let Err(e) = self.reconnect_trusted(ctx) {
+            warn!(ctx.system.log(), "Failed to connect to trusted peers on startup"; "reason" => format!("{:?}", e));
+        }
     }
 
     fn post_stop(&mut self) {
@@ -661,8 +1365,9 @@ impl Receive<DeadLetter> for PeerManager {
         // try to remove peers actor
         let peer_actor_uri = msg.recipient.uri();
         match self.peers.try_remove_peer_actor(peer_actor_uri) {
-            Ok(was_removed) => {
-                if was_removed {
+            Ok(removed_connection) => {
+                if let Some(connection) = removed_connection {
+                    record_connection_closed(&self.peer_storage, &connection, &ctx.system.log());
                     // kick immediatelly if it is a peer's actor and try_remove
                     ctx.system.stop(msg.recipient);
                 } else {
@@ -741,8 +1446,13 @@ impl Receive<SystemEvent> for PeerManager {
             // try to remove peers actor
             let peer_actor_uri = evt.actor.uri();
             match self.peers.try_remove_peer_actor(peer_actor_uri) {
-                Ok(was_removed) => {
-                    if was_removed {
+                Ok(removed_connection) => {
+                    if let Some(connection) = removed_connection {
+                        record_connection_closed(
+                            &self.peer_storage,
+                            &connection,
+                            &ctx.system.log(),
+                        );
                         self.trigger_check_peer_count(ctx);
                     }
                 }
@@ -790,6 +1500,85 @@ impl Receive<WhitelistAllIpAddresses> for PeerManager {
     ) {
         info!(ctx.system.log(), "Whitelisting all IP addresses");
         self.ip_blacklist.clear();
+        if let Err(e) = self.peer_storage.whitelist_all() {
+            warn!(ctx.system.log(), "Failed to whitelist stored IP addresses"; "reason" => format!("{}", e));
+        }
+    }
+}
+
+impl Receive<ExpireBlacklist> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: ExpireBlacklist, _sender: Sender) {
+        let now = SystemTime::now();
+        self.ip_blacklist.retain(|_, until| *until > now);
+
+        match self.peer_storage.remove_expired(now) {
+            Ok(removed) => {
+                if removed > 0 {
+                    debug!(ctx.system.log(), "Removed expired known points/peers"; "count" => removed);
+                }
+            }
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to remove expired known points/peers"; "reason" => format!("{}", e))
+            }
+        }
+    }
+}
+
+impl Receive<GetNetworkState> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: GetNetworkState, _sender: Sender) {
+        match self.network_state() {
+            Ok(network_state) => {
+                if let Err(e) = msg.result_callback.send(network_state) {
+                    warn!(ctx.system.log(), "Failed to send network state"; "reason" => format!("{}", e));
+                }
+            }
+            Err(e) => {
+                // dropped callback is reported as an error to the waiting side
+                warn!(ctx.system.log(), "Failed to collect network state"; "reason" => format!("{:?}", e));
+            }
+        }
+    }
+}
+
+impl Receive<ChangeNetworkAcl> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ChangeNetworkAcl, _sender: Sender) {
+        let ChangeNetworkAcl {
+            target,
+            acl,
+            result_callback,
+        } = msg;
+        let result = self.change_network_acl(target, acl, ctx);
+        if let Err(e) = &result {
+            warn!(ctx.system.log(), "Failed to change access control"; "reason" => format!("{}", e));
+        }
+        if let Err(e) = dispatch_oneshot_result(result_callback, || result) {
+            warn!(ctx.system.log(), "Failed to dispatch result"; "reason" => format!("{}", e));
+        }
+    }
+}
+
+impl Receive<ChangePeerPolicy> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ChangePeerPolicy, _sender: Sender) {
+        let ChangePeerPolicy {
+            peer_id,
+            policy,
+            result_callback,
+        } = msg;
+        let result = self.change_peer_policy(peer_id, policy, &ctx.system.log());
+        if let Err(e) = &result {
+            warn!(ctx.system.log(), "Failed to change peer policy"; "reason" => format!("{}", e));
+        }
+        if let Err(e) = dispatch_oneshot_result(result_callback, || result) {
+            warn!(ctx.system.log(), "Failed to dispatch result"; "reason" => format!("{}", e));
+        }
     }
 }
 
@@ -811,7 +1600,9 @@ impl Receive<ConnectToPeer> for PeerManager {
         let tokio_executor = self.tokio_executor.clone();
         let disable_mempool = self.disable_mempool;
         let private_node = self.private_node;
+        let bandwidth_limiter = self.bandwidth_limiter.clone();
         let peers = self.peers.clone();
+        let peer_storage = self.peer_storage.clone();
 
         self.tokio_executor.spawn(async move {
             let log = system.log();
@@ -819,13 +1610,23 @@ impl Receive<ConnectToPeer> for PeerManager {
             match timeout(CONNECT_TIMEOUT, TcpStream::connect(&msg.address)).await {
                 Ok(Ok(stream)) => {
                     debug!(log, "(Outgoing) Connection to peer successful, so start bootstrapping"; "incoming" => false, "ip" => msg.address);
-                    match bootstrap(Bootstrap::outgoing(stream, msg.address.clone(), disable_mempool, private_node), local_node_info, &log).await {
+                    match bootstrap(Bootstrap::outgoing(stream, msg.address, disable_mempool, private_node, bandwidth_limiter), local_node_info, &log).await {
                         Ok(bootstrap_output) => {
+                            if !peers.admit_connection(&bootstrap_output, private_node, &log) {
+                                return;
+                            }
+                            if !record_connection_succeeded(&peer_storage, msg.address, &bootstrap_output.2, false, &log) {
+                                return;
+                            }
+                            let connection = PeerConnectionInfo::new(&bootstrap_output, false);
                             match Self::create_peer(&system, network_channel.clone(), tokio_executor, bootstrap_output) {
                                 Ok(peer) => {
-                                    if let Err(e) = peers.add_outgoing_peer(peer.clone(), msg.address) {
+                                    if let Err(e) = peers.add_outgoing_peer(peer.clone(), connection) {
                                         warn!(log, "Failed to add outgoing peer to state - stopping peer actor"; "reason" => format!("{:?}", e));
                                         system.stop(peer);
+                                    } else if let Some(swapped_peer) = peers.take_swapped_peer(&msg.address) {
+                                        info!(log, "Swap succeeded - disconnecting swapped peer"; "ip" => msg.address, "swapped_peer" => swapped_peer.name());
+                                        system.stop(swapped_peer);
                                     }
                                 }
                                 Err(e) => {
@@ -835,15 +1636,18 @@ impl Receive<ConnectToPeer> for PeerManager {
                         }
                         Err(err) => {
                             warn!(log, "(Outgoing) Connection handshake to peer failed"; "incoming" => false, "reason" => format!("{}", &err), "ip" => &msg.address);
+                            record_connection_failed(&peer_storage, msg.address, &log);
                             failed_bootstrap_peer(err, msg.address, network_channel);
                         }
                     }
                 }
                 Ok(Err(e)) => {
                     info!(log, "(Outgoing) Connection to peer failed"; "ip" => msg.address, "reason" => format!("{:?}", e));
+                    record_connection_failed(&peer_storage, msg.address, &log);
                 }
                 Err(_) => {
                     info!(log, "(Outgoing) Connection timed out"; "ip" => msg.address);
+                    record_connection_failed(&peer_storage, msg.address, &log);
                 }
             }
         });
@@ -861,7 +1665,13 @@ impl Receive<AcceptPeer> for PeerManager {
 
         // TODO: TE-490 - allow here accept randomly more connections
         // if we came here we wont drop connection here, just send correct Nack
-        match self.peers.is_max_connections_exceeded() {
+        let max_connections_exceeded = if self.is_trusted_ip(&msg.address.ip()) {
+            // trusted peers are not counted to the limit
+            Ok(false)
+        } else {
+            self.peers.is_max_connections_exceeded()
+        };
+        match max_connections_exceeded {
             Ok(false) => {
                 debug!(ctx.system.log(), "Connection from"; "ip" => msg.address);
 
@@ -871,16 +1681,25 @@ impl Receive<AcceptPeer> for PeerManager {
                 let tokio_executor = self.tokio_executor.clone();
                 let disable_mempool = self.disable_mempool;
                 let private_node = self.private_node;
+                let bandwidth_limiter = self.bandwidth_limiter.clone();
                 let peers = self.peers.clone();
+                let peer_storage = self.peer_storage.clone();
 
                 self.tokio_executor.spawn(async move {
                     let log = system.log();
                     debug!(log, "Bootstrapping"; "incoming" => true, "ip" => &msg.address);
-                    match bootstrap(Bootstrap::incoming(msg.stream, msg.address.clone(), disable_mempool, private_node), local_node_info, &log).await {
+                    match bootstrap(Bootstrap::incoming(msg.stream, msg.address, disable_mempool, private_node, bandwidth_limiter), local_node_info, &log).await {
                         Ok(bootstrap_output) => {
+                            if !peers.admit_connection(&bootstrap_output, private_node, &log) {
+                                return;
+                            }
+                            if !record_connection_succeeded(&peer_storage, msg.address, &bootstrap_output.2, true, &log) {
+                                return;
+                            }
+                            let connection = PeerConnectionInfo::new(&bootstrap_output, true);
                             match Self::create_peer(&system, network_channel.clone(), tokio_executor, bootstrap_output) {
                                 Ok(peer) => {
-                                    if let Err(e) = peers.add_incoming_peer(peer.clone(), msg.address) {
+                                    if let Err(e) = peers.add_incoming_peer(peer.clone(), connection) {
                                         warn!(log, "Failed to add incoming peer to state - stopping peer actor"; "reason" => format!("{:?}", e));
                                         system.stop(peer);
                                     }
@@ -921,6 +1740,56 @@ impl Receive<AcceptPeer> for PeerManager {
     }
 }
 
+/// Records successful handshake to the peer storage, returns false, if peer is banned or greylisted and connection should be dropped
+fn record_connection_succeeded(
+    peer_storage: &PeerStorage,
+    peer_address: SocketAddr,
+    peer_public_key_hash: &CryptoboxPublicKeyHash,
+    incoming: bool,
+    log: &Logger,
+) -> bool {
+    let now = SystemTime::now();
+    match peer_storage.is_peer_rejected(peer_public_key_hash, now) {
+        Ok(true) => {
+            info!(log, "Peer is blacklisted - dropping connection"; "incoming" => incoming, "ip" => peer_address);
+            return false;
+        }
+        Ok(false) => (),
+        Err(e) => {
+            warn!(log, "Failed to check blacklisted peer"; "ip" => peer_address, "reason" => format!("{}", e))
+        }
+    }
+    if let Err(e) =
+        peer_storage.connection_succeeded(&peer_address, peer_public_key_hash, incoming, now)
+    {
+        warn!(log, "Failed to store successful connection to peer"; "ip" => peer_address, "reason" => format!("{}", e));
+    }
+    true
+}
+
+/// Records closed connection to the peer storage, so greylist delay of the long-lived connection is reset
+fn record_connection_closed(
+    peer_storage: &PeerStorage,
+    connection: &PeerConnectionInfo,
+    log: &Logger,
+) {
+    if let Err(e) = peer_storage.connection_closed(
+        &connection.peer_address,
+        &connection.peer_public_key_hash,
+        connection.incoming,
+        connection.state.connected_since(),
+        SystemTime::now(),
+    ) {
+        warn!(log, "Failed to store closed connection to peer"; "ip" => connection.peer_address, "reason" => format!("{}", e));
+    }
+}
+
+fn record_connection_failed(peer_storage: &PeerStorage, peer_address: SocketAddr, log: &Logger) {
+    if let Err(e) = peer_storage.connection_failed(&peer_address, SystemTime::now()) {
+        warn!(log, "Failed to store failed connection to peer"; "ip" => peer_address, "reason" => format!("{}", e));
+    }
+}
+
 fn failed_bootstrap_peer(
     err: PeerError,
     peer_address: SocketAddr,
@@ -946,6 +1815,13 @@ fn failed_bootstrap_peer(
     );
 }
 
+/// Finds [`PeerManager`] actor, e.g. to query the network state from RPC
+pub fn find_peer_manager(sys: &ActorSystem) -> Option<BasicActorRef> {
+    sys.user_root()
+        .children()
+        .find(|actor_ref| PeerManager::name().eq(actor_ref.name()))
+}
+
 /// Start to listen for incoming connections indefinitely.
 async fn begin_listen_incoming(
     listener_address: SocketAddr,
@@ -1064,9 +1940,47 @@ fn resolve_dns_name_to_peer_address(
 struct P2pPeerState {
     peer_ref: PeerRef,
     peer_address: SocketAddr,
+    connection: PeerConnectionInfo,
     bootstrap_requested_last: Option<Instant>,
 }
 
+/// Trusted and whitelisted peers and per-peer policies
+#[derive(Default)]
+pub(crate) struct PeerAccessControl {
+    /// Peers, which are never blacklisted, not counted to the high threshold and always reconnected
+    trusted_peers: HashSet<CryptoboxPublicKeyHash>,
+    /// Points, which are never blacklisted, not counted to the high threshold and always reconnected
+    trusted_points: HashSet<SocketAddr>,
+    /// Peers accepted in private mode (together with trusted ones)
+    private_peer_whitelist: HashSet<CryptoboxPublicKeyHash>,
+    /// Policies applied to the connections with the concrete peers
+    peer_policies: HashMap<CryptoboxPublicKeyHash, PeerPolicy>,
+}
+
+impl PeerAccessControl {
+    fn is_trusted_ip(&self, ip_address: &IpAddr) -> bool {
+        self.trusted_points
+            .iter()
+            .any(|point| point.ip() == *ip_address)
+    }
+
+    fn is_trusted(&self, peer_id: &CryptoboxPublicKeyHash, peer_address: &SocketAddr) -> bool {
+        self.trusted_peers.contains(peer_id) || self.is_trusted_ip(&peer_address.ip())
+    }
+
+    fn is_allowed_in_private_mode(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+        peer_address: &SocketAddr,
+    ) -> bool {
+        self.private_peer_whitelist.contains(peer_id) || self.is_trusted(peer_id, peer_address)
+    }
+
+    fn policy(&self, peer_id: &CryptoboxPublicKeyHash) -> PeerPolicy {
+        self.peer_policies.get(peer_id).cloned().unwrap_or_default()
+    }
+}
+
 /// Represents inner state of PeerManager about p2p peers sharable between threads
 pub(crate) struct P2pPeers {
     /// Threshold configration for peers
@@ -1080,10 +1994,19 @@ pub(crate) struct P2pPeers {
 
     /// List of potential peers to connect to
     potential_peers: Arc<RwLock<HashSet<SocketAddr>>>,
+
+    /// Trusted/whitelisted peers and policies (lock it before `connected_peers`, if both are needed)
+    access_control: Arc<RwLock<PeerAccessControl>>,
+
+    /// Accepted swap - new point to connect and the peer, which is disconnected, when connection succeeds
+    pending_swap: RwLock<Option<(SocketAddr, PeerRef)>>,
 }
 
 impl P2pPeers {
-    fn new(peers_threshold: Arc<PeerConnectionThreshold>) -> Self {
+    fn new(
+        peers_threshold: Arc<PeerConnectionThreshold>,
+        access_control: PeerAccessControl,
+    ) -> Self {
         let max_incoming_connection_tickets = {
             if peers_threshold.high == 1 {
                 1
@@ -1099,20 +2022,50 @@ impl P2pPeers {
             incoming_connection_tickets: Arc::new(Semaphore::new(max_incoming_connection_tickets)),
             connected_peers: Arc::new(RwLock::new(HashMap::new())),
             peers_threshold,
+            access_control: Arc::new(RwLock::new(access_control)),
+            pending_swap: RwLock::new(None),
+        }
+    }
+
+    /// Checks bootstrapped connection against the private mode whitelist and applies the peer policy,
+    /// returns false, if connection should be dropped
+    fn admit_connection(
+        &self,
+        bootstrap_output: &BootstrapOutput,
+        private_node: bool,
+        log: &Logger,
+    ) -> bool {
+        let access_control = match self.access_control.read() {
+            Ok(access_control) => access_control,
+            Err(e) => {
+                warn!(log, "Failed to lock access control - dropping connection"; "reason" => format!("{}", e));
+                return false;
+            }
+        };
+        let peer_id = &bootstrap_output.2;
+        let peer_address = &bootstrap_output.6;
+        if private_node && !access_control.is_allowed_in_private_mode(peer_id, peer_address) {
+            info!(log, "Peer is not whitelisted for private mode - dropping connection"; "peer_id" => bootstrap_output.3.clone(), "ip" => peer_address);
+            return false;
         }
+        bootstrap_output
+            .7
+            .set_policy(access_control.policy(peer_id));
+        true
     }
 
     fn add_outgoing_peer(
         &self,
         peer_ref: PeerRef,
-        peer_address: SocketAddr,
+        connection: PeerConnectionInfo,
     ) -> Result<(), PeerManagerError> {
         // TODO: TE-490 - handle AlreadyConnected
         let _ = self.connected_peers.write()?.insert(
             peer_ref.uri().clone(),
             P2pPeerState {
                 peer_ref,
-                peer_address,
+                peer_address: connection.peer_address,
+                connection,
                 bootstrap_requested_last: None,
             },
         );
@@ -1122,14 +2075,15 @@ impl P2pPeers {
     fn add_incoming_peer(
         &self,
         peer_ref: PeerRef,
-        peer_address: SocketAddr,
+        connection: PeerConnectionInfo,
     ) -> Result<(), PeerManagerError> {
         // TODO: TE-490 - handle AlreadyConnected
         let _ = self.connected_peers.write()?.insert(
             peer_ref.uri().clone(),
             P2pPeerState {
                 peer_ref,
-                peer_address,
+                peer_address: connection.peer_address,
+                connection,
                 bootstrap_requested_last: None,
             },
         );
@@ -1137,8 +2091,11 @@ impl P2pPeers {
     }
 
     /// Tries to remove peer_actor_uri from state.
-    /// Returns true if contained and was removed.
-    fn try_remove_peer_actor(&self, peer_actor_uri: &ActorUri) -> Result<bool, PeerManagerError> {
+    /// Returns connection of the peer, if it was contained and removed.
+    fn try_remove_peer_actor(
+        &self,
+        peer_actor_uri: &ActorUri,
+    ) -> Result<Option<PeerConnectionInfo>, PeerManagerError> {
         // try remove peers from map
         let removed_peer_state = self.connected_peers.write()?.remove(peer_actor_uri);
 
@@ -1148,9 +2105,9 @@ impl P2pPeers {
                 .potential_peers
                 .write()?
                 .remove(&removed_peer_state.peer_address);
-            Ok(true)
+            Ok(Some(removed_peer_state.connection))
         } else {
-            Ok(false)
+            Ok(None)
         }
     }
 
@@ -1171,8 +2128,58 @@ impl P2pPeers {
         }
     }
 
+    /// Returns connected peers, which are not trusted
+    fn untrusted_connected_peers(&self) -> Result<Vec<P2pPeerState>, PeerManagerError> {
+        let access_control = self.access_control.read()?;
+        Ok(self
+            .connected_peers
+            .read()?
+            .values()
+            .filter(|peer_state| {
+                !access_control.is_trusted(
+                    &peer_state.connection.peer_public_key_hash,
+                    &peer_state.peer_address,
+                )
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Returns true, if we are already connected to the point or to the peer
+    fn is_connected_to(
+        &self,
+        point: &SocketAddr,
+        peer_id: &CryptoboxPublicKeyHash,
+    ) -> Result<bool, PeerManagerError> {
+        Ok(self.connected_peers.read()?.values().any(|peer_state| {
+            peer_state.peer_address == *point
+                || peer_state.connection.peer_public_key_hash == *peer_id
+        }))
+    }
+
+    /// Untrusted outgoing connections (their addresses are listening points), which can be offered for swap
+    fn swap_candidates(&self, except: &PeerRef) -> Result<Vec<P2pPeerState>, PeerManagerError> {
+        Ok(self
+            .untrusted_connected_peers()?
+            .into_iter()
+            .filter(|peer_state| !peer_state.connection.incoming && peer_state.peer_ref != *except)
+            .collect())
+    }
+
+    /// Returns peer, which was swapped for the new connection to the address
+    fn take_swapped_peer(&self, address: &SocketAddr) -> Option<PeerRef> {
+        let mut pending_swap = self.pending_swap.write().ok()?;
+        match pending_swap.as_ref() {
+            Some((new_point, _)) if new_point == address => {
+                pending_swap.take().map(|(_, swapped_peer)| swapped_peer)
+            }
+            _ => None,
+        }
+    }
+
+    /// Trusted peers are not counted to the limit
     fn is_max_connections_exceeded(&self) -> Result<bool, PeerManagerError> {
-        Ok(self.connected_peers.read()?.len() >= self.peers_threshold.high)
+        Ok(self.untrusted_connected_peers()?.len() >= self.peers_threshold.high)
     }
 
     fn generate_next_peer_actor_name() -> String {
@@ -1209,6 +2216,22 @@ pub mod tests {
     };
     use networking::p2p::network_channel::NetworkChannel;
     use slog::Level;
+    use std::convert::TryFrom;
+
+    fn test_connection(peer_id: &PeerId, incoming: bool) -> PeerConnectionInfo {
+        PeerConnectionInfo {
+            peer_public_key_hash: peer_id.peer_public_key_hash.clone(),
+            peer_id_marker: peer_id.peer_id_marker.clone(),
+            peer_address: peer_id.peer_address,
+            incoming,
+            peer_metadata: MetadataMessage::new(false, false),
+            network_version: NetworkVersion::new("".to_owned(), 0, 0),
+            state: Arc::new(PeerConnectionState::new(
+                Arc::new(AtomicU64::new(0)),
+                Arc::new(AtomicU64::new(0)),
+            )),
+        }
+    }
 
     #[test]
     fn test_peer_actor_name() {
@@ -1246,6 +2269,8 @@ pub mod tests {
             peers_threshold: Arc::new(
                 PeerConnectionThreshold::try_new(0, threshold_high, None).expect("Incorrect range"),
             ),
+            access_control: Arc::new(RwLock::new(PeerAccessControl::default())),
+            pending_swap: RwLock::new(None),
         };
 
         // test
@@ -1275,7 +2300,7 @@ pub mod tests {
             let PeerState { peer_id, .. } =
                 test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7777);
             p2p_peers
-                .add_incoming_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+                .add_incoming_peer(peer_id.peer_ref.clone(), test_connection(&peer_id, true))
                 .unwrap();
 
             // we have more left
@@ -1295,7 +2320,7 @@ pub mod tests {
             let PeerState { peer_id, .. } =
                 test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7778);
             p2p_peers
-                .add_incoming_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+                .add_incoming_peer(peer_id.peer_ref.clone(), test_connection(&peer_id, true))
                 .unwrap();
 
             // we have more left
@@ -1310,7 +2335,7 @@ pub mod tests {
         let PeerState { peer_id, .. } =
             test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7779);
         p2p_peers
-            .add_outgoing_peer(peer_id.peer_ref.clone(), peer_id.peer_address)
+            .add_outgoing_peer(peer_id.peer_ref.clone(), test_connection(&peer_id, false))
             .unwrap();
 
         // exceeded yet
@@ -1327,7 +2352,8 @@ pub mod tests {
         // now remove one peers
         assert!(p2p_peers
             .try_remove_peer_actor(peer_id.peer_ref.uri())
-            .expect("error"));
+            .expect("error")
+            .is_some());
 
         // not exceeded
         assert!(!p2p_peers.is_max_connections_exceeded().unwrap());
@@ -1337,6 +2363,128 @@ pub mod tests {
             .try_acquire_incoming_connection_permit()
             .unwrap()
             .is_some());
+
+        // trusted peer is not counted to the limit
+        p2p_peers
+            .add_outgoing_peer(peer_id.peer_ref.clone(), test_connection(&peer_id, false))
+            .unwrap();
+        assert!(p2p_peers.is_max_connections_exceeded().unwrap());
+        p2p_peers
+            .access_control
+            .write()
+            .unwrap()
+            .trusted_peers
+            .insert(peer_id.peer_public_key_hash.clone());
+        assert!(!p2p_peers.is_max_connections_exceeded().unwrap());
+        assert_eq!(2, p2p_peers.untrusted_connected_peers().unwrap().len());
+    }
+
+    #[test]
+    fn test_p2p_peers_swap() {
+        // prerequisities
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log.clone());
+        let network_channel =
+            NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+
+        let p2p_peers = P2pPeers {
+            potential_peers: Arc::new(RwLock::new(HashSet::new())),
+            incoming_connection_tickets: Arc::new(Semaphore::new(10)),
+            connected_peers: Arc::new(RwLock::new(HashMap::new())),
+            peers_threshold: Arc::new(
+                PeerConnectionThreshold::try_new(0, 10, None).expect("Incorrect range"),
+            ),
+            access_control: Arc::new(RwLock::new(PeerAccessControl::default())),
+            pending_swap: RwLock::new(None),
+        };
+
+        let PeerState {
+            peer_id: incoming, ..
+        } = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7781);
+        let PeerState {
+            peer_id: outgoing, ..
+        } = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7782);
+        let PeerState {
+            peer_id: requester, ..
+        } = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7783);
+        let PeerState {
+            peer_id: trusted, ..
+        } = test_peer(&actor_system, network_channel.clone(), &tokio_runtime, 7784);
+        p2p_peers
+            .add_incoming_peer(incoming.peer_ref.clone(), test_connection(&incoming, true))
+            .unwrap();
+        for peer_id in &[&outgoing, &requester, &trusted] {
+            p2p_peers
+                .add_outgoing_peer(peer_id.peer_ref.clone(), test_connection(peer_id, false))
+                .unwrap();
+        }
+        p2p_peers
+            .access_control
+            .write()
+            .unwrap()
+            .trusted_peers
+            .insert(trusted.peer_public_key_hash.clone());
+
+        // just untrusted outgoing connections (except the swap requester) can be offered
+        let candidates = p2p_peers.swap_candidates(&requester.peer_ref).unwrap();
+        assert_eq!(1, candidates.len());
+        assert_eq!(outgoing.peer_ref, candidates[0].peer_ref);
+
+        // we never swap to already connected point or peer
+        let new_point: SocketAddr = "10.0.0.1:9732".parse().unwrap();
+        let new_peer = CryptoboxPublicKeyHash::try_from(vec![1; 16]).unwrap();
+        assert!(!p2p_peers.is_connected_to(&new_point, &new_peer).unwrap());
+        assert!(p2p_peers
+            .is_connected_to(&incoming.peer_address, &new_peer)
+            .unwrap());
+        assert!(p2p_peers
+            .is_connected_to(&new_point, &outgoing.peer_public_key_hash)
+            .unwrap());
+
+        // swapped peer is returned just for the connection to the new point and just once
+        *p2p_peers.pending_swap.write().unwrap() = Some((new_point, outgoing.peer_ref.clone()));
+        assert!(p2p_peers
+            .take_swapped_peer(&incoming.peer_address)
+            .is_none());
+        assert_eq!(
+            Some(outgoing.peer_ref.clone()),
+            p2p_peers.take_swapped_peer(&new_point)
+        );
+        assert!(p2p_peers.take_swapped_peer(&new_point).is_none());
+    }
+
+    #[test]
+    fn test_peer_access_control() {
+        let whitelisted: CryptoboxPublicKeyHash =
+            CryptoboxPublicKeyHash::try_from(vec![1; 16]).unwrap();
+        let trusted: CryptoboxPublicKeyHash =
+            CryptoboxPublicKeyHash::try_from(vec![2; 16]).unwrap();
+        let unknown: CryptoboxPublicKeyHash =
+            CryptoboxPublicKeyHash::try_from(vec![3; 16]).unwrap();
+        let trusted_point: SocketAddr = "10.0.0.1:9732".parse().unwrap();
+        let unknown_point: SocketAddr = "10.0.0.2:9732".parse().unwrap();
+        let policy: PeerPolicy = "disable-mempool-relay,max-msg-rate=10".parse().unwrap();
+
+        let access_control = PeerAccessControl {
+            trusted_peers: HashSet::from_iter(vec![trusted.clone()]),
+            trusted_points: HashSet::from_iter(vec![trusted_point]),
+            private_peer_whitelist: HashSet::from_iter(vec![whitelisted.clone()]),
+            peer_policies: HashMap::from_iter(vec![(whitelisted.clone(), policy)]),
+        };
+
+        // trusted point is matched by IP (incoming connection has different port)
+        assert!(access_control.is_trusted(&unknown, &"10.0.0.1:45678".parse().unwrap()));
+        assert!(access_control.is_trusted(&trusted, &unknown_point));
+        assert!(!access_control.is_trusted(&whitelisted, &unknown_point));
+
+        assert!(access_control.is_allowed_in_private_mode(&whitelisted, &unknown_point));
+        assert!(access_control.is_allowed_in_private_mode(&trusted, &unknown_point));
+        assert!(access_control.is_allowed_in_private_mode(&unknown, &trusted_point));
+        assert!(!access_control.is_allowed_in_private_mode(&unknown, &unknown_point));
+
+        assert_eq!(access_control.policy(&whitelisted), policy);
+        assert_eq!(access_control.policy(&unknown), PeerPolicy::default());
     }
 
     fn check_count_of_required_peers(current: usize, low: usize, high: usize) {
@@ -55,6 +55,8 @@ pub enum ShellChannelMsg {
     NewCurrentHead(Head, Arc<BlockHeaderWithHash>),
     BlockReceived(BlockReceived),
     BlockApplied(Arc<BlockHash>),
+    /// Chain feeder failed to apply block
+    BlockApplicationFailed(Arc<BlockHash>),
     AllBlockOperationsReceived(AllBlockOperationsReceived),
 
     /// Commands
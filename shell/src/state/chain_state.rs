@@ -17,6 +17,7 @@ use storage::{
     BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
     BlockStorageReader, ChainMetaStorage, OperationsMetaStorage, OperationsStorage, StorageError,
 };
+use tezos_messages::p2p::binary_message::MessageHash;
 use tezos_messages::p2p::encoding::current_branch::CurrentBranchMessage;
 use tezos_messages::p2p::encoding::prelude::{CurrentHeadMessage, OperationsForBlocksMessage};
 use tezos_messages::p2p::encoding::{block_header::BlockHeader, limits::HISTORY_MAX_SIZE};
@@ -150,6 +151,17 @@ impl BlockchainState {
             return Ok(false);
         }
 
+        // (checkpoint) we dont accept branches, which dont pass through checkpoint
+        let branch_head = branch.current_branch().current_head();
+        if !self.is_compatible_with_checkpoint(
+            &branch_head
+                .message_typed_hash()
+                .map_err(StorageError::from)?,
+            branch_head,
+        )? {
+            return Ok(false);
+        }
+
         if let Some(current_head) = current_head.read()?.as_ref() {
             // (only_if_fitness_increases) we can accept branch if increases fitness
             if validation::is_fitness_increases(
@@ -194,6 +206,16 @@ impl BlockchainState {
                 return Ok(BlockAcceptanceResult::IgnoreBlock);
             }
 
+            // (checkpoint) we dont accept head, which does not pass through checkpoint
+            if !self.is_compatible_with_checkpoint(
+                &validated_header
+                    .message_typed_hash()
+                    .map_err(StorageError::from)?,
+                validated_header,
+            )? {
+                return Ok(BlockAcceptanceResult::IgnoreBlock);
+            }
+
             // (only_if_fitness_increases) we can accept head if increases fitness
             if !validation::is_fitness_increases_or_same(current_head, validated_header.fitness()) {
                 return Ok(BlockAcceptanceResult::IgnoreBlock);
@@ -232,6 +254,35 @@ impl BlockchainState {
         }
     }
 
+    /// Validate if block is compatible with checkpoint (if any), equivalent to [state.ml][acceptable_block]:
+    /// - block at checkpoint level has to be checkpoint itself
+    /// - block above checkpoint has to be its successor (checked only if we know all predecessors down to the checkpoint)
+    /// - block below checkpoint cannot be decided here, so it is accepted (e.g. history download)
+    pub fn is_compatible_with_checkpoint(
+        &self,
+        block_hash: &BlockHash,
+        block_header: &BlockHeader,
+    ) -> Result<bool, StateError> {
+        let checkpoint = match self.chain_meta_storage.get_checkpoint(&self.chain_id)? {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(true),
+        };
+
+        if block_header.level() < *checkpoint.level() {
+            Ok(true)
+        } else if block_header.level() == *checkpoint.level() {
+            Ok(checkpoint.block_hash() == block_hash)
+        } else {
+            match self.block_meta_storage.find_block_at_distance(
+                block_header.predecessor().clone(),
+                block_header.level() - 1 - checkpoint.level(),
+            )? {
+                Some(ancestor) => Ok(checkpoint.block_hash() == &ancestor),
+                None => Ok(true),
+            }
+        }
+    }
+
     /// Returns triplet:
     /// 1. protocol_hash
     /// 2. applied_predecessor (only if is already applied)
@@ -699,16 +750,83 @@ impl BlockchainState {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use slog::Level;
 
     use crypto::hash::chain_id_from_block_hash;
     use storage::tests_common::TmpStorage;
 
-    use crate::state::tests::prerequisites::create_logger;
+    use crate::shell_channel::ShellChannel;
+    use crate::state::tests::prerequisites::{
+        chain_feeder_mock, create_logger, create_test_actor_system,
+    };
 
     use super::*;
 
+    #[test]
+    fn test_is_compatible_with_checkpoint() -> Result<(), failure::Error> {
+        let log = create_logger(Level::Debug);
+        let storage = TmpStorage::create_to_out_dir("__test_is_compatible_with_checkpoint")?;
+        let actor_system = create_test_actor_system(log.clone());
+        let shell_channel = ShellChannel::actor(&actor_system)?;
+        let (chain_feeder_mock, _) =
+            chain_feeder_mock(&actor_system, "mocked_chain_feeder", shell_channel.clone())?;
+        let chain_meta_storage = ChainMetaStorage::new(storage.storage());
+
+        /*
+         * Genesis - A1 - A2 - A3 - A4 - A5 - A6 - A7 - A8
+         *                      \
+         *                       B1 - B2 - B3 - B4 - B5 - B6 - B7 - B8
+         */
+        let (blocksdb, chain_id) = data::init_storage_with_branches(storage.storage(), &log)?;
+        let chain_state = BlockchainState::new(
+            chain_feeder_mock,
+            storage.storage(),
+            shell_channel,
+            Arc::new(chain_id.clone()),
+            Arc::new(blocksdb.block_hash("Genesis")),
+        );
+        let is_compatible = |name: &str| {
+            let block = blocksdb.header(name);
+            chain_state.is_compatible_with_checkpoint(&block.hash, &block.header)
+        };
+
+        // without checkpoint, everything is compatible
+        for name in &["A2", "A5", "A8", "B2", "B8"] {
+            assert!(is_compatible(name)?, "{} should be compatible", name);
+        }
+
+        // set checkpoint to A5
+        let a5 = blocksdb.header("A5");
+        chain_meta_storage.set_checkpoint(
+            &chain_id,
+            Head::new(
+                a5.hash.clone(),
+                a5.header.level(),
+                a5.header.fitness().clone(),
+            ),
+        )?;
+
+        // block below checkpoint cannot be decided, so is compatible
+        assert!(is_compatible("A2")?);
+        assert!(is_compatible("B1")?);
+
+        // block at checkpoint level has to be checkpoint itself
+        assert!(is_compatible("A5")?);
+        assert!(!is_compatible("B2")?);
+
+        // block above checkpoint has to be its successor
+        assert!(is_compatible("A6")?);
+        assert!(is_compatible("A8")?);
+        assert!(!is_compatible("B3")?);
+        assert!(!is_compatible("B8")?);
+
+        // block above checkpoint with unknown predecessors cannot be decided, so is compatible
+        assert!(is_compatible("C5")?);
+
+        Ok(())
+    }
+
     /// This test is rewritten according to [test_state.ml -> test_locator]
     #[test]
     fn test_history_and_compute_locator() -> Result<(), failure::Error> {
@@ -900,15 +1018,19 @@ mod tests {
         Ok(())
     }
 
-    mod data {
+    pub(crate) mod data {
         use std::{collections::HashMap, convert::TryInto};
 
         use itertools::Itertools;
         use slog::Logger;
 
-        use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, HashType};
+        use crypto::hash::{
+            chain_id_from_block_hash, BlockHash, ChainId, CryptoboxPublicKeyHash, HashType,
+        };
+        use storage::block_meta_storage::Meta;
         use storage::{
             BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
+            PersistentStorage,
         };
         use tezos_messages::p2p::binary_message::BinaryRead;
         use tezos_messages::p2p::encoding::block_header::BlockHeader;
@@ -1113,6 +1235,43 @@ mod tests {
             BlocksDb { blocks }
         }
 
+        /// Stores genesis with branch A1..A8 and branch B1..B8 (forked from A3)
+        pub(crate) fn init_storage_with_branches(
+            persistent_storage: &PersistentStorage,
+            log: &Logger,
+        ) -> Result<(BlocksDb, ChainId), failure::Error> {
+            let block_storage = BlockStorage::new(persistent_storage);
+            let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+            let blocksdb = init_blocks();
+
+            let genesis = blocksdb.header("Genesis");
+            let chain_id = chain_id_from_block_hash(&genesis.hash)?;
+            block_storage.put_block_header(&genesis)?;
+            block_meta_storage.put(
+                &genesis.hash,
+                &Meta::genesis_meta(&genesis.hash, &chain_id, true),
+            )?;
+
+            store_branch(
+                &["A1", "A2", "A3", "A4", "A5", "A6", "A7", "A8"],
+                &chain_id,
+                &blocksdb,
+                &block_storage,
+                &block_meta_storage,
+                log,
+            );
+            store_branch(
+                &["B1", "B2", "B3", "B4", "B5", "B6", "B7", "B8"],
+                &chain_id,
+                &blocksdb,
+                &block_storage,
+                &block_meta_storage,
+                log,
+            );
+
+            Ok((blocksdb, chain_id))
+        }
+
         pub(crate) fn store_branch(
             branch: &[&str],
             chain_id: &ChainId,
@@ -9,7 +9,10 @@ use std::sync::{Arc, RwLock};
 use crypto::hash::{BlockHash, ChainId};
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::PersistentStorage;
-use storage::{BlockHeaderWithHash, ChainMetaStorage};
+use storage::{
+    BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
+    BlockStorageReader, ChainMetaStorage,
+};
 use tezos_messages::Head;
 
 use crate::mempool::CurrentMempoolStateStorageRef;
@@ -41,6 +44,10 @@ impl fmt::Display for HeadResult {
 }
 
 pub struct HeadState {
+    /// persistent block storage
+    block_storage: BlockStorage,
+    ///persistent block metadata storage
+    block_meta_storage: BlockMetaStorage,
     ///persistent chain metadata storage
     chain_meta_storage: ChainMetaStorage,
 
@@ -62,6 +69,8 @@ impl HeadState {
         chain_genesis_block_hash: Arc<BlockHash>,
     ) -> Self {
         HeadState {
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
             chain_meta_storage: ChainMetaStorage::new(persistent_storage),
             current_head_state,
             current_mempool_state,
@@ -149,6 +158,54 @@ impl HeadState {
         Ok(Some((head, head_result)))
     }
 
+    /// Moves checkpoint to the block at `last_allowed_fork_level` of the new head (if it is higher than actual checkpoint).
+    /// Original algorithm is in [state.ml][set_head], blocks below `last_allowed_fork_level` cannot be reorganized anymore.
+    /// Returns:
+    /// - None, if checkpoint was not changed
+    /// - Some(new_checkpoint)
+    pub fn try_update_checkpoint(&self, new_head: &Head) -> Result<Option<Head>, StateError> {
+        let last_allowed_fork_level = match self
+            .block_meta_storage
+            .get_additional_data(new_head.block_hash())?
+        {
+            Some(additional_data) => additional_data.last_allowed_fork_level(),
+            None => return Ok(None),
+        };
+
+        // checkpoint can only increase
+        if let Some(checkpoint) = self.chain_meta_storage.get_checkpoint(&self.chain_id)? {
+            if last_allowed_fork_level <= *checkpoint.level() {
+                return Ok(None);
+            }
+        }
+        if last_allowed_fork_level > *new_head.level() {
+            return Ok(None);
+        }
+
+        // find block on the new head's branch
+        let checkpoint_block = match self.block_meta_storage.find_block_at_distance(
+            new_head.block_hash().clone(),
+            new_head.level() - last_allowed_fork_level,
+        )? {
+            Some(block_hash) => self.block_storage.get(&block_hash)?,
+            None => None,
+        };
+
+        match checkpoint_block {
+            Some(block) => {
+                let checkpoint = Head::new(
+                    block.hash,
+                    block.header.level(),
+                    block.header.fitness().clone(),
+                );
+                self.chain_meta_storage
+                    .set_checkpoint(&self.chain_id, checkpoint.clone())?;
+                Ok(Some(checkpoint))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Tries to load last known current head from database
     pub(crate) fn load_current_head_state(&self) -> Result<Option<Head>, StateError> {
         match self.chain_meta_storage.get_current_head(&self.chain_id)? {
@@ -161,3 +218,105 @@ impl HeadState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use slog::Level;
+
+    use storage::block_meta_storage::BlockAdditionalData;
+    use storage::tests_common::TmpStorage;
+
+    use crate::mempool::init_mempool_state_storage;
+    use crate::state::chain_state::tests::data;
+    use crate::state::tests::prerequisites::create_logger;
+
+    use super::*;
+
+    #[test]
+    fn test_try_update_checkpoint() -> Result<(), failure::Error> {
+        let log = create_logger(Level::Debug);
+        let storage = TmpStorage::create_to_out_dir("__test_try_update_checkpoint")?;
+        let block_meta_storage = BlockMetaStorage::new(storage.storage());
+        let chain_meta_storage = ChainMetaStorage::new(storage.storage());
+
+        /*
+         * Genesis - A1 - A2 - A3 - A4 - A5 - A6 - A7 - A8
+         *                      \
+         *                       B1 - B2 - B3 - B4 - B5 - B6 - B7 - B8
+         */
+        let (blocksdb, chain_id) = data::init_storage_with_branches(storage.storage(), &log)?;
+        let head_state = HeadState::new(
+            storage.storage(),
+            init_current_head_state(),
+            init_mempool_state_storage(),
+            Arc::new(chain_id.clone()),
+            Arc::new(blocksdb.block_hash("Genesis")),
+        );
+
+        let head = |name: &str| {
+            let block = blocksdb.header(name);
+            Head::new(
+                block.hash.clone(),
+                block.header.level(),
+                block.header.fitness().clone(),
+            )
+        };
+        let store_last_allowed_fork_level = |name: &str, last_allowed_fork_level: i32| {
+            block_meta_storage.put_block_additional_data(
+                &blocksdb.block_hash(name),
+                &BlockAdditionalData::new(
+                    60,
+                    last_allowed_fork_level,
+                    "PtBMwNZT94N7gXKw4i273CKcSaBrrBnqnt3RATExNKr9KNX2USV".try_into()?,
+                    "PtBMwNZT94N7gXKw4i273CKcSaBrrBnqnt3RATExNKr9KNX2USV".try_into()?,
+                    None,
+                    None,
+                    None,
+                ),
+            )?;
+            Ok::<_, failure::Error>(())
+        };
+        let checkpoint = || -> Result<Option<BlockHash>, failure::Error> {
+            Ok(chain_meta_storage
+                .get_checkpoint(&chain_id)?
+                .map(|checkpoint| checkpoint.block_hash().clone()))
+        };
+
+        // without additional data, checkpoint is not changed
+        assert!(head_state.try_update_checkpoint(&head("A8"))?.is_none());
+        assert!(checkpoint()?.is_none());
+
+        // checkpoint is moved to the block at last_allowed_fork_level
+        store_last_allowed_fork_level("A8", 5)?;
+        let new_checkpoint = head_state
+            .try_update_checkpoint(&head("A8"))?
+            .expect("Checkpoint should be updated");
+        assert_eq!(new_checkpoint.block_hash(), &blocksdb.block_hash("A5"));
+        assert_eq!(*new_checkpoint.level(), 5);
+        assert_eq!(checkpoint()?, Some(blocksdb.block_hash("A5")));
+
+        // same or lower last_allowed_fork_level does not change checkpoint
+        assert!(head_state.try_update_checkpoint(&head("A8"))?.is_none());
+        store_last_allowed_fork_level("A7", 4)?;
+        assert!(head_state.try_update_checkpoint(&head("A7"))?.is_none());
+        assert_eq!(checkpoint()?, Some(blocksdb.block_hash("A5")));
+
+        // last_allowed_fork_level above the head level does not change checkpoint
+        store_last_allowed_fork_level("A6", 7)?;
+        assert!(head_state.try_update_checkpoint(&head("A6"))?.is_none());
+        assert_eq!(checkpoint()?, Some(blocksdb.block_hash("A5")));
+
+        // checkpoint is resolved on the new head's branch
+        store_last_allowed_fork_level("B8", 6)?;
+        let new_checkpoint = head_state
+            .try_update_checkpoint(&head("B8"))?
+            .expect("Checkpoint should be updated");
+        assert_eq!(new_checkpoint.block_hash(), &blocksdb.block_hash("B3"));
+        assert_eq!(*new_checkpoint.level(), 6);
+        assert_eq!(checkpoint()?, Some(blocksdb.block_hash("B3")));
+
+        Ok(())
+    }
+}
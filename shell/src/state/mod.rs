@@ -267,7 +267,7 @@ pub mod tests {
 
     pub(crate) mod prerequisites {
         use std::net::SocketAddr;
-        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::{AtomicBool, AtomicU64};
         use std::sync::mpsc::{channel, Receiver};
         use std::sync::{Arc, Mutex};
         use std::thread;
@@ -278,7 +278,7 @@ pub mod tests {
 
         use crypto::hash::CryptoboxPublicKeyHash;
         use networking::p2p::network_channel::NetworkChannelRef;
-        use networking::p2p::peer::{BootstrapOutput, Peer};
+        use networking::p2p::peer::{BootstrapOutput, Peer, PeerConnectionState};
         use networking::PeerId;
         use tezos_identity::Identity;
         use tezos_messages::p2p::encoding::prelude::{MetadataMessage, NetworkVersion};
@@ -304,6 +304,10 @@ pub mod tests {
 
             let metadata = MetadataMessage::new(false, false);
             let version = NetworkVersion::new("".to_owned(), 0, 0);
+            let connection_state = Arc::new(PeerConnectionState::new(
+                Arc::new(AtomicU64::new(0)),
+                Arc::new(AtomicU64::new(0)),
+            ));
             let peer_ref = Peer::actor(
                 &peer_id_marker,
                 sys,
@@ -317,6 +321,7 @@ pub mod tests {
                     metadata.clone(),
                     version,
                     socket_address,
+                    connection_state.clone(),
                 ),
             )
             .unwrap();
@@ -327,6 +332,7 @@ pub mod tests {
                     peer_public_key_hash,
                     peer_id_marker,
                     socket_address,
+                    connection_state,
                 )),
                 &metadata,
                 DataQueuesLimits {
@@ -91,6 +91,11 @@ impl PeerState {
         }
     }
 
+    /// Returns true, if peer has enabled mempool and our mempool relay to the peer is not disabled by the policy
+    pub fn is_mempool_relay_enabled(&self) -> bool {
+        self.mempool_enabled && !self.peer_id.connection_state.policy().disable_mempool_relay
+    }
+
     fn available_mempool_operations_queue_capacity(&self) -> usize {
         let queued_count = self.queued_mempool_operations.len();
         if queued_count < MEMPOOL_OPERATIONS_BATCH_SIZE {
@@ -134,6 +139,14 @@ impl PeerState {
         self.missing_operations_for_blocks.clear();
     }
 
+    /// Drops chain related state, when peer deactivates our chain,
+    /// peer is activated again by its next current branch/head.
+    pub fn deactivate(&mut self) {
+        self.clear();
+        self.is_bootstrapped = false;
+        self.current_head_level = None;
+    }
+
     pub fn add_missing_mempool_operations(
         &mut self,
         operation_hash: OperationHash,
@@ -335,3 +348,45 @@ impl UpdateIsBootstrapped for PeerState {
 pub fn tell_peer(msg: Arc<PeerMessageResponse>, peer: &PeerState) {
     peer.peer_id.peer_ref.tell(SendMessage::new(msg), None);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use networking::p2p::network_channel::NetworkChannel;
+    use slog::Level;
+
+    use crate::state::tests::prerequisites::{
+        create_logger, create_test_actor_system, create_test_tokio_runtime, test_peer,
+    };
+
+    #[test]
+    fn test_deactivate_drops_chain_state() {
+        // prerequisities
+        let log = create_logger(Level::Debug);
+        let tokio_runtime = create_test_tokio_runtime();
+        let actor_system = create_test_actor_system(log);
+        let network_channel =
+            NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+
+        let mut peer = test_peer(&actor_system, network_channel, &tokio_runtime, 7790);
+        peer.is_bootstrapped = true;
+        assert!(peer.update_current_head_level(100));
+        peer.missing_operations_for_blocks.insert(
+            "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe"
+                .try_into()
+                .unwrap(),
+            vec![0].into_iter().collect(),
+        );
+
+        // peer deactivated our chain
+        peer.deactivate();
+        assert!(!peer.is_bootstrapped);
+        assert!(peer.current_head_level.is_none());
+        assert!(peer.missing_operations_for_blocks.is_empty());
+
+        // peer is activated again by its next current head
+        assert!(peer.update_current_head_level(90));
+        assert_eq!(Some(90), peer.current_head_level);
+    }
+}
@@ -19,6 +19,7 @@ use lazy_static::lazy_static;
 use serial_test::serial;
 
 use crypto::hash::OperationHash;
+use networking::p2p::throttle::BandwidthLimits;
 use networking::ShellCompatibilityVersion;
 use shell::mempool::find_mempool_prevalidator;
 use shell::peer_manager::P2p;
@@ -50,6 +51,11 @@ lazy_static! {
             disable_mempool: false,
             private_node: false,
             bootstrap_peers: vec![],
+            trusted_peers: vec![],
+            trusted_peer_ids: HashSet::new(),
+            private_peer_whitelist: HashSet::new(),
+            peer_policies: HashMap::new(),
+            bandwidth_limits: BandwidthLimits::default(),
             peer_threshold: PeerConnectionThreshold::try_new(0, 10, Some(0)).expect("Invalid range"),
         },
         SHELL_COMPATIBILITY_VERSION.clone(),
@@ -979,8 +985,10 @@ fn test_process_bootstrap_level1324_and_generate_action_file() -> Result<(), fai
     // context action recorders
     let context_action_recorders = vec![
         // action file recorder
-        Box::new(ActionFileStorage::new(target_action_file.clone()))
-            as Box<dyn ActionRecorder + Send>,
+        Box::new(ActionFileStorage::new(
+            target_action_file.clone(),
+            storage.storage(),
+        )) as Box<dyn ActionRecorder + Send>,
     ];
 
     // start node
@@ -36,7 +36,7 @@ use shell::PeerConnectionThreshold;
 use storage::chain_meta_storage::ChainMetaStorageReader;
 use storage::context::{ActionRecorder, ContextApi, TezedgeContext};
 use storage::tests_common::TmpStorage;
-use storage::{resolve_storage_init_chain_data, BlockStorage, ChainMetaStorage};
+use storage::{resolve_storage_init_chain_data, BlockStorage, ChainMetaStorage, HistoryMode};
 use tezos_api::environment::TezosEnvironmentConfiguration;
 use tezos_api::ffi::{PatchContext, TezosRuntimeConfiguration};
 use tezos_identity::Identity;
@@ -102,6 +102,7 @@ impl NodeInfrastructure {
             &tmp_storage.path(),
             &context_db_path,
             &patch_context,
+            &HistoryMode::Archive,
             one_context,
             &log,
         )
@@ -248,6 +249,7 @@ impl NodeInfrastructure {
                 &actor_system,
                 network_channel.clone(),
                 shell_channel.clone(),
+                persistent_storage.clone(),
                 tokio_runtime.handle().clone(),
                 identity,
                 Arc::new(shell_compatibility_version),
@@ -22,6 +22,7 @@ use crypto::hash::OperationHash;
 use networking::p2p::peer;
 use networking::p2p::peer::{Bootstrap, BootstrapOutput};
 use networking::p2p::stream::{EncryptedMessageReader, EncryptedMessageWriter};
+use networking::p2p::throttle::BandwidthLimiter;
 use networking::{LocalPeerInfo, ShellCompatibilityVersion};
 use tezos_identity::Identity;
 use tezos_messages::p2p::encoding::prelude::{Mempool, PeerMessage, PeerMessageResponse};
@@ -87,6 +88,7 @@ impl TestNodePeer {
                             server_address,
                             false,
                             false,
+                            Arc::new(BandwidthLimiter::default()),
                         );
 
                         match peer::bootstrap(bootstrap, local, &log).await {
@@ -158,6 +160,7 @@ impl TestNodePeer {
                             server_address,
                             false,
                             false,
+                            Arc::new(BandwidthLimiter::default()),
                         );
 
                         match peer::bootstrap(bootstrap, local, &log).await {
@@ -7,7 +7,7 @@ extern crate test;
 ///
 /// (Tests are ignored, because they need protocol-runner binary)
 /// Runs like: `PROTOCOL_RUNNER=./target/release/protocol-runner cargo test --release -- --ignored`
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
@@ -15,6 +15,7 @@ use std::time::Duration;
 use lazy_static::lazy_static;
 use serial_test::serial;
 
+use networking::p2p::throttle::BandwidthLimits;
 use networking::ShellCompatibilityVersion;
 use shell::peer_manager::P2p;
 use shell::PeerConnectionThreshold;
@@ -39,6 +40,11 @@ lazy_static! {
             disable_mempool: false,
             private_node: false,
             bootstrap_peers: vec![],
+            trusted_peers: vec![],
+            trusted_peer_ids: HashSet::new(),
+            private_peer_whitelist: HashSet::new(),
+            peer_policies: HashMap::new(),
+            bandwidth_limits: BandwidthLimits::default(),
             peer_threshold: PeerConnectionThreshold::try_new(0, 2, Some(0)).expect("Invalid range"),
         },
         SHELL_COMPATIBILITY_VERSION.clone(),
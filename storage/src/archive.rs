@@ -0,0 +1,713 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Block archives allow to move chain data between machines and storage backends without downloading them from p2p.
+//!
+//! Archive contains blocks of one branch ordered by level, layout is `<version><header><chunk>*<end>`,
+//! where every part is prefixed with its size (u32, big-endian):
+//!
+//! - version - `tezedge-block-archive-1`
+//! - header - chain id and exported level range
+//! - chunk - up to [BLOCKS_PER_CHUNK] blocks followed by blake2b checksum of the chunk data (32 bytes, not counted in size)
+//! - end - empty part
+//!
+//! Every block consists of the block hash, block header and operations (all validation passes) encoded with p2p binary encoding,
+//! and optional metadata json (just applied blocks have them). Archive structures are encoded with bincode.
+//!
+//! Import checks that block hashes match the block headers, operations belong to the blocks and match the operations hash
+//! of the headers and that blocks continue the local chain one by one. Headers and operations are stored together with the block meta and predecessor indexes,
+//! but blocks are not marked as applied - they have to be applied by the node (see `chain_feeder`),
+//! which also replaces the imported metadata json.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use failure::Fail;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+
+use crypto::blake2b;
+use crypto::hash::{BlockHash, ChainId};
+use tezos_messages::p2p::binary_message::{BinaryRead, BinaryWrite, MessageHash};
+use tezos_messages::p2p::encoding::block_header::Level;
+use tezos_messages::p2p::encoding::prelude::{BlockHeader, OperationsForBlocksMessage};
+
+use crate::{
+    BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockMetaStorageReader, BlockStorage,
+    BlockStorageReader, OperationsMetaStorage, OperationsStorage, OperationsStorageReader,
+    PersistentStorage, StorageError,
+};
+
+/// Supported archive version
+pub const ARCHIVE_VERSION: &str = "tezedge-block-archive-1";
+
+/// Count of blocks stored in one chunk
+pub const BLOCKS_PER_CHUNK: usize = 256;
+
+/// Protection against allocation of nonsense sizes from corrupted files
+const MAX_CHUNK_SIZE: usize = 512 * 1024 * 1024;
+
+const CHECKSUM_LEN: usize = 32;
+
+/// Possible errors for archive export/import
+#[derive(Debug, Fail)]
+pub enum ArchiveError {
+    #[fail(display = "I/O error: {}", error)]
+    IoError { error: io::Error },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Unsupported archive version: {}", version)]
+    UnsupportedVersion { version: String },
+    #[fail(display = "Failed to encode/decode archive data, reason: {}", reason)]
+    EncodingError { reason: String },
+    #[fail(display = "Invalid archive data, reason: {}", reason)]
+    InvalidData { reason: String },
+    #[fail(display = "Checksum of archive chunk {} does not match", chunk)]
+    ChecksumMismatch { chunk: usize },
+    #[fail(
+        display = "Block {} cannot be exported, reason: {}",
+        block_hash, reason
+    )]
+    BlockNotAvailable { block_hash: String, reason: String },
+    #[fail(
+        display = "Archive was exported for chain {}, but node runs chain {}",
+        archive, expected
+    )]
+    ChainIdMismatch { expected: String, archive: String },
+    #[fail(
+        display = "Archived block hash {} does not match hash {} of its header",
+        archived, computed
+    )]
+    BlockHashMismatch { archived: String, computed: String },
+    #[fail(
+        display = "Archived operations of block {} do not match operations hash {} of its header",
+        block_hash, expected
+    )]
+    OperationsHashMismatch {
+        block_hash: String,
+        expected: String,
+    },
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(error: io::Error) -> Self {
+        ArchiveError::IoError { error }
+    }
+}
+
+impl From<StorageError> for ArchiveError {
+    fn from(error: StorageError) -> Self {
+        ArchiveError::StorageError { error }
+    }
+}
+
+impl From<bincode::Error> for ArchiveError {
+    fn from(error: bincode::Error) -> Self {
+        ArchiveError::EncodingError {
+            reason: format!("{}", error),
+        }
+    }
+}
+
+impl slog::Value for ArchiveError {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+fn invalid_data<S: Into<String>>(reason: S) -> ArchiveError {
+    ArchiveError::InvalidData {
+        reason: reason.into(),
+    }
+}
+
+fn encoding_error<E: fmt::Display>(error: E) -> ArchiveError {
+    ArchiveError::EncodingError {
+        reason: format!("{}", error),
+    }
+}
+
+/// Describes content of the archive
+#[derive(Debug, Clone, PartialEq, Getters)]
+pub struct ArchiveInfo {
+    #[get = "pub"]
+    chain_id: ChainId,
+    #[get = "pub"]
+    from_level: Level,
+    #[get = "pub"]
+    to_level: Level,
+}
+
+impl ArchiveInfo {
+    pub fn blocks_count(&self) -> usize {
+        (self.to_level - self.from_level + 1) as usize
+    }
+}
+
+/// Result of the archive import
+#[derive(Debug, Getters)]
+pub struct ArchiveImport {
+    #[get = "pub"]
+    info: ArchiveInfo,
+    /// Imported blocks, which were not applied yet, ordered by level
+    #[get = "pub"]
+    blocks_to_apply: Vec<BlockHash>,
+}
+
+impl ArchiveImport {
+    pub fn into_blocks_to_apply(self) -> Vec<BlockHash> {
+        self.blocks_to_apply
+    }
+}
+
+/// Exports branch ending with block `block_hash` starting from level `from_level` to the archive.
+///
+/// All exported blocks must have all operations, genesis is never exported (it is initialized by node).
+pub fn export_archive<W: Write>(
+    persistent_storage: &PersistentStorage,
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    from_level: Level,
+    output: W,
+    log: &Logger,
+) -> Result<ArchiveInfo, ArchiveError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let operations_storage = OperationsStorage::new(persistent_storage);
+    let not_available = |block_hash: &BlockHash, reason: &str| ArchiveError::BlockNotAvailable {
+        block_hash: block_hash.to_base58_check(),
+        reason: reason.to_string(),
+    };
+
+    let last_block = block_storage
+        .get(block_hash)?
+        .ok_or_else(|| not_available(block_hash, "missing block header"))?;
+    let from_level = from_level.max(1);
+    if last_block.header.level() < from_level {
+        return Err(not_available(
+            block_hash,
+            &format!("block is below requested level {}", from_level),
+        ));
+    }
+
+    // collect branch from the last block down to the requested level
+    let mut block_hashes =
+        Vec::with_capacity((last_block.header.level() - from_level + 1) as usize);
+    let mut block = last_block;
+    loop {
+        block_hashes.push(block.hash.clone());
+        if block.header.level() <= from_level {
+            break;
+        }
+        block = block_storage
+            .get(block.header.predecessor())?
+            .ok_or_else(|| not_available(block.header.predecessor(), "missing block header"))?;
+    }
+    block_hashes.reverse();
+
+    let info = ArchiveInfo {
+        chain_id: chain_id.clone(),
+        from_level,
+        to_level: from_level + block_hashes.len() as Level - 1,
+    };
+    info!(log, "Exporting archive";
+               "chain_id" => info.chain_id.to_base58_check(),
+               "from_level" => info.from_level,
+               "to_level" => info.to_level);
+
+    let mut writer = ArchiveWriter::new(output, &info)?;
+    for chunk in block_hashes.chunks(BLOCKS_PER_CHUNK) {
+        let mut blocks = Vec::with_capacity(chunk.len());
+        for block_hash in chunk {
+            let block = block_storage
+                .get(block_hash)?
+                .ok_or_else(|| not_available(block_hash, "missing block header"))?;
+            let operations = operations_storage.get_operations(block_hash)?;
+            if operations.len() != block.header.validation_pass() as usize {
+                return Err(not_available(block_hash, "missing block operations"));
+            }
+
+            blocks.push(ArchivedBlock {
+                block_hash: block.hash.as_ref().clone(),
+                block_header: block.header.as_bytes().map_err(encoding_error)?,
+                operations: operations
+                    .iter()
+                    .map(|message| message.as_bytes().map_err(encoding_error))
+                    .collect::<Result<_, _>>()?,
+                json_data: block_storage.get_json_data(block_hash)?,
+            });
+        }
+        writer.write_chunk(&blocks)?;
+    }
+    writer.finish()?;
+
+    info!(log, "Archive exported";
+               "chain_id" => info.chain_id.to_base58_check(),
+               "from_level" => info.from_level,
+               "to_level" => info.to_level,
+               "blocks" => info.blocks_count());
+
+    Ok(info)
+}
+
+/// Imports blocks from the archive to the storage.
+///
+/// Archive has to continue local chain, so predecessor of the first archived block must be already stored.
+/// Returns imported blocks, which need to be applied.
+pub fn import_archive<R: Read>(
+    persistent_storage: &PersistentStorage,
+    chain_id: &ChainId,
+    input: R,
+    log: &Logger,
+) -> Result<ArchiveImport, ArchiveError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let operations_storage = OperationsStorage::new(persistent_storage);
+    let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+
+    let mut reader = ArchiveReader::new(input)?;
+    let info = reader.info.clone();
+    if &info.chain_id != chain_id {
+        return Err(ArchiveError::ChainIdMismatch {
+            expected: chain_id.to_base58_check(),
+            archive: info.chain_id.to_base58_check(),
+        });
+    }
+    info!(log, "Importing archive";
+               "chain_id" => info.chain_id.to_base58_check(),
+               "from_level" => info.from_level,
+               "to_level" => info.to_level);
+
+    let mut blocks_to_apply = vec![];
+    let mut previous: Option<(BlockHash, Level)> = None;
+    while let Some(blocks) = reader.read_chunk()? {
+        for archived_block in blocks {
+            let (block, operations) = archived_block.decode()?;
+
+            // block must continue the previous one (or the local chain)
+            let expected_level = match &previous {
+                Some((previous_hash, previous_level)) => {
+                    if block.header.predecessor() != previous_hash {
+                        return Err(invalid_data(format!(
+                            "block {} does not continue previous archived block {}",
+                            block.hash.to_base58_check(),
+                            previous_hash.to_base58_check()
+                        )));
+                    }
+                    previous_level + 1
+                }
+                None => {
+                    if block_storage.get(block.header.predecessor())?.is_none() {
+                        return Err(invalid_data(format!(
+                            "predecessor {} of the first archived block is not stored, archive does not continue local chain",
+                            block.header.predecessor().to_base58_check()
+                        )));
+                    }
+                    info.from_level
+                }
+            };
+            if block.header.level() != expected_level {
+                return Err(invalid_data(format!(
+                    "unexpected level {} of block {}, expected {}",
+                    block.header.level(),
+                    block.hash.to_base58_check(),
+                    expected_level
+                )));
+            }
+            previous = Some((block.hash.clone(), block.header.level()));
+
+            // applied blocks are left untouched
+            if block_meta_storage.is_applied(&block.hash)? {
+                continue;
+            }
+
+            block_storage.put_block_header(&block)?;
+            let meta = block_meta_storage.put_block_header(&block, chain_id, log)?;
+            block_meta_storage.store_predecessors(&block.hash, &meta)?;
+            operations_meta_storage.put_block_header(&block)?;
+            for message in &operations {
+                operations_storage.put_operations(message)?;
+                operations_meta_storage.put_operations(message)?;
+            }
+            if let Some(json_data) = archived_block.json_data {
+                block_storage.put_block_json_data(&block.hash, json_data)?;
+            }
+            blocks_to_apply.push(block.hash);
+        }
+    }
+
+    match previous {
+        Some((_, last_level)) if last_level == info.to_level => (),
+        _ => {
+            return Err(invalid_data(format!(
+                "archive does not contain all blocks up to level {}",
+                info.to_level
+            )))
+        }
+    }
+
+    info!(log, "Archive imported";
+               "chain_id" => info.chain_id.to_base58_check(),
+               "from_level" => info.from_level,
+               "to_level" => info.to_level,
+               "blocks_to_apply" => blocks_to_apply.len());
+
+    Ok(ArchiveImport {
+        info,
+        blocks_to_apply,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveHeader {
+    chain_id: Vec<u8>,
+    from_level: Level,
+    to_level: Level,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedBlock {
+    block_hash: Vec<u8>,
+    /// Block header with p2p binary encoding
+    block_header: Vec<u8>,
+    /// Operations of all validation passes with p2p binary encoding
+    operations: Vec<Vec<u8>>,
+    json_data: Option<BlockJsonData>,
+}
+
+impl ArchivedBlock {
+    /// Decodes block and validates its hash and operations
+    fn decode(
+        &self,
+    ) -> Result<(BlockHeaderWithHash, Vec<OperationsForBlocksMessage>), ArchiveError> {
+        let block = BlockHeaderWithHash::new(
+            BlockHeader::from_bytes(&self.block_header).map_err(encoding_error)?,
+        )
+        .map_err(encoding_error)?;
+        if block.hash.as_ref() != &self.block_hash {
+            return Err(ArchiveError::BlockHashMismatch {
+                archived: BlockHash::try_from(self.block_hash.clone())
+                    .map(|block_hash| block_hash.to_base58_check())
+                    .unwrap_or_else(|_| hex::encode(&self.block_hash)),
+                computed: block.hash.to_base58_check(),
+            });
+        }
+
+        let mut validation_passes = HashSet::new();
+        let operations = self
+            .operations
+            .iter()
+            .map(|bytes| {
+                let message =
+                    OperationsForBlocksMessage::from_bytes(bytes).map_err(encoding_error)?;
+                let operations_for_block = message.operations_for_block();
+                if operations_for_block.hash() != &block.hash
+                    || operations_for_block.validation_pass() < 0
+                    || operations_for_block.validation_pass()
+                        >= block.header.validation_pass() as i8
+                    || !validation_passes.insert(operations_for_block.validation_pass())
+                {
+                    return Err(invalid_data(format!(
+                        "invalid operations (validation_pass: {}) for block {}",
+                        operations_for_block.validation_pass(),
+                        block.hash.to_base58_check()
+                    )));
+                }
+                Ok(message)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if operations.len() != block.header.validation_pass() as usize {
+            return Err(invalid_data(format!(
+                "missing operations for block {}",
+                block.hash.to_base58_check()
+            )));
+        }
+        if operations_hash(&operations)?.as_slice() != block.header.operations_hash().as_ref() {
+            return Err(ArchiveError::OperationsHashMismatch {
+                block_hash: block.hash.to_base58_check(),
+                expected: block.header.operations_hash().to_base58_check(),
+            });
+        }
+
+        Ok((block, operations))
+    }
+}
+
+/// Computes operations hash of the block header from the operations of all validation passes
+fn operations_hash(operations: &[OperationsForBlocksMessage]) -> Result<Vec<u8>, ArchiveError> {
+    let mut operations = operations.iter().collect::<Vec<_>>();
+    operations.sort_by_key(|message| message.operations_for_block().validation_pass());
+
+    let operation_list_hashes = operations
+        .iter()
+        .map(|message| {
+            let operation_hashes = message
+                .operations()
+                .iter()
+                .map(|operation| operation.message_hash().map_err(encoding_error))
+                .collect::<Result<Vec<_>, _>>()?;
+            blake2b::merkle_tree(&operation_hashes).map_err(encoding_error)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    blake2b::merkle_tree(&operation_list_hashes).map_err(encoding_error)
+}
+
+struct ArchiveWriter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    fn new(mut output: W, info: &ArchiveInfo) -> Result<Self, ArchiveError> {
+        write_part(&mut output, ARCHIVE_VERSION.as_bytes())?;
+        write_part(
+            &mut output,
+            &bincode::serialize(&ArchiveHeader {
+                chain_id: info.chain_id.as_ref().clone(),
+                from_level: info.from_level,
+                to_level: info.to_level,
+            })?,
+        )?;
+        Ok(Self { output })
+    }
+
+    fn write_chunk(&mut self, blocks: &[ArchivedBlock]) -> Result<(), ArchiveError> {
+        let data = bincode::serialize(blocks)?;
+        write_part(&mut self.output, &data)?;
+        self.output.write_all(&checksum(&data)?)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), ArchiveError> {
+        write_part(&mut self.output, &[])?;
+        self.output.flush().map_err(ArchiveError::from)
+    }
+}
+
+struct ArchiveReader<R: Read> {
+    input: R,
+    info: ArchiveInfo,
+    chunks: usize,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    fn new(mut input: R) -> Result<Self, ArchiveError> {
+        let version = read_part(&mut input)?;
+        if version != ARCHIVE_VERSION.as_bytes() {
+            return Err(ArchiveError::UnsupportedVersion {
+                version: String::from_utf8_lossy(&version).to_string(),
+            });
+        }
+
+        let header: ArchiveHeader = bincode::deserialize(&read_part(&mut input)?)?;
+        if header.from_level < 1 || header.to_level < header.from_level {
+            return Err(invalid_data(format!(
+                "invalid level range {}..={}",
+                header.from_level, header.to_level
+            )));
+        }
+        let info = ArchiveInfo {
+            chain_id: ChainId::try_from(header.chain_id).map_err(encoding_error)?,
+            from_level: header.from_level,
+            to_level: header.to_level,
+        };
+        Ok(Self {
+            input,
+            info,
+            chunks: 0,
+        })
+    }
+
+    /// Returns None at the end of the archive
+    fn read_chunk(&mut self) -> Result<Option<Vec<ArchivedBlock>>, ArchiveError> {
+        let data = read_part(&mut self.input)?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let mut expected_checksum = [0_u8; CHECKSUM_LEN];
+        self.input.read_exact(&mut expected_checksum)?;
+        if checksum(&data)? != expected_checksum[..] {
+            return Err(ArchiveError::ChecksumMismatch { chunk: self.chunks });
+        }
+        self.chunks += 1;
+
+        Ok(Some(bincode::deserialize(&data)?))
+    }
+}
+
+fn checksum(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    blake2b::digest_256(data).map_err(encoding_error)
+}
+
+fn write_part<W: Write>(output: &mut W, bytes: &[u8]) -> Result<(), ArchiveError> {
+    output.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    output.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_part<R: Read>(input: &mut R) -> Result<Vec<u8>, ArchiveError> {
+    let mut size = [0_u8; 4];
+    input.read_exact(&mut size)?;
+    let size = u32::from_be_bytes(size) as usize;
+    if size > MAX_CHUNK_SIZE {
+        return Err(invalid_data(format!(
+            "chunk size {} exceeds limit {}",
+            size, MAX_CHUNK_SIZE
+        )));
+    }
+
+    let mut bytes = vec![0_u8; size];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use tezos_messages::p2p::encoding::prelude::{BlockHeaderBuilder, OperationsForBlock, Path};
+
+    use super::*;
+
+    fn info() -> ArchiveInfo {
+        ArchiveInfo {
+            chain_id: "NetXgtSLGNJvNye".try_into().unwrap(),
+            from_level: 1,
+            to_level: 2,
+        }
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut data = vec![];
+        write_part(&mut data, b"tezedge-block-archive-0").unwrap();
+
+        match ArchiveReader::new(data.as_slice()) {
+            Err(ArchiveError::UnsupportedVersion { version }) => {
+                assert_eq!("tezedge-block-archive-0", version)
+            }
+            _ => panic!("Expected UnsupportedVersion error"),
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_chunks() {
+        let mut data = vec![];
+        let mut writer = ArchiveWriter::new(&mut data, &info()).unwrap();
+        writer
+            .write_chunk(&[ArchivedBlock {
+                block_hash: vec![1; 32],
+                block_header: vec![2, 3],
+                operations: vec![vec![4]],
+                json_data: None,
+            }])
+            .unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::new(data.as_slice()).unwrap();
+        assert_eq!(info(), reader.info);
+        let blocks = reader.read_chunk().unwrap().unwrap();
+        assert_eq!(1, blocks.len());
+        assert_eq!(vec![2, 3], blocks[0].block_header);
+        assert_eq!(vec![vec![4]], blocks[0].operations);
+        assert!(reader.read_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_corrupted_chunk() {
+        let mut data = vec![];
+        let mut writer = ArchiveWriter::new(&mut data, &info()).unwrap();
+        writer.write_chunk(&[]).unwrap();
+        writer.finish().unwrap();
+
+        // flip last byte of the checksum
+        let checksum_end = data.len() - 4 - 1;
+        data[checksum_end] ^= 0xFF;
+
+        let mut reader = ArchiveReader::new(data.as_slice()).unwrap();
+        assert!(matches!(
+            reader.read_chunk(),
+            Err(ArchiveError::ChecksumMismatch { chunk: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_block_hash() {
+        let block_header = BlockHeaderBuilder::default()
+            .level(1)
+            .proto(0)
+            .predecessor(vec![0; 32].try_into().unwrap())
+            .timestamp(5_635_634)
+            .validation_pass(0)
+            .operations_hash(
+                "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc"
+                    .try_into()
+                    .unwrap(),
+            )
+            .fitness(vec![vec![0, 1]])
+            .context(vec![0; 32].try_into().unwrap())
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        let archived_block = ArchivedBlock {
+            block_hash: vec![1; 32],
+            block_header: block_header.as_bytes().unwrap(),
+            operations: vec![],
+            json_data: None,
+        };
+        assert!(matches!(
+            archived_block.decode(),
+            Err(ArchiveError::BlockHashMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_operations_hash() {
+        let archived_block = |operations_hash: Vec<u8>| {
+            let block = BlockHeaderWithHash::new(
+                BlockHeaderBuilder::default()
+                    .level(1)
+                    .proto(0)
+                    .predecessor(vec![0; 32].try_into().unwrap())
+                    .timestamp(5_635_634)
+                    .validation_pass(1)
+                    .operations_hash(operations_hash.try_into().unwrap())
+                    .fitness(vec![vec![0, 1]])
+                    .context(vec![0; 32].try_into().unwrap())
+                    .protocol_data(vec![])
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+            let operations = OperationsForBlocksMessage::new(
+                OperationsForBlock::new(block.hash.clone(), 0),
+                Path::op(),
+                vec![],
+            );
+            ArchivedBlock {
+                block_hash: block.hash.as_ref().clone(),
+                block_header: block.header.as_bytes().unwrap(),
+                operations: vec![operations.as_bytes().unwrap()],
+                json_data: None,
+            }
+        };
+
+        // one validation pass without operations
+        let empty_pass = blake2b::merkle_tree(&[]).unwrap();
+        let valid = blake2b::merkle_tree(&[empty_pass]).unwrap();
+        assert!(archived_block(valid).decode().is_ok());
+
+        let invalid = blake2b::merkle_tree(&[]).unwrap();
+        assert!(matches!(
+            archived_block(invalid).decode(),
+            Err(ArchiveError::OperationsHashMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+use clap::{App, Arg};
+use failure::{format_err, Error};
+use slog::{info, Drain, Level, Logger};
+
+use storage::context::actions::action_file::convert_v1_to_v2;
+
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    first_level: u32,
+}
+
+impl Args {
+    pub fn read_args() -> Self {
+        let app = App::new("action-file-converter")
+            .about("Converts context action file from the v1 format to the indexed v2 format")
+            .arg(Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the v1 actions file"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the converted v2 actions file, index is created next to it (<output>.idx)"))
+            .arg(Arg::with_name("first_level")
+                .long("first_level")
+                .takes_value(true)
+                .default_value("0")
+                .help("Level of the first block in the v1 file (v1 file does not contain levels), 0 for files recorded from genesis"));
+
+        let matches = app.get_matches();
+
+        Self {
+            input: matches
+                .value_of("input")
+                .unwrap()
+                .parse::<PathBuf>()
+                .expect("Provided value cannot be converted to path"),
+            output: matches
+                .value_of("output")
+                .unwrap()
+                .parse::<PathBuf>()
+                .expect("Provided value cannot be converted to path"),
+            first_level: matches
+                .value_of("first_level")
+                .unwrap()
+                .parse::<u32>()
+                .expect("Provided value cannot be converted to number"),
+        }
+    }
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .chan_size(32768)
+    .overflow_strategy(slog_async::OverflowStrategy::Block)
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::read_args();
+    let log = create_logger();
+
+    if args.output.exists() {
+        return Err(format_err!(
+            "Output file {:?} already exists, remove it first",
+            args.output
+        ));
+    }
+
+    info!(log, "Converting actions file"; "input" => format!("{:?}", args.input), "output" => format!("{:?}", args.output));
+    let blocks = convert_v1_to_v2(&args.input, &args.output, args.first_level)?;
+    info!(log, "Actions file converted"; "blocks" => blocks);
+
+    Ok(())
+}
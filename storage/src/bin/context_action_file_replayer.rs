@@ -1,20 +1,39 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::fs::File;
-use std::fs::OpenOptions;
+//! Replays recorded context actions into fresh context stores, verifies the resulting hashes
+//! against the recorded ones and writes per-block statistics and timing reports.
+//!
+//! Every replayed backend writes into `--output`:
+//! - `<backend>.stats.txt` - merkle storage latency statistics
+//! - `<backend>.report.json` or `<backend>.report.csv` - per-block timing report (json lines or csv)
+//! - `<backend>.checkpoint.json` - last fully replayed block, used by `--resume`
+//!
+//! and after all backends finished, `comparison.json` or `comparison.csv` summarizes the runs.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{sync_channel, Receiver};
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fs, path::PathBuf, sync::Arc};
 
 use clap::{App, Arg};
-use failure::Error;
-use slog::{debug, info, warn, Drain, Level, Logger};
+use failure::{Error, Fail};
+use serde::{Deserialize, Serialize};
+use slog::{debug, error, info, Drain, Level, Logger};
 
-use crypto::hash::ChainId;
-use storage::context::actions::action_file::ActionsFileReader;
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use storage::context::actions::action_file::{ActionFileError, ActionsBlock, ActionsFileReader};
 use storage::context::actions::get_new_tree_hash;
+use storage::context::gc::ContextGc;
+use storage::context::kv_store::compressed::ContextCompression;
 use storage::context::kv_store::SupportedContextKeyValueStore;
 use storage::context::merkle::merkle_storage::MerkleStorage;
 use storage::context::merkle::merkle_storage_stats::MerkleStorageAction;
@@ -24,18 +43,30 @@ use storage::initializer::{
     initialize_merkle, ContextKvStoreConfiguration, ContextRocksDbTableInitializer,
     GlobalRocksDbCacheHolder, MainChain, RocksDbConfig,
 };
+use storage::persistent::Flushable;
+use storage::HistoryMode;
 use tezos_context::channel::ContextAction;
 
+#[derive(Clone)]
 struct Args {
     blocks_per_cycle: usize,
     blocks_limit: Option<usize>,
     input: PathBuf,
     output: PathBuf,
-    context_kv_store: ContextKvStoreConfiguration,
+    context_kv_stores: Vec<ContextKvStoreConfiguration>,
+    history_mode: HistoryMode,
+    context_gc: ContextGc,
+    context_compression: Option<ContextCompression>,
+    report_format: ReportFormat,
+    resume: bool,
+    sequential: bool,
 }
 
 const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
 
+/// Count of decoded blocks waiting for replay
+const DECODED_BLOCKS_QUEUE_SIZE: usize = 16;
+
 impl Args {
     pub fn read_args() -> Self {
         let app = App::new("storage-stats")
@@ -63,11 +94,44 @@ impl Args {
             .arg(Arg::with_name("context-kv-store")
                 .long("context-kv-store")
                 .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
                 .value_name("STRING")
                 .required(true)
                 .default_value("rocksdb")
                 .possible_values(&SupportedContextKeyValueStore::possible_values())
-                .help("Choose the merkle storege backend - supported backends: 'rocksdb', 'sled', 'inmem', 'btree'"));
+                .help("Choose the merkle storege backends - supported backends: 'rocksdb', 'sled', 'inmem', 'btree', 'pack', more backends (e.g. 'rocksdb,pack') are replayed in parallel and compared"))
+            .arg(Arg::with_name("history-mode")
+                .long("history-mode")
+                .takes_value(true)
+                .value_name("STRING")
+                .default_value("archive")
+                .help("History mode of the replayed context - 'archive', 'full[:<cycles>]' or 'rolling[:<cycles>]', garbage collection is used for non-archive modes"))
+            .arg(Arg::with_name("context-gc")
+                .long("context-gc")
+                .takes_value(true)
+                .value_name("STRING")
+                .default_value("concurrent")
+                .possible_values(&ContextGc::possible_values())
+                .help("Garbage collector of the replayed context for non-archive history modes"))
+            .arg(Arg::with_name("context-compression-threshold")
+                .long("context-compression-threshold")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Compress replayed context entries with size (in bytes) above threshold, entries are not compressed by default"))
+            .arg(Arg::with_name("report-format")
+                .long("report-format")
+                .takes_value(true)
+                .value_name("STRING")
+                .default_value("json")
+                .possible_values(&ReportFormat::possible_values())
+                .help("Format of the per-block timing report and backends comparison - 'json' (json lines) or 'csv'"))
+            .arg(Arg::with_name("resume")
+                .long("resume")
+                .help("Continues from the last checkpoint in the output directory, instead of starting from scratch (not supported by 'inmem' and 'btree' backends)"))
+            .arg(Arg::with_name("sequential")
+                .long("sequential")
+                .help("Replays more backends one after another, so their timings do not affect each other"));
 
         let matches = app.get_matches();
 
@@ -77,14 +141,10 @@ impl Args {
             .parse::<PathBuf>()
             .expect("Provided value cannot be converted to path");
 
-        Self {
-            blocks_per_cycle: matches
-                .value_of("cycle-size")
-                .map(|s| s.parse::<usize>().unwrap())
-                .unwrap(),
-            context_kv_store: matches
-                .value_of("context-kv-store")
-                .unwrap()
+        let mut context_kv_stores: Vec<ContextKvStoreConfiguration> = Vec::new();
+        let mut context_kv_store_names: Vec<String> = Vec::new();
+        for value in matches.values_of("context-kv-store").unwrap() {
+            let context_kv_store = value
                 .parse::<SupportedContextKeyValueStore>()
                 .map(|v| match v {
                     SupportedContextKeyValueStore::RocksDB { .. } => {
@@ -105,6 +165,11 @@ impl Args {
                     SupportedContextKeyValueStore::BTreeMap => {
                         ContextKvStoreConfiguration::BTreeMap
                     }
+                    SupportedContextKeyValueStore::PackFile { .. } => {
+                        ContextKvStoreConfiguration::PackFile {
+                            path: out_dir.join("replayed_context_pack"),
+                        }
+                    }
                 })
                 .unwrap_or_else(|e| {
                     panic!(
@@ -112,7 +177,56 @@ impl Args {
                         SupportedContextKeyValueStore::possible_values(),
                         e
                     )
+                });
+            let (name, _) = resolve_context_kv_store(&context_kv_store);
+            if !context_kv_store_names.contains(&name) {
+                context_kv_store_names.push(name);
+                context_kv_stores.push(context_kv_store);
+            }
+        }
+
+        Self {
+            blocks_per_cycle: matches
+                .value_of("cycle-size")
+                .map(|s| s.parse::<usize>().unwrap())
+                .unwrap(),
+            context_kv_stores,
+            history_mode: matches
+                .value_of("history-mode")
+                .unwrap()
+                .parse::<HistoryMode>()
+                .unwrap_or_else(|e| panic!("Invalid history mode, error: {:?}", e)),
+            context_gc: matches
+                .value_of("context-gc")
+                .unwrap()
+                .parse::<ContextGc>()
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "Expecting one value from {:?}, error: {:?}",
+                        ContextGc::possible_values(),
+                        e
+                    )
+                }),
+            context_compression: matches.value_of("context-compression-threshold").map(
+                |threshold| ContextCompression {
+                    threshold: threshold
+                        .parse::<usize>()
+                        .expect("Provided value cannot be converted to number"),
+                },
+            ),
+            report_format: matches
+                .value_of("report-format")
+                .unwrap()
+                .parse::<ReportFormat>()
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "Expecting one value from {:?}, error: {:?}",
+                        ReportFormat::possible_values(),
+                        e
+                    )
                 }),
+            resume: matches.is_present("resume"),
+            sequential: matches.is_present("sequential"),
             blocks_limit: matches
                 .value_of("blocks_limit")
                 .map(|s| s.parse::<usize>().unwrap()),
@@ -126,6 +240,37 @@ impl Args {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    fn possible_values() -> Vec<&'static str> {
+        vec!["json", "csv"]
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("Invalid report format: {}", s)),
+        }
+    }
+}
+
 pub fn get_tree_action(action: &ContextAction) -> String {
     match action {
         ContextAction::Get { .. } => "ContextAction::Get".to_string(),
@@ -157,37 +302,6 @@ fn create_logger() -> Logger {
     Logger::root(drain, slog::o!())
 }
 
-// process actionfile without deselializing blocks
-// in order to get count of blocks
-fn get_blocks_count(log: &Logger, path: PathBuf) -> Result<u32, Error> {
-    let file = OpenOptions::new()
-        .write(false)
-        .create(false)
-        .read(true)
-        .open(path)?;
-
-    let mut reader = BufReader::new(file);
-    let mut pos = 0_u64;
-    let mut block_size = [0_u8; 4];
-    let mut counter = 0;
-    loop {
-        if reader.seek(SeekFrom::Start(pos)).is_err() {
-            warn!(log, "missing block operations information");
-            break;
-        }
-        if reader.read_exact(&mut block_size).is_err() {
-            break;
-        }
-        // skips header
-        pos += block_size.len() as u64;
-        // skips block
-        pos += u32::from_be_bytes(block_size) as u64;
-        counter += 1;
-    }
-
-    Ok(counter)
-}
-
 struct StatsWriter {
     output: File,
     block_latencies_total: usize,
@@ -195,7 +309,7 @@ struct StatsWriter {
 }
 
 impl StatsWriter {
-    fn new(output: File) -> Self {
+    fn new(output: File, write_header: bool) -> Self {
         let mut rv = Self {
             output,
             block_latencies_total: 0,
@@ -216,7 +330,9 @@ impl StatsWriter {
                 MerkleStorageAction::BlockApplied,
             ],
         };
-        rv.write_header();
+        if write_header {
+            rv.write_header();
+        }
         rv
     }
 
@@ -270,7 +386,7 @@ impl StatsWriter {
         self.output.flush().unwrap();
     }
 
-    fn update(&mut self, block_nr: usize, merkle: Arc<Mutex<MerkleStorage>>) {
+    fn update(&mut self, block_nr: usize, merkle: &Mutex<MerkleStorage>) -> Result<u64, Error> {
         let m = merkle.lock().unwrap();
 
         let report = m.get_merkle_stats().unwrap();
@@ -342,7 +458,224 @@ impl StatsWriter {
             )
         );
 
-        writeln!(&mut self.output, "{}", stats).unwrap();
+        writeln!(&mut self.output, "{}", stats)?;
+        self.output.flush()?;
+        Ok(self.output.seek(SeekFrom::Current(0))?)
+    }
+}
+
+/// Timing of one replayed block
+#[derive(Serialize, Debug)]
+struct BlockReport {
+    level: u32,
+    block_hash: String,
+    actions: usize,
+    /// Time spent by all actions except commit
+    actions_time_us: u64,
+    commit_time_us: u64,
+    /// Time spent by block application and cycle start, which drive garbage collection
+    gc_time_us: u64,
+    total_time_us: u64,
+    memory_usage_bytes: usize,
+}
+
+impl BlockReport {
+    const CSV_HEADER: &'static str = "level,block_hash,actions,actions_time_us,commit_time_us,gc_time_us,total_time_us,memory_usage_bytes";
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.level,
+            self.block_hash,
+            self.actions,
+            self.actions_time_us,
+            self.commit_time_us,
+            self.gc_time_us,
+            self.total_time_us,
+            self.memory_usage_bytes
+        )
+    }
+}
+
+struct ReportWriter {
+    output: File,
+    format: ReportFormat,
+}
+
+impl ReportWriter {
+    fn new(output: File, format: ReportFormat, write_header: bool) -> Result<Self, Error> {
+        let mut rv = Self { output, format };
+        if write_header && format == ReportFormat::Csv {
+            writeln!(&mut rv.output, "{}", BlockReport::CSV_HEADER)?;
+        }
+        Ok(rv)
+    }
+
+    /// Appends report of the block, returns length of the report file
+    fn write(&mut self, report: &BlockReport) -> Result<u64, Error> {
+        match self.format {
+            ReportFormat::Json => writeln!(&mut self.output, "{}", serde_json::to_string(report)?)?,
+            ReportFormat::Csv => writeln!(&mut self.output, "{}", report.to_csv())?,
+        }
+        self.output.flush()?;
+        Ok(self.output.seek(SeekFrom::Current(0))?)
+    }
+}
+
+/// Accumulated results of the replay of one backend
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct ReplayTotals {
+    blocks: usize,
+    last_level: Option<u32>,
+    last_context_hash: Option<String>,
+    actions_time_us: u64,
+    commit_time_us: u64,
+    gc_time_us: u64,
+    total_time_us: u64,
+}
+
+impl ReplayTotals {
+    fn add(&mut self, report: &BlockReport) {
+        self.blocks += 1;
+        self.last_level = Some(report.level);
+        self.actions_time_us += report.actions_time_us;
+        self.commit_time_us += report.commit_time_us;
+        self.gc_time_us += report.gc_time_us;
+        self.total_time_us += report.total_time_us;
+    }
+}
+
+/// State saved after every replayed block, the replay can be resumed from it
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Checkpoint {
+    /// Position of the next block to replay in the actions file (levels are not unique)
+    #[serde(alias = "next_level")]
+    next_block: usize,
+    cycle_counter: usize,
+    /// Length of the stats file after the last replayed block
+    stats_len: u64,
+    /// Length of the report file after the last replayed block
+    report_len: u64,
+    totals: ReplayTotals,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Stores checkpoint atomically - written to temp file and renamed
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// First difference between recorded and replayed context
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Context diverged at level {} (block {}), action #{} {} - {} differs\n  expected: {}\n  actual:   {}",
+    level, block_hash, action_index, action, subject, expected, actual
+)]
+struct ContextDivergence {
+    level: u32,
+    block_hash: String,
+    action_index: usize,
+    action: String,
+    subject: &'static str,
+    expected: String,
+    actual: String,
+}
+
+/// Difference found by verification of single action
+struct Mismatch {
+    subject: &'static str,
+    expected: String,
+    actual: String,
+}
+
+impl Mismatch {
+    fn check<T: PartialEq + fmt::Display>(
+        subject: &'static str,
+        expected: T,
+        actual: T,
+    ) -> Option<Mismatch> {
+        if expected == actual {
+            None
+        } else {
+            Some(Mismatch {
+                subject,
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            })
+        }
+    }
+}
+
+/// Result of the replay of one backend, used for comparison of backends
+#[derive(Serialize, Debug)]
+struct ReplaySummary {
+    backend: String,
+    /// `ok`, `diverged` or `failed`
+    status: &'static str,
+    #[serde(flatten)]
+    totals: ReplayTotals,
+    avg_block_time_us: u64,
+    error: Option<String>,
+}
+
+impl ReplaySummary {
+    const CSV_HEADER: &'static str = "backend,status,blocks,last_level,last_context_hash,actions_time_us,commit_time_us,gc_time_us,total_time_us,avg_block_time_us,error";
+
+    fn new(backend: String, totals: ReplayTotals, result: Result<(), Error>) -> Self {
+        let (status, error) = match result {
+            Ok(()) => ("ok", None),
+            Err(e) => {
+                let status = if e.downcast_ref::<ContextDivergence>().is_some() {
+                    "diverged"
+                } else {
+                    "failed"
+                };
+                (status, Some(format!("{}", e)))
+            }
+        };
+        let avg_block_time_us = if totals.blocks > 0 {
+            totals.total_time_us / totals.blocks as u64
+        } else {
+            0
+        };
+        Self {
+            backend,
+            status,
+            totals,
+            avg_block_time_us,
+            error,
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},\"{}\"",
+            self.backend,
+            self.status,
+            self.totals.blocks,
+            self.totals
+                .last_level
+                .map(|level| level.to_string())
+                .unwrap_or_default(),
+            self.totals.last_context_hash.clone().unwrap_or_default(),
+            self.totals.actions_time_us,
+            self.totals.commit_time_us,
+            self.totals.gc_time_us,
+            self.totals.total_time_us,
+            self.avg_block_time_us,
+            self.error.clone().unwrap_or_default().replace('"', "\"\"")
+        )
     }
 }
 
@@ -357,53 +690,276 @@ fn resolve_context_kv_store(
         ContextKvStoreConfiguration::Sled { path } => ("sled".to_string(), Some(path.clone())),
         ContextKvStoreConfiguration::InMem => ("inmem".to_string(), None),
         ContextKvStoreConfiguration::BTreeMap => ("btree".to_string(), None),
+        ContextKvStoreConfiguration::PackFile { path } => ("pack".to_string(), Some(path.clone())),
     }
 }
 
-fn main() -> Result<(), Error> {
-    let params = Args::read_args();
-    let log = create_logger();
+fn format_context_hash(hash: &[u8]) -> String {
+    ContextHash::try_from(hash.to_vec())
+        .map(|hash| hash.to_base58_check())
+        .unwrap_or_else(|_| hex::encode(hash))
+}
 
-    // prepare files
-    let (context_kv_storage_name, context_kv_storage_path) =
-        resolve_context_kv_store(&params.context_kv_store);
+fn format_last_commit_hash(context: &dyn ContextApi) -> Result<String, Error> {
+    Ok(context
+        .get_last_commit_hash()?
+        .map(|hash| format_context_hash(&hash))
+        .unwrap_or_else(|| "none".to_string()))
+}
 
-    // check actions file
-    let actions_file_path = params.input;
-    if !actions_file_path.exists() {
-        return Err(failure::format_err!(
-            "Input action file does not exists: {:?}",
-            actions_file_path.to_str().unwrap(),
-        ));
+fn format_block_hash(block: &ActionsBlock) -> String {
+    block
+        .block_hash
+        .as_ref()
+        .map(BlockHash::to_base58_check)
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Opens output file, when resuming it is truncated to the checkpointed length,
+/// returns `true` if the file was created from scratch
+fn open_output(path: &Path, resume_len: Option<u64>) -> Result<(File, bool), Error> {
+    match resume_len {
+        Some(len) if path.exists() => {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(len)?;
+            file.seek(SeekFrom::End(0))?;
+            Ok((file, false))
+        }
+        _ => Ok((File::create(path)?, true)),
+    }
+}
+
+/// Opens actions file and moves it to the `from_block`-th block, returns `None` if there is no such block
+fn open_reader(
+    input: &Path,
+    from_block: usize,
+) -> Result<Option<ActionsFileReader>, ActionFileError> {
+    let mut reader = ActionsFileReader::new(input)?;
+    if !reader.seek_to_position(from_block)? {
+        return Ok(None);
     }
+    Ok(Some(reader))
+}
 
-    // prepare storage path (if needed)
-    if let Some(context_kv_storage_path) = context_kv_storage_path {
-        if context_kv_storage_path.exists() {
-            let _ = fs::remove_dir_all(&context_kv_storage_path)?;
+/// Reads and decodes blocks (with their position in the file) in separate thread, so decoding runs in parallel with the replay
+fn spawn_block_reader(
+    input: PathBuf,
+    from_block: usize,
+    blocks_limit: Option<usize>,
+) -> Result<Receiver<Result<(usize, ActionsBlock), ActionFileError>>, Error> {
+    let (tx, rx) = sync_channel(DECODED_BLOCKS_QUEUE_SIZE);
+    thread::Builder::new()
+        .name("actions-reader".to_string())
+        .spawn(move || {
+            let mut reader = match open_reader(&input, from_block) {
+                Ok(Some(reader)) => reader,
+                Ok(None) => return,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            let mut position = from_block;
+            loop {
+                if matches!(blocks_limit, Some(blocks_limit) if position >= blocks_limit) {
+                    break;
+                }
+                match reader.next_block() {
+                    Ok(Some(block)) => {
+                        if tx.send(Ok((position, block))).is_err() {
+                            // replay was stopped
+                            break;
+                        }
+                        position += 1;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        break;
+                    }
+                }
+            }
+        })?;
+    Ok(rx)
+}
+
+/// Applies action to the context, commits are applied directly, so the resulting hash can be
+/// compared by [verify_action] instead of failing inside of the context
+fn apply_action(context: &mut dyn ContextApi, action: &ContextAction) -> Result<(), Error> {
+    match action {
+        ContextAction::Commit {
+            parent_context_hash,
+            block_hash: Some(block_hash),
+            author,
+            message,
+            date,
+            ..
+        } => {
+            let parent_context_hash = match parent_context_hash {
+                Some(hash) => Some(ContextHash::try_from(hash.clone())?),
+                None => None,
+            };
+            context.commit(
+                &BlockHash::try_from(block_hash.clone())?,
+                &parent_context_hash,
+                author.clone(),
+                message.clone(),
+                *date,
+            )?;
+            Ok(())
         }
-        let _ = fs::create_dir_all(&context_kv_storage_path)?;
+        _ => context.perform_context_action(action.clone()),
     }
+}
 
-    // prepare stats output file
-    let stats_output_file = {
-        if !params.output.exists() {
-            let _ = fs::create_dir_all(&params.output)?;
+/// Verifies state of the context after the action has been applied
+fn verify_action(
+    context: &mut dyn ContextApi,
+    action: &ContextAction,
+) -> Result<Option<Mismatch>, Error> {
+    let mismatch = match action {
+        ContextAction::Commit {
+            new_context_hash, ..
+        } => Mismatch::check(
+            "context hash",
+            format_context_hash(new_context_hash),
+            format_last_commit_hash(context)?,
+        ),
+        ContextAction::Checkout { context_hash, .. } => Mismatch::check(
+            "checked out context hash",
+            format_context_hash(context_hash),
+            format_last_commit_hash(context)?,
+        ),
+        ContextAction::Get { key, value, .. } => Mismatch::check(
+            "value",
+            hex::encode(value),
+            hex::encode(context.get_key(key)?),
+        ),
+        ContextAction::Mem { key, value, .. } => Mismatch::check("mem", *value, context.mem(key)?),
+        ContextAction::DirMem { key, value, .. } => {
+            Mismatch::check("dirmem", *value, context.dirmem(key)?)
         }
-        let stats_output_file = params
-            .output
-            .join(&format!("{}.stats.txt", context_kv_storage_name));
-        if stats_output_file.exists() {
-            let _ = fs::remove_file(&stats_output_file)?;
+        _ => None,
+    };
+    if mismatch.is_some() {
+        return Ok(mismatch);
+    }
+
+    // verify tree hash after each action, if it was recorded
+    match get_new_tree_hash(action)? {
+        Some(expected_hash) => Ok(Mismatch::check(
+            "tree hash",
+            hex::encode(expected_hash),
+            hex::encode(context.get_merkle_root()?),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn action_description(action: &ContextAction) -> String {
+    match action {
+        ContextAction::Get { key, .. }
+        | ContextAction::Mem { key, .. }
+        | ContextAction::DirMem { key, .. }
+        | ContextAction::Set { key, .. }
+        | ContextAction::Delete { key, .. }
+        | ContextAction::RemoveRecursively { key, .. }
+        | ContextAction::Fold { key, .. } => {
+            format!("{} {}", get_tree_action(action), key.join("/"))
         }
-        stats_output_file
+        ContextAction::Copy {
+            from_key, to_key, ..
+        } => format!(
+            "{} {} -> {}",
+            get_tree_action(action),
+            from_key.join("/"),
+            to_key.join("/")
+        ),
+        _ => get_tree_action(action),
+    }
+}
+
+/// Replays actions file into the backend, `totals` are updated after every replayed block
+fn replay(
+    params: &Args,
+    context_kv_store: &ContextKvStoreConfiguration,
+    blocks_count: usize,
+    totals: &mut ReplayTotals,
+    log: &Logger,
+) -> Result<(), Error> {
+    let (context_kv_storage_name, context_kv_storage_path) =
+        resolve_context_kv_store(context_kv_store);
+
+    let checkpoint_file = params
+        .output
+        .join(format!("{}.checkpoint.json", context_kv_storage_name));
+    let stats_output_file = params
+        .output
+        .join(format!("{}.stats.txt", context_kv_storage_name));
+    let report_output_file = params.output.join(format!(
+        "{}.report.{}",
+        context_kv_storage_name,
+        params.report_format.extension()
+    ));
+
+    let checkpoint = if params.resume {
+        if context_kv_storage_path.is_none() {
+            return Err(failure::format_err!(
+                "Context store '{}' is not persistent, replay cannot be resumed",
+                context_kv_storage_name
+            ));
+        }
+        Checkpoint::load(&checkpoint_file)?
+    } else {
+        None
     };
 
+    match &checkpoint {
+        Some(checkpoint) => {
+            info!(log, "Resuming replay from checkpoint"; "next_block" => checkpoint.next_block)
+        }
+        None => {
+            // prepare storage path (if needed)
+            if let Some(context_kv_storage_path) = &context_kv_storage_path {
+                if context_kv_storage_path.exists() {
+                    fs::remove_dir_all(context_kv_storage_path)?;
+                }
+                fs::create_dir_all(context_kv_storage_path)?;
+            }
+            if checkpoint_file.exists() {
+                fs::remove_file(&checkpoint_file)?;
+            }
+        }
+    }
+    let resuming = checkpoint.is_some();
+    let mut checkpoint = checkpoint.unwrap_or_default();
+    *totals = checkpoint.totals.clone();
+
+    let (stats_output, new_stats) = open_output(
+        &stats_output_file,
+        if resuming {
+            Some(checkpoint.stats_len)
+        } else {
+            None
+        },
+    )?;
+    let mut stat_writer = StatsWriter::new(stats_output, new_stats);
+    let (report_output, new_report) = open_output(
+        &report_output_file,
+        if resuming {
+            Some(checkpoint.report_len)
+        } else {
+            None
+        },
+    )?;
+    let mut report_writer = ReportWriter::new(report_output, params.report_format, new_report)?;
+
     info!(log, "Context actions replayer starts...";
-               "input_file" => actions_file_path.to_str().unwrap(),
+               "input_file" => params.input.to_str().unwrap(),
                "output_stats_file" => stats_output_file.to_str().unwrap(),
+               "output_report_file" => report_output_file.to_str().unwrap(),
                "target_context_kv_store_path" => params.output.to_str().unwrap(),
-               "target_context_kv_store" => context_kv_storage_name);
+               "target_context_kv_store" => &context_kv_storage_name);
 
     let mocked_test_main_chain = MainChain::new(
         ChainId::from_base58_check("NetXgtSLGNJvNye").expect("Failed to create chainId"),
@@ -413,93 +969,242 @@ fn main() -> Result<(), Error> {
     let mut global_cache_holder = GlobalRocksDbCacheHolder::with_capacity(1);
     // create merkle storage
     let merkle = Arc::new(Mutex::new(initialize_merkle(
-        &params.context_kv_store,
+        context_kv_store,
+        &params.history_mode,
+        params.context_gc,
+        params.context_compression,
         &mocked_test_main_chain,
-        &log,
+        log,
         &mut global_cache_holder,
     )?));
     let mut context: Box<dyn ContextApi> = Box::new(TezedgeContext::new(None, merkle.clone()));
-    let mut stat_writer = StatsWriter::new(File::create(stats_output_file)?);
-
-    let mut counter = 0;
-    let mut cycle_counter = 0;
-    let blocks_count = get_blocks_count(&log, actions_file_path.clone())?;
 
-    info!(log, "{} blocks found", blocks_count);
+    // garbage collector restores roots of the preserved cycles from the store, the store is flushed before
+    // the checkpoint is saved, so it can be ahead of the checkpoint (by the last block), but never behind it
+    let gc_cycle_epoch = merkle.lock().unwrap().gc_cycle_epoch();
+    if let Some(gc_cycle_epoch) = gc_cycle_epoch {
+        if gc_cycle_epoch < checkpoint.cycle_counter as u64
+            || gc_cycle_epoch > checkpoint.cycle_counter as u64 + 1
+        {
+            return Err(failure::format_err!(
+                "Garbage collector state of context store '{}' (cycle {}) does not match the checkpoint (cycle {}), replay cannot be resumed",
+                context_kv_storage_name,
+                gc_cycle_epoch,
+                checkpoint.cycle_counter
+            ));
+        }
+    }
 
-    let actions_reader = ActionsFileReader::new(&actions_file_path)?;
+    let blocks = spawn_block_reader(
+        params.input.clone(),
+        checkpoint.next_block,
+        params.blocks_limit,
+    )?;
 
-    for messages in actions_reader.take(params.blocks_limit.unwrap_or(blocks_count as usize)) {
-        counter += 1;
+    for block in blocks {
+        let (position, block) = block?;
+        let block_hash = format_block_hash(&block);
+        let counter = position + 1;
         let progress = counter as f64 / blocks_count as f64 * 100.0;
 
-        for action in messages.iter() {
+        let mut actions_time = Duration::default();
+        let mut commit_time = Duration::default();
+        let mut gc_time = Duration::default();
+
+        for (action_index, action) in block.actions.iter().enumerate() {
             // evaluate context action to context
-            context.perform_context_action(action.clone())?;
+            let started = Instant::now();
+            apply_action(context.as_mut(), action).map_err(|e| {
+                failure::format_err!(
+                    "Failed to replay action #{} {} at level {} (block {}): {}",
+                    action_index,
+                    action_description(action),
+                    block.level,
+                    block_hash,
+                    e
+                )
+            })?;
+            match action {
+                ContextAction::Commit { .. } => commit_time += started.elapsed(),
+                _ => actions_time += started.elapsed(),
+            }
 
             // verify state of the storage after action has been applied
-            match action {
-                ContextAction::Commit {
-                    new_context_hash, ..
-                } => {
-                    assert_eq!(
-                        new_context_hash.clone(),
-                        context.get_last_commit_hash()?.unwrap()
-                    );
-                }
-                ContextAction::Checkout { context_hash, .. } => {
-                    assert!(!context_hash.is_empty());
-                    assert_eq!(
-                        context_hash.clone(),
-                        context.get_last_commit_hash()?.unwrap()
-                    );
-                }
-                ContextAction::Get { key, value, .. } => {
-                    assert_eq!(value.clone(), context.get_key(key).unwrap());
-                }
-                ContextAction::Mem { key, value, .. } => {
-                    assert_eq!(*value, context.mem(key).unwrap());
+            if let Some(mismatch) = verify_action(context.as_mut(), action)? {
+                return Err(ContextDivergence {
+                    level: block.level,
+                    block_hash,
+                    action_index,
+                    action: action_description(action),
+                    subject: mismatch.subject,
+                    expected: mismatch.expected,
+                    actual: mismatch.actual,
                 }
-                ContextAction::DirMem { key, value, .. } => {
-                    assert_eq!(*value, context.dirmem(key).unwrap());
-                }
-                _ => {}
-            };
-
-            // verify context hashes after each block
-            if let Some(expected_hash) = get_new_tree_hash(&action)? {
-                assert_eq!(context.get_merkle_root()?, expected_hash);
+                .into());
             }
 
-            if let ContextAction::Commit { block_hash, .. } = &action {
+            if let ContextAction::Commit {
+                new_context_hash, ..
+            } = &action
+            {
                 debug!(
                     log,
                     "progress {:.7}% - cycle nr: {} block nr {} [{}] with {} messages processed - {} mb",
                     progress,
-                    cycle_counter,
+                    checkpoint.cycle_counter,
                     counter,
-                    hex::encode(&block_hash.clone().unwrap().clone()),
-                    messages.len(),
-                    merkle.clone()
-                        .lock()
-                        .unwrap()
-                        .get_memory_usage()
-                        .unwrap()
-                        / 1024
-                        / 1024
+                    block_hash,
+                    block.actions.len(),
+                    merkle.lock().unwrap().get_memory_usage()? / 1024 / 1024
                 );
+                checkpoint.totals.last_context_hash = Some(format_context_hash(new_context_hash));
 
-                context.block_applied().unwrap();
-                if counter > 0 && counter % params.blocks_per_cycle == 0 {
-                    context.cycle_started().unwrap();
-                    cycle_counter += 1;
+                let started = Instant::now();
+                context.block_applied()?;
+                if counter % params.blocks_per_cycle == 0 {
+                    // cycle could be already started in the store before the checkpoint was saved
+                    if gc_cycle_epoch.map_or(true, |epoch| checkpoint.cycle_counter as u64 >= epoch)
+                    {
+                        context.cycle_started()?;
+                    }
+                    checkpoint.cycle_counter += 1;
                 }
+                gc_time += started.elapsed();
             }
         }
-        stat_writer.update(counter, merkle.clone());
+
+        let report = BlockReport {
+            level: block.level,
+            block_hash,
+            actions: block.actions.len(),
+            actions_time_us: actions_time.as_micros() as u64,
+            commit_time_us: commit_time.as_micros() as u64,
+            gc_time_us: gc_time.as_micros() as u64,
+            total_time_us: (actions_time + commit_time + gc_time).as_micros() as u64,
+            memory_usage_bytes: merkle.lock().unwrap().get_memory_usage()?,
+        };
+        checkpoint.totals.add(&report);
+        *totals = checkpoint.totals.clone();
+        checkpoint.report_len = report_writer.write(&report)?;
+        checkpoint.stats_len = stat_writer.update(counter, &merkle)?;
+        checkpoint.next_block = position + 1;
+        if context_kv_storage_path.is_some() {
+            // checkpoint must not be ahead of the persisted context (and roots of the garbage collector)
+            merkle.lock().unwrap().flush()?;
+            checkpoint.save(&checkpoint_file)?;
+        }
     }
 
-    info!(log, "Context was successfully evaluated");
+    info!(log, "Context was successfully evaluated"; "blocks" => checkpoint.totals.blocks);
+
+    Ok(())
+}
+
+fn write_comparison(params: &Args, summaries: &[ReplaySummary]) -> Result<PathBuf, Error> {
+    let path = params
+        .output
+        .join(format!("comparison.{}", params.report_format.extension()));
+    let mut output = File::create(&path)?;
+    match params.report_format {
+        ReportFormat::Json => serde_json::to_writer_pretty(&mut output, summaries)?,
+        ReportFormat::Csv => {
+            writeln!(&mut output, "{}", ReplaySummary::CSV_HEADER)?;
+            for summary in summaries {
+                writeln!(&mut output, "{}", summary.to_csv())?;
+            }
+        }
+    }
+    output.flush()?;
+    Ok(path)
+}
 
+fn main() -> Result<(), Error> {
+    let params = Args::read_args();
+    let log = create_logger();
+
+    // check actions file
+    if !params.input.exists() {
+        return Err(failure::format_err!(
+            "Input action file does not exists: {:?}",
+            params.input.to_str().unwrap(),
+        ));
+    }
+    if !params.output.exists() {
+        fs::create_dir_all(&params.output)?;
+    }
+
+    let blocks_count = ActionsFileReader::new(&params.input)?.blocks_count()?;
+    info!(log, "{} blocks found", blocks_count);
+
+    let run = move |params: &Args, context_kv_store: &ContextKvStoreConfiguration, log: &Logger| {
+        let (name, _) = resolve_context_kv_store(context_kv_store);
+        let log = log.new(slog::o!("backend" => name.clone()));
+        let mut totals = ReplayTotals::default();
+        let result = replay(params, context_kv_store, blocks_count, &mut totals, &log);
+        if let Err(e) = &result {
+            error!(log, "Context actions replay failed"; "reason" => format!("{}", e));
+        }
+        ReplaySummary::new(name, totals, result)
+    };
+
+    let summaries: Vec<ReplaySummary> = if params.sequential || params.context_kv_stores.len() < 2 {
+        params
+            .context_kv_stores
+            .iter()
+            .map(|context_kv_store| run(&params, context_kv_store, &log))
+            .collect()
+    } else {
+        let params = Arc::new(params.clone());
+        let handles = params
+            .context_kv_stores
+            .iter()
+            .cloned()
+            .map(|context_kv_store| {
+                let (name, _) = resolve_context_kv_store(&context_kv_store);
+                let params = params.clone();
+                let log = log.clone();
+                let handle = thread::Builder::new()
+                    .name(format!("replayer-{}", name))
+                    .spawn(move || run(&params, &context_kv_store, &log))?;
+                Ok((name, handle))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        handles
+            .into_iter()
+            .map(|(name, handle)| {
+                handle.join().unwrap_or_else(|_| {
+                    ReplaySummary::new(
+                        name,
+                        ReplayTotals::default(),
+                        Err(failure::format_err!("Replay thread panicked")),
+                    )
+                })
+            })
+            .collect()
+    };
+
+    let comparison_file = write_comparison(&params, &summaries)?;
+    for summary in &summaries {
+        info!(log, "Context actions replay finished";
+                   "backend" => &summary.backend,
+                   "status" => summary.status,
+                   "blocks" => summary.totals.blocks,
+                   "total_time_ms" => summary.totals.total_time_us / 1000,
+                   "gc_time_ms" => summary.totals.gc_time_us / 1000,
+                   "avg_block_time_us" => summary.avg_block_time_us);
+    }
+    info!(log, "Backends comparison written"; "file" => comparison_file.to_str().unwrap());
+
+    let failed = summaries
+        .iter()
+        .filter(|summary| summary.status != "ok")
+        .count();
+    if failed > 0 {
+        return Err(failure::format_err!(
+            "Replay failed for {} of {} backend(s)",
+            failed,
+            summaries.len()
+        ));
+    }
     Ok(())
 }
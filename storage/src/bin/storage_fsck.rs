@@ -0,0 +1,185 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use clap::{App, Arg};
+use failure::{format_err, Error};
+use slog::{error, info, Drain, Level, Logger};
+
+use storage::context::kv_store::pack_file_backend::PackFileBackend;
+use storage::context::kv_store::rocksdb_backend::RocksDBBackend;
+use storage::context::kv_store::sled_backend::SledBackend;
+use storage::context::kv_store::SupportedContextKeyValueStore;
+use storage::context::merkle::merkle_storage::MerkleStorage;
+use storage::fsck::check_storage;
+use storage::initializer::{
+    ContextRocksDbTableInitializer, DbsRocksDbTableInitializer, RocksDbCache, RocksDbColumnFactory,
+};
+use storage::persistent::database::open_kv;
+use storage::persistent::sequence::Sequences;
+use storage::persistent::{open_cl, CommitLogConfiguration, CommitLogSchema, DbConfiguration};
+use storage::{BlockStorage, PersistentStorage, SystemStorage};
+
+const LRU_CACHE_SIZE_64MB: usize = 64 * 1024 * 1024;
+
+struct Args {
+    db_path: PathBuf,
+    context_kv_store: SupportedContextKeyValueStore,
+    repair: bool,
+}
+
+impl Args {
+    pub fn read_args() -> Self {
+        let app = App::new("storage-fsck")
+            .about("Checks integrity of the node storage (block headers, block metadata, predecessors index and contexts), node must not be running")
+            .arg(Arg::with_name("db-path")
+                .long("db-path")
+                .takes_value(true)
+                .required(true)
+                .help("Path to the node data directory (same as --tezos-data-dir/--bootstrap-db-path of the node)"))
+            .arg(Arg::with_name("context-kv-store")
+                .long("context-kv-store")
+                .takes_value(true)
+                .value_name("STRING")
+                .required(true)
+                .default_value("rocksdb")
+                .possible_values(&["rocksdb", "sled", "pack"])
+                .help("Merkle storage backend used by the node - supported backends: 'rocksdb', 'sled', 'pack'"))
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .help("Repairs block metadata links and predecessors index, other issues are just reported"));
+
+        let matches = app.get_matches();
+
+        Self {
+            db_path: matches
+                .value_of("db-path")
+                .unwrap()
+                .parse::<PathBuf>()
+                .expect("Provided value cannot be converted to path"),
+            context_kv_store: matches
+                .value_of("context-kv-store")
+                .unwrap()
+                .parse::<SupportedContextKeyValueStore>()
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "Expecting one value from {:?}, error: {:?}",
+                        SupportedContextKeyValueStore::possible_values(),
+                        e
+                    )
+                }),
+            repair: matches.is_present("repair"),
+        }
+    }
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .chan_size(32768)
+    .overflow_strategy(slog_async::OverflowStrategy::Block)
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
+
+/// Opens existing context store directly, without any compatibility checks, which could modify it
+fn open_merkle(
+    args: &Args,
+    caches: &mut Vec<RocksDbCache>,
+    cfg: &DbConfiguration,
+) -> Result<MerkleStorage, Error> {
+    Ok(match args.context_kv_store {
+        SupportedContextKeyValueStore::RocksDB { .. } => {
+            let cache = RocksDbCache::new_lru_cache(LRU_CACHE_SIZE_64MB)?;
+            let kv = open_kv(
+                args.db_path.join("context"),
+                ContextRocksDbTableInitializer.create(&cache),
+                cfg,
+            )?;
+            caches.push(cache);
+            MerkleStorage::new(Box::new(RocksDBBackend::new(Arc::new(kv))))
+        }
+        SupportedContextKeyValueStore::Sled { .. } => {
+            let sled = sled::Config::new()
+                .path(args.db_path.join("context_sled"))
+                .open()?;
+            MerkleStorage::new(Box::new(SledBackend::new(sled)))
+        }
+        SupportedContextKeyValueStore::PackFile { .. } => MerkleStorage::new(Box::new(
+            PackFileBackend::open(args.db_path.join("context_pack"))?,
+        )),
+        _ => return Err(format_err!("In-memory context stores are not persisted")),
+    })
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::read_args();
+    let log = create_logger();
+
+    if !args.db_path.exists() {
+        return Err(format_err!(
+            "Data directory {:?} does not exist",
+            args.db_path
+        ));
+    }
+
+    info!(log, "Opening databases"; "db_path" => format!("{:?}", args.db_path));
+
+    // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv)
+    let mut caches = Vec::with_capacity(2);
+    let cfg = DbConfiguration::default();
+
+    let kv_cache = RocksDbCache::new_lru_cache(LRU_CACHE_SIZE_64MB)?;
+    let kv = Arc::new(open_kv(
+        args.db_path.join("db"),
+        DbsRocksDbTableInitializer.create(&kv_cache),
+        &cfg,
+    )?);
+    caches.push(kv_cache);
+
+    let chain_id = SystemStorage::new(kv.clone())
+        .get_chain_id()?
+        .ok_or_else(|| format_err!("Database does not contain chain id, nothing to check"))?;
+
+    let merkle = open_merkle(&args, &mut caches, &cfg)?;
+    let commit_logs = Arc::new(open_cl(
+        &args.db_path,
+        vec![BlockStorage::descriptor()],
+        &CommitLogConfiguration::default(),
+    )?);
+    let sequences = Arc::new(Sequences::new(kv.clone(), 1000));
+    let persistent_storage = PersistentStorage::new(
+        kv,
+        commit_logs,
+        sequences,
+        Arc::new(Mutex::new(merkle)),
+        None,
+    );
+
+    let report = check_storage(&persistent_storage, &chain_id, args.repair, &log)?;
+
+    if report.has_unrepaired_issues() {
+        let repairable = report
+            .issues
+            .iter()
+            .filter(|issue| issue.is_repairable())
+            .count();
+        error!(log, "Storage is not consistent";
+                    "unrepaired_issues" => report.issues.len() - report.repaired,
+                    "repairable_issues" => if args.repair { 0 } else { repairable });
+        // flush dbs before exit
+        drop(persistent_storage);
+        std::process::exit(1);
+    }
+
+    info!(log, "Storage is consistent");
+    Ok(())
+}
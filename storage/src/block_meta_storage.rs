@@ -18,6 +18,8 @@ use tezos_messages::p2p::encoding::block_header::Level;
 use crate::persistent::database::{
     default_table_options, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema,
 };
+use crate::persistent::merge_secondary::MergeOperatorDescriptor;
+use crate::persistent::write_batch::StorageWriteBatch;
 use crate::persistent::{
     BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError,
 };
@@ -68,7 +70,7 @@ impl BlockMetaStorage {
 
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
         BlockMetaStorage {
-            kv: persistent_storage.db(),
+            kv: persistent_storage.merge_db(),
             predecessors_index: PredecessorStorage::new(persistent_storage),
             additional_data_index: persistent_storage.db(),
         }
@@ -187,6 +189,23 @@ impl BlockMetaStorage {
         Ok(())
     }
 
+    /// Adds predecessors of the block to the `batch`, see [BlockMetaStorage::store_predecessors]
+    pub fn store_predecessors_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        block_hash: &BlockHash,
+        block_meta: &Meta,
+    ) -> Result<(), StorageError> {
+        for (key, predecessor) in self.predecessors_index.predecessors_to_store(
+            block_hash,
+            block_meta,
+            Self::STORED_PREDECESSORS_SIZE,
+        )? {
+            batch.put::<PredecessorStorage>(&key, &predecessor)?;
+        }
+        Ok(())
+    }
+
     pub fn put_block_additional_data(
         &self,
         block_hash: &BlockHash,
@@ -197,11 +216,46 @@ impl BlockMetaStorage {
             .map_err(StorageError::from)
     }
 
+    pub fn put_block_additional_data_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        block_hash: &BlockHash,
+        additional_data: &BlockAdditionalData,
+    ) -> Result<(), StorageError> {
+        batch
+            .put::<BlockAdditionalData>(block_hash, additional_data)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn put(&self, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
         self.kv.merge(block_hash, meta).map_err(StorageError::from)
     }
 
+    /// Marks block as not applied, so it is applied again (metadata merge cannot unset flags, so metadata are overwritten).
+    ///
+    /// Metadata are read and written back, so it can be used just on startup, when nothing else writes them.
+    pub fn mark_as_not_applied(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        if let Some(mut meta) = self.kv.get(block_hash)? {
+            meta.set_is_applied(false);
+            self.kv.put(block_hash, &meta)?;
+        }
+        Ok(())
+    }
+
+    /// Adds merge of the metadata to the `batch`
+    #[inline]
+    pub fn put_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        block_hash: &BlockHash,
+        meta: &Meta,
+    ) -> Result<(), StorageError> {
+        batch
+            .merge::<Self>(block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
         self.kv.get(block_hash).map_err(StorageError::from)
@@ -211,6 +265,52 @@ impl BlockMetaStorage {
     pub fn iter(&self, mode: IteratorMode<Self>) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv.iterator(mode).map_err(StorageError::from)
     }
+
+    /// Removes stored predecessors, which point below `min_level` (to the pruned blocks), for blocks on the branch of `block_hash`.
+    ///
+    /// Blocks above `min_level` are never reached through pruned blocks, so predecessors of the retained blocks stay resolvable.
+    pub fn remove_predecessors_below(
+        &self,
+        block_hash: BlockHash,
+        block_level: Level,
+        min_level: Level,
+    ) -> Result<(), StorageError> {
+        // just blocks up to the longest stored distance can point below the min_level
+        let max_distance = 2_i32.pow(Self::STORED_PREDECESSORS_SIZE - 1);
+        let top_level = std::cmp::min(block_level, min_level.saturating_add(max_distance - 1));
+
+        let mut next = self.find_block_at_distance(block_hash, block_level - top_level)?;
+        while let Some(block_hash) = next.take() {
+            let meta = match self.get(&block_hash)? {
+                Some(meta) => meta,
+                None => break,
+            };
+            if meta.level() < min_level {
+                break;
+            }
+            for exponent_slot in 0..Self::STORED_PREDECESSORS_SIZE {
+                if meta.level() - 2_i32.pow(exponent_slot) < min_level {
+                    self.predecessors_index
+                        .delete(&PredecessorKey::new(block_hash.clone(), exponent_slot))?;
+                }
+            }
+            next = meta
+                .predecessor()
+                .clone()
+                .filter(|pred| *pred != block_hash);
+        }
+        Ok(())
+    }
+
+    /// Removes metadata record, stored predecessors and additional data for block
+    pub fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        for exponent_slot in 0..Self::STORED_PREDECESSORS_SIZE {
+            self.predecessors_index
+                .delete(&PredecessorKey::new(block_hash.clone(), exponent_slot))?;
+        }
+        self.additional_data_index.delete(block_hash)?;
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
 }
 
 impl BlockMetaStorageReader for BlockMetaStorage {
@@ -395,6 +495,7 @@ pub struct Meta {
     #[get = "pub"]
     predecessor: Option<BlockHash>,
     #[get = "pub"]
+    #[set = "pub"]
     successors: Vec<BlockHash>,
     #[get_copy = "pub"]
     #[set = "pub"]
@@ -551,10 +652,22 @@ impl KeyValueSchema for BlockMetaStorage {
     type Value = Meta;
 }
 
+impl BlockMetaStorage {
+    /// Merge operator of the column family, so it can be registered also by [crate::persistent::merge_secondary::SecondaryMergeDB]
+    pub fn merge_operator() -> MergeOperatorDescriptor {
+        MergeOperatorDescriptor {
+            cf_name: Self::name(),
+            name: "block_meta_storage_merge_operator",
+            merge_fn: merge_meta_value,
+        }
+    }
+}
+
 impl RocksDbKeyValueSchema for BlockMetaStorage {
     fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
+        let merge_operator = Self::merge_operator();
         let mut cf_opts = default_table_options(cache);
-        cf_opts.set_merge_operator("block_meta_storage_merge_operator", merge_meta_value, None);
+        cf_opts.set_merge_operator(merge_operator.name, merge_operator.merge_fn, None);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
@@ -569,56 +682,66 @@ fn merge_meta_value(
     existing_val: Option<&[u8]>,
     operands: &mut MergeOperands,
 ) -> Option<Vec<u8>> {
-    if let Some(val) = existing_val {
-        if val.len() < LEN_FIXED_META {
-            return None;
-        }
+    merge_meta_operands(existing_val, operands)
+}
+
+/// Operands are folded just by picking the source of every part of the merged value,
+/// so the merged value is allocated and copied just once for all operands.
+fn merge_meta_operands<'a, I: IntoIterator<Item = &'a [u8]>>(
+    existing_val: Option<&'a [u8]>,
+    operands: I,
+) -> Option<Vec<u8>> {
+    let mut operands = operands.into_iter();
+    let base = match existing_val {
+        Some(val) => val,
+        None => operands.next()?,
+    };
+    if base.len() < LEN_FIXED_META {
+        return None;
     }
 
-    let mut result = existing_val.map(|v| v.to_vec());
+    let mut mask = base[IDX_MASK];
+    let mut predecessor_src = base;
+    let mut successors_src = base;
 
     for op in operands {
-        match result {
-            Some(ref mut val) => {
-                if op.len() < LEN_FIXED_META {
-                    return None;
-                }
-
-                let mask_val = val[IDX_MASK];
-                let mask_op = op[IDX_MASK];
-
-                // merge `mask(1)`
-                val[IDX_MASK] = mask_val | mask_op;
-
-                // if op has predecessor and val has not, copy it from op to val
-                if has_predecessor!(mask_op) && !has_predecessor!(mask_val) {
-                    val.splice(
-                        IDX_PREDECESSOR..IDX_LEVEL,
-                        op[IDX_PREDECESSOR..IDX_LEVEL].iter().cloned(),
-                    );
-                }
+        if op.len() < LEN_FIXED_META {
+            return None;
+        }
+        let mask_op = op[IDX_MASK];
 
-                // replace op (successors count + successors) to val
-                let val_successors_count = successors_count!(val);
-                let op_successors_count = successors_count!(op);
-                if (has_successor!(mask_op) && !has_successor!(mask_val))
-                    || (val_successors_count != op_successors_count)
-                {
-                    val.truncate(LEN_FIXED_META);
-                    val.splice(
-                        IDX_SUCCESSOR_COUNT..,
-                        op[IDX_SUCCESSOR_COUNT..].iter().cloned(),
-                    );
-                }
+        // if op has predecessor and val has not, take it from op
+        if has_predecessor!(mask_op) && !has_predecessor!(mask) {
+            predecessor_src = op;
+        }
 
-                let total_len = total_len(op_successors_count);
-                debug_assert_eq!(total_len, val.len(), "Invalid length after merge operator was applied. Was expecting {} but found {}.", total_len, val.len());
-            }
-            None => result = Some(op.to_vec()),
+        // take (successors count + successors) from op
+        if (has_successor!(mask_op) && !has_successor!(mask))
+            || (successors_count!(successors_src) != successors_count!(op))
+        {
+            successors_src = op;
         }
+
+        // merge `mask(1)`
+        mask |= mask_op;
     }
 
-    result
+    let mut val = Vec::with_capacity(successors_src.len());
+    val.push(mask);
+    val.extend_from_slice(&predecessor_src[IDX_PREDECESSOR..IDX_LEVEL]);
+    val.extend_from_slice(&base[IDX_LEVEL..IDX_SUCCESSOR_COUNT]);
+    val.extend_from_slice(&successors_src[IDX_SUCCESSOR_COUNT..]);
+
+    let total_len = total_len(successors_count!(successors_src));
+    debug_assert_eq!(
+        total_len,
+        val.len(),
+        "Invalid length after merge operator was applied. Was expecting {} but found {}.",
+        total_len,
+        val.len()
+    );
+
+    Some(val)
 }
 
 /// Struct holds informations as a result from block apllication,
@@ -914,6 +1037,57 @@ mod tests {
         assert!(DB::destroy(&Options::default(), path).is_ok());
     }
 
+    #[test]
+    fn merge_meta_operands_test() -> Result<(), Error> {
+        let chain_id: ChainId = vec![44; 4].try_into()?;
+        let operands = vec![
+            Meta::new(false, None, 2, chain_id.clone()).encode()?,
+            Meta {
+                is_applied: true,
+                predecessor: None,
+                successors: vec![vec![21; 32].try_into()?],
+                level: 2,
+                chain_id: chain_id.clone(),
+            }
+            .encode()?,
+            Meta::new(false, Some(vec![98; 32].try_into()?), 2, chain_id.clone()).encode()?,
+            Meta {
+                is_applied: false,
+                predecessor: Some(vec![99; 32].try_into()?),
+                successors: vec![vec![22; 32].try_into()?, vec![23; 32].try_into()?],
+                level: 2,
+                chain_id: chain_id.clone(),
+            }
+            .encode()?,
+        ];
+
+        // all operands at once
+        let merged = merge_meta_operands(None, operands.iter().map(|op| op.as_slice()))
+            .expect("merge failed");
+
+        // operands one by one
+        let mut merged_one_by_one = operands[0].clone();
+        for op in operands.iter().skip(1) {
+            merged_one_by_one =
+                merge_meta_operands(Some(&merged_one_by_one), std::iter::once(op.as_slice()))
+                    .expect("merge failed");
+        }
+        assert_eq!(merged, merged_one_by_one);
+
+        let expected = Meta {
+            is_applied: true,
+            predecessor: Some(vec![98; 32].try_into()?),
+            successors: vec![vec![22; 32].try_into()?, vec![23; 32].try_into()?],
+            level: 2,
+            chain_id,
+        };
+        assert_eq!(expected, Meta::decode(&merged)?);
+
+        // invalid operand
+        assert!(merge_meta_operands(Some(&merged), vec![&merged[..LEN_FIXED_META - 1]]).is_none());
+        Ok(())
+    }
+
     /// Create and return a storage with [number_of_blocks] blocks and the last BlockHash in it
     fn init_mocked_storage(
         number_of_blocks: usize,
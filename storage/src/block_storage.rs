@@ -10,6 +10,7 @@ use crypto::hash::{BlockHash, ContextHash};
 
 use crate::persistent::database::IteratorWithSchema;
 use crate::persistent::database::RocksDbKeyValueSchema;
+use crate::persistent::write_batch::StorageWriteBatch;
 use crate::persistent::{
     BincodeEncoded, CommitLogSchema, CommitLogWithSchema, KeyValueSchema, KeyValueStoreWithSchema,
     Location,
@@ -151,6 +152,40 @@ impl BlockStorage {
         block_hash: &BlockHash,
         json_data: BlockJsonData,
     ) -> Result<(), StorageError> {
+        let (block_header, updated_column_location) =
+            self.append_block_json_data(block_hash, json_data)?;
+        // update indexes
+        self.primary_index
+            .put(&block_header.hash, &updated_column_location)
+            .and(
+                self.by_level_index
+                    .put(block_header.header.level(), &updated_column_location),
+            )
+    }
+
+    /// Appends json data to commit log and adds update of indexes to the `batch`.
+    ///
+    /// Commit log is synced, so indexes committed with the batch never point behind the end of the commit log.
+    pub fn put_block_json_data_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        block_hash: &BlockHash,
+        json_data: BlockJsonData,
+    ) -> Result<(), StorageError> {
+        let (block_header, updated_column_location) =
+            self.append_block_json_data(block_hash, json_data)?;
+        self.clog.sync()?;
+        batch.put::<BlockPrimaryIndex>(&block_header.hash, &updated_column_location)?;
+        batch.put::<BlockByLevelIndex>(&block_header.header.level(), &updated_column_location)?;
+        Ok(())
+    }
+
+    /// Appends json data to commit log, returns block header and its location updated with json data location
+    fn append_block_json_data(
+        &self,
+        block_hash: &BlockHash,
+        json_data: BlockJsonData,
+    ) -> Result<(BlockHeaderWithHash, BlockStorageColumnsLocation), StorageError> {
         let updated_column_location = {
             let block_json_data_location = self
                 .clog
@@ -163,13 +198,7 @@ impl BlockStorage {
             column_location
         };
         let block_header = self.get_block_header_by_location(&updated_column_location)?;
-        // update indexes
-        self.primary_index
-            .put(&block_header.hash, &updated_column_location)
-            .and(
-                self.by_level_index
-                    .put(block_header.header.level(), &updated_column_location),
-            )
+        Ok((block_header, updated_column_location))
     }
 
     pub fn assign_to_context(
@@ -183,6 +212,142 @@ impl BlockStorage {
         }
     }
 
+    /// Removes reference to json data (block/operations metadata) of block from indexes.
+    ///
+    /// Note: json data are not removed from commit log, they are just not reachable anymore
+    pub fn remove_block_json_data(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        let mut location = match self.primary_index.get(block_hash)? {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+        if location.block_json_data.is_none() {
+            return Ok(());
+        }
+        location.block_json_data = None;
+
+        let block_header = self.get_block_header_by_location(&location)?;
+        self.remove_from_context_hash_index(&block_header)?;
+        self.primary_index.put(block_hash, &location)?;
+        if self.is_indexed_by_level(&block_header, &location)? {
+            self.by_level_index
+                .put(block_header.header.level(), &location)?;
+        }
+        Ok(())
+    }
+
+    /// Removes block from all indexes, so it is not reachable anymore.
+    ///
+    /// Note: data are not removed from commit log
+    pub fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        let location = match self.primary_index.get(block_hash)? {
+            Some(location) => location,
+            None => return Ok(()),
+        };
+
+        let block_header = self.get_block_header_by_location(&location)?;
+        self.remove_from_context_hash_index(&block_header)?;
+        if self.is_indexed_by_level(&block_header, &location)? {
+            self.by_level_index.delete(block_header.header.level())?;
+        }
+        self.primary_index.delete(block_hash)
+    }
+
+    /// Removes commit log segments with records older than the oldest record of the main chain blocks from `caboose_level`.
+    ///
+    /// Just blocks indexed by level are taken into account, so records of the side-branch blocks below the caboose
+    /// might be removed. Genesis is moved to the end of the commit log, if its records would be removed.
+    ///
+    /// Returns count of removed segments.
+    pub fn remove_commit_log_segments_below(
+        &self,
+        caboose_level: BlockLevel,
+        genesis_hash: &BlockHash,
+    ) -> Result<usize, StorageError> {
+        let oldest_offset = match self.by_level_index.oldest_offset_from(caboose_level)? {
+            Some(oldest_offset) => oldest_offset,
+            None => return Ok(0),
+        };
+
+        if let Some(location) = self.primary_index.get(genesis_hash)? {
+            if location.oldest_offset() < oldest_offset {
+                self.relocate(genesis_hash, location)?;
+            }
+        }
+
+        self.clog
+            .remove_segments_before(oldest_offset)
+            .map_err(StorageError::from)
+    }
+
+    /// Appends block header and json data again to the commit log and points indexes to the new records
+    fn relocate(
+        &self,
+        block_hash: &BlockHash,
+        location: BlockStorageColumnsLocation,
+    ) -> Result<(), StorageError> {
+        let block_header = self.get_block_header_by_location(&location)?;
+        let block_json_data = self.get_block_json_data_by_location(&location)?;
+
+        let mut relocated = BlockStorageColumnsLocation {
+            block_header: self
+                .clog
+                .append(&BlockStorageColumn::BlockHeader(block_header.clone()))?,
+            block_json_data: None,
+        };
+        if let Some(block_json_data) = block_json_data {
+            relocated.block_json_data = Some(
+                self.clog
+                    .append(&BlockStorageColumn::BlockJsonData(block_json_data))?,
+            );
+        }
+        self.clog.sync()?;
+
+        let context_hash = block_header.header.context();
+        if let Some(context_location) = self.by_context_hash_index.get(context_hash)? {
+            if context_location.block_header.0 == location.block_header.0 {
+                self.by_context_hash_index.put(context_hash, &relocated)?;
+            }
+        }
+        if self.is_indexed_by_level(&block_header, &location)? {
+            self.by_level_index
+                .put(block_header.header.level(), &relocated)?;
+        }
+        self.primary_index.put(block_hash, &relocated)
+    }
+
+    #[inline]
+    fn remove_from_context_hash_index(
+        &self,
+        block_header: &BlockHeaderWithHash,
+    ) -> Result<(), StorageError> {
+        let context_hash = block_header.header.context();
+        match self.by_context_hash_index.get(context_hash)? {
+            Some(location) => {
+                // context hash could be shared with another block (e.g. after reorg), so we need to check it
+                if self.get_block_header_by_location(&location)?.hash == block_header.hash {
+                    self.by_context_hash_index.delete(context_hash)
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Level index holds just one block per level, so we check, if it is the requested one
+    #[inline]
+    fn is_indexed_by_level(
+        &self,
+        block_header: &BlockHeaderWithHash,
+        location: &BlockStorageColumnsLocation,
+    ) -> Result<bool, StorageError> {
+        Ok(self
+            .by_level_index
+            .get(block_header.header.level())?
+            .map(|level_location| level_location.block_header.0 == location.block_header.0)
+            .unwrap_or(false))
+    }
+
     #[inline]
     fn get_block_header_by_location(
         &self,
@@ -406,6 +571,17 @@ pub struct BlockStorageColumnsLocation {
     pub block_json_data: Option<Location>,
 }
 
+impl BlockStorageColumnsLocation {
+    /// Offset of the oldest record of the block in the commit log
+    pub fn oldest_offset(&self) -> u64 {
+        self.block_json_data
+            .as_ref()
+            .map_or(self.block_header.0, |json_data| {
+                json_data.0.min(self.block_header.0)
+            })
+    }
+}
+
 impl BincodeEncoded for BlockStorageColumnsLocation {}
 
 /// Index block data as `block_header_hash -> location`.
@@ -445,6 +621,11 @@ impl BlockPrimaryIndex {
         self.kv.contains(block_hash).map_err(StorageError::from)
     }
 
+    #[inline]
+    fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
+
     #[inline]
     fn iterator(&self) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv
@@ -487,6 +668,14 @@ impl BlockByLevelIndex {
         self.kv.put(&level, location).map_err(StorageError::from)
     }
 
+    fn get(&self, level: BlockLevel) -> Result<Option<BlockStorageColumnsLocation>, StorageError> {
+        self.kv.get(&level).map_err(StorageError::from)
+    }
+
+    fn delete(&self, level: BlockLevel) -> Result<(), StorageError> {
+        self.kv.delete(&level).map_err(StorageError::from)
+    }
+
     fn get_blocks(
         &self,
         from_level: BlockLevel,
@@ -512,6 +701,19 @@ impl BlockByLevelIndex {
             .collect()
     }
 
+    /// Returns offset of the oldest record of blocks from `from_level`
+    fn oldest_offset_from(&self, from_level: BlockLevel) -> Result<Option<u64>, StorageError> {
+        let mut oldest_offset = None;
+        for (_, location) in self
+            .kv
+            .iterator(IteratorMode::From(&from_level, Direction::Forward))?
+        {
+            let offset = location?.oldest_offset();
+            oldest_offset = Some(oldest_offset.map_or(offset, |oldest: u64| oldest.min(offset)));
+        }
+        Ok(oldest_offset)
+    }
+
     fn get_blocks_by_nth_level(
         &self,
         every_nth: BlockLevel,
@@ -573,6 +775,10 @@ impl BlockByContextHashIndex {
     fn contains(&self, context_hash: &ContextHash) -> Result<bool, StorageError> {
         self.kv.contains(context_hash).map_err(StorageError::from)
     }
+
+    fn delete(&self, context_hash: &ContextHash) -> Result<(), StorageError> {
+        self.kv.delete(context_hash).map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for BlockByContextHashIndex {
@@ -12,6 +12,7 @@ use crypto::hash::{ChainId, HashType};
 use tezos_messages::Head;
 
 use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
+use crate::persistent::write_batch::StorageWriteBatch;
 use crate::persistent::{
     BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError,
 };
@@ -30,6 +31,15 @@ pub trait ChainMetaStorageReader: Sync + Send {
     /// - caboose - so in particular it is the lowest block for which we have stored the context
     fn get_caboose(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
 
+    /// Load save_point for chain_id from dedicated storage
+    fn get_save_point(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
+
+    /// Load checkpoint for chain_id from dedicated storage
+    ///
+    /// `checkpoint` is the block, which every accepted branch has to pass through,
+    /// so blocks at the same level with different hash are rejected
+    fn get_checkpoint(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
+
     /// Load genesis for chain_id from dedicated storage
     fn get_genesis(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError>;
 }
@@ -83,6 +93,26 @@ impl ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn set_save_point(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_save_point(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_checkpoint(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &MetaKey::key_checkpoint(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn set_genesis(&self, chain_id: &ChainId, head: Head) -> Result<(), StorageError> {
         self.kv
@@ -93,6 +123,67 @@ impl ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    /// Adds current head to the `batch`, so it is committed atomically with the data of the block
+    #[inline]
+    pub fn set_current_head_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        chain_id: &ChainId,
+        head: Head,
+    ) -> Result<(), StorageError> {
+        batch
+            .put::<Self>(
+                &MetaKey::key_current_head(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_caboose_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        chain_id: &ChainId,
+        head: Head,
+    ) -> Result<(), StorageError> {
+        batch
+            .put::<Self>(
+                &MetaKey::key_caboose(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_save_point_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        chain_id: &ChainId,
+        head: Head,
+    ) -> Result<(), StorageError> {
+        batch
+            .put::<Self>(
+                &MetaKey::key_save_point(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_genesis_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        chain_id: &ChainId,
+        head: Head,
+    ) -> Result<(), StorageError> {
+        batch
+            .put::<Self>(
+                &MetaKey::key_genesis(chain_id.clone()),
+                &MetadataValue::Head(head),
+            )
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get_test_chain_id(&self, chain_id: &ChainId) -> Result<Option<ChainId>, StorageError> {
         self.kv
@@ -149,6 +240,28 @@ impl ChainMetaStorageReader for ChainMetaStorage {
             .map_err(StorageError::from)
     }
 
+    #[inline]
+    fn get_save_point(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_save_point(chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::Head(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    fn get_checkpoint(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
+        self.kv
+            .get(&MetaKey::key_checkpoint(chain_id.clone()))
+            .map(|result| match result {
+                Some(MetadataValue::Head(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     fn get_genesis(&self, chain_id: &ChainId) -> Result<Option<Head>, StorageError> {
         self.kv
@@ -191,6 +304,8 @@ impl MetaKey {
 
     const KEY_CURRENT_HEAD: &'static str = "ch";
     const KEY_CABOOSE: &'static str = "cbs";
+    const KEY_SAVE_POINT: &'static str = "svp";
+    const KEY_CHECKPOINT: &'static str = "ckp";
     const KEY_GENESIS: &'static str = "gns";
     const KEY_TEST_CHAIN_ID: &'static str = "tcid";
 
@@ -208,6 +323,20 @@ impl MetaKey {
         }
     }
 
+    fn key_save_point(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_SAVE_POINT.to_string(),
+        }
+    }
+
+    fn key_checkpoint(chain_id: ChainId) -> MetaKey {
+        MetaKey {
+            chain_id,
+            key: Self::KEY_CHECKPOINT.to_string(),
+        }
+    }
+
     fn key_genesis(chain_id: ChainId) -> MetaKey {
         MetaKey {
             chain_id,
@@ -390,6 +519,91 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_save_point() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__test_save_point")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = "NetXgtSLGNJvNye".try_into()?;
+        let block_1 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+            1,
+            vec![],
+        );
+
+        let chain_id2 = "NetXjD3HPJJjmcd".try_into()?;
+        let block_2 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisd6f5afWyME7".try_into()?,
+            2,
+            vec![],
+        );
+
+        // no save points
+        assert!(index.get_save_point(&chain_id1)?.is_none());
+        assert!(index.get_save_point(&chain_id2)?.is_none());
+
+        // set for chain_id1
+        index.set_save_point(&chain_id1, block_1.clone())?;
+        assert_eq!(
+            index.get_save_point(&chain_id1)?.unwrap().block_hash(),
+            block_1.block_hash()
+        );
+        assert!(index.get_save_point(&chain_id2)?.is_none());
+
+        // update for chain_id1 does not touch caboose
+        index.set_save_point(&chain_id1, block_2.clone())?;
+        assert_eq!(
+            index.get_save_point(&chain_id1)?.unwrap().block_hash(),
+            block_2.block_hash()
+        );
+        assert!(index.get_caboose(&chain_id1)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__test_checkpoint")?;
+        let index = ChainMetaStorage::new(tmp_storage.storage());
+
+        let chain_id1 = "NetXgtSLGNJvNye".try_into()?;
+        let block_1 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+            1,
+            vec![],
+        );
+
+        let chain_id2 = "NetXjD3HPJJjmcd".try_into()?;
+        let block_2 = Head::new(
+            "BLockGenesisGenesisGenesisGenesisGenesisd6f5afWyME7".try_into()?,
+            2,
+            vec![],
+        );
+
+        // no checkpoints
+        assert!(index.get_checkpoint(&chain_id1)?.is_none());
+        assert!(index.get_checkpoint(&chain_id2)?.is_none());
+
+        // set for chain_id1
+        index.set_checkpoint(&chain_id1, block_1.clone())?;
+        assert_eq!(
+            index.get_checkpoint(&chain_id1)?.unwrap().block_hash(),
+            block_1.block_hash()
+        );
+        assert!(index.get_checkpoint(&chain_id2)?.is_none());
+
+        // update for chain_id1 does not touch save_point/caboose
+        index.set_checkpoint(&chain_id1, block_2.clone())?;
+        assert_eq!(
+            index.get_checkpoint(&chain_id1)?.unwrap().block_hash(),
+            block_2.block_hash()
+        );
+        assert!(index.get_save_point(&chain_id1)?.is_none());
+        assert!(index.get_caboose(&chain_id1)?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_genesis() -> Result<(), Error> {
         let tmp_storage = TmpStorage::create_to_out_dir("__test_genesis")?;
@@ -1,14 +1,52 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+//! Context actions recorded per block (see `storage/docs/action_recording.md`).
+//!
+//! Version 2 file starts with magic `TZACTv02` followed by block records:
+//!
+//! | crc32 (4) | compression (1) | level (4) | block hash (32) | actions count (4) | length (4) | stored length (4) | actions |
+//!
+//! where actions are bincode serialized `Vec<ContextAction>` compressed per block. Checksum covers everything after it.
+//! Level is the level of the block header (blocks of the switched branches share levels, so levels are not unique
+//! and not necessarily increasing), blocks converted from v1 file get sequence numbers from the given first level.
+//!
+//! Sidecar index `<file>.idx` (magic `TZACTIX2`) contains entry for every record:
+//!
+//! | block hash (32) | level (4) | offset (8) | crc32 (4) |
+//!
+//! so reader can seek to any block without reading the whole file. Index is appended after the record,
+//! missing entries (crash) are restored from records and torn record at the end of the file is truncated by writer.
+//!
+//! Version 1 file has no magic and no index, records are `| length (4) | snap compressed actions |`,
+//! it can be read just forwards, see [convert_v1_to_v2]. Writer converts v1 file, before it appends to it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
 use bytes::Buf;
 use crypto::hash::BlockHash;
 use failure::Fail;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
-use std::path::Path;
 use tezos_context::channel::ContextAction;
 
+const V2_MAGIC: &[u8; 8] = b"TZACTv02";
+const INDEX_MAGIC: &[u8; 8] = b"TZACTIX2";
+const INDEX_EXTENSION: &str = "idx";
+/// Extension of the original v1 file kept after its conversion by writer
+const V1_BACKUP_EXTENSION: &str = "v1";
+
+const RECORD_HEADER_SIZE: usize = 4 + 1 + 4 + 32 + 4 + 4 + 4;
+const INDEX_ENTRY_SIZE: usize = 32 + 4 + 8 + 4;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
 /// Possible errors for storage
 #[derive(Debug, Fail)]
 pub enum ActionFileError {
@@ -18,6 +56,20 @@ pub enum ActionFileError {
     IOError { error: std::io::Error },
     #[fail(display = "Serialization error, reason: {}", error)]
     SerializeError { error: bincode::Error },
+    #[fail(
+        display = "Corrupted block record at offset {}, reason: {}",
+        offset, reason
+    )]
+    CorruptedRecord { offset: u64, reason: String },
+    #[fail(
+        display = "Action file {:?} has unsupported version {:?}, expected: {:?}",
+        path, version, expected
+    )]
+    UnsupportedVersion {
+        path: PathBuf,
+        version: ActionsFileVersion,
+        expected: ActionsFileVersion,
+    },
 }
 
 impl From<std::io::Error> for ActionFileError {
@@ -40,57 +92,316 @@ pub struct ActionsFileHeader {
     pub block_count: u32,
 }
 
+/// Format of the action file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionsFileVersion {
+    /// Length prefixed snap compressed blocks without index
+    V1,
+    /// Checksummed blocks with sidecar index
+    V2,
+}
+
+/// Actions of one recorded block
+#[derive(Clone, Debug)]
+pub struct ActionsBlock {
+    /// Hash of the block from its `Commit` action (if known)
+    pub block_hash: Option<BlockHash>,
+    pub level: u32,
+    pub actions: Vec<ContextAction>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct IndexEntry {
+    block_hash: [u8; 32],
+    level: u32,
+    offset: u64,
+}
+
+struct RecordHeader {
+    compression: u8,
+    level: u32,
+    block_hash: [u8; 32],
+    len: u32,
+    stored_len: u32,
+}
+
+impl RecordHeader {
+    fn parse(header: &[u8; RECORD_HEADER_SIZE]) -> Self {
+        let mut block_hash = [0; 32];
+        block_hash.copy_from_slice(&header[9..41]);
+        Self {
+            compression: header[4],
+            level: u32::from_be_bytes([header[5], header[6], header[7], header[8]]),
+            block_hash,
+            len: u32::from_be_bytes([header[45], header[46], header[47], header[48]]),
+            stored_len: u32::from_be_bytes([header[49], header[50], header[51], header[52]]),
+        }
+    }
+
+    fn record_size(&self) -> u64 {
+        RECORD_HEADER_SIZE as u64 + self.stored_len as u64
+    }
+}
+
 pub struct ActionsFileReader {
+    path: PathBuf,
+    file: File,
+    version: ActionsFileVersion,
+    /// Index of v2 file
+    index: Vec<IndexEntry>,
+    /// Position of the first block with the hash in the index
+    positions_by_hash: HashMap<[u8; 32], usize>,
+    /// Position of the first block with the level in the index
+    positions_by_level: BTreeMap<u32, usize>,
+    /// Position of the next block in the index (v2), or count of already read blocks (v1)
+    position: usize,
+    /// Offset of the next block in v1 file
     cursor: u64,
-    reader: BufReader<File>,
 }
 
 impl ActionsFileReader {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ActionFileError> {
+        let path = path.as_ref();
         let file = OpenOptions::new()
             .write(false)
             .create(false)
             .read(true)
             .open(path)?;
-        let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(0))?;
-        Ok(ActionsFileReader { reader, cursor: 0 })
+        let (version, index) = match detect_version(&file)? {
+            ActionsFileVersion::V1 => (ActionsFileVersion::V1, Vec::new()),
+            ActionsFileVersion::V2 => {
+                let (index, _, _) = load_index(path, &file)?;
+                (ActionsFileVersion::V2, index)
+            }
+        };
+        let mut positions_by_hash = HashMap::with_capacity(index.len());
+        let mut positions_by_level = BTreeMap::new();
+        for (position, entry) in index.iter().enumerate() {
+            positions_by_hash
+                .entry(entry.block_hash)
+                .or_insert(position);
+            positions_by_level.entry(entry.level).or_insert(position);
+        }
+        Ok(ActionsFileReader {
+            path: path.to_path_buf(),
+            file,
+            version,
+            index,
+            positions_by_hash,
+            positions_by_level,
+            position: 0,
+            cursor: 0,
+        })
     }
-}
 
-impl Iterator for ActionsFileReader {
-    type Item = Vec<ContextAction>;
+    pub fn version(&self) -> ActionsFileVersion {
+        self.version
+    }
 
-    /// Return a tuple of a block and list action in the block
-    fn next(&mut self) -> Option<Self::Item> {
-        self.cursor = match self.reader.seek(SeekFrom::Start(self.cursor)) {
-            Ok(c) => c,
-            Err(_) => {
-                return None;
+    /// Count of blocks in the file (v1 file has to be scanned)
+    pub fn blocks_count(&self) -> Result<usize, ActionFileError> {
+        match self.version {
+            ActionsFileVersion::V2 => Ok(self.index.len()),
+            ActionsFileVersion::V1 => {
+                let mut offset = 0;
+                let mut count = 0;
+                while let Some(len) = self.v1_record_len(offset)? {
+                    offset += 4 + len;
+                    count += 1;
+                }
+                Ok(count)
             }
-        };
-        let mut h = [0_u8; 4];
-        match self.reader.read_exact(&mut h) {
-            Ok(_) => {}
-            Err(_) => return None,
         }
-        let content_len = u32::from_be_bytes(h);
-        if content_len == 0 {
-            return None;
+    }
+
+    /// Moves reader to the (first) block `block_hash`, returns false (and keeps position), if there is no such block.
+    pub fn seek_to_block(&mut self, block_hash: &BlockHash) -> Result<bool, ActionFileError> {
+        self.require_index()?;
+        let mut hash = [0; 32];
+        if block_hash.as_ref().len() != hash.len() {
+            return Ok(false);
+        }
+        hash.copy_from_slice(block_hash.as_ref());
+        match self.positions_by_hash.get(&hash) {
+            Some(position) => {
+                self.position = *position;
+                Ok(true)
+            }
+            None => Ok(false),
         }
-        let mut b = vec![0; content_len as usize];
-        let _ = self.reader.read_exact(&mut b);
+    }
 
-        let reader = snap::read::FrameDecoder::new(b.reader());
+    /// Moves reader to the (first) block at `level`, returns false (and keeps position), if there is no such block.
+    pub fn seek_to_level(&mut self, level: u32) -> Result<bool, ActionFileError> {
+        self.require_index()?;
+        match self.positions_by_level.get(&level) {
+            Some(position) => {
+                self.position = *position;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 
-        let item = match bincode::deserialize_from::<_, Self::Item>(reader) {
-            Ok(item) => item,
-            Err(_) => {
-                return None;
+    /// Moves reader to the `position`-th block of the file (v1 file is read up to it),
+    /// returns false if there is no such block.
+    pub fn seek_to_position(&mut self, position: usize) -> Result<bool, ActionFileError> {
+        match self.version {
+            ActionsFileVersion::V2 => {
+                if position >= self.index.len() {
+                    return Ok(false);
+                }
+                self.position = position;
+                Ok(true)
+            }
+            ActionsFileVersion::V1 => {
+                if position < self.position {
+                    self.position = 0;
+                    self.cursor = 0;
+                }
+                while self.position < position {
+                    match self.v1_record_len(self.cursor)? {
+                        Some(len) => {
+                            self.cursor += 4 + len;
+                            self.position += 1;
+                        }
+                        None => return Ok(false),
+                    }
+                }
+                Ok(self.v1_record_len(self.cursor)?.is_some())
             }
+        }
+    }
+
+    /// Iterates blocks with level in `levels` (in the order of the file)
+    pub fn blocks_in_range<R: RangeBounds<u32>>(
+        &mut self,
+        levels: R,
+    ) -> Result<BlocksInRange<'_>, ActionFileError> {
+        self.require_index()?;
+        let levels = (levels.start_bound().cloned(), levels.end_bound().cloned());
+        let empty = match levels {
+            (Bound::Included(start), Bound::Excluded(end)) => start >= end,
+            (Bound::Excluded(start), Bound::Excluded(end)) => start.saturating_add(1) >= end,
+            (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            _ => false,
         };
-        self.cursor += h.len() as u64 + content_len as u64;
-        Some(item)
+        let start = if empty {
+            None
+        } else {
+            self.positions_by_level
+                .range(levels)
+                .map(|(_, position)| *position)
+                .min()
+        };
+        self.position = start.unwrap_or(self.index.len());
+        Ok(BlocksInRange {
+            reader: self,
+            levels,
+        })
+    }
+
+    /// Reads the next block, returns `None` at the end of file
+    pub fn next_block(&mut self) -> Result<Option<ActionsBlock>, ActionFileError> {
+        match self.version {
+            ActionsFileVersion::V2 => {
+                let entry = match self.index.get(self.position) {
+                    Some(entry) => entry,
+                    None => return Ok(None),
+                };
+                let block = match read_record(&self.file, entry.offset)? {
+                    Some((header, stored)) => ActionsBlock {
+                        block_hash: to_block_hash(&header.block_hash),
+                        level: header.level,
+                        actions: decode_actions(&header, &stored, entry.offset)?,
+                    },
+                    None => {
+                        return Err(ActionFileError::CorruptedRecord {
+                            offset: entry.offset,
+                            reason: "invalid checksum".to_string(),
+                        })
+                    }
+                };
+                self.position += 1;
+                Ok(Some(block))
+            }
+            ActionsFileVersion::V1 => {
+                let len = match self.v1_record_len(self.cursor)? {
+                    Some(len) => len,
+                    None => return Ok(None),
+                };
+                let mut data = vec![0; len as usize];
+                self.file.read_exact_at(&mut data, self.cursor + 4)?;
+                let actions: Vec<ContextAction> =
+                    bincode::deserialize_from(snap::read::FrameDecoder::new(data.reader()))?;
+
+                let block = ActionsBlock {
+                    block_hash: commit_block_hash(&actions).and_then(|hash| to_block_hash(&hash)),
+                    level: self.position as u32,
+                    actions,
+                };
+                self.cursor += 4 + len;
+                self.position += 1;
+                Ok(Some(block))
+            }
+        }
+    }
+
+    /// Length of the complete v1 record at `offset` (without length prefix)
+    fn v1_record_len(&self, offset: u64) -> Result<Option<u64>, ActionFileError> {
+        let file_len = self.file.metadata()?.len();
+        let mut len = [0; 4];
+        if offset + len.len() as u64 > file_len {
+            return Ok(None);
+        }
+        self.file.read_exact_at(&mut len, offset)?;
+        let len = u32::from_be_bytes(len) as u64;
+        if len == 0 || offset + 4 + len > file_len {
+            return Ok(None);
+        }
+        Ok(Some(len))
+    }
+
+    fn require_index(&self) -> Result<(), ActionFileError> {
+        match self.version {
+            ActionsFileVersion::V2 => Ok(()),
+            version => Err(ActionFileError::UnsupportedVersion {
+                path: self.path.clone(),
+                version,
+                expected: ActionsFileVersion::V2,
+            }),
+        }
+    }
+}
+
+impl Iterator for ActionsFileReader {
+    type Item = Vec<ContextAction>;
+
+    /// Return a list of actions of the next block
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().ok().flatten().map(|block| block.actions)
+    }
+}
+
+/// Iterator of blocks in the level range, see [ActionsFileReader::blocks_in_range]
+pub struct BlocksInRange<'a> {
+    reader: &'a mut ActionsFileReader,
+    levels: (Bound<u32>, Bound<u32>),
+}
+
+impl<'a> Iterator for BlocksInRange<'a> {
+    type Item = Result<ActionsBlock, ActionFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // levels are not monotonic (switched branches), so blocks out of range are skipped
+        while !self
+            .levels
+            .contains(&self.reader.index.get(self.reader.position)?.level)
+        {
+            self.reader.position += 1;
+        }
+        self.reader.next_block().transpose()
     }
 }
 
@@ -99,37 +410,587 @@ impl Iterator for ActionsFileReader {
 /// writes block and list actions to file in `path`
 pub struct ActionsFileWriter {
     file: File,
+    index: File,
+    /// Count of entries in the index file
+    indexed: usize,
+    /// Size of the valid part of the file (records are appended here)
+    size: u64,
 }
 
 impl ActionsFileWriter {
+    /// Opens (or creates) v2 action file, restores missing index entries and truncates torn record at the end.
+    ///
+    /// Existing v1 file is converted to v2 (blocks get levels from `0`), the original file is kept with `.v1` extension.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ActionFileError> {
+        let path = path.as_ref();
         let file = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .read(true)
             .open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len > 0 && detect_version(&file)? == ActionsFileVersion::V1 {
+            drop(file);
+            migrate_v1_to_v2(path)?;
+            return Self::new(path);
+        }
+        if file_len < V2_MAGIC.len() as u64 {
+            file.set_len(0)?;
+            file.write_all_at(V2_MAGIC, 0)?;
+            remove_if_exists(&index_path(path))?;
+        }
+
+        let (entries, size, valid_entries) = load_index(path, &file)?;
+        if size < file.metadata()?.len() {
+            file.set_len(size)?;
+        }
+
+        // rewrite just the invalid/missing tail of the index
+        let index = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .open(index_path(path))?;
+        index.set_len((INDEX_MAGIC.len() + valid_entries * INDEX_ENTRY_SIZE) as u64)?;
+        index.write_all_at(INDEX_MAGIC, 0)?;
+        let mut writer = ActionsFileWriter {
+            file,
+            index,
+            indexed: valid_entries,
+            size,
+        };
+        for entry in entries.iter().skip(valid_entries) {
+            writer.write_index_entry(entry)?;
+        }
+        Ok(writer)
+    }
 
-        Ok(ActionsFileWriter { file })
+    /// Appends actions of the next block at `level`
+    pub fn update(
+        &mut self,
+        level: u32,
+        actions: Vec<ContextAction>,
+    ) -> Result<(), ActionFileError> {
+        self.append_block(level, &actions)
+    }
+
+    pub fn append_block(
+        &mut self,
+        level: u32,
+        actions: &[ContextAction],
+    ) -> Result<(), ActionFileError> {
+        let block_hash = commit_block_hash(actions).unwrap_or([0; 32]);
+        let record = encode_record(level, &block_hash, actions)?;
+        if let Err(e) = self.file.write_all_at(&record, self.size) {
+            let _ = self.file.set_len(self.size);
+            return Err(e.into());
+        }
+
+        let entry = IndexEntry {
+            block_hash,
+            level,
+            offset: self.size,
+        };
+        self.write_index_entry(&entry)?;
+
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), ActionFileError> {
+        self.file.sync_data()?;
+        self.index.sync_data()?;
+        Ok(())
+    }
+
+    fn write_index_entry(&mut self, entry: &IndexEntry) -> Result<(), ActionFileError> {
+        let mut data = Vec::with_capacity(INDEX_ENTRY_SIZE);
+        data.extend_from_slice(&entry.block_hash);
+        data.extend_from_slice(&entry.level.to_be_bytes());
+        data.extend_from_slice(&entry.offset.to_be_bytes());
+        let crc = crc32fast::hash(&data);
+        data.extend_from_slice(&crc.to_be_bytes());
+        self.index.write_all_at(
+            &data,
+            (INDEX_MAGIC.len() + self.indexed * INDEX_ENTRY_SIZE) as u64,
+        )?;
+        self.indexed += 1;
+        Ok(())
     }
 }
 
-impl ActionsFileWriter {
-    pub fn update(&mut self, actions: Vec<ContextAction>) -> Result<(), ActionFileError> {
-        let mut out = Vec::new();
-        let writer = snap::write::FrameEncoder::new(&mut out);
-        bincode::serialize_into(writer, &actions)?;
+/// Converts v1 action file `input` to the v2 file `output`, returns count of converted blocks.
+///
+/// v1 file does not contain levels, so blocks get sequence numbers starting with `first_level`
+/// (level of the first recorded block, `0` for files recorded from genesis).
+pub fn convert_v1_to_v2<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    first_level: u32,
+) -> Result<usize, ActionFileError> {
+    let mut reader = ActionsFileReader::new(input.as_ref())?;
+    if reader.version() != ActionsFileVersion::V1 {
+        return Err(ActionFileError::UnsupportedVersion {
+            path: input.as_ref().to_path_buf(),
+            version: reader.version(),
+            expected: ActionsFileVersion::V1,
+        });
+    }
+
+    let output = output.as_ref();
+    remove_if_exists(output)?;
+    remove_if_exists(&index_path(output))?;
+    let mut writer = ActionsFileWriter::new(output)?;
+
+    let mut count = 0;
+    while let Some(block) = reader.next_block()? {
+        writer.append_block(first_level + block.level, &block.actions)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Converts v1 file at `path` to v2 in place, original file is kept with `.v1` extension
+fn migrate_v1_to_v2(path: &Path) -> Result<(), ActionFileError> {
+    let converted = with_appended_extension(path, "tmp");
+    convert_v1_to_v2(path, &converted, 0)?;
+    fs::rename(path, v1_backup_path(path))?;
+    fs::rename(&converted, path)?;
+    fs::rename(index_path(&converted), index_path(path))?;
+    Ok(())
+}
+
+fn detect_version(file: &File) -> Result<ActionsFileVersion, ActionFileError> {
+    let mut magic = [0; 8];
+    if file.metadata()?.len() >= magic.len() as u64 {
+        file.read_exact_at(&mut magic, 0)?;
+        if &magic == V2_MAGIC {
+            return Ok(ActionsFileVersion::V2);
+        }
+    }
+    Ok(ActionsFileVersion::V1)
+}
+
+/// Loads index of the v2 file and adds entries of records missing in the index.
+///
+/// Returns all entries, size of the valid part of the file and count of valid entries stored in the index file.
+fn load_index(path: &Path, file: &File) -> Result<(Vec<IndexEntry>, u64, usize), ActionFileError> {
+    let file_len = file.metadata()?.len();
+    let mut entries = Vec::new();
+
+    let data = match fs::read(index_path(path)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if data.len() >= INDEX_MAGIC.len() && &data[..INDEX_MAGIC.len()] == INDEX_MAGIC {
+        for chunk in data[INDEX_MAGIC.len()..].chunks_exact(INDEX_ENTRY_SIZE) {
+            let (content, crc) = chunk.split_at(INDEX_ENTRY_SIZE - 4);
+            if crc32fast::hash(content) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+                break;
+            }
+            let mut block_hash = [0; 32];
+            block_hash.copy_from_slice(&content[..32]);
+            let mut offset = [0; 8];
+            offset.copy_from_slice(&content[36..44]);
+            let entry = IndexEntry {
+                block_hash,
+                level: u32::from_be_bytes([content[32], content[33], content[34], content[35]]),
+                offset: u64::from_be_bytes(offset),
+            };
+            let expected_offset = entries
+                .last()
+                .map_or(V2_MAGIC.len() as u64, |last: &IndexEntry| last.offset + 1);
+            if entry.offset < expected_offset || entry.offset >= file_len {
+                break;
+            }
+            entries.push(entry);
+        }
+    }
 
-        self._update(&out)?;
+    // the last indexed record is verified and everything after it is scanned
+    let last_indexed = entries.pop();
+    let scan_start = entries.len();
+    let mut offset = last_indexed
+        .as_ref()
+        .map_or(V2_MAGIC.len() as u64, |last| last.offset);
+    while let Some((header, _)) = read_record(file, offset)? {
+        entries.push(IndexEntry {
+            block_hash: header.block_hash,
+            level: header.level,
+            offset,
+        });
+        offset += header.record_size();
+    }
+    let valid_entries =
+        if last_indexed.is_some() && entries.get(scan_start) == last_indexed.as_ref() {
+            scan_start + 1
+        } else {
+            scan_start
+        };
+    Ok((entries, offset, valid_entries))
+}
+
+/// Reads record at `offset`, returns `None` if record is incomplete or its checksum does not match
+fn read_record(
+    file: &File,
+    offset: u64,
+) -> Result<Option<(RecordHeader, Vec<u8>)>, ActionFileError> {
+    let file_len = file.metadata()?.len();
+    let mut header = [0; RECORD_HEADER_SIZE];
+    if offset + RECORD_HEADER_SIZE as u64 > file_len {
+        return Ok(None);
+    }
+    file.read_exact_at(&mut header, offset)?;
+    let parsed = RecordHeader::parse(&header);
+    if offset + parsed.record_size() > file_len {
+        return Ok(None);
+    }
+
+    let mut stored = vec![0; parsed.stored_len as usize];
+    file.read_exact_at(&mut stored, offset + RECORD_HEADER_SIZE as u64)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[4..]);
+    hasher.update(&stored);
+    if hasher.finalize() != u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+        return Ok(None);
+    }
+    Ok(Some((parsed, stored)))
+}
+
+fn encode_record(
+    level: u32,
+    block_hash: &[u8; 32],
+    actions: &[ContextAction],
+) -> Result<Vec<u8>, ActionFileError> {
+    let serialized = bincode::serialize(actions)?;
+    let stored = zstd::block::compress(&serialized, ZSTD_LEVEL)?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + stored.len());
+    record.extend_from_slice(&[0; 4]);
+    record.push(COMPRESSION_ZSTD);
+    record.extend_from_slice(&level.to_be_bytes());
+    record.extend_from_slice(block_hash);
+    record.extend_from_slice(&(actions.len() as u32).to_be_bytes());
+    record.extend_from_slice(&(serialized.len() as u32).to_be_bytes());
+    record.extend_from_slice(&(stored.len() as u32).to_be_bytes());
+    record.extend_from_slice(&stored);
+    let crc = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_be_bytes());
+    Ok(record)
+}
+
+fn decode_actions(
+    header: &RecordHeader,
+    stored: &[u8],
+    offset: u64,
+) -> Result<Vec<ContextAction>, ActionFileError> {
+    let serialized = match header.compression {
+        COMPRESSION_NONE => stored.to_vec(),
+        COMPRESSION_ZSTD => zstd::block::decompress(stored, header.len as usize).map_err(|e| {
+            ActionFileError::CorruptedRecord {
+                offset,
+                reason: format!("decompression failed: {}", e),
+            }
+        })?,
+        compression => {
+            return Err(ActionFileError::CorruptedRecord {
+                offset,
+                reason: format!("unknown compression {}", compression),
+            })
+        }
+    };
+    Ok(bincode::deserialize(&serialized)?)
+}
+
+/// Block hash of the `Commit` action, which closes the block
+fn commit_block_hash(actions: &[ContextAction]) -> Option<[u8; 32]> {
+    actions.iter().rev().find_map(|action| match action {
+        ContextAction::Commit {
+            block_hash: Some(block_hash),
+            ..
+        } if block_hash.len() == 32 => {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(block_hash);
+            Some(hash)
+        }
+        _ => None,
+    })
+}
+
+fn to_block_hash(hash: &[u8; 32]) -> Option<BlockHash> {
+    if hash == &[0; 32] {
+        None
+    } else {
+        BlockHash::try_from(&hash[..]).ok()
+    }
+}
+
+fn index_path(path: &Path) -> PathBuf {
+    with_appended_extension(path, INDEX_EXTENSION)
+}
+
+fn v1_backup_path(path: &Path) -> PathBuf {
+    with_appended_extension(path, V1_BACKUP_EXTENSION)
+}
+
+fn with_appended_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), ActionFileError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn test_file(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(out_dir.as_str()).join(name);
+        remove_if_exists(&path).unwrap();
+        remove_if_exists(&index_path(&path)).unwrap();
+        remove_if_exists(&v1_backup_path(&path)).unwrap();
+        path
+    }
+
+    /// Actions of the block `i`: checkout, `i` sets and commit
+    fn block_actions(i: u8) -> Vec<ContextAction> {
+        let mut actions = vec![ContextAction::Checkout {
+            context_hash: vec![i; 32],
+            start_time: 0.0,
+            end_time: 0.0,
+        }];
+        for j in 0..i {
+            actions.push(ContextAction::Set {
+                context_hash: None,
+                block_hash: None,
+                operation_hash: None,
+                tree_hash: None,
+                new_tree_hash: None,
+                tree_id: 0,
+                new_tree_id: 0,
+                start_time: 0.0,
+                end_time: 0.0,
+                key: vec!["data".to_string(), format!("key{}", j)],
+                value: vec![j; 64],
+                value_as_json: None,
+            });
+        }
+        actions.push(ContextAction::Commit {
+            parent_context_hash: None,
+            block_hash: Some(vec![i + 1; 32]),
+            new_context_hash: vec![i; 32],
+            tree_hash: None,
+            tree_id: 0,
+            start_time: 0.0,
+            end_time: 0.0,
+            author: "Tezos".to_string(),
+            message: "".to_string(),
+            date: 0,
+            parents: vec![],
+        });
+        actions
+    }
+
+    fn block_hash(i: u8) -> BlockHash {
+        BlockHash::try_from(vec![i + 1; 32]).unwrap()
+    }
+
+    /// Level of the block `i` (recording started from snapshot)
+    fn block_level(i: u8) -> u32 {
+        100 + i as u32
+    }
+
+    fn write_blocks(path: &Path, blocks: std::ops::Range<u8>) -> Result<(), ActionFileError> {
+        let mut writer = ActionsFileWriter::new(path)?;
+        for i in blocks {
+            writer.update(block_level(i), block_actions(i))?;
+        }
+        writer.flush()
+    }
+
+    fn write_v1_blocks(path: &Path, count: u8) -> Result<(), ActionFileError> {
+        let mut data = Vec::new();
+        for i in 0..count {
+            let mut block = Vec::new();
+            bincode::serialize_into(
+                snap::write::FrameEncoder::new(&mut block),
+                &block_actions(i),
+            )?;
+            data.extend_from_slice(&(block.len() as u32).to_be_bytes());
+            data.extend_from_slice(&block);
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_read_and_seek() -> Result<(), ActionFileError> {
+        let path = test_file("__action_file_v2_seek");
+        write_blocks(&path, 0..5)?;
+        // writer continues after the last block
+        write_blocks(&path, 5..10)?;
+        // branch switch - block 10 is applied at the level of the block 8
+        ActionsFileWriter::new(&path)?.update(block_level(8), block_actions(10))?;
+
+        let mut reader = ActionsFileReader::new(&path)?;
+        assert_eq!(ActionsFileVersion::V2, reader.version());
+        assert_eq!(11, reader.blocks_count()?);
+
+        assert!(reader.seek_to_block(&block_hash(7))?);
+        let block = reader.next_block()?.unwrap();
+        assert_eq!(block_level(7), block.level);
+        assert_eq!(Some(block_hash(7)), block.block_hash);
+        assert_eq!(block_actions(7).len(), block.actions.len());
+        assert_eq!(block_level(8), reader.next_block()?.unwrap().level);
+
+        assert!(!reader.seek_to_block(&block_hash(100))?);
+        assert!(reader.seek_to_level(block_level(2))?);
+        assert_eq!(
+            Some(block_hash(2)),
+            reader.next_block()?.unwrap().block_hash
+        );
+        assert!(!reader.seek_to_level(2)?);
+        assert!(!reader.seek_to_level(block_level(10))?);
+        // the first block of the level
+        assert!(reader.seek_to_level(block_level(8))?);
+        assert_eq!(
+            Some(block_hash(8)),
+            reader.next_block()?.unwrap().block_hash
+        );
+
+        let levels = reader
+            .blocks_in_range(block_level(3)..block_level(6))?
+            .map(|block| block.map(|block| block.level))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(vec![block_level(3), block_level(4), block_level(5)], levels);
+        let hashes = reader
+            .blocks_in_range(block_level(8)..)?
+            .map(|block| block.map(|block| block.block_hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            vec![
+                Some(block_hash(8)),
+                Some(block_hash(9)),
+                Some(block_hash(10))
+            ],
+            hashes
+        );
+        assert_eq!(0, reader.blocks_in_range(5..5)?.count());
+
+        assert!(reader.seek_to_position(9)?);
+        assert_eq!(
+            Some(block_hash(9)),
+            reader.next_block()?.unwrap().block_hash
+        );
+        assert!(!reader.seek_to_position(11)?);
+
+        // plain iteration from the beginning
+        let reader = ActionsFileReader::new(&path)?;
+        assert_eq!(11, reader.count());
         Ok(())
     }
 
-    pub fn _update(&mut self, data: &[u8]) -> Result<(), ActionFileError> {
-        self.file.seek(SeekFrom::End(0))?;
-        let chunk_size = (data.len() as u32).to_be_bytes();
-        let mut dt = vec![];
-        dt.extend_from_slice(&chunk_size);
-        dt.extend_from_slice(data);
-        self.file.write_all(dt.as_slice())?;
+    #[test]
+    fn test_recover_torn_record_and_missing_index() -> Result<(), ActionFileError> {
+        let path = test_file("__action_file_v2_recovery");
+        write_blocks(&path, 0..5)?;
+
+        // crash during write of the 6th block - torn record and missing index entry
+        let size = fs::metadata(&path)?.len();
+        let record = encode_record(block_level(5), &[6; 32], &block_actions(5))?;
+        OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .write_all_at(&record[..record.len() / 2], size)?;
+        // index entry of the 5th block is lost too
+        let index_len = fs::metadata(index_path(&path))?.len();
+        OpenOptions::new()
+            .write(true)
+            .open(index_path(&path))?
+            .set_len(index_len - INDEX_ENTRY_SIZE as u64)?;
+
+        // reader restores index entries from records and ignores the torn one
+        let reader = ActionsFileReader::new(&path)?;
+        assert_eq!(5, reader.blocks_count()?);
+
+        // writer truncates torn record and continues
+        write_blocks(&path, 5..7)?;
+        assert_eq!(
+            (INDEX_MAGIC.len() + 7 * INDEX_ENTRY_SIZE) as u64,
+            fs::metadata(index_path(&path))?.len()
+        );
+        let mut reader = ActionsFileReader::new(&path)?;
+        assert_eq!(7, reader.blocks_count()?);
+        assert!(reader.seek_to_block(&block_hash(6))?);
+        assert_eq!(block_level(6), reader.next_block()?.unwrap().level);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_v1_to_v2() -> Result<(), ActionFileError> {
+        let v1_path = test_file("__action_file_v1");
+        let v2_path = test_file("__action_file_v1_converted");
+        write_v1_blocks(&v1_path, 4)?;
+
+        let mut v1_reader = ActionsFileReader::new(&v1_path)?;
+        assert_eq!(ActionsFileVersion::V1, v1_reader.version());
+        assert_eq!(4, v1_reader.blocks_count()?);
+        assert!(matches!(
+            v1_reader.seek_to_level(1),
+            Err(ActionFileError::UnsupportedVersion { .. })
+        ));
+        assert!(v1_reader.seek_to_position(2)?);
+        assert_eq!(
+            Some(block_hash(2)),
+            v1_reader.next_block()?.unwrap().block_hash
+        );
+        assert!(!v1_reader.seek_to_position(4)?);
+
+        assert_eq!(4, convert_v1_to_v2(&v1_path, &v2_path, block_level(0))?);
+        let mut reader = ActionsFileReader::new(&v2_path)?;
+        assert_eq!(ActionsFileVersion::V2, reader.version());
+        assert!(reader.seek_to_block(&block_hash(3))?);
+        let block = reader.next_block()?.unwrap();
+        assert_eq!(block_level(3), block.level);
+        assert_eq!(block_actions(3).len(), block.actions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_writer_migrates_v1_file() -> Result<(), ActionFileError> {
+        let path = test_file("__action_file_v1_migrated");
+        write_v1_blocks(&path, 3)?;
+
+        // v1 blocks get levels from 0, writer appends after them
+        let mut writer = ActionsFileWriter::new(&path)?;
+        writer.update(3, block_actions(3))?;
+        writer.flush()?;
+
+        let mut reader = ActionsFileReader::new(&path)?;
+        assert_eq!(ActionsFileVersion::V2, reader.version());
+        assert_eq!(4, reader.blocks_count()?);
+        assert!(reader.seek_to_block(&block_hash(2))?);
+        assert_eq!(2, reader.next_block()?.unwrap().level);
+        assert_eq!(3, reader.next_block()?.unwrap().level);
+
+        // original file is kept
+        let v1_reader = ActionsFileReader::new(v1_backup_path(&path))?;
+        assert_eq!(ActionsFileVersion::V1, v1_reader.version());
+        assert_eq!(3, v1_reader.blocks_count()?);
         Ok(())
     }
 }
@@ -1,25 +1,32 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
+use crypto::hash::BlockHash;
 use tezos_context::channel::ContextAction;
 
 use crate::context::actions::action_file::{ActionFileError, ActionsFileWriter};
 use crate::context::actions::{ActionRecorder, ActionRecorderError};
+use crate::{BlockStorage, BlockStorageReader, PersistentStorage};
 
 pub struct ActionFileStorage {
     file: PathBuf,
     staging: Vec<ContextAction>,
-    level: u32,
+    /// Opened with the first stored block
+    writer: Option<ActionsFileWriter>,
+    /// Block headers are stored before the block is applied, level of the recorded block is taken from them
+    block_storage: BlockStorage,
 }
 
 impl ActionFileStorage {
-    pub fn new(path: PathBuf) -> Self {
+    pub fn new(path: PathBuf, persistent_storage: &PersistentStorage) -> Self {
         ActionFileStorage {
             file: path,
             staging: Vec::new(),
-            level: 0,
+            writer: None,
+            block_storage: BlockStorage::new(persistent_storage),
         }
     }
 
@@ -27,15 +34,60 @@ impl ActionFileStorage {
         self.staging.push(action.clone());
     }
 
-    fn store_commit_action(&mut self, action: &ContextAction) -> Result<(), ActionFileError> {
-        self.level += 1;
+    fn store_commit_action(&mut self, action: &ContextAction) -> Result<(), ActionRecorderError> {
+        let level = match self.block_level(action) {
+            Ok(level) => level,
+            Err(e) => {
+                // actions of the block are dropped, so they are not mixed with the next block
+                self.staging.clear();
+                return Err(e);
+            }
+        };
         self.store_single_action(action);
-        self.flush_entries_to_file()
+        self.flush_entries_to_file(level)
+            .map_err(|e| ActionRecorderError::StoreError {
+                reason: format!("Failed to store action to action file, reason: {:?}", e),
+            })
+    }
+
+    /// Level of the block committed by `action`
+    fn block_level(&self, action: &ContextAction) -> Result<u32, ActionRecorderError> {
+        let block_hash = match action {
+            ContextAction::Commit {
+                block_hash: Some(block_hash),
+                ..
+            } => BlockHash::try_from(&block_hash[..]).map_err(|e| {
+                ActionRecorderError::StoreError {
+                    reason: format!("Failed to decode block_hash, reason: {:?}", e),
+                }
+            })?,
+            _ => {
+                return Err(ActionRecorderError::StoreError {
+                    reason: "Commit action without block_hash".to_string(),
+                })
+            }
+        };
+        match self.block_storage.get(&block_hash) {
+            Ok(Some(block)) => Ok(block.header.level() as u32),
+            Ok(None) => Err(ActionRecorderError::StoreError {
+                reason: format!(
+                    "Block header {} of the recorded block is not stored",
+                    block_hash.to_base58_check()
+                ),
+            }),
+            Err(e) => Err(ActionRecorderError::StoreError {
+                reason: format!("Failed to read block header, reason: {:?}", e),
+            }),
+        }
     }
 
-    fn flush_entries_to_file(&mut self) -> Result<(), ActionFileError> {
-        let mut action_file_writer = ActionsFileWriter::new(&self.file)?;
-        action_file_writer.update(self.staging.clone())?;
+    fn flush_entries_to_file(&mut self, level: u32) -> Result<(), ActionFileError> {
+        if self.writer.is_none() {
+            self.writer = Some(ActionsFileWriter::new(&self.file)?);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.append_block(level, &self.staging)?;
+        }
         self.staging.clear();
         Ok(())
     }
@@ -75,11 +127,7 @@ impl ActionRecorder for ActionFileStorage {
                 self.store_single_action(context_action);
                 Ok(())
             }
-            ContextAction::Commit { .. } => self.store_commit_action(context_action).map_err(|e| {
-                ActionRecorderError::StoreError {
-                    reason: format!("Failed to store action to action file, reason: {:?}", e),
-                }
-            }),
+            ContextAction::Commit { .. } => self.store_commit_action(context_action),
             ContextAction::Shutdown => Ok(()),
         }
     }
@@ -0,0 +1,802 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+
+use crate::context::gc::cycle_roots::{is_gc_state_key, CycleRoots};
+use crate::context::gc::{
+    fetch_entry_from_store, GarbageCollectionError, GarbageCollector, GcPhase, GcStats,
+};
+use crate::context::kv_store::compressed::CompressionStats;
+use crate::context::merkle::hash::EntryHash;
+use crate::context::merkle::{Entry, NodeKind};
+use crate::context::{ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+};
+
+/// Count of entries marked between two checks of exit request (and updates of progress stats)
+const COUNT_OF_ENTRIES_TO_MARK_IN_SINGLE_GC_ITERATION: usize = 10_000;
+
+/// Count of garbage keys checked and removed at once while holding the write barrier lock
+/// (and count of scanned keys between two checks of exit request)
+const COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION: usize = 2048;
+
+/// Commands used by ConcurrentMarkSweepGCed to interact with GC thread.
+enum GcMsg {
+    Collect { epoch: u64 },
+    Exit,
+}
+
+/// State shared by the main thread (block application) and GC thread
+struct GcShared {
+    /// Commits applied during each epoch (cycle), older to newer, last one is the current epoch
+    roots: CycleRoots,
+    /// Entries written (or reused) since the last applied block, belongs to the commit, which is not a root yet
+    pending: HashSet<EntryHash>,
+    /// Set just while collection is running - entries written (or read) by the main thread
+    /// after the roots were taken, running collection never sweeps them.
+    touched: Option<HashSet<EntryHash>>,
+}
+
+impl GcShared {
+    fn written<'a>(&mut self, keys: impl Iterator<Item = &'a EntryHash> + Clone) {
+        if let Some(touched) = self.touched.as_mut() {
+            touched.extend(keys.clone());
+        }
+        self.pending.extend(keys);
+    }
+
+    fn read(&mut self, key: &EntryHash) {
+        if let Some(touched) = self.touched.as_mut() {
+            touched.insert(*key);
+        }
+    }
+}
+
+/// Garbage Collected Key Value Store, which does mark-and-sweep in a background thread.
+///
+/// Block application just records the applied commits (roots) for the current epoch, roots are persisted in the store,
+/// so they survive restart.
+/// When a new cycle starts, the GC thread marks all entries reachable from the roots of the preserved epochs
+/// and then sweeps the unmarked ones. Collection is tagged with the generation of the write barrier:
+/// entries of the in-flight commit and entries written or read during collection are never swept.
+pub struct ConcurrentMarkSweepGCed<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
+    store: Arc<T>,
+    shared: Arc<Mutex<GcShared>>,
+    stats: Arc<RwLock<GcStats>>,
+    exit: Arc<AtomicBool>,
+    /// Channel to communicate with GC thread from main thread
+    msg: Mutex<mpsc::Sender<GcMsg>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<
+        T: 'static
+            + KeyValueStoreBackend<ContextKeyValueStoreSchema>
+            + GarbageCollector
+            + Send
+            + Sync
+            + Default,
+    > ConcurrentMarkSweepGCed<T>
+{
+    pub fn new(cycle_count: usize) -> Result<Self, GarbageCollectionError> {
+        Self::with_store(Default::default(), cycle_count)
+    }
+}
+
+impl<
+        T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector + Send + Sync,
+    > ConcurrentMarkSweepGCed<T>
+{
+    /// Wraps already initialized store, entries not used by commits in last `cycle_count` cycles are removed.
+    ///
+    /// Roots of the preserved cycles persisted by the previous run are loaded from the store.
+    pub fn with_store(store: T, cycle_count: usize) -> Result<Self, GarbageCollectionError> {
+        //one extra epoch "current"
+        let roots = CycleRoots::load(&store, cycle_count + 1)?;
+        let store = Arc::new(store);
+        let shared = Arc::new(Mutex::new(GcShared {
+            roots,
+            pending: HashSet::new(),
+            touched: None,
+        }));
+        let stats = Arc::new(RwLock::new(GcStats::default()));
+        let exit = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let thread = {
+            let store = store.clone();
+            let shared = shared.clone();
+            let stats = stats.clone();
+            let exit = exit.clone();
+            thread::spawn(move || gc_thread_fn(&*store, &shared, &stats, &exit, rx))
+        };
+
+        Ok(Self {
+            store,
+            shared,
+            stats,
+            exit,
+            msg: Mutex::new(tx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        let epoch = {
+            let mut shared = self.shared.lock()?;
+            shared.roots.start_cycle(&*self.store)?;
+            shared.roots.epoch()
+        };
+
+        self.msg
+            .lock()?
+            .send(GcMsg::Collect { epoch })
+            .map_err(|_| GarbageCollectionError::GarbageCollectorError {
+                error: "cannot send message to GC thread".to_string(),
+            })
+    }
+
+    fn store_commit_as_root(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        let mut shared = self.shared.lock()?;
+        // entries written since the last block are referenced by this commit from now on
+        shared.pending.clear();
+        shared.roots.add(&*self.store, commit)
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> Drop for ConcurrentMarkSweepGCed<T> {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Release);
+        if let Ok(msg) = self.msg.lock() {
+            let _ = msg.send(GcMsg::Exit);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<
+        T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector + Send + Sync,
+    > GarbageCollector for ConcurrentMarkSweepGCed<T>
+{
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.new_cycle_started()
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        self.store_commit_as_root(commit)
+    }
+
+    fn entries_reused(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        self.shared.lock()?.written(keys.iter());
+        Ok(())
+    }
+
+    fn entries_read(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        let mut shared = self.shared.lock()?;
+        for key in keys {
+            shared.read(key);
+        }
+        Ok(())
+    }
+
+    fn gc_stats(&self) -> Option<GcStats> {
+        self.stats.read().ok().map(|stats| stats.clone())
+    }
+
+    fn cycle_epoch(&self) -> Option<u64> {
+        self.shared.lock().ok().map(|shared| shared.roots.epoch())
+    }
+}
+
+impl<
+        T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector + Send + Sync,
+    > KeyValueStoreBackend<ContextKeyValueStoreSchema> for ConcurrentMarkSweepGCed<T>
+{
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.shared.lock()?.written(std::iter::once(key));
+        self.store.put(key, value)
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        self.store.delete(key)
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.shared.lock()?.written(std::iter::once(key));
+        self.store.merge(key, value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        self.shared.lock()?.read(key);
+        self.store.get(key)
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        self.shared.lock()?.read(key);
+        self.store.contains(key)
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        self.shared
+            .lock()?
+            .written(batch.iter().map(|(key, _)| key));
+        self.store.write_batch(batch)
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        let shared = self.shared.lock()?;
+        Ok(shared.roots.len() * std::mem::size_of::<EntryHash>()
+            + shared.pending.len() * std::mem::size_of::<EntryHash>()
+            + shared.touched.as_ref().map(|t| t.len()).unwrap_or(0)
+                * std::mem::size_of::<EntryHash>()
+            + self.store.total_get_mem_usage()?)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        self.store
+            .retain(&|key| is_gc_state_key(key) || predicate(key))
+    }
+
+    fn compression_stats(&self) -> Option<CompressionStats> {
+        self.store.compression_stats()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Flushable> Flushable
+    for ConcurrentMarkSweepGCed<T>
+{
+    fn flush(&self) -> Result<(), failure::Error> {
+        self.store.flush()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Persistable> Persistable
+    for ConcurrentMarkSweepGCed<T>
+{
+    fn is_persistent(&self) -> bool {
+        self.store.is_persistent()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + MultiInstanceable> MultiInstanceable
+    for ConcurrentMarkSweepGCed<T>
+{
+    fn supports_multiple_opened_instances(&self) -> bool {
+        self.store.supports_multiple_opened_instances()
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.store.sync_with_primary()
+    }
+}
+
+/// Garbage collector main function
+fn gc_thread_fn<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector>(
+    store: &T,
+    shared: &Mutex<GcShared>,
+    stats: &RwLock<GcStats>,
+    exit: &AtomicBool,
+    rx: mpsc::Receiver<GcMsg>,
+) {
+    while let Ok(GcMsg::Collect { mut epoch }) = rx.recv() {
+        // requests queued during the previous collection are merged, the newest epoch has the newest roots
+        for msg in rx.try_iter() {
+            match msg {
+                GcMsg::Collect { epoch: newer } => epoch = newer,
+                GcMsg::Exit => return,
+            }
+        }
+
+        let result = collect(store, shared, stats, exit, epoch);
+
+        // write barrier is not needed anymore (even if collection failed)
+        if let Ok(mut shared) = shared.lock() {
+            shared.touched = None;
+        }
+        if let Ok(mut stats) = stats.write() {
+            stats.phase = GcPhase::Idle;
+            match result {
+                Ok(true) => {
+                    stats.finished_collections += 1;
+                    stats.total_reclaimed_bytes += stats.reclaimed_bytes;
+                    stats.last_error = None;
+                }
+                Ok(false) => return,
+                Err(e) => stats.last_error = Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Marks entries reachable from the roots and sweeps the rest.
+///
+/// Returns `false`, if collection was interrupted by exit request.
+fn collect<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector>(
+    store: &T,
+    shared: &Mutex<GcShared>,
+    stats: &RwLock<GcStats>,
+    exit: &AtomicBool,
+    epoch: u64,
+) -> Result<bool, GarbageCollectionError> {
+    // roots are taken atomically with enabling of the write barrier,
+    // so every entry, which is not reachable from them (or from the in-flight commit),
+    // is either garbage or touched by the main thread
+    let (roots, pending): (Vec<EntryHash>, Vec<EntryHash>) = {
+        let mut shared = shared.lock()?;
+        let roots: Vec<EntryHash> = shared.roots.cycles().flatten().copied().collect();
+        if roots.is_empty() {
+            // nothing is known to be alive (e.g. no block was applied after restart)
+            return Ok(true);
+        }
+        shared.touched = Some(shared.pending.clone());
+        (roots, shared.pending.iter().copied().collect())
+    };
+
+    {
+        let mut stats = stats.write()?;
+        stats.epoch = epoch;
+        stats.phase = GcPhase::Marking;
+        stats.roots = roots.len();
+        stats.marked_entries = 0;
+        stats.swept_entries = 0;
+        stats.reclaimed_bytes = 0;
+    }
+
+    // mark - iterative DFS, which does not load blobs
+    let mut marked: HashSet<EntryHash> = HashSet::new();
+    let mut todo = roots;
+    // entries of the in-flight commit are marked with their subtrees (reused subtree is not written again),
+    // entry is just being written, if it is not in the store yet
+    for hash in pending {
+        if store.contains(&hash)? {
+            todo.push(hash);
+        }
+    }
+    let mut processed = 0;
+    while let Some(hash) = todo.pop() {
+        if !marked.insert(hash) {
+            continue;
+        }
+
+        match fetch_entry_from_store(store, hash)? {
+            Entry::Blob(_) => (),
+            Entry::Tree(tree) => {
+                for node in tree.values() {
+                    let child = node.entry_hash()?;
+                    match node.node_kind {
+                        NodeKind::Leaf => {
+                            marked.insert(child);
+                        }
+                        NodeKind::NonLeaf => {
+                            if !marked.contains(&child) {
+                                todo.push(child);
+                            }
+                        }
+                    }
+                }
+            }
+            Entry::Commit(commit) => todo.push(commit.root_hash),
+        }
+
+        processed += 1;
+        if processed % COUNT_OF_ENTRIES_TO_MARK_IN_SINGLE_GC_ITERATION == 0 {
+            if exit.load(Ordering::Acquire) {
+                return Ok(false);
+            }
+            stats.write()?.marked_entries = marked.len();
+        }
+    }
+
+    {
+        let mut stats = stats.write()?;
+        stats.marked_entries = marked.len();
+        stats.phase = GcPhase::Sweeping;
+    }
+
+    // sweep - store is scanned without its locks and garbage is removed in chunks,
+    // barrier lock is held just while the chunk is checked and removed, so main thread cannot touch its entries in between
+    let garbage: RefCell<Vec<EntryHash>> = RefCell::new(Vec::with_capacity(
+        COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION,
+    ));
+    let scanned = Cell::new(0_usize);
+    let swept = Cell::new(0_usize);
+    let reclaimed_bytes = Cell::new(0_u64);
+    let interrupted = Cell::new(false);
+    let failure: RefCell<Option<GarbageCollectionError>> = RefCell::new(None);
+    let sweep_garbage = || -> Result<(), GarbageCollectionError> {
+        let mut garbage = garbage.borrow_mut();
+        {
+            let shared = shared.lock()?;
+            if let Some(touched) = shared.touched.as_ref() {
+                garbage.retain(|key| !touched.contains(key));
+            }
+            reclaimed_bytes.set(reclaimed_bytes.get() + store.delete_batch(&garbage)?);
+        }
+        swept.set(swept.get() + garbage.len());
+        garbage.clear();
+
+        let mut stats = stats.write()?;
+        stats.swept_entries = swept.get();
+        stats.reclaimed_bytes = reclaimed_bytes.get();
+        Ok(())
+    };
+    let scan_result = store.retain(&|key| {
+        if interrupted.get() {
+            return true;
+        }
+        scanned.set(scanned.get() + 1);
+        if scanned.get() % COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION == 0
+            && exit.load(Ordering::Acquire)
+        {
+            interrupted.set(true);
+            return true;
+        }
+        if is_gc_state_key(key) || marked.contains(key) {
+            return true;
+        }
+
+        garbage.borrow_mut().push(*key);
+        if garbage.borrow().len() >= COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION {
+            if let Err(e) = sweep_garbage() {
+                *failure.borrow_mut() = Some(e);
+                interrupted.set(true);
+            }
+        }
+        // garbage is removed by the collector, store just iterates the keys
+        true
+    });
+    if let Some(e) = failure.into_inner() {
+        return Err(e);
+    }
+    scan_result?;
+    if !interrupted.get() {
+        sweep_garbage()?;
+    }
+    drop(marked);
+    if interrupted.get() {
+        return Ok(false);
+    }
+
+    stats.write()?.phase = GcPhase::Compacting;
+    store.compact()?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+    use crate::context::merkle::hash::hash_entry;
+    use crate::context::merkle::merkle_storage::MerkleStorage;
+    use crate::context::ContextKey;
+
+    use super::*;
+
+    /// Waits for GC thread to finish `count` collections
+    fn wait_for_collections(storage: &MerkleStorage, count: u64) -> GcStats {
+        for _ in 0..500 {
+            let stats = storage.get_merkle_stats().unwrap().gc_stats.unwrap();
+            if stats.finished_collections >= count && stats.phase == GcPhase::Idle {
+                return stats;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("GC collection was not finished in time");
+    }
+
+    #[test]
+    fn test_concurrent_gc_with_merkle_storage() {
+        let mut storage = MerkleStorage::new(Box::new(
+            ConcurrentMarkSweepGCed::with_store(InMemoryBackend::new(), 1).unwrap(),
+        ));
+        let key_a_b: ContextKey = vec!["a".to_string(), "b".to_string()];
+        let key_a_c: ContextKey = vec!["a".to_string(), "c".to_string()];
+
+        // CYCLE 1
+        storage.set(1, &key_a_b, vec![1]).unwrap();
+        storage.set(2, &key_a_c, vec![2]).unwrap();
+        let commit_1 = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+        storage.start_new_cycle().unwrap();
+        let stats = wait_for_collections(&storage, 1);
+        assert_eq!(stats.swept_entries, 0);
+
+        // CYCLE 2
+        storage.set(3, &key_a_b, vec![3]).unwrap();
+        let commit_2 = storage
+            .commit(1, "Tezos".to_string(), "".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+        assert_eq!(storage.get_history(&commit_1, &key_a_b).unwrap(), vec![1]);
+
+        // CYCLE 3 - entries used just by commit_1 are removed
+        storage.start_new_cycle().unwrap();
+        let stats = wait_for_collections(&storage, 2);
+        assert_eq!(stats.epoch, 2);
+        assert!(stats.swept_entries > 0);
+        assert!(stats.reclaimed_bytes > 0);
+        assert_eq!(stats.total_reclaimed_bytes, stats.reclaimed_bytes);
+        assert!(storage.checkout(&commit_1).is_err());
+        assert_eq!(storage.get_history(&commit_2, &key_a_b).unwrap(), vec![3]);
+        assert_eq!(storage.get_history(&commit_2, &key_a_c).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_concurrent_gc_keeps_in_flight_entries() {
+        let store = InMemoryBackend::new();
+        let put = |value: Vec<u8>| {
+            let entry = Entry::Blob(value);
+            let hash = hash_entry(&entry).unwrap();
+            store
+                .put(&hash, &bincode::serialize(&entry).unwrap())
+                .unwrap();
+            hash
+        };
+        let root = put(vec![1]);
+        let garbage = put(vec![2]);
+        let in_flight = put(vec![3]);
+
+        let mut roots = CycleRoots::load(&store, 1).unwrap();
+        roots.add(&store, root).unwrap();
+
+        // `in_flight` was written by the commit, which is not applied yet
+        let shared = Mutex::new(GcShared {
+            roots,
+            pending: vec![in_flight].into_iter().collect(),
+            touched: None,
+        });
+        let stats = RwLock::new(GcStats::default());
+        let exit = AtomicBool::new(false);
+
+        assert!(collect(&store, &shared, &stats, &exit, 1).unwrap());
+        assert!(store.contains(&root).unwrap());
+        assert!(store.contains(&in_flight).unwrap());
+        assert!(!store.contains(&garbage).unwrap());
+
+        let stats = stats.read().unwrap();
+        assert_eq!(stats.epoch, 1);
+        assert_eq!(stats.roots, 1);
+        assert_eq!(stats.marked_entries, 2);
+        assert_eq!(stats.swept_entries, 1);
+    }
+
+    /// Store, which pauses the scan of the sweep on the first key until the test writes its entries
+    struct PausingStore {
+        store: InMemoryBackend,
+        sweep_started: Mutex<mpsc::Sender<()>>,
+        written: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl GarbageCollector for PausingStore {
+        fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+            Ok(())
+        }
+
+        fn block_applied(&mut self, _commit: EntryHash) -> Result<(), GarbageCollectionError> {
+            Ok(())
+        }
+    }
+
+    impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for PausingStore {
+        fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+            self.store.put(key, value)
+        }
+
+        fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+            self.store.delete(key)
+        }
+
+        fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+            self.store.delete_batch(keys)
+        }
+
+        fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+            self.store.merge(key, value)
+        }
+
+        fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+            self.store.get(key)
+        }
+
+        fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+            self.store.contains(key)
+        }
+
+        fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+            let paused = Cell::new(false);
+            self.store.retain(&|key| {
+                if !paused.get() {
+                    paused.set(true);
+                    self.sweep_started.lock().unwrap().send(()).unwrap();
+                    self.written
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_secs(10))
+                        .expect("writes were blocked by running sweep");
+                }
+                predicate(key)
+            })
+        }
+
+        fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+            self.store.write_batch(batch)
+        }
+
+        fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+            self.store.total_get_mem_usage()
+        }
+    }
+
+    #[test]
+    fn test_concurrent_gc_does_not_block_writes_during_sweep() {
+        let (sweep_started_tx, sweep_started) = mpsc::channel();
+        let (written, written_rx) = mpsc::channel();
+        let store = Arc::new(PausingStore {
+            store: InMemoryBackend::new(),
+            sweep_started: Mutex::new(sweep_started_tx),
+            written: Mutex::new(written_rx),
+        });
+        let put = |value: Vec<u8>| {
+            let entry = Entry::Blob(value);
+            let hash = hash_entry(&entry).unwrap();
+            store
+                .put(&hash, &bincode::serialize(&entry).unwrap())
+                .unwrap();
+            hash
+        };
+        let root = put(vec![0]);
+        // garbage is removed in several chunks
+        let garbage: Vec<EntryHash> = (1..3 * COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION as u32)
+            .map(|i| put(i.to_be_bytes().to_vec()))
+            .collect();
+
+        let mut roots = CycleRoots::load(&*store, 1).unwrap();
+        roots.add(&*store, root).unwrap();
+        let shared = Arc::new(Mutex::new(GcShared {
+            roots,
+            pending: HashSet::new(),
+            touched: None,
+        }));
+        let stats = Arc::new(RwLock::new(GcStats::default()));
+
+        let collector = {
+            let store = store.clone();
+            let shared = shared.clone();
+            let stats = stats.clone();
+            thread::spawn(move || {
+                collect(&*store, &shared, &stats, &AtomicBool::new(false), 1).unwrap()
+            })
+        };
+
+        // main thread writes new entry and reuses the garbage one while sweep is running
+        sweep_started.recv().unwrap();
+        let reused = garbage[COUNT_OF_KEYS_TO_SWEEP_IN_SINGLE_GC_ITERATION];
+        shared.lock().unwrap().written(std::iter::once(&reused));
+        let written_entry = {
+            let entry = Entry::Blob(vec![0xFF; 8]);
+            let hash = hash_entry(&entry).unwrap();
+            shared.lock().unwrap().written(std::iter::once(&hash));
+            store
+                .put(&hash, &bincode::serialize(&entry).unwrap())
+                .unwrap();
+            hash
+        };
+        written.send(()).unwrap();
+
+        assert!(collector.join().unwrap());
+        assert!(store.contains(&root).unwrap());
+        assert!(store.contains(&reused).unwrap());
+        assert!(store.contains(&written_entry).unwrap());
+        assert_eq!(
+            garbage
+                .iter()
+                .filter(|key| store.contains(key).unwrap())
+                .count(),
+            1
+        );
+        assert_eq!(stats.read().unwrap().swept_entries, garbage.len() - 1);
+    }
+
+    #[test]
+    fn test_concurrent_gc_keeps_roots_after_restart() {
+        let store = InMemoryBackend::new();
+        let key_a_b: ContextKey = vec!["a".to_string(), "b".to_string()];
+        let key_a_c: ContextKey = vec!["a".to_string(), "c".to_string()];
+
+        let (commit_1, commit_2) = {
+            let mut storage = MerkleStorage::new(Box::new(
+                ConcurrentMarkSweepGCed::with_store(store.clone(), 1).unwrap(),
+            ));
+            storage.set(1, &key_a_b, vec![1]).unwrap();
+            storage.set(2, &key_a_c, vec![2]).unwrap();
+            let commit_1 = storage
+                .commit(0, "Tezos".to_string(), "Genesis".to_string())
+                .unwrap();
+            storage.block_applied().unwrap();
+            storage.start_new_cycle().unwrap();
+            wait_for_collections(&storage, 1);
+
+            storage.set(3, &key_a_b, vec![3]).unwrap();
+            let commit_2 = storage
+                .commit(1, "Tezos".to_string(), "".to_string())
+                .unwrap();
+            storage.block_applied().unwrap();
+            (commit_1, commit_2)
+        };
+
+        // restart - roots of the preserved cycles are loaded from the store
+        let mut storage = MerkleStorage::new(Box::new(
+            ConcurrentMarkSweepGCed::with_store(store, 1).unwrap(),
+        ));
+        storage.checkout(&commit_2).unwrap();
+        storage.set(4, &key_a_c, vec![4]).unwrap();
+        storage
+            .commit(2, "Tezos".to_string(), "".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+
+        storage.start_new_cycle().unwrap();
+        let stats = wait_for_collections(&storage, 1);
+        assert_eq!(stats.epoch, 2);
+        assert_eq!(stats.roots, 2);
+        assert!(storage.checkout(&commit_1).is_err());
+        assert_eq!(storage.get_history(&commit_2, &key_a_b).unwrap(), vec![3]);
+        assert_eq!(storage.get_history(&commit_2, &key_a_c).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_concurrent_gc_keeps_reused_subtree_of_in_flight_commit() {
+        let mut storage = MerkleStorage::new(Box::new(
+            ConcurrentMarkSweepGCed::with_store(InMemoryBackend::new(), 1).unwrap(),
+        ));
+        let key_a_b: ContextKey = vec!["a".to_string(), "b".to_string()];
+        let key_a: ContextKey = vec!["a".to_string()];
+        let key_c: ContextKey = vec!["c".to_string()];
+
+        // CYCLE 1
+        storage.set(1, &key_a_b, vec![1]).unwrap();
+        storage.set(2, &key_c, vec![2]).unwrap();
+        let commit_1 = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+        storage.start_new_cycle().unwrap();
+        wait_for_collections(&storage, 1);
+
+        // CYCLE 2 - applied block does not reference subtree `a`
+        storage.checkout(&commit_1).unwrap();
+        storage.delete(1, &key_a).unwrap();
+        storage
+            .commit(1, "Tezos".to_string(), "".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+
+        // commit, which is not applied yet, references subtree `a` of commit_1 (it is not loaded, so not written)
+        storage.checkout(&commit_1).unwrap();
+        storage.set(2, &key_c, vec![3]).unwrap();
+        let commit_2 = storage
+            .commit(2, "Tezos".to_string(), "".to_string())
+            .unwrap();
+
+        // CYCLE 3 - commit_1 is not preserved anymore
+        storage.start_new_cycle().unwrap();
+        let stats = wait_for_collections(&storage, 2);
+        assert!(stats.swept_entries > 0);
+        assert!(storage.checkout(&commit_1).is_err());
+        assert_eq!(storage.get_history(&commit_2, &key_a_b).unwrap(), vec![1]);
+        assert_eq!(storage.get_history(&commit_2, &key_c).unwrap(), vec![3]);
+    }
+}
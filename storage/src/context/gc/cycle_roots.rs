@@ -0,0 +1,223 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Commits applied during the preserved cycles (roots of the garbage collection).
+//!
+//! Roots are persisted in the wrapped context store, so the collector does not sweep entries of the preserved cycles
+//! after restart. State is stored under reserved keys starting with [GC_STATE_KEY_PREFIX]:
+//! - `[prefix(16)][0xFE..(16)]` - epoch (count of started cycles) of the current cycle,
+//! - `[prefix(16)][epoch(8)][chunk(8)]` - commits of the cycle, [COMMITS_IN_CHUNK] commits per key,
+//!   so just the last chunk is rewritten on every applied block.
+//!
+//! Values start with a version byte, so they never start with the magic bytes of the compressed values.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+use crate::context::gc::GarbageCollectionError;
+use crate::context::merkle::hash::{EntryHash, ENTRY_HASH_LEN};
+use crate::context::ContextKeyValueStoreSchema;
+use crate::persistent::KeyValueStoreBackend;
+
+/// Keys of the garbage collector state start with this prefix, it is never swept
+pub const GC_STATE_KEY_PREFIX: [u8; 16] = [0xFE; 16];
+
+/// Key of the epoch of the current cycle
+const EPOCH_KEY: EntryHash = [0xFE; ENTRY_HASH_LEN];
+
+/// Count of commits stored under single key
+pub const COMMITS_IN_CHUNK: usize = 256;
+
+const STATE_VERSION: u8 = 1;
+
+/// Returns true, if the key belongs to the garbage collector state (it is not an entry)
+pub fn is_gc_state_key(key: &EntryHash) -> bool {
+    key[..GC_STATE_KEY_PREFIX.len()] == GC_STATE_KEY_PREFIX
+}
+
+fn roots_key(epoch: u64, chunk: u64) -> EntryHash {
+    let mut key = [0; ENTRY_HASH_LEN];
+    key[..16].copy_from_slice(&GC_STATE_KEY_PREFIX);
+    key[16..24].copy_from_slice(&epoch.to_be_bytes());
+    key[24..].copy_from_slice(&chunk.to_be_bytes());
+    key
+}
+
+fn invalid_state(error: &str) -> GarbageCollectionError {
+    GarbageCollectionError::GarbageCollectorError {
+        error: format!("invalid persisted garbage collector state: {}", error),
+    }
+}
+
+/// Commits of the preserved cycles, older to newer, last one is the current cycle
+pub struct CycleRoots {
+    /// Epoch of the current cycle
+    epoch: u64,
+    /// Count of kept cycles (including the current one)
+    limit: usize,
+    cycles: VecDeque<Vec<EntryHash>>,
+}
+
+impl CycleRoots {
+    /// Loads roots of the last `limit` cycles persisted in the store (nothing is loaded for new store)
+    pub fn load(
+        store: &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>,
+        limit: usize,
+    ) -> Result<Self, GarbageCollectionError> {
+        let epoch = match store.get(&EPOCH_KEY)? {
+            Some(value) => match value.split_first() {
+                Some((&STATE_VERSION, epoch)) => u64::from_be_bytes(
+                    epoch
+                        .try_into()
+                        .map_err(|_| invalid_state("epoch has invalid length"))?,
+                ),
+                _ => return Err(invalid_state("unsupported version of epoch")),
+            },
+            None => 0,
+        };
+
+        let first_epoch = epoch.saturating_sub(limit as u64 - 1);
+        let mut cycles: VecDeque<Vec<EntryHash>> = (0..limit - (epoch - first_epoch) as usize - 1)
+            .map(|_| Vec::new())
+            .collect();
+        for cycle_epoch in first_epoch..=epoch {
+            let mut commits = Vec::new();
+            for chunk in 0.. {
+                match store.get(&roots_key(cycle_epoch, chunk))? {
+                    Some(value) => match value.split_first() {
+                        Some((&STATE_VERSION, hashes)) if hashes.len() % ENTRY_HASH_LEN == 0 => {
+                            commits.extend(
+                                hashes
+                                    .chunks(ENTRY_HASH_LEN)
+                                    .map(|hash| hash.try_into())
+                                    .collect::<Result<Vec<EntryHash>, _>>()?,
+                            );
+                        }
+                        _ => return Err(invalid_state("unsupported version of roots")),
+                    },
+                    None => break,
+                }
+            }
+            cycles.push_back(commits);
+        }
+
+        Ok(Self {
+            epoch,
+            limit,
+            cycles,
+        })
+    }
+
+    /// Epoch (count of started cycles) of the current cycle
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Commits of the kept cycles, older to newer
+    pub fn cycles(&self) -> impl Iterator<Item = &Vec<EntryHash>> {
+        self.cycles.iter()
+    }
+
+    /// Count of all kept commits
+    pub fn len(&self) -> usize {
+        self.cycles.iter().map(|commits| commits.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cycles.iter().all(|commits| commits.is_empty())
+    }
+
+    /// Adds applied commit to the current cycle and persists it
+    pub fn add(
+        &mut self,
+        store: &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>,
+        commit: EntryHash,
+    ) -> Result<(), GarbageCollectionError> {
+        let commits = match self.cycles.back_mut() {
+            Some(commits) => commits,
+            None => return Ok(()),
+        };
+        commits.push(commit);
+
+        let chunk = (commits.len() - 1) / COMMITS_IN_CHUNK;
+        let mut value = Vec::with_capacity(1 + COMMITS_IN_CHUNK * ENTRY_HASH_LEN);
+        value.push(STATE_VERSION);
+        for commit in &commits[chunk * COMMITS_IN_CHUNK..] {
+            value.extend_from_slice(commit);
+        }
+        store.put(&roots_key(self.epoch, chunk as u64), &value)?;
+        Ok(())
+    }
+
+    /// Starts a new (empty) cycle and forgets the oldest ones over the limit, returns count of the forgotten cycles
+    pub fn start_cycle(
+        &mut self,
+        store: &dyn KeyValueStoreBackend<ContextKeyValueStoreSchema>,
+    ) -> Result<usize, GarbageCollectionError> {
+        self.epoch += 1;
+        let mut value = Vec::with_capacity(9);
+        value.push(STATE_VERSION);
+        value.extend_from_slice(&self.epoch.to_be_bytes());
+        store.put(&EPOCH_KEY, &value)?;
+        self.cycles.push_back(Vec::new());
+
+        let mut dropped = 0;
+        while self.cycles.len() > self.limit {
+            // epoch of the oldest cycle (cycles before the first epoch are always empty)
+            let dropped_epoch = (self.epoch + 1).saturating_sub(self.cycles.len() as u64);
+            if let Some(commits) = self.cycles.pop_front() {
+                for chunk in 0..(commits.len() + COMMITS_IN_CHUNK - 1) / COMMITS_IN_CHUNK {
+                    store.delete(&roots_key(dropped_epoch, chunk as u64))?;
+                }
+            }
+            dropped += 1;
+        }
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+
+    use super::*;
+
+    #[test]
+    fn test_cycle_roots_are_reloaded() -> Result<(), GarbageCollectionError> {
+        let store = InMemoryBackend::new();
+        let commit = |i: usize| -> EntryHash {
+            let mut hash = [0; ENTRY_HASH_LEN];
+            hash[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            hash
+        };
+
+        let mut roots = CycleRoots::load(&store, 3)?;
+        assert!(roots.is_empty());
+        for i in 0..COMMITS_IN_CHUNK + 10 {
+            roots.add(&store, commit(i))?;
+        }
+        assert_eq!(roots.start_cycle(&store)?, 1);
+        roots.add(&store, commit(1000))?;
+
+        let reloaded = CycleRoots::load(&store, 3)?;
+        assert_eq!(reloaded.epoch(), 1);
+        assert_eq!(reloaded.len(), COMMITS_IN_CHUNK + 11);
+        assert_eq!(
+            reloaded.cycles().cloned().collect::<Vec<_>>(),
+            roots.cycles().cloned().collect::<Vec<_>>()
+        );
+
+        // the oldest cycle is forgotten also in the store
+        assert_eq!(roots.start_cycle(&store)?, 1);
+        assert_eq!(roots.start_cycle(&store)?, 1);
+        assert!(!store.contains(&roots_key(0, 0))?);
+        assert!(!store.contains(&roots_key(0, 1))?);
+        let reloaded = CycleRoots::load(&store, 3)?;
+        assert_eq!(reloaded.epoch(), 3);
+        assert_eq!(
+            reloaded.cycles().cloned().collect::<Vec<_>>(),
+            vec![vec![commit(1000)], vec![], vec![]]
+        );
+        Ok(())
+    }
+}
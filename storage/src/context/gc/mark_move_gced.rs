@@ -15,6 +15,7 @@ use crypto::hash::HashType;
 use crate::context::gc::{
     collect_hashes_recursively, fetch_entry_from_store, GarbageCollectionError, GarbageCollector,
 };
+use crate::context::kv_store::compressed::CompressionStats;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
@@ -231,9 +232,13 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Send + Sync
         Ok(memory?.iter().sum::<usize>() + self.current.total_get_mem_usage()?)
     }
 
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
         self.current.retain(predicate)
     }
+
+    fn compression_stats(&self) -> Option<CompressionStats> {
+        self.current.compression_stats()
+    }
 }
 
 /// Garbage collector main function
@@ -6,61 +6,114 @@ use std::collections::{HashSet, VecDeque};
 
 use crypto::hash::HashType;
 
+use crate::context::gc::cycle_roots::{is_gc_state_key, CycleRoots};
 use crate::context::gc::{
     collect_hashes, fetch_entry_from_store, GarbageCollectionError, GarbageCollector,
 };
+use crate::context::kv_store::compressed::CompressionStats;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
 use crate::persistent::database::DBError;
-use crate::persistent::KeyValueStoreBackend;
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+};
+
+/// Entries used by commits of a single cycle
+struct CycleEntries {
+    entries: HashSet<EntryHash>,
+    /// Commits were (partially) applied by the previous run, so entries must be collected from persisted roots
+    restored: bool,
+}
+
+impl CycleEntries {
+    fn new(restored: bool) -> Self {
+        Self {
+            entries: HashSet::new(),
+            restored,
+        }
+    }
+}
 
 /// Garbage Collected Key Value Store
 pub struct MarkSweepGCed<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
-    // TODO: rework gc store must be passed as argument, and not to create default here
     store: T,
     cycles_limit: usize,
-    cycles: VecDeque<HashSet<EntryHash>>,
+    cycles: VecDeque<CycleEntries>,
+    roots: CycleRoots,
     cache: HashMap<EntryHash, HashSet<EntryHash>>,
 }
 
-impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> MarkSweepGCed<T> {
-    pub fn new(cycle_count: usize) -> Self {
-        let mut cycles = VecDeque::new();
-
-        for _ in 0..cycle_count + 1 {
-            //one extra buffer "current"
-            cycles.push_back(HashSet::new());
-        }
+impl<
+        T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector + Default,
+    > MarkSweepGCed<T>
+{
+    pub fn new(cycle_count: usize) -> Result<Self, GarbageCollectionError> {
+        Self::with_store(Default::default(), cycle_count)
+    }
+}
 
-        Self {
-            // TODO: zrusti default
-            store: Default::default(),
-            cycles_limit: cycle_count + 1,
+impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector>
+    MarkSweepGCed<T>
+{
+    /// Wraps already initialized store, entries not used by commits in last `cycle_count` cycles are removed.
+    ///
+    /// Roots of the preserved cycles persisted by the previous run are loaded from the store.
+    pub fn with_store(store: T, cycle_count: usize) -> Result<Self, GarbageCollectionError> {
+        //one extra buffer "current"
+        let cycles_limit = cycle_count + 1;
+        let roots = CycleRoots::load(&store, cycles_limit)?;
+        let cycles = roots
+            .cycles()
+            .map(|commits| CycleEntries::new(!commits.is_empty()))
+            .collect();
+
+        Ok(Self {
+            store,
+            cycles_limit,
             cycles,
+            roots,
             cache: HashMap::new(),
-        }
+        })
     }
 
     fn mark_reused(&mut self, reused_keys: HashSet<EntryHash>) {
         if let Some(cycle) = self.cycles.back_mut() {
-            cycle.extend(reused_keys);
+            cycle.entries.extend(reused_keys);
         }
     }
 
     pub fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
-        self.cycles.push_back(HashSet::new());
+        self.roots.start_cycle(&self.store)?;
+        self.cycles.push_back(CycleEntries::new(false));
 
         while self.cycles.len() > self.cycles_limit {
             let _ = self.cycles.pop_front();
         }
 
+        // entries of the commits applied before restart are not known, collect them from the roots
+        for (cycle, commits) in self.cycles.iter_mut().zip(self.roots.cycles()) {
+            if cycle.restored {
+                for commit in commits {
+                    let commit_entry = fetch_entry_from_store(&self.store, *commit)?;
+                    collect_hashes(
+                        &commit_entry,
+                        &mut cycle.entries,
+                        &mut self.cache,
+                        &self.store,
+                    )?;
+                }
+                cycle.restored = false;
+            }
+        }
+
         let mut entries_in_use = HashSet::new();
-        for entries in self.cycles.iter() {
-            entries_in_use.extend(entries);
+        for cycle in self.cycles.iter() {
+            entries_in_use.extend(&cycle.entries);
         }
 
         self.sweep_entries(entries_in_use)?;
+        self.store.compact()?;
 
         Ok(())
     }
@@ -83,8 +136,8 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> Ma
 
                 // remove keys non used in current block
                 self.cache.retain(|k, _| entries.contains(k));
-                self.mark_reused(entries.into_iter().collect());
-                Ok(())
+                self.mark_reused(entries);
+                self.roots.add(&self.store, commit)
             }
             _ => Err(GarbageCollectionError::GarbageCollectorError {
                 error: format!(
@@ -96,8 +149,8 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> Ma
     }
 }
 
-impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> GarbageCollector
-    for MarkSweepGCed<T>
+impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector>
+    GarbageCollector for MarkSweepGCed<T>
 {
     fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
         self.new_cycle_started()
@@ -106,9 +159,13 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default> Ga
     fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
         self.store_entries_referenced_by_commit(commit)
     }
+
+    fn cycle_epoch(&self) -> Option<u64> {
+        Some(self.roots.epoch())
+    }
 }
 
-impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
+impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema>>
     KeyValueStoreBackend<ContextKeyValueStoreSchema> for MarkSweepGCed<T>
 {
     fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
@@ -139,7 +196,7 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
         Ok(self
             .cycles
             .iter()
-            .map(|set| set.len() * std::mem::size_of::<EntryHash>() as usize)
+            .map(|cycle| cycle.entries.len() * std::mem::size_of::<EntryHash>() as usize)
             .sum::<usize>()
             + self.store.total_get_mem_usage()?
             + self
@@ -152,8 +209,41 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
                 .sum::<usize>())
     }
 
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        self.store.retain(predicate)
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        self.store
+            .retain(&|key| is_gc_state_key(key) || predicate(key))
+    }
+
+    fn compression_stats(&self) -> Option<CompressionStats> {
+        self.store.compression_stats()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Flushable> Flushable
+    for MarkSweepGCed<T>
+{
+    fn flush(&self) -> Result<(), failure::Error> {
+        self.store.flush()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Persistable> Persistable
+    for MarkSweepGCed<T>
+{
+    fn is_persistent(&self) -> bool {
+        self.store.is_persistent()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + MultiInstanceable> MultiInstanceable
+    for MarkSweepGCed<T>
+{
+    fn supports_multiple_opened_instances(&self) -> bool {
+        self.store.supports_multiple_opened_instances()
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.store.sync_with_primary()
     }
 }
 
@@ -161,7 +251,9 @@ impl<T: 'static + KeyValueStoreBackend<ContextKeyValueStoreSchema> + Default>
 mod tests {
     use crate::context::kv_store::in_memory_backend::InMemoryBackend;
     use crate::context::merkle::hash::hash_entry;
+    use crate::context::merkle::merkle_storage::MerkleStorage;
     use crate::context::merkle::Entry;
+    use crate::context::ContextKey;
 
     use super::*;
 
@@ -176,7 +268,7 @@ mod tests {
         let value_7 = Entry::Blob(vec![7]);
         let value_8 = Entry::Blob(vec![8]);
 
-        let mut store = MarkSweepGCed::<InMemoryBackend>::new(4);
+        let mut store = MarkSweepGCed::<InMemoryBackend>::new(4).unwrap();
         // CYCLE 1
         store
             .put(
@@ -419,4 +511,79 @@ mod tests {
             .unwrap()
             .is_empty());
     }
+
+    #[test]
+    fn test_mark_sweep_gc_with_merkle_storage() {
+        let mut storage = MerkleStorage::new(Box::new(
+            MarkSweepGCed::with_store(InMemoryBackend::new(), 1).unwrap(),
+        ));
+        let key_a_b: ContextKey = vec!["a".to_string(), "b".to_string()];
+        let key_a_c: ContextKey = vec!["a".to_string(), "c".to_string()];
+
+        // CYCLE 1
+        storage.set(1, &key_a_b, vec![1]).unwrap();
+        storage.set(2, &key_a_c, vec![2]).unwrap();
+        let commit_1 = storage
+            .commit(0, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+        storage.start_new_cycle().unwrap();
+
+        // CYCLE 2
+        storage.set(3, &key_a_b, vec![3]).unwrap();
+        let commit_2 = storage
+            .commit(1, "Tezos".to_string(), "".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+        assert_eq!(storage.get_history(&commit_1, &key_a_b).unwrap(), vec![1]);
+
+        // CYCLE 3 - entries used just by commit_1 are removed
+        storage.start_new_cycle().unwrap();
+        assert!(storage.checkout(&commit_1).is_err());
+        assert_eq!(storage.get_history(&commit_2, &key_a_b).unwrap(), vec![3]);
+        assert_eq!(storage.get_history(&commit_2, &key_a_c).unwrap(), vec![2]);
+    }
+    #[test]
+    fn test_mark_sweep_gc_keeps_roots_after_restart() {
+        let store = InMemoryBackend::new();
+        let key_a_b: ContextKey = vec!["a".to_string(), "b".to_string()];
+        let key_a_c: ContextKey = vec!["a".to_string(), "c".to_string()];
+
+        let (commit_1, commit_2) = {
+            let mut storage = MerkleStorage::new(Box::new(
+                MarkSweepGCed::with_store(store.clone(), 1).unwrap(),
+            ));
+            storage.set(1, &key_a_b, vec![1]).unwrap();
+            storage.set(2, &key_a_c, vec![2]).unwrap();
+            let commit_1 = storage
+                .commit(0, "Tezos".to_string(), "Genesis".to_string())
+                .unwrap();
+            storage.block_applied().unwrap();
+            storage.start_new_cycle().unwrap();
+
+            storage.set(3, &key_a_b, vec![3]).unwrap();
+            let commit_2 = storage
+                .commit(1, "Tezos".to_string(), "".to_string())
+                .unwrap();
+            storage.block_applied().unwrap();
+            (commit_1, commit_2)
+        };
+
+        // restart - roots of the preserved cycles are loaded from the store
+        let mut storage =
+            MerkleStorage::new(Box::new(MarkSweepGCed::with_store(store, 1).unwrap()));
+        storage.checkout(&commit_2).unwrap();
+        storage.set(4, &key_a_c, vec![4]).unwrap();
+        let commit_3 = storage
+            .commit(2, "Tezos".to_string(), "".to_string())
+            .unwrap();
+        storage.block_applied().unwrap();
+
+        // entries of commit_2 (applied before restart) are kept, commit_1 is swept
+        storage.start_new_cycle().unwrap();
+        assert!(storage.checkout(&commit_1).is_err());
+        assert_eq!(storage.get_history(&commit_2, &key_a_b).unwrap(), vec![3]);
+        assert_eq!(storage.get_history(&commit_2, &key_a_c).unwrap(), vec![2]);
+        assert_eq!(storage.get_history(&commit_3, &key_a_c).unwrap(), vec![4]);
+    }
 }
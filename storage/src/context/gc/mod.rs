@@ -5,10 +5,13 @@
 
 use std::array::TryFromSliceError;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::PoisonError;
 
 use blake2::digest::InvalidOutputSize;
 use failure::Fail;
+use serde::Serialize;
 
 use crypto::hash::{FromBytesError, HashType};
 
@@ -17,6 +20,8 @@ use crate::context::merkle::Entry;
 use crate::context::{ContextKeyValueStoreSchema, EntryHash};
 use crate::persistent::{DBError, KeyValueStoreBackend};
 
+pub mod concurrent_mark_sweep_gced;
+pub mod cycle_roots;
 pub mod mark_move_gced;
 pub mod mark_sweep_gced;
 
@@ -24,6 +29,122 @@ pub trait GarbageCollector {
     fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError>;
 
     fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError>;
+
+    /// Entries referenced by the new commit without being written (unchanged subtrees of the checked out context),
+    /// collector running concurrently with block application must not sweep them (with their subtrees)
+    fn entries_reused(&self, _keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        Ok(())
+    }
+
+    /// Entries used from the cache (without reading them from the store),
+    /// collector running concurrently with block application must not sweep them
+    fn entries_read(&self, _keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        Ok(())
+    }
+
+    /// Progress of the garbage collection, `None` if collector does not report it
+    fn gc_stats(&self) -> Option<GcStats> {
+        None
+    }
+
+    /// Epoch (count of started cycles) of the current cycle restored from the persisted roots,
+    /// `None` if collector does not persist its roots
+    fn cycle_epoch(&self) -> Option<u64> {
+        None
+    }
+
+    /// Reclaims space of the entries removed by garbage collector (e.g. compacts files of the store),
+    /// collectors wrapping the store call it after sweep
+    fn compact(&self) -> Result<(), GarbageCollectionError> {
+        Ok(())
+    }
+}
+
+/// Garbage collector used for context store in non-archive history modes
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ContextGc {
+    /// Marks and sweeps in a background thread, block application is not blocked
+    Concurrent,
+    /// Marks on every applied block and sweeps on the block starting a new cycle
+    MarkSweep,
+    /// Context entries are never removed
+    Disabled,
+}
+
+impl ContextGc {
+    pub fn possible_values() -> Vec<&'static str> {
+        vec!["concurrent", "mark-sweep", "disabled"]
+    }
+}
+
+impl Default for ContextGc {
+    fn default() -> Self {
+        ContextGc::Concurrent
+    }
+}
+
+impl fmt::Display for ContextGc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextGc::Concurrent => write!(f, "concurrent"),
+            ContextGc::MarkSweep => write!(f, "mark-sweep"),
+            ContextGc::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseContextGcError(String);
+
+impl fmt::Display for ParseContextGcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid context gc: {}", self.0)
+    }
+}
+
+impl FromStr for ContextGc {
+    type Err = ParseContextGcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "concurrent" => Ok(ContextGc::Concurrent),
+            "mark-sweep" => Ok(ContextGc::MarkSweep),
+            "disabled" => Ok(ContextGc::Disabled),
+            _ => Err(ParseContextGcError(s.to_string())),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcPhase {
+    Idle,
+    Marking,
+    Sweeping,
+    Compacting,
+}
+
+impl Default for GcPhase {
+    fn default() -> Self {
+        GcPhase::Idle
+    }
+}
+
+/// Progress of the garbage collection
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct GcStats {
+    /// Epoch (count of started cycles) of the running (or the last) collection
+    pub epoch: u64,
+    pub phase: GcPhase,
+    /// Count of commits, from which the running (or the last) collection marked entries
+    pub roots: usize,
+    pub marked_entries: usize,
+    pub swept_entries: usize,
+    pub reclaimed_bytes: u64,
+    pub finished_collections: u64,
+    /// Bytes reclaimed by all finished collections
+    pub total_reclaimed_bytes: u64,
+    /// Failure of the last collection, nothing is swept by failed collection
+    pub last_error: Option<String>,
 }
 
 pub trait NotGarbageCollected {}
@@ -89,8 +210,15 @@ pub fn collect_hashes(
                             .entry
                             .try_borrow()
                             .map_err(|_| HashingError::EntryBorrow)?;
-                        let entry = entry.as_ref().ok_or(HashingError::MissingEntry)?;
-                        collect_hashes(entry, &mut b, cache, store)?;
+                        match entry.as_ref() {
+                            Some(entry) => collect_hashes(entry, &mut b, cache, store)?,
+                            None => {
+                                // tree was loaded from store, so child entries are not loaded yet
+                                let entry =
+                                    fetch_entry_from_store(store, child_node.entry_hash()?)?;
+                                collect_hashes(&entry, &mut b, cache, store)?
+                            }
+                        }
                     }
                     cache.insert(hash_entry(entry)?, b.clone());
                     batch.extend(b);
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
 use std::ops::{AddAssign, DerefMut, SubAssign};
 use std::sync::RwLock;
 
@@ -10,7 +11,10 @@ use crate::context::kv_store::stats::StorageBackendStats;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
 use crate::persistent::database::DBError;
-use crate::persistent::{Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable};
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable,
+    COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK,
+};
 
 /// In Memory Key Value Store implemented with [BTreeMap](std::collections::BTreeMap)
 #[derive(Debug)]
@@ -102,18 +106,44 @@ impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for BTreeMapBackend<EntryH
         Ok(self.kv_map.read()?.contains_key(key))
     }
 
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        let garbage_keys: Vec<_> = self
-            .kv_map
-            .read()?
-            .iter()
-            .filter_map(|(k, _)| if !predicate(k) { Some(*k) } else { None })
-            .collect();
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        let mut removed_bytes = 0;
+        let mut last_checked: Option<EntryHash> = None;
+        loop {
+            let chunk: Vec<EntryHash> = self
+                .kv_map
+                .read()?
+                .range((last_checked.map_or(Unbounded, Excluded), Unbounded))
+                .take(COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK)
+                .map(|(key, _)| *key)
+                .collect();
+            last_checked = match chunk.last() {
+                Some(key) => Some(*key),
+                None => break,
+            };
+
+            let rejected: Vec<EntryHash> =
+                chunk.into_iter().filter(|key| !predicate(key)).collect();
+            if !rejected.is_empty() {
+                removed_bytes += self.delete_batch(&rejected)?;
+            }
+        }
+        Ok(removed_bytes)
+    }
 
-        for k in garbage_keys {
-            self.delete(&k)?;
+    fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+        let mut removed_bytes = 0;
+        let mut kv_map = self.kv_map.write()?;
+        let mut stats = self.stats.write()?;
+        for key in keys {
+            if let Some(value) = kv_map.remove(key) {
+                stats
+                    .deref_mut()
+                    .sub_assign(StorageBackendStats::from((key, &value)));
+                removed_bytes += (key.len() + value.len()) as u64;
+            }
         }
-        Ok(())
+        Ok(removed_bytes)
     }
 
     fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
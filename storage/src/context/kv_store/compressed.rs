@@ -0,0 +1,546 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Context store wrapper, which compresses entries above a size threshold with zstd.
+//!
+//! Entries are compressed just in the store, so [EntryHash] (computed from the entry) is not affected.
+//! Compressed values are stored with a header `[magic(1)][uncompressed_len(4)][zstd block]`,
+//! smaller values are stored untouched. Serialized entries never start with the magic bytes
+//! (first byte is a bincode enum variant), so already stored uncompressed values are still readable.
+//!
+//! Values compressed before enough samples are collected are compressed without dictionary,
+//! then a dictionary is trained on the collected samples (on background thread, so writes are not blocked)
+//! and stored under [DICTIONARY_KEY] by the first write after the training is finished.
+
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+
+use serde::Serialize;
+use zstd::block::{Compressor, Decompressor};
+
+use crate::context::gc::{GarbageCollectionError, GarbageCollector, GcStats};
+use crate::context::merkle::hash::EntryHash;
+use crate::context::{ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+};
+
+/// Key of the trained dictionary, it is kept by garbage collection
+pub const DICTIONARY_KEY: EntryHash = [0xFF; 32];
+
+const MAGIC_COMPRESSED: u8 = 0xC0;
+const MAGIC_COMPRESSED_WITH_DICTIONARY: u8 = 0xC1;
+const HEADER_LEN: usize = 5;
+
+const ZSTD_LEVEL: i32 = 3;
+/// Max size of the trained dictionary
+const DICTIONARY_MAX_SIZE: usize = 112 * 1024;
+/// Dictionary is trained, when samples of this size are collected (default)
+const DICTIONARY_SAMPLES_SIZE: usize = 8 * 1024 * 1024;
+
+/// Compression of context entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextCompression {
+    /// Entries smaller than threshold (in bytes) are stored uncompressed
+    pub threshold: usize,
+}
+
+impl ContextCompression {
+    pub const DEFAULT_THRESHOLD: usize = 256;
+}
+
+/// Savings of the context entries compression (since the store was opened)
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Count of entries stored compressed
+    pub compressed_entries: u64,
+    /// Size of the compressed entries before compression
+    pub uncompressed_bytes: u64,
+    /// Size of the compressed entries as stored (including header)
+    pub compressed_bytes: u64,
+    /// Size of the trained dictionary (if already trained)
+    pub dictionary_size: Option<usize>,
+}
+
+struct Dictionary {
+    compressor: Mutex<Compressor>,
+    decompressor: Mutex<Decompressor>,
+    size: usize,
+}
+
+impl Dictionary {
+    fn new(dictionary: Vec<u8>) -> Self {
+        Self {
+            size: dictionary.len(),
+            compressor: Mutex::new(Compressor::with_dict(dictionary.clone())),
+            decompressor: Mutex::new(Decompressor::with_dict(dictionary)),
+        }
+    }
+}
+
+/// Samples for dictionary training
+struct Samples {
+    samples: Vec<Vec<u8>>,
+    size: usize,
+}
+
+/// State of the dictionary training
+enum Training {
+    /// Samples are collected till there is enough of them
+    Collecting(Samples),
+    /// Dictionary is trained on background thread, `None` is received if training failed
+    Running(Receiver<Option<Vec<u8>>>),
+}
+
+/// Context store with values compressed (when enabled by [ContextCompression]) and decompressed
+pub struct CompressedKeyValueStore<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
+    store: T,
+    compression: Option<ContextCompression>,
+    dictionary: RwLock<Option<Dictionary>>,
+    /// Dictionary training, `None` after the dictionary was trained (or loaded)
+    training: Mutex<Option<Training>>,
+    /// Dictionary is trained, when samples of this size are collected
+    dictionary_samples_size: usize,
+    compressed_entries: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> CompressedKeyValueStore<T> {
+    /// Wraps the store, values are compressed just if `compression` is set, but compressed values are always readable
+    pub fn with_store(store: T, compression: Option<ContextCompression>) -> Result<Self, DBError> {
+        let dictionary = store.get(&DICTIONARY_KEY)?.map(Dictionary::new);
+        let training = match (&compression, &dictionary) {
+            (Some(_), None) => Some(Training::Collecting(Samples {
+                samples: Vec::new(),
+                size: 0,
+            })),
+            _ => None,
+        };
+
+        Ok(Self {
+            store,
+            compression,
+            dictionary: RwLock::new(dictionary),
+            training: Mutex::new(training),
+            dictionary_samples_size: DICTIONARY_SAMPLES_SIZE,
+            compressed_entries: AtomicU64::new(0),
+            uncompressed_bytes: AtomicU64::new(0),
+            compressed_bytes: AtomicU64::new(0),
+        })
+    }
+
+    fn compress(&self, value: &ContextValue) -> Result<Option<ContextValue>, DBError> {
+        match self.compression {
+            Some(ContextCompression { threshold }) if value.len() >= threshold => (),
+            _ => return Ok(None),
+        }
+
+        let compressed_with_dictionary = match self.dictionary.read()?.as_ref() {
+            Some(dictionary) => Some(dictionary.compressor.lock()?.compress(value, ZSTD_LEVEL)?),
+            None => None,
+        };
+        let (magic, compressed) = match compressed_with_dictionary {
+            Some(compressed) => (MAGIC_COMPRESSED_WITH_DICTIONARY, compressed),
+            None => {
+                // dictionary read lock has to be released, because the trained dictionary can be installed here
+                self.install_trained_dictionary()?;
+                self.add_sample(value)?;
+                (MAGIC_COMPRESSED, zstd::block::compress(value, ZSTD_LEVEL)?)
+            }
+        };
+
+        // incompressible values are stored as they are
+        if compressed.len() + HEADER_LEN >= value.len() {
+            return Ok(None);
+        }
+
+        let mut stored = Vec::with_capacity(HEADER_LEN + compressed.len());
+        stored.push(magic);
+        stored.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        stored.extend_from_slice(&compressed);
+
+        self.compressed_entries.fetch_add(1, Ordering::Relaxed);
+        self.uncompressed_bytes
+            .fetch_add(value.len() as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(stored.len() as u64, Ordering::Relaxed);
+        Ok(Some(stored))
+    }
+
+    /// Collects sample for dictionary training, when there is enough of samples, dictionary training is started on background thread
+    fn add_sample(&self, value: &[u8]) -> Result<(), DBError> {
+        let mut training = self.training.lock()?;
+        let collected = match training.as_mut() {
+            Some(Training::Collecting(collected)) => collected,
+            _ => return Ok(()),
+        };
+        collected.samples.push(value.to_vec());
+        collected.size += value.len();
+        if collected.size < self.dictionary_samples_size {
+            return Ok(());
+        }
+
+        let samples = std::mem::take(&mut collected.samples);
+        let (trained_tx, trained_rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("ctx-dict-training".to_string())
+            .spawn(move || {
+                // training fails e.g. for too few distinct samples, then values are compressed without dictionary
+                let _ =
+                    trained_tx.send(zstd::dict::from_samples(&samples, DICTIONARY_MAX_SIZE).ok());
+            })?;
+        *training = Some(Training::Running(trained_rx));
+        Ok(())
+    }
+
+    /// Stores and starts to use the dictionary, if its training on background thread is finished (does not wait for it)
+    fn install_trained_dictionary(&self) -> Result<(), DBError> {
+        let mut training = self.training.lock()?;
+        let trained = match training.as_ref() {
+            Some(Training::Running(trained_rx)) => match trained_rx.try_recv() {
+                Ok(trained) => trained,
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => None,
+            },
+            _ => return Ok(()),
+        };
+        *training = None;
+
+        if let Some(dictionary) = trained {
+            // dictionary has to be stored before the first value compressed with it
+            self.store.put(&DICTIONARY_KEY, &dictionary)?;
+            *self.dictionary.write()? = Some(Dictionary::new(dictionary));
+        }
+        Ok(())
+    }
+
+    fn decompress(&self, stored: ContextValue) -> Result<ContextValue, DBError> {
+        let magic = match stored.first() {
+            Some(&magic)
+                if magic == MAGIC_COMPRESSED || magic == MAGIC_COMPRESSED_WITH_DICTIONARY =>
+            {
+                magic
+            }
+            _ => return Ok(stored),
+        };
+        if stored.len() < HEADER_LEN {
+            return Err(DBError::CompressionError {
+                reason: format!("compressed value is too short: {} bytes", stored.len()),
+            });
+        }
+        let len = u32::from_be_bytes(stored[1..HEADER_LEN].try_into().map_err(|_| {
+            DBError::CompressionError {
+                reason: "invalid compressed value header".to_string(),
+            }
+        })?) as usize;
+        let compressed = &stored[HEADER_LEN..];
+
+        if magic == MAGIC_COMPRESSED {
+            return Ok(zstd::block::decompress(compressed, len)?);
+        }
+
+        if self.dictionary.read()?.is_none() {
+            // dictionary could be stored by another (primary) process after this store was opened
+            let dictionary =
+                self.store
+                    .get(&DICTIONARY_KEY)?
+                    .ok_or_else(|| DBError::CompressionError {
+                        reason: "dictionary of the compressed value is not stored".to_string(),
+                    })?;
+            let mut current = self.dictionary.write()?;
+            if current.is_none() {
+                *current = Some(Dictionary::new(dictionary));
+            }
+        }
+        match self.dictionary.read()?.as_ref() {
+            Some(dictionary) => Ok(dictionary
+                .decompressor
+                .lock()?
+                .decompress(compressed, len)?),
+            None => Err(DBError::CompressionError {
+                reason: "dictionary of the compressed value is not loaded".to_string(),
+            }),
+        }
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>>
+    KeyValueStoreBackend<ContextKeyValueStoreSchema> for CompressedKeyValueStore<T>
+{
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        match self.compress(value)? {
+            Some(compressed) => self.store.put(key, &compressed),
+            None => self.store.put(key, value),
+        }
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        self.store.delete(key)
+    }
+
+    fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+        self.store.delete_batch(keys)
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        match self.compress(value)? {
+            Some(compressed) => self.store.merge(key, &compressed),
+            None => self.store.merge(key, value),
+        }
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        match self.store.get(key)? {
+            Some(stored) => Ok(Some(self.decompress(stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        self.store.contains(key)
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        let batch = batch
+            .into_iter()
+            .map(|(key, value)| match self.compress(&value) {
+                Ok(Some(compressed)) => Ok((key, compressed)),
+                Ok(None) => Ok((key, value)),
+                Err(e) => Err(e),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.store.write_batch(batch)
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        let samples_size = match self.training.lock()?.as_ref() {
+            Some(Training::Collecting(samples)) => samples.size,
+            _ => 0,
+        };
+        Ok(self.store.total_get_mem_usage()? + samples_size)
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        self.store
+            .retain(&|key| key == &DICTIONARY_KEY || predicate(key))
+    }
+
+    fn compression_stats(&self) -> Option<CompressionStats> {
+        Some(CompressionStats {
+            compressed_entries: self.compressed_entries.load(Ordering::Relaxed),
+            uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+            dictionary_size: self
+                .dictionary
+                .read()
+                .ok()
+                .and_then(|dictionary| dictionary.as_ref().map(|dictionary| dictionary.size)),
+        })
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector> GarbageCollector
+    for CompressedKeyValueStore<T>
+{
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.store.new_cycle_started()
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        self.store.block_applied(commit)
+    }
+
+    fn entries_reused(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        self.store.entries_reused(keys)
+    }
+
+    fn entries_read(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        self.store.entries_read(keys)
+    }
+
+    fn gc_stats(&self) -> Option<GcStats> {
+        self.store.gc_stats()
+    }
+
+    fn cycle_epoch(&self) -> Option<u64> {
+        self.store.cycle_epoch()
+    }
+
+    fn compact(&self) -> Result<(), GarbageCollectionError> {
+        self.store.compact()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Flushable> Flushable
+    for CompressedKeyValueStore<T>
+{
+    fn flush(&self) -> Result<(), failure::Error> {
+        self.install_trained_dictionary()?;
+        self.store.flush()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Persistable> Persistable
+    for CompressedKeyValueStore<T>
+{
+    fn is_persistent(&self) -> bool {
+        self.store.is_persistent()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + MultiInstanceable> MultiInstanceable
+    for CompressedKeyValueStore<T>
+{
+    fn supports_multiple_opened_instances(&self) -> bool {
+        self.store.supports_multiple_opened_instances()
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.store.sync_with_primary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+
+    use super::*;
+
+    fn compressible_value(seed: u8) -> ContextValue {
+        let mut value = vec![0u8; 4];
+        value.extend((0..1024).map(|i| (i % 7) as u8 + seed));
+        value
+    }
+
+    #[test]
+    fn test_compressed_store() -> Result<(), DBError> {
+        let store = CompressedKeyValueStore::with_store(
+            InMemoryBackend::new(),
+            Some(ContextCompression { threshold: 64 }),
+        )?;
+
+        let small = vec![1, 0, 0, 0, 1, 2, 3];
+        store.put(&[1; 32], &small)?;
+        store.put(&[2; 32], &compressible_value(1))?;
+        store.write_batch(vec![([3; 32], compressible_value(2))])?;
+
+        assert_eq!(Some(small), store.get(&[1; 32])?);
+        assert_eq!(Some(compressible_value(1)), store.get(&[2; 32])?);
+        assert_eq!(Some(compressible_value(2)), store.get(&[3; 32])?);
+
+        // small value is stored as it is, big one compressed
+        assert_eq!(7, store.store.get(&[1; 32])?.unwrap().len());
+        let stored = store.store.get(&[2; 32])?.unwrap();
+        assert_eq!(MAGIC_COMPRESSED, stored[0]);
+        assert!(stored.len() < compressible_value(1).len());
+
+        let stats = store.compression_stats().unwrap();
+        assert_eq!(2, stats.compressed_entries);
+        assert_eq!(
+            2 * compressible_value(1).len() as u64,
+            stats.uncompressed_bytes
+        );
+        assert!(stats.compressed_bytes < stats.uncompressed_bytes);
+        assert_eq!(None, stats.dictionary_size);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_with_trained_dictionary() -> Result<(), DBError> {
+        let mut store = CompressedKeyValueStore::with_store(
+            InMemoryBackend::new(),
+            Some(ContextCompression { threshold: 64 }),
+        )?;
+        store.dictionary_samples_size = 256 * 1024;
+
+        let value = |i: u32| -> ContextValue {
+            let mut value = vec![1, 0, 0, 0];
+            value.extend(
+                format!("{{\"prim\":\"Pair\",\"args\":[{{\"int\":\"{}\"}},{{\"string\":\"tz1{:x}\"}}]}}", i, i * 7919)
+                    .repeat(4)
+                    .into_bytes(),
+            );
+            value
+        };
+        let key = |i: u32| -> EntryHash {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.to_be_bytes());
+            key
+        };
+
+        let mut i = 0;
+        while !matches!(*store.training.lock()?, Some(Training::Running(_))) {
+            store.put(&key(i), &value(i))?;
+            i += 1;
+            assert!(i < 10_000, "Dictionary training was not started");
+        }
+
+        // dictionary is trained on background, values are compressed without it meanwhile
+        let started = std::time::Instant::now();
+        while store.compression_stats().unwrap().dictionary_size.is_none() {
+            store.put(&key(i), &value(i))?;
+            i += 1;
+            assert!(
+                started.elapsed() < std::time::Duration::from_secs(60),
+                "Dictionary was not trained"
+            );
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(store.contains(&DICTIONARY_KEY)?);
+
+        store.put(&key(i), &value(i))?;
+        assert_eq!(
+            MAGIC_COMPRESSED_WITH_DICTIONARY,
+            store.store.get(&key(i))?.unwrap()[0]
+        );
+
+        // values compressed with and without dictionary are readable
+        for j in 0..=i {
+            assert_eq!(Some(value(j)), store.get(&key(j))?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_uncompressed_values_are_readable() -> Result<(), DBError> {
+        let backend = InMemoryBackend::new();
+        backend.put(&[1; 32], &compressible_value(1))?;
+
+        let store = CompressedKeyValueStore::with_store(backend, None)?;
+        store.put(&[2; 32], &compressible_value(2))?;
+        assert_eq!(Some(compressible_value(1)), store.get(&[1; 32])?);
+        assert_eq!(Some(compressible_value(2)), store.get(&[2; 32])?);
+        assert_eq!(
+            compressible_value(2).len(),
+            store.store.get(&[2; 32])?.unwrap().len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dictionary_is_kept_by_retain() -> Result<(), DBError> {
+        let dictionary = vec![7; 1024];
+        let backend = InMemoryBackend::new();
+        backend.put(&DICTIONARY_KEY, &dictionary)?;
+        backend.put(&[1; 32], &vec![1])?;
+
+        let store = CompressedKeyValueStore::with_store(
+            backend,
+            Some(ContextCompression { threshold: 64 }),
+        )?;
+        assert_eq!(
+            Some(1024),
+            store.compression_stats().unwrap().dictionary_size
+        );
+
+        store.retain(&|_| false)?;
+        assert!(store.contains(&DICTIONARY_KEY)?);
+        assert!(!store.contains(&[1; 32])?);
+        Ok(())
+    }
+}
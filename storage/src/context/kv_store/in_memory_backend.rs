@@ -11,7 +11,10 @@ use crate::context::kv_store::stats::StorageBackendStats;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::{ContextKeyValueStoreSchema, ContextValue};
 use crate::persistent::database::DBError;
-use crate::persistent::{Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable};
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable,
+    COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK,
+};
 
 #[derive(Default)]
 pub struct HashMapWithStats {
@@ -62,7 +65,7 @@ impl HashMapWithStats {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct InMemoryBackend {
     inner: Arc<RwLock<HashMapWithStats>>,
 }
@@ -78,18 +81,30 @@ impl InMemoryBackend {
 impl NotGarbageCollected for InMemoryBackend {}
 
 impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for InMemoryBackend {
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        let garbage_keys: Vec<_> = self
-            .inner
-            .read()?
+    fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+        let mut w = self.inner.write()?;
+        Ok(keys
             .iter()
-            .filter_map(|(k, _)| if !predicate(k) { Some(*k) } else { None })
-            .collect();
-
-        for k in garbage_keys {
-            self.delete(&k)?;
+            .filter_map(|key| w.remove(key).map(|value| (key.len() + value.len()) as u64))
+            .sum())
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        // hash map cannot be iterated from the last checked key, so keys are collected at first
+        let keys: Vec<EntryHash> = self.inner.read()?.iter().map(|(key, _)| *key).collect();
+
+        let mut removed_bytes = 0;
+        for chunk in keys.chunks(COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK) {
+            let rejected: Vec<EntryHash> = chunk
+                .iter()
+                .filter(|key| !predicate(key))
+                .copied()
+                .collect();
+            if !rejected.is_empty() {
+                removed_bytes += self.delete_batch(&rejected)?;
+            }
         }
-        Ok(())
+        Ok(removed_bytes)
     }
 
     fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
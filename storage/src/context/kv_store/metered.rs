@@ -0,0 +1,194 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Context store wrapper, which records storage metrics (see [crate::persistent::metrics]) of the wrapped store.
+
+use std::time::Instant;
+
+use crate::context::gc::{GarbageCollectionError, GarbageCollector, GcStats};
+use crate::context::merkle::hash::EntryHash;
+use crate::context::{ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::metrics::{store_metrics, StoreKind, StoreMetrics};
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+};
+
+/// Context store with recorded reads, writes and deletes
+pub struct MeteredKeyValueStore<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> {
+    store: T,
+    metrics: &'static StoreMetrics,
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>> MeteredKeyValueStore<T> {
+    /// Wraps the store, metrics are recorded under the `name` of the context store backend
+    pub fn with_store(store: T, name: &'static str) -> Self {
+        Self {
+            store,
+            metrics: store_metrics(StoreKind::ContextStore, name),
+        }
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema>>
+    KeyValueStoreBackend<ContextKeyValueStoreSchema> for MeteredKeyValueStore<T>
+{
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let started = Instant::now();
+        self.store.put(key, value)?;
+        self.metrics
+            .record_write(1, key.len() + value.len(), started);
+        Ok(())
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        let started = Instant::now();
+        self.store.delete(key)?;
+        self.metrics.record_delete(started);
+        Ok(())
+    }
+
+    fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+        let started = Instant::now();
+        let removed_bytes = self.store.delete_batch(keys)?;
+        self.metrics.record_delete(started);
+        Ok(removed_bytes)
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        let started = Instant::now();
+        self.store.merge(key, value)?;
+        self.metrics
+            .record_write(1, key.len() + value.len(), started);
+        Ok(())
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        let started = Instant::now();
+        let value = self.store.get(key)?;
+        self.metrics.record_read(
+            value.as_ref().map(|value| value.len()).unwrap_or(0),
+            started,
+        );
+        Ok(value)
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        let started = Instant::now();
+        let contains = self.store.contains(key)?;
+        self.metrics.record_read(0, started);
+        Ok(contains)
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        let count = batch.len();
+        let bytes = batch
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        let started = Instant::now();
+        self.store.write_batch(batch)?;
+        self.metrics.record_write(count, bytes, started);
+        Ok(())
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        self.store.total_get_mem_usage()
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        self.store.retain(predicate)
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + GarbageCollector> GarbageCollector
+    for MeteredKeyValueStore<T>
+{
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.store.new_cycle_started()
+    }
+
+    fn block_applied(&mut self, commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        self.store.block_applied(commit)
+    }
+
+    fn entries_reused(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        self.store.entries_reused(keys)
+    }
+
+    fn entries_read(&self, keys: &[EntryHash]) -> Result<(), GarbageCollectionError> {
+        self.store.entries_read(keys)
+    }
+
+    fn gc_stats(&self) -> Option<GcStats> {
+        self.store.gc_stats()
+    }
+
+    fn cycle_epoch(&self) -> Option<u64> {
+        self.store.cycle_epoch()
+    }
+
+    fn compact(&self) -> Result<(), GarbageCollectionError> {
+        self.store.compact()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Flushable> Flushable
+    for MeteredKeyValueStore<T>
+{
+    fn flush(&self) -> Result<(), failure::Error> {
+        self.store.flush()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + Persistable> Persistable
+    for MeteredKeyValueStore<T>
+{
+    fn is_persistent(&self) -> bool {
+        self.store.is_persistent()
+    }
+}
+
+impl<T: KeyValueStoreBackend<ContextKeyValueStoreSchema> + MultiInstanceable> MultiInstanceable
+    for MeteredKeyValueStore<T>
+{
+    fn supports_multiple_opened_instances(&self) -> bool {
+        self.store.supports_multiple_opened_instances()
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.store.sync_with_primary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::kv_store::in_memory_backend::InMemoryBackend;
+    use crate::persistent::metrics::store_metrics_snapshot;
+
+    use super::*;
+
+    #[test]
+    fn test_metered_store() -> Result<(), DBError> {
+        let store = MeteredKeyValueStore::with_store(InMemoryBackend::new(), "__test_metered");
+
+        store.put(&[1; 32], &vec![1, 2, 3])?;
+        store.write_batch(vec![([2; 32], vec![1]), ([3; 32], vec![1, 2])])?;
+        assert_eq!(Some(vec![1, 2, 3]), store.get(&[1; 32])?);
+        assert!(store.contains(&[2; 32])?);
+        store.delete(&[3; 32])?;
+
+        let metrics = store_metrics_snapshot()
+            .into_iter()
+            .find(|metrics| metrics.name == "__test_metered")
+            .expect("Store metrics should be registered");
+        assert_eq!(StoreKind::ContextStore, metrics.kind);
+        assert_eq!(2, metrics.reads);
+        assert_eq!(3, metrics.read_bytes);
+        assert_eq!(3, metrics.writes);
+        assert_eq!(3 * 32 + 6, metrics.written_bytes);
+        assert_eq!(1, metrics.deletes);
+        assert_eq!(3, metrics.write_latency.count);
+        Ok(())
+    }
+}
@@ -10,7 +10,10 @@ use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 pub mod btree_map;
+pub mod compressed;
 pub mod in_memory_backend;
+pub mod metered;
+pub mod pack_file_backend;
 pub mod rocksdb_backend;
 pub mod sled_backend;
 pub mod stats;
@@ -23,6 +26,7 @@ pub enum SupportedContextKeyValueStore {
     InMem,
     Sled { path: PathBuf },
     BTreeMap,
+    PackFile { path: PathBuf },
 }
 
 impl SupportedContextKeyValueStore {
@@ -40,6 +44,7 @@ impl SupportedContextKeyValueStore {
             SupportedContextKeyValueStore::InMem => vec!["inmem"],
             SupportedContextKeyValueStore::Sled { .. } => vec!["sled"],
             SupportedContextKeyValueStore::BTreeMap => vec!["btree"],
+            SupportedContextKeyValueStore::PackFile { .. } => vec!["pack"],
         }
     }
 }
@@ -74,9 +79,10 @@ pub mod test_support {
 
     use strum::IntoEnumIterator;
 
+    use crate::context::kv_store::compressed::{CompressedKeyValueStore, ContextCompression};
     use crate::context::merkle::Entry;
-    use crate::context::ContextKeyValueStore;
     use crate::context::EntryHash;
+    use crate::context::{ContextKeyValueStore, ContextKeyValueStoreWithGargbageCollection};
     use crate::persistent::database::RocksDbKeyValueSchema;
     use crate::persistent::{MultiInstanceable, Persistable};
 
@@ -121,6 +127,7 @@ pub mod test_support {
                     },
                     Box::new(RocksDbBackendTestContextKvStoreFactory {
                         base_path: base_dir.clone(),
+                        compression: None,
                     }),
                 ),
                 SupportedContextKeyValueStore::InMem => store_factories.insert(
@@ -133,12 +140,22 @@ pub mod test_support {
                     },
                     Box::new(SledBackendTestContextKvStoreFactory {
                         base_path: base_dir.clone(),
+                        compression: None,
                     }),
                 ),
                 SupportedContextKeyValueStore::BTreeMap => store_factories.insert(
                     SupportedContextKeyValueStore::BTreeMap,
                     Box::new(BTreeMapBackendTestContextKvStoreFactory),
                 ),
+                SupportedContextKeyValueStore::PackFile { .. } => store_factories.insert(
+                    SupportedContextKeyValueStore::PackFile {
+                        path: base_dir.clone(),
+                    },
+                    Box::new(PackFileBackendTestContextKvStoreFactory {
+                        base_path: base_dir.clone(),
+                        compression: None,
+                    }),
+                ),
             };
         }
 
@@ -151,6 +168,65 @@ pub mod test_support {
         store_factories
     }
 
+    /// Persistent kv-stores wrapped with [CompressedKeyValueStore] (registered with the same keys as in [all_kv_stores])
+    pub fn compressed_kv_stores(
+        base_dir: PathBuf,
+    ) -> HashMap<SupportedContextKeyValueStore, TestContextKvStoreFactoryInstance> {
+        let compressed_dir = base_dir.join("compressed");
+        fs::create_dir_all(&compressed_dir).expect("Failed to create dir for compressed kv-stores");
+        // low threshold, so that most of the entries are compressed
+        let compression = Some(ContextCompression { threshold: 16 });
+
+        let mut store_factories: HashMap<
+            SupportedContextKeyValueStore,
+            TestContextKvStoreFactoryInstance,
+        > = HashMap::new();
+        store_factories.insert(
+            SupportedContextKeyValueStore::RocksDB {
+                path: base_dir.clone(),
+            },
+            Box::new(RocksDbBackendTestContextKvStoreFactory {
+                base_path: compressed_dir.clone(),
+                compression,
+            }),
+        );
+        store_factories.insert(
+            SupportedContextKeyValueStore::Sled {
+                path: base_dir.clone(),
+            },
+            Box::new(SledBackendTestContextKvStoreFactory {
+                base_path: compressed_dir.clone(),
+                compression,
+            }),
+        );
+        store_factories.insert(
+            SupportedContextKeyValueStore::PackFile { path: base_dir },
+            Box::new(PackFileBackendTestContextKvStoreFactory {
+                base_path: compressed_dir,
+                compression,
+            }),
+        );
+        store_factories
+    }
+
+    /// Wraps store with [CompressedKeyValueStore], if `compression` is set (read only store just decompresses)
+    fn with_compression<T>(
+        store: T,
+        compression: Option<ContextCompression>,
+        read_only: bool,
+    ) -> Result<Box<ContextKeyValueStore>, TestKeyValueStoreError>
+    where
+        T: ContextKeyValueStoreWithGargbageCollection + Sync + Send + 'static,
+    {
+        match compression {
+            Some(compression) => Ok(Box::new(CompressedKeyValueStore::with_store(
+                store,
+                if read_only { None } else { Some(compression) },
+            )?)),
+            None => Ok(Box::new(store)),
+        }
+    }
+
     pub trait TestContextKvStoreFactory:
         'static + Send + Sync + MultiInstanceable + Persistable
     {
@@ -219,6 +295,7 @@ pub mod test_support {
     /// Sled map kv-store
     pub struct SledBackendTestContextKvStoreFactory {
         base_path: PathBuf,
+        compression: Option<ContextCompression>,
     }
 
     impl SledBackendTestContextKvStoreFactory {
@@ -249,7 +326,7 @@ pub mod test_support {
             db.clear()?;
             db.flush()?;
 
-            Ok(Box::new(SledBackend::new(db)))
+            with_compression(SledBackend::new(db), self.compression, false)
         }
     }
 
@@ -265,9 +342,62 @@ pub mod test_support {
         }
     }
 
+    /// Pack files kv-store
+    pub struct PackFileBackendTestContextKvStoreFactory {
+        base_path: PathBuf,
+        compression: Option<ContextCompression>,
+    }
+
+    impl TestContextKvStoreFactory for PackFileBackendTestContextKvStoreFactory {
+        fn create(&self, name: &str) -> Result<Box<ContextKeyValueStore>, TestKeyValueStoreError> {
+            use crate::context::kv_store::pack_file_backend::PackFileBackend;
+
+            // clear files
+            let db_path = self.base_path.join(format!("pack_{}", name));
+            if Path::new(&db_path).exists() {
+                fs::remove_dir_all(&db_path).map_err(|e| {
+                    failure::format_err!(
+                        "Failed to remove stale pack files directory: {:?}, reason: {}",
+                        db_path,
+                        e
+                    )
+                })?;
+            }
+
+            with_compression(PackFileBackend::open(db_path)?, self.compression, false)
+        }
+
+        fn open_readonly_instance(
+            &self,
+            name: &str,
+        ) -> Result<Box<ContextKeyValueStore>, TestKeyValueStoreError> {
+            use crate::context::kv_store::pack_file_backend::PackFileBackend;
+
+            let db_path = self.base_path.join(format!("pack_{}", name));
+            with_compression(
+                PackFileBackend::open_read_only(db_path)?,
+                self.compression,
+                true,
+            )
+        }
+    }
+
+    impl MultiInstanceable for PackFileBackendTestContextKvStoreFactory {
+        fn supports_multiple_opened_instances(&self) -> bool {
+            true
+        }
+    }
+
+    impl Persistable for PackFileBackendTestContextKvStoreFactory {
+        fn is_persistent(&self) -> bool {
+            true
+        }
+    }
+
     /// Rocksdb map kv-store
     pub struct RocksDbBackendTestContextKvStoreFactory {
         base_path: PathBuf,
+        compression: Option<ContextCompression>,
     }
 
     impl RocksDbBackendTestContextKvStoreFactory {
@@ -332,7 +462,7 @@ pub mod test_support {
             let db = self.db(name, true)?;
             db.flush()?;
 
-            Ok(Box::new(RocksDBBackend::new(Arc::new(db))))
+            with_compression(RocksDBBackend::new(Arc::new(db)), self.compression, false)
         }
 
         fn open_readonly_instance(
@@ -343,7 +473,7 @@ pub mod test_support {
 
             // just open db
             let db = self.db_readonly(name)?;
-            Ok(Box::new(RocksDBBackend::new(Arc::new(db))))
+            with_compression(RocksDBBackend::new(Arc::new(db)), self.compression, true)
         }
     }
 
@@ -0,0 +1,1139 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Append-only, log-structured context store.
+//!
+//! Context entries are immutable and addressed by hash, so they are just appended to segment files
+//! (`<id>.pack`) and in-memory hash index points every key to its latest record. Record layout:
+//!
+//! | crc32 (4) | kind (1) | key (32) | value length (4) | value |
+//!
+//! Checksum covers everything after it. Deleted keys are appended as tombstones (record with empty value).
+//!
+//! Index is checkpointed to `index` file on flush and after compaction, so on open just the records appended after
+//! the checkpoint are replayed. Torn record at the end of the last segment (crash during write) is truncated.
+//! Segments with at least half of dead records are compacted - live records are rewritten to the active segment.
+//!
+//! Store can be opened read-only by another process (storage replica), see [PackFileBackend::open_read_only].
+//! Such instance never modifies files and picks up records appended by the primary process on [MultiInstanceable::sync_with_primary].
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use failure::Error;
+
+use crate::context::gc::{GarbageCollectionError, GarbageCollector};
+use crate::context::merkle::hash::EntryHash;
+use crate::context::{ContextKeyValueStoreSchema, ContextValue};
+use crate::persistent::database::DBError;
+use crate::persistent::{
+    Flushable, KeyValueStoreBackend, MultiInstanceable, MultiInstanceableSyncError, Persistable,
+    COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK,
+};
+
+const RECORD_HEADER_SIZE: usize = 4 + 1 + 32 + 4;
+
+const KIND_PUT: u8 = 0;
+const KIND_DELETE: u8 = 1;
+
+const SEGMENT_EXTENSION: &str = "pack";
+const INDEX_FILE_NAME: &str = "index";
+const INDEX_MAGIC: &[u8; 8] = b"TZPACKIX";
+
+/// Count of bytes of the compacted segment rewritten while holding the write lock
+const COMPACTION_BATCH_SIZE: usize = 4 * 1024 * 1024;
+
+/// Position of the record in segment files
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    /// Length of the value
+    len: u32,
+}
+
+impl Location {
+    fn record_size(&self) -> u64 {
+        RECORD_HEADER_SIZE as u64 + self.len as u64
+    }
+}
+
+struct Segment {
+    file: File,
+    size: u64,
+    /// Bytes of overwritten or deleted records (and tombstones)
+    dead_bytes: u64,
+}
+
+struct Record {
+    kind: u8,
+    key: EntryHash,
+    value: Vec<u8>,
+}
+
+struct PackFiles {
+    index: HashMap<EntryHash, Location>,
+    segments: BTreeMap<u32, Segment>,
+    /// Segment, to which records are appended (always the newest one)
+    active_id: u32,
+    active: File,
+}
+
+/// Append-only context store with segmented pack files and in-memory hash index
+pub struct PackFileBackend {
+    path: PathBuf,
+    segment_size: u64,
+    inner: RwLock<PackFiles>,
+    /// Files are written by another process, see [PackFileBackend::open_read_only]
+    read_only: bool,
+}
+
+impl PackFileBackend {
+    /// New segment is started, when the active one reaches this size
+    pub const DEFAULT_SEGMENT_SIZE: u64 = 256 * 1024 * 1024;
+
+    /// Opens (or creates) store in directory `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DBError> {
+        Self::open_with_segment_size(path, Self::DEFAULT_SEGMENT_SIZE)
+    }
+
+    pub fn open_with_segment_size<P: AsRef<Path>>(
+        path: P,
+        segment_size: u64,
+    ) -> Result<Self, DBError> {
+        Self::open_internal(path.as_ref(), segment_size, false)
+    }
+
+    /// Opens store in directory `path`, which is written by another (primary) process.
+    ///
+    /// Files are never modified, torn record at the end of the last segment is just skipped
+    /// (it can be still written by the primary process). Writes fail with [DBError::ReadOnlyOperation].
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, DBError> {
+        Self::open_internal(path.as_ref(), Self::DEFAULT_SEGMENT_SIZE, true)
+    }
+
+    fn open_internal(path: &Path, segment_size: u64, read_only: bool) -> Result<Self, DBError> {
+        let path = path.to_path_buf();
+        if !read_only {
+            fs::create_dir_all(&path)?;
+        }
+
+        let ids = list_segment_ids(&path)?;
+        let mut segments = BTreeMap::new();
+        for id in &ids {
+            let file = File::open(segment_path(&path, *id))?;
+            let size = file.metadata()?.len();
+            segments.insert(
+                *id,
+                Segment {
+                    file,
+                    size,
+                    dead_bytes: 0,
+                },
+            );
+        }
+
+        // restore index from the checkpoint, so just the records appended after it are replayed
+        let mut index = HashMap::new();
+        let (replay_from_segment, replay_from_offset) =
+            match read_checkpoint(&path.join(INDEX_FILE_NAME), &mut segments)? {
+                Some((checkpointed_index, watermark)) => {
+                    index = checkpointed_index;
+                    watermark
+                }
+                None => (0, 0),
+            };
+
+        let last_id = ids.last().copied();
+        for id in ids.into_iter().filter(|id| *id >= replay_from_segment) {
+            let from = if id == replay_from_segment {
+                replay_from_offset
+            } else {
+                0
+            };
+            let size = segments[&id].size;
+
+            let reader = RecordReader::open(&segment_path(&path, id), from, size)?;
+            let valid_size = replay_records(reader, id, &mut index, &mut segments)?;
+            if valid_size < size {
+                if Some(id) != last_id {
+                    return Err(corrupted(format!(
+                        "invalid record in segment {} at offset {}",
+                        id, valid_size
+                    )));
+                }
+                // torn write at the end of log
+                if !read_only {
+                    OpenOptions::new()
+                        .write(true)
+                        .open(segment_path(&path, id))?
+                        .set_len(valid_size)?;
+                }
+                if let Some(segment) = segments.get_mut(&id) {
+                    segment.size = valid_size;
+                }
+            }
+        }
+
+        let active_id = match (last_id, read_only) {
+            (Some(id), _) => id,
+            (None, false) => {
+                segments.insert(0, create_segment(&path, 0)?);
+                0
+            }
+            (None, true) => {
+                return Err(corrupted(format!(
+                    "no segments found in {:?}, primary store was not initialized",
+                    path
+                )))
+            }
+        };
+        let active = if read_only {
+            File::open(segment_path(&path, active_id))?
+        } else {
+            OpenOptions::new()
+                .append(true)
+                .open(segment_path(&path, active_id))?
+        };
+
+        Ok(Self {
+            path,
+            segment_size,
+            inner: RwLock::new(PackFiles {
+                index,
+                segments,
+                active_id,
+                active,
+            }),
+            read_only,
+        })
+    }
+
+    /// Replays records appended by the primary process since the last call
+    /// and forgets segments removed by its compaction (just for read-only store).
+    fn tail_primary(&self) -> Result<(), DBError> {
+        if !self.read_only {
+            return Err(DBError::ReadOnlyOperation {
+                operation: "tail_primary",
+            });
+        }
+
+        // segments have to be listed first - live records of the compacted segment are rewritten before it is removed
+        let ids = list_segment_ids(&self.path)?;
+        let mut files = self.inner.write()?;
+        let files = &mut *files;
+        let known_active_id = files.active_id;
+        for id in ids.iter().copied().filter(|id| *id > known_active_id) {
+            let file = match File::open(segment_path(&self.path, id)) {
+                Ok(file) => file,
+                // already compacted by the primary process
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            files.active = file.try_clone()?;
+            files.active_id = id;
+            files.segments.insert(
+                id,
+                Segment {
+                    file,
+                    size: 0,
+                    dead_bytes: 0,
+                },
+            );
+        }
+
+        // opened files of segments removed in the meantime are still readable
+        let tailed: Vec<u32> = files
+            .segments
+            .range(known_active_id..)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in tailed {
+            let segment = &files.segments[&id];
+            let from = segment.size;
+            let size = segment.file.metadata()?.len();
+            let reader = RecordReader::from_file(segment.file.try_clone()?, from, size)?;
+            let valid_size = replay_records(reader, id, &mut files.index, &mut files.segments)?;
+            if let Some(segment) = files.segments.get_mut(&id) {
+                segment.size = valid_size;
+            }
+        }
+
+        // live records of removed segments were already rewritten, so the rest are deleted entries
+        let removed: Vec<u32> = files
+            .segments
+            .keys()
+            .filter(|id| !ids.contains(id))
+            .copied()
+            .collect();
+        if !removed.is_empty() {
+            for id in &removed {
+                files.segments.remove(id);
+            }
+            files
+                .index
+                .retain(|_, location| !removed.contains(&location.segment));
+        }
+        Ok(())
+    }
+
+    /// Rewrites live records of segments with at least half of dead bytes to the active segment
+    /// and removes the segment files. Returns count of removed segments.
+    pub fn compact_segments(&self) -> Result<usize, DBError> {
+        if self.read_only {
+            return Ok(0);
+        }
+        let candidates: Vec<(u32, u64)> = {
+            let files = self.inner.read()?;
+            files
+                .segments
+                .iter()
+                .filter(|(id, segment)| {
+                    **id != files.active_id && segment.dead_bytes * 2 >= segment.size
+                })
+                .map(|(id, segment)| (*id, segment.size))
+                .collect()
+        };
+
+        for (id, size) in &candidates {
+            self.compact_segment(*id, *size)?;
+        }
+        Ok(candidates.len())
+    }
+
+    fn compact_segment(&self, id: u32, size: u64) -> Result<(), DBError> {
+        // segment is immutable, so it can be read without lock
+        let mut reader = RecordReader::open(&segment_path(&self.path, id), 0, size)?;
+        loop {
+            let mut batch = Vec::new();
+            let mut batch_size = 0;
+            while batch_size < COMPACTION_BATCH_SIZE {
+                match reader.next()? {
+                    Some((offset, record)) => {
+                        batch_size += RECORD_HEADER_SIZE + record.value.len();
+                        batch.push((offset, record));
+                    }
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            let mut files = self.inner.write()?;
+            let is_oldest = files.segments.keys().next() == Some(&id);
+            let live: Vec<_> = batch
+                .iter()
+                .filter(|(offset, record)| match record.kind {
+                    KIND_PUT => {
+                        files.index.get(&record.key)
+                            == Some(&Location {
+                                segment: id,
+                                offset: *offset,
+                                len: record.value.len() as u32,
+                            })
+                    }
+                    // tombstone still hides the key in older segments
+                    _ => !is_oldest && !files.index.contains_key(&record.key),
+                })
+                .map(|(_, record)| (record.kind, &record.key, record.value.as_slice()))
+                .collect();
+            files.append(&self.path, self.segment_size, live)?;
+        }
+
+        if reader.offset < size {
+            return Err(corrupted(format!(
+                "invalid record in segment {} at offset {}",
+                id, reader.offset
+            )));
+        }
+
+        let mut files = self.inner.write()?;
+        // live records (and segments created for them) must be durable before the old segment is removed
+        files.active.sync_data()?;
+        sync_dir(&self.path)?;
+        files.segments.remove(&id);
+        fs::remove_file(segment_path(&self.path, id))?;
+        sync_dir(&self.path)?;
+        // previous checkpoint points to the removed segment
+        files.write_checkpoint(&self.path)
+    }
+
+    /// Syncs the active segment and writes the checkpoint of the index
+    fn checkpoint(&self) -> Result<(), DBError> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.inner.read()?.write_checkpoint(&self.path)
+    }
+
+    fn append(&self, records: Vec<(u8, &EntryHash, &[u8])>) -> Result<(), DBError> {
+        if self.read_only {
+            return Err(DBError::ReadOnlyOperation {
+                operation: "append",
+            });
+        }
+        self.inner
+            .write()?
+            .append(&self.path, self.segment_size, records)
+    }
+}
+
+impl PackFiles {
+    /// Syncs the active segment and atomically replaces the checkpoint of the index
+    fn write_checkpoint(&self, dir: &Path) -> Result<(), DBError> {
+        self.active.sync_data()?;
+
+        let tmp_path = dir.join(format!("{}.tmp", INDEX_FILE_NAME));
+        let mut writer = ChecksumWriter::new(BufWriter::new(File::create(&tmp_path)?));
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_all(&self.active_id.to_be_bytes())?;
+        writer.write_all(&self.segments[&self.active_id].size.to_be_bytes())?;
+        writer.write_all(&(self.segments.len() as u32).to_be_bytes())?;
+        for (id, segment) in self.segments.iter() {
+            writer.write_all(&id.to_be_bytes())?;
+            writer.write_all(&segment.size.to_be_bytes())?;
+            writer.write_all(&segment.dead_bytes.to_be_bytes())?;
+        }
+        writer.write_all(&(self.index.len() as u64).to_be_bytes())?;
+        for (key, location) in self.index.iter() {
+            writer.write_all(key)?;
+            writer.write_all(&location.segment.to_be_bytes())?;
+            writer.write_all(&location.offset.to_be_bytes())?;
+            writer.write_all(&location.len.to_be_bytes())?;
+        }
+        let checksum = writer.hasher.clone().finalize();
+        let mut file = writer.inner.into_inner().map_err(|e| e.into_error())?;
+        file.write_all(&checksum.to_be_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(tmp_path, dir.join(INDEX_FILE_NAME))?;
+        sync_dir(dir)
+    }
+
+    fn append(
+        &mut self,
+        dir: &Path,
+        segment_size: u64,
+        records: Vec<(u8, &EntryHash, &[u8])>,
+    ) -> Result<(), DBError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let active_id = self.active_id;
+        let base_offset = self.segments[&active_id].size;
+        let mut buffer = Vec::new();
+        let mut locations = Vec::with_capacity(records.len());
+        for (kind, key, value) in records {
+            let offset = base_offset + buffer.len() as u64;
+            encode_record(&mut buffer, kind, key, value);
+            locations.push((
+                kind,
+                *key,
+                Location {
+                    segment: active_id,
+                    offset,
+                    len: value.len() as u32,
+                },
+            ));
+        }
+
+        self.active.write_all(&buffer)?;
+        let size = match self.segments.get_mut(&active_id) {
+            Some(segment) => {
+                segment.size += buffer.len() as u64;
+                segment.size
+            }
+            None => return Err(corrupted(format!("missing segment {}", active_id))),
+        };
+        for (kind, key, location) in locations {
+            apply_record(&mut self.index, &mut self.segments, kind, key, location);
+        }
+
+        if size >= segment_size {
+            self.start_new_segment(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Appends tombstones of the `keys`, returns size of the removed records
+    fn remove_keys(
+        &mut self,
+        dir: &Path,
+        segment_size: u64,
+        keys: &[EntryHash],
+    ) -> Result<u64, DBError> {
+        let removed: Vec<&EntryHash> = keys
+            .iter()
+            .filter(|key| self.index.contains_key(*key))
+            .collect();
+        let removed_bytes = removed
+            .iter()
+            .filter_map(|key| self.index.get(*key))
+            .map(|location| location.record_size())
+            .sum();
+        self.append(
+            dir,
+            segment_size,
+            removed
+                .into_iter()
+                .map(|key| (KIND_DELETE, key, &[][..]))
+                .collect(),
+        )?;
+        Ok(removed_bytes)
+    }
+
+    fn start_new_segment(&mut self, dir: &Path) -> Result<(), DBError> {
+        self.active.sync_data()?;
+
+        let id = self.active_id + 1;
+        self.segments.insert(id, create_segment(dir, id)?);
+        self.active = OpenOptions::new()
+            .append(true)
+            .open(segment_path(dir, id))?;
+        self.active_id = id;
+        Ok(())
+    }
+
+    fn read_value(&self, location: &Location) -> Result<ContextValue, DBError> {
+        let segment = self
+            .segments
+            .get(&location.segment)
+            .ok_or_else(|| corrupted(format!("missing segment {}", location.segment)))?;
+
+        let mut buffer = vec![0; location.record_size() as usize];
+        segment.file.read_exact_at(&mut buffer, location.offset)?;
+        if checksum(&buffer[4..]) != u32::from_be_bytes(buffer[0..4].try_into().unwrap()) {
+            return Err(corrupted(format!(
+                "invalid checksum of record in segment {} at offset {}",
+                location.segment, location.offset
+            )));
+        }
+        Ok(buffer.split_off(RECORD_HEADER_SIZE))
+    }
+}
+
+/// Updates index with the record and counts dead bytes of segments
+fn apply_record(
+    index: &mut HashMap<EntryHash, Location>,
+    segments: &mut BTreeMap<u32, Segment>,
+    kind: u8,
+    key: EntryHash,
+    location: Location,
+) {
+    let previous = match kind {
+        KIND_PUT => index.insert(key, location),
+        _ => {
+            if let Some(segment) = segments.get_mut(&location.segment) {
+                segment.dead_bytes += location.record_size();
+            }
+            index.remove(&key)
+        }
+    };
+    if let Some(previous) = previous {
+        if let Some(segment) = segments.get_mut(&previous.segment) {
+            segment.dead_bytes += previous.record_size();
+        }
+    }
+}
+
+/// Applies records of the segment `id` read by `reader`, returns the end of the last valid record
+fn replay_records(
+    mut reader: RecordReader,
+    id: u32,
+    index: &mut HashMap<EntryHash, Location>,
+    segments: &mut BTreeMap<u32, Segment>,
+) -> Result<u64, DBError> {
+    while let Some((offset, record)) = reader.next()? {
+        let location = Location {
+            segment: id,
+            offset,
+            len: record.value.len() as u32,
+        };
+        apply_record(index, segments, record.kind, record.key, location);
+    }
+    Ok(reader.offset)
+}
+
+fn encode_record(buffer: &mut Vec<u8>, kind: u8, key: &EntryHash, value: &[u8]) {
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; 4]);
+    buffer.push(kind);
+    buffer.extend_from_slice(key);
+    buffer.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(value);
+
+    let checksum = checksum(&buffer[start + 4..]);
+    buffer[start..start + 4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+/// Sequential reader of segment records
+struct RecordReader {
+    reader: BufReader<File>,
+    /// Offset of the next record
+    offset: u64,
+    size: u64,
+}
+
+impl RecordReader {
+    fn open(path: &Path, offset: u64, size: u64) -> Result<Self, DBError> {
+        Self::from_file(File::open(path)?, offset, size)
+    }
+
+    fn from_file(mut file: File, offset: u64, size: u64) -> Result<Self, DBError> {
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            reader: BufReader::with_capacity(1024 * 1024, file),
+            offset,
+            size,
+        })
+    }
+
+    /// Returns the next record with its offset, or `None` at the end of segment or at the first invalid (torn) record
+    fn next(&mut self) -> Result<Option<(u64, Record)>, DBError> {
+        let remaining = self.size.saturating_sub(self.offset);
+        if remaining < RECORD_HEADER_SIZE as u64 {
+            return Ok(None);
+        }
+        let mut header = [0; RECORD_HEADER_SIZE];
+        self.reader.read_exact(&mut header)?;
+
+        let len = u32::from_be_bytes(header[37..41].try_into().unwrap()) as u64;
+        if remaining < RECORD_HEADER_SIZE as u64 + len {
+            return Ok(None);
+        }
+        let mut value = vec![0; len as usize];
+        self.reader.read_exact(&mut value)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&header[4..]);
+        hasher.update(&value);
+        let kind = header[4];
+        if hasher.finalize() != u32::from_be_bytes(header[0..4].try_into().unwrap())
+            || (kind != KIND_PUT && kind != KIND_DELETE)
+        {
+            return Ok(None);
+        }
+
+        let mut key = [0; 32];
+        key.copy_from_slice(&header[5..37]);
+        let offset = self.offset;
+        self.offset += RECORD_HEADER_SIZE as u64 + len;
+        Ok(Some((offset, Record { kind, key, value })))
+    }
+}
+
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads checkpoint of the index, returns `None` if there is no checkpoint or it does not match the segment files.
+///
+/// Also restores dead bytes of checkpointed segments.
+#[allow(clippy::type_complexity)]
+fn read_checkpoint(
+    path: &Path,
+    segments: &mut BTreeMap<u32, Segment>,
+) -> Result<Option<(HashMap<EntryHash, Location>, (u32, u64))>, DBError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if data.len() < INDEX_MAGIC.len() + 4 || &data[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+        return Ok(None);
+    }
+    let (content, stored_checksum) = data.split_at(data.len() - 4);
+    if checksum(content) != u32::from_be_bytes(stored_checksum.try_into().unwrap()) {
+        return Ok(None);
+    }
+
+    let (index, watermark, checkpointed_segments) =
+        match parse_checkpoint(&mut CheckpointReader(&content[INDEX_MAGIC.len()..])) {
+            Some(parsed) => parsed,
+            None => return Ok(None),
+        };
+
+    // segments up to the watermark must be the same as at the time of checkpoint
+    let existing: Vec<u32> = segments.range(..=watermark.0).map(|(id, _)| *id).collect();
+    let matches = existing.len() == checkpointed_segments.len()
+        && checkpointed_segments
+            .iter()
+            .zip(existing.iter())
+            .all(|((id, size, _), existing_id)| {
+                id == existing_id
+                    && match segments.get(id) {
+                        Some(segment) if *id == watermark.0 => segment.size >= *size,
+                        Some(segment) => segment.size == *size,
+                        None => false,
+                    }
+            });
+    if !matches {
+        return Ok(None);
+    }
+
+    for (id, _, dead_bytes) in checkpointed_segments {
+        if let Some(segment) = segments.get_mut(&id) {
+            segment.dead_bytes = dead_bytes;
+        }
+    }
+    Ok(Some((index, watermark)))
+}
+
+struct CheckpointReader<'a>(&'a [u8]);
+
+impl<'a> CheckpointReader<'a> {
+    fn read(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.read(4)?.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.read(8)?.try_into().ok()?))
+    }
+}
+
+/// Parses index, watermark (segment and offset) and segments (id, size, dead bytes) of the checkpoint
+#[allow(clippy::type_complexity)]
+fn parse_checkpoint(
+    reader: &mut CheckpointReader,
+) -> Option<(
+    HashMap<EntryHash, Location>,
+    (u32, u64),
+    Vec<(u32, u64, u64)>,
+)> {
+    let watermark = (reader.read_u32()?, reader.read_u64()?);
+    let segment_count = reader.read_u32()?;
+    let mut segments = Vec::with_capacity(segment_count as usize);
+    for _ in 0..segment_count {
+        segments.push((reader.read_u32()?, reader.read_u64()?, reader.read_u64()?));
+    }
+
+    let entry_count = reader.read_u64()?;
+    let mut index = HashMap::with_capacity(entry_count as usize);
+    for _ in 0..entry_count {
+        let key: EntryHash = reader.read(32)?.try_into().ok()?;
+        let location = Location {
+            segment: reader.read_u32()?,
+            offset: reader.read_u64()?,
+            len: reader.read_u32()?,
+        };
+        index.insert(key, location);
+    }
+    Some((index, watermark, segments))
+}
+
+/// Ids of segments in directory `dir` (sorted)
+fn list_segment_ids(dir: &Path) -> Result<Vec<u32>, DBError> {
+    let mut ids = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        if let Some(id) = parse_segment_id(&dir_entry?.path()) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:08}.{}", id, SEGMENT_EXTENSION))
+}
+
+fn parse_segment_id(path: &Path) -> Option<u32> {
+    if path.extension()? != SEGMENT_EXTENSION {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn create_segment(dir: &Path, id: u32) -> Result<Segment, DBError> {
+    let path = segment_path(dir, id);
+    OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok(Segment {
+        file: File::open(&path)?,
+        size: 0,
+        dead_bytes: 0,
+    })
+}
+
+/// Makes created, renamed and removed files of the directory durable
+fn sync_dir(dir: &Path) -> Result<(), DBError> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn corrupted(reason: String) -> DBError {
+    DBError::IOError {
+        error: io::Error::new(io::ErrorKind::InvalidData, reason),
+    }
+}
+
+impl GarbageCollector for PackFileBackend {
+    fn new_cycle_started(&mut self) -> Result<(), GarbageCollectionError> {
+        self.compact()
+    }
+
+    fn block_applied(&mut self, _commit: EntryHash) -> Result<(), GarbageCollectionError> {
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<(), GarbageCollectionError> {
+        self.compact_segments()?;
+        Ok(())
+    }
+}
+
+impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for PackFileBackend {
+    fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.append(vec![(KIND_PUT, key, value)])
+    }
+
+    fn delete(&self, key: &EntryHash) -> Result<(), DBError> {
+        if !self.contains(key)? {
+            return Ok(());
+        }
+        self.append(vec![(KIND_DELETE, key, &[])])
+    }
+
+    fn merge(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
+        self.put(key, value)
+    }
+
+    fn get(&self, key: &EntryHash) -> Result<Option<ContextValue>, DBError> {
+        let files = self.inner.read()?;
+        match files.index.get(key) {
+            Some(location) => Ok(Some(files.read_value(location)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn contains(&self, key: &EntryHash) -> Result<bool, DBError> {
+        Ok(self.inner.read()?.index.contains_key(key))
+    }
+
+    fn write_batch(&self, batch: Vec<(EntryHash, ContextValue)>) -> Result<(), DBError> {
+        self.append(
+            batch
+                .iter()
+                .map(|(key, value)| (KIND_PUT, key, value.as_slice()))
+                .collect(),
+        )
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        Ok(self.inner.read()?.index.len()
+            * (std::mem::size_of::<EntryHash>() + std::mem::size_of::<Location>()))
+    }
+
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        if self.read_only {
+            return Err(DBError::ReadOnlyOperation {
+                operation: "retain",
+            });
+        }
+        // index is a hash map, which cannot be iterated from the last checked key, so keys are collected at first
+        let keys: Vec<EntryHash> = self.inner.read()?.index.keys().copied().collect();
+
+        let mut removed_bytes = 0;
+        for chunk in keys.chunks(COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK) {
+            let rejected: Vec<EntryHash> = chunk
+                .iter()
+                .filter(|key| !predicate(key))
+                .copied()
+                .collect();
+            if !rejected.is_empty() {
+                removed_bytes += self.delete_batch(&rejected)?;
+            }
+        }
+        Ok(removed_bytes)
+    }
+
+    fn delete_batch(&self, keys: &[EntryHash]) -> Result<u64, DBError> {
+        if self.read_only {
+            return Err(DBError::ReadOnlyOperation {
+                operation: "delete_batch",
+            });
+        }
+        self.inner
+            .write()?
+            .remove_keys(&self.path, self.segment_size, keys)
+    }
+}
+
+impl Flushable for PackFileBackend {
+    fn flush(&self) -> Result<(), Error> {
+        match self.checkpoint() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(failure::format_err!(
+                "Failed to flush pack files for context, reason: {:?}",
+                e
+            )),
+        }
+    }
+}
+
+impl MultiInstanceable for PackFileBackend {
+    fn supports_multiple_opened_instances(&self) -> bool {
+        true
+    }
+
+    fn sync_with_primary(&self) -> Result<(), MultiInstanceableSyncError> {
+        self.tail_primary()
+            .map_err(|e| MultiInstanceableSyncError::new(format!("{}", e)))
+    }
+}
+
+impl Persistable for PackFileBackend {
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::context::kv_store::test_support::{blob_serialized, entry_hash};
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(&out_dir).join(format!("pack_file_backend_{}", name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        path
+    }
+
+    fn segment_count(path: &Path) -> usize {
+        fs::read_dir(path)
+            .unwrap()
+            .filter(|e| parse_segment_id(&e.as_ref().unwrap().path()).is_some())
+            .count()
+    }
+
+    #[test]
+    fn test_reopen_replays_segments() {
+        let path = test_dir("reopen");
+        {
+            let store = PackFileBackend::open_with_segment_size(&path, 100).unwrap();
+            for i in 1..10 {
+                store
+                    .put(&entry_hash(&[i]), &blob_serialized(vec![i]))
+                    .unwrap();
+            }
+            store.delete(&entry_hash(&[1])).unwrap();
+            store
+                .put(&entry_hash(&[2]), &blob_serialized(vec![22]))
+                .unwrap();
+        }
+        assert!(segment_count(&path) > 1);
+
+        let store = PackFileBackend::open_with_segment_size(&path, 100).unwrap();
+        assert!(store.get(&entry_hash(&[1])).unwrap().is_none());
+        assert_eq!(
+            store.get(&entry_hash(&[2])).unwrap().unwrap(),
+            blob_serialized(vec![22])
+        );
+        for i in 3..10 {
+            assert_eq!(
+                store.get(&entry_hash(&[i])).unwrap().unwrap(),
+                blob_serialized(vec![i])
+            );
+        }
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let path = test_dir("torn_tail");
+        {
+            let store = PackFileBackend::open(&path).unwrap();
+            store
+                .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+                .unwrap();
+        }
+        // crash in the middle of the record
+        let mut record = Vec::new();
+        encode_record(
+            &mut record,
+            KIND_PUT,
+            &entry_hash(&[2]),
+            &blob_serialized(vec![2]),
+        );
+        let segment = segment_path(&path, 0);
+        let valid_size = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&segment)
+            .unwrap()
+            .write_all(&record[..record.len() - 3])
+            .unwrap();
+
+        let store = PackFileBackend::open(&path).unwrap();
+        assert_eq!(fs::metadata(&segment).unwrap().len(), valid_size);
+        assert!(store.get(&entry_hash(&[1])).unwrap().is_some());
+        assert!(store.get(&entry_hash(&[2])).unwrap().is_none());
+
+        store
+            .put(&entry_hash(&[3]), &blob_serialized(vec![3]))
+            .unwrap();
+        drop(store);
+        let store = PackFileBackend::open(&path).unwrap();
+        assert!(store.get(&entry_hash(&[3])).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_checkpoint_and_tail_replay() {
+        let path = test_dir("checkpoint");
+        {
+            let store = PackFileBackend::open_with_segment_size(&path, 200).unwrap();
+            for i in 1..5 {
+                store
+                    .put(&entry_hash(&[i]), &blob_serialized(vec![i]))
+                    .unwrap();
+            }
+            store.flush().unwrap();
+            // appended after checkpoint
+            store.delete(&entry_hash(&[1])).unwrap();
+            store
+                .put(&entry_hash(&[5]), &blob_serialized(vec![5]))
+                .unwrap();
+        }
+        assert!(path.join(INDEX_FILE_NAME).exists());
+
+        let store = PackFileBackend::open_with_segment_size(&path, 200).unwrap();
+        assert!(store.get(&entry_hash(&[1])).unwrap().is_none());
+        for i in 2..6 {
+            assert_eq!(
+                store.get(&entry_hash(&[i])).unwrap().unwrap(),
+                blob_serialized(vec![i])
+            );
+        }
+    }
+
+    #[test]
+    fn test_compaction() {
+        let path = test_dir("compaction");
+        let store = PackFileBackend::open_with_segment_size(&path, 200).unwrap();
+        for i in 1..20 {
+            store
+                .put(&entry_hash(&[i]), &blob_serialized(vec![i]))
+                .unwrap();
+        }
+        store.flush().unwrap();
+        let segments_before = segment_count(&path);
+
+        // keep just every fifth entry
+        store.retain(&|key| key[0] % 5 == 0).unwrap();
+        assert!(store.compact_segments().unwrap() > 0);
+        assert!(segment_count(&path) < segments_before);
+        // checkpoint is rewritten without removed segments
+        assert!(path.join(INDEX_FILE_NAME).exists());
+
+        let check = |store: &PackFileBackend| {
+            for i in 1..20 {
+                let value = store.get(&entry_hash(&[i])).unwrap();
+                if i % 5 == 0 {
+                    assert_eq!(value.unwrap(), blob_serialized(vec![i]));
+                } else {
+                    assert!(value.is_none());
+                }
+            }
+        };
+        check(&store);
+
+        // deleted entries are not resurrected by replay
+        drop(store);
+        let store = PackFileBackend::open_with_segment_size(&path, 200).unwrap();
+        check(&store);
+    }
+
+    #[test]
+    fn test_read_only_follows_primary() {
+        let path = test_dir("read_only");
+        let primary = PackFileBackend::open_with_segment_size(&path, 200).unwrap();
+        primary
+            .put(&entry_hash(&[1]), &blob_serialized(vec![1]))
+            .unwrap();
+
+        let replica = PackFileBackend::open_read_only(&path).unwrap();
+        assert!(replica.get(&entry_hash(&[1])).unwrap().is_some());
+        assert!(matches!(
+            replica.put(&entry_hash(&[2]), &blob_serialized(vec![2])),
+            Err(DBError::ReadOnlyOperation { .. })
+        ));
+
+        // new records and segments are visible after sync
+        for i in 2..20 {
+            primary
+                .put(&entry_hash(&[i]), &blob_serialized(vec![i]))
+                .unwrap();
+        }
+        assert!(replica.get(&entry_hash(&[19])).unwrap().is_none());
+        replica.sync_with_primary().unwrap();
+        for i in 1..20 {
+            assert_eq!(
+                replica.get(&entry_hash(&[i])).unwrap().unwrap(),
+                blob_serialized(vec![i])
+            );
+        }
+
+        // compacted segments are forgotten, live entries are read from their new location
+        primary.retain(&|key| key[0] % 5 == 0).unwrap();
+        assert!(primary.compact_segments().unwrap() > 0);
+        replica.sync_with_primary().unwrap();
+        assert_eq!(
+            segment_count(&path),
+            replica.inner.read().unwrap().segments.len()
+        );
+        for i in 1..20 {
+            let value = replica.get(&entry_hash(&[i])).unwrap();
+            if i % 5 == 0 {
+                assert_eq!(value.unwrap(), blob_serialized(vec![i]));
+            } else {
+                assert!(value.is_none());
+            }
+        }
+    }
+}
@@ -84,7 +84,7 @@ impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for RocksDBBackend {
         self.merkle_ref().contains(key)
     }
 
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
         self.merkle_ref().retain(predicate)
     }
 
@@ -31,28 +31,20 @@ impl SledBackend {
 impl NotGarbageCollected for SledBackend {}
 
 impl KeyValueStoreBackend<ContextKeyValueStoreSchema> for SledBackend {
-    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<(), DBError> {
-        let garbage_keys: Vec<_> = self
-            .inner
-            .iter()
-            .filter_map(|i| match i {
-                Err(_) => None,
-                Ok((k, _)) => {
-                    let mut buffer = [0_u8; 32];
-                    k.to_vec().reader().read_exact(&mut buffer).ok()?;
-                    if !predicate(&buffer) {
-                        Some(buffer)
-                    } else {
-                        None
-                    }
-                }
-            })
-            .collect();
-
-        for k in garbage_keys {
-            self.delete(&k)?;
+    fn retain(&self, predicate: &dyn Fn(&EntryHash) -> bool) -> Result<u64, DBError> {
+        // sled iterator is not invalidated by removals
+        let mut removed_bytes = 0;
+        for (k, v) in self.inner.iter().flatten() {
+            let mut buffer = [0_u8; 32];
+            if k.to_vec().reader().read_exact(&mut buffer).is_err() {
+                continue;
+            }
+            if !predicate(&buffer) {
+                self.inner.remove(&k)?;
+                removed_bytes += (k.len() + v.len()) as u64;
+            }
         }
-        Ok(())
+        Ok(removed_bytes)
     }
 
     fn put(&self, key: &EntryHash, value: &ContextValue) -> Result<(), DBError> {
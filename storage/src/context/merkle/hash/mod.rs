@@ -61,9 +61,11 @@ impl From<io::Error> for HashingError {
 }
 
 /// Inode representation used for hashing directories with >256 entries.
-enum Inode {
+///
+/// Entries are just borrowed from the tree, so partitioning does not clone any node.
+enum Inode<'a> {
     Empty,
-    Value(Vec<(Arc<String>, Node)>),
+    Value(Vec<(&'a Arc<String>, &'a Node)>),
     Tree {
         depth: u32,
         children: usize,
@@ -71,6 +73,12 @@ enum Inode {
     },
 }
 
+/// Max count of pointers (and entries in value) of one inode
+const INODE_POINTERS: u32 = 32;
+
+/// Directories with more entries are hashed as inodes
+pub(crate) const MAX_SHORT_TREE_ENTRIES: usize = 256;
+
 fn encode_irmin_node_kind(kind: &NodeKind) -> [u8; 8] {
     match kind {
         NodeKind::NonLeaf => [0, 0, 0, 0, 0, 0, 0, 0],
@@ -78,38 +86,31 @@ fn encode_irmin_node_kind(kind: &NodeKind) -> [u8; 8] {
     }
 }
 
-fn index(depth: u32, name: &str) -> u32 {
-    ocaml_hash_string(depth, name.as_bytes()) % 32
+/// Index of the inode pointer, under which is the entry `name` stored on `depth`
+pub(crate) fn index(depth: u32, name: &str) -> u8 {
+    (ocaml_hash_string(depth, name.as_bytes()) % INODE_POINTERS) as u8
 }
 
 // IMPORTANT: entries must be sorted in lexicographic order of the name
 // Because we use `OrdMap`, this holds true when we iterate the items, but this is
 // something to keep in mind if the representation of `Tree` changes.
-fn partition_entries(depth: u32, entries: &[(&Arc<String>, &Node)]) -> Result<Inode, HashingError> {
+// Entries are distributed to the buckets in one pass, which keeps the order in every bucket.
+fn partition_entries<'a>(
+    depth: u32,
+    entries: Vec<(&'a Arc<String>, &'a Node)>,
+) -> Result<Inode<'a>, HashingError> {
     if entries.is_empty() {
         Ok(Inode::Empty)
-    } else if entries.len() <= 32 {
-        Ok(Inode::Value(
-            entries
-                .iter()
-                .cloned()
-                .map(|(s, n)| (s.clone(), n.clone()))
-                .collect(),
-        ))
+    } else if entries.len() <= INODE_POINTERS as usize {
+        Ok(Inode::Value(entries))
     } else {
         let children = entries.len();
-        let mut pointers = Vec::with_capacity(32);
+        let buckets = split_to_buckets(depth, entries);
 
         // pointers = {p(i) | i <- [0..31], t(i) != Empty}
-        for i in 0..=31 {
-            let entries_at_depth_and_index_i: Vec<(&Arc<String>, &Node)> = entries
-                .iter()
-                .filter(|(name, _)| index(depth, name) == i)
-                .cloned()
-                .collect();
-            let ti = partition_entries(depth + 1, &entries_at_depth_and_index_i)?;
-
-            match ti {
+        let mut pointers = Vec::with_capacity(INODE_POINTERS as usize);
+        for (i, bucket) in buckets.into_iter().enumerate() {
+            match partition_entries(depth + 1, bucket)? {
                 Inode::Empty => (),
                 non_empty => pointers.push((i as u8, hash_long_inode(&non_empty)?)),
             }
@@ -123,72 +124,183 @@ fn partition_entries(depth: u32, entries: &[(&Arc<String>, &Node)]) -> Result<In
     }
 }
 
-fn hash_long_inode(inode: &Inode) -> Result<EntryHash, HashingError> {
-    let mut hasher = VarBlake2b::new(ENTRY_HASH_LEN)?;
+fn split_to_buckets<'a>(
+    depth: u32,
+    entries: Vec<(&'a Arc<String>, &'a Node)>,
+) -> Vec<Vec<(&'a Arc<String>, &'a Node)>> {
+    let mut buckets: Vec<Vec<(&'a Arc<String>, &'a Node)>> =
+        (0..INODE_POINTERS).map(|_| Vec::new()).collect();
+    for (name, node) in entries {
+        buckets[index(depth, name) as usize].push((name, node));
+    }
+    buckets
+}
+
+/// Inodes on the way from the root of the directory (with >256 entries) to the inode value,
+/// which contains (or would contain) the entry `name`.
+pub(crate) struct InodePath<'a> {
+    /// Count of children and pointers (except the one on the path) of the inode trees, starting from depth 0
+    pub(crate) inodes: Vec<(usize, Vec<(u8, EntryHash)>)>,
+    /// Entries of the inode value except `name`
+    pub(crate) siblings: Vec<(&'a Arc<String>, &'a Node)>,
+}
+
+pub(crate) fn inode_path<'a>(tree: &'a Tree, name: &str) -> Result<InodePath<'a>, HashingError> {
+    let mut inodes = Vec::new();
+    let mut entries: Vec<(&Arc<String>, &Node)> =
+        tree.iter().map(|(s, n)| (s, n.as_ref())).collect();
+    let mut depth = 0;
+
+    while entries.len() > INODE_POINTERS as usize {
+        let children = entries.len();
+        let on_path = index(depth, name) as usize;
+
+        let mut pointers = Vec::with_capacity(INODE_POINTERS as usize);
+        let mut next = Vec::new();
+        for (i, bucket) in split_to_buckets(depth, entries).into_iter().enumerate() {
+            if i == on_path {
+                next = bucket;
+                continue;
+            }
+            match partition_entries(depth + 1, bucket)? {
+                Inode::Empty => (),
+                non_empty => pointers.push((i as u8, hash_long_inode(&non_empty)?)),
+            }
+        }
+
+        inodes.push((children, pointers));
+        entries = next;
+        depth += 1;
+    }
+
+    entries.retain(|(entry_name, _)| entry_name.as_str() != name);
+    Ok(InodePath {
+        inodes,
+        siblings: entries,
+    })
+}
 
+fn hash_long_inode(inode: &Inode) -> Result<EntryHash, HashingError> {
     match inode {
-        Inode::Empty => return Err(HashingError::UnexpectedEmptyInode),
+        Inode::Empty => Err(HashingError::UnexpectedEmptyInode),
         Inode::Value(entries) => {
-            // Inode value:
-            //
-            // |   1   |   1  |     n_1      |  ...  |      n_k      |
-            // +-------+------+--------------+-------+---------------+
-            // | \000  |  \n  | prehash(e_1) |  ...  | prehash(e_k)  |
-            //
-            // where n_i = len(prehash(e_i))
-
-            hasher.update(&[0u8]); // type tag
-            hasher.update(&[entries.len() as u8]);
-
-            // Inode value entry:
-            //
-            // |   (LEB128)  |  len(name)   |   1    |   32   |
-            // +-------------+--------------+--------+--------+
-            // | \len(name)  |     name     |  kind  |  hash  |
-
+            let mut hasher = VarBlake2b::new(ENTRY_HASH_LEN)?;
+            write_inode_value_header(&mut hasher, entries.len());
             for (name, node) in entries {
-                leb128::write::unsigned(&mut hasher, name.len() as u64)?;
-                hasher.update(name.as_bytes());
-                // \000 for nodes, and \001 for contents.
-                match node.node_kind {
-                    NodeKind::Leaf => hasher.update(&[1u8]),
-                    NodeKind::NonLeaf => hasher.update(&[0u8]),
-                };
-                hasher.update(&node.entry_hash()?);
+                write_inode_value_entry(&mut hasher, name, &node.node_kind, &node.entry_hash()?)?;
             }
+            Ok(hasher.finalize_boxed().as_ref().try_into()?)
         }
         Inode::Tree {
             depth,
             children,
             pointers,
-        } => {
-            // Inode tree:
-            //
-            // |   1    | (LEB128) |   (LEB128)    |    1   |  33  | ... |  33  |
-            // +--------+----------+---------------+--------+------+-----+------+
-            // |  \001  |  depth   | len(children) |   \k   | s_1  | ... | s_k  |
-
-            hasher.update(&[1u8]); // type tag
-            leb128::write::unsigned(&mut hasher, *depth as u64)?;
-            leb128::write::unsigned(&mut hasher, *children as u64)?;
-            hasher.update(&[pointers.len() as u8]);
-
-            // Inode pointer:
-            //
-            // |    1    |   32   |
-            // +---------+--------+
-            // |  index  |  hash  |
-
-            for (index, hash) in pointers {
-                hasher.update(&[*index]);
-                hasher.update(hash);
-            }
-        }
+        } => hash_inode_tree(*depth, *children, pointers),
+    }
+}
+
+fn write_inode_value_header(hasher: &mut VarBlake2b, entries_count: usize) {
+    // Inode value:
+    //
+    // |   1   |   1  |     n_1      |  ...  |      n_k      |
+    // +-------+------+--------------+-------+---------------+
+    // | \000  |  \n  | prehash(e_1) |  ...  | prehash(e_k)  |
+    //
+    // where n_i = len(prehash(e_i))
+
+    hasher.update(&[0u8]); // type tag
+    hasher.update(&[entries_count as u8]);
+}
+
+fn write_inode_value_entry(
+    hasher: &mut VarBlake2b,
+    name: &str,
+    kind: &NodeKind,
+    hash: &EntryHash,
+) -> Result<(), HashingError> {
+    // Inode value entry:
+    //
+    // |   (LEB128)  |  len(name)   |   1    |   32   |
+    // +-------------+--------------+--------+--------+
+    // | \len(name)  |     name     |  kind  |  hash  |
+
+    leb128::write::unsigned(hasher, name.len() as u64)?;
+    hasher.update(name.as_bytes());
+    // \000 for nodes, and \001 for contents.
+    match kind {
+        NodeKind::Leaf => hasher.update(&[1u8]),
+        NodeKind::NonLeaf => hasher.update(&[0u8]),
+    };
+    hasher.update(hash);
+    Ok(())
+}
+
+/// Calculates hash of the inode value from names, kinds and hashes of its entries (sorted by name)
+pub(crate) fn hash_inode_value(
+    entries: &[(&str, &NodeKind, EntryHash)],
+) -> Result<EntryHash, HashingError> {
+    let mut hasher = VarBlake2b::new(ENTRY_HASH_LEN)?;
+    write_inode_value_header(&mut hasher, entries.len());
+    for (name, kind, hash) in entries {
+        write_inode_value_entry(&mut hasher, name, kind, hash)?;
+    }
+    Ok(hasher.finalize_boxed().as_ref().try_into()?)
+}
+
+/// Calculates hash of the inode tree from its (non-empty) pointers sorted by index
+pub(crate) fn hash_inode_tree(
+    depth: u32,
+    children: usize,
+    pointers: &[(u8, EntryHash)],
+) -> Result<EntryHash, HashingError> {
+    let mut hasher = VarBlake2b::new(ENTRY_HASH_LEN)?;
+
+    // Inode tree:
+    //
+    // |   1    | (LEB128) |   (LEB128)    |    1   |  33  | ... |  33  |
+    // +--------+----------+---------------+--------+------+-----+------+
+    // |  \001  |  depth   | len(children) |   \k   | s_1  | ... | s_k  |
+
+    hasher.update(&[1u8]); // type tag
+    leb128::write::unsigned(&mut hasher, depth as u64)?;
+    leb128::write::unsigned(&mut hasher, children as u64)?;
+    hasher.update(&[pointers.len() as u8]);
+
+    // Inode pointer:
+    //
+    // |    1    |   32   |
+    // +---------+--------+
+    // |  index  |  hash  |
+
+    for (index, hash) in pointers {
+        hasher.update(&[*index]);
+        hasher.update(hash);
     }
 
     Ok(hasher.finalize_boxed().as_ref().try_into()?)
 }
 
+fn write_short_tree_entry(
+    hasher: &mut VarBlake2b,
+    name: &str,
+    kind: &NodeKind,
+    hash: &EntryHash,
+) -> Result<(), HashingError> {
+    // Node entry:
+    //
+    // |   8   |   (LEB128)   |  len(name)  |   8   |   32   |
+    // +-------+--------------+-------------+-------+--------+
+    // | kind  |  \len(name)  |    name     |  \32  |  hash  |
+
+    hasher.update(encode_irmin_node_kind(kind));
+    // Key length is written in LEB128 encoding
+    leb128::write::unsigned(hasher, name.len() as u64)?;
+    hasher.update(name.as_bytes());
+    hasher.update(&(ENTRY_HASH_LEN as u64).to_be_bytes());
+    hasher.update(hash);
+    Ok(())
+}
+
 // hash is calculated as:
 // <number of child nodes (8 bytes)><CHILD NODE>
 // where:
@@ -204,33 +316,33 @@ fn hash_short_inode(tree: &Tree) -> Result<EntryHash, HashingError> {
     // |   \k   | prehash(e_1) | ... | prehash(e_k) |
 
     hasher.update(&(tree.len() as u64).to_be_bytes());
-
-    // Node entry:
-    //
-    // |   8   |   (LEB128)   |  len(name)  |   8   |   32   |
-    // +-------+--------------+-------------+-------+--------+
-    // | kind  |  \len(name)  |    name     |  \32  |  hash  |
-
     for (k, v) in tree {
-        hasher.update(encode_irmin_node_kind(&v.node_kind));
-        // Key length is written in LEB128 encoding
-        leb128::write::unsigned(&mut hasher, k.len() as u64)?;
-        hasher.update(k.as_bytes());
-        hasher.update(&(ENTRY_HASH_LEN as u64).to_be_bytes());
-        hasher.update(&v.entry_hash()?);
+        write_short_tree_entry(&mut hasher, k, &v.node_kind, &v.entry_hash()?)?;
     }
 
     Ok(hasher.finalize_boxed().as_ref().try_into()?)
 }
 
+/// Calculates hash of the directory (with up to 256 entries) from names, kinds and hashes of its entries (sorted by name)
+pub(crate) fn hash_short_tree(
+    entries: &[(&str, &NodeKind, EntryHash)],
+) -> Result<EntryHash, HashingError> {
+    let mut hasher = VarBlake2b::new(ENTRY_HASH_LEN)?;
+    hasher.update(&(entries.len() as u64).to_be_bytes());
+    for (name, kind, hash) in entries {
+        write_short_tree_entry(&mut hasher, name, kind, hash)?;
+    }
+    Ok(hasher.finalize_boxed().as_ref().try_into()?)
+}
+
 // Calculates hash of tree
 // uses BLAKE2 binary 256 length hash function
 pub(crate) fn hash_tree(tree: &Tree) -> Result<EntryHash, HashingError> {
     // If there are >256 entries, we need to partition the tree and hash the resulting inode
-    if tree.len() > 256 {
+    if tree.len() > MAX_SHORT_TREE_ENTRIES {
         let entries: Vec<(&Arc<String>, &Node)> =
             tree.iter().map(|(s, n)| (s, n.as_ref())).collect();
-        let inode = partition_entries(0, &entries)?;
+        let inode = partition_entries(0, entries)?;
         hash_long_inode(&inode)
     } else {
         hash_short_inode(tree)
@@ -468,6 +580,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hash_of_large_tree() {
+        // large enough to be partitioned to inodes at depth 0 and 1
+        let mut tree = Tree::new();
+        for i in 0..2000_u32 {
+            let node = Node {
+                node_kind: if i % 3 == 0 {
+                    NodeKind::NonLeaf
+                } else {
+                    NodeKind::Leaf
+                },
+                entry_hash: RefCell::new(Some(hash_blob(&i.to_be_bytes().to_vec()).unwrap())),
+                entry: RefCell::new(None),
+            };
+            tree.insert(Arc::new(format!("key_{}", i)), Arc::new(node));
+        }
+
+        // hash calculated by the previous (not optimized) partitioning
+        assert_eq!(
+            hex::encode(hash_tree(&tree).unwrap()),
+            "78f90e163c5276a4e473229ffcd4659b07d126359ee325f6c141e487a88b4faa"
+        );
+    }
+
     // Tests from Tarides json dataset
 
     #[derive(serde::Deserialize)]
@@ -0,0 +1,126 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Shares names of tree entries, so every short name (e.g. `data`, `contracts`, `index`, `balance`)
+/// is allocated just once, regardless of how many trees (or versions of the tree) contain it.
+///
+/// Long names (mostly hashes/addresses) are not interned, because they are almost unique.
+#[derive(Default)]
+pub struct StringInterner {
+    strings: HashSet<Interned>,
+}
+
+/// Allows lookup of interned names just by `&str` (without allocation)
+#[derive(PartialEq, Eq)]
+struct Interned(Arc<String>);
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl StringInterner {
+    /// Names up to this length (in bytes) are interned, longer names are not
+    pub const MAX_INTERNED_LEN: usize = 32;
+
+    /// When reached, strings not used by any tree are released
+    const CLEANUP_THRESHOLD: usize = 100_000;
+
+    pub fn intern(&mut self, name: &str) -> Arc<String> {
+        if name.len() > Self::MAX_INTERNED_LEN {
+            return Arc::new(name.to_string());
+        }
+
+        match self.strings.get(name) {
+            Some(interned) => interned.0.clone(),
+            None => self.insert(Arc::new(name.to_string())),
+        }
+    }
+
+    /// Replaces already allocated name with the interned one (if there is any)
+    pub fn intern_arc(&mut self, name: Arc<String>) -> Arc<String> {
+        if name.len() > Self::MAX_INTERNED_LEN {
+            return name;
+        }
+        match self.strings.get(name.as_str()) {
+            Some(interned) => interned.0.clone(),
+            None => self.insert(name),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    fn insert(&mut self, name: Arc<String>) -> Arc<String> {
+        if self.strings.len() >= Self::CLEANUP_THRESHOLD {
+            self.cleanup();
+        }
+        self.strings.insert(Interned(name.clone()));
+        name
+    }
+
+    /// Releases strings, which are referenced just by interner
+    fn cleanup(&mut self) {
+        self.strings.retain(|s| Arc::strong_count(&s.0) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_short_names() {
+        let mut interner = StringInterner::default();
+
+        let a = interner.intern("data");
+        let b = interner.intern("data");
+        let c = interner.intern_arc(Arc::new("data".to_string()));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(Arc::ptr_eq(&a, &c));
+        assert_eq!(interner.len(), 1);
+
+        let longest = "a".repeat(StringInterner::MAX_INTERNED_LEN);
+        assert!(Arc::ptr_eq(
+            &interner.intern(&longest),
+            &interner.intern(&longest)
+        ));
+        assert_eq!(interner.len(), 2);
+
+        let long = "a".repeat(StringInterner::MAX_INTERNED_LEN + 1);
+        let d = interner.intern(&long);
+        let e = interner.intern(&long);
+        assert!(!Arc::ptr_eq(&d, &e));
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_cleanup_unused_names() {
+        let mut interner = StringInterner::default();
+
+        let kept = interner.intern("kept");
+        for i in 0..StringInterner::CLEANUP_THRESHOLD {
+            interner.intern(&format!("{}", i));
+        }
+        // threshold was reached by the last one, so all the previous unused were released
+        assert_eq!(interner.len(), 2);
+        assert!(Arc::ptr_eq(&kept, &interner.intern("kept")));
+    }
+}
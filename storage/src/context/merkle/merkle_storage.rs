@@ -45,10 +45,12 @@
 //! ``
 //!
 //! Reference: https://git-scm.com/book/en/v2/Git-Internals-Git-Objects
+use std::iter::Peekable;
 use std::{array::TryFromSliceError, sync::Arc};
 use std::{cell::RefCell, collections::HashMap};
 
 use failure::{Error, Fail};
+use lru::LruCache;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -57,15 +59,18 @@ use crypto::hash::{FromBytesError, HashType};
 use crate::context::gc::GarbageCollectionError;
 use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::hash::{hash_commit, hash_entry, hash_tree, HashingError};
+use crate::context::merkle::interner::StringInterner;
 use crate::context::merkle::merkle_storage_stats::{
     MerkleStorageAction, MerkleStoragePerfReport, MerkleStorageStatistics, StatUpdater,
 };
+use crate::context::merkle::proof::{CommitProof, MerkleProof, ProofTarget, TreeProof};
 use crate::context::merkle::{Commit, Entry, Node, NodeKind, Tree};
 use crate::context::{
-    ContextKey, ContextKeyValueStore, ContextValue, StringTreeEntry, StringTreeMap, TreeId,
+    ContextKey, ContextKeyDiff, ContextKeyValueStore, ContextValue, StringTreeEntry, StringTreeMap,
+    TreeId,
 };
 use crate::persistent;
-use crate::persistent::Flushable;
+use crate::persistent::{Flushable, MultiInstanceableSyncError};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SetAction {
@@ -102,6 +107,11 @@ pub struct MerkleStorage {
     last_commit_hash: Option<EntryHash>,
     /// storage latency statistics
     stats: MerkleStorageStatistics,
+    /// names of the tree entries shared by all trees
+    interner: RefCell<StringInterner>,
+    /// recently used entries decoded from db, entries are immutable, but the cache is cleared with the new GC cycle
+    /// (garbage collector removes them from db)
+    entry_cache: RefCell<LruCache<EntryHash, Entry>>,
 }
 
 unsafe impl Send for MerkleStorage {}
@@ -150,6 +160,8 @@ pub enum MerkleError {
     ValueNotFound { key: String },
     #[fail(display = "Cannot search for an empty key.")]
     KeyEmpty,
+    #[fail(display = "Key {:?} is not under prefix {:?}.", key, prefix)]
+    KeyNotUnderPrefix { key: String, prefix: String },
     #[fail(display = "Failed to convert hash into array: {}", error)]
     HashToArrayError { error: TryFromSliceError },
     #[fail(display = "Failed to convert hash into string: {}", error)]
@@ -162,6 +174,8 @@ pub enum MerkleError {
     InvalidState(&'static str),
     #[fail(display = "GC was called before first commit")]
     GCTriggeredBeforeFirstCommit,
+    #[fail(display = "Failed to sync with primary store: {}", error)]
+    SyncWithPrimaryError { error: MultiInstanceableSyncError },
 }
 
 impl From<persistent::database::DBError> for MerkleError {
@@ -216,7 +230,17 @@ pub enum CheckEntryHashError {
 }
 
 impl MerkleStorage {
+    /// Default count of decoded entries kept in memory
+    pub const DEFAULT_ENTRY_CACHE_CAPACITY: usize = 100_000;
+
     pub fn new(db: Box<ContextKeyValueStore>) -> Self {
+        Self::with_entry_cache_capacity(db, Self::DEFAULT_ENTRY_CACHE_CAPACITY)
+    }
+
+    pub fn with_entry_cache_capacity(
+        db: Box<ContextKeyValueStore>,
+        entry_cache_capacity: usize,
+    ) -> Self {
         let tree = Tree::new();
         let tree_id = 0;
         let mut trees_map: HashMap<TreeId, Tree> = HashMap::new();
@@ -229,6 +253,8 @@ impl MerkleStorage {
             working_tree: (tree, tree_id),
             last_commit_hash: None,
             stats: MerkleStorageStatistics::default(),
+            interner: RefCell::new(StringInterner::default()),
+            entry_cache: RefCell::new(LruCache::new(entry_cache_capacity)),
         }
     }
 
@@ -488,6 +514,212 @@ impl MerkleStorage {
         }
     }
 
+    /// Returns proof, that the key contains (or does not contain) a value in the context identified by commit hash,
+    /// proof can be checked by [crate::context::merkle::proof::verify_proof]
+    pub fn get_merkle_proof(
+        &self,
+        context_hash: &EntryHash,
+        key: &ContextKey,
+    ) -> Result<MerkleProof, MerkleError> {
+        let commit = self.get_commit(context_hash)?;
+        let entry = self.get_entry_from_hash(&commit.root_hash)?;
+        let mut tree = self.get_tree(&entry)?.clone();
+
+        let mut path = Vec::with_capacity(key.len());
+        let mut target = ProofTarget::Tree(commit.root_hash);
+
+        for (i, name) in key.iter().enumerate() {
+            let is_last = i + 1 == key.len();
+            path.push(TreeProof::new(&tree, name)?);
+
+            let node = match tree.get(name) {
+                Some(node) => node.clone(),
+                None => {
+                    target = ProofTarget::Missing;
+                    break;
+                }
+            };
+
+            match node.node_kind {
+                NodeKind::Leaf if is_last => match self.get_entry(&node)? {
+                    Entry::Blob(value) => target = ProofTarget::Value(value),
+                    _ => {
+                        return Err(MerkleError::ValueIsNotABlob {
+                            key: self.key_to_string(key),
+                        })
+                    }
+                },
+                NodeKind::Leaf => {
+                    target = ProofTarget::Blob(node.entry_hash()?);
+                    break;
+                }
+                NodeKind::NonLeaf => {
+                    target = ProofTarget::Tree(node.entry_hash()?);
+                    if !is_last {
+                        let entry = self.get_entry(&node)?;
+                        tree = self.get_tree(&entry)?.clone();
+                    }
+                }
+            }
+        }
+
+        Ok(MerkleProof {
+            key: key.clone(),
+            commit: CommitProof::from(&commit),
+            path,
+            target,
+        })
+    }
+
+    /// Returns iterator over added, removed and modified keys under the prefix between two commits.
+    ///
+    /// Subtrees with the same hash are the same, so they are skipped without loading.
+    pub fn diff(
+        &self,
+        from_context_hash: &EntryHash,
+        to_context_hash: &EntryHash,
+        prefix: &ContextKey,
+    ) -> Result<DiffIterator, MerkleError> {
+        Ok(DiffIterator {
+            merkle: self,
+            cursor: self.diff_cursor(from_context_hash, to_context_hash, prefix)?,
+        })
+    }
+
+    /// Same as [MerkleStorage::diff], but the returned cursor does not borrow the storage,
+    /// so the storage can be released (unlocked) between the changes
+    pub fn diff_cursor(
+        &self,
+        from_context_hash: &EntryHash,
+        to_context_hash: &EntryHash,
+        prefix: &ContextKey,
+    ) -> Result<DiffCursor, MerkleError> {
+        let from = self.get_commit_node(from_context_hash, prefix)?;
+        let to = self.get_commit_node(to_context_hash, prefix)?;
+
+        let mut cursor = DiffCursor { stack: Vec::new() };
+        if let Some(change) = cursor.compare(self, prefix.clone(), from, to)? {
+            cursor.stack.push(DiffFrame::Pending(change));
+        }
+        Ok(cursor)
+    }
+
+    /// Returns iterator over the keys under the prefix in the commit, keys are returned in lexicographic order.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - if set, keys deeper than `depth` levels under the prefix are not iterated, trees at this level are returned as [SubtreeEntry::Tree]
+    /// * `after` - continuation token, if set, iteration starts with the first key after this key (which has to be under the prefix)
+    pub fn iter_subtree(
+        &self,
+        context_hash: &EntryHash,
+        prefix: &ContextKey,
+        depth: Option<usize>,
+        after: Option<&ContextKey>,
+    ) -> Result<SubtreeIterator, MerkleError> {
+        let after = match after {
+            Some(after) if after.starts_with(prefix) => &after[prefix.len()..],
+            Some(after) => {
+                return Err(MerkleError::KeyNotUnderPrefix {
+                    key: self.key_to_string(after),
+                    prefix: self.key_to_string(prefix),
+                })
+            }
+            None => &[],
+        };
+
+        let mut iterator = SubtreeIterator {
+            merkle: self,
+            stack: Vec::new(),
+            prefix_len: prefix.len(),
+            depth,
+        };
+        if let Some(0) = depth {
+            return Ok(iterator);
+        }
+
+        let commit = self.get_commit(context_hash)?;
+        let entry = self.get_entry_from_hash(&commit.root_hash)?;
+        let mut tree = self.find_tree(self.get_tree(&entry)?, prefix)?;
+        let mut path = prefix.clone();
+        let mut after = after;
+
+        // descend along the `after` key, remaining siblings of every key component are iterated later
+        loop {
+            let (name, rest) = match after.split_first() {
+                Some(split) => split,
+                None => {
+                    iterator.stack.push(SubtreeFrame {
+                        path,
+                        children: tree.into_iter(),
+                    });
+                    break;
+                }
+            };
+
+            let (_, greater) = tree.split(name);
+            let child = tree.get(name).cloned();
+            iterator.stack.push(SubtreeFrame {
+                path: path.clone(),
+                children: greater.into_iter(),
+            });
+
+            path.push(name.clone());
+            match child {
+                Some(node)
+                    if !rest.is_empty()
+                        && node.node_kind == NodeKind::NonLeaf
+                        && !iterator.is_at_depth_limit(&path) =>
+                {
+                    tree = match self.get_entry(&node)? {
+                        Entry::Tree(tree) => tree,
+                        _ => break,
+                    };
+                    after = rest;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(iterator)
+    }
+
+    /// Returns node under the key in the commit (None if there is no such node)
+    fn get_commit_node(
+        &self,
+        context_hash: &EntryHash,
+        key: &ContextKey,
+    ) -> Result<Option<Arc<Node>>, MerkleError> {
+        let commit = self.get_commit(context_hash)?;
+        let mut node = Arc::new(Node {
+            node_kind: NodeKind::NonLeaf,
+            entry_hash: RefCell::new(Some(commit.root_hash)),
+            entry: RefCell::new(None),
+        });
+
+        for name in key {
+            if node.node_kind == NodeKind::Leaf {
+                return Ok(None);
+            }
+            let entry = self.get_entry(&node)?;
+            node = match self.get_tree(&entry)?.get(name) {
+                Some(child) => child.clone(),
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(node))
+    }
+
+    fn get_blob(&self, node: &Node) -> Result<ContextValue, MerkleError> {
+        match self.get_entry(node)? {
+            Entry::Blob(value) => Ok(value),
+            _ => Err(MerkleError::FoundUnexpectedStructure {
+                sought: "Blob".to_string(),
+                found: "Tree/Commit".to_string(),
+            }),
+        }
+    }
+
     /// Flush the working tree and and move to work on a certain commit from history.
     pub fn checkout(&mut self, context_hash: &EntryHash) -> Result<(), MerkleError> {
         let stat_updater = StatUpdater::new(MerkleStorageAction::Checkout, None);
@@ -528,7 +760,10 @@ impl MerkleStorage {
 
         // persist working tree entries to db
         let mut batch: Vec<(EntryHash, ContextValue)> = Vec::new();
-        self.get_entries_recursively(&entry, Some(&self.working_tree.0), &mut batch)?;
+        let mut reused: Vec<EntryHash> = Vec::new();
+        self.get_entries_recursively(&entry, Some(&self.working_tree.0), &mut batch, &mut reused)?;
+        // subtrees, which were not loaded, are referenced by the commit without being written
+        self.db.entries_reused(&reused)?;
         // write all entries at once (depends on backend)
         self.db.write_batch(batch)?;
 
@@ -662,7 +897,7 @@ impl MerkleStorage {
 
         match new_node {
             None => tree.remove(last),
-            Some(new_node) => tree.insert(Arc::new(last.clone()), Arc::new(new_node)),
+            Some(new_node) => tree.insert(self.interner.get_mut().intern(last), Arc::new(new_node)),
         };
 
         if tree.is_empty() {
@@ -750,15 +985,29 @@ impl MerkleStorage {
 
     /// Notify GC about new cycle
     pub fn start_new_cycle(&mut self) -> Result<(), MerkleError> {
-        Ok(self.db.new_cycle_started()?)
+        self.db.new_cycle_started()?;
+        // GC removes entries from now on, so cached entries might not be in the store anymore
+        self.entry_cache
+            .try_borrow_mut()
+            .map_err(|_| MerkleError::InvalidState("The entry cache is borrowed more than once"))?
+            .clear();
+        Ok(())
+    }
+
+    /// Epoch (count of started cycles) of the garbage collector, restored from the store on start,
+    /// `None` if context is not garbage collected
+    pub fn gc_cycle_epoch(&self) -> Option<u64> {
+        self.db.cycle_epoch()
     }
 
-    /// Builds vector of entries to be persisted to DB, recursively
+    /// Builds vector of entries to be persisted to DB, recursively,
+    /// hashes of the referenced entries, which are not loaded (so they are already stored), are collected to `reused`
     fn get_entries_recursively(
         &self,
         entry: &Entry,
         root: Option<&Tree>,
         batch: &mut Vec<(EntryHash, ContextValue)>,
+        reused: &mut Vec<EntryHash>,
     ) -> Result<(), MerkleError> {
         // add entry to batch
         let hashed = hash_entry(entry)?;
@@ -779,8 +1028,11 @@ impl MerkleStorage {
                             .map_err(|_| MerkleError::InvalidState("Entry borrows twice"))?
                             .as_ref()
                         {
-                            None => Ok(()),
-                            Some(entry) => self.get_entries_recursively(entry, None, batch),
+                            None => {
+                                reused.push(child_node.entry_hash()?);
+                                Ok(())
+                            }
+                            Some(entry) => self.get_entries_recursively(entry, None, batch, reused),
                         }
                     })
                     .find_map(|res| match res {
@@ -794,7 +1046,7 @@ impl MerkleStorage {
                     Some(root) => Entry::Tree(root.clone()),
                     None => self.get_entry_from_hash(&commit.root_hash)?,
                 };
-                self.get_entries_recursively(&entry, None, batch)
+                self.get_entries_recursively(&entry, None, batch, reused)
             }
         }
     }
@@ -857,12 +1109,48 @@ impl MerkleStorage {
     }
 
     fn get_entry_from_hash(&self, hash: &EntryHash) -> Result<Entry, MerkleError> {
+        if let Some(entry) = self
+            .entry_cache
+            .try_borrow_mut()
+            .map_err(|_| MerkleError::InvalidState("The entry cache is borrowed more than once"))?
+            .get(hash)
+        {
+            // entry is used without reading it from the store, concurrent GC must know about it
+            self.db.entries_read(&[*hash])?;
+            return Ok(entry.clone());
+        }
+
         let entry_bytes = self.db.get(&hash)?;
-        match entry_bytes {
-            None => Err(MerkleError::EntryNotFound {
-                hash: HashType::ContextHash.hash_to_b58check(hash)?,
-            }),
-            Some(entry_bytes) => Ok(bincode::deserialize(&entry_bytes)?),
+        let entry = match entry_bytes {
+            None => {
+                return Err(MerkleError::EntryNotFound {
+                    hash: HashType::ContextHash.hash_to_b58check(hash)?,
+                })
+            }
+            Some(entry_bytes) => self.intern_names(bincode::deserialize(&entry_bytes)?)?,
+        };
+
+        self.entry_cache
+            .try_borrow_mut()
+            .map_err(|_| MerkleError::InvalidState("The entry cache is borrowed more than once"))?
+            .put(*hash, entry.clone());
+        Ok(entry)
+    }
+
+    /// Replaces names of the decoded tree with the interned ones
+    fn intern_names(&self, entry: Entry) -> Result<Entry, MerkleError> {
+        match entry {
+            Entry::Tree(tree) => {
+                let mut interner = self.interner.try_borrow_mut().map_err(|_| {
+                    MerkleError::InvalidState("The interner is borrowed more than once")
+                })?;
+                Ok(Entry::Tree(
+                    tree.into_iter()
+                        .map(|(name, node)| (interner.intern_arc(name), node))
+                        .collect(),
+                ))
+            }
+            other => Ok(other),
         }
     }
 
@@ -884,6 +1172,11 @@ impl MerkleStorage {
         string.split('/').map(str::to_string).collect()
     }
 
+    /// Get entry (commit/tree/blob) directly from db by its hash
+    pub fn get_entry_by_hash(&self, hash: &EntryHash) -> Result<Entry, MerkleError> {
+        self.get_entry_from_hash(hash)
+    }
+
     /// Get last committed hash
     pub fn get_last_commit_hash(&self) -> Option<EntryHash> {
         self.last_commit_hash
@@ -894,6 +1187,8 @@ impl MerkleStorage {
         Ok(MerkleStoragePerfReport {
             perf_stats: self.stats.perf_stats.clone(),
             kv_store_stats: self.db.total_get_mem_usage()?,
+            gc_stats: self.db.gc_stats(),
+            compression_stats: self.db.compression_stats(),
         })
     }
 
@@ -905,6 +1200,18 @@ impl MerkleStorage {
         self.stats.block_latencies.get(offset_from_last_applied)
     }
 
+    /// Returns true, if entries survive restart of the node
+    pub fn is_persistent(&self) -> bool {
+        self.db.is_persistent()
+    }
+
+    /// Picks up entries written by the primary process (just for store opened as read-only replica)
+    pub fn sync_with_primary(&self) -> Result<(), MerkleError> {
+        self.db
+            .sync_with_primary()
+            .map_err(|error| MerkleError::SyncWithPrimaryError { error })
+    }
+
     fn flush_db(&self) -> Result<(), Error> {
         self.db.flush()
     }
@@ -916,6 +1223,258 @@ impl Flushable for MerkleStorage {
     }
 }
 
+/// Value of the key returned by [SubtreeIterator]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubtreeEntry {
+    Blob(ContextValue),
+    /// Tree at the depth limit, which is not iterated
+    Tree,
+}
+
+/// Iterator over the keys of the subtree of a commit, see [MerkleStorage::iter_subtree].
+///
+/// Entries are loaded lazily, only trees on the path to the current key are held in memory.
+pub struct SubtreeIterator<'a> {
+    merkle: &'a MerkleStorage,
+    /// Remaining children of the trees on the path to the current key
+    stack: Vec<SubtreeFrame>,
+    prefix_len: usize,
+    depth: Option<usize>,
+}
+
+struct SubtreeFrame {
+    path: ContextKey,
+    children: im::ordmap::ConsumingIter<(Arc<String>, Arc<Node>)>,
+}
+
+impl<'a> SubtreeIterator<'a> {
+    fn is_at_depth_limit(&self, key: &[String]) -> bool {
+        self.depth
+            .map(|depth| key.len() - self.prefix_len >= depth)
+            .unwrap_or(false)
+    }
+}
+
+impl<'a> Iterator for SubtreeIterator<'a> {
+    type Item = Result<(ContextKey, SubtreeEntry), MerkleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let (name, node) = match frame.children.next() {
+                Some(child) => child,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let mut key = frame.path.clone();
+            key.push(name.to_string());
+
+            if node.node_kind == NodeKind::NonLeaf && self.is_at_depth_limit(&key) {
+                return Some(Ok((key, SubtreeEntry::Tree)));
+            }
+
+            match self.merkle.get_entry(&node) {
+                Ok(Entry::Blob(blob)) => return Some(Ok((key, SubtreeEntry::Blob(blob)))),
+                Ok(Entry::Tree(tree)) => self.stack.push(SubtreeFrame {
+                    path: key,
+                    children: tree.into_iter(),
+                }),
+                Ok(Entry::Commit(_)) => {
+                    self.stack.clear();
+                    return Some(Err(MerkleError::FoundUnexpectedStructure {
+                        sought: "Tree/Blob".to_string(),
+                        found: "Commit".to_string(),
+                    }));
+                }
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the changes between two commits, see [MerkleStorage::diff].
+pub struct DiffIterator<'a> {
+    merkle: &'a MerkleStorage,
+    cursor: DiffCursor,
+}
+
+impl<'a> Iterator for DiffIterator<'a> {
+    type Item = Result<ContextKeyDiff, MerkleError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next_change(self.merkle)
+    }
+}
+
+/// State of the diff between two commits, see [MerkleStorage::diff_cursor].
+///
+/// Entries are loaded lazily, only trees on the path to the current key are held in memory.
+pub struct DiffCursor {
+    stack: Vec<DiffFrame>,
+}
+
+enum DiffFrame {
+    /// Remaining children of both trees, which are walked at once (sorted), so every name is compared just once
+    Compare {
+        path: ContextKey,
+        from: Peekable<im::ordmap::ConsumingIter<(Arc<String>, Arc<Node>)>>,
+        to: Peekable<im::ordmap::ConsumingIter<(Arc<String>, Arc<Node>)>>,
+    },
+    /// Remaining children of the added (or removed) tree, all their values are returned
+    Collect {
+        path: ContextKey,
+        children: im::ordmap::ConsumingIter<(Arc<String>, Arc<Node>)>,
+        added: bool,
+    },
+    /// Change returned after the frames above it
+    Pending(ContextKeyDiff),
+}
+
+impl DiffCursor {
+    /// Returns the next change, `merkle` has to be the storage, which created the cursor
+    pub fn next_change(
+        &mut self,
+        merkle: &MerkleStorage,
+    ) -> Option<Result<ContextKeyDiff, MerkleError>> {
+        loop {
+            let result = match self.stack.last_mut()? {
+                DiffFrame::Pending(_) => match self.stack.pop() {
+                    Some(DiffFrame::Pending(change)) => return Some(Ok(change)),
+                    _ => continue,
+                },
+                DiffFrame::Collect {
+                    path,
+                    children,
+                    added,
+                } => match children.next() {
+                    Some((name, node)) => {
+                        let mut key = path.clone();
+                        key.push(name.to_string());
+                        let added = *added;
+                        self.collect(merkle, key, &node, added)
+                    }
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                },
+                DiffFrame::Compare { path, from, to } => {
+                    let (name, from_node, to_node) = match (from.peek(), to.peek()) {
+                        (None, None) => {
+                            self.stack.pop();
+                            continue;
+                        }
+                        (Some((from_name, _)), Some((to_name, _))) if from_name < to_name => {
+                            let (name, node) = from.next()?;
+                            (name, Some(node), None)
+                        }
+                        (Some((from_name, _)), Some((to_name, _))) if from_name > to_name => {
+                            let (name, node) = to.next()?;
+                            (name, None, Some(node))
+                        }
+                        (Some(_), Some(_)) => {
+                            let (name, from_node) = from.next()?;
+                            let (_, to_node) = to.next()?;
+                            (name, Some(from_node), Some(to_node))
+                        }
+                        (Some(_), None) => {
+                            let (name, node) = from.next()?;
+                            (name, Some(node), None)
+                        }
+                        (None, Some(_)) => {
+                            let (name, node) = to.next()?;
+                            (name, None, Some(node))
+                        }
+                    };
+                    let mut key = path.clone();
+                    key.push(name.to_string());
+                    self.compare(merkle, key, from_node, to_node)
+                }
+            };
+
+            match result {
+                Ok(Some(change)) => return Some(Ok(change)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Returns change of the leaf at `key`, changed trees are pushed to the stack to be walked later
+    fn compare(
+        &mut self,
+        merkle: &MerkleStorage,
+        key: ContextKey,
+        from: Option<Arc<Node>>,
+        to: Option<Arc<Node>>,
+    ) -> Result<Option<ContextKeyDiff>, MerkleError> {
+        match (from, to) {
+            (Some(from), Some(to)) if from.entry_hash()? == to.entry_hash()? => Ok(None),
+            (Some(from), Some(to)) => match (&from.node_kind, &to.node_kind) {
+                (NodeKind::Leaf, NodeKind::Leaf) => Ok(Some(ContextKeyDiff::Modified {
+                    key,
+                    old_value: merkle.get_blob(&from)?,
+                    new_value: merkle.get_blob(&to)?,
+                })),
+                (NodeKind::NonLeaf, NodeKind::NonLeaf) => {
+                    let from_tree = merkle.get_tree(&merkle.get_entry(&from)?)?.clone();
+                    let to_tree = merkle.get_tree(&merkle.get_entry(&to)?)?.clone();
+                    self.stack.push(DiffFrame::Compare {
+                        path: key,
+                        from: from_tree.into_iter().peekable(),
+                        to: to_tree.into_iter().peekable(),
+                    });
+                    Ok(None)
+                }
+                _ => {
+                    // blob replaced by tree or vice versa, removed values go first
+                    if let Some(added) = self.collect(merkle, key.clone(), &to, true)? {
+                        self.stack.push(DiffFrame::Pending(added));
+                    }
+                    self.collect(merkle, key, &from, false)
+                }
+            },
+            (Some(from), None) => self.collect(merkle, key, &from, false),
+            (None, Some(to)) => self.collect(merkle, key, &to, true),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Returns added (or removed) value of the leaf, tree is pushed to the stack to be walked later
+    fn collect(
+        &mut self,
+        merkle: &MerkleStorage,
+        key: ContextKey,
+        node: &Node,
+        added: bool,
+    ) -> Result<Option<ContextKeyDiff>, MerkleError> {
+        match merkle.get_entry(node)? {
+            Entry::Blob(value) if added => Ok(Some(ContextKeyDiff::Added { key, value })),
+            Entry::Blob(value) => Ok(Some(ContextKeyDiff::Removed { key, value })),
+            Entry::Tree(tree) => {
+                self.stack.push(DiffFrame::Collect {
+                    path: key,
+                    children: tree.into_iter(),
+                    added,
+                });
+                Ok(None)
+            }
+            Entry::Commit(_) => Err(MerkleError::FoundUnexpectedStructure {
+                sought: "Tree/Blob".to_string(),
+                found: "Commit".to_string(),
+            }),
+        }
+    }
+}
+
 /// Merkle storage predefined tests with abstraction for underlaying kv_store for context
 #[cfg(test)]
 mod tests {
@@ -1463,6 +2022,212 @@ mod tests {
         assert_eq!(storage.working_tree_checkout(1).is_err(), false);
     }
 
+    fn test_diff(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let mut storage = MerkleStorage::new(kv_store_factory.create("test_diff").unwrap());
+        let key = |path: &str| -> ContextKey { path.split('/').map(|s| s.to_string()).collect() };
+
+        storage.set(1, &key("a/b"), vec![1]).unwrap();
+        storage.set(2, &key("a/c"), vec![2]).unwrap();
+        storage.set(3, &key("d"), vec![3]).unwrap();
+        storage.set(4, &key("e/f"), vec![4]).unwrap();
+        let commit1 = storage
+            .commit(0, "Tezos".to_string(), "1".to_string())
+            .unwrap();
+
+        storage.set(5, &key("a/b"), vec![10]).unwrap();
+        storage.delete(6, &key("a/c")).unwrap();
+        storage.delete(7, &key("d")).unwrap();
+        storage.set(8, &key("d/x"), vec![6]).unwrap();
+        storage.set(9, &key("g/h"), vec![5]).unwrap();
+        let commit2 = storage
+            .commit(1, "Tezos".to_string(), "2".to_string())
+            .unwrap();
+        let diff = |from: &EntryHash, to: &EntryHash, prefix: &ContextKey| -> Vec<ContextKeyDiff> {
+            storage
+                .diff(from, to, prefix)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+
+        assert_eq!(
+            diff(&commit1, &commit2, &vec![]),
+            vec![
+                ContextKeyDiff::Modified {
+                    key: key("a/b"),
+                    old_value: vec![1],
+                    new_value: vec![10]
+                },
+                ContextKeyDiff::Removed {
+                    key: key("a/c"),
+                    value: vec![2]
+                },
+                ContextKeyDiff::Removed {
+                    key: key("d"),
+                    value: vec![3]
+                },
+                ContextKeyDiff::Added {
+                    key: key("d/x"),
+                    value: vec![6]
+                },
+                ContextKeyDiff::Added {
+                    key: key("g/h"),
+                    value: vec![5]
+                },
+            ]
+        );
+        // tree replaced by blob - removed values go first
+        assert_eq!(
+            diff(&commit2, &commit1, &key("d")),
+            vec![
+                ContextKeyDiff::Removed {
+                    key: key("d/x"),
+                    value: vec![6]
+                },
+                ContextKeyDiff::Added {
+                    key: key("d"),
+                    value: vec![3]
+                },
+            ]
+        );
+        assert_eq!(diff(&commit1, &commit2, &key("a")).len(), 2);
+        assert_eq!(
+            diff(&commit2, &commit1, &key("g")),
+            vec![ContextKeyDiff::Removed {
+                key: key("g/h"),
+                value: vec![5]
+            }]
+        );
+        assert!(diff(&commit1, &commit2, &key("e")).is_empty());
+        assert!(diff(&commit1, &commit1, &vec![]).is_empty());
+
+        // cursor does not borrow the storage between the changes
+        let mut cursor = storage.diff_cursor(&commit1, &commit2, &vec![]).unwrap();
+        let mut changes = Vec::new();
+        while let Some(change) = cursor.next_change(&storage) {
+            changes.push(change.unwrap());
+        }
+        assert_eq!(diff(&commit1, &commit2, &vec![]), changes);
+    }
+
+    fn test_iter_subtree(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+        let mut storage = MerkleStorage::new(kv_store_factory.create("test_iter_subtree").unwrap());
+        let key = |path: &str| -> ContextKey { path.split('/').map(|s| s.to_string()).collect() };
+        let keys = |iterator: SubtreeIterator| -> Vec<(ContextKey, SubtreeEntry)> {
+            iterator.collect::<Result<_, _>>().unwrap()
+        };
+
+        storage.set(1, &key("a/b"), vec![1]).unwrap();
+        storage.set(2, &key("a/c/d"), vec![2]).unwrap();
+        storage.set(3, &key("a/c/e"), vec![3]).unwrap();
+        storage.set(4, &key("a/f"), vec![4]).unwrap();
+        storage.set(5, &key("g"), vec![5]).unwrap();
+        let commit = storage
+            .commit(0, "Tezos".to_string(), "1".to_string())
+            .unwrap();
+
+        // whole subtree
+        assert_eq!(
+            keys(
+                storage
+                    .iter_subtree(&commit, &key("a"), None, None)
+                    .unwrap()
+            ),
+            vec![
+                (key("a/b"), SubtreeEntry::Blob(vec![1])),
+                (key("a/c/d"), SubtreeEntry::Blob(vec![2])),
+                (key("a/c/e"), SubtreeEntry::Blob(vec![3])),
+                (key("a/f"), SubtreeEntry::Blob(vec![4])),
+            ]
+        );
+
+        // depth limit
+        assert_eq!(
+            keys(
+                storage
+                    .iter_subtree(&commit, &vec![], Some(1), None)
+                    .unwrap()
+            ),
+            vec![
+                (key("a"), SubtreeEntry::Tree),
+                (key("g"), SubtreeEntry::Blob(vec![5])),
+            ]
+        );
+        assert_eq!(
+            keys(
+                storage
+                    .iter_subtree(&commit, &key("a"), Some(1), None)
+                    .unwrap()
+            ),
+            vec![
+                (key("a/b"), SubtreeEntry::Blob(vec![1])),
+                (key("a/c"), SubtreeEntry::Tree),
+                (key("a/f"), SubtreeEntry::Blob(vec![4])),
+            ]
+        );
+        assert!(keys(
+            storage
+                .iter_subtree(&commit, &key("a"), Some(0), None)
+                .unwrap()
+        )
+        .is_empty());
+
+        // continuation after key
+        assert_eq!(
+            keys(
+                storage
+                    .iter_subtree(&commit, &key("a"), None, Some(&key("a/c/d")))
+                    .unwrap()
+            ),
+            vec![
+                (key("a/c/e"), SubtreeEntry::Blob(vec![3])),
+                (key("a/f"), SubtreeEntry::Blob(vec![4])),
+            ]
+        );
+        assert_eq!(
+            keys(
+                storage
+                    .iter_subtree(&commit, &vec![], Some(1), Some(&key("a")))
+                    .unwrap()
+            ),
+            vec![(key("g"), SubtreeEntry::Blob(vec![5]))]
+        );
+        // paging by after key returns the same keys as one iteration
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let page = keys(
+                storage
+                    .iter_subtree(&commit, &vec![], None, after.as_ref())
+                    .unwrap(),
+            )
+            .into_iter()
+            .take(2)
+            .collect::<Vec<_>>();
+            if page.is_empty() {
+                break;
+            }
+            after = page.last().map(|(key, _)| key.clone());
+            paged.extend(page);
+        }
+        assert_eq!(
+            paged,
+            keys(storage.iter_subtree(&commit, &vec![], None, None).unwrap())
+        );
+
+        // not existing prefix, after key not under prefix
+        assert!(keys(
+            storage
+                .iter_subtree(&commit, &key("x"), None, None)
+                .unwrap()
+        )
+        .is_empty());
+        assert!(matches!(
+            storage.iter_subtree(&commit, &key("a"), None, Some(&key("g"))),
+            Err(MerkleError::KeyNotUnderPrefix { .. })
+        ));
+    }
+
     macro_rules! tests_with_storage {
         ($storage_tests_name:ident, $kv_store_factory:expr) => {
             mod $storage_tests_name {
@@ -1527,6 +2292,14 @@ mod tests {
                     super::test_backtracking_on_delete($kv_store_factory)
                 }
                 #[test]
+                fn test_diff() {
+                    super::test_diff($kv_store_factory)
+                }
+                #[test]
+                fn test_iter_subtree() {
+                    super::test_iter_subtree($kv_store_factory)
+                }
+                #[test]
                 fn test_fail_to_checkout_stage_from_before_commit() {
                     super::test_checkout_stage_from_before_commit($kv_store_factory)
                 }
@@ -1536,6 +2309,7 @@ mod tests {
 
     lazy_static::lazy_static! {
         static ref SUPPORTED_KV_STORES: std::collections::HashMap<SupportedContextKeyValueStore, TestContextKvStoreFactoryInstance> = crate::context::kv_store::test_support::all_kv_stores(out_dir_path());
+        static ref COMPRESSED_KV_STORES: std::collections::HashMap<SupportedContextKeyValueStore, TestContextKvStoreFactoryInstance> = crate::context::kv_store::test_support::compressed_kv_stores(out_dir_path());
     }
 
     fn out_dir_path() -> PathBuf {
@@ -1579,6 +2353,46 @@ mod tests {
                     )
                     .unwrap()
             );
+            tests_with_storage!(
+                kv_store_pack_file_tests,
+                super::SUPPORTED_KV_STORES
+                    .get(
+                        &crate::context::kv_store::SupportedContextKeyValueStore::PackFile {
+                            path: super::out_dir_path()
+                        }
+                    )
+                    .unwrap()
+            );
+            tests_with_storage!(
+                kv_store_compressed_rocksdb_tests,
+                super::COMPRESSED_KV_STORES
+                    .get(
+                        &crate::context::kv_store::SupportedContextKeyValueStore::RocksDB {
+                            path: super::out_dir_path()
+                        }
+                    )
+                    .unwrap()
+            );
+            tests_with_storage!(
+                kv_store_compressed_sled_tests,
+                super::COMPRESSED_KV_STORES
+                    .get(
+                        &crate::context::kv_store::SupportedContextKeyValueStore::Sled {
+                            path: super::out_dir_path()
+                        }
+                    )
+                    .unwrap()
+            );
+            tests_with_storage!(
+                kv_store_compressed_pack_file_tests,
+                super::COMPRESSED_KV_STORES
+                    .get(
+                        &crate::context::kv_store::SupportedContextKeyValueStore::PackFile {
+                            path: super::out_dir_path()
+                        }
+                    )
+                    .unwrap()
+            );
         };
     }
 
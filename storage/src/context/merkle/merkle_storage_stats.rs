@@ -4,6 +4,9 @@ use std::collections::HashMap;
 use std::fmt;
 use std::time::Instant;
 
+use crate::context::gc::GcStats;
+use crate::context::kv_store::compressed::CompressionStats;
+
 /// Latency statistics for each action (in nanoseconds)
 #[derive(Serialize, Debug, Clone, Copy)]
 pub struct OperationLatencies {
@@ -46,6 +49,10 @@ pub type PerPathOperationStats = HashMap<String, OperationLatencyStats>;
 pub struct MerkleStoragePerfReport {
     pub perf_stats: MerklePerfStats,
     pub kv_store_stats: usize,
+    /// Progress of the context garbage collection (if supported by store)
+    pub gc_stats: Option<GcStats>,
+    /// Savings of the context entries compression (if store compresses entries)
+    pub compression_stats: Option<CompressionStats>,
 }
 
 #[derive(Serialize, Default, Debug, Clone)]
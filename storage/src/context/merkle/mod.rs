@@ -11,12 +11,16 @@ use crate::context::ContextValue;
 use self::hash::{hash_entry, HashingError};
 
 pub mod hash;
+pub mod interner;
 pub mod merkle_storage;
 pub mod merkle_storage_stats;
+pub mod proof;
 
 // Tree must be an ordered structure for consistent hash in hash_tree.
 // The entry names *must* be in lexicographical order, as required by the hashing algorithm.
-// Currently immutable OrdMap is used to allow cloning trees without too much overhead.
+// Currently immutable OrdMap is used to allow cloning trees without too much overhead,
+// names are shared by [interner::StringInterner] and directories with >256 entries are hashed as inodes (see [hash::hash_tree]),
+// but they are still stored as a whole (see `storage/docs/merkle_storage_inodes.md`).
 pub type Tree = im::OrdMap<Arc<String>, Arc<Node>>;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -0,0 +1,478 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Inclusion (and non-inclusion) proofs of context keys.
+//!
+//! Proof contains everything needed to recompute the context (commit) hash from the proved value:
+//! - all the other entries (siblings) of every directory on the path of the key,
+//!   for directories with >256 entries just the inodes on the path (see [hash::inode_path])
+//! - fields of the commit except the root tree hash
+//!
+//! Proof can be checked by [verify_proof] without any access to the storage.
+
+use std::convert::TryInto;
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+
+use crate::context::merkle::hash::{
+    self, hash_blob, hash_commit, hash_inode_tree, hash_inode_value, hash_short_tree, EntryHash,
+    HashingError, InodePath, MAX_SHORT_TREE_ENTRIES,
+};
+use crate::context::merkle::{Commit, Node, NodeKind, Tree};
+use crate::context::{ContextKey, ContextValue};
+
+/// Proof, that the key contains (or does not contain) a value in the context identified by commit hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub key: ContextKey,
+    pub commit: CommitProof,
+    /// Directories on the path of the key starting with the root, n-th directory contains n-th segment of the key
+    pub path: Vec<TreeProof>,
+    pub target: ProofTarget,
+}
+
+impl MerkleProof {
+    /// Value stored under the key, `None` means that there is no value under the key
+    pub fn value(&self) -> Option<&ContextValue> {
+        match &self.target {
+            ProofTarget::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Commit fields needed to calculate commit hash from the root tree hash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitProof {
+    #[serde(with = "hex_hash_opt")]
+    pub parent_commit_hash: Option<EntryHash>,
+    pub time: u64,
+    pub author: String,
+    pub message: String,
+}
+
+impl From<&Commit> for CommitProof {
+    fn from(commit: &Commit) -> Self {
+        Self {
+            parent_commit_hash: commit.parent_commit_hash,
+            time: commit.time,
+            author: commit.author.clone(),
+            message: commit.message.clone(),
+        }
+    }
+}
+
+/// Directory on the path of the key without the entry for the key segment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TreeProof {
+    /// Directory with up to 256 entries
+    Short { siblings: Vec<ProofEntry> },
+    /// Directory with >256 entries, `inodes` are the inode trees on the path (starting with depth 0)
+    /// and `siblings` are the other entries of the inode value at the end of the path
+    Long {
+        inodes: Vec<InodeProof>,
+        siblings: Vec<ProofEntry>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InodeProof {
+    pub children: usize,
+    /// Non-empty pointers except the one on the path
+    pub pointers: Vec<InodePointer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InodePointer {
+    pub index: u8,
+    #[serde(with = "hex_hash")]
+    pub hash: EntryHash,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProofEntry {
+    pub name: String,
+    pub kind: NodeKind,
+    #[serde(with = "hex_hash")]
+    pub hash: EntryHash,
+}
+
+/// What is at the end of the path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProofTarget {
+    /// Key contains the value
+    Value(#[serde(with = "hex_bytes")] ContextValue),
+    /// Last directory of the path does not contain the entry, so the key does not exist
+    Missing,
+    /// Prefix of the key (`path.len()` segments) is a value, so the key cannot exist
+    Blob(#[serde(with = "hex_hash")] EntryHash),
+    /// Key is a directory, not a value
+    Tree(#[serde(with = "hex_hash")] EntryHash),
+}
+
+#[derive(Debug, Fail)]
+pub enum MerkleProofError {
+    #[fail(display = "Invalid proof, reason: {}", reason)]
+    InvalidProof { reason: String },
+    #[fail(
+        display = "Proof does not match context hash, expected: {}, calculated: {}",
+        expected, calculated
+    )]
+    HashMismatch {
+        expected: String,
+        calculated: String,
+    },
+    #[fail(display = "Failed to calculate hash: {}", error)]
+    HashingError { error: HashingError },
+}
+
+impl From<HashingError> for MerkleProofError {
+    fn from(error: HashingError) -> Self {
+        MerkleProofError::HashingError { error }
+    }
+}
+
+fn invalid_proof<T>(reason: impl Into<String>) -> Result<T, MerkleProofError> {
+    Err(MerkleProofError::InvalidProof {
+        reason: reason.into(),
+    })
+}
+
+impl ProofEntry {
+    fn new(name: &str, node: &Node) -> Result<Self, HashingError> {
+        Ok(Self {
+            name: name.to_string(),
+            kind: node.node_kind.clone(),
+            hash: node.entry_hash()?,
+        })
+    }
+}
+
+impl TreeProof {
+    /// Creates proof of the directory `tree` without the entry `name` (which does not need to exist)
+    pub(crate) fn new(tree: &Tree, name: &str) -> Result<Self, HashingError> {
+        if tree.len() > MAX_SHORT_TREE_ENTRIES {
+            let InodePath { inodes, siblings } = hash::inode_path(tree, name)?;
+            Ok(TreeProof::Long {
+                inodes: inodes
+                    .into_iter()
+                    .map(|(children, pointers)| InodeProof {
+                        children,
+                        pointers: pointers
+                            .into_iter()
+                            .map(|(index, hash)| InodePointer { index, hash })
+                            .collect(),
+                    })
+                    .collect(),
+                siblings: siblings
+                    .into_iter()
+                    .map(|(entry_name, node)| ProofEntry::new(entry_name, node))
+                    .collect::<Result<_, _>>()?,
+            })
+        } else {
+            Ok(TreeProof::Short {
+                siblings: tree
+                    .iter()
+                    .filter(|(entry_name, _)| entry_name.as_str() != name)
+                    .map(|(entry_name, node)| ProofEntry::new(entry_name, node))
+                    .collect::<Result<_, _>>()?,
+            })
+        }
+    }
+
+    /// Calculates hash of the directory with the entry `name` (`None` - directory does not contain `name`)
+    fn hash(
+        &self,
+        name: &str,
+        entry: Option<&(NodeKind, EntryHash)>,
+    ) -> Result<EntryHash, MerkleProofError> {
+        match self {
+            TreeProof::Short { siblings } => {
+                let entries = with_entry(siblings, name, entry)?;
+                if entries.len() > MAX_SHORT_TREE_ENTRIES {
+                    return invalid_proof("too many entries in short directory");
+                }
+                Ok(hash_short_tree(&entries)?)
+            }
+            TreeProof::Long { inodes, siblings } => {
+                if inodes.is_empty() {
+                    return invalid_proof("missing inodes of long directory");
+                }
+
+                let entries = with_entry(siblings, name, entry)?;
+                let mut hash = if entries.is_empty() {
+                    None
+                } else {
+                    Some(hash_inode_value(&entries)?)
+                };
+
+                for (depth, inode) in inodes.iter().enumerate().rev() {
+                    let depth = depth as u32;
+                    let on_path = hash::index(depth, name);
+                    if inode.pointers.iter().any(|p| p.index == on_path) {
+                        return invalid_proof(format!(
+                            "inode on depth {} contains pointer on the path",
+                            depth
+                        ));
+                    }
+
+                    let mut pointers: Vec<(u8, EntryHash)> =
+                        inode.pointers.iter().map(|p| (p.index, p.hash)).collect();
+                    if let Some(hash) = hash {
+                        pointers.push((on_path, hash));
+                    }
+                    pointers.sort_by_key(|(index, _)| *index);
+
+                    hash = Some(hash_inode_tree(depth, inode.children, &pointers)?);
+                }
+
+                hash.ok_or(MerkleProofError::HashingError {
+                    error: HashingError::UnexpectedEmptyInode,
+                })
+            }
+        }
+    }
+}
+
+/// Puts the entry `name` between (sorted) siblings
+fn with_entry<'a>(
+    siblings: &'a [ProofEntry],
+    name: &'a str,
+    entry: Option<&'a (NodeKind, EntryHash)>,
+) -> Result<Vec<(&'a str, &'a NodeKind, EntryHash)>, MerkleProofError> {
+    if siblings.windows(2).any(|pair| pair[0].name >= pair[1].name) {
+        return invalid_proof("entries are not sorted by name");
+    }
+    let position = match siblings.binary_search_by(|s| s.name.as_str().cmp(name)) {
+        Ok(_) => return invalid_proof(format!("entry {} is listed as a sibling", name)),
+        Err(position) => position,
+    };
+
+    let mut entries: Vec<(&str, &NodeKind, EntryHash)> = siblings
+        .iter()
+        .map(|s| (s.name.as_str(), &s.kind, s.hash))
+        .collect();
+    if let Some((kind, hash)) = entry {
+        entries.insert(position, (name, kind, *hash));
+    }
+    Ok(entries)
+}
+
+/// Checks, that the proof leads to the context hash.
+///
+/// On success, [MerkleProof::target] is what the context contains under [MerkleProof::key].
+pub fn verify_proof(proof: &MerkleProof, context_hash: &EntryHash) -> Result<(), MerkleProofError> {
+    let key_len = proof.key.len();
+    let path_len = proof.path.len();
+
+    let mut entry = match &proof.target {
+        ProofTarget::Value(value) if key_len > 0 && path_len == key_len => {
+            Some((NodeKind::Leaf, hash_blob(value)?))
+        }
+        ProofTarget::Tree(hash) if path_len == key_len => Some((NodeKind::NonLeaf, *hash)),
+        ProofTarget::Blob(hash) if path_len > 0 && path_len < key_len => {
+            Some((NodeKind::Leaf, *hash))
+        }
+        ProofTarget::Missing if path_len > 0 && path_len <= key_len => None,
+        _ => return invalid_proof("length of the path does not match the target"),
+    };
+
+    for (name, tree) in proof.key.iter().zip(proof.path.iter()).rev() {
+        let hash = tree.hash(name, entry.as_ref())?;
+        entry = Some((NodeKind::NonLeaf, hash));
+    }
+
+    let root_hash = match entry {
+        Some((NodeKind::NonLeaf, hash)) => hash,
+        _ => return invalid_proof("root is not a directory"),
+    };
+    let calculated = hash_commit(&Commit {
+        parent_commit_hash: proof.commit.parent_commit_hash,
+        root_hash,
+        time: proof.commit.time,
+        author: proof.commit.author.clone(),
+        message: proof.commit.message.clone(),
+    })?;
+
+    if &calculated == context_hash {
+        Ok(())
+    } else {
+        Err(MerkleProofError::HashMismatch {
+            expected: hex::encode(context_hash),
+            calculated: hex::encode(calculated),
+        })
+    }
+}
+
+mod hex_hash {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &EntryHash, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<EntryHash, D::Error> {
+        let bytes = hex::decode(String::deserialize(d)?).map_err(D::Error::custom)?;
+        bytes.as_slice().try_into().map_err(D::Error::custom)
+    }
+}
+
+mod hex_hash_opt {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(hash: &Option<EntryHash>, s: S) -> Result<S::Ok, S::Error> {
+        match hash {
+            Some(hash) => s.serialize_some(&hex::encode(hash)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<EntryHash>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "hex_hash")] EntryHash);
+
+        Ok(Option::<Wrapper>::deserialize(d)?.map(|Wrapper(hash)| hash))
+    }
+}
+
+mod hex_bytes {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::context::kv_store::btree_map::BTreeMapBackend;
+    use crate::context::merkle::merkle_storage::MerkleStorage;
+
+    use super::*;
+
+    fn key(path: &str) -> ContextKey {
+        path.split('/').map(|s| s.to_string()).collect()
+    }
+
+    fn create_storage() -> (MerkleStorage, EntryHash) {
+        let mut storage = MerkleStorage::new(Box::new(BTreeMapBackend::new()));
+        storage.set(1, &key("data/version"), vec![1]).unwrap();
+        storage.set(2, &key("data/a/b/c"), vec![1, 2, 3]).unwrap();
+        // directory with >256 entries is hashed as inodes
+        for i in 0..1000 {
+            storage
+                .set(
+                    3 + i,
+                    &key(&format!("data/contracts/c{}", i)),
+                    vec![i as u8],
+                )
+                .unwrap();
+        }
+        let commit_hash = storage
+            .commit(1, "Tezos".to_string(), "Genesis".to_string())
+            .unwrap();
+        (storage, commit_hash)
+    }
+
+    #[test]
+    fn test_inclusion_proofs() {
+        let (storage, commit_hash) = create_storage();
+
+        for (path, value) in &[
+            ("data/version", vec![1]),
+            ("data/a/b/c", vec![1, 2, 3]),
+            ("data/contracts/c0", vec![0]),
+            ("data/contracts/c517", vec![5]),
+        ] {
+            let proof = storage.get_merkle_proof(&commit_hash, &key(path)).unwrap();
+            assert_eq!(proof.value(), Some(value));
+            verify_proof(&proof, &commit_hash).unwrap();
+        }
+
+        let proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/a"))
+            .unwrap();
+        assert!(matches!(proof.target, ProofTarget::Tree(_)));
+        verify_proof(&proof, &commit_hash).unwrap();
+    }
+
+    #[test]
+    fn test_non_inclusion_proofs() {
+        let (storage, commit_hash) = create_storage();
+
+        for path in &[
+            "data/missing",
+            "data/a/missing/x",
+            "data/contracts/c1000",
+            "data/version/x",
+        ] {
+            let proof = storage.get_merkle_proof(&commit_hash, &key(path)).unwrap();
+            assert_eq!(proof.value(), None);
+            verify_proof(&proof, &commit_hash).unwrap();
+        }
+
+        let proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/version/x"))
+            .unwrap();
+        assert_eq!(proof.path.len(), 2);
+        assert!(matches!(proof.target, ProofTarget::Blob(_)));
+    }
+
+    #[test]
+    fn test_forged_proofs_are_rejected() {
+        let (storage, commit_hash) = create_storage();
+
+        // changed value
+        let mut proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/contracts/c7"))
+            .unwrap();
+        proof.target = ProofTarget::Value(vec![8]);
+        assert!(matches!(
+            verify_proof(&proof, &commit_hash),
+            Err(MerkleProofError::HashMismatch { .. })
+        ));
+
+        // existing value proved as missing
+        let mut proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/version"))
+            .unwrap();
+        proof.target = ProofTarget::Missing;
+        assert!(verify_proof(&proof, &commit_hash).is_err());
+
+        // existing entry hidden from the siblings
+        let mut proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/missing"))
+            .unwrap();
+        if let Some(TreeProof::Short { siblings }) = proof.path.last_mut() {
+            siblings.retain(|s| s.name != "version");
+        }
+        assert!(verify_proof(&proof, &commit_hash).is_err());
+
+        // proof of another context
+        let proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/version"))
+            .unwrap();
+        assert!(verify_proof(&proof, &[0; 32]).is_err());
+    }
+
+    #[test]
+    fn test_proof_json_roundtrip() {
+        let (storage, commit_hash) = create_storage();
+
+        let proof = storage
+            .get_merkle_proof(&commit_hash, &key("data/contracts/c42"))
+            .unwrap();
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: MerkleProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(proof, decoded);
+        verify_proof(&decoded, &commit_hash).unwrap();
+    }
+}
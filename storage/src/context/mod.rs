@@ -13,12 +13,13 @@ use serde::Serialize;
 pub use actions::ActionRecorder;
 use crypto::hash::{BlockHash, ContextHash, FromBytesError};
 pub use merkle::hash::EntryHash;
-pub use tezedge_context::TezedgeContext;
+pub use tezedge_context::{ContextDiff, TezedgeContext};
 use tezos_context::channel::ContextAction;
 
 use crate::context::gc::GarbageCollector;
-use crate::context::merkle::merkle_storage::MerkleError;
+use crate::context::merkle::merkle_storage::{MerkleError, SubtreeEntry};
 use crate::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
+use crate::context::merkle::proof::MerkleProof;
 use crate::persistent::{
     Flushable, KeyValueSchema, KeyValueStoreBackend, MultiInstanceable, Persistable,
 };
@@ -48,6 +49,36 @@ pub enum StringTreeEntry {
     Null,
 }
 
+/// Max count of keys skipped by `offset` of [ContextApi::get_context_keys_page],
+/// skipping is done under the merkle storage lock, so deeper pages have to use `after` key
+pub const MAX_CONTEXT_KEYS_PAGE_OFFSET: usize = 10_000;
+
+/// Page of the keys under a prefix, see [ContextApi::get_context_keys_page]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextKeysPage {
+    pub entries: Vec<(ContextKey, SubtreeEntry)>,
+    /// Continuation token - last returned key, if there are more keys after this page
+    pub next_after: Option<ContextKey>,
+}
+
+/// Change of the key between two contexts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContextKeyDiff {
+    Added {
+        key: ContextKey,
+        value: ContextValue,
+    },
+    Removed {
+        key: ContextKey,
+        value: ContextValue,
+    },
+    Modified {
+        key: ContextKey,
+        old_value: ContextValue,
+        new_value: ContextValue,
+    },
+}
+
 /// Abstraction on context manipulation
 pub trait ContextApi {
     // set key-value
@@ -115,6 +146,30 @@ pub trait ContextApi {
         prefix: &ContextKey,
         depth: Option<usize>,
     ) -> Result<StringTreeEntry, ContextError>;
+    // get one page of keys under the prefix (down to `depth` levels), which follow the `after` key
+    // `offset` is limited by MAX_CONTEXT_KEYS_PAGE_OFFSET
+    fn get_context_keys_page(
+        &self,
+        context_hash: &ContextHash,
+        prefix: &ContextKey,
+        depth: Option<usize>,
+        after: Option<&ContextKey>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ContextKeysPage, ContextError>;
+    // get iterator over added, removed and modified keys under the prefix between two contexts
+    fn diff(
+        &self,
+        from: &ContextHash,
+        to: &ContextHash,
+        prefix: &ContextKey,
+    ) -> Result<ContextDiff, ContextError>;
+    // get inclusion/non-inclusion proof of the key in context
+    fn get_merkle_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<MerkleProof, ContextError>;
 
     // get currently checked out hash
     fn get_last_commit_hash(&self) -> Result<Option<Vec<u8>>, ContextError>;
@@ -171,6 +226,11 @@ pub enum ContextError {
     LockError { error: String },
     #[fail(display = "Cannot check if context is commited")]
     CommitStatusCheckFailure,
+    #[fail(
+        display = "Page offset: {} exceeds max offset: {}, use `after` key for next pages",
+        offset, max_offset
+    )]
+    PageOffsetTooBig { offset: usize, max_offset: usize },
 }
 
 impl From<MerkleError> for ContextError {
@@ -12,9 +12,13 @@ use crypto::hash::{BlockHash, ContextHash, FromBytesError};
 use crate::context::actions::context_action_storage::ContextAction;
 use crate::context::actions::{get_new_tree_hash, get_tree_id};
 use crate::context::merkle::hash::EntryHash;
-use crate::context::merkle::merkle_storage::{MerkleError, MerkleStorage};
+use crate::context::merkle::merkle_storage::{DiffCursor, MerkleError, MerkleStorage};
 use crate::context::merkle::merkle_storage_stats::MerkleStoragePerfReport;
-use crate::context::{ContextApi, ContextError, ContextKey, ContextValue, StringTreeEntry, TreeId};
+use crate::context::merkle::proof::MerkleProof;
+use crate::context::{
+    ContextApi, ContextError, ContextKey, ContextKeyDiff, ContextKeysPage, ContextValue,
+    StringTreeEntry, TreeId, MAX_CONTEXT_KEYS_PAGE_OFFSET,
+};
 use crate::{BlockStorage, BlockStorageReader, StorageError};
 
 impl ContextApi for TezedgeContext {
@@ -154,6 +158,72 @@ impl ContextApi for TezedgeContext {
             .map_err(ContextError::from)
     }
 
+    fn get_context_keys_page(
+        &self,
+        context_hash: &ContextHash,
+        prefix: &ContextKey,
+        depth: Option<usize>,
+        after: Option<&ContextKey>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ContextKeysPage, ContextError> {
+        // skipped keys are read under the lock, so do not allow to block merkle for too long
+        if offset > MAX_CONTEXT_KEYS_PAGE_OFFSET {
+            return Err(ContextError::PageOffsetTooBig {
+                offset,
+                max_offset: MAX_CONTEXT_KEYS_PAGE_OFFSET,
+            });
+        }
+
+        let context_hash_arr: EntryHash = context_hash.as_ref().as_slice().try_into()?;
+        let merkle = self.merkle.lock()?;
+
+        // one more key is read to find out, if there is a next page
+        let mut entries = merkle
+            .iter_subtree(&context_hash_arr, prefix, depth, after)?
+            .skip(offset)
+            .take(limit.saturating_add(1))
+            .collect::<Result<Vec<_>, _>>()?;
+        let next_after = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|(key, _)| key.clone())
+        } else {
+            None
+        };
+
+        Ok(ContextKeysPage {
+            entries,
+            next_after,
+        })
+    }
+
+    fn diff(
+        &self,
+        from: &ContextHash,
+        to: &ContextHash,
+        prefix: &ContextKey,
+    ) -> Result<ContextDiff, ContextError> {
+        let from: EntryHash = from.as_ref().as_slice().try_into()?;
+        let to: EntryHash = to.as_ref().as_slice().try_into()?;
+        let cursor = self.merkle.lock()?.diff_cursor(&from, &to, prefix)?;
+        Ok(ContextDiff {
+            merkle: self.merkle.clone(),
+            cursor,
+        })
+    }
+
+    fn get_merkle_proof(
+        &self,
+        context_hash: &ContextHash,
+        key: &ContextKey,
+    ) -> Result<MerkleProof, ContextError> {
+        let context_hash_arr: EntryHash = context_hash.as_ref().as_slice().try_into()?;
+        let merkle = self.merkle.lock()?;
+        merkle
+            .get_merkle_proof(&context_hash_arr, key)
+            .map_err(ContextError::from)
+    }
+
     fn get_last_commit_hash(&self) -> Result<Option<Vec<u8>>, ContextError> {
         let merkle = self.merkle.lock()?;
         Ok(merkle.get_last_commit_hash().map(|x| x.to_vec()))
@@ -322,6 +392,32 @@ pub struct TezedgeContext {
     merkle: Arc<Mutex<MerkleStorage>>,
 }
 
+/// Changes between two contexts, see [ContextApi::diff].
+///
+/// Merkle storage is locked just while the next change is looked up, so the diff can be streamed
+/// without blocking the merkle storage for the whole time.
+pub struct ContextDiff {
+    merkle: Arc<Mutex<MerkleStorage>>,
+    cursor: DiffCursor,
+}
+
+// cursor holds nodes of the merkle trees (same as MerkleStorage), they are accessed just under the merkle lock
+unsafe impl Send for ContextDiff {}
+
+impl Iterator for ContextDiff {
+    type Item = Result<ContextKeyDiff, ContextError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let merkle = match self.merkle.lock() {
+            Ok(merkle) => merkle,
+            Err(e) => return Some(Err(e.into())),
+        };
+        self.cursor
+            .next_change(&merkle)
+            .map(|change| change.map_err(ContextError::from))
+    }
+}
+
 impl TezedgeContext {
     pub fn new(block_storage: Option<BlockStorage>, merkle: Arc<Mutex<MerkleStorage>>) -> Self {
         TezedgeContext {
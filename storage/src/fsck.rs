@@ -0,0 +1,833 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Offline integrity checker for the node storage.
+//!
+//! Walks the whole data directory and verifies:
+//! - every stored block header hashes to its key,
+//! - predecessor/successor links in [BlockMetaStorage] are consistent,
+//! - every [PredecessorStorage] skip-list entry resolves to the block at the expected distance,
+//! - context of every applied block can be fully loaded from the context store with correct entry hashes.
+//!
+//! Block meta links and predecessor entries can be repaired, because they are derived from the block headers.
+//! Missing or corrupted block headers and context entries are just reported.
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::PoisonError;
+
+use failure::Fail;
+use slog::{info, warn, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash};
+use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::encoding::block_header::Level;
+
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::merkle::hash::{hash_entry, EntryHash, HashingError};
+use crate::context::merkle::merkle_storage::{MerkleError, MerkleStorage};
+use crate::context::merkle::Entry;
+use crate::persistent::database::IteratorMode;
+use crate::predecessor_storage::PredecessorKey;
+use crate::{
+    BlockMetaStorage, BlockStorage, BlockStorageReader, ChainMetaStorage, PersistentStorage,
+    PredecessorStorage, StorageError,
+};
+
+/// Possible errors, which stop the check
+#[derive(Debug, Fail)]
+pub enum FsckError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Hashing error: {}", error)]
+    HashingError { error: HashingError },
+    #[fail(display = "Failed to lock merkle storage, reason: {}", reason)]
+    LockError { reason: String },
+}
+
+impl From<StorageError> for FsckError {
+    fn from(error: StorageError) -> Self {
+        FsckError::StorageError { error }
+    }
+}
+
+impl From<HashingError> for FsckError {
+    fn from(error: HashingError) -> Self {
+        FsckError::HashingError { error }
+    }
+}
+
+impl<T> From<PoisonError<T>> for FsckError {
+    fn from(pe: PoisonError<T>) -> Self {
+        FsckError::LockError {
+            reason: format!("{}", pe),
+        }
+    }
+}
+
+impl slog::Value for FsckError {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Inconsistency found in the storage
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    /// Block header is indexed, but cannot be read from the commit log
+    UnreadableBlockHeader {
+        block_hash: BlockHash,
+        reason: String,
+    },
+    /// Stored block header hashes to another hash, than it is indexed by
+    BlockHeaderHashMismatch {
+        block_hash: BlockHash,
+        found: BlockHash,
+    },
+    /// Record (key or value) of the storage cannot be decoded
+    CorruptRecord {
+        storage: &'static str,
+        reason: String,
+    },
+    /// Block meta references predecessor, which has no meta
+    MissingPredecessorMeta {
+        block_hash: BlockHash,
+        predecessor: BlockHash,
+    },
+    /// Block level does not follow the level of its predecessor
+    InvalidLevel {
+        block_hash: BlockHash,
+        level: Level,
+        predecessor_level: Level,
+    },
+    /// Predecessor does not list block as its successor (repairable)
+    MissingSuccessorLink {
+        block_hash: BlockHash,
+        predecessor: BlockHash,
+    },
+    /// Block lists successor, which does not exist or has another predecessor (repairable)
+    InvalidSuccessorLink {
+        block_hash: BlockHash,
+        successor: BlockHash,
+    },
+    /// Skip-list entry does not point to the block at distance `2^exponent_slot` (repairable)
+    InvalidPredecessorEntry {
+        block_hash: BlockHash,
+        exponent_slot: u32,
+        predecessor: BlockHash,
+    },
+    /// Context entry is referenced, but not found in the context store
+    MissingContextEntry {
+        context_hash: ContextHash,
+        entry_hash: EntryHash,
+    },
+    /// Context entry cannot be decoded or does not hash to its key
+    CorruptContextEntry {
+        context_hash: ContextHash,
+        entry_hash: EntryHash,
+        reason: String,
+    },
+}
+
+impl FsckIssue {
+    /// Returns true, if issue can be fixed by [check_storage] with `repair` enabled
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            FsckIssue::MissingSuccessorLink { .. }
+                | FsckIssue::InvalidSuccessorLink { .. }
+                | FsckIssue::InvalidPredecessorEntry { .. }
+        )
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::UnreadableBlockHeader { block_hash, reason } => write!(
+                f,
+                "block header {} cannot be read, reason: {}",
+                block_hash.to_base58_check(),
+                reason
+            ),
+            FsckIssue::BlockHeaderHashMismatch { block_hash, found } => write!(
+                f,
+                "block header stored under {} has hash {}",
+                block_hash.to_base58_check(),
+                found.to_base58_check()
+            ),
+            FsckIssue::CorruptRecord { storage, reason } => {
+                write!(f, "corrupted record in {}, reason: {}", storage, reason)
+            }
+            FsckIssue::MissingPredecessorMeta {
+                block_hash,
+                predecessor,
+            } => write!(
+                f,
+                "block {} has predecessor {} without metadata",
+                block_hash.to_base58_check(),
+                predecessor.to_base58_check()
+            ),
+            FsckIssue::InvalidLevel {
+                block_hash,
+                level,
+                predecessor_level,
+            } => write!(
+                f,
+                "block {} has level {}, but its predecessor has level {}",
+                block_hash.to_base58_check(),
+                level,
+                predecessor_level
+            ),
+            FsckIssue::MissingSuccessorLink {
+                block_hash,
+                predecessor,
+            } => write!(
+                f,
+                "block {} is not listed as a successor of its predecessor {}",
+                block_hash.to_base58_check(),
+                predecessor.to_base58_check()
+            ),
+            FsckIssue::InvalidSuccessorLink {
+                block_hash,
+                successor,
+            } => write!(
+                f,
+                "block {} lists successor {}, which does not point back to it",
+                block_hash.to_base58_check(),
+                successor.to_base58_check()
+            ),
+            FsckIssue::InvalidPredecessorEntry {
+                block_hash,
+                exponent_slot,
+                predecessor,
+            } => write!(
+                f,
+                "predecessor {} in slot {} of block {} is not at distance 2^{}",
+                predecessor.to_base58_check(),
+                exponent_slot,
+                block_hash.to_base58_check(),
+                exponent_slot
+            ),
+            FsckIssue::MissingContextEntry {
+                context_hash,
+                entry_hash,
+            } => write!(
+                f,
+                "context {} references missing entry {}",
+                context_hash.to_base58_check(),
+                hex::encode(entry_hash)
+            ),
+            FsckIssue::CorruptContextEntry {
+                context_hash,
+                entry_hash,
+                reason,
+            } => write!(
+                f,
+                "context {} references corrupted entry {}, reason: {}",
+                context_hash.to_base58_check(),
+                hex::encode(entry_hash),
+                reason
+            ),
+        }
+    }
+}
+
+/// Result of the storage check
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Count of checked block headers
+    pub checked_blocks: usize,
+    /// Count of checked (distinct) context entries
+    pub checked_context_entries: usize,
+    /// All found issues (including the repaired ones)
+    pub issues: Vec<FsckIssue>,
+    /// Count of repaired issues
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    /// Returns true, if storage contains issues, which were not repaired
+    pub fn has_unrepaired_issues(&self) -> bool {
+        self.issues.len() > self.repaired
+    }
+}
+
+/// Checks integrity of the whole storage for chain `chain_id`.
+///
+/// If `repair` is enabled, repairable issues (block meta links and predecessor entries) are fixed in place.
+pub fn check_storage(
+    persistent_storage: &PersistentStorage,
+    chain_id: &ChainId,
+    repair: bool,
+    log: &Logger,
+) -> Result<FsckReport, FsckError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let predecessor_storage = PredecessorStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+
+    // blocks below caboose are pruned, so their predecessors are not expected to be found
+    let caboose_level = chain_meta_storage
+        .get_caboose(chain_id)?
+        .map(|caboose| *caboose.level());
+    // contexts below save point are not required to be stored
+    let save_point_level = chain_meta_storage
+        .get_save_point(chain_id)?
+        .map(|save_point| *save_point.level());
+
+    let mut report = FsckReport::default();
+
+    info!(log, "Checking block headers");
+    check_block_headers(&block_storage, &mut report)?;
+
+    info!(log, "Checking block metadata links");
+    check_block_meta_links(&block_meta_storage, caboose_level, &mut report)?;
+
+    info!(log, "Checking predecessor index");
+    check_predecessors(&block_meta_storage, &predecessor_storage, &mut report)?;
+
+    info!(log, "Checking contexts");
+    check_contexts(
+        persistent_storage,
+        &block_storage,
+        &block_meta_storage,
+        save_point_level,
+        &mut report,
+    )?;
+
+    for issue in &report.issues {
+        warn!(log, "Found storage issue"; "issue" => issue.to_string());
+    }
+
+    if repair {
+        report.repaired = repair_issues(&block_meta_storage, &predecessor_storage, &report.issues)?;
+    }
+
+    info!(log, "Storage check finished";
+               "checked_blocks" => report.checked_blocks,
+               "checked_context_entries" => report.checked_context_entries,
+               "issues" => report.issues.len(),
+               "repaired" => report.repaired);
+
+    Ok(report)
+}
+
+fn check_block_headers(
+    block_storage: &BlockStorage,
+    report: &mut FsckReport,
+) -> Result<(), FsckError> {
+    for (key, _) in block_storage.iterator()? {
+        let block_hash = match key {
+            Ok(block_hash) => block_hash,
+            Err(e) => {
+                report.issues.push(FsckIssue::CorruptRecord {
+                    storage: "block_storage",
+                    reason: format!("{}", e),
+                });
+                continue;
+            }
+        };
+        report.checked_blocks += 1;
+
+        match block_storage.get(&block_hash) {
+            Ok(Some(block)) => {
+                let found: BlockHash = block
+                    .header
+                    .message_typed_hash()
+                    .map_err(StorageError::from)?;
+                if found != block_hash {
+                    report
+                        .issues
+                        .push(FsckIssue::BlockHeaderHashMismatch { block_hash, found });
+                }
+            }
+            Ok(None) => report.issues.push(FsckIssue::UnreadableBlockHeader {
+                block_hash,
+                reason: "not found".to_string(),
+            }),
+            Err(e) => report.issues.push(FsckIssue::UnreadableBlockHeader {
+                block_hash,
+                reason: format!("{}", e),
+            }),
+        }
+    }
+    Ok(())
+}
+
+fn check_block_meta_links(
+    block_meta_storage: &BlockMetaStorage,
+    caboose_level: Option<Level>,
+    report: &mut FsckReport,
+) -> Result<(), FsckError> {
+    for (key, value) in block_meta_storage.iter(IteratorMode::Start)? {
+        let (block_hash, meta) = match (key, value) {
+            (Ok(block_hash), Ok(meta)) => (block_hash, meta),
+            (Err(e), _) | (_, Err(e)) => {
+                report.issues.push(FsckIssue::CorruptRecord {
+                    storage: "block_meta_storage",
+                    reason: format!("{}", e),
+                });
+                continue;
+            }
+        };
+
+        // placeholders (predecessor not known yet) and genesis have nothing to check
+        if let Some(predecessor) = meta.predecessor() {
+            if predecessor != &block_hash {
+                match block_meta_storage.get(predecessor)? {
+                    Some(predecessor_meta) => {
+                        if predecessor_meta.level() != meta.level() - 1 {
+                            report.issues.push(FsckIssue::InvalidLevel {
+                                block_hash: block_hash.clone(),
+                                level: meta.level(),
+                                predecessor_level: predecessor_meta.level(),
+                            });
+                        }
+                        if !predecessor_meta.successors().contains(&block_hash) {
+                            report.issues.push(FsckIssue::MissingSuccessorLink {
+                                block_hash: block_hash.clone(),
+                                predecessor: predecessor.clone(),
+                            });
+                        }
+                    }
+                    None => {
+                        let pruned = caboose_level
+                            .map(|caboose_level| meta.level() <= caboose_level)
+                            .unwrap_or(false);
+                        if !pruned {
+                            report.issues.push(FsckIssue::MissingPredecessorMeta {
+                                block_hash: block_hash.clone(),
+                                predecessor: predecessor.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        for successor in meta.successors() {
+            let points_back = match block_meta_storage.get(successor)? {
+                Some(successor_meta) => match successor_meta.predecessor() {
+                    Some(successor_predecessor) => successor_predecessor == &block_hash,
+                    None => true,
+                },
+                None => false,
+            };
+            if !points_back {
+                report.issues.push(FsckIssue::InvalidSuccessorLink {
+                    block_hash: block_hash.clone(),
+                    successor: successor.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_predecessors(
+    block_meta_storage: &BlockMetaStorage,
+    predecessor_storage: &PredecessorStorage,
+    report: &mut FsckReport,
+) -> Result<(), FsckError> {
+    for (key, value) in predecessor_storage.iter(IteratorMode::Start)? {
+        let (key, predecessor) = match (key, value) {
+            (Ok(key), Ok(predecessor)) => (key, predecessor),
+            (Err(e), _) | (_, Err(e)) => {
+                report.issues.push(FsckIssue::CorruptRecord {
+                    storage: "predecessor_storage",
+                    reason: format!("{}", e),
+                });
+                continue;
+            }
+        };
+
+        let block_meta = block_meta_storage.get(key.block_hash())?;
+        let predecessor_meta = block_meta_storage.get(&predecessor)?;
+        let valid = match (block_meta, predecessor_meta) {
+            (Some(block_meta), Some(predecessor_meta)) => {
+                let distance = 1_i64 << key.exponent_slot();
+                let level_matches =
+                    i64::from(block_meta.level()) - i64::from(predecessor_meta.level()) == distance;
+                let direct_matches = key.exponent_slot() > 0
+                    || block_meta.predecessor().as_ref() == Some(&predecessor);
+                level_matches && direct_matches
+            }
+            _ => false,
+        };
+
+        if !valid {
+            report.issues.push(FsckIssue::InvalidPredecessorEntry {
+                block_hash: key.block_hash().clone(),
+                exponent_slot: key.exponent_slot(),
+                predecessor,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_contexts(
+    persistent_storage: &PersistentStorage,
+    block_storage: &BlockStorage,
+    block_meta_storage: &BlockMetaStorage,
+    save_point_level: Option<Level>,
+    report: &mut FsckReport,
+) -> Result<(), FsckError> {
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle.lock()?;
+
+    // entries are shared between contexts, so every entry is checked just once
+    let mut checked = HashSet::new();
+
+    for (key, _) in block_meta_storage.iter(IteratorMode::Start)? {
+        let block_hash = match key {
+            Ok(block_hash) => block_hash,
+            // already reported by meta links check
+            Err(_) => continue,
+        };
+        let meta = match block_meta_storage.get(&block_hash)? {
+            Some(meta) if meta.is_applied() => meta,
+            _ => continue,
+        };
+        if let Some(save_point_level) = save_point_level {
+            if meta.level() < save_point_level {
+                continue;
+            }
+        }
+        // unreadable headers are already reported
+        let context_hash = match block_storage.get(&block_hash) {
+            Ok(Some(block)) => block.header.context().clone(),
+            _ => continue,
+        };
+        let commit_hash: EntryHash = match context_hash.as_ref()[..].try_into() {
+            Ok(commit_hash) => commit_hash,
+            Err(e) => {
+                report.issues.push(FsckIssue::CorruptRecord {
+                    storage: "block_storage",
+                    reason: format!(
+                        "invalid context hash of block {}: {}",
+                        block_hash.to_base58_check(),
+                        e
+                    ),
+                });
+                continue;
+            }
+        };
+
+        check_context_entry(&merkle, &context_hash, &commit_hash, &mut checked, report)?;
+    }
+
+    report.checked_context_entries = checked.len();
+    Ok(())
+}
+
+fn check_context_entry(
+    merkle: &MerkleStorage,
+    context_hash: &ContextHash,
+    entry_hash: &EntryHash,
+    checked: &mut HashSet<EntryHash>,
+    report: &mut FsckReport,
+) -> Result<(), FsckError> {
+    if !checked.insert(*entry_hash) {
+        return Ok(());
+    }
+
+    let entry = match merkle.get_entry_by_hash(entry_hash) {
+        Ok(entry) => entry,
+        Err(MerkleError::EntryNotFound { .. }) => {
+            report.issues.push(FsckIssue::MissingContextEntry {
+                context_hash: context_hash.clone(),
+                entry_hash: *entry_hash,
+            });
+            return Ok(());
+        }
+        Err(e) => {
+            report.issues.push(FsckIssue::CorruptContextEntry {
+                context_hash: context_hash.clone(),
+                entry_hash: *entry_hash,
+                reason: format!("{}", e),
+            });
+            return Ok(());
+        }
+    };
+
+    if &hash_entry(&entry)? != entry_hash {
+        report.issues.push(FsckIssue::CorruptContextEntry {
+            context_hash: context_hash.clone(),
+            entry_hash: *entry_hash,
+            reason: "entry does not match its hash".to_string(),
+        });
+        return Ok(());
+    }
+
+    match entry {
+        Entry::Commit(commit) => {
+            check_context_entry(merkle, context_hash, &commit.root_hash, checked, report)
+        }
+        Entry::Tree(tree) => {
+            for (_, node) in tree.iter() {
+                check_context_entry(merkle, context_hash, &node.entry_hash()?, checked, report)?;
+            }
+            Ok(())
+        }
+        Entry::Blob(_) => Ok(()),
+    }
+}
+
+/// Fixes repairable issues and returns count of repaired ones
+fn repair_issues(
+    block_meta_storage: &BlockMetaStorage,
+    predecessor_storage: &PredecessorStorage,
+    issues: &[FsckIssue],
+) -> Result<usize, FsckError> {
+    let mut repaired = 0;
+    let mut recomputed_predecessors = HashSet::new();
+
+    // every put changes count of successors by one, so the merge operator replaces whole successors list
+    for issue in issues {
+        match issue {
+            FsckIssue::MissingSuccessorLink {
+                block_hash,
+                predecessor,
+            } => {
+                if let Some(mut meta) = block_meta_storage.get(predecessor)? {
+                    if !meta.successors().contains(block_hash) {
+                        let mut successors = meta.successors().clone();
+                        successors.push(block_hash.clone());
+                        meta.set_successors(successors);
+                        block_meta_storage.put(predecessor, &meta)?;
+                    }
+                    repaired += 1;
+                }
+            }
+            FsckIssue::InvalidSuccessorLink {
+                block_hash,
+                successor,
+            } => {
+                if let Some(mut meta) = block_meta_storage.get(block_hash)? {
+                    if meta.successors().contains(successor) {
+                        let mut successors = meta.successors().clone();
+                        successors.retain(|s| s != successor);
+                        meta.set_successors(successors);
+                        block_meta_storage.put(block_hash, &meta)?;
+                    }
+                    repaired += 1;
+                }
+            }
+            FsckIssue::InvalidPredecessorEntry {
+                block_hash,
+                exponent_slot,
+                ..
+            } => {
+                predecessor_storage
+                    .delete(&PredecessorKey::new(block_hash.clone(), *exponent_slot))?;
+                // recompute all slots from the block meta, if possible
+                if recomputed_predecessors.insert(block_hash.clone()) {
+                    if let Some(meta) = block_meta_storage.get(block_hash)? {
+                        block_meta_storage.store_predecessors(block_hash, &meta)?;
+                    }
+                }
+                repaired += 1;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use failure::Error;
+    use slog::{Drain, Level};
+
+    use tezos_messages::p2p::encoding::prelude::BlockHeaderBuilder;
+
+    use crate::tests_common::TmpStorage;
+    use crate::{BlockHeaderWithHash, BlockMetaStorageReader};
+
+    use super::*;
+
+    #[test]
+    fn test_check_consistent_storage() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__fsck_consistent")?;
+        let chain_id = ChainId::try_from("NetXgtSLGNJvNye")?;
+        let log = create_logger();
+        let blocks = init_chain(&tmp_storage, &chain_id, 5)?;
+
+        let report = check_storage(tmp_storage.storage(), &chain_id, false, &log)?;
+        assert_eq!(report.checked_blocks, blocks.len());
+        assert!(report.checked_context_entries > 0);
+        assert!(report.issues.is_empty(), "issues: {:?}", report.issues);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_and_repair_broken_links() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__fsck_repair")?;
+        let chain_id = ChainId::try_from("NetXgtSLGNJvNye")?;
+        let log = create_logger();
+        let blocks = init_chain(&tmp_storage, &chain_id, 5)?;
+
+        // break successor link of block 2 and predecessor entry of block 4
+        let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+        let predecessor_storage = PredecessorStorage::new(tmp_storage.storage());
+        let mut meta = block_meta_storage.get(&blocks[2].hash)?.unwrap();
+        meta.set_successors(vec![]);
+        block_meta_storage.put(&blocks[2].hash, &meta)?;
+        predecessor_storage.put(
+            &PredecessorKey::new(blocks[4].hash.clone(), 1),
+            &blocks[1].hash,
+        )?;
+
+        let report = check_storage(tmp_storage.storage(), &chain_id, true, &log)?;
+        assert_eq!(
+            report.issues,
+            vec![
+                FsckIssue::MissingSuccessorLink {
+                    block_hash: blocks[3].hash.clone(),
+                    predecessor: blocks[2].hash.clone(),
+                },
+                FsckIssue::InvalidPredecessorEntry {
+                    block_hash: blocks[4].hash.clone(),
+                    exponent_slot: 1,
+                    predecessor: blocks[1].hash.clone(),
+                },
+            ]
+        );
+        assert_eq!(report.repaired, 2);
+        assert!(!report.has_unrepaired_issues());
+
+        // second run finds nothing
+        let report = check_storage(tmp_storage.storage(), &chain_id, false, &log)?;
+        assert!(report.issues.is_empty(), "issues: {:?}", report.issues);
+        assert_eq!(
+            block_meta_storage.find_block_at_distance(blocks[4].hash.clone(), 2)?,
+            Some(blocks[2].hash.clone())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_missing_context_entry() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create_to_out_dir("__fsck_context")?;
+        let chain_id = ChainId::try_from("NetXgtSLGNJvNye")?;
+        let log = create_logger();
+        let blocks = init_chain(&tmp_storage, &chain_id, 2)?;
+
+        // mark block with unknown context as applied
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+        let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+        let unknown_context = ContextHash::try_from(vec![7; 32].as_slice())?;
+        let block = make_block(2, blocks[1].hash.clone(), unknown_context.clone())?;
+        block_storage.put_block_header(&block)?;
+        let mut meta = block_meta_storage.put_block_header(&block, &chain_id, &log)?;
+        meta.set_is_applied(true);
+        block_meta_storage.put(&block.hash, &meta)?;
+
+        let report = check_storage(tmp_storage.storage(), &chain_id, true, &log)?;
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::MissingContextEntry {
+                context_hash: unknown_context,
+                entry_hash: [7; 32],
+            }]
+        );
+        assert!(report.has_unrepaired_issues());
+
+        Ok(())
+    }
+
+    /// Stores applied chain genesis <- block_1 <- ... <- block_(count - 1), all blocks share one context
+    fn init_chain(
+        tmp_storage: &TmpStorage,
+        chain_id: &ChainId,
+        count: i32,
+    ) -> Result<Vec<BlockHeaderWithHash>, Error> {
+        let log = create_logger();
+        let context_hash = {
+            let merkle = tmp_storage.storage().merkle();
+            let mut merkle = merkle.lock().unwrap();
+            merkle.set(1, &vec!["data".to_string(), "a".to_string()], vec![1, 2, 3])?;
+            let commit_hash = merkle.commit(0, "Tezos".to_string(), "Genesis".to_string())?;
+            ContextHash::try_from(&commit_hash[..])?
+        };
+
+        let block_storage = BlockStorage::new(tmp_storage.storage());
+        let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+        let genesis = make_block(
+            0,
+            BlockHash::try_from("BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe")?,
+            context_hash.clone(),
+        )?;
+        block_storage.put_block_header(&genesis)?;
+        block_meta_storage.put(
+            &genesis.hash,
+            &crate::block_meta_storage::Meta::genesis_meta(&genesis.hash, chain_id, true),
+        )?;
+
+        let mut blocks = vec![genesis];
+        for level in 1..count {
+            let block = make_block(
+                level,
+                blocks.last().unwrap().hash.clone(),
+                context_hash.clone(),
+            )?;
+            block_storage.put_block_header(&block)?;
+            let mut meta = block_meta_storage.put_block_header(&block, chain_id, &log)?;
+            meta.set_is_applied(true);
+            block_meta_storage.put(&block.hash, &meta)?;
+            block_meta_storage.store_predecessors(&block.hash, &meta)?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    fn make_block(
+        level: i32,
+        predecessor: BlockHash,
+        context: ContextHash,
+    ) -> Result<BlockHeaderWithHash, Error> {
+        Ok(BlockHeaderWithHash::new(
+            BlockHeaderBuilder::default()
+                .level(level)
+                .proto(0)
+                .predecessor(predecessor)
+                .timestamp(5_635_634 + i64::from(level))
+                .validation_pass(0)
+                .operations_hash(
+                    "LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?,
+                )
+                .fitness(vec![vec![0, level as u8]])
+                .context(context)
+                .protocol_data(vec![])
+                .build()
+                .unwrap(),
+        )?)
+    }
+
+    fn create_logger() -> Logger {
+        let drain = slog_async::Async::new(
+            slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+                .build()
+                .fuse(),
+        )
+        .build()
+        .filter_level(Level::Info)
+        .fuse();
+
+        Logger::root(drain, slog::o!())
+    }
+}
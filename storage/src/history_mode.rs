@@ -0,0 +1,366 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! History modes define, how much of the chain history is kept in the storage.
+//!
+//! - `archive` - everything is kept (default)
+//! - `full` - block headers and operations are kept for the whole chain, metadata (json data) and context just for the last `N` cycles
+//! - `rolling` - block headers, operations, metadata and context are kept just for the last `N` cycles
+//!
+//! Pruning is triggered on every new cycle, all blocks below the level `first_level_of_new_cycle - N * blocks_per_cycle`
+//! are pruned (but never above last allowed fork level).
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use slog::{debug, info, Logger};
+
+use crypto::hash::{BlockHash, ChainId};
+use tezos_messages::p2p::encoding::block_header::Level;
+use tezos_messages::Head;
+
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::{
+    BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, ChainMetaStorage,
+    OperationsIndexStorage, OperationsMetaStorage, OperationsStorage, OperationsStorageReader,
+    PersistentStorage, StorageError,
+};
+
+/// Default count of cycles, which are preserved for `full` and `rolling` history modes
+pub const DEFAULT_ADDITIONAL_CYCLES: u32 = 5;
+
+const ARCHIVE: &str = "archive";
+const FULL: &str = "full";
+const ROLLING: &str = "rolling";
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub enum HistoryMode {
+    Archive,
+    /// Count of cycles, for which we keep metadata and context
+    Full(u32),
+    /// Count of cycles, for which we keep all the block data
+    Rolling(u32),
+}
+
+impl HistoryMode {
+    pub fn possible_values() -> Vec<&'static str> {
+        vec![ARCHIVE, FULL, ROLLING]
+    }
+
+    /// Returns count of preserved cycles, or None for archive mode
+    pub fn additional_cycles(&self) -> Option<u32> {
+        match self {
+            HistoryMode::Archive => None,
+            HistoryMode::Full(cycles) | HistoryMode::Rolling(cycles) => Some(*cycles),
+        }
+    }
+
+    /// Returns true, if both modes are the same kind (offset is ignored)
+    pub fn is_same_kind(&self, other: &HistoryMode) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Calculates level, below which blocks should be pruned, if new cycle was started with block on `level`.
+    ///
+    /// Returns None, if nothing should be pruned.
+    pub fn prune_level(
+        &self,
+        level: Level,
+        blocks_per_cycle: i32,
+        last_allowed_fork_level: Level,
+    ) -> Option<Level> {
+        let additional_cycles = self.additional_cycles()? as i32;
+        let prune_level = std::cmp::min(
+            level.checked_sub(additional_cycles.checked_mul(blocks_per_cycle)?)?,
+            last_allowed_fork_level,
+        );
+        if prune_level > 0 {
+            Some(prune_level)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for HistoryMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryMode::Archive => write!(f, "{}", ARCHIVE),
+            HistoryMode::Full(cycles) => write!(f, "{}:{}", FULL, cycles),
+            HistoryMode::Rolling(cycles) => write!(f, "{}:{}", ROLLING, cycles),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseHistoryModeError(String);
+
+impl fmt::Display for ParseHistoryModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for HistoryMode {
+    type Err = ParseHistoryModeError;
+
+    /// Accepts `archive`, `full`, `full:<cycles>`, `rolling`, `rolling:<cycles>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let (mode, cycles) = match s.find(':') {
+            Some(idx) => {
+                let cycles = s[idx + 1..].parse::<u32>().map_err(|e| {
+                    ParseHistoryModeError(format!("Invalid cycles count: {}, reason: {}", s, e))
+                })?;
+                (&s[..idx], Some(cycles))
+            }
+            None => (s.as_str(), None),
+        };
+
+        match (mode, cycles) {
+            (ARCHIVE, None) => Ok(HistoryMode::Archive),
+            (FULL, cycles) => Ok(HistoryMode::Full(
+                cycles.unwrap_or(DEFAULT_ADDITIONAL_CYCLES),
+            )),
+            (ROLLING, cycles) => Ok(HistoryMode::Rolling(
+                cycles.unwrap_or(DEFAULT_ADDITIONAL_CYCLES),
+            )),
+            _ => Err(ParseHistoryModeError(format!(
+                "Invalid history mode: {}, expecting one of {:?} (with optional ':<cycles>' for full/rolling)",
+                s,
+                HistoryMode::possible_values()
+            ))),
+        }
+    }
+}
+
+/// Removes old blocks data from storage according to [HistoryMode]
+#[derive(Clone)]
+pub struct HistoryPruner {
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    chain_meta_storage: ChainMetaStorage,
+    operations_storage: OperationsStorage,
+    operations_meta_storage: OperationsMetaStorage,
+    operations_index: Option<OperationsIndexStorage>,
+}
+
+impl HistoryPruner {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            chain_meta_storage: ChainMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
+            operations_index: None,
+        }
+    }
+
+    /// Pruned operations are removed also from the operations index
+    pub fn with_operations_index(mut self, operations_index: OperationsIndexStorage) -> Self {
+        self.operations_index = Some(operations_index);
+        self
+    }
+
+    /// Prunes all blocks on the branch of `block_hash` with level lower than `prune_level`.
+    ///
+    /// Pruning continues from the previous save_point (full) / caboose (rolling), so every block is pruned just once.
+    /// The block at `prune_level` becomes new save_point (and caboose for rolling mode).
+    ///
+    /// Returns count of pruned blocks
+    pub fn prune(
+        &self,
+        chain_id: &ChainId,
+        history_mode: &HistoryMode,
+        block_hash: &BlockHash,
+        block_level: Level,
+        prune_level: Level,
+        log: &Logger,
+    ) -> Result<usize, StorageError> {
+        let previous_limit = match history_mode {
+            HistoryMode::Archive => return Ok(0),
+            HistoryMode::Full(_) => self.chain_meta_storage.get_save_point(chain_id)?,
+            HistoryMode::Rolling(_) => self.chain_meta_storage.get_caboose(chain_id)?,
+        };
+        let previous_limit_level = previous_limit.as_ref().map_or(0, |head| *head.level());
+        if prune_level <= previous_limit_level || prune_level >= block_level {
+            return Ok(0);
+        }
+
+        // find new save_point block on the branch
+        let new_limit = match self
+            .block_meta_storage
+            .find_block_at_distance(block_hash.clone(), block_level - prune_level)?
+        {
+            Some(new_limit) => new_limit,
+            None => return Ok(0),
+        };
+        let new_limit = match self.block_storage.get(&new_limit)? {
+            Some(header) => Head::new(
+                header.hash,
+                header.header.level(),
+                header.header.fitness().clone(),
+            ),
+            None => return Err(StorageError::MissingKey),
+        };
+
+        debug!(log, "Pruning blocks history";
+                    "history_mode" => history_mode.to_string(),
+                    "previous_limit_level" => previous_limit_level,
+                    "new_limit" => new_limit.block_hash().to_base58_check(),
+                    "new_limit_level" => new_limit.level());
+
+        // walk through predecessors and prune them
+        let mut pruned = 0;
+        let mut predecessor = self
+            .block_meta_storage
+            .get(new_limit.block_hash())?
+            .and_then(|meta| meta.predecessor().clone());
+        while let Some(block_hash) = predecessor.take() {
+            let meta = match self.block_meta_storage.get(&block_hash)? {
+                Some(meta) => meta,
+                None => break,
+            };
+            // we never prune genesis and blocks below previous limit
+            if meta.level() <= previous_limit_level {
+                break;
+            }
+            predecessor = meta.predecessor().clone();
+
+            self.prune_block(history_mode, &block_hash)?;
+            pruned += 1;
+        }
+
+        // move limits
+        match history_mode {
+            HistoryMode::Archive => (),
+            HistoryMode::Full(_) => {
+                self.chain_meta_storage
+                    .set_save_point(chain_id, new_limit.clone())?;
+            }
+            HistoryMode::Rolling(_) => {
+                self.chain_meta_storage
+                    .set_save_point(chain_id, new_limit.clone())?;
+                self.chain_meta_storage
+                    .set_caboose(chain_id, new_limit.clone())?;
+            }
+        }
+
+        // rolling mode removes whole blocks, so the oldest commit log segments are not needed anymore
+        // and retained blocks must not point to the pruned predecessors
+        if let HistoryMode::Rolling(_) = history_mode {
+            self.block_meta_storage.remove_predecessors_below(
+                block_hash.clone(),
+                block_level,
+                *new_limit.level(),
+            )?;
+            let removed_segments = match self.chain_meta_storage.get_genesis(chain_id)? {
+                Some(genesis) => self
+                    .block_storage
+                    .remove_commit_log_segments_below(*new_limit.level(), genesis.block_hash())?,
+                None => 0,
+            };
+            debug!(log, "Block storage commit log segments removed"; "removed_segments" => removed_segments);
+        }
+
+        info!(log, "Blocks history pruned";
+                   "history_mode" => history_mode.to_string(),
+                   "pruned_blocks" => pruned,
+                   "save_point" => new_limit.block_hash().to_base58_check(),
+                   "save_point_level" => new_limit.level());
+        Ok(pruned)
+    }
+
+    fn prune_block(
+        &self,
+        history_mode: &HistoryMode,
+        block_hash: &BlockHash,
+    ) -> Result<(), StorageError> {
+        match history_mode {
+            HistoryMode::Archive => Ok(()),
+            HistoryMode::Full(_) => self.block_storage.remove_block_json_data(block_hash),
+            HistoryMode::Rolling(_) => {
+                if let Some(operations_index) = self.operations_index.as_ref() {
+                    operations_index.delete_block_operations(
+                        block_hash,
+                        &self.operations_storage.get_operations(block_hash)?,
+                    )?;
+                }
+                self.operations_storage.delete_operations(block_hash)?;
+                self.operations_meta_storage.delete(block_hash)?;
+                self.block_storage.delete(block_hash)?;
+                self.block_meta_storage.delete(block_hash)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_history_mode() {
+        assert_eq!(HistoryMode::Archive, "archive".parse().unwrap());
+        assert_eq!(
+            HistoryMode::Full(DEFAULT_ADDITIONAL_CYCLES),
+            "full".parse().unwrap()
+        );
+        assert_eq!(HistoryMode::Full(3), "full:3".parse().unwrap());
+        assert_eq!(
+            HistoryMode::Rolling(DEFAULT_ADDITIONAL_CYCLES),
+            "Rolling".parse().unwrap()
+        );
+        assert_eq!(HistoryMode::Rolling(10), "rolling:10".parse().unwrap());
+
+        assert!("archive:5".parse::<HistoryMode>().is_err());
+        assert!("full:abc".parse::<HistoryMode>().is_err());
+        assert!("rolling:-1".parse::<HistoryMode>().is_err());
+        assert!("experimental".parse::<HistoryMode>().is_err());
+    }
+
+    #[test]
+    fn test_history_mode_display_parse_roundtrip() {
+        for mode in &[
+            HistoryMode::Archive,
+            HistoryMode::Full(1),
+            HistoryMode::Rolling(7),
+        ] {
+            assert_eq!(mode, &mode.to_string().parse::<HistoryMode>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_is_same_kind() {
+        assert!(HistoryMode::Full(1).is_same_kind(&HistoryMode::Full(5)));
+        assert!(!HistoryMode::Full(1).is_same_kind(&HistoryMode::Rolling(1)));
+        assert!(!HistoryMode::Archive.is_same_kind(&HistoryMode::Rolling(1)));
+    }
+
+    #[test]
+    fn test_prune_level() {
+        // archive never prunes
+        assert_eq!(None, HistoryMode::Archive.prune_level(40961, 4096, 20481));
+
+        // not enough history
+        assert_eq!(None, HistoryMode::Full(5).prune_level(12289, 4096, 0));
+
+        // first level of cycle 10 with 5 cycles preserved
+        assert_eq!(
+            Some(20481),
+            HistoryMode::Full(5).prune_level(40961, 4096, 20481)
+        );
+
+        // never prune above last allowed fork level
+        assert_eq!(
+            Some(20481),
+            HistoryMode::Rolling(2).prune_level(40961, 4096, 20481)
+        );
+        assert_eq!(
+            Some(32769),
+            HistoryMode::Rolling(2).prune_level(40961, 4096, 36865)
+        );
+    }
+}
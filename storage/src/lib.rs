@@ -13,7 +13,7 @@ use std::sync::{Arc, Mutex};
 use failure::Fail;
 use rocksdb::{Cache, DB};
 use serde::{Deserialize, Serialize};
-use slog::{info, Logger};
+use slog::{info, warn, Logger};
 
 use crypto::{
     base58::FromBase58CheckError,
@@ -24,6 +24,7 @@ use tezos_api::environment::{
 };
 use tezos_api::ffi::{ApplyBlockResponse, CommitGenesisResult, PatchContext};
 use tezos_messages::p2p::binary_message::{BinaryRead, BinaryWrite, MessageHash, MessageHashError};
+use tezos_messages::p2p::encoding::block_header::Level;
 use tezos_messages::p2p::encoding::prelude::BlockHeader;
 use tezos_messages::Head;
 
@@ -32,29 +33,50 @@ pub use crate::block_meta_storage::{
 };
 pub use crate::block_storage::{BlockJsonData, BlockStorage, BlockStorageReader};
 pub use crate::chain_meta_storage::ChainMetaStorage;
+use crate::chain_meta_storage::ChainMetaStorageReader;
+use crate::context::merkle::hash::EntryHash;
 use crate::context::merkle::merkle_storage::MerkleStorage;
+use crate::context::merkle::Entry;
+use crate::history_mode::ParseHistoryModeError;
+pub use crate::history_mode::{HistoryMode, HistoryPruner};
 pub use crate::mempool_storage::{MempoolStorage, MempoolStorageKV};
+pub use crate::operations_index_storage::OperationsIndexStorage;
 pub use crate::operations_meta_storage::{OperationsMetaStorage, OperationsMetaStorageKV};
 pub use crate::operations_storage::{
     OperationKey, OperationsStorage, OperationsStorageKV, OperationsStorageReader,
 };
+pub use crate::peer_storage::PeerStorage;
+use crate::persistent::database::{
+    catch_up_with_primary, database_stats, KeyValueStoreWithSchema, RocksDbKeyValueSchema,
+};
 pub use crate::persistent::database::{Direction, IteratorMode};
+use crate::persistent::merge_secondary::SecondaryMergeDB;
+use crate::persistent::metrics::DatabaseStats;
 use crate::persistent::sequence::{SequenceError, Sequences};
+use crate::persistent::write_batch::StorageWriteBatch;
 use crate::persistent::{
     CommitLogError, CommitLogs, DBError, Decoder, Encoder, Flushable, SchemaError,
 };
 pub use crate::predecessor_storage::PredecessorStorage;
+pub use crate::protocol_storage::ProtocolStorage;
 pub use crate::system_storage::SystemStorage;
 
+pub mod archive;
 pub mod block_meta_storage;
 pub mod block_storage;
 pub mod chain_meta_storage;
 pub mod context;
+pub mod fsck;
+pub mod history_mode;
 pub mod mempool_storage;
+pub mod migration;
+pub mod operations_index_storage;
 pub mod operations_meta_storage;
 pub mod operations_storage;
+pub mod peer_storage;
 pub mod persistent;
 pub mod predecessor_storage;
+pub mod protocol_storage;
 pub mod system_storage;
 
 /// Extension of block header with block hash
@@ -135,6 +157,18 @@ pub enum StorageError {
     HashError { error: FromBytesError },
     #[fail(display = "Error decoding hash: {}", error)]
     HashDecodeError { error: FromBase58CheckError },
+    #[fail(display = "Invalid history mode: {}", error)]
+    HistoryModeError { error: ParseHistoryModeError },
+    #[fail(display = "Invalid checkpoint: {}", reason)]
+    InvalidCheckpoint { reason: String },
+    #[fail(display = "Invalid operations json: {}", reason)]
+    InvalidOperationsJson { reason: String },
+    #[fail(display = "Inconsistent storage: {}", reason)]
+    InconsistentStorage { reason: String },
+    #[fail(display = "Merkle storage error: {}", error)]
+    MerkleStorageError {
+        error: crate::context::merkle::merkle_storage::MerkleError,
+    },
 }
 
 impl From<DBError> for StorageError {
@@ -187,6 +221,12 @@ impl From<FromBase58CheckError> for StorageError {
     }
 }
 
+impl From<ParseHistoryModeError> for StorageError {
+    fn from(error: ParseHistoryModeError) -> Self {
+        StorageError::HistoryModeError { error }
+    }
+}
+
 impl slog::Value for StorageError {
     fn serialize(
         &self,
@@ -204,9 +244,20 @@ pub struct StorageInitInfo {
     pub chain_id: ChainId,
     pub genesis_block_header_hash: BlockHash,
     pub patch_context: Option<PatchContext>,
+    pub history_mode: HistoryMode,
 
     // TODO: TE-447 - remove one_context when integration done
     pub one_context: bool,
+
+    /// If true, applied operations are indexed by operation hash and by touched accounts (see [OperationsIndexStorage])
+    pub operations_index: bool,
+}
+
+impl StorageInitInfo {
+    pub fn with_operations_index(mut self, operations_index: bool) -> Self {
+        self.operations_index = operations_index;
+        self
+    }
 }
 
 /// Resolve main chain id and genesis header from configuration
@@ -215,6 +266,7 @@ pub fn resolve_storage_init_chain_data(
     storage_db_path: &Path,
     context_db_path: &Path,
     patch_context: &Option<PatchContext>,
+    history_mode: &HistoryMode,
     one_context: bool,
     log: &Logger,
 ) -> Result<StorageInitInfo, StorageError> {
@@ -222,7 +274,9 @@ pub fn resolve_storage_init_chain_data(
         chain_id: tezos_env.main_chain_id()?,
         genesis_block_header_hash: tezos_env.genesis_header_hash()?,
         patch_context: patch_context.clone(),
+        history_mode: history_mode.clone(),
         one_context,
+        operations_index: false,
     };
 
     info!(
@@ -233,6 +287,7 @@ pub fn resolve_storage_init_chain_data(
         "init_data.genesis_header" => format!("{:?}", init_data.genesis_block_header_hash.to_base58_check()),
         "storage_db_path" => format!("{:?}", storage_db_path),
         "context_db_path" => format!("{:?}", context_db_path),
+        "history_mode" => history_mode.to_string(),
         "one_context" => one_context,
         "patch_context" => match patch_context {
                 Some(pc) => format!("{:?}", pc),
@@ -243,20 +298,29 @@ pub fn resolve_storage_init_chain_data(
 }
 
 /// Stores apply result to storage and mark block as applied, if everythnig is ok.
+///
+/// Context of the block has to be already committed. Block json data are appended to the commit log (and synced)
+/// and all the other writes (json data indexes, additional data, predecessors, applied metadata and `new_current_head`, if any)
+/// are committed atomically, so block is never marked as applied without its data and current head never points to the block without them.
 pub fn store_applied_block_result(
-    block_storage: &BlockStorage,
-    block_meta_storage: &BlockMetaStorage,
+    persistent_storage: &PersistentStorage,
     block_hash: &BlockHash,
     block_result: ApplyBlockResponse,
     block_metadata: &mut block_meta_storage::Meta,
+    new_current_head: Option<(&ChainId, Head)>,
 ) -> Result<BlockAdditionalData, StorageError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    let mut batch = persistent_storage.write_batch();
+
     // store result data - json and additional data
     let block_json_data = BlockJsonData::new(
         block_result.block_header_proto_json,
         block_result.block_header_proto_metadata_bytes,
         block_result.operations_proto_metadata_bytes,
     );
-    block_storage.put_block_json_data(&block_hash, block_json_data)?;
+    block_storage.put_block_json_data_batched(&mut batch, block_hash, block_json_data)?;
 
     // store additional data
     let block_additional_data = BlockAdditionalData::new(
@@ -282,17 +346,29 @@ pub fn store_applied_block_result(
         },
         block_result.ops_metadata_hashes,
     );
-    block_meta_storage.put_block_additional_data(&block_hash, &block_additional_data)?;
+    block_meta_storage.put_block_additional_data_batched(
+        &mut batch,
+        block_hash,
+        &block_additional_data,
+    )?;
 
     // TODO: check context checksum or context_hash
 
     // populate predecessor storage
-    block_meta_storage.store_predecessors(&block_hash, &block_metadata)?;
+    block_meta_storage.store_predecessors_batched(&mut batch, block_hash, block_metadata)?;
 
     // if everything is stored and ok, we can considere this block as applied
     // mark current head as applied
     block_metadata.set_is_applied(true);
-    block_meta_storage.put(&block_hash, &block_metadata)?;
+    block_meta_storage.put_batched(&mut batch, block_hash, block_metadata)?;
+
+    // and move current head to it
+    if let Some((chain_id, head)) = new_current_head {
+        chain_meta_storage.set_current_head_batched(&mut batch, chain_id, head)?;
+    }
+
+    // commit everything at once
+    batch.commit()?;
 
     // return additional data for later use
     Ok(block_additional_data)
@@ -301,26 +377,36 @@ pub fn store_applied_block_result(
 /// Stores commit_genesis result to storage and mark genesis block as applied, if everythnig is ok.
 /// !Important, this rewrites context_hash on stored genesis - because in initialize_storage_with_genesis_block we stored wiht Context_hash_zero
 /// And context hash of block is used for appling of successor
+///
+/// Genesis data are committed atomically (see [store_applied_block_result]) together with genesis set as current head.
 pub fn store_commit_genesis_result(
-    block_storage: &BlockStorage,
-    block_meta_storage: &BlockMetaStorage,
-    chain_meta_storage: &ChainMetaStorage,
-    operations_meta_storage: &OperationsMetaStorage,
+    persistent_storage: &PersistentStorage,
     init_storage_data: &StorageInitInfo,
     bock_result: CommitGenesisResult,
 ) -> Result<(), StorageError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+    let operations_meta_storage = OperationsMetaStorage::new(persistent_storage);
+    let mut batch = persistent_storage.write_batch();
+
     // store data for genesis
     let genesis_block_hash = &init_storage_data.genesis_block_header_hash;
     let chain_id = &init_storage_data.chain_id;
+    let genesis = block_storage
+        .get(genesis_block_hash)?
+        .ok_or(StorageError::MissingKey)?;
 
     // if everything is stored and ok, we can considere genesis block as applied
     // if storage is empty, initialize with genesis
-    block_meta_storage.put(
-        &genesis_block_hash,
-        &block_meta_storage::Meta::genesis_meta(&genesis_block_hash, chain_id, true),
+    block_meta_storage.put_batched(
+        &mut batch,
+        genesis_block_hash,
+        &block_meta_storage::Meta::genesis_meta(genesis_block_hash, chain_id, true),
     )?;
-    operations_meta_storage.put(
-        &genesis_block_hash,
+    operations_meta_storage.put_batched(
+        &mut batch,
+        genesis_block_hash,
         &operations_meta_storage::Meta::genesis_meta(),
     )?;
 
@@ -330,26 +416,197 @@ pub fn store_commit_genesis_result(
         bock_result.block_header_proto_metadata_bytes,
         bock_result.operations_proto_metadata_bytes,
     );
-    block_storage.put_block_json_data(&genesis_block_hash, block_json_data)?;
+    block_storage.put_block_json_data_batched(&mut batch, genesis_block_hash, block_json_data)?;
 
     // set genesis as current head - it is empty storage
-    match block_storage.get(&genesis_block_hash)? {
-        Some(genesis) => {
-            let head = Head::new(
-                genesis.hash,
-                genesis.header.level(),
-                genesis.header.fitness().clone(),
-            );
+    let head = Head::new(
+        genesis.hash,
+        genesis.header.level(),
+        genesis.header.fitness().clone(),
+    );
 
-            // init chain data
-            chain_meta_storage.set_genesis(&chain_id, head.clone())?;
-            chain_meta_storage.set_caboose(&chain_id, head.clone())?;
-            chain_meta_storage.set_current_head(&chain_id, head)?;
+    // init chain data
+    chain_meta_storage.set_genesis_batched(&mut batch, chain_id, head.clone())?;
+    chain_meta_storage.set_caboose_batched(&mut batch, chain_id, head.clone())?;
+    chain_meta_storage.set_save_point_batched(&mut batch, chain_id, head.clone())?;
+    chain_meta_storage.set_current_head_batched(&mut batch, chain_id, head)?;
 
-            Ok(())
+    // commit everything at once
+    batch.commit()?;
+    Ok(())
+}
+
+/// Checks, that current head is completely stored - block is applied, its json and additional data are stored
+/// and its context is present in the context store (checked just for persistent context stores).
+///
+/// Blocks stored just partially (e.g. context writes were lost by crash) are marked as not applied,
+/// so they are applied again, and current head is moved back to the last completely stored predecessor.
+/// Blocks below save point are not checked, if there is no consistent block above it, error is returned.
+///
+/// Returns new current head, if it was moved.
+pub fn ensure_consistent_current_head(
+    persistent_storage: &PersistentStorage,
+    chain_id: &ChainId,
+    log: &Logger,
+) -> Result<Option<Head>, StorageError> {
+    let block_storage = BlockStorage::new(persistent_storage);
+    let block_meta_storage = BlockMetaStorage::new(persistent_storage);
+    let chain_meta_storage = ChainMetaStorage::new(persistent_storage);
+
+    let current_head = match chain_meta_storage.get_current_head(chain_id)? {
+        Some(current_head) => current_head,
+        None => return Ok(None),
+    };
+    let lowest_level = match chain_meta_storage.get_save_point(chain_id)? {
+        Some(save_point) => *save_point.level(),
+        None => 0,
+    };
+
+    let mut block_hash = current_head.block_hash().clone();
+    let mut inconsistent_blocks = vec![];
+    let consistent_head = loop {
+        let block = block_storage
+            .get(&block_hash)?
+            .ok_or(StorageError::MissingKey)?;
+        let meta = block_meta_storage.get(&block_hash)?;
+        if is_block_completely_stored(persistent_storage, &block, meta.as_ref())? {
+            break block;
         }
-        None => Err(StorageError::MissingKey),
+
+        inconsistent_blocks.push(block_hash.clone());
+        match meta.as_ref().and_then(|meta| meta.predecessor().clone()) {
+            Some(predecessor)
+                if predecessor != block_hash && block.header.level() > lowest_level =>
+            {
+                block_hash = predecessor
+            }
+            _ => {
+                return Err(StorageError::InconsistentStorage {
+                    reason: format!(
+                        "no completely stored block found from current head {} (level: {}) to block {} (level: {})",
+                        current_head.block_hash().to_base58_check(),
+                        current_head.level(),
+                        block_hash.to_base58_check(),
+                        block.header.level(),
+                    ),
+                })
+            }
+        }
+    };
+
+    if inconsistent_blocks.is_empty() {
+        return Ok(None);
     }
+
+    for block_hash in &inconsistent_blocks {
+        block_meta_storage.mark_as_not_applied(block_hash)?;
+    }
+    let head = Head::new(
+        consistent_head.hash.clone(),
+        consistent_head.header.level(),
+        consistent_head.header.fitness().clone(),
+    );
+    chain_meta_storage.set_current_head(chain_id, head.clone())?;
+
+    warn!(log, "Current head was not completely stored, moved back to the last consistent block";
+               "previous_head" => current_head.block_hash().to_base58_check(),
+               "previous_head_level" => current_head.level(),
+               "new_head" => head.block_hash().to_base58_check(),
+               "new_head_level" => head.level(),
+               "blocks_to_reapply" => inconsistent_blocks.len());
+    Ok(Some(head))
+}
+
+fn is_block_completely_stored(
+    persistent_storage: &PersistentStorage,
+    block: &BlockHeaderWithHash,
+    meta: Option<&block_meta_storage::Meta>,
+) -> Result<bool, StorageError> {
+    if !meta.map(|meta| meta.is_applied()).unwrap_or(false) {
+        return Ok(false);
+    }
+    if BlockMetaStorage::new(persistent_storage)
+        .get_additional_data(&block.hash)?
+        .is_none()
+    {
+        return Ok(false);
+    }
+    // json data are read from commit log, so torn records are detected too
+    if !matches!(
+        BlockStorage::new(persistent_storage).get_json_data(&block.hash),
+        Ok(Some(_))
+    ) {
+        return Ok(false);
+    }
+
+    let merkle = persistent_storage.merkle();
+    let merkle = merkle
+        .lock()
+        .map_err(|e| StorageError::DBError { error: e.into() })?;
+    if merkle.is_persistent() {
+        let context_hash: EntryHash = match block.header.context().as_ref().as_slice().try_into() {
+            Ok(context_hash) => context_hash,
+            Err(_) => return Ok(false),
+        };
+        if !matches!(
+            merkle.get_entry_by_hash(&context_hash),
+            Ok(Entry::Commit(_))
+        ) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Stores checkpoint configured on startup (e.g. `--checkpoint`), which overrides actual checkpoint.
+/// If `level` is not provided, it is resolved from the already stored block header.
+pub fn store_configured_checkpoint(
+    block_storage: &BlockStorage,
+    chain_meta_storage: &ChainMetaStorage,
+    chain_id: &ChainId,
+    block_hash: &BlockHash,
+    level: Option<Level>,
+    log: &Logger,
+) -> Result<Head, StorageError> {
+    let checkpoint = match (block_storage.get(block_hash)?, level) {
+        (Some(block), level) => {
+            if let Some(level) = level {
+                if level != block.header.level() {
+                    return Err(StorageError::InvalidCheckpoint {
+                        reason: format!(
+                            "block {} is stored with level {}, but configured level is {}",
+                            block_hash.to_base58_check(),
+                            block.header.level(),
+                            level
+                        ),
+                    });
+                }
+            }
+            Head::new(
+                block.hash,
+                block.header.level(),
+                block.header.fitness().clone(),
+            )
+        }
+        (None, Some(level)) => Head::new(block_hash.clone(), level, vec![]),
+        (None, None) => {
+            return Err(StorageError::InvalidCheckpoint {
+                reason: format!(
+                    "block {} is not stored yet, so level has to be configured",
+                    block_hash.to_base58_check()
+                ),
+            });
+        }
+    };
+
+    chain_meta_storage.set_checkpoint(chain_id, checkpoint.clone())?;
+
+    info!(log,
+        "Checkpoint configured";
+        "block_hash" => checkpoint.block_hash().to_base58_check(),
+        "level" => checkpoint.level(),
+    );
+    Ok(checkpoint)
 }
 
 /// Genesis block needs extra handling because predecessor of the genesis block is genesis itself.
@@ -409,18 +666,27 @@ pub fn initialize_storage_with_genesis_block(
 
 /// Helper module to easily initialize databases
 pub mod initializer {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use std::sync::Arc;
 
-    use rocksdb::{Cache, ColumnFamilyDescriptor, DB};
-    use slog::{error, Logger};
+    use rocksdb::{Cache, ColumnFamilyDescriptor, Options, DB};
+    use slog::{error, info, Logger};
 
     use crypto::hash::ChainId;
 
+    use crate::context::gc::concurrent_mark_sweep_gced::ConcurrentMarkSweepGCed;
+    use crate::context::gc::mark_sweep_gced::MarkSweepGCed;
+    use crate::context::gc::{ContextGc, GarbageCollector};
+    use crate::context::kv_store::compressed::{CompressedKeyValueStore, ContextCompression};
+    use crate::context::kv_store::metered::MeteredKeyValueStore;
     use crate::context::merkle::merkle_storage::MerkleStorage;
-    use crate::persistent::database::{open_kv, RocksDbKeyValueSchema};
-    use crate::persistent::{DBError, DbConfiguration};
-    use crate::{StorageError, SystemStorage};
+    use crate::context::{ContextKeyValueStore, ContextKeyValueStoreSchema};
+    use crate::persistent::database::{open_kv, open_kv_as_secondary, RocksDbKeyValueSchema};
+    use crate::persistent::merge_secondary::SecondaryMergeDB;
+    use crate::persistent::{
+        DBError, DbConfiguration, Flushable, KeyValueStoreBackend, MultiInstanceable, Persistable,
+    };
+    use crate::{HistoryMode, StorageError, SystemStorage};
 
     // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv)
     pub type GlobalRocksDbCacheHolder = Vec<RocksDbCache>;
@@ -467,6 +733,13 @@ pub mod initializer {
                 crate::ChainMetaStorage::descriptor(cache),
                 crate::PredecessorStorage::descriptor(cache),
                 crate::BlockAdditionalData::descriptor(&cache),
+                crate::operations_index_storage::OperationsByHashIndex::descriptor(cache),
+                crate::operations_index_storage::OperationsByAccountIndex::descriptor(cache),
+                crate::operations_index_storage::UnindexedBlocks::descriptor(cache),
+                crate::peer_storage::KnownPeerStorageSchema::descriptor(cache),
+                crate::peer_storage::KnownPointStorageSchema::descriptor(cache),
+                crate::peer_storage::PeerAccessRuleStorageSchema::descriptor(cache),
+                crate::ProtocolStorage::descriptor(cache),
             ]
         }
     }
@@ -498,6 +771,7 @@ pub mod initializer {
         Sled { path: PathBuf },
         InMem,
         BTreeMap,
+        PackFile { path: PathBuf },
     }
 
     pub fn initialize_rocksdb<Factory: RocksDbColumnFactory>(
@@ -515,10 +789,45 @@ pub mod initializer {
         )
         .map(Arc::new)?;
 
+        verify_database_compatibility(db, config, expected_main_chain, false, log)
+            .and_then(|db| resolve_merge_operands(db, log))
+    }
+
+    /// Opens RocksDB database of another (primary) process as a read-only secondary instance,
+    /// see [open_kv_as_secondary]. Database has to be already initialized by the primary process.
+    pub fn initialize_rocksdb_secondary<Factory: RocksDbColumnFactory>(
+        log: &Logger,
+        config: &RocksDbConfig<Factory>,
+        secondary_path: &Path,
+        expected_main_chain: &MainChain,
+    ) -> Result<Arc<DB>, DBError> {
+        // all column families of the primary have to be opened
+        let cf_names = DB::list_cf(&Options::default(), &config.db_path)?;
+        let db = open_kv_as_secondary(
+            config.db_path.as_path(),
+            secondary_path,
+            cf_names,
+            &DbConfiguration {
+                max_threads: config.threads,
+            },
+        )
+        .map(Arc::new)?;
+
+        verify_database_compatibility(db, config, expected_main_chain, true, log)
+    }
+
+    fn verify_database_compatibility<Factory: RocksDbColumnFactory>(
+        db: Arc<DB>,
+        config: &RocksDbConfig<Factory>,
+        expected_main_chain: &MainChain,
+        read_only: bool,
+        log: &Logger,
+    ) -> Result<Arc<DB>, DBError> {
         match check_database_compatibility(
             db.clone(),
             config.expected_db_version,
             expected_main_chain,
+            read_only,
             &log,
         ) {
             Ok(false) => Err(DBError::DatabaseIncompatibility {
@@ -534,6 +843,54 @@ pub mod initializer {
         }
     }
 
+    /// Previous versions never compacted block and operations metadata column families, so long stacks of merge operands
+    /// could be stored for the same key and they were folded on every read. Compaction folds them just once.
+    ///
+    /// It is done just once for the database, what is recorded in [SystemStorage].
+    fn resolve_merge_operands(db: Arc<DB>, log: &Logger) -> Result<Arc<DB>, DBError> {
+        let to_db_error = |e: StorageError| DBError::DatabaseIncompatibility {
+            name: format!("Failed to resolve merge operands, reason: '{}'", e),
+        };
+        let mut system_info = SystemStorage::new(db.clone());
+        if system_info
+            .get_meta_merge_operands_compacted()
+            .map_err(to_db_error)?
+        {
+            return Ok(db);
+        }
+
+        for name in &[
+            crate::BlockMetaStorage::name(),
+            crate::OperationsMetaStorage::name(),
+        ] {
+            if let Some(cf) = db.cf_handle(name) {
+                info!(log, "Resolving merge operands of column family"; "name" => name);
+                db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+            }
+        }
+        system_info
+            .set_meta_merge_operands_compacted()
+            .map_err(to_db_error)?;
+        Ok(db)
+    }
+
+    /// Opens block and operations metadata column families of another (primary) process
+    /// as a read-only secondary instance with their merge operators, see [SecondaryMergeDB].
+    pub fn initialize_merge_operators_secondary<Factory: RocksDbColumnFactory>(
+        config: &RocksDbConfig<Factory>,
+        secondary_path: &Path,
+    ) -> Result<Arc<SecondaryMergeDB>, DBError> {
+        SecondaryMergeDB::open(
+            config.db_path.as_path(),
+            secondary_path,
+            vec![
+                crate::BlockMetaStorage::merge_operator(),
+                crate::OperationsMetaStorage::merge_operator(),
+            ],
+        )
+        .map(Arc::new)
+    }
+
     pub struct MainChain {
         chain_id: ChainId,
         chain_name: String,
@@ -548,15 +905,24 @@ pub mod initializer {
         }
     }
 
+    /// Checks database version and chain, values are stored for the first run (if database is not `read_only`)
     fn check_database_compatibility(
         db: Arc<DB>,
         expected_database_version: i64,
         expected_main_chain: &MainChain,
+        read_only: bool,
         log: &Logger,
     ) -> Result<bool, StorageError> {
         let mut system_info = SystemStorage::new(db);
         let db_version_ok = match system_info.get_db_version()? {
             Some(db_version) => db_version == expected_database_version,
+            None if read_only => {
+                error!(
+                    log,
+                    "Database was not initialized yet. Please start the primary node first!"
+                );
+                return Ok(false);
+            }
             None => {
                 system_info.set_db_version(expected_database_version)?;
                 true
@@ -602,8 +968,102 @@ pub mod initializer {
         Ok(db_version_ok && chain_id_ok)
     }
 
+    /// Checks, if database was not created with different history mode, mode is stored for the first run.
+    ///
+    /// Changing of the count of preserved cycles is allowed, but switching between modes is not.
+    pub fn check_history_mode_compatibility(
+        db: Arc<DB>,
+        history_mode: &HistoryMode,
+        log: &Logger,
+    ) -> Result<(), DBError> {
+        match check_history_mode(db, history_mode, log) {
+            Ok(false) => Err(DBError::DatabaseIncompatibility {
+                name: format!(
+                    "Database is incompatible with history mode {}",
+                    history_mode
+                ),
+            }),
+            Err(e) => Err(DBError::DatabaseIncompatibility {
+                name: format!(
+                    "Failed to verify history mode compatibility reason: '{}'",
+                    e
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_history_mode(
+        db: Arc<DB>,
+        history_mode: &HistoryMode,
+        log: &Logger,
+    ) -> Result<bool, StorageError> {
+        let mut system_info = SystemStorage::new(db);
+        match system_info.get_history_mode()? {
+            Some(previous_history_mode) => {
+                if !previous_history_mode.is_same_kind(history_mode) {
+                    error!(log, "Current database was previously created with another history mode. Please re-sync your node to empty storage - see configuration!";
+                                "requested_history_mode" => history_mode.to_string(),
+                                "previous_history_mode" => previous_history_mode.to_string()
+                    );
+                    return Ok(false);
+                }
+                if previous_history_mode != *history_mode {
+                    // just count of preserved cycles was changed
+                    system_info.set_history_mode(history_mode)?;
+                }
+                Ok(true)
+            }
+            None => {
+                system_info.set_history_mode(history_mode)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// For non-archive history modes, context store is wrapped with garbage collector,
+    /// which removes context entries not used during the preserved cycles.
+    ///
+    /// Storage metrics are recorded under the `name` of the context store backend,
+    /// entries are compressed (if enabled) before they are written to the backend.
+    fn with_history_mode<T>(
+        kv_store: T,
+        name: &'static str,
+        history_mode: &HistoryMode,
+        context_gc: ContextGc,
+        context_compression: Option<ContextCompression>,
+    ) -> Result<Box<ContextKeyValueStore>, failure::Error>
+    where
+        T: 'static
+            + KeyValueStoreBackend<ContextKeyValueStoreSchema>
+            + GarbageCollector
+            + Flushable
+            + MultiInstanceable
+            + Persistable
+            + Sync
+            + Send,
+    {
+        let kv_store = CompressedKeyValueStore::with_store(
+            MeteredKeyValueStore::with_store(kv_store, name),
+            context_compression,
+        )?;
+        Ok(match (history_mode.additional_cycles(), context_gc) {
+            (Some(cycles), ContextGc::Concurrent) => Box::new(ConcurrentMarkSweepGCed::with_store(
+                kv_store,
+                cycles as usize,
+            )?),
+            (Some(cycles), ContextGc::MarkSweep) => {
+                Box::new(MarkSweepGCed::with_store(kv_store, cycles as usize)?)
+            }
+            (Some(_), ContextGc::Disabled) | (None, _) => Box::new(kv_store),
+        })
+    }
+
     pub fn initialize_merkle(
         context_kv_store: &ContextKvStoreConfiguration,
+        history_mode: &HistoryMode,
+        context_gc: ContextGc,
+        context_compression: Option<ContextCompression>,
         expected_main_chain: &MainChain,
         log: &Logger,
         caches: &mut GlobalRocksDbCacheHolder,
@@ -616,24 +1076,95 @@ pub mod initializer {
                     initialize_rocksdb(&log, &kv_context_cache, cfg, expected_main_chain)
                         .expect("Failed to create/initialize RocksDB database (db_context)");
                 caches.push(kv_context_cache);
-                Box::new(crate::context::kv_store::rocksdb_backend::RocksDBBackend::new(kv_context))
+                with_history_mode(
+                    crate::context::kv_store::rocksdb_backend::RocksDBBackend::new(kv_context),
+                    "rocksdb",
+                    history_mode,
+                    context_gc,
+                    context_compression,
+                )?
             }
             ContextKvStoreConfiguration::Sled { path } => {
                 let sled = sled::Config::new()
                     .path(path)
                     .open()
                     .expect("Failed to create/initialize Sled database (db_context)");
-                Box::new(crate::context::kv_store::sled_backend::SledBackend::new(
-                    sled,
-                ))
+                with_history_mode(
+                    crate::context::kv_store::sled_backend::SledBackend::new(sled),
+                    "sled",
+                    history_mode,
+                    context_gc,
+                    context_compression,
+                )?
+            }
+            ContextKvStoreConfiguration::InMem => with_history_mode(
+                crate::context::kv_store::in_memory_backend::InMemoryBackend::new(),
+                "inmem",
+                history_mode,
+                context_gc,
+                context_compression,
+            )?,
+            ContextKvStoreConfiguration::BTreeMap => with_history_mode(
+                crate::context::kv_store::btree_map::BTreeMapBackend::new(),
+                "btree",
+                history_mode,
+                context_gc,
+                context_compression,
+            )?,
+            ContextKvStoreConfiguration::PackFile { path } => with_history_mode(
+                crate::context::kv_store::pack_file_backend::PackFileBackend::open(path)
+                    .expect("Failed to create/initialize pack files (db_context)"),
+                "pack",
+                history_mode,
+                context_gc,
+                context_compression,
+            )?,
+        }))
+    }
+
+    /// Opens context store of another (primary) process as a read-only replica,
+    /// entries written by the primary process are visible after [MerkleStorage::sync_with_primary].
+    ///
+    /// Garbage collection is left to the primary process, so store is not wrapped with garbage collector.
+    pub fn initialize_merkle_replica(
+        context_kv_store: &ContextKvStoreConfiguration,
+        secondary_path: &Path,
+        expected_main_chain: &MainChain,
+        log: &Logger,
+    ) -> Result<MerkleStorage, failure::Error> {
+        let kv_store: Box<ContextKeyValueStore> = match context_kv_store {
+            ContextKvStoreConfiguration::RocksDb(cfg) => {
+                let kv_context =
+                    initialize_rocksdb_secondary(log, cfg, secondary_path, expected_main_chain)?;
+                Box::new(CompressedKeyValueStore::with_store(
+                    MeteredKeyValueStore::with_store(
+                        crate::context::kv_store::rocksdb_backend::RocksDBBackend::new(kv_context),
+                        "rocksdb",
+                    ),
+                    None,
+                )?)
             }
-            ContextKvStoreConfiguration::InMem => {
-                Box::new(crate::context::kv_store::in_memory_backend::InMemoryBackend::new())
+            ContextKvStoreConfiguration::PackFile { path } => {
+                Box::new(CompressedKeyValueStore::with_store(
+                    MeteredKeyValueStore::with_store(
+                        crate::context::kv_store::pack_file_backend::PackFileBackend::open_read_only(
+                            path,
+                        )?,
+                        "pack",
+                    ),
+                    None,
+                )?)
             }
-            ContextKvStoreConfiguration::BTreeMap => {
-                Box::new(crate::context::kv_store::btree_map::BTreeMapBackend::new())
+            ContextKvStoreConfiguration::Sled { .. }
+            | ContextKvStoreConfiguration::InMem
+            | ContextKvStoreConfiguration::BTreeMap => {
+                return Err(failure::format_err!(
+                    "Context store {:?} cannot be opened by storage replica, supported are just 'rocksdb' and 'pack'",
+                    context_kv_store
+                ))
             }
-        }))
+        };
+        Ok(MerkleStorage::new(kv_store))
     }
 }
 
@@ -649,6 +1180,8 @@ pub struct PersistentStorage {
     merkle: Arc<Mutex<MerkleStorage>>,
     /// persistent context actions storage
     merkle_context_actions: Option<Arc<DB>>,
+    /// column families with merge operator of the operational database opened by storage replica
+    merge_secondary: Option<Arc<SecondaryMergeDB>>,
 }
 
 impl PersistentStorage {
@@ -665,6 +1198,26 @@ impl PersistentStorage {
             seq,
             merkle,
             merkle_context_actions,
+            merge_secondary: None,
+        }
+    }
+
+    /// Creates storage of read-only replica, column families with merge operator are read through `merge_secondary`
+    pub fn new_replica(
+        db: Arc<DB>,
+        merge_secondary: Arc<SecondaryMergeDB>,
+        clog: Arc<CommitLogs>,
+        seq: Arc<Sequences>,
+        merkle: Arc<Mutex<MerkleStorage>>,
+        merkle_context_actions: Option<Arc<DB>>,
+    ) -> Self {
+        Self {
+            clog,
+            db,
+            seq,
+            merkle,
+            merkle_context_actions,
+            merge_secondary: Some(merge_secondary),
         }
     }
 
@@ -673,6 +1226,18 @@ impl PersistentStorage {
         self.db.clone()
     }
 
+    /// Returns store for column family with merge operator, secondary instances of [DB] cannot resolve merge operands,
+    /// so replica reads them from [SecondaryMergeDB]
+    #[inline]
+    pub fn merge_db<S: RocksDbKeyValueSchema + 'static>(
+        &self,
+    ) -> Arc<dyn KeyValueStoreWithSchema<S> + Sync + Send> {
+        match &self.merge_secondary {
+            Some(merge_secondary) => merge_secondary.clone(),
+            None => self.db.clone(),
+        }
+    }
+
     #[inline]
     pub fn clog(&self) -> Arc<CommitLogs> {
         self.clog.clone()
@@ -693,6 +1258,44 @@ impl PersistentStorage {
         self.merkle_context_actions.clone()
     }
 
+    /// Catches up read-only storage replica with the primary process - picks up everything written
+    /// to databases, commit logs and context store since the last call.
+    ///
+    /// Storage has to be opened as replica (see [initializer::initialize_rocksdb_secondary]).
+    pub fn sync_with_primary(&self) -> Result<(), StorageError> {
+        // primary stores context and block data before it points to them from the operational database (e.g. current head),
+        // so they are synced after it - everything referenced by the synced operational database is already visible
+        catch_up_with_primary(&self.db)?;
+        if let Some(merge_secondary) = self.merge_secondary.as_ref() {
+            merge_secondary.catch_up_with_primary()?;
+        }
+        if let Some(merkle_context_actions) = self.merkle_context_actions.as_ref() {
+            catch_up_with_primary(merkle_context_actions)?;
+        }
+        self.clog.refresh()?;
+        self.merkle
+            .lock()
+            .map_err(|e| StorageError::DBError { error: e.into() })?
+            .sync_with_primary()
+            .map_err(|error| StorageError::MerkleStorageError { error })?;
+        Ok(())
+    }
+
+    /// Creates unit of work, which commits writes to more column families of the operational database atomically
+    #[inline]
+    pub fn write_batch(&self) -> StorageWriteBatch {
+        StorageWriteBatch::new(self.db.clone())
+    }
+
+    /// Collects memory usage and column family size statistics of all RocksDB databases
+    pub fn database_stats(&self) -> Result<Vec<DatabaseStats>, StorageError> {
+        let mut stats = vec![database_stats("db", &self.db)?];
+        if let Some(merkle_context_actions) = self.merkle_context_actions.as_ref() {
+            stats.push(database_stats("context_actions", merkle_context_actions)?);
+        }
+        Ok(stats)
+    }
+
     pub fn flush_dbs(&mut self) {
         let clog = self.clog.flush();
         let db = self.db.flush();
@@ -736,9 +1339,15 @@ pub mod tests_common {
     use crate::context::kv_store::rocksdb_backend::RocksDBBackend;
     use crate::context::merkle::merkle_storage::MerkleStorage;
     use crate::mempool_storage::MempoolStorage;
+    use crate::operations_index_storage::{
+        OperationsByAccountIndex, OperationsByHashIndex, UnindexedBlocks,
+    };
+    use crate::peer_storage::{
+        KnownPeerStorageSchema, KnownPointStorageSchema, PeerAccessRuleStorageSchema,
+    };
     use crate::persistent::database::{open_kv, RocksDbKeyValueSchema};
     use crate::persistent::sequence::Sequences;
-    use crate::persistent::{open_cl, CommitLogSchema, DbConfiguration};
+    use crate::persistent::{open_cl, CommitLogConfiguration, CommitLogSchema, DbConfiguration};
 
     use super::*;
 
@@ -791,6 +1400,13 @@ pub mod tests_common {
                     ChainMetaStorage::descriptor(&db_cache),
                     PredecessorStorage::descriptor(&db_cache),
                     BlockAdditionalData::descriptor(&db_cache),
+                    OperationsByHashIndex::descriptor(&db_cache),
+                    OperationsByAccountIndex::descriptor(&db_cache),
+                    UnindexedBlocks::descriptor(&db_cache),
+                    KnownPeerStorageSchema::descriptor(&db_cache),
+                    KnownPointStorageSchema::descriptor(&db_cache),
+                    PeerAccessRuleStorageSchema::descriptor(&db_cache),
+                    ProtocolStorage::descriptor(&db_cache),
                 ],
                 &cfg,
             )?);
@@ -826,7 +1442,11 @@ pub mod tests_common {
             )?;
 
             // commit log storage
-            let clog = open_cl(&path, vec![BlockStorage::descriptor()])?;
+            let clog = open_cl(
+                &path,
+                vec![BlockStorage::descriptor()],
+                &CommitLogConfiguration::default(),
+            )?;
 
             Ok(Self {
                 persistent_storage: PersistentStorage::new(
@@ -0,0 +1,738 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Database schema migrations driven by `db_version` stored in [SystemStorage].
+//!
+//! Every storage format change bumps expected database version and registers [Migration] step,
+//! which rewrites stored data (column families, commit log) in place from the previous version.
+//! Instead of resync, pending steps are applied one by one on the node startup (or by `--migrate-db`).
+//!
+//! Migration is crash-safe:
+//! - step in progress is stored in [SystemStorage] before it starts,
+//! - step can store checkpoints (e.g. last processed key) with [MigrationProgress::save],
+//! - interrupted step is started again with the last stored checkpoint,
+//! - `db_version` is updated just after the step is finished.
+//!
+//! So steps must be idempotent from the last stored checkpoint.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, fs};
+
+use commitlog::message::MessageSet;
+use commitlog::{LogOptions, ReadLimit};
+use failure::Fail;
+use rocksdb::DB;
+use slog::{info, Logger};
+
+use crate::initializer::{RocksDbColumnFactory, RocksDbConfig};
+use crate::persistent::commit_log::CommitLog;
+use crate::persistent::database::open_kv;
+use crate::persistent::{
+    CommitLogConfiguration, CommitLogError, CommitLogSchema, DBError, DbConfiguration,
+};
+use crate::system_storage::DbVersion;
+use crate::{BlockStorage, StorageError, SystemStorage};
+
+/// How often is progress of the running step logged
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Possible errors for migrations
+#[derive(Debug, Fail)]
+pub enum MigrationError {
+    #[fail(display = "Storage error: {}", error)]
+    StorageError { error: StorageError },
+    #[fail(display = "Database error: {}", error)]
+    DBError { error: DBError },
+    #[fail(display = "Commit log error: {}", error)]
+    CommitLogError { error: CommitLogError },
+    #[fail(
+        display = "No migration found from database version {} (expected version: {}). Please re-sync your node to empty storage - see configuration!",
+        db_version, expected_version
+    )]
+    MissingMigration {
+        db_version: DbVersion,
+        expected_version: DbVersion,
+    },
+    #[fail(
+        display = "Database version {} is newer than expected version {}, downgrade is not supported",
+        db_version, expected_version
+    )]
+    UnsupportedDowngrade {
+        db_version: DbVersion,
+        expected_version: DbVersion,
+    },
+    #[fail(
+        display = "Migration {} -> {} failed, reason: {}",
+        from_version, to_version, reason
+    )]
+    MigrationFailed {
+        from_version: DbVersion,
+        to_version: DbVersion,
+        reason: String,
+    },
+}
+
+impl From<StorageError> for MigrationError {
+    fn from(error: StorageError) -> Self {
+        MigrationError::StorageError { error }
+    }
+}
+
+impl From<DBError> for MigrationError {
+    fn from(error: DBError) -> Self {
+        MigrationError::DBError { error }
+    }
+}
+
+impl From<CommitLogError> for MigrationError {
+    fn from(error: CommitLogError) -> Self {
+        MigrationError::CommitLogError { error }
+    }
+}
+
+impl slog::Value for MigrationError {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Everything a migration step can rewrite
+pub struct MigrationContext {
+    /// Migrated database (opened with all column families of the expected version)
+    pub db: Arc<DB>,
+    /// Data directory of the node, where commit logs are stored (see [crate::persistent::open_cl])
+    pub data_path: PathBuf,
+}
+
+/// One migration step of the database from [Migration::source_version] to [Migration::target_version]
+pub trait Migration: Send + Sync {
+    /// Version of the database, which can be migrated by this step
+    fn source_version(&self) -> DbVersion;
+
+    /// Version of the database after this step
+    fn target_version(&self) -> DbVersion {
+        self.source_version() + 1
+    }
+
+    /// Short human readable description of the step (used for logging)
+    fn description(&self) -> &'static str;
+
+    /// Migrates data in place.
+    ///
+    /// If step was interrupted, it is started again and [MigrationProgress::checkpoint] returns last saved checkpoint.
+    fn migrate(
+        &self,
+        ctx: &MigrationContext,
+        progress: &mut MigrationProgress,
+    ) -> Result<(), MigrationError>;
+}
+
+/// Tracks progress of the running migration step, saved checkpoints survive crash/restart
+pub struct MigrationProgress<'a> {
+    system_storage: SystemStorage,
+    checkpoint: Option<Vec<u8>>,
+    processed: usize,
+    last_logged: Instant,
+    description: &'static str,
+    log: &'a Logger,
+}
+
+impl<'a> MigrationProgress<'a> {
+    /// Last checkpoint saved by the (previously interrupted) step, `None` means to start from the beginning
+    pub fn checkpoint(&self) -> Option<&[u8]> {
+        self.checkpoint.as_deref()
+    }
+
+    /// Persists checkpoint, from which step is resumed after crash, `processed` is just for logging
+    pub fn save(&mut self, checkpoint: Vec<u8>, processed: usize) -> Result<(), MigrationError> {
+        self.system_storage
+            .set_migration_checkpoint(checkpoint.clone())?;
+        self.checkpoint = Some(checkpoint);
+        self.processed += processed;
+
+        if self.last_logged.elapsed() > PROGRESS_LOG_INTERVAL {
+            info!(self.log, "Migration in progress";
+                            "migration" => self.description,
+                            "processed" => self.processed);
+            self.last_logged = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// Ordered set of known migration steps
+#[derive(Default)]
+pub struct MigrationRegistry {
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl MigrationRegistry {
+    /// Registry with all migrations of the operational database (see `DbsRocksDbTableInitializer`)
+    pub fn db_migrations() -> Self {
+        // register new steps here, when db version is increased
+        MigrationRegistry::default().register(CommitLogFormatMigration)
+    }
+
+    pub fn register<M: Migration + 'static>(mut self, migration: M) -> Self {
+        self.migrations.push(Box::new(migration));
+        self
+    }
+
+    /// Resolves chain of steps from `db_version` to `expected_version`
+    pub fn pending(
+        &self,
+        db_version: DbVersion,
+        expected_version: DbVersion,
+    ) -> Result<Vec<&dyn Migration>, MigrationError> {
+        if db_version > expected_version {
+            return Err(MigrationError::UnsupportedDowngrade {
+                db_version,
+                expected_version,
+            });
+        }
+
+        let mut pending = Vec::new();
+        let mut version = db_version;
+        while version < expected_version {
+            let step = self
+                .migrations
+                .iter()
+                .find(|m| {
+                    m.source_version() == version
+                        && m.target_version() > version
+                        && m.target_version() <= expected_version
+                })
+                .ok_or(MigrationError::MissingMigration {
+                    db_version: version,
+                    expected_version,
+                })?;
+            version = step.target_version();
+            pending.push(step.as_ref());
+        }
+        Ok(pending)
+    }
+}
+
+/// Result of the migration
+#[derive(Debug, PartialEq)]
+pub enum MigrationOutcome {
+    /// Empty database, version will be stored on initialization
+    NewDatabase,
+    /// Database has already expected version
+    UpToDate,
+    /// Database was migrated
+    Migrated {
+        from_version: DbVersion,
+        to_version: DbVersion,
+        steps: usize,
+    },
+}
+
+impl fmt::Display for MigrationOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationOutcome::NewDatabase => write!(f, "new database"),
+            MigrationOutcome::UpToDate => write!(f, "up to date"),
+            MigrationOutcome::Migrated {
+                from_version,
+                to_version,
+                steps,
+            } => write!(
+                f,
+                "migrated from {} to {} ({} steps)",
+                from_version, to_version, steps
+            ),
+        }
+    }
+}
+
+/// Applies all pending migrations of already opened database `db` to reach `expected_version`.
+pub fn run_migrations(
+    db: Arc<DB>,
+    data_path: &Path,
+    registry: &MigrationRegistry,
+    expected_version: DbVersion,
+    log: &Logger,
+) -> Result<MigrationOutcome, MigrationError> {
+    let mut system_storage = SystemStorage::new(db.clone());
+    let db_version = match system_storage.get_db_version()? {
+        Some(db_version) => db_version,
+        None => return Ok(MigrationOutcome::NewDatabase),
+    };
+    if db_version == expected_version {
+        return Ok(MigrationOutcome::UpToDate);
+    }
+
+    let pending = registry.pending(db_version, expected_version)?;
+    info!(log, "Database migration needed";
+               "db_version" => db_version,
+               "expected_version" => expected_version,
+               "steps" => pending.len());
+
+    let ctx = MigrationContext {
+        db,
+        data_path: data_path.to_path_buf(),
+    };
+
+    for migration in &pending {
+        // resume interrupted step or start the new one
+        let checkpoint = match system_storage.get_migration_in_progress()? {
+            Some(in_progress) if in_progress == migration.target_version() => {
+                system_storage.get_migration_checkpoint()?
+            }
+            _ => {
+                system_storage.clear_migration_in_progress()?;
+                system_storage.set_migration_in_progress(migration.target_version())?;
+                None
+            }
+        };
+
+        info!(log, "Running database migration";
+                   "migration" => migration.description(),
+                   "from_version" => migration.source_version(),
+                   "to_version" => migration.target_version(),
+                   "resumed" => checkpoint.is_some());
+
+        let mut progress = MigrationProgress {
+            system_storage: system_storage.clone(),
+            checkpoint,
+            processed: 0,
+            last_logged: Instant::now(),
+            description: migration.description(),
+            log,
+        };
+        migration.migrate(&ctx, &mut progress)?;
+
+        system_storage.set_db_version(migration.target_version())?;
+        system_storage.clear_migration_in_progress()?;
+
+        info!(log, "Database migration finished";
+                   "migration" => migration.description(),
+                   "db_version" => migration.target_version());
+    }
+
+    Ok(MigrationOutcome::Migrated {
+        from_version: db_version,
+        to_version: expected_version,
+        steps: pending.len(),
+    })
+}
+
+/// Opens rocksdb database configured by `config` and applies all pending migrations to reach `config.expected_db_version`.
+///
+/// Database is closed afterwards, so it can be opened (and checked) by [crate::initializer::initialize_rocksdb].
+pub fn migrate_rocksdb<Factory: RocksDbColumnFactory>(
+    config: &RocksDbConfig<Factory>,
+    data_path: &Path,
+    registry: &MigrationRegistry,
+    log: &Logger,
+) -> Result<MigrationOutcome, MigrationError> {
+    // IMPORTANT: Cache object must live at least as long as DB (returned by open_kv)
+    let cache = rocksdb::Cache::new_lru_cache(config.cache_size).map_err(DBError::from)?;
+    let db = Arc::new(open_kv(
+        &config.db_path,
+        config.columns.create(&cache),
+        &DbConfiguration {
+            max_threads: config.threads,
+        },
+    )?);
+
+    run_migrations(db, data_path, registry, config.expected_db_version, log)
+}
+
+/// Rewrites commit logs written by `commitlog` crate (`<offset>.log` and `<offset>.index` files)
+/// to checksummed segments of [CommitLog].
+///
+/// Offsets of records are preserved, so locations stored in the block storage indexes stay valid.
+/// Records are written to `<name>.migrated` directory, which replaces the original one at the end,
+/// interrupted step continues from the last record stored in `<name>.migrated`.
+pub struct CommitLogFormatMigration;
+
+impl CommitLogFormatMigration {
+    /// Message limit of the legacy commit log
+    const LEGACY_MESSAGE_MAX_BYTES: usize = 30_000_000;
+
+    fn migrate_commit_log(
+        &self,
+        dir: &Path,
+        progress: &mut MigrationProgress,
+    ) -> Result<(), MigrationError> {
+        let migrated_dir = dir.with_extension("migrated");
+        let legacy_dir = dir.with_extension("legacy");
+
+        // finish interrupted replacement of the directories
+        if legacy_dir.exists() {
+            if !dir.exists() {
+                fs::rename(&migrated_dir, dir).map_err(|e| self.failed(e))?;
+            }
+            return fs::remove_dir_all(&legacy_dir).map_err(|e| self.failed(e));
+        }
+        if !has_legacy_segments(dir).map_err(|e| self.failed(e))? {
+            return Ok(());
+        }
+
+        self.copy_records(dir, &migrated_dir, progress)?;
+
+        fs::rename(dir, &legacy_dir).map_err(|e| self.failed(e))?;
+        fs::rename(&migrated_dir, dir).map_err(|e| self.failed(e))?;
+        fs::remove_dir_all(&legacy_dir).map_err(|e| self.failed(e))
+    }
+
+    /// Appends records of the legacy commit log in `dir` to the commit log in `migrated_dir`
+    fn copy_records(
+        &self,
+        dir: &Path,
+        migrated_dir: &Path,
+        progress: &mut MigrationProgress,
+    ) -> Result<(), MigrationError> {
+        let mut options = LogOptions::new(dir);
+        options.message_max_bytes(Self::LEGACY_MESSAGE_MAX_BYTES);
+        let legacy = commitlog::CommitLog::new(options).map_err(|e| self.failed(e))?;
+        let mut migrated = CommitLog::open(migrated_dir, CommitLogConfiguration::default())?;
+
+        if let Some(last_offset) = legacy.last_offset() {
+            let mut offset = migrated.next_offset();
+            while offset <= last_offset {
+                let messages = legacy
+                    .read(
+                        offset,
+                        ReadLimit::max_bytes(Self::LEGACY_MESSAGE_MAX_BYTES + 32),
+                    )
+                    .map_err(|e| self.failed(e))?;
+                let mut count = 0;
+                for message in messages.iter() {
+                    if message.offset() != migrated.next_offset() {
+                        return Err(self.failed(format!(
+                            "unexpected offset of the record {}, expected {}",
+                            message.offset(),
+                            migrated.next_offset()
+                        )));
+                    }
+                    migrated.append(message.payload())?;
+                    count += 1;
+                }
+                if count == 0 {
+                    return Err(self.failed(format!("missing record at offset {}", offset)));
+                }
+                offset += count as u64;
+                progress.save(offset.to_be_bytes().to_vec(), count)?;
+            }
+        }
+        migrated.flush()?;
+        Ok(())
+    }
+
+    fn failed<E: fmt::Display>(&self, error: E) -> MigrationError {
+        MigrationError::MigrationFailed {
+            from_version: self.source_version(),
+            to_version: self.target_version(),
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl Migration for CommitLogFormatMigration {
+    fn source_version(&self) -> DbVersion {
+        19
+    }
+
+    fn description(&self) -> &'static str {
+        "rewrite commit logs to checksummed segments"
+    }
+
+    fn migrate(
+        &self,
+        ctx: &MigrationContext,
+        progress: &mut MigrationProgress,
+    ) -> Result<(), MigrationError> {
+        self.migrate_commit_log(&ctx.data_path.join(BlockStorage::name()), progress)
+    }
+}
+
+fn has_legacy_segments(dir: &Path) -> std::io::Result<bool> {
+    if !dir.exists() {
+        return Ok(false);
+    }
+    for entry in fs::read_dir(dir)? {
+        if entry?.path().extension().map_or(false, |e| e == "log") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::{env, fs};
+
+    use failure::Error;
+    use rocksdb::Cache;
+    use slog::{Drain, Level};
+
+    use crate::persistent::database::RocksDbKeyValueSchema;
+
+    use super::*;
+
+    struct TestMigration {
+        from_version: DbVersion,
+        to_version: DbVersion,
+    }
+
+    impl Migration for TestMigration {
+        fn source_version(&self) -> DbVersion {
+            self.from_version
+        }
+
+        fn target_version(&self) -> DbVersion {
+            self.to_version
+        }
+
+        fn description(&self) -> &'static str {
+            "test migration"
+        }
+
+        fn migrate(
+            &self,
+            _ctx: &MigrationContext,
+            _progress: &mut MigrationProgress,
+        ) -> Result<(), MigrationError> {
+            Ok(())
+        }
+    }
+
+    /// Processes items `0..10`, fails once after item 5 was saved
+    struct InterruptedMigration {
+        fail: AtomicBool,
+        processed: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Migration for InterruptedMigration {
+        fn source_version(&self) -> DbVersion {
+            1
+        }
+
+        fn description(&self) -> &'static str {
+            "interrupted migration"
+        }
+
+        fn migrate(
+            &self,
+            _ctx: &MigrationContext,
+            progress: &mut MigrationProgress,
+        ) -> Result<(), MigrationError> {
+            let start = progress.checkpoint().map(|c| c[0] + 1).unwrap_or(0);
+            for item in start..10 {
+                self.processed.lock().unwrap().push(item);
+                progress.save(vec![item], 1)?;
+                if item == 5 && self.fail.swap(false, Ordering::SeqCst) {
+                    return Err(MigrationError::MigrationFailed {
+                        from_version: 1,
+                        to_version: 2,
+                        reason: "interrupted".to_string(),
+                    });
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn step(from_version: DbVersion, to_version: DbVersion) -> TestMigration {
+        TestMigration {
+            from_version,
+            to_version,
+        }
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let registry = MigrationRegistry::default()
+            .register(step(1, 2))
+            .register(step(2, 4))
+            .register(step(4, 5));
+
+        let versions = |pending: Vec<&dyn Migration>| {
+            pending
+                .iter()
+                .map(|m| (m.source_version(), m.target_version()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            versions(registry.pending(1, 5).unwrap()),
+            vec![(1, 2), (2, 4), (4, 5)]
+        );
+        assert_eq!(versions(registry.pending(2, 4).unwrap()), vec![(2, 4)]);
+        assert!(registry.pending(5, 5).unwrap().is_empty());
+
+        // step 2 -> 4 overshoots expected version
+        assert!(matches!(
+            registry.pending(1, 3),
+            Err(MigrationError::MissingMigration { db_version: 2, .. })
+        ));
+        assert!(matches!(
+            registry.pending(0, 5),
+            Err(MigrationError::MissingMigration { db_version: 0, .. })
+        ));
+        assert!(matches!(
+            registry.pending(6, 5),
+            Err(MigrationError::UnsupportedDowngrade { .. })
+        ));
+    }
+
+    #[test]
+    fn test_run_migrations() -> Result<(), Error> {
+        let path = test_path("__migration_run");
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+        let db = Arc::new(open_kv(
+            &path,
+            vec![SystemStorage::descriptor(&cache)],
+            &DbConfiguration::default(),
+        )?);
+        let log = create_logger();
+        let registry = MigrationRegistry::default()
+            .register(step(1, 2))
+            .register(step(2, 3));
+
+        // empty database is not migrated
+        assert_eq!(
+            run_migrations(db.clone(), &path, &registry, 3, &log)?,
+            MigrationOutcome::NewDatabase
+        );
+
+        let mut system_storage = SystemStorage::new(db.clone());
+        system_storage.set_db_version(1)?;
+        assert_eq!(
+            run_migrations(db.clone(), &path, &registry, 3, &log)?,
+            MigrationOutcome::Migrated {
+                from_version: 1,
+                to_version: 3,
+                steps: 2,
+            }
+        );
+        assert_eq!(system_storage.get_db_version()?, Some(3));
+        assert_eq!(system_storage.get_migration_in_progress()?, None);
+
+        assert_eq!(
+            run_migrations(db.clone(), &path, &registry, 3, &log)?,
+            MigrationOutcome::UpToDate
+        );
+        assert!(run_migrations(db, &path, &registry, 4, &log).is_err());
+        assert_eq!(system_storage.get_db_version()?, Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_interrupted_migration() -> Result<(), Error> {
+        let path = test_path("__migration_resume");
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+        let db = Arc::new(open_kv(
+            &path,
+            vec![SystemStorage::descriptor(&cache)],
+            &DbConfiguration::default(),
+        )?);
+        let log = create_logger();
+        let mut system_storage = SystemStorage::new(db.clone());
+        system_storage.set_db_version(1)?;
+
+        let processed = Arc::new(Mutex::new(vec![]));
+        let migration = InterruptedMigration {
+            fail: AtomicBool::new(true),
+            processed: processed.clone(),
+        };
+        let registry = MigrationRegistry::default().register(migration);
+
+        // first run is interrupted, version stays
+        assert!(run_migrations(db.clone(), &path, &registry, 2, &log).is_err());
+        assert_eq!(system_storage.get_db_version()?, Some(1));
+        assert_eq!(system_storage.get_migration_in_progress()?, Some(2));
+        assert_eq!(system_storage.get_migration_checkpoint()?, Some(vec![5]));
+
+        // second run continues from checkpoint
+        run_migrations(db, &path, &registry, 2, &log)?;
+        assert_eq!(system_storage.get_db_version()?, Some(2));
+        assert_eq!(system_storage.get_migration_in_progress()?, None);
+        assert_eq!(system_storage.get_migration_checkpoint()?, None);
+        assert_eq!(*processed.lock().unwrap(), (0..10).collect::<Vec<u8>>());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_commit_log_format_migration() -> Result<(), Error> {
+        let path = test_path("__migration_commit_log_format");
+        let cl_path = path.join(BlockStorage::name());
+        let records: Vec<Vec<u8>> = (0..100u32)
+            .map(|i| i.to_be_bytes().repeat(i as usize + 1))
+            .collect();
+        {
+            let mut legacy = commitlog::CommitLog::new(LogOptions::new(&cl_path))?;
+            for record in &records {
+                legacy.append_msg(record)?;
+            }
+            legacy.flush()?;
+        }
+        assert!(matches!(
+            CommitLog::open(&cl_path, CommitLogConfiguration::default()),
+            Err(CommitLogError::LegacyFormat { .. })
+        ));
+
+        let cache = Cache::new_lru_cache(32 * 1024 * 1024)?;
+        let db = Arc::new(open_kv(
+            path.join("db"),
+            vec![SystemStorage::descriptor(&cache)],
+            &DbConfiguration::default(),
+        )?);
+        let mut system_storage = SystemStorage::new(db.clone());
+        system_storage.set_db_version(19)?;
+        run_migrations(
+            db,
+            &path,
+            &MigrationRegistry::db_migrations(),
+            20,
+            &create_logger(),
+        )?;
+        assert_eq!(system_storage.get_db_version()?, Some(20));
+
+        let migrated = CommitLog::open(&cl_path, CommitLogConfiguration::default())?;
+        assert_eq!(migrated.next_offset(), records.len() as u64);
+        for (offset, record) in records.iter().enumerate() {
+            assert_eq!(&migrated.read(offset as u64)?, record);
+        }
+        assert!(!path.join("block_storage.migrated").exists());
+        assert!(!path.join("block_storage.legacy").exists());
+
+        Ok(())
+    }
+
+    fn test_path(dir_name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(out_dir.as_str()).join(dir_name);
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        path
+    }
+
+    fn create_logger() -> Logger {
+        let drain = slog_async::Async::new(
+            slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+                .build()
+                .fuse(),
+        )
+        .build()
+        .filter_level(Level::Info)
+        .fuse();
+
+        Logger::root(drain, slog::o!())
+    }
+}
@@ -0,0 +1,717 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Optional secondary indexes of applied operations for explorer-style queries:
+//! - operation hash -> location of the operation (block, validation pass, index in validation pass)
+//! - account (implicit or originated) -> operations, which touch the account, newest first
+//!
+//! Indexes are built on block application from the operations decoded by protocol
+//! (see [extract_indexed_operations]), so they contain also operations of blocks, which were later abandoned by reorg.
+//! Every entry contains hash of its block, so queries return just operations of the main chain (ancestors of the given head).
+//!
+//! Blocks, which failed to be indexed, are recorded (see [OperationsIndexStorage::mark_unindexed]),
+//! so the index can be rebuilt for them later.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use getset::{CopyGetters, Getters};
+use rocksdb::{Cache, ColumnFamilyDescriptor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crypto::hash::{BlockHash, HashType, OperationHash};
+use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::encoding::block_header::Level;
+use tezos_messages::p2p::encoding::prelude::OperationsForBlocksMessage;
+
+use crate::context::actions::context_action_storage::{
+    contract_id_to_contract_address_for_index, ContractAddress,
+};
+use crate::num_from_slice;
+use crate::persistent::codec::range_from_idx_len;
+use crate::persistent::database::{
+    default_table_options, Direction, IteratorMode, RocksDbKeyValueSchema,
+};
+use crate::persistent::{
+    BincodeEncoded, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError,
+};
+use crate::{BlockMetaStorage, BlockMetaStorageReader, PersistentStorage, StorageError};
+
+/// Fields of decoded operation contents (and their metadata), which contain account addresses
+const ACCOUNT_FIELDS: [&str; 7] = [
+    "source",
+    "destination",
+    "delegate",
+    "pkh",
+    "contract",
+    "baker",
+    "originated_contracts",
+];
+
+/// Fields with Michelson data, addresses inside them are not considered as touched accounts
+const SKIPPED_FIELDS: [&str; 5] = [
+    "parameters",
+    "script",
+    "storage",
+    "big_map_diff",
+    "lazy_storage_diff",
+];
+
+/// Operation prepared for indexing
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedOperation {
+    pub hash: OperationHash,
+    pub validation_pass: u8,
+    pub operation_index: u16,
+    /// Accounts touched by operation - sorted and without duplicates
+    pub accounts: Vec<ContractAddress>,
+}
+
+/// Resolves hashes and touched accounts of all operations of the block.
+///
+/// # Arguments
+/// * `operations` - operations of the block (all validation passes)
+/// * `operations_json` - the same operations decoded by protocol as json (`[[operation]]`), including operation metadata
+pub fn extract_indexed_operations(
+    operations: &[OperationsForBlocksMessage],
+    operations_json: &str,
+) -> Result<Vec<IndexedOperation>, StorageError> {
+    let operations_json: Vec<Vec<Value>> =
+        serde_json::from_str(operations_json).map_err(|e| StorageError::InvalidOperationsJson {
+            reason: format!("{}", e),
+        })?;
+
+    let mut result = vec![];
+    for message in operations {
+        let validation_pass = u8::try_from(message.operations_for_block().validation_pass())
+            .map_err(|_| StorageError::InvalidOperationsJson {
+                reason: format!(
+                    "Invalid validation pass: {}",
+                    message.operations_for_block().validation_pass()
+                ),
+            })?;
+        let operations_json = operations_json
+            .get(validation_pass as usize)
+            .ok_or_else(|| StorageError::InvalidOperationsJson {
+                reason: format!("Missing validation pass: {}", validation_pass),
+            })?;
+
+        for (operation_index, operation) in message.operations().iter().enumerate() {
+            let operation_json = operations_json.get(operation_index).ok_or_else(|| {
+                StorageError::InvalidOperationsJson {
+                    reason: format!(
+                        "Missing operation: {} in validation pass: {}",
+                        operation_index, validation_pass
+                    ),
+                }
+            })?;
+            let operation_index = u16::try_from(operation_index).map_err(|_| {
+                StorageError::InvalidOperationsJson {
+                    reason: format!(
+                        "Too many operations in validation pass: {}",
+                        validation_pass
+                    ),
+                }
+            })?;
+
+            result.push(IndexedOperation {
+                hash: OperationHash::try_from(operation.message_hash()?)?,
+                validation_pass,
+                operation_index,
+                accounts: extract_accounts(operation_json),
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Collects addresses of accounts, which are touched by the operation, from contents and metadata
+fn extract_accounts(operation_json: &Value) -> Vec<ContractAddress> {
+    fn collect_address(value: &Value, accounts: &mut Vec<ContractAddress>) {
+        match value {
+            Value::String(address) => {
+                let is_account = ["tz1", "tz2", "tz3", "KT1"]
+                    .iter()
+                    .any(|prefix| address.starts_with(prefix));
+                if is_account {
+                    if let Ok(address) = contract_id_to_contract_address_for_index(address) {
+                        accounts.push(address);
+                    }
+                }
+            }
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| collect_address(value, accounts)),
+            _ => (),
+        }
+    }
+
+    fn walk(value: &Value, accounts: &mut Vec<ContractAddress>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    if SKIPPED_FIELDS.contains(&name.as_str()) {
+                        continue;
+                    }
+                    if ACCOUNT_FIELDS.contains(&name.as_str()) {
+                        collect_address(value, accounts);
+                    } else {
+                        walk(value, accounts);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| walk(value, accounts)),
+            _ => (),
+        }
+    }
+
+    let mut accounts = vec![];
+    if let Some(contents) = operation_json.get("contents") {
+        walk(contents, &mut accounts);
+    }
+    accounts.sort();
+    accounts.dedup();
+    accounts
+}
+
+/// Secondary indexes of operations, see module documentation
+#[derive(Clone)]
+pub struct OperationsIndexStorage {
+    by_hash: OperationsByHashIndex,
+    by_account: OperationsByAccountIndex,
+    unindexed: UnindexedBlocks,
+    block_meta_storage: BlockMetaStorage,
+}
+
+impl OperationsIndexStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            by_hash: OperationsByHashIndex::new(persistent_storage.db()),
+            by_account: OperationsByAccountIndex::new(persistent_storage.db()),
+            unindexed: UnindexedBlocks::new(persistent_storage.db()),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+        }
+    }
+
+    /// Records block, which failed to be indexed, so the index can be rebuilt for it later
+    pub fn mark_unindexed(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.unindexed
+            .kv
+            .put(block_hash, &())
+            .map_err(StorageError::from)
+    }
+
+    /// Returns blocks, which failed to be indexed (see [OperationsIndexStorage::mark_unindexed])
+    pub fn unindexed_blocks(&self) -> Result<Vec<BlockHash>, StorageError> {
+        let mut result = vec![];
+        for (block_hash, _) in self.unindexed.kv.iterator(IteratorMode::Start)? {
+            result.push(block_hash?);
+        }
+        Ok(result)
+    }
+
+    /// Removes the mark of the block, which was indexed again (or it does not need to be indexed anymore)
+    pub fn remove_unindexed_mark(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.unindexed
+            .kv
+            .delete(block_hash)
+            .map_err(StorageError::from)
+    }
+
+    /// Indexes all operations of the applied block, indexing the same block again is harmless
+    pub fn put_block_operations(
+        &self,
+        block_hash: &BlockHash,
+        level: Level,
+        operations: Vec<IndexedOperation>,
+    ) -> Result<(), StorageError> {
+        for operation in operations {
+            let position =
+                OperationPosition::new(level, operation.validation_pass, operation.operation_index);
+            for account in &operation.accounts {
+                self.by_account.kv.put(
+                    &OperationsByAccountKey::new(account, position, &block_hash.0),
+                    &AccountOperationValue {
+                        operation_hash: operation.hash.clone(),
+                    },
+                )?;
+            }
+            self.by_hash.kv.put(
+                &operation.hash,
+                &OperationLocation {
+                    block_hash: block_hash.clone(),
+                    level,
+                    validation_pass: operation.validation_pass,
+                    operation_index: operation.operation_index,
+                    accounts: operation.accounts,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Removes index entries of operations of the block (used by history pruning)
+    pub fn delete_block_operations(
+        &self,
+        block_hash: &BlockHash,
+        operations: &[OperationsForBlocksMessage],
+    ) -> Result<(), StorageError> {
+        for message in operations {
+            for operation in message.operations() {
+                let operation_hash = OperationHash::try_from(operation.message_hash()?)?;
+                let location = match self.by_hash.kv.get(&operation_hash)? {
+                    Some(location) => location,
+                    None => continue,
+                };
+                // the same operation could be included in other block on the abandoned branch
+                if location.block_hash != *block_hash {
+                    continue;
+                }
+                let position = location.position();
+                for account in &location.accounts {
+                    self.by_account.kv.delete(&OperationsByAccountKey::new(
+                        account,
+                        position,
+                        &block_hash.0,
+                    ))?;
+                }
+                self.by_hash.kv.delete(&operation_hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds location of the operation by operation hash, operations of blocks, which are not on the main chain, are not found
+    pub fn find_operation(
+        &self,
+        operation_hash: &OperationHash,
+        head: &MainChainHead,
+    ) -> Result<Option<OperationLocation>, StorageError> {
+        match self.by_hash.kv.get(operation_hash)? {
+            Some(location) if head.contains(&location.block_hash, location.level, self)? => {
+                Ok(Some(location))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns operations touching the account, newest first, operations of blocks, which are not on the main chain, are skipped.
+    ///
+    /// # Arguments
+    /// * `account` - account address in the index format, see [contract_id_to_contract_address_for_index]
+    /// * `cursor_id` - if set, returns operations starting with [OperationPosition] with this id (inclusive) and older
+    /// * `limit` - max count of returned operations
+    /// * `head` - head of the main chain
+    pub fn get_account_operations(
+        &self,
+        account: &ContractAddress,
+        cursor_id: Option<u64>,
+        limit: usize,
+        head: &MainChainHead,
+    ) -> Result<Vec<AccountOperation>, StorageError> {
+        let from_key = OperationsByAccountKey::new(
+            account,
+            cursor_id.map_or(OperationPosition::MAX, OperationPosition),
+            &OperationsByAccountKey::MAX_BLOCK_HASH,
+        );
+
+        let mut result = Vec::with_capacity(limit);
+        for (key, value) in self
+            .by_account
+            .kv
+            .iterator(IteratorMode::From(&from_key, Direction::Reverse))?
+        {
+            if result.len() >= limit {
+                break;
+            }
+            let key = key?;
+            if key.account != *account {
+                break;
+            }
+            let block_hash = BlockHash::try_from(key.block_hash)?;
+            if !head.contains(&block_hash, key.position.level(), self)? {
+                continue;
+            }
+            let value = value?;
+            result.push(AccountOperation {
+                operation_hash: value.operation_hash,
+                block_hash,
+                level: key.position.level(),
+                validation_pass: key.position.validation_pass(),
+                operation_index: key.position.operation_index(),
+                cursor_id: key.position.0,
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Head of the main chain, indexed operations are filtered by it
+pub struct MainChainHead {
+    block_hash: BlockHash,
+    level: Level,
+    /// Resolved main chain blocks by level
+    resolved: std::cell::RefCell<HashMap<Level, Option<BlockHash>>>,
+}
+
+impl MainChainHead {
+    pub fn new(block_hash: BlockHash, level: Level) -> Self {
+        Self {
+            block_hash,
+            level,
+            resolved: Default::default(),
+        }
+    }
+
+    /// Returns true, if the block is the head or its ancestor
+    fn contains(
+        &self,
+        block_hash: &BlockHash,
+        level: Level,
+        index: &OperationsIndexStorage,
+    ) -> Result<bool, StorageError> {
+        if level > self.level {
+            return Ok(false);
+        }
+        if let Some(main_chain_block) = self.resolved.borrow().get(&level) {
+            return Ok(main_chain_block.as_ref() == Some(block_hash));
+        }
+        let main_chain_block = index
+            .block_meta_storage
+            .find_block_at_distance(self.block_hash.clone(), self.level - level)?;
+        let result = main_chain_block.as_ref() == Some(block_hash);
+        self.resolved.borrow_mut().insert(level, main_chain_block);
+        Ok(result)
+    }
+}
+
+/// Position of the operation in the chain as single ordered number `[level(32)][validation_pass(8)][operation_index(16)]`,
+/// which is used as cursor for pagination
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct OperationPosition(u64);
+
+impl OperationPosition {
+    const MAX: OperationPosition = OperationPosition(u64::MAX);
+
+    fn new(level: Level, validation_pass: u8, operation_index: u16) -> Self {
+        OperationPosition(
+            (level as u32 as u64) << 24 | (validation_pass as u64) << 16 | operation_index as u64,
+        )
+    }
+
+    fn level(&self) -> Level {
+        (self.0 >> 24) as u32 as Level
+    }
+
+    fn validation_pass(&self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    fn operation_index(&self) -> u16 {
+        self.0 as u16
+    }
+}
+
+/// Operation touching the account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountOperation {
+    pub operation_hash: OperationHash,
+    pub block_hash: BlockHash,
+    pub level: Level,
+    pub validation_pass: u8,
+    pub operation_index: u16,
+    /// Cursor id of this operation
+    pub cursor_id: u64,
+}
+
+/// Location of the operation, value of [OperationsByHashIndex]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct OperationLocation {
+    #[get = "pub"]
+    block_hash: BlockHash,
+    #[get_copy = "pub"]
+    level: Level,
+    #[get_copy = "pub"]
+    validation_pass: u8,
+    #[get_copy = "pub"]
+    operation_index: u16,
+    /// Indexed accounts, so the account index entries can be removed with the operation
+    #[get = "pub"]
+    accounts: Vec<ContractAddress>,
+}
+
+impl OperationLocation {
+    fn position(&self) -> OperationPosition {
+        OperationPosition::new(self.level, self.validation_pass, self.operation_index)
+    }
+}
+
+impl BincodeEncoded for OperationLocation {}
+
+/// Index data as `operation_hash -> location`
+#[derive(Clone)]
+pub struct OperationsByHashIndex {
+    kv: Arc<OperationsByHashIndexKV>,
+}
+
+pub type OperationsByHashIndexKV = dyn KeyValueStoreWithSchema<OperationsByHashIndex> + Sync + Send;
+
+impl OperationsByHashIndex {
+    fn new(kv: Arc<OperationsByHashIndexKV>) -> Self {
+        Self { kv }
+    }
+}
+
+impl KeyValueSchema for OperationsByHashIndex {
+    type Key = OperationHash;
+    type Value = OperationLocation;
+}
+
+impl RocksDbKeyValueSchema for OperationsByHashIndex {
+    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
+        let cf_opts = default_table_options(cache);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    #[inline]
+    fn name() -> &'static str {
+        "operations_by_hash_index"
+    }
+}
+
+/// Index data as `account -> operation`.
+///
+/// Index is composed from:
+/// * account address
+/// * operation position
+/// * block hash (the same position could be occupied by operations of different blocks at the same level)
+///
+/// Index is iterated backwards (newest operations first), so no prefix extractor is used.
+#[derive(Clone)]
+pub struct OperationsByAccountIndex {
+    kv: Arc<OperationsByAccountIndexKV>,
+}
+
+pub type OperationsByAccountIndexKV =
+    dyn KeyValueStoreWithSchema<OperationsByAccountIndex> + Sync + Send;
+
+impl OperationsByAccountIndex {
+    fn new(kv: Arc<OperationsByAccountIndexKV>) -> Self {
+        Self { kv }
+    }
+}
+
+impl KeyValueSchema for OperationsByAccountIndex {
+    type Key = OperationsByAccountKey;
+    type Value = AccountOperationValue;
+}
+
+impl RocksDbKeyValueSchema for OperationsByAccountIndex {
+    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
+        let cf_opts = default_table_options(cache);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    #[inline]
+    fn name() -> &'static str {
+        "operations_by_account_index"
+    }
+}
+
+/// Blocks, which failed to be indexed, as `block_hash -> ()`
+#[derive(Clone)]
+pub struct UnindexedBlocks {
+    kv: Arc<UnindexedBlocksKV>,
+}
+
+pub type UnindexedBlocksKV = dyn KeyValueStoreWithSchema<UnindexedBlocks> + Sync + Send;
+
+impl UnindexedBlocks {
+    fn new(kv: Arc<UnindexedBlocksKV>) -> Self {
+        Self { kv }
+    }
+}
+
+impl KeyValueSchema for UnindexedBlocks {
+    type Key = BlockHash;
+    type Value = ();
+}
+
+impl RocksDbKeyValueSchema for UnindexedBlocks {
+    fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
+        let cf_opts = default_table_options(cache);
+        ColumnFamilyDescriptor::new(Self::name(), cf_opts)
+    }
+
+    #[inline]
+    fn name() -> &'static str {
+        "operations_index_unindexed_blocks"
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct OperationsByAccountKey {
+    account: ContractAddress,
+    position: OperationPosition,
+    block_hash: Vec<u8>,
+}
+
+impl OperationsByAccountKey {
+    const LEN_ACCOUNT: usize = 22;
+    const LEN_POSITION: usize = mem::size_of::<u64>();
+    const LEN_BLOCK_HASH: usize = HashType::BlockHash.size();
+    const LEN_TOTAL: usize = Self::LEN_ACCOUNT + Self::LEN_POSITION + Self::LEN_BLOCK_HASH;
+
+    const IDX_ACCOUNT: usize = 0;
+    const IDX_POSITION: usize = Self::IDX_ACCOUNT + Self::LEN_ACCOUNT;
+    const IDX_BLOCK_HASH: usize = Self::IDX_POSITION + Self::LEN_POSITION;
+
+    const RANGE_ACCOUNT: Range<usize> = range_from_idx_len(Self::IDX_ACCOUNT, Self::LEN_ACCOUNT);
+    const RANGE_BLOCK_HASH: Range<usize> =
+        range_from_idx_len(Self::IDX_BLOCK_HASH, Self::LEN_BLOCK_HASH);
+
+    /// Greatest block hash, so reverse iteration starts with all operations at the position
+    const MAX_BLOCK_HASH: [u8; Self::LEN_BLOCK_HASH] = [0xFF; Self::LEN_BLOCK_HASH];
+
+    fn new(account: &[u8], position: OperationPosition, block_hash: &[u8]) -> Self {
+        Self {
+            account: account.to_vec(),
+            position,
+            block_hash: block_hash.to_vec(),
+        }
+    }
+}
+
+/// Decoder for `OperationsByAccountKey`
+///
+/// * bytes layout `[account(22)][position(8)][block_hash(32)]`
+impl Decoder for OperationsByAccountKey {
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        if Self::LEN_TOTAL == bytes.len() {
+            Ok(OperationsByAccountKey {
+                account: bytes[Self::RANGE_ACCOUNT].to_vec(),
+                position: OperationPosition(num_from_slice!(bytes, Self::IDX_POSITION, u64)),
+                block_hash: bytes[Self::RANGE_BLOCK_HASH].to_vec(),
+            })
+        } else {
+            Err(SchemaError::DecodeError)
+        }
+    }
+}
+
+/// Encoder for `OperationsByAccountKey`
+///
+/// * bytes layout `[account(22)][position(8)][block_hash(32)]`
+impl Encoder for OperationsByAccountKey {
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        if self.account.len() != Self::LEN_ACCOUNT || self.block_hash.len() != Self::LEN_BLOCK_HASH
+        {
+            return Err(SchemaError::EncodeError);
+        }
+        let mut result = Vec::with_capacity(Self::LEN_TOTAL);
+        result.extend(&self.account);
+        result.extend(&self.position.0.to_be_bytes());
+        result.extend(&self.block_hash);
+        Ok(result)
+    }
+}
+
+/// Value of [OperationsByAccountIndex]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountOperationValue {
+    operation_hash: OperationHash,
+}
+
+impl BincodeEncoded for AccountOperationValue {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_position_ordering() {
+        let position = OperationPosition::new(1_234_567, 3, 257);
+        assert_eq!(1_234_567, position.level());
+        assert_eq!(3, position.validation_pass());
+        assert_eq!(257, position.operation_index());
+
+        assert!(OperationPosition::new(10, 0, 5) < OperationPosition::new(10, 1, 0));
+        assert!(OperationPosition::new(10, 3, 65535) < OperationPosition::new(11, 0, 0));
+        assert!(OperationPosition::new(Level::MAX, 255, 65535) < OperationPosition::MAX);
+    }
+
+    #[test]
+    fn test_account_key_encode_decode() -> Result<(), failure::Error> {
+        let account =
+            contract_id_to_contract_address_for_index("tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17")?;
+        let expected = OperationsByAccountKey::new(
+            &account,
+            OperationPosition::new(100, 3, 7),
+            &[7; HashType::BlockHash.size()],
+        );
+        let encoded = expected.encode()?;
+        assert_eq!(OperationsByAccountKey::LEN_TOTAL, encoded.len());
+        assert_eq!(expected, OperationsByAccountKey::decode(&encoded)?);
+
+        assert!(OperationsByAccountKey::new(
+            &[1, 2, 3],
+            OperationPosition::new(1, 0, 0),
+            &[7; HashType::BlockHash.size()]
+        )
+        .encode()
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_accounts() -> Result<(), failure::Error> {
+        let operation: Value = serde_json::from_str(
+            r#"{
+                "protocol": "PsDELPH1Kxsxt8f9eWbxQeRxkjfbxoqM52jvs5Y5fBxWWh4ifpo",
+                "hash": "opNXWhq9tJk5Km6WsHbh6N2ob5b2YwrH6dwoXqRcHpa1R9mE4mT",
+                "contents": [
+                    {
+                        "kind": "transaction",
+                        "source": "tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17",
+                        "destination": "KT1VG2WtYdSWz5E7chTeAdDPZNy2MpP8pTfL",
+                        "parameters": { "entrypoint": "transfer", "value": { "string": "tz1b7tUupMgCNw2cCLpKTkSD1NZzB5TkP2sv" } },
+                        "metadata": {
+                            "balance_updates": [
+                                { "kind": "contract", "contract": "tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17", "change": "-1000" },
+                                { "kind": "freezer", "category": "fees", "delegate": "tz1VwmmesDxud2BJEyDKUTV5T5VEP8tGBKGD", "change": "1000" }
+                            ],
+                            "operation_result": { "status": "applied", "storage": { "string": "tz1b7tUupMgCNw2cCLpKTkSD1NZzB5TkP2sv" } },
+                            "internal_operation_results": [
+                                {
+                                    "kind": "origination",
+                                    "source": "KT1VG2WtYdSWz5E7chTeAdDPZNy2MpP8pTfL",
+                                    "result": { "status": "applied", "originated_contracts": [ "KT1Hkg5qeNhfwpKW4fXvq7HGZB9z2EnmCCA9" ] }
+                                }
+                            ]
+                        }
+                    }
+                ]
+            }"#,
+        )?;
+
+        let mut expected = vec![
+            contract_id_to_contract_address_for_index("tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17")?,
+            contract_id_to_contract_address_for_index("KT1VG2WtYdSWz5E7chTeAdDPZNy2MpP8pTfL")?,
+            contract_id_to_contract_address_for_index("tz1VwmmesDxud2BJEyDKUTV5T5VEP8tGBKGD")?,
+            contract_id_to_contract_address_for_index("KT1Hkg5qeNhfwpKW4fXvq7HGZB9z2EnmCCA9")?,
+        ];
+        expected.sort();
+        assert_eq!(expected, extract_accounts(&operation));
+
+        // no contents, no accounts
+        assert!(extract_accounts(
+            &serde_json::json!({ "branch": "tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17" })
+        )
+        .is_empty());
+        Ok(())
+    }
+}
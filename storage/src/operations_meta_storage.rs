@@ -12,6 +12,8 @@ use tezos_messages::p2p::encoding::prelude::*;
 use crate::persistent::database::{
     default_table_options, IteratorMode, IteratorWithSchema, RocksDbKeyValueSchema,
 };
+use crate::persistent::merge_secondary::MergeOperatorDescriptor;
+use crate::persistent::write_batch::StorageWriteBatch;
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, SchemaError};
 use crate::PersistentStorage;
 use crate::{BlockHeaderWithHash, StorageError};
@@ -28,7 +30,7 @@ pub struct OperationsMetaStorage {
 impl OperationsMetaStorage {
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
         Self {
-            kv: persistent_storage.db(),
+            kv: persistent_storage.merge_db(),
         }
     }
 
@@ -93,6 +95,19 @@ impl OperationsMetaStorage {
         self.kv.merge(block_hash, meta).map_err(StorageError::from)
     }
 
+    /// Adds merge of the metadata to the `batch`
+    #[inline]
+    pub fn put_batched(
+        &self,
+        batch: &mut StorageWriteBatch,
+        block_hash: &BlockHash,
+        meta: &Meta,
+    ) -> Result<(), StorageError> {
+        batch
+            .merge::<Self>(block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
         self.kv.get(block_hash).map_err(StorageError::from)
@@ -103,6 +118,11 @@ impl OperationsMetaStorage {
         self.kv.contains(block_hash).map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn delete(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.delete(block_hash).map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn iter(&self, mode: IteratorMode<Self>) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv.iterator(mode).map_err(StorageError::from)
@@ -114,14 +134,22 @@ impl KeyValueSchema for OperationsMetaStorage {
     type Value = Meta;
 }
 
+impl OperationsMetaStorage {
+    /// Merge operator of the column family, so it can be registered also by [crate::persistent::merge_secondary::SecondaryMergeDB]
+    pub fn merge_operator() -> MergeOperatorDescriptor {
+        MergeOperatorDescriptor {
+            cf_name: Self::name(),
+            name: "operations_meta_storage_merge_operator",
+            merge_fn: merge_meta_value,
+        }
+    }
+}
+
 impl RocksDbKeyValueSchema for OperationsMetaStorage {
     fn descriptor(cache: &Cache) -> ColumnFamilyDescriptor {
+        let merge_operator = Self::merge_operator();
         let mut cf_opts = default_table_options(cache);
-        cf_opts.set_merge_operator(
-            "operations_meta_storage_merge_operator",
-            merge_meta_value,
-            None,
-        );
+        cf_opts.set_merge_operator(merge_operator.name, merge_operator.merge_fn, None);
         ColumnFamilyDescriptor::new(Self::name(), cf_opts)
     }
 
@@ -135,6 +163,13 @@ fn merge_meta_value(
     _new_key: &[u8],
     existing_val: Option<&[u8]>,
     operands: &mut MergeOperands,
+) -> Option<Vec<u8>> {
+    merge_meta_operands(existing_val, operands)
+}
+
+fn merge_meta_operands<'a, I: IntoIterator<Item = &'a [u8]>>(
+    existing_val: Option<&[u8]>,
+    operands: I,
 ) -> Option<Vec<u8>> {
     let mut result = existing_val.map(|v| v.to_vec());
 
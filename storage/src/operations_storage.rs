@@ -47,6 +47,24 @@ impl OperationsStorage {
         self.put(&key, &message)
     }
 
+    /// Removes all stored operations (all validation passes) for block
+    pub fn delete_operations(&self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        let key = OperationKey {
+            block_hash: block_hash.clone(),
+            validation_pass: 0,
+        };
+
+        let mut keys = vec![];
+        for (key, _) in self.kv.prefix_iterator(&key)? {
+            keys.push(key?);
+        }
+
+        for key in keys {
+            self.kv.delete(&key)?;
+        }
+        Ok(())
+    }
+
     #[inline]
     fn put(
         &self,
@@ -0,0 +1,685 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Persistent state of known p2p points (socket addresses) and peers (identities), which survives node restart.
+//!
+//! Every point and peer has a score, last seen time and count of successful and failed connections.
+//! Misbehaving points and peers are greylisted for a limited time, every next greylisting doubles its duration
+//! (up to [MAX_GREYLIST_DELAY]), connection, which lasted at least [MIN_CONNECTION_LIFETIME], resets it back to [INITIAL_GREYLIST_DELAY]
+//! on disconnect (see Octez `P2p_point_state`), so peers, which are disconnected right after the handshake, are not forgiven.
+//! Points and peers banned explicitly (by RPC) are rejected until they are unbanned, ban does not expire.
+//! Points and peers, which were not seen for [KNOWN_ENTRY_EXPIRY], are removed (if they are neither greylisted nor banned).
+//! Trusted peers/points, private mode whitelist and peer policies changed at runtime are kept as [PeerAccessRule]s, they never expire.
+
+use std::cmp::{self, Reverse};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crypto::hash::CryptoboxPublicKeyHash;
+
+use crate::persistent::database::{DBError, IteratorMode, RocksDbKeyValueSchema};
+use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
+use crate::{PersistentStorage, StorageError};
+
+/// Score added for every successful connection
+pub const SCORE_CONNECTION_SUCCEEDED: i32 = 10;
+/// Score added for every failed connection
+pub const SCORE_CONNECTION_FAILED: i32 = -5;
+/// Score added for every greylisting
+pub const SCORE_GREYLISTED: i32 = -50;
+/// Score is kept in range `-MAX_SCORE..=MAX_SCORE`, so old history does not outweigh the recent one forever
+pub const MAX_SCORE: i32 = 1_000;
+
+/// Duration of the first greylisting
+pub const INITIAL_GREYLIST_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Maximal duration of greylisting
+pub const MAX_GREYLIST_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// Greylist delay is reset just by connections, which lasted at least this time
+pub const MIN_CONNECTION_LIFETIME: Duration = Duration::from_secs(10 * 60);
+/// Points and peers not seen for this time are removed (if they are neither greylisted nor banned)
+pub const KNOWN_ENTRY_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub type KnownPeerStorageKV = dyn KeyValueStoreWithSchema<KnownPeerStorageSchema> + Sync + Send;
+pub type KnownPointStorageKV = dyn KeyValueStoreWithSchema<KnownPointStorageSchema> + Sync + Send;
+pub type PeerAccessRuleStorageKV =
+    dyn KeyValueStoreWithSchema<PeerAccessRuleStorageSchema> + Sync + Send;
+
+lazy_static! {
+    /// Guards read-modify-write of points and peers
+    static ref PEER_WRITE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Converts time to seconds since unix epoch (times before epoch are stored as zero)
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Connection history shared by points and peers, times are stored as seconds since unix epoch
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, CopyGetters)]
+pub struct ConnectionStats {
+    #[get_copy = "pub"]
+    score: i32,
+    /// When point/peer was added to the storage
+    #[get_copy = "pub"]
+    known_since: u64,
+    /// Last successful connection
+    #[get_copy = "pub"]
+    last_seen: Option<u64>,
+    /// Last failed connection
+    #[get_copy = "pub"]
+    last_failed: Option<u64>,
+    #[get_copy = "pub"]
+    successful_connections: u64,
+    #[get_copy = "pub"]
+    failed_connections: u64,
+    #[get_copy = "pub"]
+    greylisted_until: Option<u64>,
+    /// Duration of the next greylisting in seconds
+    #[get_copy = "pub"]
+    greylist_delay: u64,
+    /// Banned until unbanned
+    #[get_copy = "pub"]
+    banned: bool,
+}
+
+impl ConnectionStats {
+    fn new(now: SystemTime) -> Self {
+        Self {
+            score: 0,
+            known_since: unix_secs(now),
+            last_seen: None,
+            last_failed: None,
+            successful_connections: 0,
+            failed_connections: 0,
+            greylisted_until: None,
+            greylist_delay: INITIAL_GREYLIST_DELAY.as_secs(),
+            banned: false,
+        }
+    }
+
+    fn add_score(&mut self, score: i32) {
+        self.score = (self.score + score).clamp(-MAX_SCORE, MAX_SCORE);
+    }
+
+    fn connection_succeeded(&mut self, now: SystemTime) {
+        self.add_score(SCORE_CONNECTION_SUCCEEDED);
+        self.last_seen = Some(unix_secs(now));
+        self.successful_connections += 1;
+    }
+
+    /// Resets greylist delay, if connection lasted long enough (and it was not closed by greylisting)
+    fn connection_closed(&mut self, connected_since: SystemTime, now: SystemTime) {
+        let lifetime = now.duration_since(connected_since).unwrap_or_default();
+        if lifetime >= MIN_CONNECTION_LIFETIME && !self.is_greylisted(now) {
+            self.greylist_delay = INITIAL_GREYLIST_DELAY.as_secs();
+        }
+    }
+
+    fn connection_failed(&mut self, now: SystemTime) {
+        self.add_score(SCORE_CONNECTION_FAILED);
+        self.last_failed = Some(unix_secs(now));
+        self.failed_connections += 1;
+    }
+
+    /// Greylists for the current delay and doubles the delay for the next time, returns end of greylisting
+    fn greylist(&mut self, now: SystemTime) -> SystemTime {
+        self.add_score(SCORE_GREYLISTED);
+        let greylisted_until = unix_secs(now) + self.greylist_delay;
+        self.greylisted_until = Some(
+            self.greylisted_until
+                .map_or(greylisted_until, |until| cmp::max(until, greylisted_until)),
+        );
+        self.greylist_delay = cmp::min(self.greylist_delay * 2, MAX_GREYLIST_DELAY.as_secs());
+        UNIX_EPOCH + Duration::from_secs(greylisted_until)
+    }
+
+    /// Returns end of greylisting, if it did not expire yet
+    pub fn greylisted_until_time(&self, now: SystemTime) -> Option<SystemTime> {
+        self.greylisted_until
+            .filter(|until| *until > unix_secs(now))
+            .map(|until| UNIX_EPOCH + Duration::from_secs(until))
+    }
+
+    pub fn is_greylisted(&self, now: SystemTime) -> bool {
+        self.greylisted_until_time(now).is_some()
+    }
+
+    /// Returns true, if connections should be rejected (banned or greylisted)
+    pub fn is_rejected(&self, now: SystemTime) -> bool {
+        self.banned || self.is_greylisted(now)
+    }
+
+    /// Ends ban and greylisting, greylist delay is kept, returns false, if nothing was changed
+    fn unban(&mut self) -> bool {
+        let greylisted = self.greylisted_until.take().is_some();
+        let banned = std::mem::replace(&mut self.banned, false);
+        greylisted || banned
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        let last_activity = cmp::max(
+            self.known_since,
+            cmp::max(self.last_seen.unwrap_or(0), self.last_failed.unwrap_or(0)),
+        );
+        !self.is_rejected(now) && last_activity + KNOWN_ENTRY_EXPIRY.as_secs() < unix_secs(now)
+    }
+}
+
+/// Known point (socket address) with the identity of the peer, which was last connected from it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Getters)]
+pub struct KnownPoint {
+    #[get = "pub"]
+    stats: ConnectionStats,
+    #[get = "pub"]
+    peer_id: Option<CryptoboxPublicKeyHash>,
+}
+
+/// Known peer (identity) with the last address it was connected from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Getters)]
+pub struct KnownPeer {
+    #[get = "pub"]
+    stats: ConnectionStats,
+    #[get = "pub"]
+    last_address: Option<SocketAddr>,
+}
+
+/// Access rule of the peer or point, which was set at runtime (by RPC)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PeerAccessRule {
+    TrustedPeer(CryptoboxPublicKeyHash),
+    TrustedPoint(SocketAddr),
+    PrivateWhitelistedPeer(CryptoboxPublicKeyHash),
+    /// Stored value is the policy in the text form, e.g. `disable-mempool-relay,max-msg-rate=100`
+    PeerPolicy(CryptoboxPublicKeyHash),
+}
+
+/// Storage of known p2p points and peers
+#[derive(Clone)]
+pub struct PeerStorage {
+    peers: Arc<KnownPeerStorageKV>,
+    points: Arc<KnownPointStorageKV>,
+    access_rules: Arc<PeerAccessRuleStorageKV>,
+}
+
+impl PeerStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            peers: persistent_storage.db(),
+            points: persistent_storage.db(),
+            access_rules: persistent_storage.db(),
+        }
+    }
+
+    #[inline]
+    pub fn get_point(&self, address: &SocketAddr) -> Result<Option<KnownPoint>, StorageError> {
+        self.points.get(address).map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get_peer(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+    ) -> Result<Option<KnownPeer>, StorageError> {
+        self.peers.get(peer_id).map_err(StorageError::from)
+    }
+
+    /// Returns score of the point, unknown points have zero score
+    pub fn point_score(&self, address: &SocketAddr) -> Result<i32, StorageError> {
+        Ok(self
+            .get_point(address)?
+            .map_or(0, |point| point.stats.score))
+    }
+
+    /// Stores newly discovered points, already known points are not changed
+    pub fn add_points<I: IntoIterator<Item = SocketAddr>>(
+        &self,
+        addresses: I,
+        now: SystemTime,
+    ) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        for address in addresses {
+            if !self.points.contains(&address)? {
+                self.points.put(
+                    &address,
+                    &KnownPoint {
+                        stats: ConnectionStats::new(now),
+                        peer_id: None,
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records successful connection (finished handshake) with the peer.
+    ///
+    /// Point is recorded just for outgoing connections, incoming connections come from random ports.
+    pub fn connection_succeeded(
+        &self,
+        address: &SocketAddr,
+        peer_id: &CryptoboxPublicKeyHash,
+        incoming: bool,
+        now: SystemTime,
+    ) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        if !incoming {
+            let mut point = self.point_or_new(address, now)?;
+            point.stats.connection_succeeded(now);
+            point.peer_id = Some(peer_id.clone());
+            self.points.put(address, &point)?;
+        }
+
+        let mut peer = self.peer_or_new(peer_id, now)?;
+        peer.stats.connection_succeeded(now);
+        peer.last_address = Some(*address);
+        self.peers.put(peer_id, &peer).map_err(StorageError::from)
+    }
+
+    /// Records closed connection with the peer, greylist delay is reset, if connection lasted at least [MIN_CONNECTION_LIFETIME].
+    ///
+    /// Point is recorded just for outgoing connections (see [PeerStorage::connection_succeeded]).
+    pub fn connection_closed(
+        &self,
+        address: &SocketAddr,
+        peer_id: &CryptoboxPublicKeyHash,
+        incoming: bool,
+        connected_since: SystemTime,
+        now: SystemTime,
+    ) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        if !incoming {
+            if let Some(mut point) = self.get_point(address)? {
+                point.stats.connection_closed(connected_since, now);
+                self.points.put(address, &point)?;
+            }
+        }
+        if let Some(mut peer) = self.get_peer(peer_id)? {
+            peer.stats.connection_closed(connected_since, now);
+            self.peers.put(peer_id, &peer)?;
+        }
+        Ok(())
+    }
+
+    /// Records failed outgoing connection to the point
+    pub fn connection_failed(
+        &self,
+        address: &SocketAddr,
+        now: SystemTime,
+    ) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut point = self.point_or_new(address, now)?;
+        point.stats.connection_failed(now);
+        self.points.put(address, &point).map_err(StorageError::from)
+    }
+
+    /// Greylists point and peer (if known) with exponential backoff, returns end of the point greylisting
+    pub fn greylist(
+        &self,
+        address: &SocketAddr,
+        peer_id: Option<&CryptoboxPublicKeyHash>,
+        now: SystemTime,
+    ) -> Result<SystemTime, StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut point = self.point_or_new(address, now)?;
+        let greylisted_until = point.stats.greylist(now);
+        self.points.put(address, &point)?;
+
+        if let Some(peer_id) = peer_id {
+            let mut peer = self.peer_or_new(peer_id, now)?;
+            peer.stats.greylist(now);
+            peer.last_address = Some(*address);
+            self.peers.put(peer_id, &peer)?;
+        }
+        Ok(greylisted_until)
+    }
+
+    /// Greylists just the peer (regardless of its address) with exponential backoff, returns end of greylisting
+    pub fn greylist_peer(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+        now: SystemTime,
+    ) -> Result<SystemTime, StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut peer = self.peer_or_new(peer_id, now)?;
+        let greylisted_until = peer.stats.greylist(now);
+        self.peers.put(peer_id, &peer)?;
+        Ok(greylisted_until)
+    }
+
+    /// Bans the point until it is unbanned
+    pub fn ban_point(&self, address: &SocketAddr, now: SystemTime) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut point = self.point_or_new(address, now)?;
+        point.stats.banned = true;
+        self.points.put(address, &point).map_err(StorageError::from)
+    }
+
+    /// Bans the peer (regardless of its address) until it is unbanned
+    pub fn ban_peer(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+        now: SystemTime,
+    ) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut peer = self.peer_or_new(peer_id, now)?;
+        peer.stats.banned = true;
+        self.peers.put(peer_id, &peer).map_err(StorageError::from)
+    }
+
+    /// Ends ban and greylisting of the point, greylist delay is kept
+    pub fn whitelist_point(&self, address: &SocketAddr) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        if let Some(mut point) = self.get_point(address)? {
+            if point.stats.unban() {
+                self.points.put(address, &point)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends ban and greylisting of the peer, greylist delay is kept
+    pub fn whitelist_peer(&self, peer_id: &CryptoboxPublicKeyHash) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        if let Some(mut peer) = self.get_peer(peer_id)? {
+            if peer.stats.unban() {
+                self.peers.put(peer_id, &peer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all known points
+    pub fn known_points(&self) -> Result<Vec<(SocketAddr, KnownPoint)>, StorageError> {
+        let mut result = vec![];
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            result.push((address?, point?));
+        }
+        Ok(result)
+    }
+
+    /// Returns all known peers
+    pub fn known_peers(&self) -> Result<Vec<(CryptoboxPublicKeyHash, KnownPeer)>, StorageError> {
+        let mut result = vec![];
+        for (peer_id, peer) in self.peers.iterator(IteratorMode::Start)? {
+            result.push((peer_id?, peer?));
+        }
+        Ok(result)
+    }
+
+    /// Returns true, if peer is banned or greylisted
+    pub fn is_peer_rejected(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+        now: SystemTime,
+    ) -> Result<bool, StorageError> {
+        Ok(self
+            .get_peer(peer_id)?
+            .filter(|peer| peer.stats.is_rejected(now))
+            .is_some())
+    }
+
+    /// Returns all banned points
+    pub fn banned_points(&self) -> Result<Vec<SocketAddr>, StorageError> {
+        let mut result = vec![];
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            if point?.stats.banned {
+                result.push(address?);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns all points, which are greylisted now, with the end of greylisting
+    pub fn greylisted_points(
+        &self,
+        now: SystemTime,
+    ) -> Result<Vec<(SocketAddr, SystemTime)>, StorageError> {
+        let mut result = vec![];
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            if let Some(greylisted_until) = point?.stats.greylisted_until_time(now) {
+                result.push((address?, greylisted_until));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns neither greylisted nor banned points ordered by score (the best first), points with the same score are ordered by last seen time
+    pub fn dial_candidates(
+        &self,
+        now: SystemTime,
+        limit: usize,
+    ) -> Result<Vec<SocketAddr>, StorageError> {
+        let mut candidates = vec![];
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            let point = point?;
+            if !point.stats.is_rejected(now) {
+                candidates.push((address?, point.stats.score, point.stats.last_seen));
+            }
+        }
+        candidates.sort_by_key(|(_, score, last_seen)| Reverse((*score, *last_seen)));
+        Ok(candidates
+            .into_iter()
+            .take(limit)
+            .map(|(address, ..)| address)
+            .collect())
+    }
+
+    /// Ends greylisting of all points and peers, greylist delays are kept, so next greylisting is still longer.
+    ///
+    /// Bans are kept, banned points and peers have to be unbanned one by one.
+    pub fn whitelist_all(&self) -> Result<(), StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            let mut point = point?;
+            if point.stats.greylisted_until.take().is_some() {
+                self.points.put(&address?, &point)?;
+            }
+        }
+        for (peer_id, peer) in self.peers.iterator(IteratorMode::Start)? {
+            let mut peer = peer?;
+            if peer.stats.greylisted_until.take().is_some() {
+                self.peers.put(&peer_id?, &peer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the access rule, value is used just by [PeerAccessRule::PeerPolicy] (empty for the other rules)
+    pub fn put_access_rule(&self, rule: &PeerAccessRule, value: &str) -> Result<(), StorageError> {
+        self.access_rules
+            .put(rule, &value.to_string())
+            .map_err(StorageError::from)
+    }
+
+    pub fn delete_access_rule(&self, rule: &PeerAccessRule) -> Result<(), StorageError> {
+        self.access_rules.delete(rule).map_err(StorageError::from)
+    }
+
+    /// Returns all stored access rules with their values
+    pub fn access_rules(&self) -> Result<Vec<(PeerAccessRule, String)>, StorageError> {
+        let mut result = vec![];
+        for (rule, value) in self.access_rules.iterator(IteratorMode::Start)? {
+            result.push((rule?, value?));
+        }
+        Ok(result)
+    }
+
+    /// Removes points and peers, which were not seen for [KNOWN_ENTRY_EXPIRY], returns count of removed entries
+    pub fn remove_expired(&self, now: SystemTime) -> Result<usize, StorageError> {
+        let _guard = PEER_WRITE_LOCK.lock().map_err(DBError::from)?;
+        let mut removed = 0;
+        for (address, point) in self.points.iterator(IteratorMode::Start)? {
+            if point?.stats.is_expired(now) {
+                self.points.delete(&address?)?;
+                removed += 1;
+            }
+        }
+        for (peer_id, peer) in self.peers.iterator(IteratorMode::Start)? {
+            if peer?.stats.is_expired(now) {
+                self.peers.delete(&peer_id?)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn point_or_new(
+        &self,
+        address: &SocketAddr,
+        now: SystemTime,
+    ) -> Result<KnownPoint, StorageError> {
+        Ok(self.get_point(address)?.unwrap_or_else(|| KnownPoint {
+            stats: ConnectionStats::new(now),
+            peer_id: None,
+        }))
+    }
+
+    fn peer_or_new(
+        &self,
+        peer_id: &CryptoboxPublicKeyHash,
+        now: SystemTime,
+    ) -> Result<KnownPeer, StorageError> {
+        Ok(self.get_peer(peer_id)?.unwrap_or_else(|| KnownPeer {
+            stats: ConnectionStats::new(now),
+            last_address: None,
+        }))
+    }
+}
+
+impl BincodeEncoded for SocketAddr {}
+
+impl BincodeEncoded for KnownPeer {}
+
+impl BincodeEncoded for KnownPoint {}
+
+impl BincodeEncoded for PeerAccessRule {}
+
+/// Schema of known peers as `peer_id -> peer`
+pub struct KnownPeerStorageSchema;
+
+impl KeyValueSchema for KnownPeerStorageSchema {
+    type Key = CryptoboxPublicKeyHash;
+    type Value = KnownPeer;
+}
+
+impl RocksDbKeyValueSchema for KnownPeerStorageSchema {
+    #[inline]
+    fn name() -> &'static str {
+        "p2p_peer_storage"
+    }
+}
+
+/// Schema of known points as `address -> point`
+pub struct KnownPointStorageSchema;
+
+impl KeyValueSchema for KnownPointStorageSchema {
+    type Key = SocketAddr;
+    type Value = KnownPoint;
+}
+
+impl RocksDbKeyValueSchema for KnownPointStorageSchema {
+    #[inline]
+    fn name() -> &'static str {
+        "p2p_point_storage"
+    }
+}
+
+/// Schema of access rules set at runtime as `rule -> value`
+pub struct PeerAccessRuleStorageSchema;
+
+impl KeyValueSchema for PeerAccessRuleStorageSchema {
+    type Key = PeerAccessRule;
+    type Value = String;
+}
+
+impl RocksDbKeyValueSchema for PeerAccessRuleStorageSchema {
+    #[inline]
+    fn name() -> &'static str {
+        "p2p_access_rule_storage"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_greylist_exponential_backoff() {
+        let initial = INITIAL_GREYLIST_DELAY.as_secs();
+        let mut stats = ConnectionStats::new(at(1_000));
+
+        assert_eq!(at(1_000 + initial), stats.greylist(at(1_000)));
+        assert!(stats.is_greylisted(at(1_000 + initial - 1)));
+        assert!(!stats.is_greylisted(at(1_000 + initial)));
+
+        // next greylisting is twice as long
+        assert_eq!(at(5_000 + 2 * initial), stats.greylist(at(5_000)));
+        assert_eq!(4 * initial, stats.greylist_delay());
+        assert_eq!(2 * SCORE_GREYLISTED, stats.score());
+
+        // delay is limited
+        for _ in 0..32 {
+            stats.greylist(at(5_000));
+        }
+        assert_eq!(MAX_GREYLIST_DELAY.as_secs(), stats.greylist_delay());
+
+        // successful connection does not reset delay, just the connection, which lasted long enough
+        stats.connection_succeeded(at(100_000));
+        assert_eq!(MAX_GREYLIST_DELAY.as_secs(), stats.greylist_delay());
+        assert_eq!(Some(100_000), stats.last_seen());
+        assert_eq!(1, stats.successful_connections());
+
+        let min_lifetime = MIN_CONNECTION_LIFETIME.as_secs();
+        stats.connection_closed(at(100_000), at(100_000 + min_lifetime - 1));
+        assert_eq!(MAX_GREYLIST_DELAY.as_secs(), stats.greylist_delay());
+        stats.connection_closed(at(100_000), at(100_000 + min_lifetime));
+        assert_eq!(initial, stats.greylist_delay());
+    }
+
+    #[test]
+    fn test_score_is_limited() {
+        let mut stats = ConnectionStats::new(at(0));
+        for _ in 0..1_000 {
+            stats.connection_failed(at(1));
+        }
+        assert_eq!(-MAX_SCORE, stats.score());
+        assert_eq!(1_000, stats.failed_connections());
+        assert_eq!(Some(1), stats.last_failed());
+
+        for _ in 0..1_000 {
+            stats.connection_succeeded(at(2));
+        }
+        assert_eq!(MAX_SCORE, stats.score());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let expiry = KNOWN_ENTRY_EXPIRY.as_secs();
+        let mut stats = ConnectionStats::new(at(0));
+        assert!(!stats.is_expired(at(expiry)));
+        assert!(stats.is_expired(at(expiry + 1)));
+
+        // greylisted and banned entries do not expire
+        stats.greylist(at(expiry + 1));
+        assert!(!stats.is_expired(at(expiry + 1)));
+        stats.banned = true;
+        assert!(!stats.is_expired(at(10 * expiry)));
+        assert!(stats.unban());
+        assert!(!stats.unban());
+
+        stats.connection_succeeded(at(expiry + 2));
+        assert!(!stats.is_expired(at(2 * expiry + 2)));
+        assert!(stats.is_expired(at(2 * expiry + 3)));
+    }
+}
@@ -1,13 +1,40 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::collections::HashMap;
+//! Append-only commit logs (used for block headers and block json data).
+//!
+//! Every registered commit log is a directory of segment files `<base offset>.seg`, where base offset
+//! is the offset of the first record in the segment. Offset is the sequence number of the record,
+//! so [Location] does not depend on the physical layout (compression, segment size). Segment layout:
+//!
+//! | magic (8) | compression (1) | reserved (7) | records... |
+//!
+//! and record layout:
+//!
+//! | crc32 (4) | stored length (4) | length (4) | payload |
+//!
+//! Checksum covers both lengths and the stored (possibly compressed) payload, it is verified on every read.
+//! Compression is chosen when segment is created, so changed [CommitLogConfiguration::compression] is applied
+//! to the next segment. When the active segment reaches [CommitLogConfiguration::segment_size], it is sealed
+//! (positions of its records are stored to `<base offset>.idx`) and new segment is started.
+//!
+//! On open just the active (last) segment is scanned, torn record at its end (crash during write) is truncated.
+//! Sealed segments with all records older than some offset can be removed (see [CommitLogWithSchema::remove_segments_before]).
+//!
+//! Commit log can be also opened read-only by another process (storage replica), see [CommitLog::open_read_only],
+//! records appended by the primary process are picked up with [CommitLog::refresh].
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::{fmt, io};
 
-use commitlog::message::MessageSet;
-use commitlog::{AppendError, CommitLog, LogOptions, Offset, ReadError, ReadLimit};
+use derive_builder::Builder;
 use failure::Fail;
 use serde::{Deserialize, Serialize};
 
@@ -17,22 +44,53 @@ use crate::persistent::BincodeEncoded;
 
 pub type CommitLogRef = Arc<RwLock<CommitLog>>;
 
+/// Sequence number of the record in a commit log
+pub type Offset = u64;
+
+const SEGMENT_MAGIC: &[u8; 8] = b"TZCLOG01";
+const SEGMENT_HEADER_SIZE: u64 = 16;
+const RECORD_HEADER_SIZE: usize = 4 + 4 + 4;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const INDEX_EXTENSION: &str = "idx";
+const INDEX_MAGIC: &[u8; 8] = b"TZCLOGIX";
+/// Extension of segments written by the previous implementation (`commitlog` crate)
+const LEGACY_SEGMENT_EXTENSION: &str = "log";
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
+/// Limit of the (uncompressed) record, positions of records in a segment fit into `u32`
+const MAX_RECORD_SIZE: usize = 1024 * 1024 * 1024;
+
 /// Possible errors for commit log
 #[derive(Debug, Fail)]
 pub enum CommitLogError {
     #[fail(display = "Schema error: {}", error)]
     SchemaError { error: SchemaError },
-    #[fail(display = "Failed to append record: {}", error)]
-    AppendError { error: AppendError },
-    #[fail(display = "Failed to read record at {}: {}", location, error)]
-    ReadError {
-        error: ReadError,
-        location: Location,
-    },
+    #[fail(display = "Record at {} was not found", location)]
+    MissingRecord { location: Location },
+    #[fail(display = "Record at {} is corrupted: {}", location, reason)]
+    CorruptedRecord { location: Location, reason: String },
+    #[fail(display = "Commit log segment {:?} is corrupted: {}", path, reason)]
+    CorruptedSegment { path: PathBuf, reason: String },
+    #[fail(display = "Record of {} bytes exceeds limit of {} bytes", size, limit)]
+    RecordTooLarge { size: usize, limit: usize },
+    #[fail(
+        display = "Commit log {:?} has legacy format, database migration is required",
+        path
+    )]
+    LegacyFormat { path: PathBuf },
     #[fail(display = "Commit log I/O error {}", error)]
     IOError { error: io::Error },
     #[fail(display = "Commit log {} is missing", name)]
     MissingCommitLog { name: &'static str },
+    #[fail(
+        display = "Operation {} is not supported by read-only commit log",
+        operation
+    )]
+    ReadOnlyOperation { operation: &'static str },
 }
 
 impl From<SchemaError> for CommitLogError {
@@ -84,6 +142,86 @@ impl BincodeEncoded for Location {}
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Range(pub Offset, pub ByteLimit, pub ItemCount);
 
+/// Compression of records in newly created segments
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommitLogCompression {
+    None,
+    Zstd,
+}
+
+impl CommitLogCompression {
+    pub fn possible_values() -> Vec<&'static str> {
+        vec!["none", "zstd"]
+    }
+
+    fn from_flag(flag: u8) -> Option<Self> {
+        match flag {
+            COMPRESSION_NONE => Some(CommitLogCompression::None),
+            COMPRESSION_ZSTD => Some(CommitLogCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn flag(&self) -> u8 {
+        match self {
+            CommitLogCompression::None => COMPRESSION_NONE,
+            CommitLogCompression::Zstd => COMPRESSION_ZSTD,
+        }
+    }
+}
+
+impl fmt::Display for CommitLogCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommitLogCompression::None => write!(f, "none"),
+            CommitLogCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseCommitLogCompressionError(String);
+
+impl fmt::Display for ParseCommitLogCompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid commit log compression: {}", self.0)
+    }
+}
+
+impl FromStr for CommitLogCompression {
+    type Err = ParseCommitLogCompressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(CommitLogCompression::None),
+            "zstd" => Ok(CommitLogCompression::Zstd),
+            _ => Err(ParseCommitLogCompressionError(s.to_string())),
+        }
+    }
+}
+
+/// Commit log configuration
+/// - [segment_size] - size in bytes, when the active segment is sealed and new one is started
+/// - [compression] - compression of records in newly created segments
+#[derive(Builder, Debug, Clone)]
+pub struct CommitLogConfiguration {
+    #[builder(default = "CommitLogConfiguration::DEFAULT_SEGMENT_SIZE")]
+    pub segment_size: u64,
+    #[builder(default = "CommitLogCompression::Zstd")]
+    pub compression: CommitLogCompression,
+}
+
+impl CommitLogConfiguration {
+    pub const DEFAULT_SEGMENT_SIZE: u64 = 256 * 1024 * 1024;
+    pub const MAX_SEGMENT_SIZE: u64 = 1024 * 1024 * 1024;
+}
+
+impl Default for CommitLogConfiguration {
+    fn default() -> Self {
+        CommitLogConfigurationBuilder::default().build().unwrap()
+    }
+}
+
 /// Implement this trait for a commit log engine.
 pub trait CommitLogWithSchema<S: CommitLogSchema> {
     /// Append new record to a commit log.
@@ -94,6 +232,14 @@ pub trait CommitLogWithSchema<S: CommitLogSchema> {
 
     /// Retrieve stored records stored in a single range.
     fn get_range(&self, range: &Range) -> Result<Vec<S::Value>, CommitLogError>;
+
+    /// Removes sealed segments, which contain just records older than `offset`.
+    ///
+    /// Returns count of removed segments.
+    fn remove_segments_before(&self, offset: Offset) -> Result<usize, CommitLogError>;
+
+    /// Syncs appended records to disk
+    fn sync(&self) -> Result<(), CommitLogError>;
 }
 
 impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
@@ -103,9 +249,7 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
         let mut cl = cl.write().expect("Write lock failed");
         let bytes = value.encode()?;
-        let offset = cl
-            .append_msg(&bytes)
-            .map_err(|error| CommitLogError::AppendError { error })?;
+        let offset = cl.append(&bytes)?;
 
         Ok(Location(offset, bytes.len()))
     }
@@ -115,17 +259,8 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
             .cl_handle(S::name())
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
         let cl = cl.read().expect("Read lock failed");
-        let msg_buf = cl
-            .read(location.0, fit_read_limit(location.1))
-            .map_err(|error| CommitLogError::ReadError {
-                error,
-                location: *location,
-            })?;
-        let bytes = msg_buf.iter().next().ok_or(CommitLogError::ReadError {
-            error: ReadError::CorruptLog,
-            location: *location,
-        })?;
-        let value = S::Value::decode(bytes.payload())?;
+        let bytes = cl.read(location.0)?;
+        let value = S::Value::decode(&bytes)?;
 
         Ok(value)
     }
@@ -135,33 +270,32 @@ impl<S: CommitLogSchema> CommitLogWithSchema<S> for CommitLogs {
             .cl_handle(S::name())
             .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
         let cl = cl.read().expect("Read lock failed");
-        let msg_buf = cl
-            .read(range.0, fit_batch_read_limit(range.1, range.2))
-            .map_err(|error| CommitLogError::ReadError {
-                error,
-                location: Location(range.0, range.1),
-            })?;
-        msg_buf
-            .iter()
-            .take(range.2 as usize)
-            .map(|message| {
-                S::Value::decode(message.payload()).map_err(|_| CommitLogError::ReadError {
-                    error: ReadError::CorruptLog,
-                    location: Location(message.offset(), message.size() as usize),
+        (range.0..range.0 + range.2 as Offset)
+            .map(|offset| {
+                let bytes = cl.read(offset)?;
+                S::Value::decode(&bytes).map_err(|error| CommitLogError::CorruptedRecord {
+                    location: Location(offset, bytes.len()),
+                    reason: error.to_string(),
                 })
             })
             .collect()
     }
-}
 
-#[inline]
-fn fit_read_limit(limit: ByteLimit) -> ReadLimit {
-    ReadLimit::max_bytes(limit + 32)
-}
+    fn remove_segments_before(&self, offset: Offset) -> Result<usize, CommitLogError> {
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let mut cl = cl.write().expect("Write lock failed");
+        cl.remove_segments_before(offset)
+    }
 
-#[inline]
-fn fit_batch_read_limit(limit: ByteLimit, items: ItemCount) -> ReadLimit {
-    ReadLimit::max_bytes(limit + (32 * items as usize))
+    fn sync(&self) -> Result<(), CommitLogError> {
+        let cl = self
+            .cl_handle(S::name())
+            .ok_or(CommitLogError::MissingCommitLog { name: S::name() })?;
+        let mut cl = cl.write().expect("Write lock failed");
+        cl.flush()
+    }
 }
 
 pub fn fold_consecutive_locations(locations: &[Location]) -> Vec<Range> {
@@ -188,21 +322,654 @@ pub fn fold_consecutive_locations(locations: &[Location]) -> Vec<Range> {
     }
 }
 
+struct Segment {
+    path: PathBuf,
+    file: File,
+    compression: CommitLogCompression,
+    /// Positions of records in the segment file
+    positions: Vec<u32>,
+    size: u64,
+}
+
+impl Segment {
+    fn create(
+        dir: &Path,
+        base_offset: Offset,
+        compression: CommitLogCompression,
+    ) -> Result<Self, CommitLogError> {
+        let path = segment_path(dir, base_offset);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        let mut header = [0; SEGMENT_HEADER_SIZE as usize];
+        header[..SEGMENT_MAGIC.len()].copy_from_slice(SEGMENT_MAGIC);
+        header[SEGMENT_MAGIC.len()] = compression.flag();
+        file.write_all_at(&header, 0)?;
+        file.sync_data()?;
+
+        Ok(Self {
+            path,
+            file,
+            compression,
+            positions: Vec::new(),
+            size: SEGMENT_HEADER_SIZE,
+        })
+    }
+
+    /// Opens segment and resolves positions of its records.
+    ///
+    /// Sealed segment has to be valid, torn record at the end of the active segment is truncated.
+    fn open(path: PathBuf, active: bool) -> Result<Self, CommitLogError> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let file_size = file.metadata()?.len();
+        let compression = read_header(&file, &path, file_size)?
+            .ok_or_else(|| corrupted_segment(&path, "incomplete header"))?;
+
+        let mut segment = Self {
+            path,
+            file,
+            compression,
+            positions: Vec::new(),
+            size: file_size,
+        };
+
+        if !active {
+            if let Some(positions) = segment.read_index()? {
+                segment.positions = positions;
+                return Ok(segment);
+            }
+        }
+
+        let (positions, valid_size) = segment.scan(SEGMENT_HEADER_SIZE, file_size)?;
+        if valid_size < file_size {
+            if !active {
+                return Err(corrupted_segment(
+                    &segment.path,
+                    &format!("invalid record at position {}", valid_size),
+                ));
+            }
+            segment.file.set_len(valid_size)?;
+            segment.file.sync_data()?;
+        }
+        segment.positions = positions;
+        segment.size = valid_size;
+        if !active {
+            segment.write_index()?;
+        }
+        Ok(segment)
+    }
+
+    /// Opens segment written by another process, segment is never modified.
+    ///
+    /// Invalid records at the end are not considered to be torn, they can be still written by the primary process,
+    /// so they are just skipped - see [Segment::tail]. Returns `None`, if segment header is not written yet.
+    fn open_read_only(path: PathBuf) -> Result<Option<Self>, CommitLogError> {
+        let file = File::open(&path)?;
+        let file_size = file.metadata()?.len();
+        let compression = match read_header(&file, &path, file_size)? {
+            Some(compression) => compression,
+            None => return Ok(None),
+        };
+
+        let mut segment = Self {
+            path,
+            file,
+            compression,
+            positions: Vec::new(),
+            size: file_size,
+        };
+        match segment.read_index()? {
+            Some(positions) => segment.positions = positions,
+            None => {
+                segment.size = SEGMENT_HEADER_SIZE;
+                segment.tail()?;
+            }
+        }
+        Ok(Some(segment))
+    }
+
+    /// Resolves positions of records appended to the segment by another process since the last call,
+    /// returns count of new records.
+    fn tail(&mut self) -> Result<usize, CommitLogError> {
+        let file_size = self.file.metadata()?.len();
+        if file_size <= self.size {
+            return Ok(0);
+        }
+        let (positions, valid_size) = self.scan(self.size, file_size)?;
+        let count = positions.len();
+        self.positions.extend(positions);
+        self.size = valid_size;
+        Ok(count)
+    }
+
+    /// Reads records from position `from` up to `to` and returns their positions and the end of the last valid record
+    fn scan(&self, from: u64, to: u64) -> Result<(Vec<u32>, u64), CommitLogError> {
+        let mut reader = BufReader::with_capacity(1024 * 1024, &self.file);
+        reader.seek(SeekFrom::Start(from))?;
+
+        let mut positions = Vec::new();
+        let mut position = from;
+        let mut header = [0; RECORD_HEADER_SIZE];
+        let mut payload = Vec::new();
+        loop {
+            let remaining = to - position;
+            if remaining < RECORD_HEADER_SIZE as u64 {
+                break;
+            }
+            reader.read_exact(&mut header)?;
+            let stored_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as u64;
+            if remaining < RECORD_HEADER_SIZE as u64 + stored_len {
+                break;
+            }
+            payload.resize(stored_len as usize, 0);
+            reader.read_exact(&mut payload)?;
+            if checksum(&header[4..], &payload)
+                != u32::from_be_bytes(header[..4].try_into().unwrap())
+            {
+                break;
+            }
+            positions.push(position as u32);
+            position += RECORD_HEADER_SIZE as u64 + stored_len;
+        }
+        Ok((positions, position))
+    }
+
+    /// Reads stored positions of records, returns `None`, if index is missing or does not match the segment
+    fn read_index(&self) -> Result<Option<Vec<u32>>, CommitLogError> {
+        let data = match fs::read(self.path.with_extension(INDEX_EXTENSION)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let header_size = INDEX_MAGIC.len() + 8 + 4;
+        if data.len() < header_size + 4 || &data[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let (content, stored_checksum) = data.split_at(data.len() - 4);
+        if checksum(content, &[]) != u32::from_be_bytes(stored_checksum.try_into().unwrap()) {
+            return Ok(None);
+        }
+
+        let segment_size = u64::from_be_bytes(content[8..16].try_into().unwrap());
+        let count = u32::from_be_bytes(content[16..20].try_into().unwrap()) as usize;
+        let positions = &content[header_size..];
+        if segment_size != self.size || positions.len() != count * 4 {
+            return Ok(None);
+        }
+        Ok(Some(
+            positions
+                .chunks_exact(4)
+                .map(|position| u32::from_be_bytes(position.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    /// Stores positions of records, so sealed segment does not have to be scanned on open
+    fn write_index(&self) -> Result<(), CommitLogError> {
+        let mut data = Vec::with_capacity(INDEX_MAGIC.len() + 16 + self.positions.len() * 4);
+        data.extend_from_slice(INDEX_MAGIC);
+        data.extend_from_slice(&self.size.to_be_bytes());
+        data.extend_from_slice(&(self.positions.len() as u32).to_be_bytes());
+        for position in &self.positions {
+            data.extend_from_slice(&position.to_be_bytes());
+        }
+        let crc = checksum(&data, &[]);
+        data.extend_from_slice(&crc.to_be_bytes());
+
+        let path = self.path.with_extension(INDEX_EXTENSION);
+        let tmp_path = self.path.with_extension("idx.tmp");
+        fs::write(&tmp_path, &data)?;
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, payload: &[u8]) -> Result<(), CommitLogError> {
+        let stored = match self.compression {
+            CommitLogCompression::None => None,
+            CommitLogCompression::Zstd => Some(zstd::block::compress(payload, ZSTD_LEVEL)?),
+        };
+        let stored = stored.as_deref().unwrap_or(payload);
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + stored.len());
+        record.extend_from_slice(&[0; 4]);
+        record.extend_from_slice(&(stored.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(stored);
+        let crc = checksum(&record[4..RECORD_HEADER_SIZE], stored);
+        record[..4].copy_from_slice(&crc.to_be_bytes());
+
+        if let Err(e) = self.file.write_all_at(&record, self.size) {
+            // do not leave partially written record behind
+            let _ = self.file.set_len(self.size);
+            return Err(e.into());
+        }
+        self.positions.push(self.size as u32);
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    fn read(&self, index: usize, location: Location) -> Result<Vec<u8>, CommitLogError> {
+        let position = self.positions[index] as u64;
+        let mut header = [0; RECORD_HEADER_SIZE];
+        self.file.read_exact_at(&mut header, position)?;
+        let stored_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let len = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        if position + (RECORD_HEADER_SIZE + stored_len) as u64 > self.size || len > MAX_RECORD_SIZE
+        {
+            return Err(CommitLogError::CorruptedRecord {
+                location,
+                reason: "invalid record length".to_string(),
+            });
+        }
+
+        let mut stored = vec![0; stored_len];
+        self.file
+            .read_exact_at(&mut stored, position + RECORD_HEADER_SIZE as u64)?;
+        if checksum(&header[4..], &stored) != u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            return Err(CommitLogError::CorruptedRecord {
+                location,
+                reason: "checksum mismatch".to_string(),
+            });
+        }
+
+        match self.compression {
+            CommitLogCompression::None => Ok(stored),
+            CommitLogCompression::Zstd => {
+                zstd::block::decompress(&stored, len).map_err(|e| CommitLogError::CorruptedRecord {
+                    location,
+                    reason: format!("decompression failed: {}", e),
+                })
+            }
+        }
+    }
+
+    fn remove(self) -> Result<(), CommitLogError> {
+        let index_path = self.path.with_extension(INDEX_EXTENSION);
+        fs::remove_file(&self.path)?;
+        remove_if_exists(&index_path)
+    }
+}
+
+/// Single commit log - a directory of segments.
+pub struct CommitLog {
+    dir: PathBuf,
+    config: CommitLogConfiguration,
+    /// Segments by the offset of their first record, the last one is the active one
+    segments: BTreeMap<Offset, Segment>,
+    /// Commit log is written by another process, see [CommitLog::open_read_only]
+    read_only: bool,
+}
+
+impl CommitLog {
+    /// Opens (or creates) commit log in directory `dir`
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        config: CommitLogConfiguration,
+    ) -> Result<Self, CommitLogError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let (base_offsets, index_files) = list_segments(&dir)?;
+
+        // index without segment is left behind, when removal of segment was interrupted
+        for index_path in index_files {
+            if !index_path.with_extension(SEGMENT_EXTENSION).exists() {
+                remove_if_exists(&index_path)?;
+            }
+        }
+
+        let mut segments = BTreeMap::new();
+        let mut next_offset = None;
+        for (i, base_offset) in base_offsets.iter().enumerate() {
+            let path = segment_path(&dir, *base_offset);
+            if let Some(next_offset) = next_offset {
+                if next_offset != *base_offset {
+                    return Err(corrupted_segment(
+                        &path,
+                        &format!("expected base offset {}", next_offset),
+                    ));
+                }
+            }
+            let active = i == base_offsets.len() - 1;
+            let segment = if active && fs::metadata(&path)?.len() < SEGMENT_HEADER_SIZE {
+                // crash right after the segment was created, there are no records
+                Segment::create(&dir, *base_offset, config.compression)?
+            } else {
+                Segment::open(path, active)?
+            };
+            next_offset = Some(base_offset + segment.positions.len() as Offset);
+            segments.insert(*base_offset, segment);
+        }
+        if segments.is_empty() {
+            segments.insert(0, Segment::create(&dir, 0, config.compression)?);
+        }
+
+        Ok(Self {
+            dir,
+            config,
+            segments,
+            read_only: false,
+        })
+    }
+
+    /// Opens commit log in directory `dir`, which is written by another (primary) process.
+    ///
+    /// Nothing is modified, torn records are not truncated and index files are not written.
+    /// Records appended by the primary process are visible after [CommitLog::refresh].
+    pub fn open_read_only<P: AsRef<Path>>(
+        dir: P,
+        config: CommitLogConfiguration,
+    ) -> Result<Self, CommitLogError> {
+        let mut log = Self {
+            dir: dir.as_ref().to_path_buf(),
+            config,
+            segments: BTreeMap::new(),
+            read_only: true,
+        };
+        log.refresh()?;
+        Ok(log)
+    }
+
+    /// Picks up records and segments appended by the primary process and forgets segments removed by it,
+    /// returns count of new records. Supported just for commit log opened by [CommitLog::open_read_only].
+    pub fn refresh(&mut self) -> Result<usize, CommitLogError> {
+        if !self.read_only {
+            return Err(CommitLogError::ReadOnlyOperation {
+                operation: "refresh",
+            });
+        }
+
+        let (base_offsets, _) = list_segments(&self.dir)?;
+        // segments removed by the primary process
+        let first_offset = base_offsets.first().copied().unwrap_or(Offset::MAX);
+        let removed: Vec<Offset> = self
+            .segments
+            .range(..first_offset)
+            .map(|(o, _)| *o)
+            .collect();
+        for base_offset in removed {
+            self.segments.remove(&base_offset);
+        }
+
+        let mut count = 0;
+        if let Some(last) = self.segments.values_mut().next_back() {
+            count += last.tail()?;
+        }
+        for base_offset in base_offsets {
+            if self.segments.contains_key(&base_offset)
+                || (!self.segments.is_empty() && base_offset < self.next_offset())
+            {
+                continue;
+            }
+            let path = segment_path(&self.dir, base_offset);
+            if !self.segments.is_empty() && base_offset != self.next_offset() {
+                return Err(corrupted_segment(
+                    &path,
+                    &format!("expected base offset {}", self.next_offset()),
+                ));
+            }
+            let segment = match Segment::open_read_only(path)? {
+                Some(segment) => segment,
+                // segment is being created right now, it is picked up by the next refresh
+                None => break,
+            };
+            count += segment.positions.len();
+            self.segments.insert(base_offset, segment);
+        }
+        Ok(count)
+    }
+
+    /// Offset of the next appended record
+    pub fn next_offset(&self) -> Offset {
+        match self.segments.iter().next_back() {
+            Some((base_offset, active)) => base_offset + active.positions.len() as Offset,
+            None => 0,
+        }
+    }
+
+    /// Offset of the oldest stored record
+    pub fn first_offset(&self) -> Offset {
+        self.segments.keys().next().copied().unwrap_or(0)
+    }
+
+    /// Count of segments (including the active one)
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Appends record and returns its offset
+    pub fn append(&mut self, payload: &[u8]) -> Result<Offset, CommitLogError> {
+        if self.read_only {
+            return Err(CommitLogError::ReadOnlyOperation {
+                operation: "append",
+            });
+        }
+        if payload.len() > MAX_RECORD_SIZE {
+            return Err(CommitLogError::RecordTooLarge {
+                size: payload.len(),
+                limit: MAX_RECORD_SIZE,
+            });
+        }
+
+        let segment_size = self
+            .config
+            .segment_size
+            .min(CommitLogConfiguration::MAX_SEGMENT_SIZE);
+        let (_, active) = self.active();
+        if !active.positions.is_empty()
+            && active.size + (RECORD_HEADER_SIZE + payload.len()) as u64 > segment_size
+        {
+            self.start_new_segment()?;
+        }
+
+        let offset = self.next_offset();
+        self.active_mut().append(payload)?;
+        Ok(offset)
+    }
+
+    /// Reads record stored at `offset`
+    pub fn read(&self, offset: Offset) -> Result<Vec<u8>, CommitLogError> {
+        let location = Location(offset, 0);
+        let (base_offset, segment) = self
+            .segments
+            .range(..=offset)
+            .next_back()
+            .ok_or(CommitLogError::MissingRecord { location })?;
+        let index = (offset - base_offset) as usize;
+        if index >= segment.positions.len() {
+            return Err(CommitLogError::MissingRecord { location });
+        }
+        segment.read(index, location)
+    }
+
+    /// Removes sealed segments, which contain just records older than `offset`, returns count of removed segments.
+    pub fn remove_segments_before(&mut self, offset: Offset) -> Result<usize, CommitLogError> {
+        if self.read_only {
+            return Err(CommitLogError::ReadOnlyOperation {
+                operation: "remove_segments_before",
+            });
+        }
+        let active_base_offset = *self.active().0;
+        let removable: Vec<Offset> = self
+            .segments
+            .iter()
+            .filter(|(base_offset, segment)| {
+                **base_offset < active_base_offset
+                    && **base_offset + segment.positions.len() as Offset <= offset
+            })
+            .map(|(base_offset, _)| *base_offset)
+            .collect();
+
+        // oldest first, so there is never a gap after interrupted removal
+        for base_offset in &removable {
+            if let Some(segment) = self.segments.remove(base_offset) {
+                segment.remove()?;
+            }
+        }
+        Ok(removable.len())
+    }
+
+    pub fn flush(&mut self) -> Result<(), CommitLogError> {
+        if !self.read_only {
+            self.active().1.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn start_new_segment(&mut self) -> Result<(), CommitLogError> {
+        let next_offset = self.next_offset();
+        {
+            let (_, active) = self.active();
+            active.file.sync_data()?;
+            active.write_index()?;
+        }
+        let segment = Segment::create(&self.dir, next_offset, self.config.compression)?;
+        self.segments.insert(next_offset, segment);
+        Ok(())
+    }
+
+    fn active(&self) -> (&Offset, &Segment) {
+        self.segments
+            .iter()
+            .next_back()
+            .expect("Commit log has always at least one segment")
+    }
+
+    fn active_mut(&mut self) -> &mut Segment {
+        self.segments
+            .values_mut()
+            .next_back()
+            .expect("Commit log has always at least one segment")
+    }
+}
+
+/// Checksum of the record header (without the checksum itself) and stored payload
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+fn segment_path(dir: &Path, base_offset: Offset) -> PathBuf {
+    dir.join(format!("{:020}.{}", base_offset, SEGMENT_EXTENSION))
+}
+
+fn parse_base_offset(path: &Path) -> Option<Offset> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), CommitLogError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn corrupted_segment(path: &Path, reason: &str) -> CommitLogError {
+    CommitLogError::CorruptedSegment {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Verifies segment header and returns compression of its records, returns `None`, if header is incomplete
+fn read_header(
+    file: &File,
+    path: &Path,
+    file_size: u64,
+) -> Result<Option<CommitLogCompression>, CommitLogError> {
+    if file_size < SEGMENT_HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut header = [0; SEGMENT_HEADER_SIZE as usize];
+    file.read_exact_at(&mut header, 0)?;
+    if &header[..SEGMENT_MAGIC.len()] != SEGMENT_MAGIC {
+        return Err(corrupted_segment(path, "invalid magic"));
+    }
+    CommitLogCompression::from_flag(header[SEGMENT_MAGIC.len()])
+        .map(Some)
+        .ok_or_else(|| corrupted_segment(path, "unknown compression"))
+}
+
+/// Lists base offsets of segments in commit log directory (sorted) and paths of index files
+fn list_segments(dir: &Path) -> Result<(Vec<Offset>, Vec<PathBuf>), CommitLogError> {
+    let mut base_offsets = Vec::new();
+    let mut index_files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(SEGMENT_EXTENSION) => {
+                if let Some(base_offset) = parse_base_offset(&path) {
+                    base_offsets.push(base_offset);
+                }
+            }
+            Some(INDEX_EXTENSION) => index_files.push(path),
+            Some(LEGACY_SEGMENT_EXTENSION) => {
+                return Err(CommitLogError::LegacyFormat {
+                    path: dir.to_path_buf(),
+                })
+            }
+            _ => (),
+        }
+    }
+    base_offsets.sort_unstable();
+    Ok((base_offsets, index_files))
+}
+
 /// Provides access to all registered commit logs via a log family reference.
 pub struct CommitLogs {
     base_path: PathBuf,
+    config: CommitLogConfiguration,
     commit_log_map: RwLock<HashMap<String, CommitLogRef>>,
+    read_only: bool,
 }
 
 impl CommitLogs {
-    pub(crate) fn new<P, I>(path: P, cfs: I) -> Result<Self, CommitLogError>
+    pub(crate) fn new<P, I>(
+        path: P,
+        cfs: I,
+        config: &CommitLogConfiguration,
+    ) -> Result<Self, CommitLogError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = CommitLogDescriptor>,
+    {
+        Self::with_mode(path, cfs, config, false)
+    }
+
+    /// Opens commit logs written by another (primary) process, see [CommitLog::open_read_only]
+    pub(crate) fn new_read_only<P, I>(
+        path: P,
+        cfs: I,
+        config: &CommitLogConfiguration,
+    ) -> Result<Self, CommitLogError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = CommitLogDescriptor>,
+    {
+        Self::with_mode(path, cfs, config, true)
+    }
+
+    fn with_mode<P, I>(
+        path: P,
+        cfs: I,
+        config: &CommitLogConfiguration,
+        read_only: bool,
+    ) -> Result<Self, CommitLogError>
     where
         P: AsRef<Path>,
         I: IntoIterator<Item = CommitLogDescriptor>,
     {
         let myself = Self {
             base_path: path.as_ref().into(),
+            config: config.clone(),
             commit_log_map: RwLock::new(HashMap::new()),
+            read_only,
         };
 
         for descriptor in cfs.into_iter() {
@@ -215,14 +982,11 @@ impl CommitLogs {
     /// Register a new commit log.
     fn register(&self, name: &str) -> Result<(), CommitLogError> {
         let path = self.base_path.join(name);
-        if !Path::new(&path).exists() {
-            std::fs::create_dir_all(&path)?;
-        }
-
-        let mut opts = LogOptions::new(&path);
-        // TODO: TE-396 - rework
-        opts.message_max_bytes(30_000_000);
-        let log = CommitLog::new(opts)?;
+        let log = if self.read_only {
+            CommitLog::open_read_only(path, self.config.clone())?
+        } else {
+            CommitLog::open(path, self.config.clone())?
+        };
 
         let mut commit_log_map = self.commit_log_map.write().unwrap();
         commit_log_map.insert(name.into(), Arc::new(RwLock::new(log)));
@@ -247,6 +1011,17 @@ impl CommitLogs {
 
         Ok(())
     }
+
+    /// Picks up records appended to all registered commit logs by the primary process (just for read-only commit logs).
+    pub fn refresh(&self) -> Result<(), CommitLogError> {
+        let commit_log_map = self.commit_log_map.read().unwrap();
+        for commit_log in commit_log_map.values() {
+            let mut commit_log = commit_log.write().unwrap();
+            commit_log.refresh()?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for CommitLogs {
@@ -268,6 +1043,8 @@ impl Location {
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+
     use crate::persistent::commit_log::fold_consecutive_locations;
 
     use super::*;
@@ -308,4 +1085,189 @@ mod tests {
             ranges
         );
     }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined - check build.rs");
+        let path = Path::new(out_dir.as_str()).join(name);
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        path
+    }
+
+    fn config(segment_size: u64, compression: CommitLogCompression) -> CommitLogConfiguration {
+        CommitLogConfiguration {
+            segment_size,
+            compression,
+        }
+    }
+
+    /// Pseudo-random (not much compressible) record of ~200 bytes
+    fn record(i: u64) -> Vec<u8> {
+        let mut state = i + 1;
+        (0..200 + i % 7)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_append_read_with_segment_rotation() -> Result<(), CommitLogError> {
+        let dir = test_dir("__commit_log_rotation");
+        {
+            let mut log = CommitLog::open(&dir, config(4096, CommitLogCompression::Zstd))?;
+            for i in 0..100 {
+                assert_eq!(i, log.append(&record(i))?);
+            }
+            assert!(log.segment_count() > 1);
+            for i in 0..100 {
+                assert_eq!(record(i), log.read(i)?);
+            }
+            assert!(matches!(
+                log.read(100),
+                Err(CommitLogError::MissingRecord { .. })
+            ));
+            log.flush()?;
+        }
+
+        // reopen with changed compression, old segments are still readable
+        let mut log = CommitLog::open(&dir, config(4096, CommitLogCompression::None))?;
+        assert_eq!(100, log.next_offset());
+        for i in 100..200 {
+            assert_eq!(i, log.append(&record(i))?);
+        }
+        for i in 0..200 {
+            assert_eq!(record(i), log.read(i)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() -> Result<(), CommitLogError> {
+        let dir = test_dir("__commit_log_torn_tail");
+        let active_path = {
+            let mut log = CommitLog::open(&dir, CommitLogConfiguration::default())?;
+            for i in 0..10 {
+                log.append(&record(i))?;
+            }
+            log.flush()?;
+            log.active().1.path.clone()
+        };
+
+        // simulate crash during write of the record
+        let size = fs::metadata(&active_path)?.len();
+        OpenOptions::new()
+            .write(true)
+            .open(&active_path)?
+            .set_len(size - 5)?;
+
+        let mut log = CommitLog::open(&dir, CommitLogConfiguration::default())?;
+        assert_eq!(9, log.next_offset());
+        assert_eq!(record(8), log.read(8)?);
+        assert_eq!(9, log.append(&record(9))?);
+        assert_eq!(record(9), log.read(9)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_record_is_detected() -> Result<(), CommitLogError> {
+        let dir = test_dir("__commit_log_corrupted_record");
+        let mut log = CommitLog::open(&dir, config(4096, CommitLogCompression::None))?;
+        for i in 0..3 {
+            log.append(&record(i))?;
+        }
+
+        // flip one byte of the payload of the second record
+        let (_, segment) = log.active();
+        let position = segment.positions[1] as u64 + RECORD_HEADER_SIZE as u64;
+        let mut byte = [0];
+        segment.file.read_exact_at(&mut byte, position)?;
+        segment.file.write_all_at(&[byte[0] ^ 0xff], position)?;
+
+        assert_eq!(record(0), log.read(0)?);
+        assert!(matches!(
+            log.read(1),
+            Err(CommitLogError::CorruptedRecord { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_segments_before() -> Result<(), CommitLogError> {
+        let dir = test_dir("__commit_log_retention");
+        let mut log = CommitLog::open(&dir, config(4096, CommitLogCompression::None))?;
+        for i in 0..100 {
+            log.append(&record(i))?;
+        }
+        let segment_count = log.segment_count();
+        let second_segment = *log.segments.keys().nth(1).unwrap();
+
+        // first segment contains record of offset `second_segment - 1`, so it is still needed
+        assert_eq!(0, log.remove_segments_before(second_segment - 1)?);
+        assert_eq!(1, log.remove_segments_before(second_segment)?);
+        assert_eq!(second_segment, log.first_offset());
+        assert!(matches!(
+            log.read(0),
+            Err(CommitLogError::MissingRecord { .. })
+        ));
+        assert_eq!(record(99), log.read(99)?);
+
+        // active segment is never removed
+        assert_eq!(segment_count - 2, log.remove_segments_before(1000)?);
+        assert_eq!(1, log.segment_count());
+        drop(log);
+
+        let mut log = CommitLog::open(&dir, config(4096, CommitLogCompression::None))?;
+        assert_eq!(100, log.next_offset());
+        assert_eq!(record(99), log.read(99)?);
+        assert_eq!(100, log.append(&record(100))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only_follows_primary() -> Result<(), CommitLogError> {
+        let dir = test_dir("__commit_log_read_only");
+        let mut primary = CommitLog::open(&dir, config(4096, CommitLogCompression::Zstd))?;
+        for i in 0..10 {
+            primary.append(&record(i))?;
+        }
+
+        let mut replica = CommitLog::open_read_only(&dir, CommitLogConfiguration::default())?;
+        assert_eq!(10, replica.next_offset());
+        assert_eq!(record(9), replica.read(9)?);
+        assert!(matches!(
+            replica.append(&record(10)),
+            Err(CommitLogError::ReadOnlyOperation { .. })
+        ));
+
+        // torn record written by primary is not visible, nor truncated
+        let (_, active) = primary.active();
+        let size = active.size;
+        active.file.write_all_at(&[1, 2, 3, 4, 5, 6, 7], size)?;
+        assert_eq!(0, replica.refresh()?);
+        assert_eq!(size + 7, fs::metadata(&active.path)?.len());
+        active.file.set_len(size)?;
+
+        // appended records and new segments are picked up
+        for i in 10..100 {
+            primary.append(&record(i))?;
+        }
+        assert!(primary.segment_count() > 1);
+        assert_eq!(90, replica.refresh()?);
+        assert_eq!(primary.segment_count(), replica.segment_count());
+        for i in 0..100 {
+            assert_eq!(record(i), replica.read(i)?);
+        }
+
+        // removed segments are forgotten
+        let second_segment = *primary.segments.keys().nth(1).unwrap();
+        primary.remove_segments_before(second_segment)?;
+        assert_eq!(0, replica.refresh()?);
+        assert_eq!(second_segment, replica.first_offset());
+        Ok(())
+    }
 }
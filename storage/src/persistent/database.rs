@@ -8,6 +8,7 @@ use std::io;
 use std::marker::PhantomData;
 use std::path::Path;
 use std::sync::PoisonError;
+use std::time::Instant;
 
 use failure::Fail;
 use rocksdb::{
@@ -19,6 +20,7 @@ use serde::Serialize;
 use crypto::hash::FromBytesError;
 
 use crate::persistent::codec::{Decoder, Encoder, SchemaError};
+use crate::persistent::metrics::{store_metrics, ColumnFamilyStats, DatabaseStats, StoreKind};
 use crate::persistent::{DbConfiguration, KeyValueSchema, KeyValueStoreBackend};
 
 /// Open RocksDB database at given path with specified Column Family configurations
@@ -34,6 +36,42 @@ where
     DB::open_cf_descriptors(&default_kv_options(cfg), path, cfs).map_err(DBError::from)
 }
 
+/// Open RocksDB database at given path as a read-only secondary instance of the primary database,
+/// secondary instance sees just data written before the last [catch_up_with_primary] call.
+///
+/// Column families of secondary instance are opened with default options (rocksdb crate does not allow more),
+/// so they cannot resolve merge operands - see [crate::persistent::merge_secondary::SecondaryMergeDB].
+///
+/// # Arguments
+/// * `primary_path` - Path to RocksDB opened by primary instance
+/// * `secondary_path` - Path, where secondary instance keeps its own info log and state
+/// * `cf_names` - Names of column families to open
+pub fn open_kv_as_secondary<P, I, N>(
+    primary_path: P,
+    secondary_path: P,
+    cf_names: I,
+    cfg: &DbConfiguration,
+) -> Result<DB, DBError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = N>,
+    N: AsRef<str>,
+{
+    let mut db_opts = default_kv_options(cfg);
+    db_opts.create_missing_column_families(false);
+    db_opts.create_if_missing(false);
+    // secondary instance has to keep all files opened, otherwise primary could remove them
+    db_opts.set_max_open_files(-1);
+
+    DB::open_cf_as_secondary(&db_opts, primary_path, secondary_path, cf_names)
+        .map_err(DBError::from)
+}
+
+/// Tails MANIFEST and WAL of the primary database, so secondary instance sees the latest writes
+pub fn catch_up_with_primary(db: &DB) -> Result<(), DBError> {
+    db.try_catch_up_with_primary().map_err(DBError::from)
+}
+
 /// Create default database configuration options,
 /// based on recommended setting: https://github.com/facebook/rocksdb/wiki/Setup-Options-and-Basic-Tuning#other-general-options
 fn default_kv_options(cfg: &DbConfiguration) -> Options {
@@ -98,6 +136,47 @@ pub struct RocksDBStats {
     pub cache_total: u64,
 }
 
+impl RocksDBStats {
+    /// Collects memory usage statistics of the database
+    pub fn collect(db: &DB) -> Result<Self, DBError> {
+        let memory_usage_stats = rocksdb::perf::get_memory_usage_stats(Some(&[db]), None)?;
+        Ok(Self {
+            mem_table_total: memory_usage_stats.mem_table_total,
+            mem_table_unflushed: memory_usage_stats.mem_table_unflushed,
+            mem_table_readers_total: memory_usage_stats.mem_table_readers_total,
+            cache_total: memory_usage_stats.cache_total,
+        })
+    }
+}
+
+/// Collects memory usage and per column family size statistics (as estimated by RocksDB) of the database
+pub fn database_stats(name: &'static str, db: &DB) -> Result<DatabaseStats, DBError> {
+    let property = |cf, property_name| -> Result<u64, DBError> {
+        Ok(db.property_int_value_cf(cf, property_name)?.unwrap_or(0))
+    };
+
+    let mut column_families = vec![];
+    for cf_name in DB::list_cf(&Options::default(), db.path())? {
+        let cf = match db.cf_handle(&cf_name) {
+            Some(cf) => cf,
+            None => continue,
+        };
+        column_families.push(ColumnFamilyStats {
+            estimated_keys: property(cf, "rocksdb.estimate-num-keys")?,
+            estimated_live_data_size: property(cf, "rocksdb.estimate-live-data-size")?,
+            sst_files_size: property(cf, "rocksdb.total-sst-files-size")?,
+            mem_tables_size: property(cf, "rocksdb.size-all-mem-tables")?,
+            name: cf_name,
+        });
+    }
+
+    Ok(DatabaseStats {
+        name,
+        memory: RocksDBStats::collect(db)?,
+        column_families,
+    })
+}
+
 /// Possible errors for schema
 #[derive(Debug, Fail)]
 pub enum DBError {
@@ -105,6 +184,8 @@ pub enum DBError {
     SchemaError { error: SchemaError },
     #[fail(display = "RocksDB error: {}", error)]
     RocksDBError { error: Error },
+    #[fail(display = "RocksDB error: {}", reason)]
+    RocksDBApiError { reason: String },
     #[fail(display = "Column family {} is missing", name)]
     MissingColumnFamily { name: &'static str },
     #[fail(display = "Database incompatibility {}", name)]
@@ -123,6 +204,13 @@ pub enum DBError {
     IOError { error: io::Error },
     #[fail(display = "MemoryStatisticsOverflow")]
     MemoryStatisticsOverflow,
+    #[fail(
+        display = "Operation {} is not supported by read-only store",
+        operation
+    )]
+    ReadOnlyOperation { operation: &'static str },
+    #[fail(display = "Compression error: {}", reason)]
+    CompressionError { reason: String },
 }
 
 impl From<SchemaError> for DBError {
@@ -243,8 +331,15 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
             .cf_handle(S::name())
             .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
 
+        let started = Instant::now();
         self.put_cf_opt(cf, &key, &value, &default_write_options())
-            .map_err(DBError::from)
+            .map_err(DBError::from)?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_write(
+            1,
+            key.len() + value.len(),
+            started,
+        );
+        Ok(())
     }
 
     fn delete(&self, key: &S::Key) -> Result<(), DBError> {
@@ -253,8 +348,11 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
             .cf_handle(S::name())
             .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
 
+        let started = Instant::now();
         self.delete_cf_opt(cf, &key, &default_write_options())
-            .map_err(DBError::from)
+            .map_err(DBError::from)?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_delete(started);
+        Ok(())
     }
 
     fn merge(&self, key: &S::Key, value: &S::Value) -> Result<(), DBError> {
@@ -264,8 +362,15 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
             .cf_handle(S::name())
             .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
 
+        let started = Instant::now();
         self.merge_cf_opt(cf, &key, &value, &default_write_options())
-            .map_err(DBError::from)
+            .map_err(DBError::from)?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_write(
+            1,
+            key.len() + value.len(),
+            started,
+        );
+        Ok(())
     }
 
     fn get(&self, key: &S::Key) -> Result<Option<S::Value>, DBError> {
@@ -274,8 +379,14 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
             .cf_handle(S::name())
             .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
 
-        self.get_cf(cf, &key)
-            .map_err(DBError::from)?
+        let started = Instant::now();
+        let value = self.get_cf(cf, &key).map_err(DBError::from)?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_read(
+            value.as_ref().map(|value| value.len()).unwrap_or(0),
+            started,
+        );
+
+        value
             .map(|value| S::Value::decode(&value))
             .transpose()
             .map_err(DBError::from)
@@ -287,12 +398,16 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
             .cf_handle(S::name())
             .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
 
+        let started = Instant::now();
         let val = self.get_pinned_cf(cf, &key)?;
+        store_metrics(StoreKind::ColumnFamily, S::name())
+            .record_read(val.as_ref().map(|value| value.len()).unwrap_or(0), started);
         Ok(val.is_some())
     }
 
     fn write_batch(&self, batch: Vec<(S::Key, S::Value)>) -> Result<(), DBError> {
         let mut rocksb_batch = WriteBatch::default(); // batch containing DB key values to persist
+        let mut written_bytes = 0;
 
         for (k, v) in batch.iter() {
             let key = k.encode()?;
@@ -301,9 +416,16 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
                 .cf_handle(S::name())
                 .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
             rocksb_batch.put_cf(cf, &key, &value);
+            written_bytes += key.len() + value.len();
         }
 
+        let started = Instant::now();
         self.write_opt(rocksb_batch, &default_write_options())?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_write(
+            batch.len(),
+            written_bytes,
+            started,
+        );
         Ok(())
     }
 
@@ -326,25 +448,26 @@ impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for DB {
         Ok(usage)
     }
 
-    fn retain(&self, predicate: &dyn Fn(&S::Key) -> bool) -> Result<(), DBError> {
-        let garbage: Vec<_> = (self as &dyn KeyValueStoreWithSchemaIterator<S>)
-            .iterator(IteratorMode::Start)?
-            .filter_map(|(k, _)| match k {
-                Err(_) => None,
-                Ok(value) => {
-                    if !predicate(&value) {
-                        Some(value)
-                    } else {
-                        None
-                    }
-                }
-            })
-            .collect();
-
-        for i in garbage {
-            (self as &dyn KeyValueStoreBackend<S>).delete(&i)?;
+    fn retain(&self, predicate: &dyn Fn(&S::Key) -> bool) -> Result<u64, DBError> {
+        let cf = self
+            .cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+
+        // iterator reads implicit snapshot, so keys can be deleted while iterating
+        let mut removed_bytes = 0;
+        for (key, value) in self.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let decoded = match S::Key::decode(&key) {
+                Ok(decoded) => decoded,
+                Err(_) => continue,
+            };
+            if !predicate(&decoded) {
+                let started = Instant::now();
+                self.delete_cf_opt(cf, &key, &default_write_options())?;
+                store_metrics(StoreKind::ColumnFamily, S::name()).record_delete(started);
+                removed_bytes += (key.len() + value.len()) as u64;
+            }
         }
-        Ok(())
+        Ok(removed_bytes)
     }
 }
 
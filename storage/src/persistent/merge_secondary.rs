@@ -0,0 +1,284 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Read-only secondary instance of RocksDB column families with merge operators.
+//!
+//! rocksdb crate opens column families of secondary instances just with default options (see [crate::persistent::database::open_kv_as_secondary]),
+//! so merge operands written by the primary instance cannot be resolved there.
+//! This module opens these column families directly through RocksDB C API with their merge operators registered.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::time::Instant;
+
+use libc::{c_char, c_int, c_void};
+use librocksdb_sys as ffi;
+use rocksdb::merge_operator::{self, MergeFn, MergeOperatorCallback};
+
+use crate::persistent::database::{
+    IteratorMode, IteratorWithSchema, KeyValueStoreWithSchemaIterator, RocksDbKeyValueSchema,
+};
+use crate::persistent::metrics::{store_metrics, StoreKind};
+use crate::persistent::{DBError, Decoder, Encoder, KeyValueStoreBackend, KeyValueStoreWithSchema};
+
+/// RocksDB requires default column family to be opened always
+const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// Merge operator registered for the column family
+pub struct MergeOperatorDescriptor {
+    pub cf_name: &'static str,
+    pub name: &'static str,
+    pub merge_fn: MergeFn,
+}
+
+/// Secondary instance of the primary database, which opens just column families with merge operators.
+///
+/// It supports just reads of the single values (`get`/`contains`), everything else fails with [DBError::ReadOnlyOperation].
+pub struct SecondaryMergeDB {
+    db: *mut ffi::rocksdb_t,
+    cfs: HashMap<&'static str, *mut ffi::rocksdb_column_family_handle_t>,
+    read_options: *mut ffi::rocksdb_readoptions_t,
+    /// Options (with merge operators) have to outlive the database
+    options: Vec<*mut ffi::rocksdb_options_t>,
+}
+
+// RocksDB database and column family handles can be shared between threads
+unsafe impl Send for SecondaryMergeDB {}
+unsafe impl Sync for SecondaryMergeDB {}
+
+impl SecondaryMergeDB {
+    /// Opens column families of `merge_operators` of the database at `primary_path`
+    ///
+    /// # Arguments
+    /// * `primary_path` - Path to RocksDB opened by primary instance
+    /// * `secondary_path` - Path, where secondary instance keeps its own info log and state (cannot be shared with other secondary instance)
+    /// * `merge_operators` - Column families to open with their merge operators
+    pub fn open<P: AsRef<Path>>(
+        primary_path: P,
+        secondary_path: P,
+        merge_operators: Vec<MergeOperatorDescriptor>,
+    ) -> Result<Self, DBError> {
+        let primary_path = to_cstring(primary_path.as_ref().as_os_str().as_bytes())?;
+        let secondary_path = to_cstring(secondary_path.as_ref().as_os_str().as_bytes())?;
+
+        // everything created is owned by `db`, so it is released on failure too
+        let mut db = SecondaryMergeDB {
+            db: ptr::null_mut(),
+            cfs: HashMap::new(),
+            read_options: unsafe { ffi::rocksdb_readoptions_create() },
+            options: Vec::with_capacity(merge_operators.len() + 2),
+        };
+
+        let db_options = db.create_options();
+        // secondary instance has to keep all files opened, otherwise primary could remove them
+        unsafe { ffi::rocksdb_options_set_max_open_files(db_options, -1) };
+
+        let mut cf_names = vec![to_cstring(DEFAULT_COLUMN_FAMILY_NAME)?];
+        let mut cf_options = vec![db.create_options() as *const ffi::rocksdb_options_t];
+        for descriptor in merge_operators.iter() {
+            let cf_opts = db.create_options();
+            let callback = Box::new(MergeOperatorCallback {
+                name: to_cstring(descriptor.name)?,
+                full_merge_fn: descriptor.merge_fn,
+                partial_merge_fn: descriptor.merge_fn,
+            });
+            unsafe {
+                // merge operator (and its callback) is owned by options
+                let operator = ffi::rocksdb_mergeoperator_create(
+                    Box::into_raw(callback) as *mut c_void,
+                    Some(merge_operator::destructor_callback),
+                    Some(merge_operator::full_merge_callback),
+                    Some(merge_operator::partial_merge_callback),
+                    Some(merge_operator::delete_callback),
+                    Some(merge_operator::name_callback),
+                );
+                ffi::rocksdb_options_set_merge_operator(cf_opts, operator);
+            }
+            cf_names.push(to_cstring(descriptor.cf_name)?);
+            cf_options.push(cf_opts);
+        }
+
+        let cf_name_ptrs: Vec<*const c_char> = cf_names.iter().map(|name| name.as_ptr()).collect();
+        let mut cf_handles: Vec<*mut ffi::rocksdb_column_family_handle_t> =
+            vec![ptr::null_mut(); cf_names.len()];
+        let mut error = ptr::null_mut();
+        db.db = unsafe {
+            ffi::rocksdb_open_as_secondary_column_families(
+                db_options,
+                primary_path.as_ptr(),
+                secondary_path.as_ptr(),
+                cf_names.len() as c_int,
+                cf_name_ptrs.as_ptr(),
+                cf_options.as_ptr(),
+                cf_handles.as_mut_ptr(),
+                &mut error,
+            )
+        };
+        check_error(error)?;
+        if db.db.is_null() {
+            return Err(DBError::RocksDBApiError {
+                reason: "Could not open secondary instance".to_string(),
+            });
+        }
+
+        // default column family handle is not needed, but it has to be released too
+        db.cfs.insert(DEFAULT_COLUMN_FAMILY_NAME, cf_handles[0]);
+        for (descriptor, cf_handle) in merge_operators.iter().zip(cf_handles.into_iter().skip(1)) {
+            db.cfs.insert(descriptor.cf_name, cf_handle);
+        }
+
+        Ok(db)
+    }
+
+    /// Tails MANIFEST and WAL of the primary database, so secondary instance sees the latest writes
+    pub fn catch_up_with_primary(&self) -> Result<(), DBError> {
+        let mut error = ptr::null_mut();
+        unsafe { ffi::rocksdb_try_catch_up_with_primary(self.db, &mut error) };
+        check_error(error)
+    }
+
+    fn create_options(&mut self) -> *mut ffi::rocksdb_options_t {
+        let options = unsafe { ffi::rocksdb_options_create() };
+        self.options.push(options);
+        options
+    }
+
+    fn get_cf(&self, cf_name: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        let cf = *self
+            .cfs
+            .get(cf_name)
+            .ok_or(DBError::MissingColumnFamily { name: cf_name })?;
+
+        let mut value_len = 0;
+        let mut error = ptr::null_mut();
+        unsafe {
+            let value = ffi::rocksdb_get_cf(
+                self.db,
+                self.read_options,
+                cf,
+                key.as_ptr() as *const c_char,
+                key.len(),
+                &mut value_len,
+                &mut error,
+            );
+            check_error(error)?;
+            if value.is_null() {
+                Ok(None)
+            } else {
+                let result = slice::from_raw_parts(value as *const u8, value_len).to_vec();
+                ffi::rocksdb_free(value as *mut c_void);
+                Ok(Some(result))
+            }
+        }
+    }
+}
+
+impl Drop for SecondaryMergeDB {
+    fn drop(&mut self) {
+        unsafe {
+            for cf_handle in self.cfs.values() {
+                ffi::rocksdb_column_family_handle_destroy(*cf_handle);
+            }
+            if !self.db.is_null() {
+                ffi::rocksdb_close(self.db);
+            }
+            ffi::rocksdb_readoptions_destroy(self.read_options);
+            for options in self.options.iter() {
+                ffi::rocksdb_options_destroy(*options);
+            }
+        }
+    }
+}
+
+impl<S: RocksDbKeyValueSchema> KeyValueStoreBackend<S> for SecondaryMergeDB {
+    fn put(&self, _key: &S::Key, _value: &S::Value) -> Result<(), DBError> {
+        Err(DBError::ReadOnlyOperation { operation: "put" })
+    }
+
+    fn delete(&self, _key: &S::Key) -> Result<(), DBError> {
+        Err(DBError::ReadOnlyOperation {
+            operation: "delete",
+        })
+    }
+
+    fn merge(&self, _key: &S::Key, _value: &S::Value) -> Result<(), DBError> {
+        Err(DBError::ReadOnlyOperation { operation: "merge" })
+    }
+
+    fn get(&self, key: &S::Key) -> Result<Option<S::Value>, DBError> {
+        let key = key.encode()?;
+
+        let started = Instant::now();
+        let value = self.get_cf(S::name(), &key)?;
+        store_metrics(StoreKind::ColumnFamily, S::name()).record_read(
+            value.as_ref().map(|value| value.len()).unwrap_or(0),
+            started,
+        );
+
+        value
+            .map(|value| S::Value::decode(&value))
+            .transpose()
+            .map_err(DBError::from)
+    }
+
+    fn contains(&self, key: &S::Key) -> Result<bool, DBError> {
+        let key = key.encode()?;
+        Ok(self.get_cf(S::name(), &key)?.is_some())
+    }
+
+    fn retain(&self, _predicate: &dyn Fn(&S::Key) -> bool) -> Result<u64, DBError> {
+        Err(DBError::ReadOnlyOperation {
+            operation: "retain",
+        })
+    }
+
+    fn write_batch(&self, _batch: Vec<(S::Key, S::Value)>) -> Result<(), DBError> {
+        Err(DBError::ReadOnlyOperation {
+            operation: "write_batch",
+        })
+    }
+
+    fn total_get_mem_usage(&self) -> Result<usize, DBError> {
+        Ok(0)
+    }
+}
+
+impl<S: RocksDbKeyValueSchema> KeyValueStoreWithSchemaIterator<S> for SecondaryMergeDB {
+    fn iterator(&self, _mode: IteratorMode<S>) -> Result<IteratorWithSchema<S>, DBError> {
+        Err(DBError::ReadOnlyOperation {
+            operation: "iterator",
+        })
+    }
+
+    fn prefix_iterator(&self, _key: &S::Key) -> Result<IteratorWithSchema<S>, DBError> {
+        Err(DBError::ReadOnlyOperation {
+            operation: "prefix_iterator",
+        })
+    }
+}
+
+impl<S: RocksDbKeyValueSchema> KeyValueStoreWithSchema<S> for SecondaryMergeDB {}
+
+fn to_cstring<T: Into<Vec<u8>>>(value: T) -> Result<CString, DBError> {
+    CString::new(value).map_err(|e| DBError::IOError {
+        error: io::Error::new(io::ErrorKind::InvalidInput, e),
+    })
+}
+
+/// Converts error message allocated by RocksDB (if any) to [DBError::RocksDBApiError]
+fn check_error(error: *mut c_char) -> Result<(), DBError> {
+    if error.is_null() {
+        return Ok(());
+    }
+    let reason = unsafe {
+        let reason = CStr::from_ptr(error).to_string_lossy().into_owned();
+        ffi::rocksdb_free(error as *mut c_void);
+        reason
+    };
+    Err(DBError::RocksDBApiError { reason })
+}
@@ -0,0 +1,495 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Storage metrics - counts of reads, writes and deletes, read/written bytes and latency histograms
+//! per RocksDB column family (schema) and per context store backend.
+//!
+//! Metrics are collected to the process-wide registry, so every store with the same name
+//! (e.g. `system_storage` column family, which is present in more databases) shares the same metrics.
+//! Registered metrics live for the whole process and every thread caches the ones it already resolved,
+//! so recording of reads/writes does not lock the registry.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::persistent::database::RocksDBStats;
+
+/// Upper bounds of latency histogram buckets in microseconds
+const LATENCY_BUCKETS_US: [u64; 14] = [
+    5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000, 1_000_000,
+];
+
+type StoreMetricsRegistry = HashMap<(StoreKind, &'static str), &'static StoreMetrics>;
+
+lazy_static! {
+    static ref STORE_METRICS: RwLock<StoreMetricsRegistry> = RwLock::new(HashMap::new());
+    /// Used, if registry is poisoned - metrics are just not collected
+    static ref UNREGISTERED_STORE_METRICS: StoreMetrics = StoreMetrics::default();
+}
+
+thread_local! {
+    /// Metrics already resolved by the thread
+    static RESOLVED_STORE_METRICS: RefCell<StoreMetricsRegistry> = RefCell::new(HashMap::new());
+}
+
+/// Kind of the instrumented store
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    /// RocksDB column family, named by [crate::persistent::database::RocksDbKeyValueSchema::name]
+    ColumnFamily,
+    /// Context key-value store backend
+    ContextStore,
+}
+
+impl fmt::Display for StoreKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreKind::ColumnFamily => write!(f, "column_family"),
+            StoreKind::ContextStore => write!(f, "context_store"),
+        }
+    }
+}
+
+/// Returns metrics of the store, metrics are registered on the first access and cached by the calling thread
+pub fn store_metrics(kind: StoreKind, name: &'static str) -> &'static StoreMetrics {
+    RESOLVED_STORE_METRICS.with(|resolved| {
+        *resolved
+            .borrow_mut()
+            .entry((kind, name))
+            .or_insert_with(|| register_store_metrics(kind, name))
+    })
+}
+
+fn register_store_metrics(kind: StoreKind, name: &'static str) -> &'static StoreMetrics {
+    match STORE_METRICS.write() {
+        // registered metrics are never removed, so they can live for the whole process
+        Ok(mut registry) => {
+            let metrics: &'static StoreMetrics = registry
+                .entry((kind, name))
+                .or_insert_with(|| Box::leak(Box::new(StoreMetrics::default())));
+            metrics
+        }
+        Err(_) => &UNREGISTERED_STORE_METRICS,
+    }
+}
+
+/// Snapshot of metrics of all registered stores, sorted by kind and name
+pub fn store_metrics_snapshot() -> Vec<StoreMetricsSnapshot> {
+    let mut snapshot: Vec<StoreMetricsSnapshot> = match STORE_METRICS.read() {
+        Ok(registry) => registry
+            .iter()
+            .map(|((kind, name), metrics)| metrics.snapshot(*kind, name))
+            .collect(),
+        Err(_) => vec![],
+    };
+    snapshot.sort_by(|a, b| (a.kind, a.name).cmp(&(b.kind, b.name)));
+    snapshot
+}
+
+/// Counters and latency histograms of one store
+#[derive(Default)]
+pub struct StoreMetrics {
+    reads: AtomicU64,
+    read_bytes: AtomicU64,
+    writes: AtomicU64,
+    written_bytes: AtomicU64,
+    deletes: AtomicU64,
+    read_latency: LatencyHistogram,
+    write_latency: LatencyHistogram,
+}
+
+impl StoreMetrics {
+    /// Records one read (get/contains) started at `started`, `bytes` is size of the read value (if any)
+    #[inline]
+    pub fn record_read(&self, bytes: usize, started: Instant) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.read_latency.record(started);
+    }
+
+    /// Records `count` writes (put/merge, or writes in one batch) of `bytes` (keys and values) started at `started`
+    #[inline]
+    pub fn record_write(&self, count: usize, bytes: usize, started: Instant) {
+        self.writes.fetch_add(count as u64, Ordering::Relaxed);
+        self.written_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.write_latency.record(started);
+    }
+
+    /// Records one delete started at `started`, deletes are measured with writes latency
+    #[inline]
+    pub fn record_delete(&self, started: Instant) {
+        self.deletes.fetch_add(1, Ordering::Relaxed);
+        self.write_latency.record(started);
+    }
+
+    fn snapshot(&self, kind: StoreKind, name: &'static str) -> StoreMetricsSnapshot {
+        StoreMetricsSnapshot {
+            kind,
+            name,
+            reads: self.reads.load(Ordering::Relaxed),
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            written_bytes: self.written_bytes.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            read_latency: self.read_latency.snapshot(),
+            write_latency: self.write_latency.snapshot(),
+        }
+    }
+}
+
+/// Histogram of operation latencies with fixed buckets [LATENCY_BUCKETS_US] (and overflow bucket)
+#[derive(Default)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    #[inline]
+    fn record(&self, started: Instant) {
+        let elapsed_us = started.elapsed().as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|upper_bound| elapsed_us <= *upper_bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(elapsed_us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencyHistogramSnapshot {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        LatencyHistogramSnapshot {
+            count: counts.iter().sum(),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+            buckets: LATENCY_BUCKETS_US
+                .iter()
+                .map(|upper_bound| Some(*upper_bound))
+                .chain(std::iter::once(None))
+                .zip(counts)
+                .map(|(le_us, count)| LatencyBucket { le_us, count })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StoreMetricsSnapshot {
+    pub kind: StoreKind,
+    pub name: &'static str,
+    pub reads: u64,
+    pub read_bytes: u64,
+    pub writes: u64,
+    pub written_bytes: u64,
+    pub deletes: u64,
+    pub read_latency: LatencyHistogramSnapshot,
+    pub write_latency: LatencyHistogramSnapshot,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencyHistogramSnapshot {
+    pub count: u64,
+    pub sum_us: u64,
+    /// Non-cumulative counts of operations per bucket
+    pub buckets: Vec<LatencyBucket>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencyBucket {
+    /// Upper bound of the bucket in microseconds, `None` for the overflow bucket
+    pub le_us: Option<u64>,
+    pub count: u64,
+}
+
+/// Size statistics of one RocksDB column family (estimated by RocksDB)
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ColumnFamilyStats {
+    pub name: String,
+    pub estimated_keys: u64,
+    pub estimated_live_data_size: u64,
+    pub sst_files_size: u64,
+    pub mem_tables_size: u64,
+}
+
+/// Size statistics of one RocksDB database
+#[derive(Serialize, Debug, Clone)]
+pub struct DatabaseStats {
+    pub name: &'static str,
+    pub memory: RocksDBStats,
+    pub column_families: Vec<ColumnFamilyStats>,
+}
+
+/// Prometheus metric name, help and value of the store
+type StoreMetric = (&'static str, &'static str, fn(&StoreMetricsSnapshot) -> u64);
+
+/// Prometheus metric name, help and histogram of the store
+type StoreHistogramMetric = (
+    &'static str,
+    &'static str,
+    fn(&StoreMetricsSnapshot) -> &LatencyHistogramSnapshot,
+);
+
+/// Prometheus metric name, help and value of the column family
+type ColumnFamilyMetric = (&'static str, &'static str, fn(&ColumnFamilyStats) -> u64);
+
+/// Renders metrics in Prometheus text exposition format
+pub fn to_prometheus_text(databases: &[DatabaseStats], stores: &[StoreMetricsSnapshot]) -> String {
+    let mut out = String::new();
+
+    let counters: [StoreMetric; 5] = [
+        ("reads_total", "Count of reads", |s| s.reads),
+        ("read_bytes_total", "Count of read bytes", |s| s.read_bytes),
+        ("writes_total", "Count of written values", |s| s.writes),
+        (
+            "written_bytes_total",
+            "Count of written bytes (keys and values)",
+            |s| s.written_bytes,
+        ),
+        ("deletes_total", "Count of deletes", |s| s.deletes),
+    ];
+    for (name, help, value) in counters.iter() {
+        let _ = writeln!(out, "# HELP tezedge_storage_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE tezedge_storage_{} counter", name);
+        for store in stores {
+            let _ = writeln!(
+                out,
+                "tezedge_storage_{}{{kind=\"{}\",store=\"{}\"}} {}",
+                name,
+                store.kind,
+                store.name,
+                value(store)
+            );
+        }
+    }
+
+    let histograms: [StoreHistogramMetric; 2] = [
+        ("read_latency_seconds", "Latency of reads", |s| {
+            &s.read_latency
+        }),
+        (
+            "write_latency_seconds",
+            "Latency of writes and deletes",
+            |s| &s.write_latency,
+        ),
+    ];
+    for (name, help, histogram) in histograms.iter() {
+        let _ = writeln!(out, "# HELP tezedge_storage_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE tezedge_storage_{} histogram", name);
+        for store in stores {
+            let histogram = histogram(store);
+            let labels = format!("kind=\"{}\",store=\"{}\"", store.kind, store.name);
+            let mut cumulative = 0;
+            for bucket in &histogram.buckets {
+                cumulative += bucket.count;
+                let le = match bucket.le_us {
+                    Some(le_us) => format!("{}", le_us as f64 / 1_000_000.0),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "tezedge_storage_{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "tezedge_storage_{}_sum{{{}}} {}",
+                name,
+                labels,
+                histogram.sum_us as f64 / 1_000_000.0
+            );
+            let _ = writeln!(
+                out,
+                "tezedge_storage_{}_count{{{}}} {}",
+                name, labels, histogram.count
+            );
+        }
+    }
+
+    let gauges: [ColumnFamilyMetric; 4] = [
+        (
+            "cf_estimated_keys",
+            "Estimated count of keys in column family",
+            |cf| cf.estimated_keys,
+        ),
+        (
+            "cf_estimated_live_data_bytes",
+            "Estimated size of live data in column family",
+            |cf| cf.estimated_live_data_size,
+        ),
+        (
+            "cf_sst_files_bytes",
+            "Size of all SST files of column family",
+            |cf| cf.sst_files_size,
+        ),
+        (
+            "cf_mem_tables_bytes",
+            "Size of all mem tables of column family",
+            |cf| cf.mem_tables_size,
+        ),
+    ];
+    let _ = writeln!(
+        out,
+        "# HELP tezedge_storage_db_memory_bytes Memory used by database"
+    );
+    let _ = writeln!(out, "# TYPE tezedge_storage_db_memory_bytes gauge");
+    for database in databases {
+        let memory = &database.memory;
+        for (usage, value) in [
+            ("mem_table_total", memory.mem_table_total),
+            ("mem_table_unflushed", memory.mem_table_unflushed),
+            ("mem_table_readers_total", memory.mem_table_readers_total),
+            ("cache_total", memory.cache_total),
+        ]
+        .iter()
+        {
+            let _ = writeln!(
+                out,
+                "tezedge_storage_db_memory_bytes{{db=\"{}\",usage=\"{}\"}} {}",
+                database.name, usage, value
+            );
+        }
+    }
+
+    for (name, help, value) in gauges.iter() {
+        let _ = writeln!(out, "# HELP tezedge_storage_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE tezedge_storage_{} gauge", name);
+        for database in databases {
+            for cf in &database.column_families {
+                let _ = writeln!(
+                    out,
+                    "tezedge_storage_{}{{db=\"{}\",cf=\"{}\"}} {}",
+                    name,
+                    database.name,
+                    cf.name,
+                    value(cf)
+                );
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_store_metrics_registry() {
+        let metrics = store_metrics(StoreKind::ContextStore, "__test_store_metrics_registry");
+        metrics.record_read(10, Instant::now());
+        metrics.record_read(0, Instant::now());
+        metrics.record_write(3, 100, Instant::now());
+        metrics.record_delete(Instant::now());
+
+        // the same store shares metrics (also with other threads)
+        std::thread::spawn(|| {
+            store_metrics(StoreKind::ContextStore, "__test_store_metrics_registry").record_write(
+                1,
+                20,
+                Instant::now(),
+            )
+        })
+        .join()
+        .expect("Failed to record metrics in other thread");
+        assert!(std::ptr::eq(
+            metrics,
+            store_metrics(StoreKind::ContextStore, "__test_store_metrics_registry")
+        ));
+
+        let snapshot = store_metrics_snapshot()
+            .into_iter()
+            .find(|s| s.name == "__test_store_metrics_registry")
+            .expect("Store metrics should be registered");
+        assert_eq!(StoreKind::ContextStore, snapshot.kind);
+        assert_eq!(2, snapshot.reads);
+        assert_eq!(10, snapshot.read_bytes);
+        assert_eq!(4, snapshot.writes);
+        assert_eq!(120, snapshot.written_bytes);
+        assert_eq!(1, snapshot.deletes);
+        assert_eq!(2, snapshot.read_latency.count);
+        assert_eq!(3, snapshot.write_latency.count);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Instant::now());
+        histogram.record(Instant::now() - Duration::from_millis(3));
+        histogram.record(Instant::now() - Duration::from_secs(2));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(3, snapshot.count);
+        assert_eq!(LATENCY_BUCKETS_US.len() + 1, snapshot.buckets.len());
+        assert!(snapshot.sum_us >= 2_003_000);
+        assert_eq!(1, snapshot.buckets[0].count);
+        // 2.5ms < 3ms <= 5ms
+        let bucket_5ms = LATENCY_BUCKETS_US.iter().position(|b| *b == 5_000).unwrap();
+        assert_eq!(1, snapshot.buckets[bucket_5ms].count);
+        // overflow
+        assert_eq!(None, snapshot.buckets[LATENCY_BUCKETS_US.len()].le_us);
+        assert_eq!(1, snapshot.buckets[LATENCY_BUCKETS_US.len()].count);
+    }
+
+    #[test]
+    fn test_to_prometheus_text() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Instant::now());
+        histogram.record(Instant::now());
+        let stores = vec![StoreMetricsSnapshot {
+            kind: StoreKind::ColumnFamily,
+            name: "block_meta_storage",
+            reads: 5,
+            read_bytes: 500,
+            writes: 2,
+            written_bytes: 300,
+            deletes: 1,
+            read_latency: histogram.snapshot(),
+            write_latency: LatencyHistogram::default().snapshot(),
+        }];
+        let databases = vec![DatabaseStats {
+            name: "db",
+            memory: RocksDBStats {
+                mem_table_total: 1024,
+                mem_table_unflushed: 0,
+                mem_table_readers_total: 0,
+                cache_total: 0,
+            },
+            column_families: vec![ColumnFamilyStats {
+                name: "block_meta_storage".to_string(),
+                estimated_keys: 7,
+                ..Default::default()
+            }],
+        }];
+
+        let text = to_prometheus_text(&databases, &stores);
+        assert!(text.contains("# TYPE tezedge_storage_reads_total counter\n"));
+        assert!(text.contains(
+            "tezedge_storage_reads_total{kind=\"column_family\",store=\"block_meta_storage\"} 5\n"
+        ));
+        assert!(text.contains("tezedge_storage_read_latency_seconds_bucket{kind=\"column_family\",store=\"block_meta_storage\",le=\"0.000005\"} 2\n"));
+        assert!(text.contains("tezedge_storage_read_latency_seconds_bucket{kind=\"column_family\",store=\"block_meta_storage\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("tezedge_storage_read_latency_seconds_count{kind=\"column_family\",store=\"block_meta_storage\"} 2\n"));
+        assert!(text.contains(
+            "tezedge_storage_cf_estimated_keys{db=\"db\",cf=\"block_meta_storage\"} 7\n"
+        ));
+        assert!(text.contains(
+            "tezedge_storage_db_memory_bytes{db=\"db\",usage=\"mem_table_total\"} 1024\n"
+        ));
+    }
+}
@@ -1,20 +1,29 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::fmt;
 use std::path::Path;
 
 use derive_builder::Builder;
 
+use crate::context::kv_store::compressed::CompressionStats;
+
 pub use codec::{BincodeEncoded, Codec, Decoder, Encoder, SchemaError};
-pub use commit_log::{CommitLogError, CommitLogRef, CommitLogWithSchema, CommitLogs, Location};
+pub use commit_log::{
+    CommitLogCompression, CommitLogConfiguration, CommitLogError, CommitLogRef,
+    CommitLogWithSchema, CommitLogs, Location,
+};
 pub use database::{DBError, KeyValueStoreWithSchema, KeyValueStoreWithSchemaIterator};
 pub use schema::{CommitLogDescriptor, CommitLogSchema};
 
 pub mod codec;
 pub mod commit_log;
 pub mod database;
+pub mod merge_secondary;
+pub mod metrics;
 pub mod schema;
 pub mod sequence;
+pub mod write_batch;
 
 /// Rocksdb database system configuration
 /// - [max_num_of_threads] - if not set, num of cpus is used
@@ -31,12 +40,29 @@ impl Default for DbConfiguration {
 }
 
 /// Open commit log at a given path.
-pub fn open_cl<P, I>(path: P, cfs: I) -> Result<CommitLogs, CommitLogError>
+pub fn open_cl<P, I>(
+    path: P,
+    cfs: I,
+    config: &CommitLogConfiguration,
+) -> Result<CommitLogs, CommitLogError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = CommitLogDescriptor>,
+{
+    CommitLogs::new(path, cfs, config)
+}
+
+/// Open commit log at a given path, which is written by another process (see [CommitLogs::refresh]).
+pub fn open_cl_read_only<P, I>(
+    path: P,
+    cfs: I,
+    config: &CommitLogConfiguration,
+) -> Result<CommitLogs, CommitLogError>
 where
     P: AsRef<Path>,
     I: IntoIterator<Item = CommitLogDescriptor>,
 {
-    CommitLogs::new(path, cfs)
+    CommitLogs::new_read_only(path, cfs, config)
 }
 
 /// This trait extends basic column family by introducing Codec types safety and enforcement
@@ -80,6 +106,15 @@ impl MultiInstanceableSyncError {
     }
 }
 
+impl fmt::Display for MultiInstanceableSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Count of keys checked by [KeyValueStoreBackend::retain] before the rejected ones are removed
+pub const COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK: usize = 2048;
+
 /// Custom trait to unify any kv-store schema access
 pub trait KeyValueStoreBackend<S: KeyValueSchema> {
     /// Insert new key value pair into the database.
@@ -126,11 +161,30 @@ pub trait KeyValueStoreBackend<S: KeyValueSchema> {
     /// * `key` - Key (specified by schema), to be checked for existence
     fn contains(&self, key: &S::Key) -> Result<bool, DBError>;
 
-    /// Removes every element that predicate(elem) evaluates to false
+    /// Delete all the keys at once, returns size of the removed keys and values in bytes.
+    ///
+    /// # Arguments
+    /// * `keys` - Values of keys specified by schema
+    fn delete_batch(&self, keys: &[S::Key]) -> Result<u64, DBError> {
+        let mut removed_bytes = 0;
+        for key in keys {
+            if let Some(value) = self.try_delete(key)? {
+                removed_bytes += (key.encode()?.len() + value.encode()?.len()) as u64;
+            }
+        }
+        Ok(removed_bytes)
+    }
+
+    /// Removes every element that predicate(elem) evaluates to false, returns size of the removed keys and values in bytes.
+    ///
+    /// Keys are processed in chunks of [COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK]: rejected keys of the chunk are removed
+    /// before the predicate is evaluated for the next chunk. Predicate is evaluated without holding locks of the store
+    /// (so it can access the store) and the store is locked just while the chunk is removed,
+    /// so writers are not blocked for the whole retain. Keys written while retain is running might not be checked at all.
     ///
     /// # Arguments
     /// * `predicate` - functor used for assessment
-    fn retain(&self, predicate: &dyn Fn(&S::Key) -> bool) -> Result<(), DBError>;
+    fn retain(&self, predicate: &dyn Fn(&S::Key) -> bool) -> Result<u64, DBError>;
 
     /// Write batch into DB atomically
     ///
@@ -141,4 +195,9 @@ pub trait KeyValueStoreBackend<S: KeyValueSchema> {
     /// Return memory usage statistics
     ///
     fn total_get_mem_usage(&self) -> Result<usize, DBError>;
+
+    /// Savings of the values compression, `None` if store does not compress values
+    fn compression_stats(&self) -> Option<CompressionStats> {
+        None
+    }
 }
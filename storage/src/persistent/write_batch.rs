@@ -0,0 +1,110 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Unit of work over the operational database - writes to more column families are collected
+//! and committed atomically at once.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rocksdb::{ColumnFamily, WriteBatch, WriteOptions, DB};
+
+use crate::persistent::codec::Encoder;
+use crate::persistent::database::{DBError, RocksDbKeyValueSchema};
+use crate::persistent::metrics::{store_metrics, StoreKind};
+
+/// Writes to column families of one database collected to be committed atomically.
+///
+/// Nothing is visible to readers until [StorageWriteBatch::commit], dropped batch is discarded.
+/// Batch does not read anything and does not lock anything, merged values ([StorageWriteBatch::merge])
+/// are resolved by merge operator of the column family.
+pub struct StorageWriteBatch {
+    db: Arc<DB>,
+    batch: WriteBatch,
+    /// Count of writes and written bytes per column family, recorded to metrics after commit
+    written: HashMap<&'static str, (usize, usize)>,
+}
+
+impl StorageWriteBatch {
+    pub fn new(db: Arc<DB>) -> Self {
+        Self {
+            db,
+            batch: WriteBatch::default(),
+            written: HashMap::new(),
+        }
+    }
+
+    /// Adds put of the key value pair to the batch
+    pub fn put<S: RocksDbKeyValueSchema>(
+        &mut self,
+        key: &S::Key,
+        value: &S::Value,
+    ) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let value = value.encode()?;
+        let cf = cf_handle::<S>(&self.db)?;
+        self.batch.put_cf(cf, &key, &value);
+        self.record_written::<S>(key.len() + value.len());
+        Ok(())
+    }
+
+    /// Adds merge of the `value` to the batch, column family has to have merge operator.
+    ///
+    /// More merges of the same key in one batch are merged together, as they would be merged one by one.
+    pub fn merge<S: RocksDbKeyValueSchema>(
+        &mut self,
+        key: &S::Key,
+        value: &S::Value,
+    ) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let value = value.encode()?;
+        let cf = cf_handle::<S>(&self.db)?;
+        self.batch.merge_cf(cf, &key, &value);
+        self.record_written::<S>(key.len() + value.len());
+        Ok(())
+    }
+
+    /// Adds delete of the key to the batch
+    pub fn delete<S: RocksDbKeyValueSchema>(&mut self, key: &S::Key) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let cf = cf_handle::<S>(&self.db)?;
+        self.batch.delete_cf(cf, &key);
+        Ok(())
+    }
+
+    /// Count of writes (puts, merges and deletes) in the batch
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// Atomically writes the whole batch to the database
+    pub fn commit(self) -> Result<(), DBError> {
+        let Self { db, batch, written } = self;
+
+        let started = Instant::now();
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(false);
+        db.write_opt(batch, &write_options)?;
+
+        for (name, (count, bytes)) in written {
+            store_metrics(StoreKind::ColumnFamily, name).record_write(count, bytes, started);
+        }
+        Ok(())
+    }
+
+    fn record_written<S: RocksDbKeyValueSchema>(&mut self, bytes: usize) {
+        let written = self.written.entry(S::name()).or_insert((0, 0));
+        written.0 += 1;
+        written.1 += bytes;
+    }
+}
+
+fn cf_handle<S: RocksDbKeyValueSchema>(db: &DB) -> Result<&ColumnFamily, DBError> {
+    db.cf_handle(S::name())
+        .ok_or(DBError::MissingColumnFamily { name: S::name() })
+}
@@ -30,6 +30,14 @@ impl PredecessorKey {
             exponent_slot,
         }
     }
+
+    pub fn block_hash(&self) -> &BlockHash {
+        &self.block_hash
+    }
+
+    pub fn exponent_slot(&self) -> u32 {
+        self.exponent_slot
+    }
 }
 
 #[derive(Clone)]
@@ -50,16 +58,32 @@ impl PredecessorStorage {
         block_meta: &Meta,
         stored_predecessors_size: u32,
     ) -> Result<(), StorageError> {
+        for (key, predecessor) in
+            self.predecessors_to_store(block_hash, block_meta, stored_predecessors_size)?
+        {
+            self.put(&key, &predecessor)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves predecessors of the block for all exponent slots (from the stored predecessors of its direct predecessor)
+    pub fn predecessors_to_store(
+        &self,
+        block_hash: &BlockHash,
+        block_meta: &Meta,
+        stored_predecessors_size: u32,
+    ) -> Result<Vec<(PredecessorKey, BlockHash)>, StorageError> {
+        let mut predecessors = vec![];
         if let Some(direct_predecessor) = block_meta.predecessor() {
             // genesis
             if direct_predecessor == block_hash {
-                return Ok(());
+                return Ok(predecessors);
             } else {
                 // put the direct predecessor to slot 0
-                self.put(
-                    &PredecessorKey::new(block_hash.clone(), 0),
-                    direct_predecessor,
-                )?;
+                predecessors.push((
+                    PredecessorKey::new(block_hash.clone(), 0),
+                    direct_predecessor.clone(),
+                ));
 
                 // fill other slots
                 let mut predecessor = direct_predecessor.clone();
@@ -69,16 +93,16 @@ impl PredecessorStorage {
                     if let Some(p) = self.get(&predecessor_key)? {
                         let key =
                             PredecessorKey::new(block_hash.clone(), predecessor_exponent_slot);
-                        self.put(&key, &p)?;
+                        predecessors.push((key, p.clone()));
                         predecessor = p;
                     } else {
-                        return Ok(());
+                        return Ok(predecessors);
                     }
                 }
             }
         }
 
-        Ok(())
+        Ok(predecessors)
     }
 
     #[inline]
@@ -97,6 +121,11 @@ impl PredecessorStorage {
         self.kv.get(key).map_err(StorageError::from)
     }
 
+    #[inline]
+    pub fn delete(&self, key: &PredecessorKey) -> Result<(), StorageError> {
+        self.kv.delete(key).map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn iter(&self, mode: IteratorMode<Self>) -> Result<IteratorWithSchema<Self>, StorageError> {
         self.kv.iterator(mode).map_err(StorageError::from)
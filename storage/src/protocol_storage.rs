@@ -0,0 +1,75 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Sources of the protocols fetched from the peers (`Protocol` p2p message), stored by protocol hash.
+//!
+//! Stored protocols are served to the other peers, which ask for them by `GetProtocols` p2p message.
+
+use std::sync::Arc;
+
+use crypto::hash::ProtocolHash;
+use tezos_messages::p2p::encoding::protocol::Protocol;
+
+use crate::persistent::database::{IteratorMode, RocksDbKeyValueSchema};
+use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
+use crate::{PersistentStorage, StorageError};
+
+pub type ProtocolStorageKV = dyn KeyValueStoreWithSchema<ProtocolStorage> + Sync + Send;
+
+/// Protocol sources storage
+#[derive(Clone)]
+pub struct ProtocolStorage {
+    kv: Arc<ProtocolStorageKV>,
+}
+
+impl ProtocolStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self {
+            kv: persistent_storage.db(),
+        }
+    }
+
+    #[inline]
+    pub fn put(
+        &self,
+        protocol_hash: &ProtocolHash,
+        protocol: &Protocol,
+    ) -> Result<(), StorageError> {
+        self.kv
+            .put(protocol_hash, protocol)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get(&self, protocol_hash: &ProtocolHash) -> Result<Option<Protocol>, StorageError> {
+        self.kv.get(protocol_hash).map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn contains(&self, protocol_hash: &ProtocolHash) -> Result<bool, StorageError> {
+        self.kv.contains(protocol_hash).map_err(StorageError::from)
+    }
+
+    /// Returns hashes of all stored protocols
+    pub fn protocol_hashes(&self) -> Result<Vec<ProtocolHash>, StorageError> {
+        let mut protocol_hashes = Vec::new();
+        for (key, _) in self.kv.iterator(IteratorMode::Start)? {
+            protocol_hashes.push(key?);
+        }
+        Ok(protocol_hashes)
+    }
+}
+
+impl KeyValueSchema for ProtocolStorage {
+    type Key = ProtocolHash;
+    type Value = Protocol;
+}
+
+impl RocksDbKeyValueSchema for ProtocolStorage {
+    #[inline]
+    fn name() -> &'static str {
+        "protocol_storage"
+    }
+}
+
+impl BincodeEncoded for Protocol {}
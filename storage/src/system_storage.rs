@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crypto::hash::ChainId;
 
+use crate::history_mode::HistoryMode;
 use crate::persistent::database::{default_table_options, RocksDbKeyValueSchema};
 use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
 use crate::StorageError;
@@ -28,6 +29,10 @@ impl SystemStorage {
     const CHAIN_ID: &'static str = "chain_id";
     const DB_VERSION: &'static str = "db_version";
     const CHAIN_NAME: &'static str = "chain_name";
+    const HISTORY_MODE: &'static str = "history_mode";
+    const MIGRATION_IN_PROGRESS: &'static str = "migration_in_progress";
+    const MIGRATION_CHECKPOINT: &'static str = "migration_checkpoint";
+    const META_MERGE_OPERANDS_COMPACTED: &'static str = "meta_merge_operands_compacted";
 
     pub fn new(kv: Arc<SystemStorageKv>) -> Self {
         SystemStorage { kv }
@@ -105,6 +110,98 @@ impl SystemStorage {
             )
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    pub fn get_history_mode(&self) -> Result<Option<HistoryMode>, StorageError> {
+        match self.kv.get(&Self::HISTORY_MODE.to_string())? {
+            Some(SystemValue::String(value)) => Ok(Some(value.parse::<HistoryMode>()?)),
+            _ => Ok(None),
+        }
+    }
+
+    #[inline]
+    pub fn set_history_mode(&mut self, history_mode: &HistoryMode) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::HISTORY_MODE.to_string(),
+                &SystemValue::String(history_mode.to_string()),
+            )
+            .map_err(StorageError::from)
+    }
+
+    /// Returns target version of the migration step, which was started, but not finished yet
+    #[inline]
+    pub fn get_migration_in_progress(&self) -> Result<Option<DbVersion>, StorageError> {
+        self.kv
+            .get(&Self::MIGRATION_IN_PROGRESS.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Integer(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_migration_in_progress(&mut self, db_version: DbVersion) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::MIGRATION_IN_PROGRESS.to_string(),
+                &SystemValue::Integer(db_version),
+            )
+            .map_err(StorageError::from)
+    }
+
+    /// Returns last checkpoint stored by the migration step in progress
+    #[inline]
+    pub fn get_migration_checkpoint(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        self.kv
+            .get(&Self::MIGRATION_CHECKPOINT.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Bytes(value)) => Some(value),
+                _ => None,
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_migration_checkpoint(&mut self, checkpoint: Vec<u8>) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::MIGRATION_CHECKPOINT.to_string(),
+                &SystemValue::Bytes(checkpoint),
+            )
+            .map_err(StorageError::from)
+    }
+
+    /// Returns true, if merge operands of block and operations metadata stored by previous versions were already compacted
+    #[inline]
+    pub fn get_meta_merge_operands_compacted(&self) -> Result<bool, StorageError> {
+        self.kv
+            .get(&Self::META_MERGE_OPERANDS_COMPACTED.to_string())
+            .map(|result| matches!(result, Some(SystemValue::Integer(1))))
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_meta_merge_operands_compacted(&mut self) -> Result<(), StorageError> {
+        self.kv
+            .put(
+                &Self::META_MERGE_OPERANDS_COMPACTED.to_string(),
+                &SystemValue::Integer(1),
+            )
+            .map_err(StorageError::from)
+    }
+
+    /// Removes migration step in progress together with its checkpoint
+    #[inline]
+    pub fn clear_migration_in_progress(&mut self) -> Result<(), StorageError> {
+        self.kv
+            .delete(&Self::MIGRATION_CHECKPOINT.to_string())
+            .map_err(StorageError::from)?;
+        self.kv
+            .delete(&Self::MIGRATION_IN_PROGRESS.to_string())
+            .map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for SystemStorage {
@@ -129,6 +226,7 @@ pub enum SystemValue {
     String(String),
     Integer(i64),
     Hash(Vec<u8>),
+    Bytes(Vec<u8>),
 }
 
 impl BincodeEncoded for SystemValue {}
@@ -0,0 +1,175 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+
+use failure::Error;
+use slog::{Drain, Level, Logger};
+
+use crypto::blake2b;
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use storage::archive::{export_archive, import_archive, ArchiveError};
+use storage::tests_common::TmpStorage;
+use storage::*;
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[test]
+fn test_export_and_import_archive() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let context_hash: ContextHash = vec![0; HashType::ContextHash.size()].try_into()?;
+
+    // prepare source storage with chain: genesis <- block_1 <- block_2
+    let source = TmpStorage::create_to_out_dir("__archive_export")?;
+    let genesis = make_block_header(
+        0,
+        "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        0,
+        context_hash.clone(),
+    )?;
+    let block_1 = make_block_header(1, genesis.hash.clone(), 0, context_hash.clone())?;
+    let block_2 = make_block_header(2, block_1.hash.clone(), 1, context_hash.clone())?;
+    let operations = OperationsForBlocksMessage::new(
+        OperationsForBlock::new(block_2.hash.clone(), 0),
+        Path::op(),
+        vec![],
+    );
+    store_blocks(
+        source.storage(),
+        &chain_id,
+        &[&genesis, &block_1, &block_2],
+        &log,
+    )?;
+    OperationsStorage::new(source.storage()).put_operations(&operations)?;
+    BlockStorage::new(source.storage()).put_block_json_data(
+        &block_2.hash,
+        BlockJsonData::new("{}".to_string(), vec![], vec![vec![]]),
+    )?;
+
+    // export
+    let mut data = vec![];
+    let exported = export_archive(
+        source.storage(),
+        &chain_id,
+        &block_2.hash,
+        1,
+        &mut data,
+        &log,
+    )?;
+    assert_eq!(&1, exported.from_level());
+    assert_eq!(&2, exported.to_level());
+
+    // archive cannot be imported without genesis
+    let target = TmpStorage::create_to_out_dir("__archive_import")?;
+    assert!(matches!(
+        import_archive(target.storage(), &chain_id, data.as_slice(), &log),
+        Err(ArchiveError::InvalidData { .. })
+    ));
+
+    // archive of another chain cannot be imported
+    store_blocks(target.storage(), &chain_id, &[&genesis], &log)?;
+    assert!(matches!(
+        import_archive(
+            target.storage(),
+            &"NetXdQprcVkpaWU".try_into()?,
+            data.as_slice(),
+            &log
+        ),
+        Err(ArchiveError::ChainIdMismatch { .. })
+    ));
+
+    // import
+    let imported = import_archive(target.storage(), &chain_id, data.as_slice(), &log)?;
+    assert_eq!(imported.info(), &exported);
+    assert_eq!(
+        imported.blocks_to_apply(),
+        &vec![block_1.hash.clone(), block_2.hash.clone()]
+    );
+
+    let block_storage = BlockStorage::new(target.storage());
+    let block_meta_storage = BlockMetaStorage::new(target.storage());
+    assert_eq!(block_storage.get(&block_1.hash)?, Some(block_1.clone()));
+    assert_eq!(block_storage.get(&block_2.hash)?, Some(block_2.clone()));
+    assert!(block_storage.get_json_data(&block_2.hash)?.is_some());
+    assert!(!block_meta_storage.is_applied(&block_2.hash)?);
+    assert_eq!(
+        block_meta_storage
+            .get(&block_1.hash)?
+            .map(|meta| meta.successors().clone()),
+        Some(vec![block_2.hash.clone()])
+    );
+    assert_eq!(
+        block_meta_storage.find_block_at_distance(block_2.hash.clone(), 2)?,
+        Some(genesis.hash.clone())
+    );
+    assert_eq!(
+        OperationsStorage::new(target.storage()).get_operations(&block_2.hash)?,
+        vec![operations]
+    );
+    assert!(OperationsMetaStorage::new(target.storage()).is_complete(&block_2.hash)?);
+
+    Ok(())
+}
+
+fn store_blocks(
+    storage: &PersistentStorage,
+    chain_id: &ChainId,
+    blocks: &[&BlockHeaderWithHash],
+    log: &Logger,
+) -> Result<(), Error> {
+    let block_storage = BlockStorage::new(storage);
+    let block_meta_storage = BlockMetaStorage::new(storage);
+    let operations_meta_storage = OperationsMetaStorage::new(storage);
+    for block in blocks {
+        block_storage.put_block_header(block)?;
+        let mut meta = block_meta_storage.put_block_header(block, chain_id, log)?;
+        meta.set_is_applied(true);
+        block_meta_storage.put(&block.hash, &meta)?;
+        operations_meta_storage.put_block_header(block)?;
+    }
+    Ok(())
+}
+
+fn make_block_header(
+    level: i32,
+    predecessor: BlockHash,
+    validation_pass: u8,
+    context: ContextHash,
+) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(validation_pass)
+            .operations_hash(empty_operations_hash(validation_pass)?.try_into()?)
+            .fitness(vec![vec![0, level as u8]])
+            .context(context)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?)
+}
+
+/// Operations hash of the block with all `validation_pass` operation lists empty
+fn empty_operations_hash(validation_pass: u8) -> Result<Vec<u8>, Error> {
+    let empty_pass = blake2b::merkle_tree(&[])?;
+    Ok(blake2b::merkle_tree(&vec![
+        empty_pass;
+        validation_pass as usize
+    ])?)
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
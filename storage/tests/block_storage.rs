@@ -5,7 +5,7 @@ use std::convert::TryInto;
 
 use failure::Error;
 
-use crypto::hash::HashType;
+use crypto::hash::{BlockHash, HashType};
 use storage::tests_common::TmpStorage;
 use storage::*;
 use tezos_messages::p2p::binary_message::BinaryRead;
@@ -73,6 +73,115 @@ fn block_storage_assign_context() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn block_storage_remove_json_data_and_delete() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__block_remove_json_data_and_delete")?;
+    let storage = BlockStorage::new(tmp_storage.storage());
+
+    let block_header = make_test_block_header()?;
+    let context_hash = block_header.header.context().clone();
+
+    storage.put_block_header(&block_header)?;
+    storage.put_block_json_data(
+        &block_header.hash,
+        BlockJsonData::new("{}".to_string(), vec![1, 2, 3], vec![]),
+    )?;
+    storage.assign_to_context(&block_header.hash, &context_hash)?;
+    assert!(storage.get_json_data(&block_header.hash)?.is_some());
+    assert!(storage.contains_context_hash(&context_hash)?);
+
+    // remove json data, header is still there
+    storage.remove_block_json_data(&block_header.hash)?;
+    assert_eq!(block_header, storage.get(&block_header.hash)?.unwrap());
+    assert!(storage.get_json_data(&block_header.hash)?.is_none());
+    assert!(!storage.contains_context_hash(&context_hash)?);
+    assert_eq!(
+        1,
+        storage
+            .get_multiple_without_json(&block_header.hash, 10)?
+            .len()
+    );
+
+    // delete block
+    storage.delete(&block_header.hash)?;
+    assert!(storage.get(&block_header.hash)?.is_none());
+    assert!(storage.get_location(&block_header.hash)?.is_none());
+
+    // delete is idempotent
+    storage.delete(&block_header.hash)?;
+
+    Ok(())
+}
+
+#[test]
+fn block_storage_remove_commit_log_segments_keeps_genesis() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__block_remove_commit_log_segments")?;
+    let storage = BlockStorage::new(tmp_storage.storage());
+
+    // genesis <- block_1 <- block_2
+    let genesis = make_block_header(
+        0,
+        "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+    )?;
+    let block_1 = make_block_header(1, genesis.hash.clone())?;
+    let block_2 = make_block_header(2, block_1.hash.clone())?;
+    for block in &[&genesis, &block_1, &block_2] {
+        storage.put_block_header(block)?;
+        storage.put_block_json_data(
+            &block.hash,
+            BlockJsonData::new("{}".to_string(), vec![], vec![]),
+        )?;
+        storage.assign_to_context(&block.hash, block.header.context())?;
+    }
+    let genesis_location = storage.get_location(&genesis.hash)?.unwrap();
+    let caboose_location = storage.get_location(&block_2.hash)?.unwrap();
+
+    // records older than the caboose are not needed, just genesis is moved to the end of the commit log
+    storage.remove_commit_log_segments_below(2, &genesis.hash)?;
+    let relocated = storage.get_location(&genesis.hash)?.unwrap();
+    assert!(relocated.oldest_offset() > caboose_location.oldest_offset());
+    assert!(relocated.oldest_offset() > genesis_location.oldest_offset());
+    assert_eq!(Some(genesis.clone()), storage.get(&genesis.hash)?);
+    assert!(storage.get_json_data(&genesis.hash)?.is_some());
+    assert_eq!(
+        Some(genesis.clone()),
+        storage.get_by_context_hash(genesis.header.context())?
+    );
+    assert_eq!(
+        1,
+        storage.get_multiple_without_json(&genesis.hash, 1)?.len()
+    );
+
+    // genesis is not moved again
+    storage.remove_commit_log_segments_below(2, &genesis.hash)?;
+    assert_eq!(
+        relocated.oldest_offset(),
+        storage
+            .get_location(&genesis.hash)?
+            .unwrap()
+            .oldest_offset()
+    );
+
+    Ok(())
+}
+
+fn make_block_header(level: i32, predecessor: BlockHash) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(0)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![vec![0, level as u8]])
+            .context(vec![level as u8; HashType::ContextHash.size()].try_into()?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?)
+}
+
 fn make_test_block_header() -> Result<BlockHeaderWithHash, Error> {
     let message_bytes = hex::decode("00006d6e0102dd00defaf70c53e180ea148b349a6feb4795610b2abc7b07fe91ce50a90814000000005c1276780432bc1d3a28df9a67b363aa1638f807214bb8987e5f9c0abcbd69531facffd1c80000001100000001000000000800000000000c15ef15a6f54021cb353780e2847fb9c546f1d72c1dc17c3db510f45553ce501ce1de000000000003c762c7df00a856b8bfcaf0676f069f825ca75f37f2bee9fe55ba109cec3d1d041d8c03519626c0c0faa557e778cb09d2e0c729e8556ed6a7a518c84982d1f2682bc6aa753f")?;
     let block_header = BlockHeaderWithHash::new(BlockHeader::from_bytes(message_bytes)?)?;
@@ -0,0 +1,210 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+
+use failure::Error;
+use slog::{Drain, Level, Logger};
+
+use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
+use storage::chain_meta_storage::ChainMetaStorageReader;
+use storage::tests_common::TmpStorage;
+use storage::*;
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[test]
+fn test_prune_rolling_history() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_prune_rolling")?;
+    let blocks = store_chain(tmp_storage.storage(), &chain_id, 40, &log)?;
+    let head = &blocks[39];
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+    let pruner = HistoryPruner::new(tmp_storage.storage());
+
+    let pruned = pruner.prune(
+        &chain_id,
+        &HistoryMode::Rolling(1),
+        &head.hash,
+        39,
+        20,
+        &log,
+    )?;
+    assert_eq!(19, pruned);
+
+    // genesis and blocks from the new caboose are kept
+    assert!(block_storage.get(&blocks[0].hash)?.is_some());
+    assert!(block_meta_storage.get(&blocks[0].hash)?.is_some());
+    for block in &blocks[1..20] {
+        assert!(block_storage.get(&block.hash)?.is_none());
+        assert!(block_meta_storage.get(&block.hash)?.is_none());
+    }
+    for block in &blocks[20..] {
+        assert!(block_storage.get(&block.hash)?.is_some());
+        assert!(block_meta_storage.get(&block.hash)?.is_some());
+    }
+    assert_eq!(
+        Some(blocks[20].hash.clone()),
+        chain_meta_storage
+            .get_caboose(&chain_id)?
+            .map(|caboose| caboose.block_hash().clone())
+    );
+    assert_eq!(
+        Some(blocks[20].hash.clone()),
+        chain_meta_storage
+            .get_save_point(&chain_id)?
+            .map(|save_point| save_point.block_hash().clone())
+    );
+
+    // retained blocks are resolvable, but there is no predecessor pointing to the pruned blocks
+    assert_eq!(
+        Some(blocks[20].hash.clone()),
+        block_meta_storage.find_block_at_distance(head.hash.clone(), 19)?
+    );
+    assert_eq!(
+        Some(blocks[21].hash.clone()),
+        block_meta_storage.find_block_at_distance(blocks[37].hash.clone(), 16)?
+    );
+    assert_eq!(
+        None,
+        block_meta_storage.find_block_at_distance(head.hash.clone(), 20)?
+    );
+    assert_eq!(
+        None,
+        block_meta_storage.find_block_at_distance(blocks[30].hash.clone(), 16)?
+    );
+    assert_eq!(
+        None,
+        block_meta_storage.find_block_at_distance(blocks[20].hash.clone(), 1)?
+    );
+
+    // every block is pruned just once
+    assert_eq!(
+        0,
+        pruner.prune(
+            &chain_id,
+            &HistoryMode::Rolling(1),
+            &head.hash,
+            39,
+            20,
+            &log
+        )?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_full_history() -> Result<(), Error> {
+    let log = create_logger();
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let tmp_storage = TmpStorage::create_to_out_dir("__history_mode_prune_full")?;
+    let blocks = store_chain(tmp_storage.storage(), &chain_id, 10, &log)?;
+    let head = &blocks[9];
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+
+    let pruned = HistoryPruner::new(tmp_storage.storage()).prune(
+        &chain_id,
+        &HistoryMode::Full(1),
+        &head.hash,
+        9,
+        5,
+        &log,
+    )?;
+    assert_eq!(4, pruned);
+
+    // just json data are removed, headers and predecessors are kept
+    for block in &blocks[1..5] {
+        assert!(block_storage.get(&block.hash)?.is_some());
+        assert!(block_storage.get_json_data(&block.hash)?.is_none());
+    }
+    for block in &blocks[5..] {
+        assert!(block_storage.get_json_data(&block.hash)?.is_some());
+    }
+    assert_eq!(
+        Some(blocks[0].hash.clone()),
+        block_meta_storage.find_block_at_distance(head.hash.clone(), 9)?
+    );
+    assert_eq!(
+        Some(blocks[5].hash.clone()),
+        chain_meta_storage
+            .get_save_point(&chain_id)?
+            .map(|save_point| save_point.block_hash().clone())
+    );
+    assert!(chain_meta_storage.get_caboose(&chain_id)?.is_none());
+
+    Ok(())
+}
+
+/// Stores chain of `count` applied blocks (including genesis) with json data and predecessors
+fn store_chain(
+    storage: &PersistentStorage,
+    chain_id: &ChainId,
+    count: i32,
+    log: &Logger,
+) -> Result<Vec<BlockHeaderWithHash>, Error> {
+    let block_storage = BlockStorage::new(storage);
+    let block_meta_storage = BlockMetaStorage::new(storage);
+    let context_hash: ContextHash = vec![0; HashType::ContextHash.size()].try_into()?;
+
+    let mut blocks: Vec<BlockHeaderWithHash> = Vec::with_capacity(count as usize);
+    for level in 0..count {
+        let predecessor = match blocks.last() {
+            Some(predecessor) => predecessor.hash.clone(),
+            None => "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe".try_into()?,
+        };
+        let block = make_block_header(level, predecessor, context_hash.clone())?;
+
+        block_storage.put_block_header(&block)?;
+        block_storage.put_block_json_data(
+            &block.hash,
+            BlockJsonData::new("{}".to_string(), vec![], vec![]),
+        )?;
+        let mut meta = block_meta_storage.put_block_header(&block, chain_id, log)?;
+        meta.set_is_applied(true);
+        block_meta_storage.put(&block.hash, &meta)?;
+        block_meta_storage.store_predecessors(&block.hash, &meta)?;
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+fn make_block_header(
+    level: i32,
+    predecessor: BlockHash,
+    context: ContextHash,
+) -> Result<BlockHeaderWithHash, Error> {
+    Ok(BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(0)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![vec![0, level as u8]])
+            .context(context)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?)
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(
+        slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
+            .build()
+            .fuse(),
+    )
+    .build()
+    .filter_level(Level::Info)
+    .fuse();
+
+    Logger::root(drain, slog::o!())
+}
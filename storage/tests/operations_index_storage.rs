@@ -0,0 +1,235 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::{TryFrom, TryInto};
+
+use crypto::hash::{BlockHash, ChainId, OperationHash};
+use failure::Error;
+
+use storage::context::actions::context_action_storage::contract_id_to_contract_address_for_index;
+use storage::operations_index_storage::{extract_indexed_operations, MainChainHead};
+use storage::tests_common::TmpStorage;
+use storage::*;
+use tezos_messages::p2p::binary_message::{BinaryRead, MessageHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+const SOURCE: &str = "tz1PirboZKFVqkfE45hVLpkpXaZtLk3mqC17";
+const DESTINATION: &str = "KT1VG2WtYdSWz5E7chTeAdDPZNy2MpP8pTfL";
+const BAKER: &str = "tz1VwmmesDxud2BJEyDKUTV5T5VEP8tGBKGD";
+
+fn operation(last_byte: &str) -> Result<Operation, Error> {
+    let bytes = hex::decode(format!("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd{}", last_byte))?;
+    Ok(Operation::from_bytes(bytes)?)
+}
+
+fn operation_json(source: &str, destination: &str) -> String {
+    format!(
+        r#"{{ "contents": [ {{ "kind": "transaction", "source": "{}", "destination": "{}", "metadata": {{ "balance_updates": [ {{ "kind": "freezer", "delegate": "{}" }} ] }} }} ] }}"#,
+        source, destination, BAKER
+    )
+}
+
+/// Block with one endorsement (validation pass 0) and two transactions (validation pass 3)
+fn block_operations(
+    block_hash: &BlockHash,
+    seed: &str,
+) -> Result<(Vec<OperationsForBlocksMessage>, String), Error> {
+    let operations = vec![
+        OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block_hash.clone(), 0),
+            Path::op(),
+            vec![operation(&format!("{}0", seed))?],
+        ),
+        OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block_hash.clone(), 1),
+            Path::op(),
+            vec![],
+        ),
+        OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block_hash.clone(), 2),
+            Path::op(),
+            vec![],
+        ),
+        OperationsForBlocksMessage::new(
+            OperationsForBlock::new(block_hash.clone(), 3),
+            Path::op(),
+            vec![
+                operation(&format!("{}1", seed))?,
+                operation(&format!("{}2", seed))?,
+            ],
+        ),
+    ];
+    let json = format!(
+        r#"[ [ {{ "contents": [ {{ "kind": "endorsement", "metadata": {{ "delegate": "{}" }} }} ] }} ], [], [], [ {}, {} ] ]"#,
+        BAKER,
+        operation_json(SOURCE, DESTINATION),
+        operation_json(DESTINATION, SOURCE),
+    );
+    Ok((operations, json))
+}
+
+#[test]
+fn test_operations_index() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__op_index_storage_test")?;
+    let storage = OperationsIndexStorage::new(tmp_storage.storage());
+
+    // main chain genesis <- block_1 <- block_2 and block_2_fork on the abandoned branch
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let genesis = store_block(&block_meta_storage, 0, genesis_hash()?, 0)?;
+    let block_1 = store_block(&block_meta_storage, 1, genesis, 0)?;
+    let block_2 = store_block(&block_meta_storage, 2, block_1.clone(), 0)?;
+    let block_2_fork = store_block(&block_meta_storage, 2, block_1.clone(), 1)?;
+    let head = MainChainHead::new(block_2.clone(), 2);
+
+    let (operations_1, json_1) = block_operations(&block_1, "a")?;
+    let (operations_2, json_2) = block_operations(&block_2, "b")?;
+    let (operations_fork, json_fork) = block_operations(&block_2_fork, "d")?;
+    storage.put_block_operations(
+        &block_1,
+        1,
+        extract_indexed_operations(&operations_1, &json_1)?,
+    )?;
+    storage.put_block_operations(
+        &block_2_fork,
+        2,
+        extract_indexed_operations(&operations_fork, &json_fork)?,
+    )?;
+    storage.put_block_operations(
+        &block_2,
+        2,
+        extract_indexed_operations(&operations_2, &json_2)?,
+    )?;
+
+    // find by operation hash
+    let operation_hash = OperationHash::try_from(operations_2[3].operations()[1].message_hash()?)?;
+    let location = storage
+        .find_operation(&operation_hash, &head)?
+        .expect("Operation should be indexed");
+    assert_eq!(&block_2, location.block_hash());
+    assert_eq!(2, location.level());
+    assert_eq!(3, location.validation_pass());
+    assert_eq!(1, location.operation_index());
+
+    // operations of the abandoned branch are not found
+    let fork_operation_hash =
+        OperationHash::try_from(operations_fork[3].operations()[1].message_hash()?)?;
+    assert!(storage
+        .find_operation(&fork_operation_hash, &head)?
+        .is_none());
+    assert!(storage
+        .find_operation(
+            &fork_operation_hash,
+            &MainChainHead::new(block_2_fork.clone(), 2)
+        )?
+        .is_some());
+
+    // account operations are returned from the newest one
+    let source = contract_id_to_contract_address_for_index(SOURCE)?;
+    let operations = storage.get_account_operations(&source, None, 100, &head)?;
+    assert_eq!(4, operations.len());
+    assert_eq!(
+        vec![(2, 3, 1), (2, 3, 0), (1, 3, 1), (1, 3, 0)],
+        operations
+            .iter()
+            .map(|op| (op.level, op.validation_pass, op.operation_index))
+            .collect::<Vec<_>>()
+    );
+    assert!(operations.iter().all(|op| op.block_hash != block_2_fork));
+    assert_eq!(operation_hash, operations[0].operation_hash);
+
+    // baker is touched by all operations
+    let baker = contract_id_to_contract_address_for_index(BAKER)?;
+    assert_eq!(
+        6,
+        storage
+            .get_account_operations(&baker, None, 100, &head)?
+            .len()
+    );
+
+    // pagination with cursor (inclusive)
+    let page_1 = storage.get_account_operations(&source, None, 3, &head)?;
+    assert_eq!(3, page_1.len());
+    let page_2 = storage.get_account_operations(&source, Some(page_1[2].cursor_id), 3, &head)?;
+    assert_eq!(2, page_2.len());
+    assert_eq!(page_1[2], page_2[0]);
+    assert_eq!(operations[3], page_2[1]);
+
+    // unknown account
+    let unknown =
+        contract_id_to_contract_address_for_index("tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx")?;
+    assert!(storage
+        .get_account_operations(&unknown, None, 100, &head)?
+        .is_empty());
+
+    // pruned block is removed from indexes
+    storage.delete_block_operations(&block_1, &operations_1)?;
+    assert!(storage
+        .find_operation(
+            &OperationHash::try_from(operations_1[3].operations()[0].message_hash()?)?,
+            &head
+        )?
+        .is_none());
+    assert!(storage.find_operation(&operation_hash, &head)?.is_some());
+    assert_eq!(
+        vec![2, 2],
+        storage
+            .get_account_operations(&source, None, 100, &head)?
+            .iter()
+            .map(|op| op.level)
+            .collect::<Vec<_>>()
+    );
+
+    // blocks, which failed to be indexed, are recorded for rebuild
+    storage.mark_unindexed(&block_1)?;
+    assert_eq!(vec![block_1.clone()], storage.unindexed_blocks()?);
+    storage.remove_unindexed_mark(&block_1)?;
+    assert!(storage.unindexed_blocks()?.is_empty());
+
+    Ok(())
+}
+
+fn genesis_hash() -> Result<BlockHash, Error> {
+    Ok(BlockHash::try_from(
+        "BLockGenesisGenesisGenesisGenesisGenesisb83baZgbyZe",
+    )?)
+}
+
+/// Stores applied block with predecessors, so it can be resolved on the main chain, returns its hash
+fn store_block(
+    block_meta_storage: &BlockMetaStorage,
+    level: i32,
+    predecessor: BlockHash,
+    branch: u8,
+) -> Result<BlockHash, Error> {
+    let block = BlockHeaderWithHash::new(
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(0)
+            .predecessor(predecessor)
+            .timestamp(5_635_634 + level as i64)
+            .validation_pass(4)
+            .operations_hash("LLoaGLRPRx3Zf8kB4ACtgku8F4feeBiskeb41J1ciwfcXB3KzHKXc".try_into()?)
+            .fitness(vec![vec![branch, level as u8]])
+            .context("CoVmAcMV64uAQo8XvfLr9VDuz7HVZLT4cgK1w1qYmTjQNbGwQwDd".try_into()?)
+            .protocol_data(vec![])
+            .build()
+            .unwrap(),
+    )?;
+    let chain_id = ChainId::try_from("NetXgtSLGNJvNye")?;
+    let log = slog::Logger::root(slog::Discard, slog::o!());
+    let mut meta = block_meta_storage.put_block_header(&block, &chain_id, &log)?;
+    meta.set_is_applied(true);
+    block_meta_storage.put(&block.hash, &meta)?;
+    block_meta_storage.store_predecessors(&block.hash, &meta)?;
+    Ok(block.hash)
+}
+
+#[test]
+fn test_extract_indexed_operations_mismatch() -> Result<(), Error> {
+    let block_hash = BlockHash::try_from("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+    let (operations, _) = block_operations(&block_hash, "c")?;
+
+    assert!(extract_indexed_operations(&operations, "[[], [], [], []]").is_err());
+    assert!(extract_indexed_operations(&operations, "not a json").is_err());
+    Ok(())
+}
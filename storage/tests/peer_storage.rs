@@ -0,0 +1,126 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use failure::Error;
+
+use crypto::hash::{CryptoboxPublicKeyHash, HashType};
+use storage::peer_storage::{INITIAL_GREYLIST_DELAY, KNOWN_ENTRY_EXPIRY, MIN_CONNECTION_LIFETIME};
+use storage::tests_common::TmpStorage;
+use storage::PeerStorage;
+
+#[test]
+fn test_peer_storage_scoring_and_greylisting() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__peer_storage_scoring")?;
+    let storage = PeerStorage::new(tmp_storage.storage());
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    let good: SocketAddr = "10.0.0.1:9732".parse()?;
+    let bad: SocketAddr = "10.0.0.2:9732".parse()?;
+    let unknown: SocketAddr = "10.0.0.3:9732".parse()?;
+    let peer_id: CryptoboxPublicKeyHash =
+        vec![1; HashType::CryptoboxPublicKeyHash.size()].try_into()?;
+
+    storage.add_points(vec![good, bad, unknown], now)?;
+    storage.connection_succeeded(&good, &peer_id, false, now)?;
+    storage.connection_failed(&bad, now)?;
+
+    assert_eq!(storage.dial_candidates(now, 10)?, vec![good, unknown, bad]);
+    assert_eq!(storage.dial_candidates(now, 1)?, vec![good]);
+    assert_eq!(
+        storage
+            .get_peer(&peer_id)?
+            .and_then(|peer| *peer.last_address()),
+        Some(good)
+    );
+
+    // greylisted point is not a dial candidate
+    let until = storage.greylist(&good, Some(&peer_id), now)?;
+    assert_eq!(until, now + INITIAL_GREYLIST_DELAY);
+    assert!(storage.is_peer_rejected(&peer_id, now)?);
+    assert_eq!(storage.greylisted_points(now)?, vec![(good, until)]);
+    assert_eq!(storage.dial_candidates(now, 10)?, vec![unknown, bad]);
+
+    // greylisting expires
+    assert!(storage.greylisted_points(until)?.is_empty());
+
+    // whitelisting ends greylisting, but keeps backoff
+    storage.whitelist_all()?;
+    assert!(!storage.is_peer_rejected(&peer_id, now)?);
+    assert_eq!(
+        storage.greylist(&good, None, now)?,
+        now + 2 * INITIAL_GREYLIST_DELAY
+    );
+
+    // long-lived connection resets backoff on disconnect
+    storage.whitelist_all()?;
+    storage.connection_succeeded(&good, &peer_id, false, now)?;
+    let later = now + MIN_CONNECTION_LIFETIME;
+    storage.connection_closed(&good, &peer_id, false, now, later)?;
+    assert_eq!(
+        storage.greylist(&good, None, later)?,
+        later + INITIAL_GREYLIST_DELAY
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_storage_ban() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__peer_storage_ban")?;
+    let storage = PeerStorage::new(tmp_storage.storage());
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    let banned: SocketAddr = "10.0.0.1:9732".parse()?;
+    let other: SocketAddr = "10.0.0.2:9732".parse()?;
+    let peer_id: CryptoboxPublicKeyHash =
+        vec![1; HashType::CryptoboxPublicKeyHash.size()].try_into()?;
+
+    storage.add_points(vec![banned, other], now)?;
+    storage.ban_point(&banned, now)?;
+    storage.ban_peer(&peer_id, now)?;
+
+    // ban does not expire and it is not ended by whitelisting of all points and peers
+    let later = now + 10 * KNOWN_ENTRY_EXPIRY;
+    storage.whitelist_all()?;
+    assert_eq!(storage.remove_expired(later)?, 1);
+    assert_eq!(storage.banned_points()?, vec![banned]);
+    assert!(storage.greylisted_points(later)?.is_empty());
+    assert!(storage.dial_candidates(later, 10)?.is_empty());
+    assert!(storage.is_peer_rejected(&peer_id, later)?);
+
+    // unban
+    storage.whitelist_point(&banned)?;
+    storage.whitelist_peer(&peer_id)?;
+    assert!(storage.banned_points()?.is_empty());
+    assert_eq!(storage.dial_candidates(later, 10)?, vec![banned]);
+    assert!(!storage.is_peer_rejected(&peer_id, later)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_storage_remove_expired() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__peer_storage_expiry")?;
+    let storage = PeerStorage::new(tmp_storage.storage());
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+    let seen: SocketAddr = "10.0.0.1:9732".parse()?;
+    let not_seen: SocketAddr = "10.0.0.2:9732".parse()?;
+    let peer_id: CryptoboxPublicKeyHash =
+        vec![1; HashType::CryptoboxPublicKeyHash.size()].try_into()?;
+
+    storage.add_points(vec![seen, not_seen], now)?;
+    let later = now + KNOWN_ENTRY_EXPIRY;
+    storage.connection_succeeded(&seen, &peer_id, false, later)?;
+
+    assert_eq!(storage.remove_expired(later + Duration::from_secs(1))?, 1);
+    assert!(storage.get_point(&seen)?.is_some());
+    assert!(storage.get_point(&not_seen)?.is_none());
+    assert!(storage.get_peer(&peer_id)?.is_some());
+
+    Ok(())
+}
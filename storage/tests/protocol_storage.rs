@@ -0,0 +1,37 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use crypto::hash::ProtocolHash;
+use failure::Error;
+
+use storage::tests_common::TmpStorage;
+use storage::ProtocolStorage;
+use tezos_messages::p2p::binary_message::{BinaryWrite, MessageHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[test]
+fn protocol_storage_read_write() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__protocol_storage_read_write")?;
+    let storage = ProtocolStorage::new(tmp_storage.storage());
+
+    let protocol = Protocol::new(
+        0,
+        vec![Component::new(
+            "Main".to_string(),
+            Some("val x : int".to_string()),
+            "let x = 1".to_string(),
+        )],
+    );
+    let protocol_hash = protocol.message_typed_hash::<ProtocolHash>()?;
+
+    assert!(!storage.contains(&protocol_hash)?);
+    storage.put(&protocol_hash, &protocol)?;
+    assert!(storage.contains(&protocol_hash)?);
+
+    let stored = storage.get(&protocol_hash)?.expect("Protocol was stored");
+    assert_eq!(stored.as_bytes()?, protocol.as_bytes()?);
+    assert_eq!(stored.components()[0].name(), "Main");
+    assert_eq!(storage.protocol_hashes()?, vec![protocol_hash]);
+
+    Ok(())
+}
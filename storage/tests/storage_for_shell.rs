@@ -32,7 +32,6 @@ fn test_storage() -> Result<(), Error> {
     let block_storage = BlockStorage::new(tmp_storage.storage());
     let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
     let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
-    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
 
     // tezos env - sample
     let tezos_env = TezosEnvironmentConfiguration {
@@ -60,6 +59,7 @@ fn test_storage() -> Result<(), Error> {
         &tmp_storage_dir,
         &context_dir,
         &None,
+        &HistoryMode::Archive,
         false,
         &log,
     );
@@ -101,10 +101,7 @@ fn test_storage() -> Result<(), Error> {
         operations_proto_metadata_bytes: Vec::new(),
     };
     let _ = store_commit_genesis_result(
-        &block_storage,
-        &block_meta_storage,
-        &chain_meta_storage,
-        &operations_meta_storage,
+        tmp_storage.storage(),
         &init_data,
         commit_genesis_result.clone(),
     )?;
@@ -201,22 +198,20 @@ fn test_storage() -> Result<(), Error> {
         ops_metadata_hashes: None,
         ops_metadata_hash: None,
     };
+    // and set block as current head
     let block_additional_data = store_applied_block_result(
-        &block_storage,
-        &block_meta_storage,
+        tmp_storage.storage(),
         &block.hash,
         apply_result.clone(),
         &mut metadata,
-    )?;
-
-    // set block as current head
-    chain_meta_storage.set_current_head(
-        &init_data.chain_id,
-        Head::new(
-            block.hash.clone(),
-            block.header.level(),
-            block.header.fitness().to_vec(),
-        ),
+        Some((
+            &init_data.chain_id,
+            Head::new(
+                block.hash.clone(),
+                block.header.level(),
+                block.header.fitness().to_vec(),
+            ),
+        )),
     )?;
 
     // check if data stored
@@ -273,6 +268,103 @@ fn test_storage() -> Result<(), Error> {
         &block.hash
     );
 
+    // current head is completely stored, so nothing to repair
+    assert!(
+        ensure_consistent_current_head(tmp_storage.storage(), &init_data.chain_id, &log)?.is_none()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_store_configured_checkpoint() -> Result<(), Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create_to_out_dir("__store_configured_checkpoint")?;
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let chain_meta_storage = ChainMetaStorage::new(tmp_storage.storage());
+    let chain_id = chain_id_from_block_hash(&BlockHash::try_from(
+        "BLockGenesisGenesisGenesisGenesisGenesiscde8db4cX94",
+    )?)?;
+
+    let block = make_test_block_header()?;
+    let unknown_block_hash =
+        BlockHash::try_from("BLockGenesisGenesisGenesisGenesisGenesisd6f5afWyME7")?;
+
+    // unknown block without level cannot be configured
+    assert!(matches!(
+        store_configured_checkpoint(
+            &block_storage,
+            &chain_meta_storage,
+            &chain_id,
+            &unknown_block_hash,
+            None,
+            &log,
+        ),
+        Err(StorageError::InvalidCheckpoint { .. })
+    ));
+    assert!(chain_meta_storage.get_checkpoint(&chain_id)?.is_none());
+
+    // unknown block with level is accepted as it is
+    let checkpoint = store_configured_checkpoint(
+        &block_storage,
+        &chain_meta_storage,
+        &chain_id,
+        &unknown_block_hash,
+        Some(5),
+        &log,
+    )?;
+    assert_eq!(checkpoint.block_hash(), &unknown_block_hash);
+    assert_eq!(*checkpoint.level(), 5);
+    assert_eq!(
+        chain_meta_storage
+            .get_checkpoint(&chain_id)?
+            .expect("Checkpoint should be set")
+            .block_hash(),
+        &unknown_block_hash
+    );
+
+    // stored block with different level is rejected and does not touch actual checkpoint
+    block_storage.put_block_header(&block)?;
+    assert!(matches!(
+        store_configured_checkpoint(
+            &block_storage,
+            &chain_meta_storage,
+            &chain_id,
+            &block.hash,
+            Some(block.header.level() + 1),
+            &log,
+        ),
+        Err(StorageError::InvalidCheckpoint { .. })
+    ));
+    assert_eq!(
+        chain_meta_storage
+            .get_checkpoint(&chain_id)?
+            .expect("Checkpoint should be set")
+            .block_hash(),
+        &unknown_block_hash
+    );
+
+    // stored block overrides actual checkpoint, level and fitness are resolved from header
+    for level in &[None, Some(block.header.level())] {
+        let checkpoint = store_configured_checkpoint(
+            &block_storage,
+            &chain_meta_storage,
+            &chain_id,
+            &block.hash,
+            *level,
+            &log,
+        )?;
+        assert_eq!(checkpoint.block_hash(), &block.hash);
+        assert_eq!(*checkpoint.level(), block.header.level());
+        assert_eq!(checkpoint.fitness(), block.header.fitness());
+
+        let stored = chain_meta_storage
+            .get_checkpoint(&chain_id)?
+            .expect("Checkpoint should be set");
+        assert_eq!(stored.block_hash(), &block.hash);
+        assert_eq!(*stored.level(), block.header.level());
+    }
+
     Ok(())
 }
 
@@ -1,14 +1,20 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::env;
 use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
 use storage::context::kv_store::test_support::{
     blob_serialized, entry_hash, TestContextKvStoreFactoryInstance,
 };
 use storage::context::kv_store::SupportedContextKeyValueStore;
+use storage::context::ContextKeyValueStore;
+use storage::persistent::COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK;
 
 fn test_put_get(kv_store_factory: &TestContextKvStoreFactoryInstance) {
     let storage = kv_store_factory.create("test_put_get").unwrap();
@@ -130,6 +136,58 @@ fn test_retain(kv_store_factory: &TestContextKvStoreFactoryInstance) {
     assert!(storage.get(&entry_hash(&[2])).unwrap().is_none());
 }
 
+fn test_retain_does_not_block_writers(kv_store_factory: &TestContextKvStoreFactoryInstance) {
+    let storage: Arc<ContextKeyValueStore> = Arc::from(
+        kv_store_factory
+            .create("test_retain_does_not_block_writers")
+            .unwrap(),
+    );
+
+    // keys for more chunks
+    let count = 3 * COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK as u32;
+    for i in 0..count {
+        storage
+            .put(&entry_hash(&i.to_be_bytes()), &blob_serialized(vec![1]))
+            .unwrap();
+    }
+    let kept = entry_hash(&0_u32.to_be_bytes());
+    let written = entry_hash(&[0xFF; 8]);
+
+    let (retain_started, retain_started_rx) = mpsc::channel();
+    let (written_tx, written_rx) = mpsc::channel();
+    let writer = {
+        let storage = storage.clone();
+        thread::spawn(move || {
+            retain_started_rx.recv().unwrap();
+            storage.put(&written, &blob_serialized(vec![2])).unwrap();
+            written_tx.send(()).unwrap();
+        })
+    };
+
+    // retain waits for the writer in the middle of the store
+    let checked = Cell::new(0);
+    storage
+        .retain(&|key| {
+            checked.set(checked.get() + 1);
+            if checked.get() == COUNT_OF_KEYS_TO_RETAIN_IN_SINGLE_CHUNK + 1 {
+                retain_started.send(()).unwrap();
+                written_rx
+                    .recv_timeout(Duration::from_secs(10))
+                    .expect("writer was blocked by running retain");
+            }
+            key == &kept || key == &written
+        })
+        .unwrap();
+    writer.join().unwrap();
+
+    assert!(storage.get(&kept).unwrap().is_some());
+    assert!(storage.get(&written).unwrap().is_some());
+    assert!(storage
+        .get(&entry_hash(&1_u32.to_be_bytes()))
+        .unwrap()
+        .is_none());
+}
+
 // TODO: TE-150 - real support mutliprocess
 fn test_multiple_open_instances(kv_store_factory: &TestContextKvStoreFactoryInstance) {
     if !kv_store_factory.supports_multiple_opened_instances() {
@@ -223,6 +281,10 @@ macro_rules! tests_with_storage {
                 super::test_retain($kv_store_factory)
             }
             #[test]
+            fn test_retain_does_not_block_writers() {
+                super::test_retain_does_not_block_writers($kv_store_factory)
+            }
+            #[test]
             fn test_multiple_open_instances() {
                 super::test_multiple_open_instances($kv_store_factory)
             }
@@ -274,6 +336,16 @@ macro_rules! tests_with_all_kv_stores {
                 )
                 .unwrap()
         );
+        tests_with_storage!(
+            kv_store_pack_file_tests,
+            super::SUPPORTED_KV_STORES
+                .get(
+                    &storage::context::kv_store::SupportedContextKeyValueStore::PackFile {
+                        path: super::out_dir_path()
+                    }
+                )
+                .unwrap()
+        );
     };
 }
 
@@ -0,0 +1,120 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryInto;
+
+use failure::Error;
+
+use crypto::hash::{BlockHash, ChainId, HashType};
+use storage::block_meta_storage::Meta;
+use storage::tests_common::TmpStorage;
+use storage::*;
+
+#[test]
+fn test_write_batch_commit() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__write_batch_commit")?;
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let block_hash: BlockHash = vec![1; HashType::BlockHash.size()].try_into()?;
+    let predecessor: BlockHash = vec![2; HashType::BlockHash.size()].try_into()?;
+
+    let mut batch = tmp_storage.storage().write_batch();
+    block_meta_storage.put_batched(
+        &mut batch,
+        &block_hash,
+        &Meta::new(false, None, 5, chain_id.clone()),
+    )?;
+    block_meta_storage.put_batched(
+        &mut batch,
+        &block_hash,
+        &Meta::new(true, Some(predecessor.clone()), 5, chain_id.clone()),
+    )?;
+    assert_eq!(2, batch.len());
+
+    // nothing is visible before commit
+    assert!(block_meta_storage.get(&block_hash)?.is_none());
+    batch.commit()?;
+
+    // merges in the same batch are merged together
+    let meta = block_meta_storage
+        .get(&block_hash)?
+        .expect("Metadata should be stored");
+    assert!(meta.is_applied());
+    assert_eq!(&Some(predecessor), meta.predecessor());
+    assert_eq!(5, meta.level());
+
+    // merge operator cannot unset flags
+    block_meta_storage.put(&block_hash, &Meta::new(false, None, 5, chain_id))?;
+    assert!(block_meta_storage
+        .get(&block_hash)?
+        .expect("Metadata should be stored")
+        .is_applied());
+
+    Ok(())
+}
+
+#[test]
+fn test_write_batch_does_not_block_writers() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__write_batch_does_not_block_writers")?;
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let block_hash: BlockHash = vec![1; HashType::BlockHash.size()].try_into()?;
+    let predecessor: BlockHash = vec![2; HashType::BlockHash.size()].try_into()?;
+
+    let mut batch = tmp_storage.storage().write_batch();
+    block_meta_storage.put_batched(
+        &mut batch,
+        &block_hash,
+        &Meta::new(true, None, 5, chain_id.clone()),
+    )?;
+
+    // the same thread writes the same metadata directly, while the batch is not committed yet
+    block_meta_storage.put(
+        &block_hash,
+        &Meta::new(false, Some(predecessor.clone()), 5, chain_id),
+    )?;
+    assert!(!block_meta_storage
+        .get(&block_hash)?
+        .expect("Metadata should be stored")
+        .is_applied());
+
+    // committed batch is merged with the direct write
+    batch.commit()?;
+    let meta = block_meta_storage
+        .get(&block_hash)?
+        .expect("Metadata should be stored");
+    assert!(meta.is_applied());
+    assert_eq!(&Some(predecessor), meta.predecessor());
+
+    Ok(())
+}
+
+#[test]
+fn test_write_batch_dropped() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create_to_out_dir("__write_batch_dropped")?;
+    let block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+    let chain_id: ChainId = "NetXgtSLGNJvNye".try_into()?;
+    let block_hash: BlockHash = vec![1; HashType::BlockHash.size()].try_into()?;
+
+    {
+        let mut batch = tmp_storage.storage().write_batch();
+        block_meta_storage.put_batched(
+            &mut batch,
+            &block_hash,
+            &Meta::new(true, None, 1, chain_id.clone()),
+        )?;
+    }
+    assert!(block_meta_storage.get(&block_hash)?.is_none());
+
+    // dropped batch is discarded
+    block_meta_storage.put(&block_hash, &Meta::new(false, None, 1, chain_id))?;
+    assert!(!block_meta_storage
+        .get(&block_hash)?
+        .expect("Metadata should be stored")
+        .is_applied());
+
+    Ok(())
+}
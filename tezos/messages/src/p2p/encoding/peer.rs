@@ -97,6 +97,8 @@ into_peer_message!(GetOperationsForBlocksMessage, GetOperationsForBlocks);
 into_peer_message!(OperationsForBlocksMessage, OperationsForBlocks);
 into_peer_message!(GetOperationsMessage, GetOperations);
 into_peer_message!(OperationMessage, Operation);
+into_peer_message!(GetProtocolsMessage, GetProtocols);
+into_peer_message!(ProtocolMessage, Protocol);
 
 impl SizeFromChunk for PeerMessageResponse {
     fn size_from_chunk(
@@ -14,6 +14,16 @@ pub struct ProtocolMessage {
     protocol: Protocol,
 }
 
+impl ProtocolMessage {
+    pub fn new(protocol: Protocol) -> Self {
+        Self { protocol }
+    }
+
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+}
+
 // -----------------------------------------------------------------------------------------------
 #[derive(Serialize, Deserialize, Debug, Clone, HasEncoding, NomReader)]
 pub struct Component {
@@ -22,6 +32,28 @@ pub struct Component {
     implementation: String,
 }
 
+impl Component {
+    pub fn new(name: String, interface: Option<String>, implementation: String) -> Self {
+        Self {
+            name,
+            interface,
+            implementation,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn interface(&self) -> Option<&str> {
+        self.interface.as_deref()
+    }
+
+    pub fn implementation(&self) -> &str {
+        &self.implementation
+    }
+}
+
 // -----------------------------------------------------------------------------------------------
 #[derive(Serialize, Deserialize, Debug, Clone, HasEncoding, NomReader)]
 pub struct Protocol {
@@ -31,6 +63,13 @@ pub struct Protocol {
 }
 
 impl Protocol {
+    pub fn new(expected_env_version: i16, components: Vec<Component>) -> Self {
+        Self {
+            expected_env_version,
+            components,
+        }
+    }
+
     pub fn expected_env_version(&self) -> i16 {
         self.expected_env_version
     }
@@ -46,3 +85,47 @@ pub struct GetProtocolsMessage {
     #[encoding(dynamic, list = "GET_PROTOCOLS_MAX_LENGTH")]
     get_protocols: Vec<ProtocolHash>,
 }
+
+impl GetProtocolsMessage {
+    pub fn new(get_protocols: Vec<ProtocolHash>) -> Self {
+        Self { get_protocols }
+    }
+
+    pub fn get_protocols(&self) -> &Vec<ProtocolHash> {
+        &self.get_protocols
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::p2p::binary_message::{BinaryRead, BinaryWrite};
+
+    use super::*;
+
+    #[test]
+    fn test_protocol_message_roundtrip() {
+        let protocol = Protocol::new(
+            1,
+            vec![
+                Component::new(
+                    "Main".to_string(),
+                    Some("val x : int".to_string()),
+                    "let x = 1".to_string(),
+                ),
+                Component::new("Utils".to_string(), None, "let y = 2".to_string()),
+            ],
+        );
+        let bytes = ProtocolMessage::new(protocol)
+            .as_bytes()
+            .expect("Failed to encode protocol");
+
+        let decoded = ProtocolMessage::from_bytes(&bytes).expect("Failed to decode protocol");
+        let protocol = decoded.protocol();
+        assert_eq!(protocol.expected_env_version(), 1);
+        assert_eq!(protocol.components().len(), 2);
+        assert_eq!(protocol.components()[0].interface(), Some("val x : int"));
+        assert_eq!(protocol.components()[1].name(), "Utils");
+        assert_eq!(protocol.components()[1].interface(), None);
+        assert_eq!(protocol.components()[1].implementation(), "let y = 2");
+    }
+}